@@ -0,0 +1,140 @@
+use agent_box_common::config::{Config, load_config};
+use agent_box_common::path::RepoIdentifier;
+use agent_box_common::repo::list_entries;
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::{Bash, EnvCompleter, Fish, Zsh};
+use eyre::Result;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::io::Write;
+
+/// Env var the shell sets when it calls `ab` back for completions
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Shells `ab completions` writes a script for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Write the script that registers `ab`'s completions with `shell`. The script
+/// calls `ab` back on each completion, so repo, session, and profile names are
+/// always current.
+pub fn write_registration(shell: CompletionShell, out: &mut dyn Write) -> Result<()> {
+    let completer: &dyn EnvCompleter = match shell {
+        CompletionShell::Bash => &Bash,
+        CompletionShell::Zsh => &Zsh,
+        CompletionShell::Fish => &Fish,
+    };
+    completer.write_registration(COMPLETE_VAR, "ab", "ab", "ab", out)?;
+    Ok(())
+}
+
+/// Repository identifiers under base_repo_dir, sorted
+pub fn repo_names(config: &Config) -> Vec<String> {
+    let mut repos = RepoIdentifier::discover_repo_ids(config).unwrap_or_default();
+    repos.sort();
+    repos
+        .iter()
+        .map(|repo| repo.relative_path().to_string_lossy().to_string())
+        .collect()
+}
+
+/// Session workspace names across all repositories, sorted and deduplicated
+pub fn session_names(config: &Config) -> Vec<String> {
+    let repos = RepoIdentifier::discover_repo_ids(config).unwrap_or_default();
+    list_entries(config, &repos, None)
+        .into_iter()
+        .filter_map(|entry| entry.session)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Candidates from the config, or none when it doesn't load
+fn from_config(names: fn(&Config) -> Vec<String>) -> Vec<CompletionCandidate> {
+    load_config()
+        .map(|config| names(&config))
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+pub fn repo_candidates() -> Vec<CompletionCandidate> {
+    from_config(repo_names)
+}
+
+pub fn session_candidates() -> Vec<CompletionCandidate> {
+    from_config(session_names)
+}
+
+/// Profile names for `-p`, completing the last name of a comma-separated list
+pub fn profile_candidates(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    let (done, last) = match current.rsplit_once(',') {
+        Some((done, last)) => (format!("{},", done), last),
+        None => (String::new(), current.as_ref()),
+    };
+    from_config(|config| config.profiles.keys().cloned().collect())
+        .into_iter()
+        .filter(|candidate| candidate.get_value().to_string_lossy().starts_with(last))
+        .map(|candidate| candidate.add_prefix(&done))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_registration() {
+        for (shell, needle) in [
+            (CompletionShell::Bash, "complete -o nospace -o bashdefault"),
+            (CompletionShell::Zsh, "compdef"),
+            (
+                CompletionShell::Fish,
+                "complete --keep-order --exclusive --command ab",
+            ),
+        ] {
+            let mut out = Vec::new();
+            write_registration(shell, &mut out).unwrap();
+            let script = String::from_utf8(out).unwrap();
+            assert!(script.contains(needle), "{:?}: {}", shell, script);
+            assert!(script.contains("COMPLETE="), "{:?}: {}", shell, script);
+        }
+    }
+
+    #[test]
+    fn test_repo_and_session_names() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-completions-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            base_repo_dir: temp_dir.join("repos"),
+            workspace_dir: temp_dir.join("workspaces"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: Default::default(),
+        };
+        for repo in ["fr/agent-box", "tools"] {
+            std::fs::create_dir_all(config.base_repo_dir.join(repo).join(".git")).unwrap();
+        }
+        for session in ["main", "fix"] {
+            let dir = config.workspace_dir.join("git/fr/agent-box").join(session);
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        std::fs::create_dir_all(config.workspace_dir.join("jj/tools/main")).unwrap();
+
+        assert_eq!(repo_names(&config), ["fr/agent-box", "tools"]);
+        assert_eq!(session_names(&config), ["fix", "main"]);
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
@@ -0,0 +1,449 @@
+use agent_box_common::config::{Config, ValidationResult, validate_config};
+use eyre::{Result, WrapErr, bail};
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+/// Open `path` in `$EDITOR` (falling back to `vi`) and wait for it to exit
+pub fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("EDITOR")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    // Run through the shell so EDITOR may carry arguments (e.g. "code --wait")
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .wrap_err_with(|| format!("Failed to run editor '{}'", editor))?;
+
+    if !status.success() {
+        bail!("Editor '{}' exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+/// Edit a config file, then reload and validate the merged config.
+///
+/// The file is created from `template` if missing. When the edited config fails
+/// to load, the error is shown and `reopen` decides whether to edit again;
+/// declining returns the load error.
+pub fn edit_config(
+    path: &Path,
+    template: &str,
+    mut edit: impl FnMut(&Path) -> Result<()>,
+    load: impl Fn() -> Result<Config>,
+    mut reopen: impl FnMut() -> Result<bool>,
+) -> Result<ValidationResult> {
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, template)
+            .wrap_err_with(|| format!("Failed to create {}", path.display()))?;
+        eprintln!("Created {} from template", path.display());
+    }
+
+    loop {
+        edit(path)?;
+
+        match load() {
+            Ok(config) => return Ok(validate_config(&config)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                if !reopen()? {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Split a dotted config key (`runtime.image`) into its parts
+fn key_parts(key: &str) -> Result<Vec<&str>> {
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|p| p.trim().is_empty()) {
+        bail!("Invalid config key '{}'", key);
+    }
+    Ok(parts)
+}
+
+/// Value of the dotted `key` in the merged config, including defaults.
+/// Strings print bare, other scalars as literals, and arrays and tables as JSON.
+pub fn get_config_value(config: &Config, key: &str) -> Result<String> {
+    let mut value = serde_json::to_value(config)?;
+    for (i, part) in key_parts(key)?.into_iter().enumerate() {
+        let serde_json::Value::Object(mut table) = value else {
+            bail!(
+                "'{}' is not a table",
+                key.split('.').take(i).collect::<Vec<_>>().join(".")
+            );
+        };
+        value = table
+            .remove(part)
+            .ok_or_else(|| eyre::eyre!("Unknown config key '{}'", key))?;
+    }
+
+    Ok(match value {
+        serde_json::Value::Null => bail!("'{}' is not set", key),
+        serde_json::Value::String(s) => s,
+        serde_json::Value::Bool(_) | serde_json::Value::Number(_) => value.to_string(),
+        _ => serde_json::to_string_pretty(&value)?,
+    })
+}
+
+/// Set the dotted `key` in a TOML document to `value`, creating tables as
+/// needed and keeping other keys, comments and formatting.
+///
+/// `value` is parsed as a TOML value (`3`, `true`, `["a", "b"]`,
+/// `{ helper = "pass" }`), falling back to a plain string.
+pub fn set_config_value(input: &str, key: &str, value: &str) -> Result<String> {
+    let mut doc: DocumentMut = input.parse().wrap_err("Failed to parse config as TOML")?;
+    let parts = key_parts(key)?;
+    let (last, parents) = parts.split_last().expect("key has at least one part");
+
+    let mut table = doc.as_table_mut() as &mut dyn toml_edit::TableLike;
+    for (i, part) in parents.iter().enumerate() {
+        let item = table.entry(part).or_insert_with(|| {
+            let mut new = Table::new();
+            new.set_implicit(true);
+            Item::Table(new)
+        });
+        table = item
+            .as_table_like_mut()
+            .ok_or_else(|| eyre::eyre!("'{}' is not a table", parts[..=i].join(".")))?;
+    }
+
+    let mut new = value
+        .parse::<Value>()
+        .unwrap_or_else(|_| Value::from(value));
+    new.decor_mut().clear();
+    match table.get_mut(last) {
+        Some(Item::Value(old)) => {
+            // Keep comments attached to the old value
+            *new.decor_mut() = old.decor().clone();
+            *old = new;
+        }
+        Some(Item::None) | None => {
+            table.insert(last, Item::Value(new));
+        }
+        Some(_) => bail!("'{}' is a table; set one of its keys instead", key),
+    }
+
+    Ok(doc.to_string())
+}
+
+/// `ab config set`: set `key` in the config file at `path` (created if missing),
+/// then reload and validate the merged config with `load`.
+/// If the result fails to load or has validation errors, the file is restored.
+pub fn set_config_file(
+    path: &Path,
+    key: &str,
+    value: &str,
+    load: impl Fn() -> Result<Config>,
+) -> Result<ValidationResult> {
+    let original = match std::fs::read_to_string(path) {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).wrap_err_with(|| format!("Failed to read {}", path.display())),
+    };
+
+    let updated = set_config_value(original.as_deref().unwrap_or_default(), key, value)
+        .wrap_err_with(|| format!("Failed to set {} in {}", key, path.display()))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, &updated)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+    let restore = || -> Result<()> {
+        match &original {
+            Some(contents) => std::fs::write(path, contents)?,
+            None => std::fs::remove_file(path)?,
+        }
+        Ok(())
+    };
+
+    match load() {
+        Ok(config) => {
+            let result = validate_config(&config);
+            if result.is_ok() {
+                return Ok(result);
+            }
+            restore()?;
+            let errors: Vec<String> = result.errors.iter().map(ToString::to_string).collect();
+            bail!(
+                "Setting {} makes the config invalid; {} was left unchanged:\n  {}",
+                key,
+                path.display(),
+                errors.join("\n  ")
+            )
+        }
+        Err(e) => {
+            restore()?;
+            Err(e.wrap_err(format!(
+                "Setting {} makes the config fail to load; {} was left unchanged",
+                key,
+                path.display()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent_box_common::config::{CONFIG_TEMPLATE, load_config_from};
+    use std::cell::Cell;
+    use std::path::PathBuf;
+
+    fn temp_config(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ab-edit-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join(".agent-box.toml")
+    }
+
+    fn load(path: &Path) -> impl Fn() -> Result<Config> + '_ {
+        move || load_config_from(&path.to_path_buf(), None)
+    }
+
+    #[test]
+    fn test_missing_file_is_created_from_template_and_validated() {
+        let path = temp_config("template");
+        let seen = Cell::new(String::new());
+
+        let result = edit_config(
+            &path,
+            CONFIG_TEMPLATE,
+            |p| {
+                seen.set(std::fs::read_to_string(p)?);
+                Ok(())
+            },
+            load(&path),
+            || panic!("template should load"),
+        )
+        .unwrap();
+
+        assert_eq!(seen.take(), CONFIG_TEMPLATE);
+        assert!(result.is_ok());
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_validation_issues_are_reported_after_edit() {
+        let path = temp_config("validate");
+
+        let result = edit_config(
+            &path,
+            CONFIG_TEMPLATE,
+            |p| {
+                std::fs::write(
+                    p,
+                    "workspace_dir = \"/w\"\nbase_repo_dir = \"/r\"\n\
+                     default_profile = \"nope\"\n[profiles.empty]\n",
+                )?;
+                Ok(())
+            },
+            load(&path),
+            || panic!("config should load"),
+        )
+        .unwrap();
+
+        assert_eq!(result.errors.len(), 1);
+        assert!(
+            result.errors[0]
+                .to_string()
+                .contains("'nope' is not defined")
+        );
+        assert_eq!(result.warnings.len(), 1);
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_parse_error_reopens_until_fixed() {
+        let path = temp_config("reopen");
+        let edits = Cell::new(0);
+        let reopens = Cell::new(0);
+
+        let result = edit_config(
+            &path,
+            CONFIG_TEMPLATE,
+            |p| {
+                edits.set(edits.get() + 1);
+                let contents = if edits.get() == 1 {
+                    "workspace_dir = \n"
+                } else {
+                    "workspace_dir = \"/w\"\nbase_repo_dir = \"/r\"\n"
+                };
+                std::fs::write(p, contents)?;
+                Ok(())
+            },
+            load(&path),
+            || {
+                reopens.set(reopens.get() + 1);
+                Ok(true)
+            },
+        )
+        .unwrap();
+
+        assert_eq!((edits.get(), reopens.get()), (2, 1));
+        assert!(result.is_ok() && !result.has_warnings());
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_parse_error_without_reopen_is_returned() {
+        let path = temp_config("giveup");
+
+        let err = edit_config(
+            &path,
+            CONFIG_TEMPLATE,
+            |p| {
+                std::fs::write(p, "workspace_dir = [")?;
+                Ok(())
+            },
+            load(&path),
+            || Ok(false),
+        )
+        .unwrap_err();
+
+        assert!(!err.to_string().is_empty());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "workspace_dir = [");
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    const BASE: &str = "# agent-box config\nworkspace_dir = \"/w\"\nbase_repo_dir = \"/r\"\n\n[runtime]\nimage = \"old:1\" # pinned\n";
+
+    #[test]
+    fn test_set_config_value_scalars() {
+        let updated = set_config_value(BASE, "runtime.image", "new:2").unwrap();
+        assert_eq!(
+            updated,
+            BASE.replace("\"old:1\" # pinned", "\"new:2\" # pinned")
+        );
+
+        let updated = set_config_value(&updated, "runtime.spawn_retries", "3").unwrap();
+        let updated = set_config_value(&updated, "runtime.verify_image", "true").unwrap();
+        assert!(
+            updated
+                .ends_with("image = \"new:2\" # pinned\nspawn_retries = 3\nverify_image = true\n")
+        );
+        assert!(updated.starts_with("# agent-box config\n"));
+
+        let updated = set_config_value("", "default_profile", "rust").unwrap();
+        assert_eq!(updated, "default_profile = \"rust\"\n");
+    }
+
+    #[test]
+    fn test_set_config_value_nested_tables() {
+        let updated =
+            set_config_value(BASE, "runtime.mounts.ro.absolute", r#"["/nix/store"]"#).unwrap();
+        let updated =
+            set_config_value(&updated, "profiles.rust.env", r#"["CARGO_HOME=/c"]"#).unwrap();
+        let updated = set_config_value(
+            &updated,
+            "runtime.secret_env.GH_TOKEN",
+            r#"{ helper = "pass", args = ["gh"] }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            updated,
+            format!(
+                "{}{}",
+                BASE,
+                "\n[runtime.mounts.ro]\nabsolute = [\"/nix/store\"]\n\n\
+                 [runtime.secret_env]\nGH_TOKEN = { helper = \"pass\", args = [\"gh\"] }\n\n\
+                 [profiles.rust]\nenv = [\"CARGO_HOME=/c\"]\n"
+            )
+        );
+
+        let path = temp_config("nested");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &updated).unwrap();
+        let config = load(&path)().unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+        assert_eq!(config.runtime.mounts.ro.absolute, vec!["/nix/store"]);
+        assert_eq!(config.profiles["rust"].env, vec!["CARGO_HOME=/c"]);
+        assert_eq!(config.runtime.secret_env["GH_TOKEN"].helper, "pass");
+
+        let err = set_config_value(BASE, "runtime", "x").unwrap_err();
+        assert!(err.to_string().contains("is a table"), "{}", err);
+        let err = set_config_value(BASE, "runtime.image.tag", "x").unwrap_err();
+        assert!(
+            err.to_string().contains("'runtime.image' is not a table"),
+            "{}",
+            err
+        );
+        assert!(set_config_value(BASE, "runtime..image", "x").is_err());
+    }
+
+    #[test]
+    fn test_get_config_value() {
+        let path = temp_config("get");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            format!(
+                "{}spawn_retries = 2\n[profiles.rust]\nenv = [\"A=1\"]\n",
+                BASE
+            ),
+        )
+        .unwrap();
+        let config = load(&path)().unwrap();
+
+        assert_eq!(get_config_value(&config, "runtime.image").unwrap(), "old:1");
+        assert_eq!(
+            get_config_value(&config, "runtime.spawn_retries").unwrap(),
+            "2"
+        );
+        // Defaults are included
+        assert_eq!(
+            get_config_value(&config, "runtime.backend").unwrap(),
+            "podman"
+        );
+        assert_eq!(
+            get_config_value(&config, "profiles.rust.env").unwrap(),
+            "[\n  \"A=1\"\n]"
+        );
+
+        let err = get_config_value(&config, "runtime.imag").unwrap_err();
+        assert!(err.to_string().contains("Unknown config key"), "{}", err);
+        let err = get_config_value(&config, "runtime.working_dir").unwrap_err();
+        assert!(err.to_string().contains("is not set"), "{}", err);
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_set_config_file_validates_and_restores() {
+        let path = temp_config("set");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, BASE).unwrap();
+
+        let result = set_config_file(&path, "runtime.image", "new:2", load(&path)).unwrap();
+        assert!(result.is_ok());
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("image = \"new:2\" # pinned")
+        );
+        let written = std::fs::read_to_string(&path).unwrap();
+
+        // A validation error leaves the file as it was
+        let err = set_config_file(&path, "default_profile", "nope", load(&path)).unwrap_err();
+        assert!(err.to_string().contains("'nope' is not defined"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+
+        // So does a value of the wrong type
+        let err = set_config_file(&path, "runtime.spawn_retries", "many", load(&path)).unwrap_err();
+        assert!(err.to_string().contains("fail to load"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+
+        // A file created by a failing set is removed again
+        std::fs::remove_file(&path).unwrap();
+        assert!(set_config_file(&path, "runtime.image", "x", load(&path)).is_err());
+        assert!(!path.exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
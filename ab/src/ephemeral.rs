@@ -0,0 +1,276 @@
+use eyre::{Result, WrapErr, bail};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Where `ab spawn --ephemeral` keeps workspace copies:
+/// `$XDG_STATE_HOME/agent-box/ephemeral`, or `~/.local/state/agent-box/ephemeral`
+pub fn ephemeral_root() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+        })?;
+    Some(base.join("agent-box").join("ephemeral"))
+}
+
+/// What an ephemeral copy was made from, stored next to it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EphemeralMeta {
+    /// The workspace that was copied
+    pub source: PathBuf,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+}
+
+/// A copy of a workspace the container got instead of the workspace itself, with
+/// a snapshot of its starting state (in a git dir of its own) to diff against
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ephemeral {
+    pub id: String,
+    pub dir: PathBuf,
+}
+
+impl Ephemeral {
+    /// The copied workspace
+    pub fn workspace(&self) -> PathBuf {
+        self.dir.join("workspace")
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        self.dir.join("meta.json")
+    }
+
+    /// Copy `source` into a new directory under `root` and snapshot the copy
+    pub fn create(root: &Path, source: &Path, created: u64) -> Result<Self> {
+        std::fs::create_dir_all(root)
+            .wrap_err_with(|| format!("Failed to create {}", root.display()))?;
+        let name = source
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "workspace".to_string());
+        let (id, dir) = (0..)
+            .map(|n| match n {
+                0 => format!("{}-{}", created, name),
+                n => format!("{}-{}-{}", created, name, n),
+            })
+            .map(|id| {
+                let dir = root.join(&id);
+                (id, dir)
+            })
+            .find(|(_, dir)| std::fs::create_dir(dir).is_ok())
+            .expect("unbounded");
+        let ephemeral = Self { id, dir };
+
+        let result = (|| -> Result<()> {
+            std::fs::write(
+                ephemeral.meta_path(),
+                serde_json::to_string_pretty(&EphemeralMeta {
+                    source: source.to_path_buf(),
+                    created,
+                })?,
+            )?;
+            // cp -a keeps modes, times, and symlinks; --reflink makes it cheap where the fs can
+            run(Command::new("cp")
+                .args(["-a", "--reflink=auto"])
+                .arg(source.join("."))
+                .arg(ephemeral.workspace()))?;
+            ephemeral.git(&["init", "-q"])?;
+            ephemeral.git(&["add", "-A"])?;
+            ephemeral.git(&[
+                "commit",
+                "-q",
+                "--allow-empty",
+                "--no-verify",
+                "-m",
+                "baseline",
+            ])?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            let _ = std::fs::remove_dir_all(&ephemeral.dir);
+            bail!("Failed to copy {} for --ephemeral: {}", source.display(), e);
+        }
+        Ok(ephemeral)
+    }
+
+    /// The copy with the given id under `root`
+    pub fn open(root: &Path, id: &str) -> Result<Self> {
+        let ephemeral = Self {
+            id: id.to_string(),
+            dir: root.join(id),
+        };
+        if id.contains('/') || !ephemeral.meta_path().is_file() {
+            bail!("No ephemeral workspace '{}' in {}", id, root.display());
+        }
+        Ok(ephemeral)
+    }
+
+    pub fn meta(&self) -> Result<EphemeralMeta> {
+        let path = self.meta_path();
+        let text = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&text).wrap_err_with(|| format!("Invalid {}", path.display()))
+    }
+
+    /// `git` on the snapshot git dir with the copy as its work tree
+    fn git_command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new("git");
+        command
+            .arg("--git-dir")
+            .arg(self.dir.join("snapshot.git"))
+            .arg("--work-tree")
+            .arg(self.workspace())
+            .args([
+                "-c",
+                "user.name=agent-box",
+                "-c",
+                "user.email=agent-box@localhost",
+                "-c",
+                "core.hooksPath=/dev/null",
+            ])
+            .args(args);
+        command
+    }
+
+    fn git(&self, args: &[&str]) -> Result<Vec<u8>> {
+        run(&mut self.git_command(args))
+    }
+
+    /// Everything changed in the copy since it was made, as a binary-safe patch
+    /// with paths relative to the workspace. Files ignored by the workspace's
+    /// `.gitignore`s are left out.
+    pub fn diff(&self) -> Result<Vec<u8>> {
+        self.git(&["add", "-A"])?;
+        self.git(&["diff", "--cached", "--binary", "--no-color", "HEAD"])
+    }
+
+    /// Remove the copy
+    pub fn discard(&self) -> Result<()> {
+        std::fs::remove_dir_all(&self.dir)
+            .wrap_err_with(|| format!("Failed to remove {}", self.dir.display()))
+    }
+}
+
+/// All copies under `root` with their metadata, oldest first
+pub fn list_ephemeral(root: &Path) -> Result<Vec<(Ephemeral, EphemeralMeta)>> {
+    let Ok(dirs) = std::fs::read_dir(root) else {
+        return Ok(Vec::new());
+    };
+    let mut copies: Vec<(Ephemeral, EphemeralMeta)> = dirs
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let ephemeral = Ephemeral::open(root, &e.file_name().to_string_lossy()).ok()?;
+            let meta = ephemeral.meta().ok()?;
+            Some((ephemeral, meta))
+        })
+        .collect();
+    copies.sort_by(|(a, am), (b, bm)| am.created.cmp(&bm.created).then(a.id.cmp(&b.id)));
+    Ok(copies)
+}
+
+/// Apply `patch` (from [`Ephemeral::diff`]) to the files in `target`
+pub fn apply_patch(target: &Path, patch: &[u8]) -> Result<()> {
+    let mut child = Command::new("git")
+        .current_dir(target)
+        .args(["apply", "--binary", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err("Failed to run git apply")?;
+    child.stdin.take().expect("piped stdin").write_all(patch)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "git apply failed in {}: {}",
+            target.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Run `command`, returning its stdout or failing with its stderr
+fn run(command: &mut Command) -> Result<Vec<u8>> {
+    let output = command
+        .output()
+        .wrap_err_with(|| format!("Failed to run {:?}", command.get_program()))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            command.get_program().to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ephemeral_copy_diff_apply_discard() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-ephemeral-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let source = temp_dir.join("ws");
+        let root = temp_dir.join("ephemeral");
+        std::fs::create_dir_all(source.join("src")).unwrap();
+        std::fs::write(source.join("src/lib.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(source.join("gone.txt"), "bye\n").unwrap();
+        std::fs::write(source.join(".gitignore"), "target/\n").unwrap();
+
+        let ephemeral = Ephemeral::create(&root, &source, 100).unwrap();
+        let again = Ephemeral::create(&root, &source, 100).unwrap();
+        assert_eq!(ephemeral.id, "100-ws");
+        assert_eq!(again.id, "100-ws-1");
+        again.discard().unwrap();
+
+        let copy = ephemeral.workspace();
+        std::fs::write(copy.join("src/lib.rs"), "fn b() {}\n").unwrap();
+        std::fs::remove_file(copy.join("gone.txt")).unwrap();
+        std::fs::write(copy.join("new.bin"), [0u8, 159, 146, 150]).unwrap();
+        std::fs::create_dir_all(copy.join("target")).unwrap();
+        std::fs::write(copy.join("target/out"), "build output").unwrap();
+
+        // The original is untouched until the patch is applied
+        assert_eq!(
+            std::fs::read_to_string(source.join("src/lib.rs")).unwrap(),
+            "fn a() {}\n"
+        );
+        let patch = ephemeral.diff().unwrap();
+        let text = String::from_utf8_lossy(&patch);
+        assert!(text.contains("a/src/lib.rs b/src/lib.rs"), "{}", text);
+        assert!(text.contains("deleted file"), "{}", text);
+        assert!(text.contains("GIT binary patch"), "{}", text);
+        assert!(!text.contains("target/out"), "{}", text);
+
+        apply_patch(&source, &patch).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(source.join("src/lib.rs")).unwrap(),
+            "fn b() {}\n"
+        );
+        assert!(!source.join("gone.txt").exists());
+        assert_eq!(
+            std::fs::read(source.join("new.bin")).unwrap(),
+            [0u8, 159, 146, 150]
+        );
+
+        let listed = list_ephemeral(&root).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(
+            listed[0].1,
+            EphemeralMeta {
+                source: source.clone(),
+                created: 100
+            }
+        );
+        assert!(Ephemeral::open(&root, "nope").is_err());
+
+        ephemeral.discard().unwrap();
+        assert!(list_ephemeral(&root).unwrap().is_empty());
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
@@ -0,0 +1,215 @@
+use agent_box_common::path::{GitWorktreeInfo, JjWorkspaceInfo, WorkspaceType};
+use eyre::Result;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// One session workspace `ab foreach` spawns into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeachTarget {
+    pub session: String,
+    pub wtype: WorkspaceType,
+}
+
+impl std::fmt::Display for ForeachTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.wtype.as_str(), self.session)
+    }
+}
+
+/// The session workspaces among a repo's git worktrees and jj workspaces.
+///
+/// Only worktrees directly under `git_root` and jj workspaces whose directory
+/// exists under `jj_root` are sessions; the source checkout itself (the main
+/// worktree, jj's `default` workspace) is skipped. Git sessions come first,
+/// each kind sorted by name.
+pub fn session_targets(
+    git_root: &Path,
+    worktrees: &[GitWorktreeInfo],
+    jj_root: &Path,
+    jj_workspaces: &[JjWorkspaceInfo],
+    is_dir: impl Fn(&Path) -> bool,
+) -> Vec<ForeachTarget> {
+    let mut git: Vec<String> = worktrees
+        .iter()
+        .filter(|wt| !wt.is_main && wt.path.parent() == Some(git_root))
+        .filter_map(|wt| Some(wt.path.file_name()?.to_string_lossy().into_owned()))
+        .collect();
+    let mut jj: Vec<String> = jj_workspaces
+        .iter()
+        .map(|ws| ws.name.clone())
+        .filter(|name| is_dir(&jj_root.join(name)))
+        .collect();
+    git.sort();
+    jj.sort();
+
+    git.into_iter()
+        .map(|session| ForeachTarget {
+            session,
+            wtype: WorkspaceType::Git,
+        })
+        .chain(jj.into_iter().map(|session| ForeachTarget {
+            session,
+            wtype: WorkspaceType::Jj,
+        }))
+        .collect()
+}
+
+/// Run `run` for every target on up to `parallel` threads, continuing past
+/// failures. Results come back in target order: the exit code, or an error if
+/// the run couldn't be started or was killed.
+pub fn run_foreach(
+    targets: &[ForeachTarget],
+    parallel: usize,
+    run: impl Fn(&ForeachTarget) -> Result<i32> + Sync,
+) -> Vec<Result<i32>> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<i32>>>> =
+        Mutex::new(targets.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..parallel.clamp(1, targets.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(target) = targets.get(i) else {
+                        break;
+                    };
+                    let result = run(target);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every target is run"))
+        .collect()
+}
+
+/// Write one line per workspace and a totals line; returns the number that failed
+pub fn write_summary(
+    targets: &[ForeachTarget],
+    results: &[Result<i32>],
+    out: &mut dyn Write,
+) -> std::io::Result<usize> {
+    let mut failed = 0;
+    for (target, result) in targets.iter().zip(results) {
+        match result {
+            Ok(0) => writeln!(out, "  ✓ {}: exit 0", target)?,
+            Ok(code) => {
+                failed += 1;
+                writeln!(out, "  ✗ {}: exit {}", target, code)?;
+            }
+            Err(e) => {
+                failed += 1;
+                writeln!(out, "  ✗ {}: {}", target, e)?;
+            }
+        }
+    }
+    writeln!(
+        out,
+        "{} succeeded, {} failed",
+        results.len() - failed,
+        failed
+    )?;
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn worktree(path: &str, is_main: bool) -> GitWorktreeInfo {
+        GitWorktreeInfo {
+            path: PathBuf::from(path),
+            id: None,
+            is_main,
+            is_locked: false,
+        }
+    }
+
+    fn jj_workspace(name: &str) -> JjWorkspaceInfo {
+        JjWorkspaceInfo {
+            name: name.to_string(),
+            commit_id: String::new(),
+            description: String::new(),
+            is_empty: true,
+        }
+    }
+
+    fn target(session: &str, wtype: WorkspaceType) -> ForeachTarget {
+        ForeachTarget {
+            session: session.to_string(),
+            wtype,
+        }
+    }
+
+    #[test]
+    fn test_session_targets_skips_source_checkouts() {
+        let worktrees = [
+            worktree("/repos/proj", true),
+            worktree("/ws/git/proj/lint", false),
+            worktree("/elsewhere/manual", false),
+            worktree("/ws/git/proj/feat", false),
+        ];
+        let jj = [jj_workspace("default"), jj_workspace("review")];
+
+        let targets = session_targets(
+            Path::new("/ws/git/proj"),
+            &worktrees,
+            Path::new("/ws/jj/proj"),
+            &jj,
+            |path| path == Path::new("/ws/jj/proj/review"),
+        );
+
+        assert_eq!(
+            targets,
+            [
+                target("feat", WorkspaceType::Git),
+                target("lint", WorkspaceType::Git),
+                target("review", WorkspaceType::Jj),
+            ]
+        );
+        assert_eq!(targets[2].to_string(), "jj/review");
+    }
+
+    #[test]
+    fn test_run_foreach_continues_past_failures_in_order() {
+        let targets: Vec<_> = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|s| target(s, WorkspaceType::Jj))
+            .collect();
+
+        for parallel in [1, 3, 10] {
+            let results = run_foreach(&targets, parallel, |t| match t.session.as_str() {
+                "b" => Ok(2),
+                "c" => Err(eyre::eyre!("terminated by signal")),
+                _ => Ok(0),
+            });
+
+            let mut out = Vec::new();
+            let failed = write_summary(&targets, &results, &mut out).unwrap();
+            assert_eq!(failed, 2);
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                "  ✓ jj/a: exit 0\n  \
+                 ✗ jj/b: exit 2\n  \
+                 ✗ jj/c: terminated by signal\n  \
+                 ✓ jj/d: exit 0\n\
+                 2 succeeded, 2 failed\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_foreach_with_no_targets() {
+        let results = run_foreach(&[], 4, |_| unreachable!());
+        assert!(results.is_empty());
+    }
+}
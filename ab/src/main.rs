@@ -1,18 +1,41 @@
 use agent_box_common::config::{
-    collect_profiles_to_apply, load_config, resolve_profiles, validate_config,
+    BuildConfig, CONFIG_TEMPLATE, Config, DevcontainerPaths, Mount, MountResolutionCache, REDACTED,
+    REPO_CONFIG_TEMPLATE, ValidationResult, VolumeSpec, collect_profiles_to_apply, config_drift,
+    config_layer_paths, expand_alias, find_devcontainer, freeze_config, is_empty_profile,
+    load_config, load_devcontainer, parse_age, profile_graph, profile_mount_counts,
+    redact_env_entry, render_profile_tree, resolve_build, resolve_mount_sources,
+    resolve_profiles_with_cache, should_redact_env, split_profile_args, validate_config,
     validate_config_or_err,
 };
-use agent_box_common::display::info;
-use agent_box_common::path::WorkspaceType;
-use agent_box_common::repo::{locate_repo, new_workspace, remove_repo, resolve_repo_id};
-use clap::{Parser, Subcommand};
-use eyre::Result;
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use agent_box_common::display::{info, is_json_output, repo_info};
+use agent_box_common::migrate::{fix_profiles, line_diff, migrate_legacy_docker_config};
+use agent_box_common::path::{WorkspaceType, expand_path};
+use agent_box_common::repo::{
+    export_repo, gc_keep_reason, list_entries, list_repos, locate_repo, new_workspace,
+    prune_git_worktrees, remove_repo, remove_workspace, repo_counts, repo_json, resolve_repo_id,
+    write_repo_counts, write_repos_json_lines,
+};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::engine::{ArgValueCandidates, ArgValueCompleter};
+use eyre::{Result, WrapErr, bail};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+mod completions;
+mod edit;
+mod ephemeral;
+mod foreach;
+mod run_once;
 mod runtime;
+mod state;
+mod timings;
+mod watch;
 
-use runtime::{build_container_config, create_runtime};
+use run_once::{CleanupPolicy, run_once};
+use runtime::{PullPolicy, build_container_config, create_runtime};
+use timings::Timings;
 
 type ManagedPortal = agent_portal::host::ManagedPortalHandle;
 
@@ -45,31 +68,345 @@ fn maybe_start_managed_portal(
     )?))
 }
 
+/// Write `contents` to `path` after copying the original to `<path>.bak`
+fn write_with_backup(path: &Path, contents: &str) -> Result<PathBuf> {
+    let mut backup = path.to_path_buf().into_os_string();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    std::fs::copy(path, &backup)
+        .wrap_err_with(|| format!("Failed to back up {}", path.display()))?;
+    std::fs::write(path, contents)
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    Ok(backup)
+}
+
+/// `ab dbg validate --fix`: drop empty profiles and dangling `extends` entries from
+/// whichever config layer defines them. The default profile is kept even if empty.
+fn fix_profile_problems(config: &Config) -> Result<()> {
+    let remove: HashSet<String> = config
+        .profiles
+        .iter()
+        .filter(|(name, profile)| {
+            is_empty_profile(profile) && config.default_profile.as_ref() != Some(*name)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    let known: HashSet<String> = config
+        .profiles
+        .keys()
+        .filter(|name| !remove.contains(*name))
+        .cloned()
+        .collect();
+
+    let (global, repo) = config_layer_paths()?;
+    let mut fixed_any = false;
+    for path in std::iter::once(global).chain(repo) {
+        if !path.exists() {
+            continue;
+        }
+        let contents = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let Some(fixed) = fix_profiles(&contents, &remove, &known)? else {
+            continue;
+        };
+
+        println!("--- {}\n+++ {}", path.display(), path.display());
+        print!("{}", line_diff(&contents, &fixed));
+        let backup = write_with_backup(&path, &fixed)?;
+        eprintln!("Fixed {} (backup at {})", path.display(), backup.display());
+        fixed_any = true;
+    }
+
+    if !fixed_any {
+        eprintln!("Nothing to fix.");
+    }
+    Ok(())
+}
+
+/// Print validation errors and warnings to stderr
+fn print_validation_issues(result: &ValidationResult) {
+    if !result.errors.is_empty() {
+        eprintln!("Errors:");
+        for error in &result.errors {
+            eprintln!("  ✗ {}", error);
+        }
+    }
+
+    if !result.warnings.is_empty() {
+        if !result.errors.is_empty() {
+            eprintln!();
+        }
+        eprintln!("Warnings:");
+        for warning in &result.warnings {
+            eprintln!("  ⚠ {}", warning);
+        }
+    }
+}
+
+/// Make sure the spawn image exists: build it from a profile's `build`, or
+/// pull it per `pull` when the preflight check is on
+fn ensure_spawn_image(
+    config: &Config,
+    image: &str,
+    build: Option<&BuildConfig>,
+    rebuild: bool,
+    verify_image: bool,
+    pull: Option<PullPolicy>,
+) -> Result<()> {
+    match build {
+        Some(build) => create_runtime(config).ensure_built(build, image, rebuild),
+        None if verify_image => {
+            create_runtime(config).ensure_image(image, pull.unwrap_or_default())
+        }
+        None => Ok(()),
+    }
+}
+
+/// `ab dbg resolve --format json`: the resolved profile with each mount's resolution
+/// (or error), and env values redacted as in the text output
+fn resolve_json(config: &Config, profile: &[String], no_dedup: bool) -> Result<serde_json::Value> {
+    let mut mount_cache = MountResolutionCache::default();
+    let resolved = resolve_profiles_with_cache(config, profile, &mut mount_cache)?;
+    let homes = runtime::HomeMap::from_env();
+
+    let mounts: Vec<(Mount, Option<String>)> = if no_dedup {
+        resolve_mount_sources(config, profile)?
+            .into_iter()
+            .map(|(m, source)| (m, Some(source)))
+            .collect()
+    } else {
+        resolved.mounts.iter().map(|m| (m.clone(), None)).collect()
+    };
+    let mounts = mounts
+        .into_iter()
+        .map(|(m, source)| {
+            let mut entry = serde_json::to_value(&m)?;
+            entry["source"] = serde_json::json!(source);
+            let resolved_mounts = match &homes {
+                Ok(h) => mount_cache.resolve_chain(&m, &h.host, &h.container),
+                Err(e) => Err(eyre::eyre!("{}", e)),
+            };
+            match resolved_mounts {
+                // Empty when the path doesn't exist
+                Ok(resolved_mounts) => entry["resolved"] = serde_json::json!(resolved_mounts),
+                Err(e) => entry["error"] = serde_json::json!(e.to_string()),
+            }
+            Ok(entry)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let env: Vec<String> = resolved
+        .env
+        .iter()
+        .map(|e| redact_env_entry(e, &config.runtime.redact_env))
+        .collect();
+    // Host values the passthrough variables would get; null when unset
+    let env_passthrough: serde_json::Map<String, serde_json::Value> = resolved
+        .env_passthrough
+        .iter()
+        .map(|name| {
+            let value = std::env::var(name).ok().map(|value| {
+                if should_redact_env(name, &config.runtime.redact_env) {
+                    REDACTED.to_string()
+                } else {
+                    value
+                }
+            });
+            (name.clone(), serde_json::json!(value))
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "profiles": collect_profiles_to_apply(config, profile),
+        "mounts": mounts,
+        "tmpfs": resolved.tmpfs,
+        "volumes": resolved.volumes,
+        "env": env,
+        "env_passthrough": env_passthrough,
+        "ports": resolved.ports,
+        "hosts": resolved.hosts,
+        "context": resolved.context,
+        "command": resolved.command,
+        "network": resolved.network,
+        "allow_hosts": resolved.allow_hosts,
+        "resources": resolved.resources,
+        "build": resolved.build,
+        "secrets": resolved.secrets,
+    }))
+}
+
+/// `ab edit`: edit the global (or repo-local) config, then reload and validate it
+fn run_edit(repo: bool) -> Result<()> {
+    let (global, repo_path) = config_layer_paths()?;
+    let (path, template) = if repo {
+        let path = repo_path.ok_or_else(|| eyre::eyre!("--repo needs to run inside a git repo"))?;
+        (path, REPO_CONFIG_TEMPLATE)
+    } else {
+        (global, CONFIG_TEMPLATE)
+    };
+
+    let result = edit::edit_config(&path, template, edit::open_in_editor, load_config, || {
+        if agent_box_common::prompt::is_non_interactive() {
+            return Ok(false);
+        }
+        inquire::Confirm::new("Config failed to load. Reopen the editor?")
+            .with_default(true)
+            .prompt()
+            .map_err(|e| eyre::eyre!("Failed to get confirmation: {}", e))
+    })?;
+
+    print_validation_issues(&result);
+    if !result.is_ok() {
+        bail!(
+            "Configuration invalid: {} error(s), {} warning(s)",
+            result.errors.len(),
+            result.warnings.len()
+        );
+    }
+    if result.has_warnings() {
+        println!(
+            "Configuration valid with {} warning(s).",
+            result.warnings.len()
+        );
+    } else {
+        println!("Configuration valid. No errors or warnings.");
+    }
+    Ok(())
+}
+
+/// Build the container config `ab spawn --session` would use, without creating
+/// the workspace, starting a portal, or spawning. CLI-only spawn flags are not applied.
+fn dry_container_config(
+    config: &Config,
+    repo: Option<&str>,
+    session: &str,
+    wtype: WorkspaceType,
+    profile: &[String],
+) -> Result<runtime::ContainerConfig> {
+    let repo_id = resolve_repo_id(config, repo)?;
+    let workspace_path = repo_id.workspace_path(config, wtype, session);
+    if !workspace_path.exists() {
+        bail!("Workspace {} does not exist", workspace_path.display());
+    }
+
+    validate_config_or_err(config)?;
+    let mut mount_cache = MountResolutionCache::default();
+    let profile = split_profile_args(profile)?;
+    let resolved_profile = resolve_profiles_with_cache(config, &profile, &mut mount_cache)?;
+
+    // Secrets don't affect mounts; don't run (possibly prompting) credential helpers
+    let mut config = config.clone();
+    config.runtime.secret_env.clear();
+
+    build_container_config(
+        &config,
+        &workspace_path,
+        &repo_id.source_path(&config),
+        false,
+        false,
+        false,
+        false,
+        None,
+        &resolved_profile,
+        &[],
+        &[],
+        &[],
+        &[],
+        None,
+        None,
+        true,
+        None,
+        None,
+        None,
+        &mut mount_cache,
+    )
+}
+
 #[derive(Parser)]
 #[command(name = "ab")]
 #[command(about = "Agent Box - Git repository management tool")]
 struct Cli {
+    /// Fail instead of prompting for missing input (also enabled by AGENT_BOX_NONINTERACTIVE=1)
+    #[arg(long, global = true)]
+    no_interactive: bool,
+    /// Ignore ~/.agent-box.toml and load only the repo-local config (also enabled by AGENT_BOX_NO_GLOBAL=1)
+    #[arg(long, global = true)]
+    no_global_config: bool,
+    /// Output format of `info`, `status`, `list`, `audit`, `dbg list`, `dbg resolve`, and `dbg validate`
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
     #[command(subcommand)]
     command: Commands,
 }
 
-#[derive(Subcommand)]
+/// `--format`: human-readable text or JSON for scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+// Spawn carries all of its flags inline; boxing it would only complicate the match
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Clone)]
 enum Commands {
     /// Show repository information and list workspaces
-    Info,
+    Info {
+        /// Print the mounts a spawn in this session would get, as JSON, without spawning
+        #[arg(long, requires = "session")]
+        mounts_json: bool,
+        /// Session/workspace name to inspect (with --mounts-json)
+        #[arg(long, short, requires = "mounts_json", add = ArgValueCandidates::new(completions::session_candidates))]
+        session: Option<String>,
+        /// Repository identifier (defaults to current directory's git repo)
+        #[arg(long, short, requires = "mounts_json", add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Inspect a git worktree instead of a jj workspace
+        #[arg(long, requires = "mounts_json")]
+        git: bool,
+        /// Profiles to apply, as with `ab spawn -p`
+        #[arg(long, short = 'p', value_name = "PROFILE", requires = "mounts_json", add = ArgValueCompleter::new(completions::profile_candidates))]
+        profile: Vec<String>,
+    },
+    /// Open the config in $EDITOR, then reload and validate it
+    Edit {
+        /// Edit the repo-local .agent-box.toml instead of ~/.agent-box.toml
+        #[arg(long)]
+        repo: bool,
+    },
+    /// Read or change single config values from scripts
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
     /// Create a new workspace (jj or git worktree)
     New {
         /// Repository name (defaults to current directory's git repo)
+        #[arg(add = ArgValueCandidates::new(completions::repo_candidates))]
         repo_name: Option<String>,
         /// Session/workspace name
-        #[arg(long, short)]
+        #[arg(long, short, add = ArgValueCandidates::new(completions::session_candidates))]
         session: Option<String>,
-        /// Create a git worktree
+        /// Create a git worktree (default: jj if the source repo has `.jj`, else git)
         #[arg(long)]
         git: bool,
         /// Create a jj workspace
         #[arg(long)]
         jj: bool,
+        /// Start the jj workspace on this bookmark (runs `jj new <bookmark>` in it)
+        #[arg(long, conflicts_with = "git")]
+        bookmark: Option<String>,
+        /// Print only the absolute workspace path to stdout (progress goes to stderr)
+        #[arg(long)]
+        print_path: bool,
+        /// After creating the workspace, print a diff stat against this base revision
+        /// (`git diff --stat BASE..SESSION` or `jj diff --stat --from BASE --to @`)
+        #[arg(long, value_name = "BASE")]
+        show_diff_stat: Option<String>,
+        /// If the jj workspace name is already taken in the repo, use the first free
+        /// SESSION-N instead of failing
+        #[arg(long, conflicts_with = "git")]
+        auto_rename: bool,
     },
     /// Spawn a new container for a workspace
     Spawn {
@@ -78,18 +415,30 @@ enum Commands {
             long,
             short,
             conflicts_with = "local",
-            required_unless_present = "local"
+            required_unless_present = "local",
+            add = ArgValueCandidates::new(completions::session_candidates)
         )]
         session: Option<String>,
         /// Use the enclosing git root, or current directory if not in a git repo (mutually exclusive with --session)
         #[arg(long, short, conflicts_with = "session")]
         local: bool,
         /// Repository identifier (ignored when --local is used)
-        #[arg(long, short)]
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
         repo: Option<String>,
+        /// Start the session container in the background under a stable name derived
+        /// from repo and session; reconnect with `ab attach`, stop with `ab stop`
+        #[arg(long, short, conflicts_with_all = ["local", "watch"])]
+        detach: bool,
         /// Override entrypoint from config
         #[arg(long, short)]
         entrypoint: Option<String>,
+        /// Run the image's own entrypoint, ignoring runtime.entrypoint
+        #[arg(long, conflicts_with_all = ["entrypoint", "init_script"])]
+        use_image_entrypoint: bool,
+        /// Host shell script to run in the container before the entrypoint and
+        /// command (overrides runtime.init_script)
+        #[arg(long, value_name = "HOST_PATH")]
+        init_script: Option<PathBuf>,
         /// Command to run in the container (passed to entrypoint)
         #[arg(long, short)]
         command: Option<Vec<String>>,
@@ -103,22 +452,42 @@ enum Commands {
         /// Mount source directory as read-only
         #[arg(long)]
         ro: bool,
+        /// Mount the source repo's .git/.jj read-only, keeping the workspace writable.
+        /// Commits and other history changes from inside the container will fail.
+        #[arg(long, visible_alias = "no-source-rw", conflicts_with = "local")]
+        source_ro: bool,
+        /// Don't bind the workspace; run against code already in the image
+        /// (starts in --workdir or runtime.working_dir)
+        #[arg(long, conflicts_with = "ro")]
+        no_workdir_mount: bool,
+        /// In-image working directory for --no-workdir-mount
+        #[arg(long, requires = "no_workdir_mount")]
+        workdir: Option<String>,
+        /// Give the container a throwaway copy of the workspace instead of the
+        /// workspace itself (implies --source-ro). Its changes are kept aside
+        /// until exported with `ab diff-export`.
+        #[arg(long, conflicts_with_all = ["no_workdir_mount", "watch"])]
+        ephemeral: bool,
         /// Additional mount (home-relative). Format: [MODE:]PATH or [MODE:]SRC:DST
         /// MODE is ro, rw, or o (default: rw). Paths use ~ for home directory.
         /// Relative host source paths are resolved against the current working directory.
-        /// Example: -m ~/.config/git -m ro:~/secrets -m rw:~/data:/app/data -m ../pierre
+        /// tmpfs:PATH mounts an empty tmpfs at the container PATH (~ is the container home);
+        /// volume:NAME:PATH[:MODE] mounts a named volume there, creating it if needed.
+        /// Example: -m ~/.config/git -m ro:~/secrets -m rw:~/data:/app/data -m ../pierre -m tmpfs:~/.cache
+        /// -m volume:cargo-cache:~/.cargo/registry
         #[arg(long, short = 'm', value_name = "MOUNT")]
         mount: Vec<String>,
         /// Additional mount (absolute). Format: [MODE:]PATH or [MODE:]SRC:DST
         /// MODE is ro, rw, or o (default: rw). Same path used on host and container.
         /// Relative host source paths are resolved against the current working directory.
-        /// Example: -M /nix/store -M ro:/etc/hosts -M ../shared
+        /// tmpfs:PATH and volume:NAME:PATH[:MODE] work as for -m.
+        /// Example: -M /nix/store -M ro:/etc/hosts -M ../shared -M tmpfs:/tmp
         #[arg(long = "Mount", short = 'M', value_name = "MOUNT")]
         mount_abs: Vec<String>,
-        /// Additional profiles to apply (can be specified multiple times).
+        /// Additional profiles to apply (can be specified multiple times or comma-separated).
         /// Profiles are applied after the default_profile (if set) and in order specified.
-        /// Example: -p git -p rust
-        #[arg(long, short = 'p', value_name = "PROFILE")]
+        /// Example: -p git -p rust or -p git,rust
+        #[arg(long, short = 'p', value_name = "PROFILE", add = ArgValueCompleter::new(completions::profile_candidates))]
         profile: Vec<String>,
         /// Port mapping to expose (can be specified multiple times).
         /// Format: [HOST_IP:]HOST_PORT:CONTAINER_PORT or just CONTAINER_PORT.
@@ -130,13 +499,340 @@ enum Commands {
         /// Example: -H myhost:192.168.1.1 -H host.docker.internal:host-gateway
         #[arg(long = "add-host", short = 'H', value_name = "HOST:IP")]
         add_host: Vec<String>,
+        /// Add host entries from the host's /etc/hosts whose names match this glob
+        /// (can be specified multiple times). Names already mapped are skipped.
+        /// Example: --import-hosts '*.internal'
+        #[arg(long, value_name = "PATTERN")]
+        import_hosts: Vec<String>,
+        /// Use the nameservers and search domains from the host's /etc/resolv.conf
+        /// instead of runtime.dns (loopback nameservers are skipped)
+        #[arg(long)]
+        dns_from_host: bool,
+        /// Supplementary group (GID or name) for the container user (can be specified
+        /// multiple times). Merged with runtime.group_add.
+        /// Example: --group-add video --group-add 989
+        #[arg(long, value_name = "GID|NAME")]
+        group_add: Vec<String>,
+        /// Mount the entire host home read-only (translated to the container home).
+        /// Exposes every file in your home to the container; combine with --no-skip
+        /// so more specific rw mounts still apply to subpaths.
+        #[arg(long = "home-ro", alias = "mount-readonly-home")]
+        home_ro: bool,
+        /// Override the host and container homes used to translate home-relative mounts.
+        /// Format: HOST_HOME:CONTAINER_HOME. Also sets HOME inside the container.
+        /// Example: --home-map /home/me:/root
+        #[arg(long, value_name = "HOST_HOME:CONTAINER_HOME")]
+        home_map: Option<String>,
+        /// Translate home-relative mounts to the container user's home from the
+        /// image's /etc/passwd instead of /home/$USER. Also sets HOME inside the container.
+        #[arg(long, conflicts_with = "home_map")]
+        resolve_home_from_passwd: bool,
         /// Don't skip mounts that are already covered by parent mounts
         #[arg(long)]
         no_skip: bool,
+        /// Fail instead of spawning if any configured or CLI mount is missing on the
+        /// host or matches a skip_mounts pattern, listing each
+        #[arg(long)]
+        require_all_mounts: bool,
+        /// Mount `/`, the home directory, or huge directories read-write without
+        /// asking (see runtime.large_mount_warn_entries)
+        #[arg(long, short = 'y', alias = "confirm-large-mount")]
+        yes: bool,
         /// Network mode to use (e.g. host, bridge, none, or a container name).
         /// Passed directly as --network=<MODE> to the container runtime.
         #[arg(long, value_name = "MODE")]
         network: Option<String>,
+        /// Container image to use instead of runtime.image (and AGENT_BOX_IMAGE)
+        #[arg(long, value_name = "IMAGE")]
+        image: Option<String>,
+        /// Import image, bind mounts, env, ports, and postCreateCommand from a
+        /// devcontainer.json (default: the workspace's .devcontainer/devcontainer.json)
+        #[arg(long, value_name = "PATH", num_args = 0..=1)]
+        devcontainer: Option<Option<PathBuf>>,
+        /// Image pull policy for the preflight image check.
+        /// Implies the check even when runtime.verify_image is not set.
+        #[arg(long, value_enum, value_name = "POLICY")]
+        pull: Option<PullPolicy>,
+        /// Rebuild the profiles' build image even if it exists, e.g. after
+        /// changing files in its build context
+        #[arg(long)]
+        rebuild: bool,
+        /// Copy a host file or directory into the container before it starts (repeatable).
+        /// Format: HOST:CONTAINER. Relative container paths are resolved against the working dir.
+        /// Example: --copy-in ./setup.sh:setup.sh --copy-in ~/.npmrc:/home/me/.npmrc
+        #[arg(long = "copy-in", value_name = "HOST:CONTAINER")]
+        copy_in: Vec<String>,
+        /// Retry up to N times if the runtime fails to start the container with a
+        /// transient error (e.g. daemon not running). Overrides runtime.spawn_retries.
+        #[arg(long, value_name = "N")]
+        retries: Option<u32>,
+        /// Pass env_passthrough variables as PREFIX_NAME=value instead of NAME=value,
+        /// so host values don't clobber the container's own (e.g. HOME as HOST_HOME).
+        #[arg(long, value_name = "PREFIX")]
+        prefix_env: Option<String>,
+        /// Inherit nothing from the host env: skip TERM/COLORTERM/TERMINFO and
+        /// env_passthrough, and fail if any other var would reach the container
+        #[arg(long, conflicts_with = "prefix_env")]
+        clean_env: bool,
+        /// Write the final container env to PATH (stdout if omitted or `-`) as
+        /// KEY=VALUE lines. Secret-like values are masked when stdout is a terminal.
+        #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = "-")]
+        print_env: Option<PathBuf>,
+        /// Write --print-env as `export KEY='VALUE'` lines instead
+        #[arg(long, requires = "print_env")]
+        export_format: bool,
+        /// Exit after --print-env instead of spawning the container
+        #[arg(long, requires = "print_env")]
+        no_spawn: bool,
+        /// Write the fully resolved spawn (image, mounts, env, ports, entrypoint,
+        /// command, backend) to FILE as JSON, for `ab replay`
+        #[arg(long, value_name = "FILE")]
+        record: Option<PathBuf>,
+        /// Print extra diagnostics, such as mount ownership warnings
+        #[arg(long, short)]
+        verbose: bool,
+        /// Don't print the mount summary line before spawning
+        #[arg(long)]
+        quiet: bool,
+        /// Print how long each setup phase took to stderr, or as a `timings` JSON
+        /// object on stdout with `--format json`
+        #[arg(long, alias = "time")]
+        timings: bool,
+        /// After the container exits, watch the global and repo-local config
+        /// and re-spawn whenever they change (Ctrl-C to stop)
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Spawn exactly the configuration written by `ab spawn --record`, ignoring
+    /// the current config and profiles
+    Replay {
+        /// Spawn record file
+        file: PathBuf,
+        /// Retry a failed container start this many times
+        #[arg(long)]
+        retries: Option<u32>,
+    },
+    /// Attach to the running container of a session spawned with `ab spawn --session`
+    Attach {
+        /// Session name
+        #[arg(long, short, add = ArgValueCandidates::new(completions::session_candidates))]
+        session: String,
+        /// Repository identifier, to pick between sessions of the same name in several repos
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+    },
+    /// Show the log of a session started with `ab spawn --detach`
+    Logs {
+        /// Session name
+        #[arg(long, short, add = ArgValueCandidates::new(completions::session_candidates))]
+        session: String,
+        /// Repository identifier, to pick between sessions of the same name in several repos
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Keep printing the log as it grows, until interrupted
+        #[arg(long, short)]
+        follow: bool,
+        /// Only print the last N lines
+        #[arg(long, short = 'n', value_name = "N")]
+        lines: Option<usize>,
+    },
+    /// Stop the running container of a session started with `ab spawn --detach`
+    Stop {
+        /// Session name
+        #[arg(long, short, add = ArgValueCandidates::new(completions::session_candidates))]
+        session: String,
+        /// Repository identifier, to pick between sessions of the same name in several repos
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+    },
+    /// Run a command in the running container of a session, e.g. `ab exec -s main -- cargo test`
+    Exec {
+        /// Session name
+        #[arg(long, short, add = ArgValueCandidates::new(completions::session_candidates))]
+        session: String,
+        /// Repository identifier, to pick between sessions of the same name in several repos
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Working directory in the container (defaults to the one the container was spawned with)
+        #[arg(long)]
+        workdir: Option<String>,
+        /// Extra environment variable for the command (KEY=VALUE, repeatable)
+        #[arg(long = "env", short = 'e', value_name = "KEY=VALUE")]
+        env: Vec<String>,
+        /// Command to run, after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Show the workspace the current directory is in, whether its session container
+    /// is running, and how the current config differs from its last spawn
+    Status {
+        /// Print the mounts a spawn in this session would get, as JSON, without spawning
+        #[arg(long, requires = "session")]
+        mounts_json: bool,
+        /// Session/workspace name to inspect (with --mounts-json)
+        #[arg(long, short, requires = "mounts_json", add = ArgValueCandidates::new(completions::session_candidates))]
+        session: Option<String>,
+        /// Repository identifier (defaults to current directory's git repo)
+        #[arg(long, short, requires = "mounts_json", add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Inspect a git worktree instead of a jj workspace
+        #[arg(long, requires = "mounts_json")]
+        git: bool,
+        /// Profiles to apply, as with `ab spawn -p`
+        #[arg(long, short = 'p', value_name = "PROFILE", requires = "mounts_json", add = ArgValueCompleter::new(completions::profile_candidates))]
+        profile: Vec<String>,
+    },
+    /// List session containers
+    Ps {
+        /// Only list sessions of this repository
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+    },
+    /// List repositories and their session workspaces
+    List {
+        /// Only repos whose path contains this substring (case-insensitive)
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Only git or only jj checkouts and workspaces
+        #[arg(long = "type", value_name = "git|jj", value_parser = parse_workspace_type)]
+        wtype: Option<WorkspaceType>,
+        /// Add a column with the status of each session's container
+        #[arg(long)]
+        with_containers: bool,
+    },
+    /// Remove old session workspaces that have no unsaved work, then prune
+    /// stale git worktree registrations
+    Gc {
+        /// Only repos whose path contains this substring (case-insensitive)
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Only workspaces not modified for this long, e.g. 12h, 7d, 2w
+        /// (default: gc.older_than)
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<u64>,
+        /// Remove without asking
+        #[arg(long)]
+        force: bool,
+        /// Only show what would be removed and pruned
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Manage the named volumes used by `mounts.volumes` and `-m volume:...`
+    Volume {
+        #[command(subcommand)]
+        command: VolumeCommands,
+    },
+    /// Query the audit log of container spawns, oldest first
+    Audit {
+        /// Only spawns of this repository, as recorded (relative to base_repo_dir)
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Only spawns of this session
+        #[arg(long, short, add = ArgValueCandidates::new(completions::session_candidates))]
+        session: Option<String>,
+        /// Only spawns that bind-mounted this host path or something under it
+        #[arg(long, value_name = "PATH")]
+        mount: Option<PathBuf>,
+        /// Only spawns that failed or whose container exited non-zero
+        #[arg(long)]
+        failed: bool,
+        /// Show only the last N matching spawns
+        #[arg(long, short = 'n', value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Export or drop the changes made in a workspace copy from `ab spawn --ephemeral`.
+    /// Prints them as a patch (for `git apply`) by default, keeping the copy.
+    DiffExport {
+        /// Ephemeral workspace id (default: the most recent one)
+        id: Option<String>,
+        /// Write the patch to FILE instead of stdout
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Apply the changes to the original workspace, then remove the copy
+        #[arg(long, conflicts_with = "output")]
+        apply: bool,
+        /// Remove the copy without exporting its changes
+        #[arg(long, conflicts_with_all = ["output", "apply"])]
+        discard: bool,
+        /// List the ephemeral workspaces instead
+        #[arg(long, conflicts_with_all = ["id", "output", "apply", "discard"])]
+        list: bool,
+    },
+    /// Run a spawn alias defined in the [aliases] config table
+    Run {
+        /// Alias name
+        alias: String,
+        /// Extra spawn arguments appended after the alias tokens
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Create a workspace, spawn a command in it, then remove the workspace
+    RunOnce {
+        /// Session/workspace name
+        #[arg(long, short, add = ArgValueCandidates::new(completions::session_candidates))]
+        session: String,
+        /// Repository identifier (defaults to current directory's git repo)
+        #[arg(long, short, add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: Option<String>,
+        /// Command to run in the container (passed to entrypoint)
+        #[arg(long, short, required = true)]
+        command: Vec<String>,
+        /// Create a git worktree instead of a jj workspace
+        #[arg(long)]
+        git: bool,
+        /// Keep the workspace after the container exits
+        #[arg(long)]
+        keep_workspace: bool,
+        /// Remove the workspace even if the spawn fails (kept for debugging by default)
+        #[arg(long, conflicts_with = "keep_workspace")]
+        remove_on_failure: bool,
+        /// Extra `ab spawn` arguments, after `--`
+        #[arg(last = true)]
+        spawn_args: Vec<String>,
+    },
+    /// Spawn a container in every session workspace of a repo and run a command in each
+    Foreach {
+        /// Repository identifier
+        #[arg(add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: String,
+        /// Command to run in each container (passed to entrypoint)
+        #[arg(long, short, required = true)]
+        command: Vec<String>,
+        /// Run up to N workspaces at once (their output interleaves)
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        parallel: u32,
+        /// Extra `ab spawn` arguments, after `--`
+        #[arg(last = true)]
+        spawn_args: Vec<String>,
+    },
+    /// Export a repo's committed history as a bare git clone
+    Export {
+        /// Repository identifier
+        #[arg(add = ArgValueCandidates::new(completions::repo_candidates))]
+        repo: String,
+        /// Path of the bare clone to create
+        dest: PathBuf,
+        /// Export even if tracked files have uncommitted changes (they are left out)
+        #[arg(long)]
+        allow_dirty: bool,
+    },
+    /// Run the allowlist HTTP(S) proxy; `ab spawn` starts it in the sidecar
+    /// of profiles with network.allow_hosts
+    #[command(hide = true)]
+    Proxy {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:3128")]
+        listen: String,
+        /// Host to let through (repeatable); `*.example.com` allows its subdomains
+        #[arg(long = "allow", value_name = "HOST")]
+        allow_hosts: Vec<String>,
+    },
+    /// Print a shell completion script, e.g. `ab completions bash > ~/.local/share/bash-completion/completions/ab`.
+    /// It completes repo, session, and profile names by calling `ab` back.
+    Completions {
+        /// Shell to write the script for
+        #[arg(value_enum)]
+        shell: completions::CompletionShell,
     },
     /// Debug commands (hidden from main help)
     #[command(hide = true)]
@@ -146,16 +842,64 @@ enum Commands {
     },
 }
 
-#[derive(Subcommand)]
+#[derive(Subcommand, Clone)]
+enum ConfigCommands {
+    /// Print a value from the merged config (e.g. runtime.image)
+    Get {
+        /// Dotted key path
+        key: String,
+    },
+    /// Set a value in ~/.agent-box.toml, creating tables as needed, then validate
+    Set {
+        /// Dotted key path (e.g. runtime.image or profiles.rust.env)
+        key: String,
+        /// TOML value (e.g. 3, true, ["a", "b"]); anything else is set as a string
+        value: String,
+        /// Write the repo-local .agent-box.toml instead of ~/.agent-box.toml
+        #[arg(long)]
+        repo: bool,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+enum VolumeCommands {
+    /// List the volumes agent-box created and the ones the config uses
+    Ls,
+    /// Remove volumes agent-box created, deleting their contents
+    Rm {
+        /// Volume names
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Clone)]
 enum DbgCommands {
     /// Locate a repository by partial path match (or list all if no search given)
     Locate {
         /// Repository search string (e.g., "agent-box" or "fr/agent-box")
+        #[arg(add = ArgValueCandidates::new(completions::repo_candidates))]
         repo: Option<String>,
     },
+    /// List discovered repositories
+    List {
+        /// Only show repos whose path contains this substring (case-insensitive)
+        #[arg(long)]
+        filter: Option<String>,
+        /// Show at most this many repos
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Print only the number of repos and their git worktrees and jj workspaces
+        #[arg(long, conflicts_with = "json_lines")]
+        count: bool,
+        /// Stream one JSON object per repo as it is discovered (unsorted)
+        #[arg(long)]
+        json_lines: bool,
+    },
     /// Remove all workspaces for a given repo ID
     Remove {
         /// Repository identifier (e.g., "fr/agent-box" or "agent-box")
+        #[arg(add = ArgValueCandidates::new(completions::repo_candidates))]
         repo: String,
         /// Show what would be deleted without actually deleting
         #[arg(long)]
@@ -165,14 +909,50 @@ enum DbgCommands {
         force: bool,
     },
     /// Validate configuration (profiles, extends, default_profile)
-    Validate,
+    Validate {
+        /// Remove empty profiles and dangling `extends` entries from the config
+        /// files that define them (keeping `.bak` copies), printing a diff
+        #[arg(long)]
+        fix: bool,
+    },
+    /// List profiles with their extends and mount counts
+    Profiles {
+        /// Show the extends graph as an indented tree
+        #[arg(long)]
+        tree: bool,
+        /// Print the extends adjacency (profile -> extended profiles) as JSON
+        #[arg(long, conflicts_with = "tree")]
+        json: bool,
+    },
     /// Show resolved/merged configuration from profiles
     Resolve {
-        /// Profiles to apply (can be specified multiple times).
+        /// Profiles to apply (can be specified multiple times or comma-separated).
         /// If none specified, shows resolution with just default_profile (if set).
-        /// Example: -p git -p rust
-        #[arg(long, short = 'p', value_name = "PROFILE")]
+        /// Example: -p git -p rust or -p git,rust
+        #[arg(long, short = 'p', value_name = "PROFILE", add = ArgValueCompleter::new(completions::profile_candidates))]
         profile: Vec<String>,
+        /// Show every mount before deduplication, annotated with its source profile
+        #[arg(long)]
+        no_dedup: bool,
+    },
+    /// Freeze the merged, path-expanded configuration into a lockfile
+    Freeze {
+        /// Write the lockfile here instead of printing it to stdout
+        #[arg(long, short)]
+        out: Option<PathBuf>,
+    },
+    /// Compare the current configuration against a lockfile from `dbg freeze`
+    VerifyFreeze {
+        /// Lockfile written by `ab dbg freeze`
+        lockfile: PathBuf,
+    },
+    /// Rewrite a legacy `[docker]` config section into the `[runtime]` shape
+    MigrateConfig {
+        /// Config file to migrate (defaults to ~/.agent-box.toml)
+        path: Option<PathBuf>,
+        /// Rewrite the file in place, keeping a `.bak` copy, instead of printing to stdout
+        #[arg(long)]
+        in_place: bool,
     },
     /// Check if a path exists in a container image
     CheckPath {
@@ -181,6 +961,11 @@ enum DbgCommands {
         /// Path to check in the image (e.g., "/nix/store")
         path: String,
     },
+    /// Warn if the image's default user differs from the uid:gid `ab spawn` runs as
+    CheckUser {
+        /// Container image to inspect (defaults to runtime.image)
+        image: Option<String>,
+    },
     /// List all paths (directories) in a container image
     ListPaths {
         /// Container image to inspect (e.g., "nixos/nix:latest")
@@ -195,6 +980,10 @@ enum DbgCommands {
 }
 
 fn main() {
+    // Answers the shell when the script from `ab completions` calls back
+    clap_complete::CompleteEnv::with_factory(Cli::command)
+        .var(completions::COMPLETE_VAR)
+        .complete();
     if let Err(e) = run() {
         eprintln!("Error: {}", e);
         std::process::exit(1);
@@ -203,52 +992,673 @@ fn main() {
 
 fn run() -> eyre::Result<()> {
     let cli = Cli::parse();
+    if cli.no_interactive {
+        agent_box_common::prompt::set_non_interactive(true);
+    }
+    if cli.no_global_config {
+        agent_box_common::config::set_no_global_config(true);
+    }
+    if cli.format == OutputFormat::Json {
+        agent_box_common::display::set_json_output(true);
+    }
+    // Editing must work even when the current config doesn't load
+    match cli.command {
+        Commands::Edit { repo } => return run_edit(repo),
+        Commands::Config {
+            command: ConfigCommands::Set { key, value, repo },
+        } => return run_config_set(&key, &value, repo),
+        Commands::Replay { file, retries } => return run_replay(&file, retries),
+        // Runs in a sidecar container, which has no config
+        Commands::Proxy {
+            listen,
+            allow_hosts,
+        } => return runtime::egress::serve(&listen, allow_hosts),
+        // The log is readable even when the config isn't
+        Commands::Audit {
+            repo,
+            session,
+            mount,
+            failed,
+            limit,
+        } => return run_audit(repo, session, mount, failed, limit),
+        Commands::DiffExport {
+            id,
+            output,
+            apply,
+            discard,
+            list,
+        } => return run_diff_export(id, output, apply, discard, list),
+        Commands::Completions { shell } => {
+            return completions::write_registration(shell, &mut std::io::stdout());
+        }
+        _ => {}
+    }
+
+    let config_load_start = Instant::now();
     let config = load_config()?;
+    let config_load_time = config_load_start.elapsed();
 
-    match cli.command {
-        Commands::Info => {
-            info(&config)?;
+    run_command(cli.command, &config, config_load_time)
+}
+
+/// `ab volume ls` / `ab volume rm`
+fn run_volume(config: &Config, command: VolumeCommands) -> Result<()> {
+    let container_runtime = create_runtime(config);
+    let created = container_runtime.list_volumes()?;
+    match command {
+        VolumeCommands::Ls => {
+            // Volume names to the config layers using them
+            let mut used_by: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            let layers = std::iter::once(("runtime", &config.runtime.mounts)).chain(
+                config
+                    .profiles
+                    .iter()
+                    .map(|(name, profile)| (name.as_str(), &profile.mounts)),
+            );
+            for (layer, mounts) in layers {
+                for spec in &mounts.volumes {
+                    if let Ok(volume) = spec.parse::<VolumeSpec>() {
+                        let users = used_by.entry(volume.name).or_default();
+                        if !users.iter().any(|u| u == layer) {
+                            users.push(layer.to_string());
+                        }
+                    }
+                }
+            }
+            let rows = runtime::volume::volume_rows(&created, used_by);
+            if is_json_output() {
+                println!("{}", serde_json::to_string_pretty(&rows)?);
+            } else {
+                runtime::volume::write_volume_table(&rows, &mut std::io::stdout())?;
+            }
+        }
+        VolumeCommands::Rm { names } => {
+            if let Some(name) = names.iter().find(|name| !created.contains(name)) {
+                bail!(
+                    "Volume '{}' wasn't created by agent-box (no {} label); remove it with the runtime directly",
+                    name,
+                    runtime::volume::VOLUME_LABEL
+                );
+            }
+            for name in &names {
+                container_runtime.remove_volume(name)?;
+                eprintln!("Removed volume {}", name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `ab status`: the workspace the current directory is in, its session
+/// container, and the drift between its last spawn and the current config
+/// `--mounts-json` of `ab info` and `ab status`: the mounts a spawn in `session`
+/// would get, built without spawning
+fn print_mounts_json(
+    config: &Config,
+    repo: Option<&str>,
+    session: &str,
+    git: bool,
+    profiles: &[String],
+) -> Result<()> {
+    let wtype = if git {
+        WorkspaceType::Git
+    } else {
+        WorkspaceType::Jj
+    };
+    let container_config = dry_container_config(config, repo, session, wtype, profiles)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&runtime::binds_json(&container_config.mounts))?
+    );
+    Ok(())
+}
+
+fn run_status(config: &Config) -> Result<()> {
+    let cwd = std::env::current_dir()?;
+    let entry = agent_box_common::repo::workspace_at(config, &cwd)?.ok_or_else(|| {
+        eyre::eyre!(
+            "{} is not in a session workspace or source checkout",
+            cwd.display()
+        )
+    })?;
+    let Some(session) = entry.session.as_deref() else {
+        if is_json_output() {
+            println!("{}", serde_json::to_string_pretty(&entry)?);
+        } else {
+            println!("Workspace:  {} source checkout", entry.repo);
+            println!("Path:       {}", entry.path.display());
+        }
+        return Ok(());
+    };
+
+    let container = create_runtime(config)
+        .list_sessions()
+        .map(|containers| runtime::session::entry_container(&entry, &containers).cloned());
+    let last = state::WorkspaceState::read(&entry.path)?;
+    // The recorded -p profiles under the current default_profile, as a spawn now would use
+    let drift = last.as_ref().map(|last| {
+        match dry_container_config(
+            config,
+            Some(&entry.repo),
+            session,
+            entry.wtype,
+            &last.profiles,
+        ) {
+            Ok(current) => {
+                let mut current = state::WorkspaceState::new(
+                    &current,
+                    config.default_profile.as_deref(),
+                    &last.profiles,
+                    last.spawned,
+                );
+                // The image as `ab spawn` picks it, without --image
+                current.image = match resolve_build(config, &last.profiles) {
+                    Ok(Some(build)) => build.image_tag().unwrap_or_default(),
+                    _ => runtime::select_image(
+                        None,
+                        |name| std::env::var(name).ok(),
+                        &config.runtime.image,
+                    ),
+                };
+                last.drift(&current)
+            }
+            Err(e) => vec![format!("current config doesn't resolve: {}", e)],
+        }
+    });
+
+    if is_json_output() {
+        let mut value = serde_json::to_value(&entry)?;
+        value["container"] = match &container {
+            Ok(Some(c)) => serde_json::json!({"name": c.name, "status": c.status}),
+            Ok(None) => serde_json::Value::Null,
+            Err(e) => serde_json::json!({"error": e.to_string()}),
+        };
+        value["last_spawn"] = serde_json::to_value(&last)?;
+        value["drift"] = serde_json::to_value(&drift)?;
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+
+    println!(
+        "Workspace:  {} {} session {}",
+        entry.repo,
+        entry.wtype.as_str(),
+        session
+    );
+    println!("Path:       {}", entry.path.display());
+    match &container {
+        Ok(Some(c)) => println!("Container:  {} ({})", c.name, c.status),
+        Ok(None) => println!("Container:  none"),
+        Err(e) => println!("Container:  unknown ({})", e),
+    }
+    let (Some(last), Some(drift)) = (&last, &drift) else {
+        println!("Last spawn: never (no {})", state::STATE_FILE);
+        return Ok(());
+    };
+    let profiles: Vec<&str> = last
+        .default_profile
+        .iter()
+        .chain(&last.profiles)
+        .map(String::as_str)
+        .collect();
+    println!(
+        "Last spawn: {} with {}{}",
+        runtime::audit::format_timestamp(last.spawned),
+        last.image,
+        if profiles.is_empty() {
+            String::new()
+        } else {
+            format!(", profiles {}", profiles.join(", "))
+        }
+    );
+    if drift.is_empty() {
+        println!("Drift:      none");
+    } else {
+        println!("Drift:");
+        for line in drift {
+            println!("  {}", line);
+        }
+    }
+    Ok(())
+}
+
+/// `ab gc`: remove the session workspaces [`gc_keep_reason`] doesn't keep and
+/// that have no session container, after confirmation unless `force`
+fn run_gc(
+    config: &Config,
+    repo: Option<&str>,
+    older_than: Option<u64>,
+    force: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let older_than = match older_than {
+        Some(age) => age,
+        None => {
+            parse_age(&config.gc.older_than).map_err(|e| eyre::eyre!("gc.older_than: {}", e))?
+        }
+    };
+    let repos = list_repos(config, repo, None)?;
+    let containers = create_runtime(config).list_sessions().unwrap_or_else(|e| {
+        eprintln!(
+            "WARNING: Can't list session containers, so workspaces in use may be removed: {}",
+            e
+        );
+        Vec::new()
+    });
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut removable = Vec::new();
+    for entry in list_entries(config, &repos, None) {
+        let Some(session) = &entry.session else {
+            continue;
+        };
+        let label = format!("{} {}/{}", entry.repo, entry.wtype.as_str(), session);
+        let reason = runtime::session::entry_container(&entry, &containers)
+            .map(|c| format!("session container {}", c.name))
+            .or_else(|| gc_keep_reason(&entry, older_than, now));
+        match reason {
+            Some(reason) => println!("keep    {}  ({})", label, reason),
+            None => {
+                println!("remove  {}", label);
+                removable.push((label, entry));
+            }
+        }
+    }
+
+    if dry_run {
+        println!("Dry run: nothing removed");
+    } else if removable.is_empty() {
+        println!("No workspaces to remove");
+    } else {
+        if !force {
+            agent_box_common::prompt::require_interactive(
+                "confirmation required; pass --force to remove without prompting",
+            )?;
+            let labels: Vec<String> = removable.iter().map(|(label, _)| label.clone()).collect();
+            let selected = inquire::MultiSelect::new("Remove these workspaces?", labels)
+                .with_all_selected_by_default()
+                .prompt()
+                .unwrap_or_default();
+            removable.retain(|(label, _)| selected.contains(label));
+            if removable.is_empty() {
+                println!("Nothing selected");
+            }
+        }
+
+        let mut failed = 0;
+        for (label, entry) in &removable {
+            let repo_id = agent_box_common::path::RepoIdentifier {
+                relative_path: PathBuf::from(&entry.repo),
+            };
+            let session = entry.session.as_deref().unwrap_or_default();
+            if let Err(e) = remove_workspace(config, &repo_id, entry.wtype, session) {
+                eprintln!("Failed to remove {}: {}", label, e);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            bail!("{} workspace(s) could not be removed", failed);
+        }
+    }
+
+    for repo_id in &repos {
+        if !repo_id.source_path(config).join(".git").exists() {
+            continue;
+        }
+        let pruned = prune_git_worktrees(config, repo_id, dry_run)?;
+        if !pruned.is_empty() {
+            println!(
+                "{}:\n  {}",
+                repo_id.relative_path().display(),
+                pruned.replace('\n', "\n  ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `--type` value: `git` or `jj`
+fn parse_workspace_type(s: &str) -> Result<WorkspaceType, String> {
+    match s {
+        "git" => Ok(WorkspaceType::Git),
+        "jj" => Ok(WorkspaceType::Jj),
+        _ => Err(format!("expected git or jj, not '{}'", s)),
+    }
+}
+
+/// A `--repo` argument as the repo label value stamped on session containers
+fn repo_label(config: &Config, repo: Option<&str>) -> Result<Option<String>> {
+    repo.map(|r| resolve_repo_id(config, Some(r)))
+        .transpose()
+        .map(|id| id.map(|id| id.relative_path().display().to_string()))
+}
+
+/// `ab replay`: spawn a recorded configuration without loading the config
+fn run_replay(file: &Path, retries: Option<u32>) -> Result<()> {
+    let record = runtime::SpawnRecord::read(file)?;
+    record
+        .runtime()
+        .spawn_container_with_retries(&record.container, retries.unwrap_or(0))
+}
+
+/// `ab audit`: print the audit log entries matching the filters
+fn run_audit(
+    repo: Option<String>,
+    session: Option<String>,
+    mount: Option<PathBuf>,
+    failed: bool,
+    limit: Option<usize>,
+) -> Result<()> {
+    let path = runtime::audit::audit_log_path()
+        .ok_or_else(|| eyre::eyre!("Can't locate the audit log: HOME is not set"))?;
+    // Recorded host paths may or may not have symlinks resolved, so try both
+    let mount = mount
+        .map(|m| -> Result<Vec<PathBuf>> {
+            let resolved = expand_path(&m)?;
+            let literal = std::path::absolute(&m)?;
+            Ok(if m.starts_with("~") || literal == resolved {
+                vec![resolved]
+            } else {
+                vec![literal, resolved]
+            })
+        })
+        .transpose()?;
+    let mut entries: Vec<_> = runtime::audit::read_audit_log(&path)?
+        .into_iter()
+        .filter(|e| repo.is_none() || e.repo == repo)
+        .filter(|e| session.is_none() || e.session == session)
+        .filter(|e| {
+            mount
+                .as_ref()
+                .is_none_or(|paths| paths.iter().any(|m| e.mounts_path(m)))
+        })
+        .filter(|e| !failed || e.failed())
+        .collect();
+    if let Some(limit) = limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+    if is_json_output() {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        runtime::audit::write_audit_table(&entries, &mut std::io::stdout())?;
+    }
+    Ok(())
+}
+
+/// `ab diff-export`: print, apply, or drop the changes in an `ab spawn --ephemeral` copy
+fn run_diff_export(
+    id: Option<String>,
+    output: Option<PathBuf>,
+    apply: bool,
+    discard: bool,
+    list: bool,
+) -> Result<()> {
+    let root = ephemeral::ephemeral_root()
+        .ok_or_else(|| eyre::eyre!("Can't find a state directory: HOME is not set"))?;
+    if list {
+        let copies = ephemeral::list_ephemeral(&root)?;
+        if is_json_output() {
+            let copies: Vec<_> = copies
+                .iter()
+                .map(|(copy, meta)| {
+                    serde_json::json!({
+                        "id": copy.id,
+                        "source": meta.source,
+                        "created": meta.created,
+                        "path": copy.workspace(),
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&copies)?);
+        } else if copies.is_empty() {
+            println!("No ephemeral workspaces");
+        } else {
+            for (copy, meta) in copies {
+                println!(
+                    "{}  {}  {}",
+                    copy.id,
+                    runtime::audit::format_timestamp(meta.created),
+                    meta.source.display()
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    let copy = match id {
+        Some(id) => ephemeral::Ephemeral::open(&root, &id)?,
+        None => ephemeral::list_ephemeral(&root)?
+            .pop()
+            .map(|(copy, _)| copy)
+            .ok_or_else(|| eyre::eyre!("No ephemeral workspaces in {}", root.display()))?,
+    };
+    if discard {
+        copy.discard()?;
+        eprintln!("Discarded ephemeral workspace {}", copy.id);
+        return Ok(());
+    }
+
+    let patch = copy.diff()?;
+    if apply {
+        let source = copy.meta()?.source;
+        if patch.is_empty() {
+            eprintln!("No changes in ephemeral workspace {}", copy.id);
+        } else {
+            ephemeral::apply_patch(&source, &patch)?;
+            eprintln!(
+                "Applied the changes from {} to {}",
+                copy.id,
+                source.display()
+            );
+        }
+        copy.discard()?;
+        return Ok(());
+    }
+    match output {
+        Some(path) => {
+            std::fs::write(&path, &patch)
+                .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+            eprintln!("Wrote the changes from {} to {}", copy.id, path.display());
+        }
+        None => std::io::stdout().write_all(&patch)?,
+    }
+    Ok(())
+}
+
+/// `ab config set`: write one key to the global or repo-local config and validate
+fn run_config_set(key: &str, value: &str, repo: bool) -> Result<()> {
+    let (global, repo_path) = config_layer_paths()?;
+    let path = if repo {
+        repo_path.ok_or_else(|| eyre::eyre!("--repo needs to run inside a git repo"))?
+    } else {
+        global
+    };
+
+    let result = edit::set_config_file(&path, key, value, load_config)?;
+    print_validation_issues(&result);
+    eprintln!("Set {} in {}", key, path.display());
+    Ok(())
+}
+
+/// `ab spawn --watch`: spawn, then re-spawn with the reloaded config whenever
+/// a config layer changes
+fn run_watch(mut command: Commands, config_load_time: Duration) -> Result<()> {
+    let Commands::Spawn { watch, .. } = &mut command else {
+        unreachable!("--watch is a spawn flag");
+    };
+    *watch = false;
+
+    let (global_path, repo_path) = config_layer_paths()?;
+    let paths: Vec<PathBuf> = std::iter::once(global_path).chain(repo_path).collect();
+    let mut source = watch::NotifySource::new(&paths)?;
+
+    let load = || {
+        let config = load_config()?;
+        validate_config_or_err(&config)?;
+        Ok(config)
+    };
+    watch::watch_loop(
+        load()?,
+        &mut source,
+        watch::DEBOUNCE,
+        || watch::read_snapshot(&paths),
+        load,
+        |config| {
+            let result = run_command(command.clone(), config, config_load_time);
+            // The workspace exists after the first spawn
+            if let Commands::Spawn { new, .. } = &mut command {
+                *new = false;
+            }
+            result
+        },
+    )
+}
+
+fn run_command(command: Commands, config: &Config, config_load_time: Duration) -> Result<()> {
+    if let Commands::Spawn { watch: true, .. } = command {
+        return run_watch(command, config_load_time);
+    }
+
+    match command {
+        Commands::Config {
+            command: ConfigCommands::Get { key },
+        } => println!("{}", edit::get_config_value(config, &key)?),
+        Commands::Info {
+            mounts_json,
+            session,
+            repo,
+            git,
+            profile,
+        } => {
+            if mounts_json {
+                let session = session.expect("--mounts-json requires --session");
+                print_mounts_json(config, repo.as_deref(), &session, git, &profile)?;
+            } else if is_json_output() {
+                println!("{}", serde_json::to_string_pretty(&repo_info(config)?)?);
+            } else {
+                info(config)?;
+            }
         }
         Commands::New {
             repo_name,
             session,
             git,
             jj,
+            bookmark,
+            print_path,
+            show_diff_stat,
+            auto_rename,
         } => {
+            // Neither flag: detect from the source repo
             let workspace_type = if git {
-                WorkspaceType::Git
+                Some(WorkspaceType::Git)
             } else if jj {
-                WorkspaceType::Jj
+                Some(WorkspaceType::Jj)
             } else {
-                // Default to jj if neither specified
-                WorkspaceType::Jj
+                None
             };
 
-            new_workspace(
-                &config,
+            let mut progress: Box<dyn std::io::Write> = if print_path {
+                Box::new(std::io::stderr())
+            } else {
+                Box::new(std::io::stdout())
+            };
+            let workspace_path = new_workspace(
+                config,
                 repo_name.as_deref(),
                 session.as_deref(),
                 workspace_type,
+                bookmark.as_deref(),
+                show_diff_stat.as_deref(),
+                auto_rename,
+                &mut progress,
             )?;
+            if print_path {
+                println!("{}", workspace_path.display());
+            }
         }
         Commands::Spawn {
             repo,
             session,
             local,
+            detach,
             entrypoint,
+            use_image_entrypoint,
+            init_script,
             command,
             git,
             jj: _,
             new: create_new,
             ro,
-            mount,
-            mount_abs,
+            source_ro,
+            no_workdir_mount,
+            workdir,
+            ephemeral,
+            mut mount,
+            mut mount_abs,
             profile,
             port,
-            add_host,
+            mut add_host,
+            import_hosts,
+            dns_from_host,
+            group_add,
+            home_ro,
+            home_map,
+            resolve_home_from_passwd,
             no_skip,
+            require_all_mounts,
+            yes,
             network,
+            image,
+            devcontainer,
+            pull,
+            rebuild,
+            copy_in,
+            retries,
+            prefix_env,
+            clean_env,
+            print_env,
+            export_format,
+            no_spawn,
+            record,
+            verbose,
+            quiet,
+            timings: show_timings,
+            watch: _,
         } => {
+            let mut timings = Timings::default();
+            timings.add("config load", config_load_time);
+            let profile = split_profile_args(&profile)?;
+
+            let cli_image = image;
+            let mut image = runtime::select_image(
+                cli_image.as_deref(),
+                |name| std::env::var(name).ok(),
+                &config.runtime.image,
+            );
+
+            // A profile's build beats AGENT_BOX_IMAGE and runtime.image; --image beats it
+            let mut build = if cli_image.as_deref().is_none_or(str::is_empty) {
+                resolve_build(config, &profile)?
+            } else {
+                None
+            };
+            if let Some(build) = &build {
+                image = build.image_tag()?;
+            } else if rebuild {
+                bail!("--rebuild needs a profile with a [profiles.NAME.build] section");
+            }
+
+            // Check the image up front so a typo fails before any setup.
+            // A devcontainer may pick another image, so that's checked once it's read.
+            let verify_image = config.runtime.verify_image || pull.is_some();
+            if devcontainer.is_none() {
+                ensure_spawn_image(config, &image, build.as_ref(), rebuild, verify_image, pull)?;
+            }
+
             let wtype = if git {
                 WorkspaceType::Git
             } else {
@@ -256,87 +1666,775 @@ fn run() -> eyre::Result<()> {
             };
 
             // Build container configuration
-            let (workspace_path, source_path) = if local {
+            let (workspace_path, source_path, repo_rel) = if local {
                 // In local mode, prefer the enclosing git root if one exists.
                 // Otherwise, use the current directory directly.
                 // No base_repo_dir lookup is required.
                 let cwd = std::env::current_dir()?;
                 let path = agent_box_common::repo::find_git_root().unwrap_or(cwd);
-                (path.clone(), path)
+                (path.clone(), path, None)
             } else {
                 // In session mode, we need a valid repo_id in base_repo_dir
-                // Create workspace first if --new flag is set
-                if create_new {
-                    let session_name = session
-                        .as_ref()
-                        .expect("session required when --new is set");
-                    new_workspace(&config, repo.as_deref(), Some(session_name), wtype)?;
+                let repo_id = resolve_repo_id(config, repo.as_deref())?;
+                let session_name = session.as_ref().expect("session required");
+                let workspace_path = repo_id.workspace_path(config, wtype, session_name);
+
+                // Catch --git/--jj mix-ups before treating the workspace as missing
+                if !create_new {
+                    let other = match wtype {
+                        WorkspaceType::Git => WorkspaceType::Jj,
+                        WorkspaceType::Jj => WorkspaceType::Git,
+                    };
+                    runtime::check_workspace_type(
+                        wtype,
+                        session_name,
+                        &workspace_path,
+                        &repo_id.workspace_path(config, other, session_name),
+                    )?;
                 }
 
-                // Resolve repo_id from repo argument
-                let repo_id = resolve_repo_id(&config, repo.as_deref())?;
-                let session_name = session.as_ref().expect("session required");
-                let workspace_path = repo_id.workspace_path(&config, wtype, session_name);
-                let source_path = repo_id.source_path(&config);
-                (workspace_path, source_path)
+                // Create the workspace first with --new, or per runtime.on_missing_workspace
+                let interactive = !agent_box_common::prompt::is_non_interactive()
+                    && std::io::stdin().is_terminal();
+                if runtime::should_create_workspace(
+                    config.runtime.on_missing_workspace,
+                    create_new,
+                    &workspace_path,
+                    interactive,
+                    || {
+                        Ok(inquire::Confirm::new(&format!(
+                            "Workspace {} does not exist. Create it?",
+                            workspace_path.display()
+                        ))
+                        .with_default(true)
+                        .prompt()?)
+                    },
+                )? {
+                    new_workspace(
+                        config,
+                        repo.as_deref(),
+                        Some(session_name),
+                        Some(wtype),
+                        None,
+                        None,
+                        false,
+                        &mut std::io::stdout(),
+                    )?;
+                }
+                let source_path = repo_id.source_path(config);
+                let repo_rel = repo_id.relative_path().display().to_string();
+                (workspace_path, source_path, Some(repo_rel))
+            };
+
+            // Host-side {session}/{repo}/{workspace} expansion, before the shell sees them
+            let placeholders = runtime::SpawnPlaceholders {
+                session: session.as_deref().filter(|_| !local),
+                repo: repo_rel.as_deref(),
+                workspace: &workspace_path,
+            };
+            let entrypoint = entrypoint
+                .as_deref()
+                .map(|e| placeholders.expand(e))
+                .transpose()?;
+            let command = command
+                .map(|tokens| {
+                    tokens
+                        .iter()
+                        .map(|t| placeholders.expand(t))
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?;
+
+            let image_workdir = if no_workdir_mount {
+                let dir = workdir.or_else(|| config.runtime.working_dir.clone());
+                Some(dir.ok_or_else(|| {
+                    eyre::eyre!("--no-workdir-mount needs --workdir or runtime.working_dir")
+                })?)
+            } else {
+                None
+            };
+
+            let devcontainer = match devcontainer {
+                Some(path) => {
+                    let path = match path {
+                        Some(path) => path,
+                        None => find_devcontainer(&workspace_path).ok_or_else(|| {
+                            eyre::eyre!(
+                                "No .devcontainer/devcontainer.json or .devcontainer.json in {}",
+                                workspace_path.display()
+                            )
+                        })?,
+                    };
+                    let container_workspace = match &image_workdir {
+                        Some(dir) => PathBuf::from(dir),
+                        None => runtime::RootPrefix::from_config(&config.runtime)?
+                            .apply(&workspace_path),
+                    };
+                    let devcontainer = load_devcontainer(
+                        &path,
+                        &DevcontainerPaths {
+                            local_workspace: &workspace_path,
+                            container_workspace: &container_workspace,
+                        },
+                    )?;
+                    for warning in &devcontainer.warnings {
+                        eprintln!("WARNING: {}", warning);
+                    }
+                    // --image still wins; the devcontainer's beats profile builds,
+                    // AGENT_BOX_IMAGE, and runtime.image
+                    if let Some(devcontainer_image) = &devcontainer.image
+                        && cli_image.as_deref().is_none_or(str::is_empty)
+                    {
+                        image = devcontainer_image.clone();
+                        if build.take().is_some() {
+                            eprintln!(
+                                "WARNING: Using the devcontainer image '{}' instead of building the profile image",
+                                image
+                            );
+                        }
+                    }
+                    ensure_spawn_image(
+                        config,
+                        &image,
+                        build.as_ref(),
+                        rebuild,
+                        verify_image,
+                        pull,
+                    )?;
+                    Some(devcontainer)
+                }
+                None => None,
             };
 
             // Validate config before resolving profiles
-            validate_config_or_err(&config)?;
+            timings.record("validation", || validate_config_or_err(config))?;
 
             // Resolve profiles (default + CLI-specified)
-            let resolved_profile = resolve_profiles(&config, &profile)?;
+            // Shared by profile dedup and bind construction so each mount resolves once
+            let mut mount_cache = MountResolutionCache::default();
+            let mut resolved_profile = timings.record("profile resolution", || {
+                resolve_profiles_with_cache(config, &profile, &mut mount_cache)
+            })?;
+            if let Some(devcontainer) = &devcontainer {
+                resolved_profile.merge(&devcontainer.profile);
+                resolved_profile.dedup_mounts_with(&mut mount_cache);
+            }
+
+            // Prefixed passthrough variables become plain env entries
+            if let Some(prefix) = &prefix_env {
+                let passthrough = std::mem::take(&mut resolved_profile.env_passthrough);
+                resolved_profile.env.extend(runtime::passthrough_env(
+                    &passthrough,
+                    Some(prefix),
+                    |name| std::env::var(name).ok(),
+                ));
+            }
+
+            if !import_hosts.is_empty() {
+                let etc_hosts = std::fs::read_to_string("/etc/hosts")
+                    .wrap_err("Failed to read /etc/hosts for --import-hosts")?;
+                let existing: Vec<String> = resolved_profile
+                    .hosts
+                    .iter()
+                    .chain(&add_host)
+                    .cloned()
+                    .collect();
+                add_host.extend(runtime::import_hosts(&etc_hosts, &import_hosts, &existing)?);
+            }
 
-            // Parse CLI mount arguments
-            let cli_mounts = runtime::parse_cli_mounts(&mount, &mount_abs)?;
+            // Parse CLI mount arguments; tmpfs and volume mounts have no host side,
+            // so they join the profiles' instead of the binds
+            let cli_tmpfs = runtime::take_cli_tmpfs(&mut mount, &mut mount_abs)?;
+            resolved_profile.tmpfs.extend(cli_tmpfs);
+            resolved_profile.dedup_tmpfs();
+            let cli_volumes = runtime::take_cli_volumes(&mut mount, &mut mount_abs)?;
+            resolved_profile.volumes.extend(cli_volumes);
+            resolved_profile.dedup_volumes();
+            let mut cli_mounts = runtime::parse_cli_mounts(&mount, &mount_abs)?;
+            let home_map = match home_map.as_deref() {
+                Some(arg) => Some(runtime::parse_home_map(arg)?),
+                None if resolve_home_from_passwd => {
+                    let mut homes = runtime::HomeMap::from_env()?;
+                    homes.container = create_runtime(config).passwd_home(
+                        &image,
+                        &runtime::env_user_name(),
+                        nix::unistd::getuid().as_raw(),
+                    )?;
+                    Some(homes)
+                }
+                None => None,
+            };
+            if home_ro {
+                eprintln!(
+                    "WARNING: --home-ro exposes your entire home directory (including keys and tokens) to the container, and may be slow for large homes"
+                );
+                cli_mounts.insert(0, runtime::home_ro_mount());
+            }
+
+            if verbose {
+                let homes = match &home_map {
+                    Some(homes) => homes.clone(),
+                    None => runtime::HomeMap::from_env()?,
+                };
+                let all_mounts: Vec<_> =
+                    resolved_profile.mounts.iter().chain(&cli_mounts).collect();
+                for warning in runtime::userns_overlay_warnings(
+                    &runtime::BackendCapabilities::for_backend(&config.runtime.backend),
+                    &all_mounts,
+                    &homes,
+                ) {
+                    eprintln!("{}", warning);
+                }
+            }
 
-            let managed_portal = maybe_start_managed_portal(&config)?;
+            let managed_portal = maybe_start_managed_portal(config)?;
             let portal_socket_override = managed_portal.as_ref().map(|p| p.socket_path());
 
-            let container_config = match build_container_config(
-                &config,
-                &workspace_path,
-                &source_path,
-                local,
-                ro,
-                entrypoint.as_deref(),
-                &resolved_profile,
-                &cli_mounts,
-                &port,
-                &add_host,
-                portal_socket_override,
-                command,
-                !no_skip,
-                network,
-            ) {
+            let mut container_config = match timings.record("mount building", || {
+                build_container_config(
+                    config,
+                    &workspace_path,
+                    &source_path,
+                    local,
+                    ro,
+                    source_ro || ephemeral,
+                    clean_env,
+                    entrypoint.as_deref(),
+                    &resolved_profile,
+                    &cli_mounts,
+                    &port,
+                    &add_host,
+                    &group_add,
+                    portal_socket_override,
+                    command,
+                    !no_skip,
+                    network,
+                    image_workdir.as_deref(),
+                    home_map.as_ref(),
+                    &mut mount_cache,
+                )
+            }) {
                 Ok(cfg) => cfg,
-                Err(e) => {
-                    eprintln!("Error building container config: {}", e);
-                    std::process::exit(1);
+                Err(e) => bail!("Error building container config: {}", e),
+            };
+
+            container_config.image = image;
+            if let (Some(session), Some(repo)) = (session.as_deref().filter(|_| !local), &repo_rel)
+            {
+                container_config.labels = runtime::session_labels(session, repo);
+                if detach {
+                    container_config.name = runtime::session::session_container_name(repo, session);
+                    container_config.detach = true;
+                }
+            }
+            container_config.copy_in = copy_in
+                .iter()
+                .map(|arg| runtime::parse_copy_in(arg, &container_config.working_dir))
+                .collect::<Result<_>>()?;
+
+            let post_create = devcontainer.and_then(|d| d.post_create_command);
+            if use_image_entrypoint {
+                if post_create.is_some() {
+                    bail!(
+                        "--use-image-entrypoint can't be combined with a devcontainer \
+                         postCreateCommand, which replaces the entrypoint"
+                    );
+                }
+                if config.runtime.init_script.is_some() {
+                    bail!(
+                        "--use-image-entrypoint can't be combined with runtime.init_script, \
+                         which replaces the entrypoint"
+                    );
                 }
+                container_config.entrypoint = None;
+            }
+
+            if let Some(post_create) = &post_create {
+                let (entrypoint, command) = runtime::wrap_with_post_create(
+                    container_config.entrypoint.take(),
+                    container_config.command.take(),
+                    post_create,
+                );
+                container_config.entrypoint = Some(entrypoint);
+                container_config.command = Some(command);
+            }
+            if let Some(script) = init_script.or_else(|| config.runtime.init_script.clone()) {
+                runtime::apply_init_script(&mut container_config, &script)?;
+            }
+
+            if dns_from_host {
+                let resolv_conf = std::fs::read_to_string("/etc/resolv.conf")
+                    .wrap_err("Failed to read /etc/resolv.conf for --dns-from-host")?;
+                runtime::apply_host_dns(
+                    &mut container_config,
+                    runtime::parse_resolv_conf(&resolv_conf),
+                );
+            }
+
+            if require_all_mounts {
+                container_config.mount_summary.require_all()?;
+            }
+            if !quiet {
+                eprintln!("{}", container_config.mount_summary);
+            }
+
+            if let Some(path) = print_env {
+                if path.as_os_str() == "-" {
+                    let env: Vec<String> = if std::io::stdout().is_terminal() {
+                        container_config
+                            .env
+                            .iter()
+                            .map(|e| redact_env_entry(e, &container_config.redact_env))
+                            .collect()
+                    } else {
+                        container_config.env.clone()
+                    };
+                    print!("{}", runtime::env_file(&env, export_format));
+                } else {
+                    std::fs::write(
+                        &path,
+                        runtime::env_file(&container_config.env, export_format),
+                    )
+                    .wrap_err_with(|| format!("Failed to write env to {}", path.display()))?;
+                }
+                if no_spawn {
+                    return Ok(());
+                }
+            }
+
+            container_config
+                .mount_summary
+                .confirm_large_mounts(yes, |list| {
+                    eprintln!("WARNING: {}", list);
+                    Ok(inquire::Confirm::new("Mount them anyway?")
+                        .with_default(false)
+                        .prompt()?)
+                })?;
+
+            // Recorded before an ephemeral copy replaces the workspace mount, so the
+            // state compares cleanly against the config
+            let workspace_state = (!local).then(|| {
+                let mut workspace_state = state::WorkspaceState::new(
+                    &container_config,
+                    config.default_profile.as_deref(),
+                    &profile,
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                );
+                // A managed portal's socket is new each spawn; it isn't drift
+                if let Some(socket) = portal_socket_override {
+                    workspace_state
+                        .mounts
+                        .retain(|bind| Path::new(runtime::parse_bind(bind).host) != socket);
+                }
+                workspace_state
+            });
+
+            // The copy takes the workspace's place at the same container path
+            let ephemeral = if ephemeral {
+                let root = ephemeral::ephemeral_root()
+                    .ok_or_else(|| eyre::eyre!("Can't find a state directory for --ephemeral"))?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let copy = timings.record("ephemeral copy", || {
+                    ephemeral::Ephemeral::create(&root, &workspace_path, now)
+                })?;
+                runtime::replace_bind_source(
+                    &mut container_config.mounts,
+                    &workspace_path,
+                    &copy.workspace(),
+                )?;
+                Some(copy)
+            } else {
+                None
             };
 
+            if let Some(path) = record {
+                runtime::SpawnRecord::new(&config.runtime.backend, &container_config)
+                    .write(&path)?;
+                eprintln!("Recorded spawn to {}", path.display());
+            }
+
             // Get the appropriate runtime backend
-            let container_runtime = create_runtime(&config);
+            let container_runtime = create_runtime(config);
+
+            if detach
+                && container_runtime
+                    .list_sessions()?
+                    .iter()
+                    .any(|c| c.name == container_config.name)
+            {
+                bail!(
+                    "Session container {} already exists; reconnect with `ab attach --session {}` or stop it with `ab stop --session {}`",
+                    container_config.name,
+                    session.as_deref().unwrap_or_default(),
+                    session.as_deref().unwrap_or_default()
+                );
+            }
+
+            if let Some(workspace_state) = &workspace_state {
+                let written = state::exclude_state_file(&source_path)
+                    .and_then(|()| workspace_state.write(&workspace_path));
+                if let Err(e) = written {
+                    eprintln!("WARNING: Failed to record workspace state: {}", e);
+                }
+            }
 
             // Spawn the container
-            container_runtime.spawn_container(&container_config)?;
+            let result = timings.record("container", || {
+                container_runtime.spawn_container_with_retries(
+                    &container_config,
+                    retries.unwrap_or(config.runtime.spawn_retries),
+                )
+            });
+
+            if show_timings {
+                if is_json_output() {
+                    let value = serde_json::json!({ "timings": timings.to_json() });
+                    println!("{}", serde_json::to_string_pretty(&value)?);
+                } else {
+                    eprint!("{}", timings.summary());
+                }
+            }
+            if let Some(copy) = &ephemeral {
+                eprintln!(
+                    "Changes stay in ephemeral workspace {}; export them with `ab diff-export {}` or drop them with `ab diff-export --discard {}`",
+                    copy.id, copy.id, copy.id
+                );
+            }
+            result?;
+            if detach {
+                let session = session.as_deref().unwrap_or_default();
+                eprintln!(
+                    "Started {} in the background; reconnect with `ab attach --session {}`, stop with `ab stop --session {}`",
+                    container_config.name, session, session
+                );
+                if let (true, Some(repo), Some(dir)) = (
+                    container_config.detach,
+                    &repo_rel,
+                    runtime::logs::logs_dir(),
+                ) {
+                    let path = runtime::logs::session_log_path(&dir, repo, session);
+                    match container_runtime.capture_logs(&container_config.name, &path) {
+                        Ok(()) => eprintln!(
+                            "Logging its output to {}; follow it with `ab logs -f --session {}`",
+                            path.display(),
+                            session
+                        ),
+                        Err(e) => eprintln!(
+                            "WARNING: Failed to capture logs: {}; once it exits, remove it with `ab stop --session {}`",
+                            e, session
+                        ),
+                    }
+                }
+            }
+        }
+        Commands::Attach { session, repo } => {
+            let repo = repo_label(config, repo.as_deref())?;
+            let container_runtime = create_runtime(config);
+            let name = container_runtime.find_container_by_session(&session, repo.as_deref())?;
+            container_runtime.attach_container(&name)?;
+        }
+        Commands::Logs {
+            session,
+            repo,
+            follow,
+            lines,
+        } => {
+            let repo = repo_label(config, repo.as_deref())?;
+            let dir = runtime::logs::logs_dir()
+                .ok_or_else(|| eyre::eyre!("Can't locate session logs: HOME is not set"))?;
+            let path = runtime::logs::pick_session_log(&dir, &session, repo.as_deref())?;
+            runtime::logs::write_log(&path, lines, follow, &mut std::io::stdout())?;
+        }
+        Commands::Stop { session, repo } => {
+            let repo = repo_label(config, repo.as_deref())?;
+            let container_runtime = create_runtime(config);
+            let name = container_runtime.find_container_by_session(&session, repo.as_deref())?;
+            container_runtime.stop_container(&name)?;
+            eprintln!("Stopped {}", name);
+        }
+        Commands::Exec {
+            session,
+            repo,
+            workdir,
+            env,
+            command,
+        } => {
+            if let Some(var) = env.iter().find(|var| !var.contains('=')) {
+                bail!("Invalid --env '{}': expected KEY=VALUE", var);
+            }
+            let repo = repo_label(config, repo.as_deref())?;
+            let container_runtime = create_runtime(config);
+            let name = container_runtime.find_container_by_session(&session, repo.as_deref())?;
+
+            // Same terminal passthrough as spawn; the container's own env is inherited
+            let tty = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+            let mut exec_env = Vec::new();
+            if tty {
+                for var_name in ["TERM", "COLORTERM"] {
+                    if let Ok(value) = std::env::var(var_name) {
+                        exec_env.push(format!("{}={}", var_name, value));
+                    }
+                }
+            }
+            exec_env.extend(env);
+
+            let code = container_runtime.exec_in_container(
+                &name,
+                &command,
+                &exec_env,
+                workdir.as_deref(),
+                tty,
+            )?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Ps { repo } => {
+            let repo = repo_label(config, repo.as_deref())?;
+            let sessions: Vec<_> = create_runtime(config)
+                .list_sessions()?
+                .into_iter()
+                .filter(|c| repo.as_ref().is_none_or(|repo| &c.repo == repo))
+                .collect();
+            runtime::session::write_sessions_table(&sessions, &mut std::io::stdout())?;
+        }
+        Commands::List {
+            repo,
+            wtype,
+            with_containers,
+        } => {
+            let repos = list_repos(config, repo.as_deref(), None)?;
+            let entries = list_entries(config, &repos, wtype);
+            let containers = if with_containers {
+                Some(create_runtime(config).list_sessions()?)
+            } else {
+                None
+            };
+            if is_json_output() {
+                let value: Vec<serde_json::Value> = entries
+                    .iter()
+                    .map(|entry| {
+                        let mut value = serde_json::to_value(entry)?;
+                        if let Some(containers) = &containers {
+                            value["container"] =
+                                runtime::session::entry_container(entry, containers)
+                                    .map(
+                                        |c| serde_json::json!({"name": c.name, "status": c.status}),
+                                    )
+                                    .unwrap_or_default();
+                        }
+                        Ok(value)
+                    })
+                    .collect::<Result<_>>()?;
+                println!("{}", serde_json::to_string_pretty(&value)?);
+            } else {
+                runtime::session::write_list_table(
+                    &entries,
+                    containers.as_deref(),
+                    &mut std::io::stdout(),
+                )?;
+            }
+        }
+        Commands::Gc {
+            repo,
+            older_than,
+            force,
+            dry_run,
+        } => run_gc(config, repo.as_deref(), older_than, force, dry_run)?,
+        Commands::Volume { command } => run_volume(config, command)?,
+        Commands::Status {
+            mounts_json,
+            session,
+            repo,
+            git,
+            profile,
+        } => {
+            if mounts_json {
+                let session = session.expect("--mounts-json requires --session");
+                print_mounts_json(config, repo.as_deref(), &session, git, &profile)?;
+            } else {
+                run_status(config)?;
+            }
+        }
+        Commands::Run { alias, args } => {
+            let spawn_args = expand_alias(&config.aliases, &alias, &args)?;
+            let argv = ["ab", "spawn"]
+                .into_iter()
+                .map(String::from)
+                .chain(spawn_args);
+            let cli = Cli::try_parse_from(argv).unwrap_or_else(|e| e.exit());
+            if cli.no_interactive {
+                agent_box_common::prompt::set_non_interactive(true);
+            }
+            return run_command(cli.command, config, config_load_time);
+        }
+        Commands::RunOnce {
+            session,
+            repo,
+            command,
+            git,
+            keep_workspace,
+            remove_on_failure,
+            spawn_args,
+        } => {
+            let wtype = if git {
+                WorkspaceType::Git
+            } else {
+                WorkspaceType::Jj
+            };
+            let repo_id = resolve_repo_id(config, repo.as_deref())?;
+            let workspace_path = repo_id.workspace_path(config, wtype, &session);
+
+            let mut argv: Vec<String> = ["ab", "spawn", "--session", &session]
+                .into_iter()
+                .map(String::from)
+                .collect();
+            if let Some(repo) = &repo {
+                argv.extend(["--repo".to_string(), repo.clone()]);
+            }
+            if git {
+                argv.push("--git".to_string());
+            }
+            argv.extend(command.iter().map(|c| format!("--command={}", c)));
+            argv.extend(spawn_args);
+            let spawn_cli = Cli::try_parse_from(argv).unwrap_or_else(|e| e.exit());
+
+            let policy = CleanupPolicy {
+                keep_workspace,
+                remove_on_failure,
+            };
+            run_once(
+                &workspace_path,
+                policy,
+                || {
+                    new_workspace(
+                        config,
+                        repo.as_deref(),
+                        Some(&session),
+                        Some(wtype),
+                        None,
+                        None,
+                        false,
+                        &mut std::io::stdout(),
+                    )
+                    .map(|_| ())
+                },
+                || run_command(spawn_cli.command, config, config_load_time),
+                || remove_workspace(config, &repo_id, wtype, &session),
+            )?;
+        }
+        Commands::Export {
+            repo,
+            dest,
+            allow_dirty,
+        } => {
+            let repo_id = resolve_repo_id(config, Some(&repo))?;
+            export_repo(config, &repo_id, &dest, allow_dirty, &mut std::io::stderr())?;
+        }
+        Commands::Foreach {
+            repo,
+            command,
+            parallel,
+            spawn_args,
+        } => {
+            let repo_id = resolve_repo_id(config, Some(&repo))?;
+            let targets = foreach::session_targets(
+                &repo_id.git_workspace_path(config, ""),
+                &repo_id.git_worktrees(config)?,
+                &repo_id.jj_workspace_path(config, ""),
+                &repo_id.jj_workspaces(config)?,
+                Path::is_dir,
+            );
+            if targets.is_empty() {
+                bail!("No session workspaces found for {}", repo);
+            }
+
+            // Each spawn is a separate `ab spawn` process, so runs can overlap
+            let exe = std::env::current_exe().wrap_err("Failed to locate the ab executable")?;
+            let results = foreach::run_foreach(&targets, parallel as usize, |target| {
+                let mut cmd = std::process::Command::new(&exe);
+                if agent_box_common::prompt::is_non_interactive() {
+                    cmd.arg("--no-interactive");
+                }
+                if agent_box_common::config::is_no_global_config() {
+                    cmd.arg("--no-global-config");
+                }
+                cmd.args(["spawn", "--session", &target.session, "--repo", &repo]);
+                if target.wtype == WorkspaceType::Git {
+                    cmd.arg("--git");
+                }
+                cmd.args(command.iter().map(|c| format!("--command={}", c)));
+                cmd.args(&spawn_args);
+
+                eprintln!("==> {}", target);
+                let status = cmd
+                    .status()
+                    .wrap_err_with(|| format!("Failed to run {}", exe.display()))?;
+                status
+                    .code()
+                    .ok_or_else(|| eyre::eyre!("terminated by signal ({})", status))
+            });
+
+            println!("\nforeach {}:", repo);
+            let failed = foreach::write_summary(&targets, &results, &mut std::io::stdout())?;
+            if failed > 0 {
+                bail!("{} of {} workspaces failed", failed, targets.len());
+            }
         }
         Commands::Dbg { command } => match command {
             DbgCommands::Locate { repo } => {
-                let repo_id = locate_repo(&config, repo.as_deref())?;
+                let repo_id = locate_repo(config, repo.as_deref())?;
                 println!("{}", repo_id.relative_path().display());
             }
+            DbgCommands::List {
+                filter,
+                limit,
+                count: _,
+                json_lines: true,
+            } => {
+                write_repos_json_lines(config, filter.as_deref(), limit, &mut std::io::stdout())?;
+            }
+            DbgCommands::List {
+                filter,
+                limit,
+                count,
+                json_lines: false,
+            } => {
+                let repos = list_repos(config, filter.as_deref(), limit)?;
+                if is_json_output() {
+                    let value = if count {
+                        serde_json::to_value(repo_counts(config, &repos))?
+                    } else {
+                        repos
+                            .iter()
+                            .map(|repo_id| repo_json(config, repo_id))
+                            .collect()
+                    };
+                    println!("{}", serde_json::to_string_pretty(&value)?);
+                } else if count {
+                    write_repo_counts(config, &repos, &mut std::io::stdout())?;
+                } else {
+                    for repo_id in repos {
+                        println!("{}", repo_id.relative_path().display());
+                    }
+                }
+            }
             DbgCommands::Remove {
                 repo,
                 dry_run,
                 force,
             } => {
                 // Locate the repository identifier
-                let repo_id = locate_repo(&config, Some(&repo))?;
+                let repo_id = locate_repo(config, Some(&repo))?;
 
                 // Show what will be removed (always, even if --force is used)
-                remove_repo(&config, &repo_id, true)?;
+                remove_repo(config, &repo_id, true)?;
 
                 // If dry-run, we're done
                 if dry_run {
@@ -345,6 +2443,10 @@ fn run() -> eyre::Result<()> {
 
                 // Prompt for confirmation unless --force is used
                 if !force {
+                    agent_box_common::prompt::require_interactive(
+                        "confirmation required; pass --force to remove without prompting",
+                    )?;
+
                     let confirmed =
                         inquire::Confirm::new("Are you sure you want to remove these directories?")
                             .with_default(false)
@@ -358,29 +2460,29 @@ fn run() -> eyre::Result<()> {
                 }
 
                 // Actually remove
-                remove_repo(&config, &repo_id, false)?;
+                remove_repo(config, &repo_id, false)?;
             }
-            DbgCommands::Validate => {
-                let result = validate_config(&config);
-
-                // Print errors
-                if !result.errors.is_empty() {
-                    eprintln!("Errors:");
-                    for error in &result.errors {
-                        eprintln!("  ✗ {}", error);
-                    }
-                }
-
-                // Print warnings
-                if !result.warnings.is_empty() {
-                    if !result.errors.is_empty() {
-                        eprintln!();
-                    }
-                    eprintln!("Warnings:");
-                    for warning in &result.warnings {
-                        eprintln!("  ⚠ {}", warning);
-                    }
+            DbgCommands::Validate { fix: true } if is_json_output() => {
+                bail!("--format json can't be combined with --fix, which prints a diff");
+            }
+            DbgCommands::Validate { fix: false } if is_json_output() => {
+                let result = validate_config(config);
+                let value = serde_json::json!({
+                    "valid": result.is_ok(),
+                    "errors": result.errors,
+                    "warnings": result.warnings,
+                });
+                println!("{}", serde_json::to_string_pretty(&value)?);
+                if !result.is_ok() {
+                    std::process::exit(1);
                 }
+            }
+            DbgCommands::Validate { fix: true } => {
+                fix_profile_problems(config)?;
+            }
+            DbgCommands::Validate { fix: false } => {
+                let result = validate_config(config);
+                print_validation_issues(&result);
 
                 // Print summary
                 if result.is_ok() {
@@ -418,12 +2520,97 @@ fn run() -> eyre::Result<()> {
                     std::process::exit(1);
                 }
             }
-            DbgCommands::Resolve { profile } => {
+            DbgCommands::Freeze { out } => {
+                let lockfile = freeze_config(config)?;
+                match out {
+                    Some(path) => {
+                        std::fs::write(&path, format!("{}\n", lockfile)).wrap_err_with(|| {
+                            format!("Failed to write lockfile {}", path.display())
+                        })?;
+                        eprintln!("Wrote config lockfile to {}", path.display());
+                    }
+                    None => println!("{}", lockfile),
+                }
+            }
+            DbgCommands::VerifyFreeze { lockfile } => {
+                let contents = std::fs::read_to_string(&lockfile)
+                    .wrap_err_with(|| format!("Failed to read lockfile {}", lockfile.display()))?;
+                let drift = config_drift(&contents, config)?;
+                if drift.is_empty() {
+                    println!("✓ Configuration matches {}", lockfile.display());
+                } else {
+                    eprintln!("Drift from {}:", lockfile.display());
+                    for line in &drift {
+                        eprintln!("  ~ {}", line);
+                    }
+                    eyre::bail!(
+                        "Configuration drifted from lockfile ({} change(s))",
+                        drift.len()
+                    );
+                }
+            }
+            DbgCommands::MigrateConfig { path, in_place } => {
+                let path = match path {
+                    Some(path) => path,
+                    None => PathBuf::from(std::env::var("HOME")?).join(".agent-box.toml"),
+                };
+                let contents = std::fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+                let Some(migrated) = migrate_legacy_docker_config(&contents)? else {
+                    eprintln!("No legacy [docker] section found in {}", path.display());
+                    return Ok(());
+                };
+
+                if in_place {
+                    let backup = write_with_backup(&path, &migrated)?;
+                    eprintln!(
+                        "Migrated {} (backup at {})",
+                        path.display(),
+                        backup.display()
+                    );
+                } else {
+                    print!("{}", migrated);
+                }
+            }
+            DbgCommands::Profiles { tree, json } => {
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&profile_graph(config))?);
+                } else if tree {
+                    print!("{}", render_profile_tree(config));
+                } else if config.profiles.is_empty() {
+                    println!("No profiles defined");
+                } else {
+                    for (name, extends) in profile_graph(config) {
+                        let extends_info = if extends.is_empty() {
+                            String::new()
+                        } else {
+                            format!(" (extends: {})", extends.join(", "))
+                        };
+                        let mounts_info = match profile_mount_counts(config, &name) {
+                            Ok((own, total)) => {
+                                format!("{} mount(s), {} inherited", own, total.saturating_sub(own))
+                            }
+                            Err(e) => format!("unresolvable: {}", e),
+                        };
+                        println!("{}{}: {}", name, extends_info, mounts_info);
+                    }
+                }
+            }
+            DbgCommands::Resolve { profile, no_dedup } => {
+                let profile = split_profile_args(&profile)?;
+
                 // Validate config first
-                validate_config_or_err(&config)?;
+                validate_config_or_err(config)?;
+
+                if is_json_output() {
+                    let value = resolve_json(config, &profile, no_dedup)?;
+                    println!("{}", serde_json::to_string_pretty(&value)?);
+                    return Ok(());
+                }
 
                 // Show which profiles will be applied
-                let profiles_applied = collect_profiles_to_apply(&config, &profile);
+                let profiles_applied = collect_profiles_to_apply(config, &profile);
 
                 if profiles_applied.is_empty() {
                     println!("No profiles to apply (no default_profile set, no -p flags)");
@@ -437,33 +2624,61 @@ fn run() -> eyre::Result<()> {
                 }
 
                 // Resolve profiles
-                let resolved = resolve_profiles(&config, &profile)?;
+                let mut mount_cache = MountResolutionCache::default();
+                let resolved = resolve_profiles_with_cache(config, &profile, &mut mount_cache)?;
+                let homes = runtime::HomeMap::from_env();
 
-                // Show mounts
+                // Show mounts, optionally without dedup and with their source
+                let mounts: Vec<(Mount, Option<String>)> = if no_dedup {
+                    resolve_mount_sources(config, &profile)?
+                        .into_iter()
+                        .map(|(m, source)| (m, Some(source)))
+                        .collect()
+                } else {
+                    resolved.mounts.iter().map(|m| (m.clone(), None)).collect()
+                };
                 println!("\n  Mounts:");
-                if resolved.mounts.is_empty() {
+                if mounts.is_empty() && resolved.tmpfs.is_empty() && resolved.volumes.is_empty() {
                     println!("    (none)");
                 } else {
-                    for m in &resolved.mounts {
-                        match m.to_resolved_mounts() {
+                    for (m, source) in &mounts {
+                        let label = match source {
+                            Some(source) => format!("{} [{}]", m, source),
+                            None => m.to_string(),
+                        };
+                        let resolved_mounts = match &homes {
+                            Ok(h) => mount_cache.resolve_chain(m, &h.host, &h.container),
+                            Err(e) => Err(eyre::eyre!("{}", e)),
+                        };
+                        match resolved_mounts {
                             Ok(resolved_mounts) if resolved_mounts.is_empty() => {
                                 // Path was filtered out (doesn't exist)
-                                println!("    {} -> FILTERED (path does not exist)", m);
+                                println!("    {} -> FILTERED (path does not exist)", label);
                             }
                             Ok(resolved_mounts) if resolved_mounts.len() == 1 => {
-                                println!("    {} -> {}", m, resolved_mounts[0].to_bind_string());
+                                println!(
+                                    "    {} -> {}",
+                                    label,
+                                    resolved_mounts[0].to_bind_string()
+                                );
                             }
                             Ok(resolved_mounts) => {
                                 // Multiple resolved_mounts (symlink chain)
-                                println!("    {} ->", m);
+                                println!("    {} ->", label);
                                 for rm in resolved_mounts {
                                     println!("      {}", rm.to_bind_string());
                                 }
                             }
-                            Err(e) => println!("    {} -> ERROR: {}", m, e),
+                            Err(e) => println!("    {} -> ERROR: {}", label, e),
                         }
                     }
                 }
+                for path in &resolved.tmpfs {
+                    println!("    tmpfs:{}", path);
+                }
+                for spec in &resolved.volumes {
+                    println!("    volume:{}", spec);
+                }
 
                 // Show env
                 println!("\n  Environment:");
@@ -471,7 +2686,7 @@ fn run() -> eyre::Result<()> {
                     println!("    (none)");
                 } else {
                     for e in &resolved.env {
-                        println!("    {}", e);
+                        println!("    {}", redact_env_entry(e, &config.runtime.redact_env));
                     }
                 }
 
@@ -483,6 +2698,9 @@ fn run() -> eyre::Result<()> {
                     for var_name in &resolved.env_passthrough {
                         // Show what value it would have if it were to be passed through
                         match std::env::var(var_name) {
+                            Ok(_) if should_redact_env(var_name, &config.runtime.redact_env) => {
+                                println!("    {} = {}", var_name, REDACTED)
+                            }
                             Ok(value) => println!("    {} = {}", var_name, value),
                             Err(_) => println!("    {} = (not set in host)", var_name),
                         }
@@ -518,9 +2736,75 @@ fn run() -> eyre::Result<()> {
                         println!("    {}", c);
                     }
                 }
+
+                // Show default command
+                println!("\n  Command:");
+                match &resolved.command {
+                    Some(command) => println!("    {}", shell_words::join(command)),
+                    None => println!("    (none)"),
+                }
+
+                // Show network mode
+                println!("\n  Network:");
+                println!("    {}", resolved.network.as_deref().unwrap_or("(default)"));
+                if !resolved.allow_hosts.is_empty() {
+                    println!(
+                        "    egress through a proxy to: {}",
+                        resolved.allow_hosts.join(", ")
+                    );
+                }
+
+                // Show resource limits as the flags they become
+                println!("\n  Resources:");
+                if resolved.resources.is_empty() {
+                    println!("    (none)");
+                } else {
+                    println!(
+                        "    {}",
+                        runtime::resource_args(&resolved.resources).join(" ")
+                    );
+                }
+
+                // Show the image build and the tag it gets
+                println!("\n  Build:");
+                match &resolved.build {
+                    Some(build) => {
+                        println!("    file: {}", build.file.display());
+                        println!("    context: {}", build.context_dir().display());
+                        for (name, value) in &build.args {
+                            println!("    arg: {}={}", name, value);
+                        }
+                        if let Some(target) = &build.target {
+                            println!("    target: {}", target);
+                        }
+                        match build.image_tag() {
+                            Ok(tag) => println!("    image: {}", tag),
+                            Err(e) => println!("    image: (error: {})", e),
+                        }
+                    }
+                    None => println!("    (none)"),
+                }
+
+                // Show where each secret comes from; values are only fetched at spawn
+                println!("\n  Secrets:");
+                if resolved.secrets.is_empty() {
+                    println!("    (none)");
+                }
+                for (name, secret) in &resolved.secrets {
+                    let source = match (&secret.command, &secret.file, &secret.env) {
+                        (Some(command), _, _) => format!("command: {}", shell_words::join(command)),
+                        (_, Some(file), _) => format!("file: {}", file),
+                        (_, _, Some(var)) => format!("env: ${}", var),
+                        _ => "(no source)".to_string(),
+                    };
+                    match &secret.path {
+                        Some(path) => println!("    {} -> {} ({})", name, path, source),
+                        None => println!("    {} ({})", name, source),
+                    }
+                }
             }
             DbgCommands::CheckPath { image, path } => {
-                let runtime = create_runtime(&config);
+                let runtime = create_runtime(config);
 
                 println!("Checking if path exists in image...");
                 println!("  Image: {}", image);
@@ -542,12 +2826,33 @@ fn run() -> eyre::Result<()> {
                     }
                 }
             }
+            DbgCommands::CheckUser { image } => {
+                let image = image.unwrap_or_else(|| config.runtime.image.clone());
+                let uid = nix::unistd::getuid().as_raw();
+                let gid = nix::unistd::getgid().as_raw();
+                let image_user = create_runtime(config).image_user(&image)?;
+
+                println!("Image: {}", image);
+                println!(
+                    "  Image user: {}",
+                    if image_user.is_empty() {
+                        "(unset, root)"
+                    } else {
+                        &image_user
+                    }
+                );
+                println!("  Container user: {}:{}", uid, gid);
+                match runtime::image_user_mismatch(&image_user, uid, gid) {
+                    Some(warning) => println!("\nWARNING: {}", warning),
+                    None => println!("\n✓ No uid/gid mismatch"),
+                }
+            }
             DbgCommands::ListPaths {
                 image,
                 root_path,
                 filter,
             } => {
-                let runtime = create_runtime(&config);
+                let runtime = create_runtime(config);
 
                 let root = root_path.as_deref();
                 let root_display = root.unwrap_or("/");
@@ -587,6 +2892,17 @@ fn run() -> eyre::Result<()> {
                 }
             }
         },
+        Commands::Edit { .. }
+        | Commands::Config {
+            command: ConfigCommands::Set { .. },
+        }
+        | Commands::Replay { .. }
+        | Commands::Proxy { .. }
+        | Commands::Audit { .. }
+        | Commands::DiffExport { .. }
+        | Commands::Completions { .. } => {
+            unreachable!("run() handles these before loading the config")
+        }
     }
 
     Ok(())
@@ -0,0 +1,135 @@
+use eyre::Result;
+use std::path::Path;
+
+/// What `ab run-once` does with the session workspace after the container exits
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CleanupPolicy {
+    /// Never remove the workspace
+    pub keep_workspace: bool,
+    /// Also remove the workspace when the spawn fails (kept for debugging otherwise)
+    pub remove_on_failure: bool,
+}
+
+impl CleanupPolicy {
+    fn should_remove(&self, spawn_succeeded: bool) -> bool {
+        !self.keep_workspace && (spawn_succeeded || self.remove_on_failure)
+    }
+}
+
+/// Create a workspace, spawn into it, then clean it up according to `policy`.
+///
+/// The spawn error (including a non-zero container exit) is returned even when
+/// cleanup runs; a cleanup failure after a failed spawn is only reported.
+pub fn run_once(
+    workspace: &Path,
+    policy: CleanupPolicy,
+    create: impl FnOnce() -> Result<()>,
+    spawn: impl FnOnce() -> Result<()>,
+    remove: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    create()?;
+
+    let result = spawn();
+
+    if policy.should_remove(result.is_ok()) {
+        match (remove(), &result) {
+            (Err(e), Err(_)) => eprintln!("Warning: failed to remove workspace: {}", e),
+            (Err(e), Ok(())) => return Err(e),
+            (Ok(()), _) => {}
+        }
+    } else if result.is_err() {
+        eprintln!("Keeping workspace for debugging: {}", workspace.display());
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn run(policy: CleanupPolicy, spawn_ok: bool) -> (Result<()>, Vec<&'static str>) {
+        let steps = RefCell::new(Vec::new());
+        let result = run_once(
+            Path::new("/workspaces/jj/proj/ci"),
+            policy,
+            || {
+                steps.borrow_mut().push("create");
+                Ok(())
+            },
+            || {
+                steps.borrow_mut().push("spawn");
+                if spawn_ok {
+                    Ok(())
+                } else {
+                    Err(eyre::eyre!("container exited with status: 1"))
+                }
+            },
+            || {
+                steps.borrow_mut().push("remove");
+                Ok(())
+            },
+        );
+        (result, steps.into_inner())
+    }
+
+    #[test]
+    fn test_success_creates_spawns_then_removes() {
+        let (result, steps) = run(CleanupPolicy::default(), true);
+        assert!(result.is_ok());
+        assert_eq!(steps, ["create", "spawn", "remove"]);
+    }
+
+    #[test]
+    fn test_keep_workspace_skips_remove() {
+        let policy = CleanupPolicy {
+            keep_workspace: true,
+            ..Default::default()
+        };
+        let (result, steps) = run(policy, true);
+        assert!(result.is_ok());
+        assert_eq!(steps, ["create", "spawn"]);
+    }
+
+    #[test]
+    fn test_spawn_failure_keeps_workspace_by_default() {
+        let (result, steps) = run(CleanupPolicy::default(), false);
+        assert!(result.is_err());
+        assert_eq!(steps, ["create", "spawn"]);
+    }
+
+    #[test]
+    fn test_spawn_failure_removes_when_requested() {
+        let policy = CleanupPolicy {
+            remove_on_failure: true,
+            ..Default::default()
+        };
+        let (result, steps) = run(policy, false);
+        assert!(result.unwrap_err().to_string().contains("status: 1"));
+        assert_eq!(steps, ["create", "spawn", "remove"]);
+    }
+
+    #[test]
+    fn test_create_failure_stops_before_spawn() {
+        let steps = RefCell::new(Vec::new());
+        let result = run_once(
+            Path::new("/workspaces/jj/proj/ci"),
+            CleanupPolicy::default(),
+            || {
+                steps.borrow_mut().push("create");
+                Err(eyre::eyre!("workspace exists"))
+            },
+            || {
+                steps.borrow_mut().push("spawn");
+                Ok(())
+            },
+            || {
+                steps.borrow_mut().push("remove");
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(steps.into_inner(), ["create"]);
+    }
+}
@@ -0,0 +1,341 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::{
+    ContainerConfig, ContainerExited, REPO_LABEL, SESSION_LABEL, StartFailure, parse_bind,
+};
+
+/// Append-only log of every container spawn, one JSON object per line:
+/// `$XDG_DATA_HOME/agent-box/audit.jsonl`, or `~/.local/share/agent-box/audit.jsonl`
+pub fn audit_log_path() -> Option<PathBuf> {
+    Some(super::data_dir()?.join("audit.jsonl"))
+}
+
+/// One spawn, as recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the spawn started, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Runtime backend (`podman`, `docker`, `bwrap`, or `kubernetes`)
+    pub backend: String,
+    /// Repo and session from the session labels; unset for `--local` spawns
+    pub repo: Option<String>,
+    pub session: Option<String>,
+    pub name: String,
+    pub image: String,
+    pub working_dir: String,
+    /// Bind mounts as `HOST:CONTAINER:MODE`
+    pub mounts: Vec<String>,
+    /// Names of the env vars set in the container; values may be secrets
+    pub env: Vec<String>,
+    pub entrypoint: Option<Vec<String>>,
+    pub command: Option<Vec<String>>,
+    /// Started in the background, so `exit_code` is that of the start
+    pub detach: bool,
+    /// The container's exit code; unset if it never ran or was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Why the spawn failed, if it did
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Entry for spawning `config` at `timestamp` with the given outcome
+    pub fn new(
+        backend: &str,
+        config: &ContainerConfig,
+        timestamp: u64,
+        result: &Result<()>,
+    ) -> Self {
+        let label = |key: &str| {
+            config
+                .labels
+                .iter()
+                .find_map(|l| l.strip_prefix(key)?.strip_prefix('='))
+                .map(str::to_string)
+        };
+        let (exit_code, error) = match result {
+            Ok(()) => (Some(0), None),
+            Err(e) => {
+                let code = if let Some(exited) = e.downcast_ref::<ContainerExited>() {
+                    exited.status.code()
+                } else {
+                    e.downcast_ref::<StartFailure>()
+                        .and_then(|failure| failure.status.code())
+                };
+                (code, Some(e.to_string()))
+            }
+        };
+
+        Self {
+            timestamp,
+            backend: backend.to_string(),
+            repo: label(REPO_LABEL),
+            session: label(SESSION_LABEL),
+            name: config.name.clone(),
+            image: config.image.clone(),
+            working_dir: config.working_dir.clone(),
+            mounts: config.mounts.clone(),
+            env: config
+                .env
+                .iter()
+                .map(|e| e.split_once('=').map_or(e.as_str(), |(name, _)| name))
+                .map(str::to_string)
+                .collect(),
+            entrypoint: config.entrypoint.clone(),
+            command: config.command.clone(),
+            detach: config.detach,
+            exit_code,
+            error,
+        }
+    }
+
+    /// True if the spawn failed or the container exited non-zero
+    pub fn failed(&self) -> bool {
+        self.exit_code != Some(0)
+    }
+
+    /// True if a bind mount's host path is `path` or under it
+    pub fn mounts_path(&self, path: &Path) -> bool {
+        self.mounts
+            .iter()
+            .any(|bind| Path::new(parse_bind(bind).host).starts_with(path))
+    }
+}
+
+/// Append `entry` to the log at `path`, creating it readable only by the owner
+pub fn append_audit_entry(path: &Path, entry: &AuditEntry) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| eyre::eyre!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let mut line = serde_json::to_string(entry)
+        .map_err(|e| eyre::eyre!("Failed to serialize audit entry: {}", e))?;
+    line.push('\n');
+    // One write per entry, so concurrent spawns don't interleave lines
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| eyre::eyre!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Every entry in the log at `path`, oldest first; a missing log is empty.
+/// Lines that don't parse (e.g. a write cut short) are skipped.
+pub fn read_audit_log(path: &Path) -> Result<Vec<AuditEntry>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(eyre::eyre!("Failed to read {}: {}", path.display(), e)),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Record the spawn of `config` in the audit log. Failing to write only warns:
+/// by now the container has run.
+pub(crate) fn record_spawn(
+    backend: &str,
+    config: &ContainerConfig,
+    started: std::time::SystemTime,
+    result: &Result<()>,
+) {
+    let Some(path) = audit_log_path() else {
+        eprintln!("WARNING: Not writing the audit log: HOME is not set");
+        return;
+    };
+    let timestamp = started
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let entry = AuditEntry::new(backend, config, timestamp, result);
+    if let Err(e) = append_audit_entry(&path, &entry) {
+        eprintln!("WARNING: Failed to write the audit log: {}", e);
+    }
+}
+
+/// `timestamp` (seconds since the Unix epoch) as UTC `YYYY-MM-DDTHH:MM:SSZ`
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (days, secs) = (timestamp / 86400, timestamp % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// `ab audit` table of entries
+pub fn write_audit_table(entries: &[AuditEntry], out: &mut dyn Write) -> Result<()> {
+    if entries.is_empty() {
+        writeln!(out, "No spawns recorded")?;
+        return Ok(());
+    }
+
+    let headers = ["TIME", "REPO", "SESSION", "IMAGE", "EXIT", "MOUNTS"];
+    let rows: Vec<[String; 6]> = entries
+        .iter()
+        .map(|e| {
+            let exit = match (e.exit_code, &e.error) {
+                (Some(code), _) => code.to_string(),
+                (None, Some(_)) => "error".to_string(),
+                (None, None) => "-".to_string(),
+            };
+            [
+                format_timestamp(e.timestamp),
+                e.repo.clone().unwrap_or_else(|| e.working_dir.clone()),
+                e.session.clone().unwrap_or_else(|| "-".to_string()),
+                e.image.clone(),
+                exit,
+                e.mounts.len().to_string(),
+            ]
+        })
+        .collect();
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header_row = headers.map(str::to_string);
+    for row in std::iter::once(&header_row).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::session_labels;
+
+    fn container() -> ContainerConfig {
+        ContainerConfig {
+            name: "ab-fr-agent-box-main-1".to_string(),
+            working_dir: "/workspace".to_string(),
+            mounts: vec![
+                "/home/me/ws/agent-box:/workspace:rw".to_string(),
+                "/home/me/.gitconfig:/home/me/.gitconfig:ro".to_string(),
+            ],
+            env: vec!["GITHUB_TOKEN=secret".to_string(), "TERM".to_string()],
+            command: Some(vec!["claude".to_string()]),
+            labels: session_labels("main", "fr/agent-box"),
+            ..ContainerConfig::for_test()
+        }
+    }
+
+    #[test]
+    fn test_audit_entry_records_names_not_values() {
+        let entry = AuditEntry::new("podman", &container(), 1_700_000_000, &Ok(()));
+        assert_eq!(entry.repo.as_deref(), Some("fr/agent-box"));
+        assert_eq!(entry.session.as_deref(), Some("main"));
+        assert_eq!(entry.env, ["GITHUB_TOKEN", "TERM"]);
+        assert_eq!(entry.exit_code, Some(0));
+        assert!(!entry.failed());
+        assert!(entry.mounts_path(Path::new("/home/me/ws")));
+        assert!(entry.mounts_path(Path::new("/home/me/.gitconfig")));
+        assert!(!entry.mounts_path(Path::new("/home/me/.ssh")));
+        assert!(!serde_json::to_string(&entry).unwrap().contains("secret"));
+
+        let exited: Result<()> = Err(ContainerExited {
+            runtime: "Podman",
+            status: std::os::unix::process::ExitStatusExt::from_raw(2 << 8),
+        }
+        .into());
+        let entry = AuditEntry::new("podman", &container(), 1_700_000_000, &exited);
+        assert_eq!(entry.exit_code, Some(2));
+        assert!(entry.failed());
+
+        let entry = AuditEntry::new(
+            "podman",
+            &container(),
+            1_700_000_000,
+            &Err(eyre::eyre!("Failed to execute podman command")),
+        );
+        assert_eq!(entry.exit_code, None);
+        assert_eq!(
+            entry.error.as_deref(),
+            Some("Failed to execute podman command")
+        );
+    }
+
+    #[test]
+    fn test_audit_log_appends_and_reads_back() {
+        let dir = std::env::temp_dir().join(format!("ab-test-audit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("agent-box/audit.jsonl");
+
+        assert!(read_audit_log(&path).unwrap().is_empty());
+        let first = AuditEntry::new("docker", &container(), 1, &Ok(()));
+        let second = AuditEntry::new("docker", &container(), 2, &Ok(()));
+        append_audit_entry(&path, &first).unwrap();
+        // A torn line is skipped, not fatal
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"timestamp\":\n")
+            .unwrap();
+        append_audit_entry(&path, &second).unwrap();
+
+        let mode = std::os::unix::fs::PermissionsExt::mode(
+            &std::fs::metadata(&path).unwrap().permissions(),
+        );
+        let entries = read_audit_log(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(entries, [first, second]);
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_write_audit_table() {
+        let entries = [AuditEntry::new(
+            "podman",
+            &container(),
+            1_700_000_000,
+            &Ok(()),
+        )];
+        let mut out = Vec::new();
+        write_audit_table(&entries, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "TIME                  REPO          SESSION  IMAGE         EXIT  MOUNTS\n\
+             2023-11-14T22:13:20Z  fr/agent-box  main     agent:latest  0     2\n"
+        );
+
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_825_600), "2000-02-29T12:00:00Z");
+
+        let mut out = Vec::new();
+        write_audit_table(&[], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "No spawns recorded\n");
+    }
+}
@@ -0,0 +1,487 @@
+use eyre::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use agent_box_common::config::BuildConfig;
+
+use super::docker::ContainerBackend;
+use super::egress::EgressProxy;
+use super::session::SessionContainer;
+use super::{
+    BWRAP_CAPABILITIES, BackendCapabilities, ContainerConfig, parse_bind, print_command,
+    redact_args, run_attached,
+};
+
+/// Bubblewrap (`bwrap`) runtime: runs the command in a sandbox on the host, with
+/// no daemon and no images. `runtime.image` names a root filesystem directory
+/// (`/` for the host's own), which is bound read-only.
+pub struct BubblewrapRuntime;
+
+impl BubblewrapRuntime {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for BubblewrapRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `PATH` for the sandbox when the env sets none, since `--clearenv` drops the host's
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Top-level directories bwrap sets up itself instead of binding them from the root
+const OWN_DIRS: &[&str] = &["dev", "proc", "tmp", "run"];
+
+/// The root filesystem directory `image` names
+fn root_dir(image: &str) -> Result<&Path> {
+    let root = Path::new(image);
+    if !root.is_absolute() || !root.is_dir() {
+        return Err(eyre::eyre!(
+            "The bwrap backend needs runtime.image to be a root filesystem directory \
+             (\"/\" for the host's); '{}' is not one",
+            image
+        ));
+    }
+    Ok(root)
+}
+
+/// Error for a feature the sandbox has no equivalent of, worded like
+/// [`BackendCapabilities::require`]
+fn unsupported<T>(feature: &str) -> Result<T> {
+    Err(eyre::eyre!(
+        "{} not supported by the {} backend",
+        feature,
+        BWRAP_CAPABILITIES.name
+    ))
+}
+
+/// `root` joined with the container path `path`
+fn in_root(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+/// `bwrap` arguments running `config` with `root` as the filesystem root
+pub(crate) fn bwrap_args(config: &ContainerConfig, root: &Path) -> Result<Vec<String>> {
+    let caps = BWRAP_CAPABILITIES;
+    caps.check(config)?;
+    caps.require(config.ports.is_empty(), "Port mappings are")?;
+    caps.require(config.hosts.is_empty(), "Host entries (--add-host) are")?;
+    caps.require(config.resources.is_empty(), "Resource limits are")?;
+    caps.require(!config.detach, "Detached containers are")?;
+    caps.require(config.copy_in.is_empty(), "--copy-in is")?;
+    caps.require(config.volumes.is_empty(), "Named volumes are")?;
+    caps.require(config.gpus.is_empty(), "GPU passthrough is")?;
+    // The sandbox already has no capabilities, no_new_privs, and a read-only root
+    caps.require(config.security.cap_add.is_empty(), "security.cap_add is")?;
+    caps.require(
+        config.security.seccomp_profile.is_none(),
+        "security.seccomp_profile is",
+    )?;
+    let host_user = format!(
+        "{}:{}",
+        nix::unistd::getuid().as_raw(),
+        nix::unistd::getgid().as_raw()
+    );
+    caps.require(config.user == host_user, "Running as another user is")?;
+
+    let mut args: Vec<String> = [
+        "--die-with-parent",
+        "--unshare-pid",
+        "--unshare-ipc",
+        "--unshare-uts",
+        "--hostname",
+        &config.name,
+    ]
+    .map(String::from)
+    .to_vec();
+    match config.network.as_deref() {
+        None | Some("host") => {}
+        Some("none") => args.push("--unshare-net".to_string()),
+        Some(other) => {
+            return Err(eyre::eyre!(
+                "Network mode '{}' not supported by the bwrap backend (use host or none)",
+                other
+            ));
+        }
+    }
+
+    // bwrap starts from an empty tmpfs root. Bind the root's top-level entries into
+    // it, except where a mount's destination is missing from the root: its top-level
+    // directory stays an empty tmpfs so bwrap can create the mount point there.
+    let mounts: Vec<_> = config.mounts.iter().map(|bind| parse_bind(bind)).collect();
+    let tmpfs_dirs: BTreeSet<&str> = mounts
+        .iter()
+        .map(|m| m.container)
+        .chain(config.tmpfs.iter().map(String::as_str))
+        .filter(|path| !in_root(root, path).exists())
+        .filter_map(|path| path.trim_start_matches('/').split('/').next())
+        .collect();
+    let mut entries = std::fs::read_dir(root)
+        .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+        .wrap_err_with(|| format!("Failed to read root filesystem {}", root.display()))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if OWN_DIRS.contains(&name.as_str()) {
+            continue;
+        }
+        let dest = format!("/{}", name);
+        if entry.file_type()?.is_symlink() {
+            let target = std::fs::read_link(entry.path())?;
+            args.extend(["--symlink".to_string(), target.display().to_string(), dest]);
+        } else if tmpfs_dirs.contains(name.as_str()) {
+            args.extend(["--tmpfs".to_string(), dest]);
+        } else {
+            args.extend([
+                "--ro-bind".to_string(),
+                entry.path().display().to_string(),
+                dest,
+            ]);
+        }
+    }
+    args.extend(
+        [
+            "--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp", "--tmpfs", "/run",
+        ]
+        .map(String::from),
+    );
+
+    // Binds are always recursive; bwrap has no propagation options
+    for mount in &mounts {
+        let flag = match mount.mode {
+            "ro" => "--ro-bind",
+            "rw" | "" => "--bind",
+            other => {
+                return Err(eyre::eyre!(
+                    "Mount mode '{}' not supported by the bwrap backend",
+                    other
+                ));
+            }
+        };
+        args.extend([
+            flag.to_string(),
+            mount.host.to_string(),
+            mount.container.to_string(),
+        ]);
+    }
+    for path in &config.tmpfs {
+        args.extend(["--tmpfs".to_string(), path.clone()]);
+    }
+
+    args.push("--clearenv".to_string());
+    if !config.env.iter().any(|var| var.starts_with("PATH=")) {
+        args.extend(["--setenv", "PATH", DEFAULT_PATH].map(String::from));
+    }
+    for var in &config.env {
+        let (name, value) = var
+            .split_once('=')
+            .ok_or_else(|| eyre::eyre!("Invalid env entry '{}': expected KEY=VALUE", var))?;
+        args.extend(["--setenv".to_string(), name.to_string(), value.to_string()]);
+    }
+    args.extend(["--chdir".to_string(), config.working_dir.clone()]);
+
+    // With no entrypoint or command there's no image default to fall back on
+    let mut command: Vec<String> = config
+        .entrypoint
+        .iter()
+        .chain(&config.command)
+        .flatten()
+        .cloned()
+        .collect();
+    if command.is_empty() {
+        command.push("/bin/sh".to_string());
+    }
+    args.push("--".to_string());
+    args.extend(command);
+    Ok(args)
+}
+
+/// Directories under `dir` (container path `path`), not following symlinks;
+/// unreadable directories are skipped
+fn collect_dirs(dir: &Path, path: &str, out: &mut Vec<String>) {
+    out.push(path.to_string());
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            let child = format!(
+                "{}/{}",
+                path.trim_end_matches('/'),
+                entry.file_name().to_string_lossy()
+            );
+            collect_dirs(&entry.path(), &child, out);
+        }
+    }
+}
+
+impl ContainerBackend for BubblewrapRuntime {
+    fn capabilities(&self) -> BackendCapabilities {
+        BWRAP_CAPABILITIES
+    }
+
+    fn path_exists_in_image(&self, image: &str, path: &str) -> Result<bool> {
+        Ok(in_root(root_dir(image)?, path).is_dir())
+    }
+
+    fn list_paths_in_image(&self, image: &str, root_path: Option<&str>) -> Result<Vec<String>> {
+        let path = root_path.unwrap_or("/");
+        let mut dirs = Vec::new();
+        collect_dirs(&in_root(root_dir(image)?, path), path, &mut dirs);
+        Ok(dirs)
+    }
+
+    fn image_exists(&self, image: &str) -> Result<bool> {
+        Ok(root_dir(image).is_ok())
+    }
+
+    fn image_user(&self, _image: &str) -> Result<String> {
+        // The sandbox always runs as the host user
+        Ok(String::new())
+    }
+
+    fn image_id(&self, image: &str) -> Result<String> {
+        let root = root_dir(image)?.canonicalize()?;
+        Ok(root.display().to_string())
+    }
+
+    fn read_image_file(&self, image: &str, path: &str) -> Result<String> {
+        let file = in_root(root_dir(image)?, path);
+        std::fs::read_to_string(&file)
+            .wrap_err_with(|| format!("Failed to read {}", file.display()))
+    }
+
+    fn list_containers_by_labels(&self, _labels: &[String]) -> Result<Vec<String>> {
+        unsupported("Session containers are")
+    }
+
+    fn attach_container(&self, _name: &str) -> Result<()> {
+        unsupported("Attaching to containers is")
+    }
+
+    fn exec_in_container(
+        &self,
+        _name: &str,
+        _command: &[String],
+        _env: &[String],
+        _workdir: Option<&str>,
+        _tty: bool,
+    ) -> Result<i32> {
+        unsupported("Running commands in containers is")
+    }
+
+    fn list_sessions(&self) -> Result<Vec<SessionContainer>> {
+        unsupported("Session containers are")
+    }
+
+    fn stop_container(&self, _name: &str) -> Result<()> {
+        unsupported("Stopping containers is")
+    }
+
+    fn pull_image(&self, image: &str) -> Result<()> {
+        Err(eyre::eyre!(
+            "The bwrap backend can't pull images; '{}' must be a local root filesystem directory",
+            image
+        ))
+    }
+
+    fn build_image(&self, _build: &BuildConfig, _tag: &str) -> Result<()> {
+        unsupported("Building images is")
+    }
+
+    fn start_egress_proxy(
+        &self,
+        _proxy: &EgressProxy,
+        _image: &str,
+        _allow_hosts: &[String],
+    ) -> Result<()> {
+        unsupported("network.allow_hosts is")
+    }
+
+    fn stop_egress_proxy(&self, _proxy: &EgressProxy) -> Result<()> {
+        Ok(())
+    }
+
+    fn ensure_volume(&self, _name: &str) -> Result<()> {
+        unsupported("Named volumes are")
+    }
+
+    fn list_volumes(&self) -> Result<Vec<String>> {
+        unsupported("Named volumes are")
+    }
+
+    fn remove_volume(&self, _name: &str) -> Result<()> {
+        unsupported("Named volumes are")
+    }
+
+    fn copy_into_container(&self, _container: &str, _host: &Path, _dest: &str) -> Result<()> {
+        unsupported("--copy-in is")
+    }
+
+    fn log_command(&self, _name: &str) -> Result<(&'static str, Vec<String>)> {
+        unsupported("Capturing container logs is")
+    }
+
+    fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
+        let args = bwrap_args(config, root_dir(&config.image)?)?;
+        print_command("bwrap", &redact_args(&args, &config.redact_env));
+        run_attached("bwrap", "Bubblewrap", &args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container_config() -> ContainerConfig {
+        ContainerConfig {
+            image: "/".to_string(),
+            entrypoint: Some(vec!["/bin/bash".to_string()]),
+            command: Some(vec!["-l".to_string()]),
+            user: format!(
+                "{}:{}",
+                nix::unistd::getuid().as_raw(),
+                nix::unistd::getgid().as_raw()
+            ),
+            working_dir: "/home/me/ws".to_string(),
+            mounts: vec![
+                "/host/ws:/home/me/ws:rw".to_string(),
+                "/nix/store:/nix/store:ro".to_string(),
+                "/host/share:/usr/share/x:ro".to_string(),
+            ],
+            env: vec!["HOME=/home/me".to_string(), "API_TOKEN=hunter2".to_string()],
+            network: Some("none".to_string()),
+            dns: vec!["1.1.1.1".to_string()],
+            ..ContainerConfig::for_test()
+        }
+    }
+
+    #[test]
+    fn test_bwrap_args() {
+        let root = std::env::temp_dir().join(format!("ab_bwrap_root_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["usr/share/x", "home", "etc", "dev", "tmp"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::os::unix::fs::symlink("usr/bin", root.join("bin")).unwrap();
+        let in_root = |dir: &str| root.join(dir).display().to_string();
+
+        let args = bwrap_args(&container_config(), &root).unwrap();
+        let mut expected: Vec<String> = [
+            "--die-with-parent",
+            "--unshare-pid",
+            "--unshare-ipc",
+            "--unshare-uts",
+            "--hostname",
+            "ab-test",
+            "--unshare-net",
+            "--symlink",
+            "usr/bin",
+            "/bin",
+            "--ro-bind",
+            &in_root("etc"),
+            "/etc",
+            // /home/me/ws isn't in the root, so /home becomes a tmpfs to create it in
+            "--tmpfs",
+            "/home",
+            "--ro-bind",
+            &in_root("usr"),
+            "/usr",
+            "--dev",
+            "/dev",
+            "--proc",
+            "/proc",
+            "--tmpfs",
+            "/tmp",
+            "--tmpfs",
+            "/run",
+            "--bind",
+            "/host/ws",
+            "/home/me/ws",
+            "--ro-bind",
+            "/nix/store",
+            "/nix/store",
+            "--ro-bind",
+            "/host/share",
+            "/usr/share/x",
+            "--clearenv",
+            "--setenv",
+            "PATH",
+            DEFAULT_PATH,
+            "--setenv",
+            "HOME",
+            "/home/me",
+            "--setenv",
+            "API_TOKEN",
+            "hunter2",
+            "--chdir",
+            "/home/me/ws",
+            "--",
+            "/bin/bash",
+            "-l",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(args, expected);
+
+        // The printed command masks secrets passed with --setenv
+        let printed = redact_args(&args, &[]);
+        let token = printed.iter().position(|a| a == "API_TOKEN").unwrap();
+        assert_ne!(printed[token + 1], "hunter2");
+        assert_eq!(printed[token - 1], "--setenv");
+
+        // No entrypoint or command runs a shell
+        let mut config = container_config();
+        config.entrypoint = None;
+        config.command = None;
+        let args = bwrap_args(&config, &root).unwrap();
+        expected.truncate(expected.len() - 2);
+        expected.push("/bin/sh".to_string());
+        assert_eq!(args, expected);
+
+        // tmpfs mounts come after the binds they may shadow; as for binds, a
+        // destination missing from the root makes its top-level directory a tmpfs
+        let mut config = container_config();
+        config.tmpfs = vec!["/home/me/.cache".to_string(), "/etc/scratch".to_string()];
+        let args = bwrap_args(&config, &root).unwrap();
+        let pairs: Vec<_> = args
+            .windows(2)
+            .map(|w| (w[0].as_str(), w[1].as_str()))
+            .collect();
+        assert!(pairs.contains(&("--tmpfs", "/etc")));
+        let clearenv = args.iter().position(|a| a == "--clearenv").unwrap();
+        assert_eq!(
+            args[clearenv - 4..clearenv],
+            ["--tmpfs", "/home/me/.cache", "--tmpfs", "/etc/scratch"]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_bwrap_args_rejects_unsupported_features() {
+        let err = |change: fn(&mut ContainerConfig)| {
+            let mut config = container_config();
+            change(&mut config);
+            bwrap_args(&config, Path::new("/")).unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            err(|c| c.ports.push("8080:80".to_string())),
+            "Port mappings are not supported by the bwrap backend"
+        );
+        assert_eq!(
+            err(|c| c.resources.memory = Some("4g".to_string())),
+            "Resource limits are not supported by the bwrap backend"
+        );
+        assert_eq!(
+            err(|c| c.mounts.push("/a:/a:O".to_string())),
+            "Overlay (:O) mounts are not supported by the bwrap backend"
+        );
+        assert!(err(|c| c.network = Some("bridge".to_string())).contains("'bridge'"));
+        assert!(root_dir("agent:latest").is_err());
+    }
+}
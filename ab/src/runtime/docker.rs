@@ -1,6 +1,17 @@
 use eyre::{Context, Result};
+use std::path::Path;
 
-use super::{ContainerConfig, print_command};
+use agent_box_common::config::BuildConfig;
+
+use super::egress::{self, EgressProxy};
+use super::logs;
+use super::session::{self, SessionContainer};
+use super::volume;
+use super::{
+    BackendCapabilities, ContainerConfig, DOCKER_CAPABILITIES, build_args, copy_in_args,
+    inspect_image_id, inspect_image_user, list_containers_by_labels, list_image_dirs,
+    print_command, read_image_file, redact_args, run_args, run_attached, run_build, start_args,
+};
 
 /// Docker container runtime implementation
 pub struct DockerRuntime;
@@ -18,6 +29,10 @@ impl Default for DockerRuntime {
 }
 
 impl ContainerBackend for DockerRuntime {
+    fn capabilities(&self) -> BackendCapabilities {
+        DOCKER_CAPABILITIES
+    }
+
     fn path_exists_in_image(&self, image: &str, path: &str) -> Result<bool> {
         use std::process::Stdio;
 
@@ -83,89 +98,172 @@ impl ContainerBackend for DockerRuntime {
     }
 
     fn list_paths_in_image(&self, image: &str, root_path: Option<&str>) -> Result<Vec<String>> {
-        use std::process::Stdio;
+        list_image_dirs("docker", image, root_path)
+    }
 
-        // Create a container without starting it
-        let create_output = std::process::Command::new("docker")
-            .args(["create", image])
-            .output()
-            .wrap_err("Failed to create container")?;
+    fn image_user(&self, image: &str) -> Result<String> {
+        inspect_image_user("docker", image)
+    }
 
-        if !create_output.status.success() {
-            let stderr = String::from_utf8_lossy(&create_output.stderr);
-            return Err(eyre::eyre!("Failed to create container: {}", stderr));
+    fn image_id(&self, image: &str) -> Result<String> {
+        inspect_image_id("docker", image)
+    }
+
+    fn read_image_file(&self, image: &str, path: &str) -> Result<String> {
+        read_image_file("docker", image, path)
+    }
+
+    fn list_containers_by_labels(&self, labels: &[String]) -> Result<Vec<String>> {
+        list_containers_by_labels("docker", labels)
+    }
+
+    fn list_sessions(&self) -> Result<Vec<SessionContainer>> {
+        session::list_sessions("docker")
+    }
+
+    fn stop_container(&self, name: &str) -> Result<()> {
+        print_command("docker", &["stop".to_string(), name.to_string()]);
+        session::stop_container("docker", name)
+    }
+
+    fn exec_in_container(
+        &self,
+        name: &str,
+        command: &[String],
+        env: &[String],
+        workdir: Option<&str>,
+        tty: bool,
+    ) -> Result<i32> {
+        let args = session::exec_args(name, command, env, workdir, tty);
+        print_command("docker", &args);
+        session::run_exec("docker", &args)
+    }
+
+    fn attach_container(&self, name: &str) -> Result<()> {
+        let args = vec!["attach".to_string(), name.to_string()];
+        print_command("docker", &args);
+        run_attached("docker", "Docker", &args)
+    }
+
+    fn image_exists(&self, image: &str) -> Result<bool> {
+        let status = std::process::Command::new("docker")
+            .args(["image", "inspect", image])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .wrap_err("Failed to execute docker image inspect")?;
+
+        Ok(status.success())
+    }
+
+    fn pull_image(&self, image: &str) -> Result<()> {
+        let status = std::process::Command::new("docker")
+            .args(["pull", image])
+            .status()
+            .wrap_err("Failed to execute docker pull")?;
+
+        if !status.success() {
+            return Err(eyre::eyre!(
+                "Failed to pull image '{}' with Docker: {}",
+                image,
+                status
+            ));
         }
 
-        let container_id = String::from_utf8_lossy(&create_output.stdout)
-            .trim()
-            .to_string();
+        Ok(())
+    }
 
-        // Export the container filesystem and list contents with tar
-        let export_child = std::process::Command::new("docker")
-            .args(["export", &container_id])
-            .stdout(Stdio::piped())
-            .spawn()
-            .wrap_err("Failed to spawn docker export")?;
+    fn build_image(&self, build: &BuildConfig, tag: &str) -> Result<()> {
+        let args = build_args(build, tag);
+        print_command("docker", &args);
+        run_build("docker", "Docker", &args)
+    }
 
-        let tar_child = std::process::Command::new("tar")
-            .args(["-tv"]) // Verbose mode shows file types
-            .stdin(export_child.stdout.unwrap())
-            .stdout(Stdio::piped())
-            .spawn()
-            .wrap_err("Failed to spawn tar")?;
+    fn start_egress_proxy(
+        &self,
+        proxy: &EgressProxy,
+        image: &str,
+        allow_hosts: &[String],
+    ) -> Result<()> {
+        egress::start_egress_proxy("docker", "bridge", proxy, image, allow_hosts)
+    }
 
-        let output = tar_child
-            .wait_with_output()
-            .wrap_err("Failed to read tar output")?;
+    fn stop_egress_proxy(&self, proxy: &EgressProxy) -> Result<()> {
+        egress::stop_egress_proxy("docker", proxy)
+    }
 
-        // Cleanup the container
-        let _ = std::process::Command::new("docker")
-            .args(["rm", &container_id])
-            .output();
+    fn ensure_volume(&self, name: &str) -> Result<()> {
+        volume::ensure_volume("docker", name)
+    }
+
+    fn list_volumes(&self) -> Result<Vec<String>> {
+        volume::list_volumes("docker")
+    }
+
+    fn remove_volume(&self, name: &str) -> Result<()> {
+        volume::remove_volume("docker", name)
+    }
+
+    fn log_command(&self, name: &str) -> Result<(&'static str, Vec<String>)> {
+        Ok(("sh", logs::follow_and_remove_args("docker", name)))
+    }
+
+    fn copy_into_container(&self, container: &str, host: &Path, dest: &str) -> Result<()> {
+        let args = copy_in_args(container, host, dest);
+        print_command("docker", &args);
+
+        let output = std::process::Command::new("docker")
+            .args(&args)
+            .output()
+            .wrap_err("Failed to execute docker cp")?;
 
         if !output.status.success() {
-            return Err(eyre::eyre!("Failed to list tar contents"));
+            return Err(eyre::eyre!(
+                "Failed to copy {} into container: {}",
+                host.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
         }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let all_paths: Vec<String> = stdout
-            .lines()
-            // Filter for directories only (line starts with 'd' in permissions)
-            .filter(|line| line.starts_with('d'))
-            .filter_map(|line| {
-                // Parse tar verbose output: "drwxr-xr-x 0/0  0 2024-01-01 00:00 path/to/dir/"
-                // The path is the last field
-                line.split_whitespace().last().map(|s| {
-                    let trimmed = s.trim_end_matches('/');
-                    if trimmed.is_empty() || trimmed == "." {
-                        "/".to_string()
-                    } else if trimmed.starts_with('/') {
-                        trimmed.to_string()
-                    } else {
-                        format!("/{}", trimmed)
-                    }
-                })
-            })
-            .collect();
-
-        // Filter by root_path if specified
-        let filtered_paths: Vec<String> = if let Some(root) = root_path {
-            let root_normalized = root.trim_end_matches('/');
-            all_paths
-                .into_iter()
-                .filter(|p| {
-                    if root_normalized.is_empty() || root_normalized == "/" {
-                        true
-                    } else {
-                        p == root_normalized || p.starts_with(&format!("{}/", root_normalized))
-                    }
-                })
-                .collect()
-        } else {
-            all_paths
-        };
+        Ok(())
+    }
+
+    fn create_container(&self, config: &ContainerConfig) -> Result<()> {
+        let mut args = run_args(config, &self.capabilities())?;
+        args[0] = "create".to_string();
+        print_command("docker", &redact_args(&args, &config.redact_env));
+
+        let output = std::process::Command::new("docker")
+            .args(&args)
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .wrap_err("Failed to execute docker create")?;
+
+        if !output.status.success() {
+            return Err(eyre::eyre!(
+                "Failed to create Docker container: {}",
+                output.status
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn remove_container(&self, name: &str) -> Result<()> {
+        let output = std::process::Command::new("docker")
+            .args(["rm", "-f", name])
+            .output()
+            .wrap_err("Failed to execute docker rm")?;
+
+        if !output.status.success() {
+            return Err(eyre::eyre!(
+                "Failed to remove container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
 
-        Ok(filtered_paths)
+        Ok(())
     }
 
     fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
@@ -183,94 +281,155 @@ impl ContainerBackend for DockerRuntime {
         eprintln!("  Network: {:?}", config.network);
         eprintln!("  DNS: {} servers", config.dns.len());
 
-        let mut args = vec![
-            "run".to_string(),
-            "--name".to_string(),
-            config.name.clone(),
-            "--rm".to_string(),
-            "-it".to_string(),
-            "--user".to_string(),
-            config.user.clone(),
-            "--workdir".to_string(),
-            config.working_dir.clone(),
-        ];
-
-        // Add network mode if specified
-        if let Some(ref network) = config.network {
-            args.push("--network".to_string());
-            args.push(network.clone());
-        }
+        // With --copy-in, `create_container` has already created it and the files are in
+        let args = if config.copy_in.is_empty() {
+            run_args(config, &self.capabilities())?
+        } else {
+            start_args(&config.name, config.detach)
+        };
 
-        // Add mounts
-        for mount in &config.mounts {
-            args.push("-v".to_string());
-            args.push(mount.clone());
-        }
+        print_command("docker", &redact_args(&args, &config.redact_env));
 
-        // Add environment variables
-        for env in &config.env {
-            args.push("-e".to_string());
-            args.push(env.clone());
-        }
+        // Execute docker run with inherited stdin/stdout
+        run_attached("docker", "Docker", &args)
+    }
+}
 
-        // Add port mappings
-        for port in &config.ports {
-            args.push("-p".to_string());
-            args.push(port.clone());
-        }
+/// Internal trait for runtime implementations. Every method but
+/// [`capabilities`](Self::capabilities) defaults to an unsupported-feature error.
+pub(super) trait ContainerBackend: Send + Sync {
+    /// Backend-specific features this backend supports
+    fn capabilities(&self) -> BackendCapabilities;
 
-        // Add custom host entries
-        for host in &config.hosts {
-            args.push("--add-host".to_string());
-            args.push(host.clone());
-        }
+    fn spawn_container(&self, _config: &ContainerConfig) -> Result<()> {
+        self.capabilities().unsupported("Spawning containers is")
+    }
 
-        // Add custom DNS servers
-        for dns in &config.dns {
-            args.push("--dns".to_string());
-            args.push(dns.clone());
-        }
+    /// Check if a path exists in the container image
+    fn path_exists_in_image(&self, _image: &str, _path: &str) -> Result<bool> {
+        self.capabilities().unsupported("Inspecting images is")
+    }
 
-        // Add entrypoint if specified
-        if let Some(entrypoint) = &config.entrypoint {
-            args.push("--entrypoint".to_string());
-            args.push(entrypoint.join(" "));
-        }
+    /// List all paths in the container image
+    fn list_paths_in_image(&self, _image: &str, _root_path: Option<&str>) -> Result<Vec<String>> {
+        self.capabilities().unsupported("Inspecting images is")
+    }
 
-        // Add image
-        args.push(config.image.clone());
+    /// Check if the image is present in local storage
+    fn image_exists(&self, _image: &str) -> Result<bool> {
+        self.capabilities().unsupported("Inspecting images is")
+    }
 
-        // Add command arguments (passed to entrypoint)
-        if let Some(command) = &config.command {
-            args.extend(command.clone());
-        }
+    /// The image's default `USER` (`Config.User`), empty if it sets none
+    fn image_user(&self, _image: &str) -> Result<String> {
+        self.capabilities().unsupported("Inspecting images is")
+    }
 
-        print_command("docker", &args);
+    /// The image's ID (`image inspect` `.Id`)
+    fn image_id(&self, _image: &str) -> Result<String> {
+        self.capabilities().unsupported("Inspecting images is")
+    }
 
-        // Execute docker run with inherited stdio
-        let status = std::process::Command::new("docker")
-            .args(&args)
-            .status()
-            .wrap_err("Failed to execute docker command")?;
+    /// Contents of a file in the image
+    fn read_image_file(&self, _image: &str, _path: &str) -> Result<String> {
+        self.capabilities().unsupported("Inspecting images is")
+    }
 
-        if !status.success() {
-            return Err(eyre::eyre!(
-                "Docker container exited with status: {}",
-                status
-            ));
-        }
+    /// Names of running containers carrying all `KEY=VALUE` labels
+    fn list_containers_by_labels(&self, _labels: &[String]) -> Result<Vec<String>> {
+        self.capabilities().unsupported("Listing containers is")
+    }
 
-        Ok(())
+    /// Attach the terminal to a running container's main process
+    fn attach_container(&self, _name: &str) -> Result<()> {
+        self.capabilities()
+            .unsupported("Attaching to containers is")
     }
-}
 
-/// Internal trait for runtime implementations
-pub(super) trait ContainerBackend: Send + Sync {
-    fn spawn_container(&self, config: &ContainerConfig) -> Result<()>;
+    /// Run `command` in a running container, returning its exit code
+    fn exec_in_container(
+        &self,
+        _name: &str,
+        _command: &[String],
+        _env: &[String],
+        _workdir: Option<&str>,
+        _tty: bool,
+    ) -> Result<i32> {
+        self.capabilities()
+            .unsupported("Running commands in containers is")
+    }
 
-    /// Check if a path exists in the container image
-    fn path_exists_in_image(&self, image: &str, path: &str) -> Result<bool>;
+    /// Containers carrying a session label, running or not
+    fn list_sessions(&self) -> Result<Vec<SessionContainer>> {
+        self.capabilities()
+            .unsupported("Listing session containers is")
+    }
 
-    /// List all paths in the container image
-    fn list_paths_in_image(&self, image: &str, root_path: Option<&str>) -> Result<Vec<String>>;
+    /// Stop a running container
+    fn stop_container(&self, _name: &str) -> Result<()> {
+        self.capabilities().unsupported("Stopping containers is")
+    }
+
+    /// Pull the image from its registry
+    fn pull_image(&self, _image: &str) -> Result<()> {
+        self.capabilities().unsupported("Pulling images is")
+    }
+
+    /// Build `build` into the image `tag`
+    fn build_image(&self, _build: &BuildConfig, _tag: &str) -> Result<()> {
+        self.capabilities().unsupported("Building images is")
+    }
+
+    /// Create `proxy`'s internal network and start its sidecar from `image`
+    fn start_egress_proxy(
+        &self,
+        _proxy: &EgressProxy,
+        _image: &str,
+        _allow_hosts: &[String],
+    ) -> Result<()> {
+        self.capabilities().unsupported("network.allow_hosts is")
+    }
+
+    /// Remove `proxy`'s sidecar and internal network
+    fn stop_egress_proxy(&self, _proxy: &EgressProxy) -> Result<()> {
+        self.capabilities().unsupported("network.allow_hosts is")
+    }
+
+    /// Create the named volume unless it exists
+    fn ensure_volume(&self, _name: &str) -> Result<()> {
+        self.capabilities().unsupported("Named volumes are")
+    }
+
+    /// Names of the named volumes agent-box created
+    fn list_volumes(&self) -> Result<Vec<String>> {
+        self.capabilities().unsupported("Named volumes are")
+    }
+
+    /// Remove a named volume
+    fn remove_volume(&self, _name: &str) -> Result<()> {
+        self.capabilities().unsupported("Named volumes are")
+    }
+
+    /// Copy a host file or directory into a created container
+    fn copy_into_container(&self, _container: &str, _host: &Path, _dest: &str) -> Result<()> {
+        self.capabilities().unsupported("--copy-in is")
+    }
+
+    /// Create `config`'s container without starting it, so `--copy-in` files can
+    /// be copied in; [`spawn_container`](Self::spawn_container) then starts it
+    fn create_container(&self, _config: &ContainerConfig) -> Result<()> {
+        self.capabilities().unsupported("--copy-in is")
+    }
+
+    /// Remove a container, running or not
+    fn remove_container(&self, _name: &str) -> Result<()> {
+        self.capabilities().unsupported("Removing containers is")
+    }
+
+    /// Program and args following a detached container's output until it exits,
+    /// then removing it if it doesn't remove itself
+    fn log_command(&self, _name: &str) -> Result<(&'static str, Vec<String>)> {
+        self.capabilities()
+            .unsupported("Capturing container logs is")
+    }
 }
@@ -0,0 +1,446 @@
+use eyre::Result;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{ContainerConfig, Runtime, print_command};
+
+/// Port the proxy sidecar listens on
+pub const PROXY_PORT: u16 = 3128;
+
+/// Where the host `ab` binary is mounted in the proxy sidecar
+const PROXY_BINARY: &str = "/agent-box-proxy";
+
+/// Longest request head the proxy reads before giving up
+const MAX_HEAD: usize = 64 * 1024;
+
+/// True if `host` matches an `allow_hosts` entry: an exact name, or with a
+/// `*.` prefix any subdomain of it. Case and a trailing dot are ignored.
+pub fn host_allowed(allow_hosts: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    allow_hosts.iter().any(|pattern| {
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .and_then(|sub| sub.strip_suffix('.'))
+                .is_some_and(|sub| !sub.is_empty()),
+            None => host == pattern,
+        }
+    })
+}
+
+/// Where a proxied request goes, from its request line
+#[derive(Debug, PartialEq)]
+pub(crate) struct ProxyTarget {
+    pub host: String,
+    pub port: u16,
+    /// `CONNECT` tunnel (HTTPS) rather than a plain HTTP request
+    pub connect: bool,
+    /// Request line to send upstream, in origin form (plain HTTP only)
+    pub request_line: String,
+}
+
+/// Parse `CONNECT host:port HTTP/1.1` or `GET http://host[:port]/path HTTP/1.1`
+pub(crate) fn parse_proxy_request(line: &str) -> Result<ProxyTarget> {
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        eyre::bail!("Malformed request line '{}'", line);
+    };
+
+    let split_port = |authority: &str, default: u16| -> Result<(String, u16)> {
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| eyre::eyre!("Invalid port in '{}'", authority))?,
+            ),
+            _ => (authority, default),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            eyre::bail!("Missing host in '{}'", authority);
+        }
+        Ok((host.to_string(), port))
+    };
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_port(target, 443)?;
+        return Ok(ProxyTarget {
+            host,
+            port,
+            connect: true,
+            request_line: String::new(),
+        });
+    }
+
+    let rest = target.strip_prefix("http://").ok_or_else(|| {
+        eyre::eyre!(
+            "Only http:// URLs and CONNECT are proxied, not '{}'",
+            target
+        )
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_port(authority, 80)?;
+    Ok(ProxyTarget {
+        host,
+        port,
+        connect: false,
+        request_line: format!("{} {} {}", method, path, version),
+    })
+}
+
+/// Run the allowlist proxy on `listen` until killed; see `ab proxy`
+pub fn serve(listen: &str, allow_hosts: Vec<String>) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .map_err(|e| eyre::eyre!("Failed to listen on {}: {}", listen, e))?;
+    eprintln!(
+        "Proxy listening on {}, allowing: {}",
+        listen,
+        allow_hosts.join(", ")
+    );
+    let allow_hosts = Arc::new(allow_hosts);
+    for client in listener.incoming().flatten() {
+        let allow_hosts = Arc::clone(&allow_hosts);
+        std::thread::spawn(move || {
+            if let Err(e) = handle_client(client, &allow_hosts) {
+                eprintln!("proxy: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer the client with a bodyless status
+fn respond(client: &mut TcpStream, status: &str) -> Result<()> {
+    client.write_all(
+        format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        )
+        .as_bytes(),
+    )?;
+    Ok(())
+}
+
+fn handle_client(mut client: TcpStream, allow_hosts: &[String]) -> Result<()> {
+    // Read up to the end of the request head; anything after it is body or tunnel data
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if buf.len() > MAX_HEAD {
+            respond(&mut client, "431 Request Header Fields Too Large")?;
+            eyre::bail!("request head too large");
+        }
+        let n = client.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let (line, headers) = head.split_once("\r\n").unwrap_or((&head, ""));
+
+    let target = match parse_proxy_request(line) {
+        Ok(target) => target,
+        Err(e) => {
+            respond(&mut client, "400 Bad Request")?;
+            return Err(e);
+        }
+    };
+    if !host_allowed(allow_hosts, &target.host) {
+        respond(&mut client, "403 Forbidden")?;
+        eyre::bail!("denied {}:{}", target.host, target.port);
+    }
+    let mut upstream = match TcpStream::connect((target.host.as_str(), target.port)) {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            respond(&mut client, "502 Bad Gateway")?;
+            eyre::bail!("{}:{}: {}", target.host, target.port, e);
+        }
+    };
+
+    if target.connect {
+        client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+    } else {
+        // One request per connection, so a kept-alive connection can't switch hosts
+        let mut request = format!("{}\r\n", target.request_line);
+        for header in headers.split("\r\n").filter(|h| !h.is_empty()) {
+            let name = header.split(':').next().unwrap_or("").to_ascii_lowercase();
+            if name != "connection" && !name.starts_with("proxy-") {
+                request.push_str(header);
+                request.push_str("\r\n");
+            }
+        }
+        request.push_str("Connection: close\r\n\r\n");
+        upstream.write_all(request.as_bytes())?;
+    }
+    upstream.write_all(&buf[head_end..])?;
+
+    // Copy both ways until either side closes
+    let mut client_read = client.try_clone()?;
+    let mut upstream_write = upstream.try_clone()?;
+    let uploader = std::thread::spawn(move || {
+        let _ = std::io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(Shutdown::Write);
+    });
+    let _ = std::io::copy(&mut upstream, &mut client);
+    // The upstream is done; a client still holding its end open gets closed too
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = uploader.join();
+    Ok(())
+}
+
+/// The internal network and proxy sidecar a spawn with `allow_hosts` runs behind.
+/// The container joins only the internal network, so the sidecar, which is also
+/// on the runtime's default network, is its only way out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EgressProxy {
+    pub network: String,
+    pub container: String,
+}
+
+impl EgressProxy {
+    pub fn for_container(name: &str) -> Self {
+        Self {
+            network: format!("{}-egress", name),
+            container: format!("{}-proxy", name),
+        }
+    }
+
+    /// Commands (runtime arguments) that create the network and start the
+    /// sidecar: the host `ab` binary, mounted into `image`, on `outside_network`
+    pub fn start_commands(
+        &self,
+        image: &str,
+        binary: &Path,
+        outside_network: &str,
+        allow_hosts: &[String],
+    ) -> Vec<Vec<String>> {
+        let mut run: Vec<String> = [
+            "run",
+            "-d",
+            "--rm",
+            "--name",
+            &self.container,
+            "--network",
+            outside_network,
+            "--entrypoint",
+            PROXY_BINARY,
+            "-v",
+            &format!("{}:{}:ro", binary.display(), PROXY_BINARY),
+            image,
+            "proxy",
+            "--listen",
+            &format!("0.0.0.0:{}", PROXY_PORT),
+        ]
+        .map(str::to_string)
+        .to_vec();
+        for host in allow_hosts {
+            run.push("--allow".to_string());
+            run.push(host.clone());
+        }
+        vec![
+            ["network", "create", "--internal", &self.network]
+                .map(str::to_string)
+                .to_vec(),
+            run,
+            ["network", "connect", &self.network, &self.container]
+                .map(str::to_string)
+                .to_vec(),
+        ]
+    }
+
+    /// Commands that remove the sidecar and the network
+    pub fn stop_commands(&self) -> Vec<Vec<String>> {
+        vec![
+            ["rm", "-f", &self.container].map(str::to_string).to_vec(),
+            ["network", "rm", &self.network]
+                .map(str::to_string)
+                .to_vec(),
+        ]
+    }
+
+    /// Put `config` on the internal network and point it at the sidecar
+    pub fn apply(&self, config: &mut ContainerConfig) {
+        config.network = Some(self.network.clone());
+        let url = format!("http://{}:{}", self.container, PROXY_PORT);
+        for var in ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"] {
+            config.env.push(format!("{}={}", var, url));
+        }
+        for var in ["NO_PROXY", "no_proxy"] {
+            config.env.push(format!("{}=localhost,127.0.0.1", var));
+        }
+    }
+}
+
+/// Start `proxy` with `program`, removing whatever was created if a step fails
+pub(crate) fn start_egress_proxy(
+    program: &str,
+    outside_network: &str,
+    proxy: &EgressProxy,
+    image: &str,
+    allow_hosts: &[String],
+) -> Result<()> {
+    let binary = std::env::current_exe()
+        .map_err(|e| eyre::eyre!("Failed to find the ab binary for the proxy: {}", e))?;
+    for args in proxy.start_commands(image, &binary, outside_network, allow_hosts) {
+        if let Err(e) = run_quiet(program, &args) {
+            let _ = stop_egress_proxy(program, proxy);
+            eyre::bail!("Failed to start the egress proxy: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Remove `proxy`'s sidecar and network with `program`
+pub(crate) fn stop_egress_proxy(program: &str, proxy: &EgressProxy) -> Result<()> {
+    let mut result = Ok(());
+    for args in proxy.stop_commands() {
+        if let Err(e) = run_quiet(program, &args) {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Run `program args`, failing with its stderr
+fn run_quiet(program: &str, args: &[String]) -> Result<()> {
+    print_command(program, args);
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| eyre::eyre!("Failed to execute {}: {}", program, e))?;
+    if !output.status.success() {
+        eyre::bail!(
+            "{} {} failed: {}",
+            program,
+            args[..2].join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Removes a spawn's egress proxy when dropped
+pub(crate) struct EgressGuard<'a> {
+    pub runtime: &'a Runtime,
+    pub proxy: EgressProxy,
+}
+
+impl Drop for EgressGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.runtime.stop_egress_proxy(&self.proxy) {
+            eprintln!("WARNING: Failed to remove the egress proxy: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_allowed() {
+        let allow = ["github.com".to_string(), "*.NPMJS.org".to_string()];
+        assert!(host_allowed(&allow, "github.com"));
+        assert!(host_allowed(&allow, "GitHub.com."));
+        assert!(!host_allowed(&allow, "api.github.com"));
+        assert!(!host_allowed(&allow, "evilgithub.com"));
+        assert!(host_allowed(&allow, "registry.npmjs.org"));
+        assert!(host_allowed(&allow, "a.b.npmjs.org"));
+        assert!(!host_allowed(&allow, "npmjs.org"));
+        assert!(!host_allowed(&allow, "evilnpmjs.org"));
+        assert!(!host_allowed(&[], "github.com"));
+    }
+
+    #[test]
+    fn test_parse_proxy_request() {
+        let target = |host: &str, port, connect, request_line: &str| ProxyTarget {
+            host: host.to_string(),
+            port,
+            connect,
+            request_line: request_line.to_string(),
+        };
+        assert_eq!(
+            parse_proxy_request("CONNECT github.com:443 HTTP/1.1").unwrap(),
+            target("github.com", 443, true, "")
+        );
+        assert_eq!(
+            parse_proxy_request("CONNECT [::1]:8443 HTTP/1.1").unwrap(),
+            target("::1", 8443, true, "")
+        );
+        assert_eq!(
+            parse_proxy_request("GET http://example.com:8080/a?b=c HTTP/1.1").unwrap(),
+            target("example.com", 8080, false, "GET /a?b=c HTTP/1.1")
+        );
+        assert_eq!(
+            parse_proxy_request("HEAD http://example.com HTTP/1.0").unwrap(),
+            target("example.com", 80, false, "HEAD / HTTP/1.0")
+        );
+        for bad in [
+            "GET /local HTTP/1.1",
+            "GET https://example.com/ HTTP/1.1",
+            "CONNECT :443 HTTP/1.1",
+            "CONNECT example.com:https HTTP/1.1",
+            "GET",
+        ] {
+            assert!(parse_proxy_request(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_proxy_denies_hosts_off_the_allowlist() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let client = listener.incoming().next().unwrap().unwrap();
+            let _ = handle_client(client, &["github.com".to_string()]);
+        });
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"CONNECT evil.example:443 HTTP/1.1\r\nHost: evil.example\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 403 "), "{}", response);
+    }
+
+    #[test]
+    fn test_egress_proxy_commands_and_env() {
+        let proxy = EgressProxy::for_container("ab-repo");
+        let commands = proxy.start_commands(
+            "img",
+            Path::new("/usr/bin/ab"),
+            "bridge",
+            &["github.com".to_string()],
+        );
+        let joined: Vec<String> = commands.iter().map(|c| c.join(" ")).collect();
+        assert_eq!(
+            joined,
+            [
+                "network create --internal ab-repo-egress",
+                "run -d --rm --name ab-repo-proxy --network bridge --entrypoint /agent-box-proxy \
+                 -v /usr/bin/ab:/agent-box-proxy:ro img proxy --listen 0.0.0.0:3128 --allow github.com",
+                "network connect ab-repo-egress ab-repo-proxy",
+            ]
+        );
+        assert_eq!(
+            proxy
+                .stop_commands()
+                .iter()
+                .map(|c| c.join(" "))
+                .collect::<Vec<_>>(),
+            ["rm -f ab-repo-proxy", "network rm ab-repo-egress"]
+        );
+    }
+}
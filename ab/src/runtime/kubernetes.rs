@@ -0,0 +1,1091 @@
+use eyre::{Context, Result};
+use serde_json::{Value, json};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use agent_box_common::config::{
+    BuildConfig, KubernetesConfig, KubernetesMountSource, should_redact_env,
+};
+
+use super::docker::ContainerBackend;
+use super::egress::EgressProxy;
+use super::session::{self, SessionContainer};
+use super::volume::VOLUME_LABEL;
+use super::{
+    BackendCapabilities, ContainerConfig, KUBERNETES_CAPABILITIES, REPO_LABEL, SESSION_LABEL,
+    parse_bind, print_command, redact_args, run_attached,
+};
+
+/// Kubernetes runtime: runs each spawn as a pod through `kubectl`, in the
+/// context and namespace `[runtime.kubernetes]` names. The cluster pulls
+/// `runtime.image` itself; bind mounts reach the pod through the adapter
+/// `kubernetes.mounts` selects.
+pub struct KubernetesRuntime {
+    config: KubernetesConfig,
+}
+
+impl KubernetesRuntime {
+    pub fn new(config: KubernetesConfig) -> Self {
+        Self { config }
+    }
+
+    /// `--context` and `--namespace` args for the configured cluster
+    fn global_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(context) = &self.config.context {
+            args.extend(["--context".to_string(), context.clone()]);
+        }
+        if let Some(namespace) = &self.config.namespace {
+            args.extend(["--namespace".to_string(), namespace.clone()]);
+        }
+        args
+    }
+
+    /// `kubectl` args: the configured context and namespace, then `args`
+    fn args<S: AsRef<str>>(&self, args: &[S]) -> Vec<String> {
+        let mut all = self.global_args();
+        all.extend(args.iter().map(|arg| arg.as_ref().to_string()));
+        all
+    }
+
+    /// Run `kubectl args` with `stdin` piped in, returning its stdout
+    fn run(&self, args: &[String], stdin: Option<&str>) -> Result<String> {
+        let mut child = Command::new("kubectl")
+            .args(args)
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .wrap_err("Failed to execute kubectl")?;
+        if let Some(input) = stdin
+            && let Some(mut pipe) = child.stdin.take()
+        {
+            pipe.write_all(input.as_bytes())
+                .wrap_err("Failed to write to kubectl")?;
+        }
+        let output = child
+            .wait_with_output()
+            .wrap_err("Failed to wait for kubectl")?;
+        if !output.status.success() {
+            let verb = args[self.global_args().len()..]
+                .iter()
+                .take(2)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ");
+            eyre::bail!(
+                "kubectl {} failed: {}",
+                verb,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Create the Secret [`secret_spec`] holds the pod's secrets in, if it has
+    /// any, owned by the pod so deleting the pod deletes it. `created` is the
+    /// `kubectl create -o json` output for the pod, which has its uid. Until
+    /// the Secret exists, the kubelet holds the container back.
+    fn create_secret(&self, config: &ContainerConfig, created: &str) -> Result<()> {
+        let Some(mut secret) = secret_spec(config) else {
+            return Ok(());
+        };
+        let pod: Value = serde_json::from_str(created)
+            .map_err(|e| eyre::eyre!("Invalid kubectl create output: {}", e))?;
+        secret["metadata"]["ownerReferences"] = json!([{
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "name": pod["metadata"]["name"],
+            "uid": pod["metadata"]["uid"],
+        }]);
+        let create = self.args(&["create", "-f", "-"]);
+        print_command("kubectl", &create);
+        self.run(&create, Some(&secret.to_string())).map(|_| ())
+    }
+
+    /// Wait for the pod to be ready, copy files in, then run the command in it
+    /// unless it's detached. A detached pod's entrypoint is its main process, so
+    /// it's already running by the time the files are copied in.
+    fn start_pod(&self, config: &ContainerConfig, pod: &str) -> Result<()> {
+        let timeout = self.config.ready_timeout.unwrap_or(DEFAULT_READY_TIMEOUT);
+        let wait = self.args(&[
+            "wait".to_string(),
+            "--for=condition=Ready".to_string(),
+            format!("pod/{}", pod),
+            format!("--timeout={}s", timeout),
+        ]);
+        print_command("kubectl", &wait);
+        self.run(&wait, None)?;
+
+        for copy in &config.copy_in {
+            self.copy_into_container(pod, &copy.host, &copy.container)?;
+        }
+        if config.detach {
+            return Ok(());
+        }
+
+        let mut args = self.args(&["exec", "-it", pod, "-c", CONTAINER_NAME, "--"]);
+        args.extend(foreground_command(config));
+        print_command("kubectl", &redact_args(&args, &config.redact_env));
+        run_attached("kubectl", "Kubernetes", &args)
+    }
+}
+
+/// Seconds `kubectl wait` gives a pod to become ready, unless
+/// `kubernetes.ready_timeout` says otherwise
+const DEFAULT_READY_TIMEOUT: u64 = 300;
+
+/// Size of the claims named volumes create, unless `kubernetes.volume_size` says otherwise
+const DEFAULT_VOLUME_SIZE: &str = "10Gi";
+
+/// Name of the pod's one container
+const CONTAINER_NAME: &str = "agent";
+
+/// Label marking the pods agent-box created
+const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+
+/// Error for a feature pods have no equivalent of, worded like
+/// [`BackendCapabilities::require`]
+fn unsupported<T>(feature: &str) -> Result<T> {
+    Err(eyre::eyre!(
+        "{} not supported by the {} backend",
+        feature,
+        KUBERNETES_CAPABILITIES.name
+    ))
+}
+
+/// `name` as a pod name: container names may contain `_`, which DNS names can't
+pub(crate) fn pod_name(name: &str) -> String {
+    name.replace('_', "-")
+}
+
+/// `value` as a label value: at most 63 characters of `[A-Za-z0-9._-]`, starting
+/// and ending alphanumeric. Other characters, such as the `/` in repo names,
+/// become `.`; the original goes in an annotation.
+pub(crate) fn label_value(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || matches!(ch, '.' | '_' | '-') {
+                ch
+            } else {
+                '.'
+            }
+        })
+        .take(63)
+        .collect();
+    cleaned
+        .trim_matches(|ch: char| !ch.is_ascii_alphanumeric())
+        .to_string()
+}
+
+/// `-l` selector for `KEY=VALUE` labels, with values as [`label_value`] sets them
+fn label_selector(labels: &[String]) -> String {
+    labels
+        .iter()
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) => format!("{}={}", key, label_value(value)),
+            None => label.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A memory size as the runtimes take it (`512m`, `1.5g`, `4GiB`) as a
+/// Kubernetes quantity (`512Mi`, `1.5Gi`, `4Gi`)
+pub(crate) fn memory_quantity(size: &str) -> String {
+    let trimmed = size.trim_end_matches(['b', 'B']);
+    let trimmed = trimmed.strip_suffix(['i', 'I']).unwrap_or(trimmed);
+    let (number, unit) = match trimmed.char_indices().last() {
+        Some((i, ch)) if ch.is_ascii_alphabetic() => (&trimmed[..i], Some(ch)),
+        _ => (trimmed, None),
+    };
+    match unit.map(|ch| ch.to_ascii_lowercase()) {
+        Some('k') => format!("{}Ki", number),
+        Some('m') => format!("{}Mi", number),
+        Some('g') => format!("{}Gi", number),
+        Some('t') => format!("{}Ti", number),
+        Some('p') => format!("{}Pi", number),
+        _ => number.to_string(),
+    }
+}
+
+/// Kubernetes `mountPropagation` for a bind's propagation option
+fn mount_propagation(propagation: &str) -> Result<&'static str> {
+    match propagation {
+        "private" | "rprivate" => Ok("None"),
+        "slave" | "rslave" => Ok("HostToContainer"),
+        "shared" | "rshared" => Ok("Bidirectional"),
+        other => Err(eyre::eyre!(
+            "Mount propagation '{}' not supported by the kubernetes backend",
+            other
+        )),
+    }
+}
+
+/// Numeric `uid:gid` of `user`, which pods need for `runAsUser`
+fn numeric_user(user: &str) -> Result<(u32, Option<u32>)> {
+    let (uid, gid) = match user.split_once(':') {
+        Some((uid, gid)) => (uid, Some(gid)),
+        None => (user, None),
+    };
+    let parse = |id: &str| {
+        id.parse::<u32>().map_err(|_| {
+            eyre::eyre!(
+                "Running as the named user '{}' is not supported by the kubernetes backend \
+                 (use a numeric uid:gid)",
+                user
+            )
+        })
+    };
+    Ok((parse(uid)?, gid.map(parse).transpose()?))
+}
+
+/// Pod volume and volume mount for the bind `bind`, the `index`th
+fn bind_volume(bind: &str, index: usize, kube: &KubernetesConfig) -> Result<(Value, Value)> {
+    let parts = parse_bind(bind);
+    let read_only = match parts.mode {
+        "ro" => true,
+        "rw" | "" => false,
+        other => {
+            return Err(eyre::eyre!(
+                "Mount mode '{}' not supported by the kubernetes backend",
+                other
+            ));
+        }
+    };
+    let name = format!("bind-{}", index);
+    let mut mount = json!({
+        "name": name,
+        "mountPath": parts.container,
+        "readOnly": read_only,
+    });
+    if let Some(propagation) = parts.propagation {
+        mount["mountPropagation"] = json!(mount_propagation(propagation)?);
+    }
+
+    let volume = match kube.mounts {
+        KubernetesMountSource::HostPath => json!({
+            "name": name,
+            "hostPath": { "path": parts.host },
+        }),
+        KubernetesMountSource::Pvc => {
+            let (Some(claim), Some(root)) = (&kube.claim, &kube.claim_root) else {
+                eyre::bail!("kubernetes.mounts = \"pvc\" needs kubernetes.claim and claim_root");
+            };
+            let sub_path = Path::new(parts.host).strip_prefix(root).map_err(|_| {
+                eyre::eyre!(
+                    "Bind {} is outside kubernetes.claim_root {}, so the claim can't provide it",
+                    parts.host,
+                    root.display()
+                )
+            })?;
+            if !sub_path.as_os_str().is_empty() {
+                mount["subPath"] = json!(sub_path.to_string_lossy());
+            }
+            json!({
+                "name": name,
+                "persistentVolumeClaim": { "claimName": claim },
+            })
+        }
+    };
+    Ok((volume, mount))
+}
+
+/// What `kubectl exec` runs for a foreground spawn. With no entrypoint or
+/// command there's no way to run the image's default, so it's a shell.
+fn foreground_command(config: &ContainerConfig) -> Vec<String> {
+    let command: Vec<String> = config
+        .entrypoint
+        .iter()
+        .chain(&config.command)
+        .flatten()
+        .cloned()
+        .collect();
+    if command.is_empty() {
+        vec!["/bin/sh".to_string()]
+    } else {
+        command
+    }
+}
+
+/// Pod manifest running `config`. A foreground pod idles so `kubectl exec` can
+/// run the command attached once files are copied in; a detached one runs the
+/// command as its main process.
+pub(crate) fn pod_spec(config: &ContainerConfig, kube: &KubernetesConfig) -> Result<Value> {
+    let caps = KUBERNETES_CAPABILITIES;
+    caps.check(config)?;
+    caps.require(config.ports.is_empty(), "Port mappings are")?;
+    caps.require(
+        config.resources.memory_swap.is_none(),
+        "resources.memory_swap is",
+    )?;
+    caps.require(
+        config.resources.pids_limit.is_none(),
+        "resources.pids_limit is",
+    )?;
+    caps.require(config.resources.ulimits.is_empty(), "resources.ulimits are")?;
+    caps.require(config.gpus.is_empty(), "GPU passthrough is")?;
+    caps.require(
+        matches!(
+            config.security.seccomp_profile.as_deref(),
+            None | Some("unconfined")
+        ),
+        "A security.seccomp_profile file is",
+    )?;
+
+    let mut labels = serde_json::Map::new();
+    let mut annotations = serde_json::Map::new();
+    labels.insert(MANAGED_BY_LABEL.to_string(), json!("agent-box"));
+    for label in &config.labels {
+        let (key, value) = label.split_once('=').unwrap_or((label, ""));
+        labels.insert(key.to_string(), json!(label_value(value)));
+        annotations.insert(key.to_string(), json!(value));
+    }
+
+    let env: Vec<Value> = config
+        .env
+        .iter()
+        .map(|var| {
+            let (name, value) = var
+                .split_once('=')
+                .ok_or_else(|| eyre::eyre!("Invalid env entry '{}': expected KEY=VALUE", var))?;
+            if should_redact_env(name, &config.redact_env) {
+                // Kept out of the manifest, see `secret_spec`
+                return Ok(json!({
+                    "name": name,
+                    "valueFrom": {
+                        "secretKeyRef": {
+                            "name": pod_name(&config.name),
+                            "key": env_secret_key(name),
+                        },
+                    },
+                }));
+            }
+            Ok(json!({ "name": name, "value": value }))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut volumes = Vec::new();
+    let mut volume_mounts = Vec::new();
+    for (index, bind) in config.mounts.iter().enumerate() {
+        let (volume, mount) = bind_volume(bind, index, kube)?;
+        volumes.push(volume);
+        volume_mounts.push(mount);
+    }
+    for (index, path) in config.tmpfs.iter().enumerate() {
+        let name = format!("tmpfs-{}", index);
+        volumes.push(json!({ "name": name, "emptyDir": { "medium": "Memory" } }));
+        volume_mounts.push(json!({ "name": name, "mountPath": path }));
+    }
+    for (index, spec) in config.volumes.iter().enumerate() {
+        let parts = parse_bind(spec);
+        let name = format!("volume-{}", index);
+        volumes.push(json!({
+            "name": name,
+            "persistentVolumeClaim": { "claimName": parts.host },
+        }));
+        volume_mounts.push(json!({
+            "name": name,
+            "mountPath": parts.container,
+            "readOnly": parts.mode == "ro",
+        }));
+    }
+    if !config.secret_files.is_empty() {
+        let items: Vec<Value> = (0..config.secret_files.len())
+            .map(|index| json!({ "key": file_secret_key(index), "path": file_secret_key(index) }))
+            .collect();
+        volumes.push(json!({
+            "name": "secrets",
+            "secret": { "secretName": pod_name(&config.name), "items": items },
+        }));
+        for (index, file) in config.secret_files.iter().enumerate() {
+            volume_mounts.push(json!({
+                "name": "secrets",
+                "mountPath": file.path,
+                "subPath": file_secret_key(index),
+                "readOnly": true,
+            }));
+        }
+    }
+
+    let mut container = json!({
+        "name": CONTAINER_NAME,
+        "image": config.image,
+        "workingDir": config.working_dir,
+        "env": env,
+        "volumeMounts": volume_mounts,
+        "stdin": true,
+        "tty": true,
+    });
+    if config.detach {
+        if let Some(entrypoint) = &config.entrypoint {
+            container["command"] = json!(entrypoint);
+        }
+        if let Some(command) = &config.command {
+            container["args"] = json!(command);
+        }
+    } else {
+        container["command"] = json!(["sleep", "infinity"]);
+    }
+    let mut limits = serde_json::Map::new();
+    if let Some(cpus) = config.resources.cpus {
+        limits.insert("cpu".to_string(), json!(cpus.to_string()));
+    }
+    if let Some(memory) = &config.resources.memory {
+        limits.insert("memory".to_string(), json!(memory_quantity(memory)));
+    }
+    if !limits.is_empty() {
+        container["resources"] = json!({ "limits": limits });
+    }
+    let security = &config.security;
+    container["securityContext"] = json!({
+        "capabilities": { "drop": security.cap_drop, "add": security.cap_add },
+        "allowPrivilegeEscalation": !security.no_new_privileges,
+        "readOnlyRootFilesystem": security.read_only_rootfs,
+        "seccompProfile": {
+            "type": if security.seccomp_profile.is_some() { "Unconfined" } else { "RuntimeDefault" },
+        },
+    });
+
+    let (uid, gid) = numeric_user(&config.user)?;
+    let mut security = json!({ "runAsUser": uid });
+    if let Some(gid) = gid {
+        security["runAsGroup"] = json!(gid);
+    }
+    if !config.group_add.is_empty() {
+        let groups = config
+            .group_add
+            .iter()
+            .map(|group| {
+                group.parse::<u32>().map_err(|_| {
+                    eyre::eyre!(
+                        "Group '{}' must be a numeric gid for the kubernetes backend",
+                        group
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        security["supplementalGroups"] = json!(groups);
+    }
+
+    let mut spec = json!({
+        "restartPolicy": "Never",
+        "securityContext": security,
+        "containers": [container],
+        "volumes": volumes,
+    });
+    if !config.detach {
+        // The idle process ignores SIGTERM; nothing is lost by killing it at once
+        spec["terminationGracePeriodSeconds"] = json!(0);
+    }
+    match config.network.as_deref() {
+        None => {}
+        Some("host") => spec["hostNetwork"] = json!(true),
+        Some(other) => {
+            return Err(eyre::eyre!(
+                "Network mode '{}' not supported by the kubernetes backend (use host, or leave it unset for the pod network)",
+                other
+            ));
+        }
+    }
+    if !config.hosts.is_empty() {
+        let aliases = config
+            .hosts
+            .iter()
+            .map(|entry| {
+                let (host, ip) = entry.split_once(':').ok_or_else(|| {
+                    eyre::eyre!("Invalid host entry '{}': expected HOST:IP", entry)
+                })?;
+                Ok(json!({ "ip": ip, "hostnames": [host] }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        spec["hostAliases"] = json!(aliases);
+    }
+    if !config.dns.is_empty() || !config.dns_search.is_empty() {
+        spec["dnsPolicy"] = json!("None");
+        spec["dnsConfig"] = json!({
+            "nameservers": config.dns,
+            "searches": config.dns_search,
+        });
+    }
+
+    Ok(json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {
+            "name": pod_name(&config.name),
+            "labels": labels,
+            "annotations": annotations,
+        },
+        "spec": spec,
+    }))
+}
+
+/// Key of env var `name` in the pod's Secret
+fn env_secret_key(name: &str) -> String {
+    format!("env.{}", name)
+}
+
+/// Key of the `index`th of `secret_files` in the pod's Secret
+fn file_secret_key(index: usize) -> String {
+    format!("file-{}", index)
+}
+
+/// Secret manifest holding what [`pod_spec`] keeps out of the pod manifest: the
+/// values of redacted env vars and the `secret_files`. `None` if there is
+/// nothing to hold.
+pub(crate) fn secret_spec(config: &ContainerConfig) -> Option<Value> {
+    let mut data = serde_json::Map::new();
+    for var in &config.env {
+        if let Some((name, value)) = var.split_once('=')
+            && should_redact_env(name, &config.redact_env)
+        {
+            data.insert(env_secret_key(name), json!(value));
+        }
+    }
+    for (index, file) in config.secret_files.iter().enumerate() {
+        data.insert(file_secret_key(index), json!(file.value));
+    }
+    if data.is_empty() {
+        return None;
+    }
+    Some(json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": pod_name(&config.name),
+            "labels": { MANAGED_BY_LABEL: "agent-box" },
+        },
+        "type": "Opaque",
+        "stringData": data,
+    }))
+}
+
+/// PersistentVolumeClaim manifest for the named volume `name`
+fn claim_spec(name: &str, kube: &KubernetesConfig) -> Value {
+    let mut spec = json!({
+        "accessModes": ["ReadWriteOnce"],
+        "resources": {
+            "requests": {
+                "storage": kube.volume_size.as_deref().unwrap_or(DEFAULT_VOLUME_SIZE),
+            },
+        },
+    });
+    if let Some(class) = &kube.storage_class {
+        spec["storageClassName"] = json!(class);
+    }
+    json!({
+        "apiVersion": "v1",
+        "kind": "PersistentVolumeClaim",
+        "metadata": {
+            "name": name,
+            "labels": { VOLUME_LABEL: "1" },
+        },
+        "spec": spec,
+    })
+}
+
+/// Parse `kubectl get pods -o json` output. Labels hold sanitized values, so
+/// the repo and session come from the annotations.
+pub(crate) fn parse_pod_sessions(output: &str) -> Result<Vec<SessionContainer>> {
+    let list: Value = serde_json::from_str(output)
+        .map_err(|e| eyre::eyre!("Invalid kubectl get pods output: {}", e))?;
+    let mut sessions: Vec<SessionContainer> = list["items"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|pod| {
+            let text = |value: &Value| value.as_str().unwrap_or_default().to_string();
+            let annotation = |key: &str| text(&pod["metadata"]["annotations"][key]);
+            SessionContainer {
+                name: text(&pod["metadata"]["name"]),
+                repo: annotation(REPO_LABEL),
+                session: annotation(SESSION_LABEL),
+                image: text(&pod["spec"]["containers"][0]["image"]),
+                status: text(&pod["status"]["phase"]),
+            }
+        })
+        .collect();
+    sessions.sort_by(|a, b| (&a.repo, &a.session).cmp(&(&b.repo, &b.session)));
+    Ok(sessions)
+}
+
+impl ContainerBackend for KubernetesRuntime {
+    fn capabilities(&self) -> BackendCapabilities {
+        KUBERNETES_CAPABILITIES
+    }
+
+    fn path_exists_in_image(&self, _image: &str, _path: &str) -> Result<bool> {
+        unsupported("Inspecting images is")
+    }
+
+    fn list_paths_in_image(&self, _image: &str, _root_path: Option<&str>) -> Result<Vec<String>> {
+        unsupported("Inspecting images is")
+    }
+
+    fn image_exists(&self, _image: &str) -> Result<bool> {
+        // The cluster pulls the image when the pod starts
+        Ok(true)
+    }
+
+    fn image_user(&self, _image: &str) -> Result<String> {
+        // Pods always run as the user from the spawn, see `pod_spec`
+        Ok(String::new())
+    }
+
+    fn image_id(&self, _image: &str) -> Result<String> {
+        unsupported("Inspecting images is")
+    }
+
+    fn read_image_file(&self, _image: &str, _path: &str) -> Result<String> {
+        unsupported("Inspecting images is")
+    }
+
+    fn list_containers_by_labels(&self, labels: &[String]) -> Result<Vec<String>> {
+        let args = self.args(&[
+            "get".to_string(),
+            "pods".to_string(),
+            "-l".to_string(),
+            label_selector(labels),
+            "--field-selector=status.phase=Running".to_string(),
+            "-o".to_string(),
+            "jsonpath={.items[*].metadata.name}".to_string(),
+        ]);
+        Ok(self
+            .run(&args, None)?
+            .split_whitespace()
+            .map(String::from)
+            .collect())
+    }
+
+    fn attach_container(&self, name: &str) -> Result<()> {
+        let args = self.args(&["attach", "-it", &pod_name(name), "-c", CONTAINER_NAME]);
+        print_command("kubectl", &args);
+        run_attached("kubectl", "Kubernetes", &args)
+    }
+
+    fn exec_in_container(
+        &self,
+        name: &str,
+        command: &[String],
+        env: &[String],
+        workdir: Option<&str>,
+        tty: bool,
+    ) -> Result<i32> {
+        let pod = pod_name(name);
+        let mut args = self.args(&[
+            "exec",
+            if tty { "-it" } else { "-i" },
+            &pod,
+            "-c",
+            CONTAINER_NAME,
+            "--",
+        ]);
+        // kubectl exec takes neither env nor a working directory
+        if !env.is_empty() {
+            args.push("env".to_string());
+            args.extend(env.iter().cloned());
+        }
+        if let Some(workdir) = workdir {
+            args.extend(["sh", "-c", "cd \"$0\" && exec \"$@\"", workdir].map(String::from));
+        }
+        args.extend(command.iter().cloned());
+        print_command("kubectl", &args);
+        session::run_exec("kubectl", &args)
+    }
+
+    fn list_sessions(&self) -> Result<Vec<SessionContainer>> {
+        let args = self.args(&["get", "pods", "-l", SESSION_LABEL, "-o", "json"]);
+        parse_pod_sessions(&self.run(&args, None)?)
+    }
+
+    fn stop_container(&self, name: &str) -> Result<()> {
+        let args = self.args(&["delete", "pod", &pod_name(name), "--wait=false"]);
+        print_command("kubectl", &args);
+        self.run(&args, None).map(|_| ())
+    }
+
+    fn pull_image(&self, _image: &str) -> Result<()> {
+        // The cluster pulls the image when the pod starts
+        Ok(())
+    }
+
+    fn build_image(&self, _build: &BuildConfig, _tag: &str) -> Result<()> {
+        unsupported("Building images is")
+    }
+
+    fn start_egress_proxy(
+        &self,
+        _proxy: &EgressProxy,
+        _image: &str,
+        _allow_hosts: &[String],
+    ) -> Result<()> {
+        unsupported("network.allow_hosts is")
+    }
+
+    fn stop_egress_proxy(&self, _proxy: &EgressProxy) -> Result<()> {
+        Ok(())
+    }
+
+    fn ensure_volume(&self, name: &str) -> Result<()> {
+        let exists = Command::new("kubectl")
+            .args(self.args(&["get", "pvc", name]))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .wrap_err("Failed to execute kubectl get pvc")?
+            .success();
+        if !exists {
+            let args = self.args(&["create", "-f", "-"]);
+            print_command("kubectl", &args);
+            self.run(&args, Some(&claim_spec(name, &self.config).to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn list_volumes(&self) -> Result<Vec<String>> {
+        let args = self.args(&[
+            "get",
+            "pvc",
+            "-l",
+            VOLUME_LABEL,
+            "-o",
+            "jsonpath={.items[*].metadata.name}",
+        ]);
+        let mut names: Vec<String> = self
+            .run(&args, None)?
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn remove_volume(&self, name: &str) -> Result<()> {
+        let args = self.args(&["delete", "pvc", name]);
+        print_command("kubectl", &args);
+        self.run(&args, None).map(|_| ())
+    }
+
+    fn log_command(&self, name: &str) -> Result<(&'static str, Vec<String>)> {
+        let args = self.args(&["logs", "-f", &pod_name(name), "-c", CONTAINER_NAME]);
+        Ok(("kubectl", args))
+    }
+
+    fn copy_into_container(&self, container: &str, host: &Path, dest: &str) -> Result<()> {
+        let args = self.args(&[
+            "cp".to_string(),
+            host.display().to_string(),
+            format!("{}:{}", pod_name(container), dest),
+            "-c".to_string(),
+            CONTAINER_NAME.to_string(),
+        ]);
+        print_command("kubectl", &args);
+        self.run(&args, None)
+            .map(|_| ())
+            .wrap_err_with(|| format!("Failed to copy {} into pod", host.display()))
+    }
+
+    fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
+        let manifest = pod_spec(config, &self.config)?;
+        let pod = pod_name(&config.name);
+        let create = self.args(&["create", "-f", "-", "-o", "json"]);
+        print_command("kubectl", &create);
+        let created = self.run(&create, Some(&manifest.to_string()))?;
+
+        let result = self
+            .create_secret(config, &created)
+            .and_then(|()| self.start_pod(config, &pod));
+        // A failed detached start leaves nothing worth keeping either
+        if !config.detach || result.is_err() {
+            let delete = self.args(&["delete", "pod", &pod, "--wait=false"]);
+            print_command("kubectl", &delete);
+            if let Err(e) = self.run(&delete, None) {
+                eprintln!("WARNING: {}", e);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::CopyIn;
+    use crate::runtime::secrets::SecretFile;
+    use agent_box_common::config::ResourcesConfig;
+    use std::path::PathBuf;
+
+    fn container_config() -> ContainerConfig {
+        ContainerConfig {
+            name: "ab-fr-agent-box-main_1".to_string(),
+            image: "ghcr.io/fr/agent:latest".to_string(),
+            entrypoint: Some(vec!["/bin/init".to_string()]),
+            command: Some(vec!["claude".to_string()]),
+            group_add: vec!["27".to_string()],
+            working_dir: "/ws/main".to_string(),
+            mounts: vec![
+                "/home/u/ws/main:/ws/main:rw".to_string(),
+                "/home/u/.gitconfig:/home/u/.gitconfig:ro".to_string(),
+            ],
+            env: vec!["TERM=xterm".to_string()],
+            hosts: vec!["db:10.0.0.5".to_string()],
+            resources: ResourcesConfig {
+                cpus: Some(1.5),
+                memory: Some("4g".to_string()),
+                ..Default::default()
+            },
+            labels: vec![
+                format!("{}=main", SESSION_LABEL),
+                format!("{}=fr/agent-box", REPO_LABEL),
+            ],
+            tmpfs: vec!["/tmp".to_string()],
+            volumes: vec!["cargo-cache:/home/u/.cargo:rw".to_string()],
+            ..ContainerConfig::for_test()
+        }
+    }
+
+    #[test]
+    fn test_pod_spec_host_path() {
+        let pod = pod_spec(&container_config(), &KubernetesConfig::default()).unwrap();
+        assert_eq!(pod["metadata"]["name"], "ab-fr-agent-box-main-1");
+        assert_eq!(pod["metadata"]["labels"][REPO_LABEL], "fr.agent-box");
+        assert_eq!(pod["metadata"]["annotations"][REPO_LABEL], "fr/agent-box");
+        assert_eq!(pod["metadata"]["labels"][MANAGED_BY_LABEL], "agent-box");
+
+        let spec = &pod["spec"];
+        assert_eq!(
+            spec["securityContext"],
+            json!({ "runAsUser": 1000, "runAsGroup": 1000, "supplementalGroups": [27] })
+        );
+        assert_eq!(
+            spec["hostAliases"],
+            json!([{ "ip": "10.0.0.5", "hostnames": ["db"] }])
+        );
+        assert_eq!(spec["terminationGracePeriodSeconds"], 0);
+
+        let container = &spec["containers"][0];
+        assert_eq!(container["image"], "ghcr.io/fr/agent:latest");
+        assert_eq!(container["workingDir"], "/ws/main");
+        assert_eq!(container["command"], json!(["sleep", "infinity"]));
+        assert_eq!(
+            container["env"],
+            json!([{ "name": "TERM", "value": "xterm" }])
+        );
+        assert_eq!(
+            container["resources"],
+            json!({ "limits": { "cpu": "1.5", "memory": "4Gi" } })
+        );
+        assert_eq!(
+            container["securityContext"],
+            json!({
+                "capabilities": { "drop": ["ALL"], "add": [] },
+                "allowPrivilegeEscalation": false,
+                "readOnlyRootFilesystem": false,
+                "seccompProfile": { "type": "RuntimeDefault" },
+            })
+        );
+        assert_eq!(
+            spec["volumes"],
+            json!([
+                { "name": "bind-0", "hostPath": { "path": "/home/u/ws/main" } },
+                { "name": "bind-1", "hostPath": { "path": "/home/u/.gitconfig" } },
+                { "name": "tmpfs-0", "emptyDir": { "medium": "Memory" } },
+                { "name": "volume-0", "persistentVolumeClaim": { "claimName": "cargo-cache" } },
+            ])
+        );
+        assert_eq!(
+            container["volumeMounts"],
+            json!([
+                { "name": "bind-0", "mountPath": "/ws/main", "readOnly": false },
+                { "name": "bind-1", "mountPath": "/home/u/.gitconfig", "readOnly": true },
+                { "name": "tmpfs-0", "mountPath": "/tmp" },
+                { "name": "volume-0", "mountPath": "/home/u/.cargo", "readOnly": false },
+            ])
+        );
+        assert_eq!(
+            foreground_command(&container_config()),
+            ["/bin/init", "claude"]
+        );
+    }
+
+    #[test]
+    fn test_pod_spec_pvc_and_detach() {
+        let kube = KubernetesConfig {
+            mounts: KubernetesMountSource::Pvc,
+            claim: Some("agent-home".to_string()),
+            claim_root: Some(PathBuf::from("/home/u")),
+            ..Default::default()
+        };
+        let mut config = container_config();
+        config.detach = true;
+        let pod = pod_spec(&config, &kube).unwrap();
+        let spec = &pod["spec"];
+        assert_eq!(
+            spec["volumes"][0],
+            json!({ "name": "bind-0", "persistentVolumeClaim": { "claimName": "agent-home" } })
+        );
+        assert_eq!(
+            spec["containers"][0]["volumeMounts"][0]["subPath"],
+            "ws/main"
+        );
+        assert_eq!(
+            spec["containers"][0]["volumeMounts"][1]["subPath"],
+            ".gitconfig"
+        );
+        assert_eq!(spec["containers"][0]["command"], json!(["/bin/init"]));
+        assert_eq!(spec["containers"][0]["args"], json!(["claude"]));
+        assert!(spec.get("terminationGracePeriodSeconds").is_none());
+
+        config
+            .mounts
+            .push("/tmp/secrets:/run/secrets:ro".to_string());
+        let err = pod_spec(&config, &kube).unwrap_err().to_string();
+        assert!(err.contains("outside kubernetes.claim_root"), "{}", err);
+
+        // Detached pods get --copy-in files once they're Ready
+        let mut config = container_config();
+        config.detach = true;
+        config.copy_in = vec![CopyIn {
+            host: PathBuf::from("/etc/hosts"),
+            container: "/tmp/hosts".to_string(),
+        }];
+        let detached = pod_spec(&config, &kube).unwrap();
+        assert_eq!(
+            detached["spec"]["containers"][0]["command"],
+            json!(["/bin/init"])
+        );
+    }
+
+    #[test]
+    fn test_pod_spec_secrets() {
+        let kube = KubernetesConfig::default();
+        let config = container_config();
+        assert_eq!(secret_spec(&config), None);
+
+        let mut config = container_config();
+        config.env.push("GITHUB_TOKEN=ghp_x".to_string());
+        config.secret_files = vec![SecretFile {
+            path: "/run/secrets/npmrc".to_string(),
+            value: "//registry/:_authToken=x".to_string(),
+        }];
+        let pod = pod_spec(&config, &kube).unwrap();
+        let manifest = pod.to_string();
+        assert!(!manifest.contains("ghp_x"), "{}", manifest);
+        assert!(!manifest.contains("_authToken"), "{}", manifest);
+        let container = &pod["spec"]["containers"][0];
+        assert_eq!(
+            container["env"][0],
+            json!({ "name": "TERM", "value": "xterm" })
+        );
+        assert_eq!(
+            container["env"][1],
+            json!({
+                "name": "GITHUB_TOKEN",
+                "valueFrom": {
+                    "secretKeyRef": { "name": "ab-fr-agent-box-main-1", "key": "env.GITHUB_TOKEN" },
+                },
+            })
+        );
+        assert_eq!(
+            pod["spec"]["volumes"][4],
+            json!({
+                "name": "secrets",
+                "secret": {
+                    "secretName": "ab-fr-agent-box-main-1",
+                    "items": [{ "key": "file-0", "path": "file-0" }],
+                },
+            })
+        );
+        assert_eq!(
+            container["volumeMounts"][4],
+            json!({
+                "name": "secrets",
+                "mountPath": "/run/secrets/npmrc",
+                "subPath": "file-0",
+                "readOnly": true,
+            })
+        );
+
+        let secret = secret_spec(&config).unwrap();
+        assert_eq!(secret["metadata"]["name"], "ab-fr-agent-box-main-1");
+        assert_eq!(
+            secret["stringData"],
+            json!({ "env.GITHUB_TOKEN": "ghp_x", "file-0": "//registry/:_authToken=x" })
+        );
+    }
+
+    #[test]
+    fn test_pod_spec_unsupported() {
+        let kube = KubernetesConfig::default();
+        let mut config = container_config();
+        config.network = Some("bridge".to_string());
+        assert!(pod_spec(&config, &kube).is_err());
+
+        let mut config = container_config();
+        config.user = "agent".to_string();
+        assert!(pod_spec(&config, &kube).is_err());
+
+        let mut config = container_config();
+        config.resources.pids_limit = Some(100);
+        assert_eq!(
+            pod_spec(&config, &kube).unwrap_err().to_string(),
+            "resources.pids_limit is not supported by the kubernetes backend"
+        );
+
+        let mut config = container_config();
+        config.network = Some("host".to_string());
+        config.dns = vec!["1.1.1.1".to_string()];
+        let pod = pod_spec(&config, &kube).unwrap();
+        assert_eq!(pod["spec"]["hostNetwork"], true);
+        assert_eq!(pod["spec"]["dnsPolicy"], "None");
+        assert_eq!(pod["spec"]["dnsConfig"]["nameservers"], json!(["1.1.1.1"]));
+    }
+
+    #[test]
+    fn test_label_value_and_memory_quantity() {
+        assert_eq!(label_value("fr/agent-box"), "fr.agent-box");
+        assert_eq!(label_value("/x/"), "x");
+        assert_eq!(label_value(&"a".repeat(70)).len(), 63);
+        assert_eq!(
+            label_selector(&[format!("{}=fr/agent-box", REPO_LABEL)]),
+            format!("{}=fr.agent-box", REPO_LABEL)
+        );
+
+        assert_eq!(memory_quantity("512m"), "512Mi");
+        assert_eq!(memory_quantity("1.5g"), "1.5Gi");
+        assert_eq!(memory_quantity("4GiB"), "4Gi");
+        assert_eq!(memory_quantity("1048576"), "1048576");
+        assert_eq!(memory_quantity("1024b"), "1024");
+    }
+
+    #[test]
+    fn test_parse_pod_sessions() {
+        let output = json!({
+            "items": [{
+                "metadata": {
+                    "name": "ab-fr-agent-box-main",
+                    "annotations": { REPO_LABEL: "fr/agent-box", SESSION_LABEL: "main" },
+                },
+                "spec": { "containers": [{ "image": "agent:latest" }] },
+                "status": { "phase": "Running" },
+            }],
+        });
+        assert_eq!(
+            parse_pod_sessions(&output.to_string()).unwrap(),
+            [SessionContainer {
+                name: "ab-fr-agent-box-main".to_string(),
+                repo: "fr/agent-box".to_string(),
+                session: "main".to_string(),
+                image: "agent:latest".to_string(),
+                status: "Running".to_string(),
+            }]
+        );
+    }
+}
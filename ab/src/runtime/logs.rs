@@ -0,0 +1,217 @@
+use eyre::{Result, WrapErr};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::data_dir;
+
+/// Directory of the logs of detached sessions:
+/// `$XDG_DATA_HOME/agent-box/logs`, or `~/.local/share/agent-box/logs`
+pub fn logs_dir() -> Option<PathBuf> {
+    Some(data_dir()?.join("logs"))
+}
+
+/// Log file of `session` of `repo` (relative to base_repo_dir) under `dir`
+pub fn session_log_path(dir: &Path, repo: &str, session: &str) -> PathBuf {
+    dir.join(repo).join(format!("{}.log", session))
+}
+
+/// `sh` args following container `name`'s output with `program logs -f` until
+/// it exits, then removing it. Detached containers run without `--rm`, which
+/// could remove one that exits at once before its output is read.
+pub(crate) fn follow_and_remove_args(program: &str, name: &str) -> Vec<String> {
+    [
+        "-c",
+        r#""$0" logs -f "$1"; "$0" rm "$1" >/dev/null 2>&1"#,
+        program,
+        name,
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
+/// Repos with a log of `session` under `dir`, sorted, with the log's path
+pub fn find_session_logs(dir: &Path, session: &str) -> Vec<(String, PathBuf)> {
+    let file_name = format!("{}.log", session);
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                pending.push(path);
+            } else if entry.file_name().to_string_lossy() == file_name
+                && let Ok(repo) = current.strip_prefix(dir)
+                && !repo.as_os_str().is_empty()
+            {
+                found.push((repo.display().to_string(), path));
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// The log of `session` under `dir`, of `repo` if given. Errors if there is
+/// none, or if several repos have one and no `repo` picks between them.
+pub fn pick_session_log(dir: &Path, session: &str, repo: Option<&str>) -> Result<PathBuf> {
+    let mut logs = find_session_logs(dir, session);
+    if let Some(repo) = repo {
+        logs.retain(|(log_repo, _)| log_repo == repo);
+    }
+    match logs.len() {
+        0 => Err(eyre::eyre!(
+            "No log for session '{}'{}; logs are written by `ab spawn --detach`",
+            session,
+            repo.map(|r| format!(" of {}", r)).unwrap_or_default()
+        )),
+        1 => Ok(logs.remove(0).1),
+        _ => Err(eyre::eyre!(
+            "Session '{}' has logs in several repos ({}); pass --repo to pick one",
+            session,
+            logs.iter()
+                .map(|(repo, _)| repo.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Open `path` for appending, creating it and its directory, and write a line
+/// marking the start of container `name`'s output
+pub(crate) fn open_session_log(path: &Path, name: &str, started: u64) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    writeln!(
+        file,
+        "==> {} {} started <==",
+        super::audit::format_timestamp(started),
+        name
+    )?;
+    Ok(file)
+}
+
+/// Byte offset in `text` where its last `lines` lines start
+fn tail_start(text: &[u8], lines: usize) -> usize {
+    let body = text.strip_suffix(b"\n").unwrap_or(text);
+    body.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, byte)| **byte == b'\n')
+        .nth(lines.saturating_sub(1))
+        .filter(|_| lines > 0)
+        .map(|(i, _)| i + 1)
+        .unwrap_or(if lines == 0 { text.len() } else { 0 })
+}
+
+/// Write the log at `path` to `out`, only its last `lines` lines if given.
+/// With `follow`, keep writing what is appended to it until interrupted.
+pub fn write_log(
+    path: &Path,
+    lines: Option<usize>,
+    follow: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut file =
+        File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    let mut text = Vec::new();
+    file.read_to_end(&mut text)?;
+    let start = lines.map_or(0, |lines| tail_start(&text, lines));
+    out.write_all(&text[start..])?;
+    out.flush()?;
+    if !follow {
+        return Ok(());
+    }
+
+    let mut offset = text.len() as u64;
+    let mut chunk = Vec::new();
+    loop {
+        std::thread::sleep(Duration::from_millis(500));
+        let len = std::fs::metadata(path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?
+            .len();
+        // Truncated or replaced: start over from the beginning
+        if len < offset {
+            file = File::open(path)?;
+            offset = 0;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        chunk.clear();
+        offset += file.read_to_end(&mut chunk)? as u64;
+        if !chunk.is_empty() {
+            out.write_all(&chunk)?;
+            out.flush()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follow_and_remove_args() {
+        let output = std::process::Command::new("sh")
+            .args(follow_and_remove_args("echo", "ab-r-main"))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "logs -f ab-r-main\n"
+        );
+    }
+
+    #[test]
+    fn test_tail_start() {
+        let text = b"one\ntwo\nthree\n";
+        assert_eq!(&text[tail_start(text, 2)..], b"two\nthree\n");
+        assert_eq!(&text[tail_start(text, 3)..], text);
+        assert_eq!(&text[tail_start(text, 10)..], text);
+        assert_eq!(&text[tail_start(text, 0)..], b"");
+        let partial = b"one\ntwo";
+        assert_eq!(&partial[tail_start(partial, 1)..], b"two");
+    }
+
+    #[test]
+    fn test_session_logs() {
+        let dir = std::env::temp_dir().join(format!("ab-test-logs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let main = session_log_path(&dir, "fr/agent-box", "main");
+        let mut file = open_session_log(&main, "ab-fr-agent-box-main", 0).unwrap();
+        writeln!(file, "hello").unwrap();
+        let mut out = Vec::new();
+        write_log(&main, None, false, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "==> 1970-01-01T00:00:00Z ab-fr-agent-box-main started <==\nhello\n"
+        );
+
+        assert_eq!(pick_session_log(&dir, "main", None).unwrap(), main);
+        let tools = session_log_path(&dir, "tools", "main");
+        open_session_log(&tools, "ab-tools-main", 0).unwrap();
+        let err = pick_session_log(&dir, "main", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Session 'main' has logs in several repos (fr/agent-box, tools); pass --repo to pick one"
+        );
+        assert_eq!(
+            pick_session_log(&dir, "main", Some("tools")).unwrap(),
+            tools
+        );
+        assert!(pick_session_log(&dir, "fix", None).is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
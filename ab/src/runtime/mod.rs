@@ -1,13 +1,39 @@
+pub mod audit;
+pub mod bwrap;
 pub mod docker;
+pub mod egress;
+pub mod kubernetes;
+pub mod logs;
 pub mod podman;
+pub mod secrets;
+pub mod session;
+pub mod volume;
 
 use docker::ContainerBackend;
 use eyre::Result;
 use glob::Pattern as GlobPattern;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::path::{Path, PathBuf};
 
-use agent_box_common::config::{Config, Mount, MountMode, ResolvedMount, ResolvedProfile};
+use agent_box_common::config::{
+    BuildConfig, Config, Mount, MountMode, MountRemap, MountResolutionCache, MountSyntax,
+    OnMissingWorkspace, ResolvedMount, ResolvedProfile, ResourcesConfig, RuntimeConfig,
+    SecretHelper, SecurityConfig, TmpfsMounts, VolumeSpec, redact_env_entry,
+};
+use agent_box_common::path::{RepoIdentifier, WorkspaceType};
+use agent_box_common::repo::linked_worktree_common_dir;
+
+/// Where agent-box keeps its data: `$XDG_DATA_HOME/agent-box`, or
+/// `~/.local/share/agent-box`
+pub fn data_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+        })?;
+    Some(base.join("agent-box"))
+}
 
 /// Check if a path should be skipped based on configured skip patterns.
 /// Returns true if the path matches any of the skip patterns (supports globs).
@@ -24,8 +50,13 @@ fn should_skip_path(path: &Path, skip_patterns: &[String]) -> bool {
 
 /// Pretty print a command with arguments, grouping flags with their values
 pub(crate) fn print_command(command: &str, args: &[String]) {
-    eprintln!("DEBUG: Running command:");
-    eprintln!("  {} \\", command);
+    eprint!("{}", format_command(command, args));
+}
+
+/// Format a command the way `print_command` shows it, one flag per line
+fn format_command(command: &str, args: &[String]) -> String {
+    let mut out = String::from("DEBUG: Running command:\n");
+    out.push_str(&format!("  {} \\\n", command));
     let mut i = 0;
     while i < args.len() {
         let arg = &args[i];
@@ -33,13 +64,39 @@ pub(crate) fn print_command(command: &str, args: &[String]) {
 
         // Check if this is a flag with a value (flag starts with -, next arg doesn't)
         if arg.starts_with('-') && i + 1 < args.len() && !args[i + 1].starts_with('-') {
-            eprintln!("    {} {}{}", arg, args[i + 1], continuation);
+            out.push_str(&format!("    {} {}{}\n", arg, args[i + 1], continuation));
             i += 2; // Skip both the flag and its value
         } else {
-            eprintln!("    {}{}", arg, continuation);
+            out.push_str(&format!("    {}{}\n", arg, continuation));
             i += 1;
         }
     }
+    out
+}
+
+/// Mask secret values in `-e KEY=VALUE` (and bwrap `--setenv KEY VALUE`) arguments
+/// before printing them.
+/// The unredacted args are still what gets passed to the runtime.
+pub(crate) fn redact_args(args: &[String], patterns: &[String]) -> Vec<String> {
+    let mut redacted = Vec::with_capacity(args.len());
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        redacted.push(arg.clone());
+        if arg == "-e"
+            && let Some(entry) = iter.next()
+        {
+            redacted.push(redact_env_entry(entry, patterns));
+        } else if arg == "--setenv"
+            && let (Some(name), Some(value)) = (iter.next(), iter.next())
+        {
+            // bwrap takes the name and value as separate arguments
+            let entry = redact_env_entry(&format!("{}={}", name, value), patterns);
+            let (_, value) = entry.split_once('=').unwrap_or_default();
+            redacted.push(name.clone());
+            redacted.push(value.to_string());
+        }
+    }
+    redacted
 }
 
 /// Convert an arbitrary path component into a container-name-safe segment.
@@ -71,16 +128,27 @@ fn sanitize_container_name_component(component: &str) -> String {
 
 /// Derive a human-readable, unique container name from the workspace path.
 ///
-/// Local mode uses the current directory name plus a timestamp suffix. Session
-/// mode uses the repo directory name and session name plus the same suffix.
-/// Example: `ab-agent-box-main-1713573890`.
-fn derive_container_name(_config: &Config, workspace_path: &Path, local: bool) -> String {
-    let label = if local {
-        workspace_path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .map(sanitize_container_name_component)
-            .unwrap_or_else(|| "workspace".to_string())
+/// Session mode uses the repo directory name and session name plus a timestamp
+/// suffix. Example: `ab-agent-box-main-1713573890`.
+///
+/// Local mode has no session, so it uses the repo's path relative to
+/// `base_repo_dir` (or the directory name outside it) tagged with `-local`.
+/// Example: `ab-fr-agent-box-local-1713573890`.
+fn derive_container_name(config: &Config, workspace_path: &Path, local: bool) -> String {
+    let (label, tag) = if local {
+        let label = RepoIdentifier::from_repo_path(config, workspace_path)
+            .ok()
+            .and_then(|id| id.relative_path().to_str().map(str::to_string))
+            .filter(|rel| !rel.is_empty())
+            .or_else(|| {
+                workspace_path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(str::to_string)
+            })
+            .map(|name| sanitize_container_name_component(&name))
+            .unwrap_or_else(|| "workspace".to_string());
+        (label, "-local")
     } else {
         let repo = workspace_path
             .parent()
@@ -93,7 +161,7 @@ fn derive_container_name(_config: &Config, workspace_path: &Path, local: bool) -
             .and_then(|name| name.to_str())
             .map(sanitize_container_name_component)
             .unwrap_or_else(|| "workspace".to_string());
-        format!("{}-{}", repo, session)
+        (format!("{}-{}", repo, session), "")
     };
 
     let suffix = std::time::SystemTime::now()
@@ -102,15 +170,15 @@ fn derive_container_name(_config: &Config, workspace_path: &Path, local: bool) -
         .as_secs();
     let suffix_len = suffix.to_string().len();
 
-    // Keep enough room for the label and timestamp.
-    let max_label_len = 63usize.saturating_sub("ab--".len() + suffix_len);
+    // Keep enough room for the tag and timestamp.
+    let max_label_len = 63usize.saturating_sub("ab--".len() + tag.len() + suffix_len);
     let label = if label.len() > max_label_len {
         label[..max_label_len].trim_matches('-').to_string()
     } else {
         label
     };
 
-    format!("ab-{}-{}", label, suffix)
+    format!("ab-{}{}-{}", label, tag, suffix)
 }
 
 fn terminfo_entry_paths(term: &str) -> impl Iterator<Item = PathBuf> {
@@ -151,1067 +219,5227 @@ fn find_terminfo_dir(term: &str) -> Option<PathBuf> {
         .find(|dir| terminfo_entry_paths(term).any(|relative| dir.join(relative).exists()))
 }
 
-/// Configuration for running a container
-#[derive(Debug, Clone)]
-pub struct ContainerConfig {
-    pub name: String,
-    pub image: String,
-    pub entrypoint: Option<Vec<String>>,
-    pub command: Option<Vec<String>>,
-    pub user: String,
-    pub working_dir: String,
-    pub mounts: Vec<String>,
-    pub env: Vec<String>,
-    pub ports: Vec<String>,
-    pub hosts: Vec<String>,
-    pub network: Option<String>,
-    pub dns: Vec<String>,
+/// A host file or directory to copy into the container before it starts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyIn {
+    pub host: PathBuf,
+    pub container: String,
 }
 
-/// Enum of available container runtimes
-pub enum Runtime {
-    Docker(docker::DockerRuntime),
-    Podman(podman::PodmanRuntime),
+/// Parse a `--copy-in HOST:CONTAINER` argument.
+///
+/// Relative host paths are resolved against the current directory and must
+/// exist. Relative container paths are resolved against `working_dir`.
+pub fn parse_copy_in(arg: &str, working_dir: &str) -> Result<CopyIn> {
+    let (host, container) = arg
+        .split_once(':')
+        .filter(|(h, c)| !h.is_empty() && !c.is_empty())
+        .ok_or_else(|| eyre::eyre!("Invalid --copy-in '{}': expected HOST:CONTAINER", arg))?;
+
+    let host = PathBuf::from(host)
+        .canonicalize()
+        .map_err(|e| eyre::eyre!("--copy-in host path '{}' is not accessible: {}", host, e))?;
+
+    let container = if container.starts_with('/') {
+        container.to_string()
+    } else {
+        format!("{}/{}", working_dir.trim_end_matches('/'), container)
+    };
+
+    Ok(CopyIn { host, container })
 }
 
-impl Runtime {
-    /// Spawn a container with the given configuration
-    pub fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
-        match self {
-            Runtime::Docker(rt) => rt.spawn_container(config),
-            Runtime::Podman(rt) => rt.spawn_container(config),
-        }
-    }
+/// Container path the init script is mounted at
+pub const INIT_SCRIPT_PATH: &str = "/tmp/agent-box-init.sh";
 
-    /// Check if a path exists in the container image
-    pub fn path_exists_in_image(&self, image: &str, path: &str) -> Result<bool> {
-        match self {
-            Runtime::Docker(rt) => rt.path_exists_in_image(image, path),
-            Runtime::Podman(rt) => rt.path_exists_in_image(image, path),
-        }
-    }
+/// Runs the init script (`$0`) with `/bin/sh`, stopping if it fails, then execs
+/// the original entrypoint and command (`$@`), or a shell when there are none
+const INIT_SCRIPT_WRAPPER: &str =
+    r#"/bin/sh "$0" || exit; [ $# -gt 0 ] || set -- /bin/sh; exec "$@""#;
 
-    /// List all paths in the container image
-    pub fn list_paths_in_image(&self, image: &str, root_path: Option<&str>) -> Result<Vec<String>> {
-        match self {
-            Runtime::Docker(rt) => rt.list_paths_in_image(image, root_path),
-            Runtime::Podman(rt) => rt.list_paths_in_image(image, root_path),
-        }
-    }
+/// Entrypoint and command that run the init script before `entrypoint` and `command`:
+/// `/bin/sh -c WRAPPER INIT_SCRIPT_PATH <entrypoint...> <command...>`
+pub fn wrap_with_init_script(
+    entrypoint: Option<Vec<String>>,
+    command: Option<Vec<String>>,
+) -> (Vec<String>, Vec<String>) {
+    let wrapped = ["-c", INIT_SCRIPT_WRAPPER, INIT_SCRIPT_PATH]
+        .into_iter()
+        .map(String::from)
+        .chain(entrypoint.into_iter().flatten())
+        .chain(command.into_iter().flatten())
+        .collect();
+    (vec!["/bin/sh".to_string()], wrapped)
 }
 
-/// Factory to create the appropriate container runtime
-pub fn create_runtime(config: &Config) -> Runtime {
-    match config.runtime.backend.as_str() {
-        "podman" => Runtime::Podman(podman::PodmanRuntime::new()),
-        _ => Runtime::Docker(docker::DockerRuntime::new()),
+/// Mount the host `script` read-only at [`INIT_SCRIPT_PATH`] and wrap the
+/// entrypoint so it runs first (`--init-script` / `runtime.init_script`)
+pub fn apply_init_script(config: &mut ContainerConfig, script: &Path) -> Result<()> {
+    let host = script.canonicalize().map_err(|e| {
+        eyre::eyre!(
+            "Init script '{}' is not accessible: {}",
+            script.display(),
+            e
+        )
+    })?;
+    if !host.is_file() {
+        return Err(eyre::eyre!(
+            "Init script '{}' is not a file",
+            script.display()
+        ));
     }
+
+    config
+        .mounts
+        .push(format!("{}:{}:ro", host.display(), INIT_SCRIPT_PATH));
+    config.mount_summary.count_binds(&config.mounts);
+
+    let (entrypoint, command) =
+        wrap_with_init_script(config.entrypoint.take(), config.command.take());
+    config.entrypoint = Some(entrypoint);
+    config.command = Some(command);
+    Ok(())
 }
 
-/// Parse mode from string prefix (e.g., "ro:", "rw:", "o:")
-fn parse_mode_prefix(s: &str) -> Option<(MountMode, &str)> {
-    if let Some(rest) = s.strip_prefix("ro:") {
-        Some((MountMode::Ro, rest))
-    } else if let Some(rest) = s.strip_prefix("rw:") {
-        Some((MountMode::Rw, rest))
-    } else if let Some(rest) = s.strip_prefix("o:") {
-        Some((MountMode::Overlay, rest))
-    } else {
-        None
-    }
+/// Entrypoint and command that run the devcontainer `postCreateCommand` line in a
+/// subshell, stopping if it fails, then exec `entrypoint` and `command` (or a shell)
+pub fn wrap_with_post_create(
+    entrypoint: Option<Vec<String>>,
+    command: Option<Vec<String>>,
+    post_create: &str,
+) -> (Vec<String>, Vec<String>) {
+    let script = format!(
+        "(\n{}\n) || exit; [ $# -gt 0 ] || set -- /bin/sh; exec \"$@\"",
+        post_create
+    );
+    let wrapped = ["-c".to_string(), script, "sh".to_string()]
+        .into_iter()
+        .chain(entrypoint.into_iter().flatten())
+        .chain(command.into_iter().flatten())
+        .collect();
+    (vec!["/bin/sh".to_string()], wrapped)
 }
 
-/// Parse CLI mount arguments into Mount structs.
-///
-/// Format: `[MODE:]PATH` or `[MODE:]SRC:DST`
-/// - MODE is optional, defaults to "rw"
-/// - Valid modes: "ro", "rw", "o"
-///
-/// Examples:
-/// - `~/data` → mode=rw, spec=~/data
-/// - `ro:~/config` → mode=ro, spec=~/config
-/// - `rw:~/src:/app` → mode=rw, spec=~/src:/app
-pub fn parse_cli_mounts(home_relative: &[String], absolute: &[String]) -> Result<Vec<Mount>> {
-    let mut mounts = Vec::new();
+/// CDI device kind Podman requests GPUs as unless `runtime.gpu.cdi_kind` says otherwise
+pub const DEFAULT_GPU_CDI_KIND: &str = "nvidia.com/gpu";
 
-    for spec in home_relative {
-        mounts.push(parse_single_cli_mount(spec, true)?);
+/// Runtime args passing `gpus` (`all` or device IDs) through: one CDI
+/// `--device KIND=ID` per GPU with `cdi`, else Docker's `--gpus`
+pub(crate) fn gpu_args(gpus: &[String], cdi_kind: Option<&str>, cdi: bool) -> Vec<String> {
+    if gpus.is_empty() {
+        return Vec::new();
     }
-
-    for spec in absolute {
-        mounts.push(parse_single_cli_mount(spec, false)?);
+    if cdi {
+        let kind = cdi_kind.unwrap_or(DEFAULT_GPU_CDI_KIND);
+        return gpus
+            .iter()
+            .flat_map(|id| ["--device".to_string(), format!("{}={}", kind, id)])
+            .collect();
+    }
+    if gpus == ["all"] {
+        return vec!["--gpus".to_string(), "all".to_string()];
     }
+    // Docker reads the value as CSV, so a device list has to be quoted
+    vec![
+        "--gpus".to_string(),
+        format!("\"device={}\"", gpus.join(",")),
+    ]
+}
 
-    Ok(mounts)
+/// `--cpus`, `--memory`, `--memory-swap`, `--pids-limit`, and `--ulimit` flags for
+/// the set limits; Docker and Podman spell them the same. Values are joined with
+/// `=` so a `-1` can't read as a flag.
+pub(crate) fn resource_args(resources: &ResourcesConfig) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(cpus) = resources.cpus {
+        args.push(format!("--cpus={}", cpus));
+    }
+    if let Some(memory) = &resources.memory {
+        args.push(format!("--memory={}", memory));
+    }
+    if let Some(swap) = &resources.memory_swap {
+        args.push(format!("--memory-swap={}", swap));
+    }
+    if let Some(pids) = resources.pids_limit {
+        args.push(format!("--pids-limit={}", pids));
+    }
+    for (name, limit) in &resources.ulimits {
+        args.push("--ulimit".to_string());
+        args.push(format!("{}={}", name, limit));
+    }
+    args
 }
 
-/// Parse a single CLI mount argument.
-fn parse_single_cli_mount(arg: &str, home_relative: bool) -> Result<Mount> {
-    // Check for mode prefix (ro:, rw:, o:)
-    let (mode, spec) = match parse_mode_prefix(arg) {
-        Some((mode, rest)) => (mode, rest.to_string()),
-        None => (MountMode::Rw, arg.to_string()),
-    };
+/// Arguments for `<runtime> cp` copying `host` to `dest` inside `container`
+pub(crate) fn copy_in_args(container: &str, host: &Path, dest: &str) -> Vec<String> {
+    vec![
+        "cp".to_string(),
+        host.to_string_lossy().to_string(),
+        format!("{}:{}", container, dest),
+    ]
+}
 
-    // Validate the spec is not empty
-    if spec.is_empty() {
-        return Err(eyre::eyre!("Empty mount path after mode prefix: {}", arg));
+/// `build` args building `build` into the image `tag`
+pub(crate) fn build_args(build: &BuildConfig, tag: &str) -> Vec<String> {
+    let mut args = vec![
+        "build".to_string(),
+        "--file".to_string(),
+        build.file.to_string_lossy().to_string(),
+        "--tag".to_string(),
+        tag.to_string(),
+    ];
+    for (name, value) in &build.args {
+        args.push("--build-arg".to_string());
+        args.push(format!("{}={}", name, value));
     }
+    if let Some(target) = &build.target {
+        args.push("--target".to_string());
+        args.push(target.clone());
+    }
+    args.push(build.context_dir().to_string_lossy().to_string());
+    args
+}
 
-    // For CLI mounts, allow relative host source paths and resolve them against cwd.
-    // For src:dst, only source is resolved here. Destination is left as-is and
-    // later expanded/validated in Mount::resolve_paths (must be absolute or ~).
-    let spec = if let Some((src, dst)) = spec.split_once(':') {
-        if src.starts_with('/') || src.starts_with('~') {
-            spec
-        } else {
-            let cwd = std::env::current_dir()
-                .map_err(|e| eyre::eyre!("Failed to resolve current directory: {}", e))?;
-            let abs_src = cwd.join(src).to_string_lossy().to_string();
-            format!("{}:{}", abs_src, dst)
-        }
-    } else if spec.starts_with('/') || spec.starts_with('~') {
-        spec
-    } else {
-        let cwd = std::env::current_dir()
-            .map_err(|e| eyre::eyre!("Failed to resolve current directory: {}", e))?;
-        cwd.join(&spec).to_string_lossy().to_string()
-    };
-
-    // Validate path format (source side must now be absolute or home-relative)
-    let path_to_check = if spec.contains(':') {
-        // For src:dst format, check the src part
-        spec.split(':').next().unwrap()
-    } else {
-        &spec
-    };
+/// Run `program build` with `args`, showing its output
+pub(crate) fn run_build(program: &str, name: &str, args: &[String]) -> Result<()> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| eyre::eyre!("Failed to execute {} build: {}", program, e))?;
 
-    if !path_to_check.starts_with('/') && !path_to_check.starts_with('~') {
+    if !status.success() {
         return Err(eyre::eyre!(
-            "Mount path must be absolute (/...) or home-relative (~/...): {}",
-            arg
+            "Failed to build image with {}: {}",
+            name,
+            status
         ));
     }
-
-    Ok(Mount {
-        spec,
-        home_relative,
-        mode,
-    })
+    Ok(())
 }
 
-/// Build container configuration from workspace and source paths
-/// - workspace_path: the directory to mount as working directory
-/// - source_path: the source repo to mount .git/.jj from
-/// - local: if true, workspace and source are the same, so don't double-mount
-/// - ro: if true, mount workspace path as read-only
-/// - resolved_profile: resolved mounts, env, ports, and hosts from profile resolution
-/// - cli_mounts: additional mounts from CLI arguments
-/// - cli_ports: additional port mappings from CLI arguments
-/// - cli_hosts: additional host entries from CLI arguments
-/// - command: command arguments to pass to the container entrypoint
-/// - should_skip: if true, skip mounts that are already covered by parent mounts
-/// - network: optional network mode (e.g. "host", "bridge", "none")
-#[allow(clippy::too_many_arguments)]
-pub fn build_container_config(
-    config: &Config,
-    workspace_path: &Path,
-    source_path: &Path,
-    local: bool,
-    ro: bool,
-    entrypoint_override: Option<&str>,
-    resolved_profile: &ResolvedProfile,
-    cli_mounts: &[Mount],
-    cli_ports: &[String],
-    cli_hosts: &[String],
-    portal_socket_override: Option<&Path>,
-    command: Option<Vec<String>>,
-    should_skip: bool,
-    network: Option<String>,
-) -> Result<ContainerConfig> {
-    let pb_to_str = |pb: &Path| {
-        pb.canonicalize()
-            .unwrap_or_else(|_| panic!("couldnt canonicalize: {pb:?}"))
-            .to_string_lossy()
-            .to_string()
-    };
+/// `--group-add` flags for the container's supplementary groups
+pub(crate) fn group_add_args(groups: &[String]) -> Vec<String> {
+    groups
+        .iter()
+        .flat_map(|group| ["--group-add".to_string(), group.clone()])
+        .collect()
+}
 
-    /// Format a mount as bind string (host:container:mode)
-    pub fn format_bind(host_path: &Path, container_path: &Path, mode: MountMode) -> String {
-        format!(
-            "{}:{}:{}",
-            host_path.display(),
-            container_path.display(),
-            mode.as_str()
-        )
+/// `--cap-drop`, `--cap-add`, `--security-opt`, and `--read-only` flags for `security`
+pub(crate) fn security_args(security: &SecurityConfig) -> Vec<String> {
+    let mut args = Vec::new();
+    for cap in &security.cap_drop {
+        args.extend(["--cap-drop".to_string(), cap.clone()]);
+    }
+    for cap in &security.cap_add {
+        args.extend(["--cap-add".to_string(), cap.clone()]);
+    }
+    if let Some(profile) = &security.seccomp_profile {
+        args.extend(["--security-opt".to_string(), format!("seccomp={}", profile)]);
     }
+    if security.no_new_privileges {
+        args.extend([
+            "--security-opt".to_string(),
+            "no-new-privileges".to_string(),
+        ]);
+    }
+    if security.read_only_rootfs {
+        args.push("--read-only".to_string());
+    }
+    args
+}
 
-    let workspace_path_str = pb_to_str(workspace_path);
+/// `--entrypoint` flag for the container, none to run the image's own entrypoint
+pub(crate) fn entrypoint_args(entrypoint: Option<&Vec<String>>) -> Vec<String> {
+    entrypoint
+        .map(|entrypoint| vec!["--entrypoint".to_string(), entrypoint.join(" ")])
+        .unwrap_or_default()
+}
 
-    let workspace_mode = if ro { MountMode::Ro } else { MountMode::Rw };
-    let mut binds = vec![format_bind(workspace_path, workspace_path, workspace_mode)];
+/// `run` arguments for the container, up to and including the image and
+/// command. Backend-specific flags are emitted according to `caps`, and a
+/// config needing a feature the backend lacks is an error rather than a flag
+/// the runtime would reject.
+pub(crate) fn run_args(
+    config: &ContainerConfig,
+    caps: &BackendCapabilities,
+) -> Result<Vec<String>> {
+    caps.check(config)?;
+
+    let mut args = vec!["run".to_string(), "--name".to_string(), config.name.clone()];
+    // A detached container is removed once its logs are captured, see
+    // `logs::follow_and_remove_args`
+    if !config.detach {
+        args.push("--rm".to_string());
+    }
+    args.push("-it".to_string());
+    if config.detach {
+        args.push("-d".to_string());
+    }
+    if caps.userns_keep_id {
+        args.push("--userns".to_string());
+        args.push("keep-id".to_string());
+    }
+    args.extend([
+        "--user".to_string(),
+        config.user.clone(),
+        "--workdir".to_string(),
+        config.working_dir.clone(),
+    ]);
 
-    // Mount source repo's .git and .jj directories only if not local
-    // (in local mode, workspace IS the source, so they're already included)
-    if !local {
-        let source_git = source_path.join(".git");
-        let source_jj = source_path.join(".jj");
+    // Add supplementary groups for the mapped user
+    args.extend(group_add_args(&config.group_add));
+    args.extend(security_args(&config.security));
 
-        if source_git.exists() {
-            binds.push(format_bind(&source_git, &source_git, MountMode::Rw));
-        }
-        if source_jj.exists() {
-            binds.push(format_bind(&source_jj, &source_jj, MountMode::Rw));
-        }
+    // Add network mode if specified
+    if let Some(ref network) = config.network {
+        args.push("--network".to_string());
+        args.push(network.clone());
     }
 
-    // Combine profile mounts and CLI mounts
-    let all_mounts: Vec<&Mount> = resolved_profile
-        .mounts
-        .iter()
-        .chain(cli_mounts.iter())
-        .collect();
+    args.extend(resource_args(&config.resources));
+    args.extend(gpu_args(
+        &config.gpus,
+        config.gpu_cdi_kind.as_deref(),
+        caps.cdi_gpus,
+    ));
 
-    // Check for overlay mounts and validate backend
-    let has_overlay = all_mounts.iter().any(|m| m.mode == MountMode::Overlay);
+    for label in &config.labels {
+        args.push("--label".to_string());
+        args.push(label.clone());
+    }
 
-    if has_overlay && config.runtime.backend != "podman" {
-        return Err(eyre::eyre!(
-            "Overlay mounts are only supported with Podman backend, but '{}' is configured",
-            config.runtime.backend
-        ));
+    // Add mounts
+    for mount in &config.mounts {
+        args.extend(mount_args(mount, config.mount_syntax));
+    }
+    for path in &config.tmpfs {
+        args.extend(tmpfs_args(path, config.mount_syntax));
+    }
+    for volume in &config.volumes {
+        args.extend(volume_args(volume, config.mount_syntax));
     }
 
-    add_mounts(
-        &all_mounts,
-        &mut binds,
-        should_skip,
-        &config.runtime.skip_mounts,
-    )?;
+    // Add environment variables
+    for env in &config.env {
+        args.push("-e".to_string());
+        args.push(env.clone());
+    }
 
-    let uid = nix::unistd::getuid().as_raw();
-    let gid = nix::unistd::getgid().as_raw();
+    // Add port mappings
+    for port in &config.ports {
+        args.push("-p".to_string());
+        args.push(port.clone());
+    }
 
-    let username = std::env::var("USER")
-        .or_else(|_| std::env::var("LOGNAME"))
-        .unwrap_or_else(|_| "user".to_string());
+    // Add custom host entries
+    for host in &config.hosts {
+        args.push("--add-host".to_string());
+        args.push(host.clone());
+    }
 
-    let entrypoint = entrypoint_override
-        .map(|s| vec![s.to_string()])
-        .or_else(|| config.runtime.entrypoint.clone());
+    // Add custom DNS servers
+    for dns in &config.dns {
+        args.push("--dns".to_string());
+        args.push(dns.clone());
+    }
+    for domain in &config.dns_search {
+        args.push("--dns-search".to_string());
+        args.push(domain.clone());
+    }
 
-    let mut env = vec![
-        format!("USER={}", username),
-        format!("HOME=/home/{}", username),
-    ];
+    // Add entrypoint if specified
+    args.extend(entrypoint_args(config.entrypoint.as_ref()));
 
-    for var_name in ["TERM", "COLORTERM"] {
-        if let Ok(value) = std::env::var(var_name) {
-            env.push(format!("{}={}", var_name, value));
-        }
+    // Add image
+    args.push(config.image.clone());
+
+    // Add command arguments (passed to entrypoint)
+    if let Some(command) = &config.command {
+        args.extend(command.clone());
     }
-    // Use env from resolved profile (includes runtime.env + profile envs)
-    env.extend(resolved_profile.env.iter().cloned());
 
-    // Pass through specified environment variables from host
-    for var_name in &resolved_profile.env_passthrough {
-        if let Ok(value) = std::env::var(var_name) {
-            env.push(format!("{}={}", var_name, value));
+    Ok(args)
+}
+
+/// Arguments for `<runtime> start` on a created container: attached, or just
+/// started when the container is detached
+pub(crate) fn start_args(container: &str, detach: bool) -> Vec<String> {
+    if detach {
+        return vec!["start".to_string(), container.to_string()];
+    }
+    start_attached_args(container)
+}
+
+/// Arguments for `<runtime> start` attaching to a created container
+pub(crate) fn start_attached_args(container: &str) -> Vec<String> {
+    vec![
+        "start".to_string(),
+        "--attach".to_string(),
+        "--interactive".to_string(),
+        container.to_string(),
+    ]
+}
+
+/// Path of a directory entry in `tar -tv` output, made absolute, or `None` for
+/// other entry types.
+/// Lines look like `drwxr-xr-x 0/0  0 2024-01-01 00:00 path/to/dir/`; the path is the last field.
+fn parse_tar_dir_line(line: &str) -> Option<String> {
+    if !line.starts_with('d') {
+        return None;
+    }
+    line.split_whitespace().last().map(|s| {
+        let trimmed = s.trim_end_matches('/');
+        if trimmed.is_empty() || trimmed == "." {
+            "/".to_string()
+        } else if trimmed.starts_with('/') {
+            trimmed.to_string()
         } else {
-            eprintln!(
-                "WARNING: env_passthrough variable '{}' not found in host environment",
-                var_name
-            );
+            format!("/{}", trimmed)
+        }
+    })
+}
+
+/// Returns true if `path` is `root` or below it (everything is under `/` or no root)
+fn is_under_root(path: &str, root: Option<&str>) -> bool {
+    let Some(root) = root.map(|r| r.trim_end_matches('/')) else {
+        return true;
+    };
+    root.is_empty() || path == root || path.starts_with(&format!("{}/", root))
+}
+
+/// Directories under `root_path` from `tar -tv` output, read line by line so
+/// only the matches are kept in memory
+pub(crate) fn collect_tar_dirs(
+    mut reader: impl std::io::BufRead,
+    root_path: Option<&str>,
+) -> Result<Vec<String>> {
+    let mut dirs = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(dirs);
+        }
+        if let Some(dir) = parse_tar_dir_line(&String::from_utf8_lossy(&line))
+            && is_under_root(&dir, root_path)
+        {
+            dirs.push(dir);
         }
     }
+}
 
-    if let Ok(term) = std::env::var("TERM")
-        && let Some(terminfo_dir) = find_terminfo_dir(&term)
-    {
-        binds.push(format_bind(&terminfo_dir, &terminfo_dir, MountMode::Ro));
-        env.push(format!("TERMINFO={}", terminfo_dir.display()));
+/// Label naming the session a spawned container belongs to
+pub const SESSION_LABEL: &str = "com.agent-box.session";
+
+/// Label naming the repo (relative to `base_repo_dir`) a spawned container belongs to
+pub const REPO_LABEL: &str = "com.agent-box.repo";
+
+/// Labels stamped on a session container, so `ab attach --session` can find it
+pub fn session_labels(session: &str, repo: &str) -> Vec<String> {
+    vec![
+        format!("{}={}", SESSION_LABEL, session),
+        format!("{}={}", REPO_LABEL, repo),
+    ]
+}
+
+/// `ps` args listing the names of running containers carrying all `labels`
+fn ps_by_labels_args(labels: &[String]) -> Vec<String> {
+    let mut args = vec!["ps".to_string()];
+    for label in labels {
+        args.push("--filter".to_string());
+        args.push(format!("label={}", label));
+    }
+    args.extend(["--format".to_string(), "{{.Names}}".to_string()]);
+    args
+}
+
+/// Names of running containers carrying all `labels`
+pub(crate) fn list_containers_by_labels(program: &str, labels: &[String]) -> Result<Vec<String>> {
+    let output = std::process::Command::new(program)
+        .args(ps_by_labels_args(labels))
+        .output()
+        .map_err(|e| eyre::eyre!("Failed to execute {} ps: {}", program, e))?;
+
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "Failed to list containers: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
     }
 
-    // Mount portal socket into container (if enabled and socket exists)
-    if config.portal.enabled {
-        let socket_path = if config.portal.global {
-            Some(std::path::PathBuf::from(&config.portal.socket_path))
-        } else {
-            portal_socket_override.map(std::path::PathBuf::from)
-        };
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect())
+}
 
-        if let Some(socket_path) = socket_path {
-            if socket_path.exists() {
-                binds.push(format_bind(&socket_path, &socket_path, MountMode::Rw));
-                env.push(format!("AGENT_PORTAL_SOCKET={}", socket_path.display()));
-            } else {
-                eprintln!(
-                    "WARNING: portal is enabled but socket does not exist yet: {}",
-                    socket_path.display()
-                );
-            }
+/// Pick the one container found for a session, or explain why there isn't one
+fn pick_session_container(
+    session: &str,
+    repo: Option<&str>,
+    mut names: Vec<String>,
+) -> Result<String> {
+    let target = match repo {
+        Some(repo) => format!("session '{}' in repo '{}'", session, repo),
+        None => format!("session '{}'", session),
+    };
+    match names.len() {
+        0 => Err(eyre::eyre!(
+            "No running container found for {} (looked for label {}={})",
+            target,
+            SESSION_LABEL,
+            session
+        )),
+        1 => Ok(names.remove(0)),
+        _ => {
+            names.sort();
+            Err(eyre::eyre!(
+                "{} running containers found for {}: {}{}",
+                names.len(),
+                target,
+                names.join(", "),
+                if repo.is_none() {
+                    "; pass --repo to pick one"
+                } else {
+                    ""
+                }
+            ))
         }
     }
+}
 
-    // Combine profile ports and CLI ports, deduplicate (first occurrence wins)
-    let mut all_ports: Vec<String> = resolved_profile.ports.clone();
-    all_ports.extend(cli_ports.iter().cloned());
-    let mut seen_ports = HashSet::new();
-    all_ports.retain(|p| seen_ports.insert(p.clone()));
+/// The image's default `USER` (`Config.User`), empty if it sets none
+pub(crate) fn inspect_image_user(program: &str, image: &str) -> Result<String> {
+    inspect_image_field(program, image, "{{.Config.User}}")
+}
 
-    // Combine profile hosts and CLI hosts, deduplicate (first occurrence wins)
-    let mut all_hosts: Vec<String> = resolved_profile.hosts.clone();
-    all_hosts.extend(cli_hosts.iter().cloned());
-    let mut seen_hosts = HashSet::new();
-    all_hosts.retain(|h| seen_hosts.insert(h.clone()));
+/// The image's ID, which changes whenever the image is rebuilt or re-pulled
+pub(crate) fn inspect_image_id(program: &str, image: &str) -> Result<String> {
+    inspect_image_field(program, image, "{{.Id}}")
+}
 
-    // Create context file if context is not empty
-    if !resolved_profile.context.is_empty() {
-        // Create a temporary file with a unique name
-        let context_content = resolved_profile.context.join("\n");
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        let pid = std::process::id();
-        let context_file_path = format!("/tmp/agent-box-context-{}-{}", timestamp, pid);
+/// One `image inspect --format` field of the image
+fn inspect_image_field(program: &str, image: &str, format: &str) -> Result<String> {
+    let output = std::process::Command::new(program)
+        .args(["image", "inspect", "--format", format, image])
+        .output()
+        .map_err(|e| eyre::eyre!("Failed to execute {} image inspect: {}", program, e))?;
 
-        // Write context to file (this automatically closes and flushes)
-        std::fs::write(&context_file_path, context_content.as_bytes())
-            .map_err(|e| eyre::eyre!("Failed to write context file: {}", e))?;
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "Failed to inspect image '{}': {}",
+            image,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
 
-        eprintln!("DEBUG: Created context file at {}", context_file_path);
-        eprintln!("DEBUG: Context content:\n{}", context_content);
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
-        // Expand ~ in context_path to container home directory
-        let container_home = format!("/home/{}", username);
-        let container_context_path = if config.context_path.starts_with("~/") {
-            config.context_path.replacen("~", &container_home, 1)
-        } else if config.context_path == "~" {
-            container_home
-        } else {
-            config.context_path.clone()
-        };
+/// Compare the image's default `USER` with the `uid:gid` that `ab spawn` runs as.
+///
+/// Returns a warning when they differ in a way that commonly breaks file
+/// ownership: files the image created for its user (such as its home) won't be
+/// writable by the host uid. Root and unset users are fine, since `--user`
+/// replaces them and such images rarely own files for a specific user.
+pub fn image_user_mismatch(image_user: &str, uid: u32, gid: u32) -> Option<String> {
+    let (user, group) = match image_user.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (image_user, None),
+    };
+    if matches!(user, "" | "root" | "0") {
+        return None;
+    }
 
-        // Add mount for context file (read-write so user can edit)
-        binds.push(format!(
-            "{}:{}:rw",
-            context_file_path, container_context_path
+    let Ok(image_uid) = user.parse::<u32>() else {
+        return Some(format!(
+            "image runs as named user '{}', but ab runs the container as {}:{}; \
+             files the image owns for '{}' (e.g. its home) may not be writable \
+             unless that user's uid is {}",
+            user, uid, gid, user, uid
+        ));
+    };
+    if image_uid != uid {
+        return Some(format!(
+            "image runs as uid {}, but ab runs the container as {}:{}; \
+             files the image owns for uid {} (e.g. its home) will not be writable",
+            image_uid, uid, gid, image_uid
         ));
     }
 
-    Ok(ContainerConfig {
-        name: derive_container_name(config, workspace_path, local),
-        image: config.runtime.image.clone(),
-        entrypoint,
-        command,
-        user: format!("{}:{}", uid, gid),
-        working_dir: workspace_path_str,
-        mounts: binds,
-        env,
-        ports: all_ports,
-        hosts: all_hosts,
-        network,
-        dns: config.runtime.dns.clone(),
-    })
+    match group.map(|g| g.parse::<u32>()) {
+        Some(Ok(image_gid)) if image_gid != gid => Some(format!(
+            "image runs with gid {}, but ab runs the container as {}:{}; \
+             group-owned files from the image may not be writable",
+            image_gid, uid, gid
+        )),
+        Some(Err(_)) => Some(format!(
+            "image runs with named group '{}', but ab runs the container with gid {}; \
+             group-owned files from the image may not be writable",
+            group.unwrap_or_default(),
+            gid
+        )),
+        _ => None,
+    }
 }
 
-/// Check if a path is covered by any existing mount (exact match or subpath).
-/// Returns Some(existing_mode) if covered, None if not covered.
-fn find_covering_mount<'a>(
-    host_path: &Path,
-    existing_mounts: &'a [ResolvedMount],
-) -> Option<&'a ResolvedMount> {
-    for mount in existing_mounts {
-        // Exact match - already mounted
-        if host_path == mount.host {
-            return Some(mount);
-        }
+/// List directories in `image` by exporting a created (never started)
+/// container through `tar -tv`, then removing the container
+pub(crate) fn list_image_dirs(
+    program: &str,
+    image: &str,
+    root_path: Option<&str>,
+) -> Result<Vec<String>> {
+    use std::process::Stdio;
+
+    // Create a container without starting it
+    let create_output = std::process::Command::new(program)
+        .args(["create", image])
+        .output()
+        .map_err(|e| eyre::eyre!("Failed to create container: {}", e))?;
+
+    if !create_output.status.success() {
+        let stderr = String::from_utf8_lossy(&create_output.stderr);
+        return Err(eyre::eyre!("Failed to create container: {}", stderr));
+    }
 
-        // Check if new path is under existing mount
-        if host_path.starts_with(&mount.host) {
-            return Some(mount);
+    let container_id = String::from_utf8_lossy(&create_output.stdout)
+        .trim()
+        .to_string();
+
+    // Export the container filesystem and stream its listing through tar
+    let listing = (|| {
+        let mut export_child = std::process::Command::new(program)
+            .args(["export", &container_id])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| eyre::eyre!("Failed to spawn {} export: {}", program, e))?;
+
+        let mut tar_child = std::process::Command::new("tar")
+            .args(["-tv"]) // Verbose mode shows file types
+            .stdin(export_child.stdout.take().expect("export stdout is piped"))
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| eyre::eyre!("Failed to spawn tar: {}", e))?;
+
+        let stdout = tar_child.stdout.take().expect("tar stdout is piped");
+        let dirs = collect_tar_dirs(std::io::BufReader::new(stdout), root_path)
+            .map_err(|e| eyre::eyre!("Failed to read tar output: {}", e))?;
+
+        let status = tar_child
+            .wait()
+            .map_err(|e| eyre::eyre!("Failed to wait for tar: {}", e))?;
+        let _ = export_child.wait();
+        if !status.success() {
+            return Err(eyre::eyre!("Failed to list tar contents"));
         }
+        Ok(dirs)
+    })();
+
+    // Cleanup the container
+    let _ = std::process::Command::new(program)
+        .args(["rm", &container_id])
+        .output();
+
+    listing
+}
+
+/// Contents of the file at `path` in the image, read by copying it out of a
+/// created (never started) container, so the image needs no shell or tools
+pub(crate) fn read_image_file(program: &str, image: &str, path: &str) -> Result<String> {
+    use std::process::Stdio;
+
+    let create_output = std::process::Command::new(program)
+        .args(["create", image])
+        .output()
+        .map_err(|e| eyre::eyre!("Failed to create container: {}", e))?;
+
+    if !create_output.status.success() {
+        let stderr = String::from_utf8_lossy(&create_output.stderr);
+        return Err(eyre::eyre!("Failed to create container: {}", stderr));
     }
-    None
+
+    let container_id = String::from_utf8_lossy(&create_output.stdout)
+        .trim()
+        .to_string();
+
+    // `cp CONTAINER:PATH -` writes a tar archive; extract the file to stdout
+    let contents = (|| {
+        let mut cp_child = std::process::Command::new(program)
+            .args(["cp", &format!("{}:{}", container_id, path), "-"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| eyre::eyre!("Failed to spawn {} cp: {}", program, e))?;
+
+        let output = std::process::Command::new("tar")
+            .args(["-xO"])
+            .stdin(cp_child.stdout.take().expect("cp stdout is piped"))
+            .output()
+            .map_err(|e| eyre::eyre!("Failed to spawn tar: {}", e))?;
+        let cp_output = cp_child
+            .wait_with_output()
+            .map_err(|e| eyre::eyre!("Failed to wait for {} cp: {}", program, e))?;
+        if !cp_output.status.success() {
+            return Err(eyre::eyre!(
+                "Failed to read {} from image '{}': {}",
+                path,
+                image,
+                String::from_utf8_lossy(&cp_output.stderr).trim()
+            ));
+        }
+        if !output.status.success() {
+            return Err(eyre::eyre!("Failed to extract {} from tar", path));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    })();
+
+    // Cleanup the container
+    let _ = std::process::Command::new(program)
+        .args(["rm", &container_id])
+        .output();
+
+    contents
 }
 
-/// Add mounts to the binds vector.
-/// Handles symlinks by mounting the entire symlink chain.
-/// Skips paths that are already covered by a parent mount (unless should_skip is false).
-/// Skips paths that match configured skip_mounts patterns (supports globs).
-///
-/// Mount mode behavior (existing parent → new child):
-///
-/// | Parent | Child | Action |
-/// |--------|-------|--------|
-/// | ro     | ro    | Skip (covered) [unless --no-skip] |
-/// | ro     | rw    | Skip (covered, child mount overrides parent) [unless --no-skip] |
-/// | ro     | O     | Skip (covered) [unless --no-skip] |
-/// | rw     | ro    | Skip (covered, ro ⊆ rw) [unless --no-skip] |
-/// | rw     | rw    | Skip (covered) [unless --no-skip] |
-/// | rw     | O     | Skip (covered) [unless --no-skip] |
-/// | O      | ro    | Skip (covered) [unless --no-skip] |
-/// | O      | rw    | Skip (covered) [unless --no-skip] |
-/// | O      | O     | Skip (covered) [unless --no-skip] |
-fn add_mounts(
-    mounts: &[&Mount],
-    binds: &mut Vec<String>,
-    should_skip: bool,
-    skip_patterns: &[String],
-) -> Result<()> {
-    // Parse existing binds into resolved mounts for coverage checking
-    let mut existing_resolved: Vec<ResolvedMount> = binds
+/// Home directory in passwd-format text (an `/etc/passwd` file) of the entry
+/// named `user`, or else of the first entry with `uid`. Entries without an
+/// absolute home are ignored.
+pub fn parse_passwd_home(passwd: &str, user: &str, uid: u32) -> Option<String> {
+    let entries: Vec<(&str, &str, &str)> = passwd
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.as_slice() {
+                [name, _, entry_uid, _, _, home, ..] if home.starts_with('/') => {
+                    Some((*name, *entry_uid, *home))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+    let uid = uid.to_string();
+
+    entries
         .iter()
-        .filter_map(|b| {
-            let parts: Vec<&str> = b.split(':').collect();
-            if parts.len() >= 3 {
-                Some(ResolvedMount {
-                    host: PathBuf::from(parts[0]),
-                    container: PathBuf::from(parts[1]),
-                    mode: parts[2].parse().unwrap_or(MountMode::Rw),
-                })
+        .find(|(name, _, _)| *name == user)
+        .or_else(|| entries.iter().find(|(_, entry_uid, _)| *entry_uid == uid))
+        .map(|(_, _, home)| home.trim_end_matches('/').to_string())
+        .map(|home| {
+            if home.is_empty() {
+                "/".to_string()
             } else {
-                None
+                home
             }
         })
-        .collect();
+}
 
-    // First, resolve all mounts and collect them
-    let mut all_resolved: Vec<ResolvedMount> = Vec::new();
-    for mount in mounts {
-        // to_resolved_mounts handles existence check and symlink chain
-        let mount_resolved = mount.to_resolved_mounts()?;
-        all_resolved.extend(mount_resolved);
+/// Cache of container homes found in image passwd files, keyed by
+/// `"<image id> <user> <uid>"` so a rebuilt image is looked up again
+pub fn passwd_home_cache_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("agent-box").join("passwd-homes.json"))
+}
+
+/// Read the passwd home cache; a missing or unreadable cache is empty
+pub fn load_passwd_home_cache(path: &Path) -> BTreeMap<String, String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_passwd_home_cache(path: &Path, cache: &BTreeMap<String, String>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| eyre::eyre!("Failed to create {}: {}", parent.display(), e))?;
     }
+    let json = serde_json::to_string_pretty(cache)
+        .map_err(|e| eyre::eyre!("Failed to serialize passwd home cache: {}", e))?;
+    std::fs::write(path, json).map_err(|e| eyre::eyre!("Failed to write {}: {}", path.display(), e))
+}
 
-    // Sort by host path length (shortest first) so parent paths are processed before children.
-    // This ensures that when a symlink chain resolves to paths under /nix/store,
-    // the /nix/store mount is already in existing_resolved and coverage check works.
-    all_resolved.sort_by(|a, b| {
-        a.host
-            .as_os_str()
-            .len()
-            .cmp(&b.host.as_os_str().len())
-            .then_with(|| a.host.cmp(&b.host))
-    });
+/// Exit code docker and podman use when the runtime itself failed, before
+/// the container process ran
+const RUNTIME_START_FAILURE_CODE: i32 = 125;
+
+/// stderr fragments that suggest the runtime failure is transient and worth retrying
+const TRANSIENT_START_ERRORS: &[&str] = &[
+    "connection refused",
+    "connection reset",
+    "cannot connect to the docker daemon",
+    "is the docker daemon running",
+    "daemon is not running",
+    "i/o timeout",
+    "temporary failure",
+];
+
+/// The runtime failed to start the container (as opposed to the container
+/// exiting non-zero on its own)
+#[derive(Debug)]
+pub struct StartFailure {
+    pub runtime: &'static str,
+    pub status: std::process::ExitStatus,
+    pub stderr: String,
+}
 
-    for resolved in all_resolved {
-        // Check if this path should be skipped based on configured skip patterns
-        if should_skip_path(&resolved.host, skip_patterns) {
-            eprintln!(
-                "DEBUG: Skipping mount path matching skip_mounts pattern: {}",
-                resolved.host.display(),
-            );
-            continue;
-        }
+impl std::fmt::Display for StartFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} failed to start container: {}",
+            self.runtime, self.status
+        )
+    }
+}
 
-        if let Some(_existing_mode) = find_covering_mount(&resolved.host, &existing_resolved) {
-            // Skip if covered (unless should_skip is false)
-            if !should_skip {
-                // Add even though it's covered
-                binds.push(resolved.to_bind_string());
-                existing_resolved.push(resolved);
-            }
-            // Otherwise skip - already covered
-        } else {
-            // Not covered - add to existing resolved mounts and binds
-            binds.push(resolved.to_bind_string());
-            existing_resolved.push(resolved);
-        }
+impl std::error::Error for StartFailure {}
+
+/// The container ran and exited non-zero
+#[derive(Debug)]
+pub struct ContainerExited {
+    pub runtime: &'static str,
+    pub status: std::process::ExitStatus,
+}
+
+impl std::fmt::Display for ContainerExited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} container exited with status: {}",
+            self.runtime, self.status
+        )
     }
+}
 
-    Ok(())
+impl std::error::Error for ContainerExited {}
+
+/// Returns true if a start failure's stderr matches a known transient error
+fn is_transient_start_error(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    TRANSIENT_START_ERRORS
+        .iter()
+        .any(|pattern| stderr.contains(pattern))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Only transient failures to start are retried, never the container's own exit codes
+fn should_retry_spawn(err: &eyre::Report) -> bool {
+    err.downcast_ref::<StartFailure>()
+        .is_some_and(|failure| is_transient_start_error(&failure.stderr))
+}
 
-    #[test]
-    fn test_resolved_mount_to_bind_string() {
-        let resolved = ResolvedMount {
-            host: PathBuf::from("/host/path"),
-            container: PathBuf::from("/container/path"),
-            mode: MountMode::Ro,
-        };
-        assert_eq!(resolved.to_bind_string(), "/host/path:/container/path:ro");
+/// Backoff before retry number `attempt` (1-based): 500ms, 1s, 2s, ... capped at 8s
+fn retry_backoff(attempt: u32) -> std::time::Duration {
+    std::time::Duration::from_millis(500 * 2u64.pow(attempt.saturating_sub(1).min(4)))
+}
+
+/// Call `spawn` until it succeeds, fails for a non-retryable reason, or
+/// `retries` extra attempts are used up.
+fn spawn_with_retries(
+    retries: u32,
+    mut spawn: impl FnMut() -> Result<()>,
+    sleep: impl Fn(std::time::Duration),
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match spawn() {
+            Err(e) if attempt < retries && should_retry_spawn(&e) => {
+                attempt += 1;
+                let delay = retry_backoff(attempt);
+                eprintln!(
+                    "WARNING: {}; retrying in {:?} ({}/{})",
+                    e, delay, attempt, retries
+                );
+                sleep(delay);
+            }
+            result => return result,
+        }
     }
+}
 
-    #[test]
-    fn test_resolved_mount_overlay() {
-        let resolved = ResolvedMount {
-            host: PathBuf::from("/host"),
-            container: PathBuf::from("/container"),
-            mode: MountMode::Overlay,
-        };
-        assert_eq!(resolved.to_bind_string(), "/host:/container:O");
+/// Spawn with `--copy-in`: create the container and copy the files in once,
+/// before its entrypoint runs, then start it, retrying only the start.
+/// The container is removed if copying the files or starting it fails.
+fn spawn_with_copy_in(
+    backend: &dyn ContainerBackend,
+    config: &ContainerConfig,
+    retries: u32,
+    sleep: impl Fn(std::time::Duration),
+) -> Result<()> {
+    backend.create_container(config)?;
+    config
+        .copy_in
+        .iter()
+        .try_for_each(|copy| backend.copy_into_container(&config.name, &copy.host, &copy.container))
+        .and_then(|()| spawn_with_retries(retries, || backend.spawn_container(config), sleep))
+        .inspect_err(|_| {
+            let _ = backend.remove_container(&config.name);
+        })
+}
+
+/// Run `program args` attached to the terminal, forwarding the runtime's
+/// stderr while keeping a copy to classify start failures.
+/// With `-t` the container's own output goes through the pty on stdout,
+/// so stderr only carries the runtime CLI's messages.
+pub(crate) fn run_attached(program: &str, label: &'static str, args: &[String]) -> Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| eyre::eyre!("Failed to execute {} command: {}", program, e))?;
+
+    let mut captured = String::new();
+    if let Some(stderr) = child.stderr.take() {
+        let mut host_stderr = std::io::stderr();
+        for line in BufReader::new(stderr).lines().map_while(|l| l.ok()) {
+            let _ = writeln!(host_stderr, "{}", line);
+            captured.push_str(&line);
+            captured.push('\n');
+        }
     }
 
-    #[test]
-    fn test_find_covering_mount_exact_match() {
-        let mounts = vec![ResolvedMount {
-            host: PathBuf::from("/host/path"),
-            container: PathBuf::from("/container/path"),
-            mode: MountMode::Ro,
-        }];
-        let result = find_covering_mount(Path::new("/host/path"), &mounts).map(|m| m.mode);
-        assert_eq!(result, Some(MountMode::Ro));
+    let status = child
+        .wait()
+        .map_err(|e| eyre::eyre!("Failed to wait for {} command: {}", program, e))?;
+
+    if status.success() {
+        Ok(())
+    } else if status.code() == Some(RUNTIME_START_FAILURE_CODE) {
+        Err(StartFailure {
+            runtime: label,
+            status,
+            stderr: captured,
+        }
+        .into())
+    } else {
+        Err(ContainerExited {
+            runtime: label,
+            status,
+        }
+        .into())
     }
+}
 
-    #[test]
-    fn test_find_covering_mount_subpath() {
-        let mounts = vec![ResolvedMount {
-            host: PathBuf::from("/nix/store"),
-            container: PathBuf::from("/nix/store"),
-            mode: MountMode::Ro,
-        }];
-        let result =
-            find_covering_mount(Path::new("/nix/store/abc123-package"), &mounts).map(|m| m.mode);
-        assert_eq!(result, Some(MountMode::Ro));
-    }
+/// Returns true if the workspace itself contains both `.git` and `.jj`
+/// (a colocated jj workspace), in which case the source repo's metadata
+/// directories must not be mounted separately.
+fn is_colocated_workspace(workspace_path: &Path) -> bool {
+    workspace_path.join(".git").exists() && workspace_path.join(".jj").exists()
+}
 
-    #[test]
-    fn test_find_covering_mount_not_covered() {
-        let mounts = vec![ResolvedMount {
-            host: PathBuf::from("/nix/store"),
-            container: PathBuf::from("/nix/store"),
-            mode: MountMode::Ro,
-        }];
-        let result = find_covering_mount(Path::new("/home/user"), &mounts);
-        assert_eq!(result, None);
+/// Configuration for running a container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerConfig {
+    pub name: String,
+    pub image: String,
+    pub entrypoint: Option<Vec<String>>,
+    pub command: Option<Vec<String>>,
+    pub user: String,
+    /// Supplementary groups (GIDs or names) for `user`
+    pub group_add: Vec<String>,
+    pub working_dir: String,
+    pub mounts: Vec<String>,
+    pub env: Vec<String>,
+    pub ports: Vec<String>,
+    pub hosts: Vec<String>,
+    pub network: Option<String>,
+    /// CPU, memory, process, and ulimit limits
+    #[serde(default)]
+    pub resources: ResourcesConfig,
+    /// `KEY=VALUE` container labels, e.g. the session labels from [`session_labels`]
+    pub labels: Vec<String>,
+    pub dns: Vec<String>,
+    /// DNS search domains (`--dns-search`)
+    pub dns_search: Vec<String>,
+    /// Whether `mounts` are passed as `-v` or `--mount`
+    pub mount_syntax: MountSyntax,
+    pub copy_in: Vec<CopyIn>,
+    /// Extra env var name patterns whose values are masked when printing commands
+    pub redact_env: Vec<String>,
+    /// Counts of the binds in `mounts` and of the mounts left out
+    #[serde(skip)]
+    pub mount_summary: MountSummary,
+    /// Start in the background (`-d`) instead of attaching; see `ab spawn --detach`
+    #[serde(default)]
+    pub detach: bool,
+    /// Secrets mounted as read-only files; written to the host only while spawning.
+    /// Never part of a spawn record.
+    #[serde(skip)]
+    pub secret_files: Vec<secrets::SecretFile>,
+    /// Hosts reachable through an egress proxy sidecar started for the spawn;
+    /// empty means none is started
+    #[serde(default)]
+    pub allow_hosts: Vec<String>,
+    /// Container paths mounted as empty tmpfs
+    #[serde(default)]
+    pub tmpfs: Vec<String>,
+    /// `NAME:CONTAINER:MODE` named volume mounts, created before the spawn if missing
+    #[serde(default)]
+    pub volumes: Vec<String>,
+    /// GPUs to pass through: `all` or device IDs; empty means none
+    #[serde(default)]
+    pub gpus: Vec<String>,
+    /// CDI device kind Podman requests `gpus` as; unset means [`DEFAULT_GPU_CDI_KIND`]
+    #[serde(default)]
+    pub gpu_cdi_kind: Option<String>,
+    /// Capabilities, seccomp profile, and privilege and root filesystem lockdown
+    #[serde(default)]
+    pub security: SecurityConfig,
+}
+
+#[cfg(test)]
+impl ContainerConfig {
+    /// An attached `agent:latest` container with nothing else set, for tests to
+    /// fill in the fields they exercise with `..ContainerConfig::for_test()`
+    pub(crate) fn for_test() -> Self {
+        Self {
+            name: "ab-test".to_string(),
+            image: "agent:latest".to_string(),
+            entrypoint: None,
+            command: None,
+            user: "1000:1000".to_string(),
+            group_add: Vec::new(),
+            working_dir: "/work".to_string(),
+            mounts: Vec::new(),
+            env: Vec::new(),
+            ports: Vec::new(),
+            hosts: Vec::new(),
+            network: None,
+            resources: Default::default(),
+            labels: Vec::new(),
+            dns: Vec::new(),
+            dns_search: Vec::new(),
+            mount_syntax: MountSyntax::Volume,
+            copy_in: Vec::new(),
+            redact_env: Vec::new(),
+            mount_summary: MountSummary::default(),
+            detach: false,
+            secret_files: Vec::new(),
+            allow_hosts: Vec::new(),
+            tmpfs: Vec::new(),
+            volumes: Vec::new(),
+            gpus: Vec::new(),
+            gpu_cdi_kind: None,
+            security: Default::default(),
+        }
     }
+}
 
-    const HOST_HOME: &str = "/home/hostuser";
-    const CONTAINER_HOME: &str = "/home/containeruser";
+/// Format version of `ab spawn --record` files
+const SPAWN_RECORD_VERSION: u32 = 1;
+
+/// A fully resolved spawn, written by `ab spawn --record` and run as-is by
+/// `ab replay` without consulting the config or profiles
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpawnRecord {
+    pub version: u32,
+    /// Runtime backend (`podman`, `docker`, `bwrap`, or `kubernetes`)
+    pub backend: String,
+    pub container: ContainerConfig,
+}
 
-    /// Helper to create a Mount and resolve with test homes (without canonicalization)
-    fn resolve_test(spec: &str, home_relative: bool) -> (String, String) {
-        let mount = Mount {
-            spec: spec.to_string(),
-            home_relative,
-            mode: MountMode::Rw,
-        };
-        // Use resolve_paths directly to avoid canonicalization in tests
-        mount.resolve_paths(HOST_HOME, CONTAINER_HOME).unwrap()
+impl SpawnRecord {
+    pub fn new(backend: &str, container: &ContainerConfig) -> Self {
+        Self {
+            version: SPAWN_RECORD_VERSION,
+            backend: backend.to_string(),
+            container: container.clone(),
+        }
     }
 
-    #[test]
-    fn test_resolve_absolute_single_path() {
-        // absolute (home_relative=false): same path on both sides
-        let (host, container) = resolve_test("/nix/store", false);
-        assert_eq!(host, "/nix/store");
-        assert_eq!(container, "/nix/store");
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| eyre::eyre!("Failed to serialize spawn record: {}", e))
     }
 
-    #[test]
-    fn test_resolve_absolute_single_path_with_tilde() {
-        // absolute with ~: expands to host home, container gets same absolute path
-        let (host, container) = resolve_test("~/.config", false);
-        assert_eq!(host, "/home/hostuser/.config");
-        assert_eq!(container, "/home/hostuser/.config"); // same path, NOT translated
+    pub fn from_json(json: &str) -> Result<Self> {
+        let record: Self =
+            serde_json::from_str(json).map_err(|e| eyre::eyre!("Invalid spawn record: {}", e))?;
+        if record.version != SPAWN_RECORD_VERSION {
+            return Err(eyre::eyre!(
+                "Unsupported spawn record version {} (expected {})",
+                record.version,
+                SPAWN_RECORD_VERSION
+            ));
+        }
+        if !matches!(
+            record.backend.as_str(),
+            "podman" | "docker" | "bwrap" | "kubernetes"
+        ) {
+            return Err(eyre::eyre!(
+                "Unknown backend '{}' in spawn record",
+                record.backend
+            ));
+        }
+        Ok(record)
     }
 
-    #[test]
-    fn test_resolve_home_relative_single_path() {
-        // home_relative=true: host home prefix replaced with container home
-        let (host, container) = resolve_test("~/.config", true);
-        assert_eq!(host, "/home/hostuser/.config");
-        assert_eq!(container, "/home/containeruser/.config"); // translated!
-    }
+    /// Write the record readable only by the owner, since env values may be secrets.
+    /// Secret files aren't written, so a spawn that has them can't be recorded.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        use std::io::Write;
 
-    #[test]
-    fn test_resolve_home_relative_path_not_under_home() {
-        // home_relative=true but path not under home: use as-is
-        let (host, container) = resolve_test("/nix/store", true);
-        assert_eq!(host, "/nix/store");
-        assert_eq!(container, "/nix/store");
-    }
+        if !self.container.secret_files.is_empty() {
+            eyre::bail!(
+                "--record can't be used with profile secrets that have a path: `ab replay` has no config to fetch them from"
+            );
+        }
 
-    #[test]
-    fn test_resolve_explicit_mapping_absolute() {
-        // Explicit source:dest mapping
-        let (host, container) = resolve_test("/host/path:/container/path", false);
-        assert_eq!(host, "/host/path");
-        assert_eq!(container, "/container/path");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options
+            .open(path)
+            .map_err(|e| eyre::eyre!("Failed to write {}: {}", path.display(), e))?;
+        writeln!(file, "{}", self.to_json()?)
+            .map_err(|e| eyre::eyre!("Failed to write {}: {}", path.display(), e))
     }
 
-    #[test]
-    fn test_resolve_explicit_mapping_with_tilde() {
-        // Explicit mapping with ~ on dest side expands to container home
-        let (host, container) = resolve_test("/run/user/1000/gnupg:~/.gnupg", true);
-        assert_eq!(host, "/run/user/1000/gnupg");
-        assert_eq!(container, "/home/containeruser/.gnupg");
+    pub fn read(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_json(&json)
     }
 
-    #[test]
-    fn test_resolve_explicit_mapping_tilde_both_sides() {
-        // ~ on both sides: host ~ -> host home, container ~ -> container home
-        let (host, container) = resolve_test("~/.foo:~/.bar", false);
-        assert_eq!(host, "/home/hostuser/.foo");
-        assert_eq!(container, "/home/containeruser/.bar");
+    /// The backend the record was captured with
+    pub fn runtime(&self) -> Runtime {
+        backend_runtime(&self.backend)
     }
+}
 
-    #[test]
-    fn test_add_mounts_skips_covered_paths() {
-        // Test that symlink chain paths under already-mounted directories are skipped
-        let base_dir = std::env::temp_dir().join(format!("ab_base_{}", std::process::id()));
-        let temp_dir = std::env::temp_dir().join(format!("ab_covered_{}", std::process::id()));
-        let link_path = temp_dir.join("mylink");
-
-        // Create base directory that will be mounted
-        let _ = std::fs::remove_dir_all(&base_dir);
-        std::fs::create_dir_all(&base_dir).unwrap();
-
-        // Create temp directory for the symlink
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&temp_dir).unwrap();
+/// Mount counts printed before spawning, e.g.
+/// `Mounts: 3 ro, 5 rw, 1 overlay (2 skipped as covered, 1 filtered missing)`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountSummary {
+    pub ro: usize,
+    pub rw: usize,
+    pub overlay: usize,
+    pub tmpfs: usize,
+    pub volumes: usize,
+    /// Mounts left out because an existing bind already covers them
+    pub covered: usize,
+    /// Mounts whose host path does not exist
+    pub filtered: usize,
+    /// Mounts left out because they are missing or match `skip_mounts`, with the reason
+    pub dropped: Vec<String>,
+    /// The mount spec whose symlink chain resolved to the most paths, with the count
+    pub largest_expansion: Option<(String, usize)>,
+    /// Writable (rw or overlay) mounts of `/`, the host home, or a huge directory,
+    /// as `PATH (reason)`
+    pub large: Vec<String>,
+}
 
-        // Create initial bind for the base directory
-        let mut binds = vec![format!("{}:{}:ro", base_dir.display(), base_dir.display())];
+impl MountSummary {
+    /// `--require-all-mounts`: fail if any configured mount was dropped, listing each
+    pub fn require_all(&self) -> Result<()> {
+        if self.dropped.is_empty() {
+            return Ok(());
+        }
+        Err(eyre::eyre!(
+            "--require-all-mounts: {} mount(s) would not be mounted:\n  {}",
+            self.dropped.len(),
+            self.dropped.join("\n  ")
+        ))
+    }
 
-        // Create symlink to base_dir (which exists)
-        std::os::unix::fs::symlink(&base_dir, &link_path).unwrap();
+    /// Ask before mounting the [`large`](Self::large) writable mounts; `yes`
+    /// (`--yes`) skips the question, and non-interactive runs need it
+    pub fn confirm_large_mounts(
+        &self,
+        yes: bool,
+        confirm: impl FnOnce(&str) -> Result<bool>,
+    ) -> Result<()> {
+        if self.large.is_empty() || yes {
+            return Ok(());
+        }
+        let list = format!(
+            "{} large writable mount(s):\n  {}",
+            self.large.len(),
+            self.large.join("\n  ")
+        );
+        agent_box_common::prompt::require_interactive(&format!(
+            "{}\nPass --yes to mount them anyway",
+            list
+        ))?;
+        if confirm(&list)? {
+            Ok(())
+        } else {
+            Err(eyre::eyre!("Aborted: not mounting {}", list))
+        }
+    }
 
-        let mount = Mount {
-            spec: link_path.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
+    /// Fail if `binds` exceeds `runtime.max_mounts` (0 disables the limit), naming
+    /// the mount that expanded the most, since a runaway symlink chain is the usual cause
+    pub fn check_max_mounts(&self, binds: usize, max_mounts: usize) -> Result<()> {
+        if max_mounts == 0 || binds <= max_mounts {
+            return Ok(());
+        }
+        let culprit = match &self.largest_expansion {
+            Some((spec, paths)) => format!(
+                "; mount '{}' alone expanded to {} paths (check for recursive symlinks)",
+                spec, paths
+            ),
+            None => String::new(),
         };
+        Err(eyre::eyre!(
+            "{} mounts exceed runtime.max_mounts ({}){}",
+            binds,
+            max_mounts,
+            culprit
+        ))
+    }
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
-
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        let _ = std::fs::remove_dir_all(&base_dir);
+    /// Set the per-mode counts from `host:container:mode` bind strings
+    fn count_binds(&mut self, binds: &[String]) {
+        self.ro = 0;
+        self.rw = 0;
+        self.overlay = 0;
+        for bind in binds {
+            match parse_bind(bind).mode.parse().ok() {
+                Some(MountMode::Ro) => self.ro += 1,
+                Some(MountMode::Overlay) => self.overlay += 1,
+                Some(MountMode::Rw) | None => self.rw += 1,
+            }
+        }
+    }
+}
 
-        // Should have 2 mounts: original base_dir and the symlink itself
-        // The symlink target (base_dir) should NOT be added again
-        assert_eq!(binds.len(), 2);
-        assert!(binds[0].contains(&base_dir.file_name().unwrap().to_string_lossy().to_string()));
-        assert!(binds[1].contains("mylink"));
+impl std::fmt::Display for MountSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Mounts: {} ro, {} rw, {} overlay",
+            self.ro, self.rw, self.overlay
+        )?;
+        if self.tmpfs > 0 {
+            write!(f, ", {} tmpfs", self.tmpfs)?;
+        }
+        if self.volumes > 0 {
+            write!(f, ", {} volume(s)", self.volumes)?;
+        }
+        if self.covered > 0 || self.filtered > 0 {
+            write!(
+                f,
+                " ({} skipped as covered, {} filtered missing)",
+                self.covered, self.filtered
+            )?;
+        }
+        Ok(())
     }
+}
 
-    #[test]
-    fn test_add_mounts_ro_under_rw_allowed() {
-        // ro mount under rw parent should be skipped (covered)
-        let temp_dir = std::env::temp_dir().join(format!("ab_ro_rw_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+/// The parts of a `HOST:CONTAINER:MODE[,PROPAGATION][,nonrecursive]` bind string
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BindParts<'a> {
+    pub host: &'a str,
+    pub container: &'a str,
+    pub mode: &'a str,
+    pub propagation: Option<&'a str>,
+    pub recursive: bool,
+}
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+/// Split a bind string from the right, so colons in the host path survive
+pub(crate) fn parse_bind(bind: &str) -> BindParts<'_> {
+    let mut parts = bind.rsplitn(3, ':');
+    let options = parts.next().unwrap_or_default();
+    let container = parts.next().unwrap_or_default();
+    let host = parts.next().unwrap_or(container);
+    let mut options = options.split(',');
+    let mode = options.next().unwrap_or_default();
+    let mut propagation = None;
+    let mut recursive = true;
+    for option in options {
+        if option == "nonrecursive" {
+            recursive = false;
+        } else {
+            propagation = Some(option);
+        }
+    }
+    BindParts {
+        host,
+        container,
+        mode,
+        propagation,
+        recursive,
+    }
+}
 
-        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+/// Quote a `--mount` field if it contains a comma or quote (the value is CSV)
+fn mount_field(field: String) -> String {
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
-        };
+/// Runtime args for one bind string in the configured `runtime.mount_syntax`.
+/// Overlay mounts have no `type=bind` form and always use `-v`; non-recursive
+/// binds have no `-v` form and always use `--mount`.
+pub fn mount_args(bind: &str, syntax: MountSyntax) -> Vec<String> {
+    let parts = parse_bind(bind);
+    let mode = parts.mode.parse().unwrap_or(MountMode::Rw);
+    if (syntax == MountSyntax::Volume && parts.recursive) || mode == MountMode::Overlay {
+        return vec!["-v".to_string(), bind.to_string()];
+    }
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
+    let mut fields = vec![
+        "type=bind".to_string(),
+        mount_field(format!("source={}", parts.host)),
+        mount_field(format!("target={}", parts.container)),
+    ];
+    if mode == MountMode::Ro {
+        fields.push("readonly".to_string());
+    }
+    if let Some(propagation) = parts.propagation {
+        fields.push(format!("bind-propagation={}", propagation));
+    }
+    if !parts.recursive {
+        fields.push("bind-nonrecursive".to_string());
+    }
+    vec!["--mount".to_string(), fields.join(",")]
+}
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+/// Runtime args for a tmpfs mount at container `path` in the configured
+/// `runtime.mount_syntax`
+pub fn tmpfs_args(path: &str, syntax: MountSyntax) -> Vec<String> {
+    match syntax {
+        MountSyntax::Volume => vec!["--tmpfs".to_string(), path.to_string()],
+        MountSyntax::Mount => vec![
+            "--mount".to_string(),
+            format!(
+                "type=tmpfs,{}",
+                mount_field(format!("destination={}", path))
+            ),
+        ],
+    }
+}
 
-        // Should still have just 1 mount (subdir skipped as covered by parent)
-        assert_eq!(binds.len(), 1);
+/// Runtime args for a `NAME:CONTAINER:MODE` named volume mount in the configured
+/// `runtime.mount_syntax`
+pub fn volume_args(volume: &str, syntax: MountSyntax) -> Vec<String> {
+    let parts = parse_bind(volume);
+    match syntax {
+        MountSyntax::Volume => vec!["-v".to_string(), volume.to_string()],
+        MountSyntax::Mount => {
+            let mut fields = vec![
+                "type=volume".to_string(),
+                format!("source={}", parts.host),
+                mount_field(format!("target={}", parts.container)),
+            ];
+            if parts.mode == "ro" {
+                fields.push("readonly".to_string());
+            }
+            vec!["--mount".to_string(), fields.join(",")]
+        }
     }
+}
 
-    #[test]
-    fn test_add_mounts_rw_under_ro_skipped() {
-        // rw mount under ro parent should be skipped (covered)
-        let temp_dir = std::env::temp_dir().join(format!("ab_rw_ro_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+/// Remove the CLI mount arguments starting with `prefix`, returning the rest of each
+fn take_prefixed(args: [&mut Vec<String>; 2], prefix: &str) -> Vec<String> {
+    let mut taken = Vec::new();
+    for args in args {
+        args.retain(|arg| match arg.strip_prefix(prefix) {
+            Some(rest) => {
+                taken.push(rest.to_string());
+                false
+            }
+            None => true,
+        });
+    }
+    taken
+}
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+/// `-m tmpfs:PATH` / `-M tmpfs:PATH`: remove the tmpfs mounts from the CLI mount
+/// arguments, returning their container paths
+pub fn take_cli_tmpfs(
+    home_relative: &mut Vec<String>,
+    absolute: &mut Vec<String>,
+) -> Result<Vec<String>> {
+    let paths = take_prefixed([home_relative, absolute], "tmpfs:");
+    let tmpfs = TmpfsMounts { paths };
+    if let Some(problem) = tmpfs.problems().into_iter().next() {
+        eyre::bail!("Invalid tmpfs mount: {}", problem);
+    }
+    Ok(tmpfs.paths)
+}
 
-        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+/// `-m volume:NAME:PATH[:MODE]` / `-M volume:...`: remove the named volume mounts
+/// from the CLI mount arguments, returning their specs
+pub fn take_cli_volumes(
+    home_relative: &mut Vec<String>,
+    absolute: &mut Vec<String>,
+) -> Result<Vec<String>> {
+    let specs = take_prefixed([home_relative, absolute], "volume:");
+    for spec in &specs {
+        spec.parse::<VolumeSpec>()
+            .map_err(|e| eyre::eyre!("Invalid named volume mount: {}", e))?;
+    }
+    Ok(specs)
+}
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Rw,
-        };
+/// `HOST:CONTAINER:MODE` bind strings as JSON objects with `host`, `container`,
+/// and `mode` (`ro`, `rw`, or `overlay`), plus `propagation` when set and
+/// `"recursive": false` for non-recursive binds, in mount order
+pub fn binds_json(binds: &[String]) -> serde_json::Value {
+    binds
+        .iter()
+        .map(|bind| {
+            let parts = parse_bind(bind);
+            let mode = match parts.mode.parse() {
+                Ok(MountMode::Ro) => "ro",
+                Ok(MountMode::Rw) => "rw",
+                Ok(MountMode::Overlay) => "overlay",
+                Err(_) => parts.mode,
+            };
+            let mut json = serde_json::json!({
+                "host": parts.host,
+                "container": parts.container,
+                "mode": mode,
+            });
+            if let Some(propagation) = parts.propagation {
+                json["propagation"] = propagation.into();
+            }
+            if !parts.recursive {
+                json["recursive"] = false.into();
+            }
+            json
+        })
+        .collect()
+}
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
+/// Container env entries as a sourceable file: `KEY=VALUE` lines (`.env` style),
+/// or `export KEY='VALUE'` lines with `export`. Entries without a value stay bare.
+pub fn env_file(env: &[String], export: bool) -> String {
+    env.iter()
+        .map(|entry| match (entry.split_once('='), export) {
+            (Some((key, value)), true) => {
+                format!("export {}='{}'\n", key, value.replace('\'', "'\\''"))
+            }
+            (None, true) => format!("export {}\n", entry),
+            (_, false) => format!("{}\n", entry),
+        })
+        .collect()
+}
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+/// Enum of available container runtimes
+pub enum Runtime {
+    Docker(docker::DockerRuntime),
+    Podman(podman::PodmanRuntime),
+    Bubblewrap(bwrap::BubblewrapRuntime),
+    Kubernetes(kubernetes::KubernetesRuntime),
+}
 
-        // Should still have just 1 mount (subdir skipped as covered)
-        assert_eq!(binds.len(), 1);
+impl Runtime {
+    /// Spawn a container with the given configuration
+    pub fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => rt.spawn_container(config),
+            Runtime::Podman(rt) => rt.spawn_container(config),
+            Runtime::Bubblewrap(rt) => rt.spawn_container(config),
+            Runtime::Kubernetes(rt) => rt.spawn_container(config),
+        }
     }
 
-    #[test]
-    fn test_add_mounts_overlay_under_ro_skipped() {
-        // overlay mount under ro parent should be skipped (covered)
-        let temp_dir = std::env::temp_dir().join(format!("ab_o_ro_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
-
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
-
-        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+    fn backend(&self) -> &dyn ContainerBackend {
+        match self {
+            Runtime::Docker(rt) => rt,
+            Runtime::Podman(rt) => rt,
+            Runtime::Bubblewrap(rt) => rt,
+            Runtime::Kubernetes(rt) => rt,
+        }
+    }
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Overlay,
+    /// Spawn a container, retrying up to `retries` times on transient start
+    /// failures, and record the spawn in the audit log
+    pub fn spawn_container_with_retries(
+        &self,
+        config: &ContainerConfig,
+        retries: u32,
+    ) -> Result<()> {
+        let started = std::time::SystemTime::now();
+        let mut config = std::borrow::Cow::Borrowed(config);
+        // Dropping it once the spawn returns removes the files from the host.
+        // Pods get them from a Kubernetes Secret instead, see `kubernetes::secret_spec`
+        let _secret_dir = if config.secret_files.is_empty()
+            || matches!(self, Runtime::Kubernetes(_))
+        {
+            None
+        } else {
+            if config.detach {
+                eyre::bail!(
+                    "Profile secrets with a path can't be used with --detach: their files are removed from the host once ab spawn returns"
+                );
+            }
+            let (dir, binds) = secrets::SecretDir::create(&config.secret_files)?;
+            config.to_mut().mounts.extend(binds);
+            Some(dir)
+        };
+        // Dropping it once the spawn returns removes the sidecar and network
+        let _egress = if config.allow_hosts.is_empty() {
+            None
+        } else {
+            if config.detach {
+                eyre::bail!(
+                    "network.allow_hosts can't be used with --detach: the proxy sidecar only runs while ab spawn does"
+                );
+            }
+            let proxy = egress::EgressProxy::for_container(&config.name);
+            self.start_egress_proxy(&proxy, &config.image, &config.allow_hosts)?;
+            proxy.apply(config.to_mut());
+            Some(egress::EgressGuard {
+                runtime: self,
+                proxy,
+            })
         };
+        for volume in &config.volumes {
+            self.ensure_volume(parse_bind(volume).host)?;
+        }
+        // Pods get their --copy-in files in `KubernetesRuntime::spawn_container`
+        let result = if config.copy_in.is_empty() || matches!(self, Runtime::Kubernetes(_)) {
+            spawn_with_retries(
+                retries,
+                || self.spawn_container(&config),
+                std::thread::sleep,
+            )
+        } else {
+            spawn_with_copy_in(self.backend(), &config, retries, std::thread::sleep)
+        };
+        audit::record_spawn(self.backend_name(), &config, started, &result);
+        result
+    }
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
+    /// Backend name as configured in `runtime.backend`
+    pub fn backend_name(&self) -> &'static str {
+        match self {
+            Runtime::Docker(rt) => rt.capabilities().name,
+            Runtime::Podman(rt) => rt.capabilities().name,
+            Runtime::Bubblewrap(rt) => rt.capabilities().name,
+            Runtime::Kubernetes(rt) => rt.capabilities().name,
+        }
+    }
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    /// Check if a path exists in the container image
+    pub fn path_exists_in_image(&self, image: &str, path: &str) -> Result<bool> {
+        match self {
+            Runtime::Docker(rt) => rt.path_exists_in_image(image, path),
+            Runtime::Podman(rt) => rt.path_exists_in_image(image, path),
+            Runtime::Bubblewrap(rt) => rt.path_exists_in_image(image, path),
+            Runtime::Kubernetes(rt) => rt.path_exists_in_image(image, path),
+        }
+    }
 
-        // Should still have just 1 mount (subdir skipped as covered)
-        assert_eq!(binds.len(), 1);
+    /// The image's default `USER`, empty if it sets none
+    pub fn image_user(&self, image: &str) -> Result<String> {
+        match self {
+            Runtime::Docker(rt) => rt.image_user(image),
+            Runtime::Podman(rt) => rt.image_user(image),
+            Runtime::Bubblewrap(rt) => rt.image_user(image),
+            Runtime::Kubernetes(rt) => rt.image_user(image),
+        }
     }
 
-    #[test]
-    fn test_add_mounts_ro_under_ro_skipped() {
-        // ro mount under ro parent should be skipped
-        let temp_dir = std::env::temp_dir().join(format!("ab_ro_ro_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+    /// Home directory of `user` (or else `uid`) in the image's `/etc/passwd`.
+    /// Lookups are cached per image ID in [`passwd_home_cache_path`].
+    pub fn passwd_home(&self, image: &str, user: &str, uid: u32) -> Result<String> {
+        let backend = self.backend();
+        let key = format!("{} {} {}", backend.image_id(image)?, user, uid);
+        let cache_path = passwd_home_cache_path();
+        let mut cache = cache_path
+            .as_deref()
+            .map(load_passwd_home_cache)
+            .unwrap_or_default();
+        if let Some(home) = cache.get(&key) {
+            return Ok(home.clone());
+        }
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+        let passwd = backend.read_image_file(image, "/etc/passwd")?;
+        let home = parse_passwd_home(&passwd, user, uid).ok_or_else(|| {
+            eyre::eyre!(
+                "No /etc/passwd entry with a home for user '{}' or uid {} in image '{}'",
+                user,
+                uid,
+                image
+            )
+        })?;
+
+        cache.insert(key, home.clone());
+        if let Some(path) = cache_path
+            && let Err(e) = save_passwd_home_cache(&path, &cache)
+        {
+            eprintln!("WARNING: {}", e);
+        }
+        Ok(home)
+    }
 
-        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+    /// List all paths in the container image
+    pub fn list_paths_in_image(&self, image: &str, root_path: Option<&str>) -> Result<Vec<String>> {
+        match self {
+            Runtime::Docker(rt) => rt.list_paths_in_image(image, root_path),
+            Runtime::Podman(rt) => rt.list_paths_in_image(image, root_path),
+            Runtime::Bubblewrap(rt) => rt.list_paths_in_image(image, root_path),
+            Runtime::Kubernetes(rt) => rt.list_paths_in_image(image, root_path),
+        }
+    }
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
+    /// Find the running container spawned for `session` (and `repo`, if given)
+    /// by its session labels. Errors if none or more than one match.
+    pub fn find_container_by_session(&self, session: &str, repo: Option<&str>) -> Result<String> {
+        let mut labels = vec![format!("{}={}", SESSION_LABEL, session)];
+        if let Some(repo) = repo {
+            labels.push(format!("{}={}", REPO_LABEL, repo));
+        }
+        let names = match self {
+            Runtime::Docker(rt) => rt.list_containers_by_labels(&labels)?,
+            Runtime::Podman(rt) => rt.list_containers_by_labels(&labels)?,
+            Runtime::Bubblewrap(rt) => rt.list_containers_by_labels(&labels)?,
+            Runtime::Kubernetes(rt) => rt.list_containers_by_labels(&labels)?,
         };
+        pick_session_container(session, repo, names)
+    }
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
-
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    /// Session containers, running or not, for `ab ps`
+    pub fn list_sessions(&self) -> Result<Vec<session::SessionContainer>> {
+        match self {
+            Runtime::Docker(rt) => rt.list_sessions(),
+            Runtime::Podman(rt) => rt.list_sessions(),
+            Runtime::Bubblewrap(rt) => rt.list_sessions(),
+            Runtime::Kubernetes(rt) => rt.list_sessions(),
+        }
+    }
 
-        // Should still have just 1 mount
-        assert_eq!(binds.len(), 1);
+    /// Stop and remove a container
+    pub fn stop_container(&self, name: &str) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => rt.stop_container(name),
+            Runtime::Podman(rt) => rt.stop_container(name),
+            Runtime::Bubblewrap(rt) => rt.stop_container(name),
+            Runtime::Kubernetes(rt) => rt.stop_container(name),
+        }
     }
 
-    #[test]
-    fn test_add_mounts_rw_under_rw_skipped() {
-        // rw mount under rw parent should be skipped
-        let temp_dir = std::env::temp_dir().join(format!("ab_rw_rw_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+    /// Create an egress proxy's internal network and start its sidecar
+    pub fn start_egress_proxy(
+        &self,
+        proxy: &egress::EgressProxy,
+        image: &str,
+        allow_hosts: &[String],
+    ) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => rt.start_egress_proxy(proxy, image, allow_hosts),
+            Runtime::Podman(rt) => rt.start_egress_proxy(proxy, image, allow_hosts),
+            Runtime::Bubblewrap(rt) => rt.start_egress_proxy(proxy, image, allow_hosts),
+            Runtime::Kubernetes(rt) => rt.start_egress_proxy(proxy, image, allow_hosts),
+        }
+    }
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+    /// Remove an egress proxy's sidecar and internal network
+    pub fn stop_egress_proxy(&self, proxy: &egress::EgressProxy) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => rt.stop_egress_proxy(proxy),
+            Runtime::Podman(rt) => rt.stop_egress_proxy(proxy),
+            Runtime::Bubblewrap(rt) => rt.stop_egress_proxy(proxy),
+            Runtime::Kubernetes(rt) => rt.stop_egress_proxy(proxy),
+        }
+    }
 
-        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+    /// Create a named volume unless it exists
+    pub fn ensure_volume(&self, name: &str) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => rt.ensure_volume(name),
+            Runtime::Podman(rt) => rt.ensure_volume(name),
+            Runtime::Bubblewrap(rt) => rt.ensure_volume(name),
+            Runtime::Kubernetes(rt) => rt.ensure_volume(name),
+        }
+    }
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Rw,
-        };
+    /// Names of the named volumes agent-box created
+    pub fn list_volumes(&self) -> Result<Vec<String>> {
+        match self {
+            Runtime::Docker(rt) => rt.list_volumes(),
+            Runtime::Podman(rt) => rt.list_volumes(),
+            Runtime::Bubblewrap(rt) => rt.list_volumes(),
+            Runtime::Kubernetes(rt) => rt.list_volumes(),
+        }
+    }
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
+    /// Remove a named volume
+    pub fn remove_volume(&self, name: &str) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => rt.remove_volume(name),
+            Runtime::Podman(rt) => rt.remove_volume(name),
+            Runtime::Bubblewrap(rt) => rt.remove_volume(name),
+            Runtime::Kubernetes(rt) => rt.remove_volume(name),
+        }
+    }
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    /// Attach the terminal to a running container
+    pub fn attach_container(&self, name: &str) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => rt.attach_container(name),
+            Runtime::Podman(rt) => rt.attach_container(name),
+            Runtime::Bubblewrap(rt) => rt.attach_container(name),
+            Runtime::Kubernetes(rt) => rt.attach_container(name),
+        }
+    }
 
-        // Should still have just 1 mount
-        assert_eq!(binds.len(), 1);
+    /// Append a detached container's output to the log at `path` until it
+    /// exits, from a process left running in the background that then removes
+    /// the container
+    pub fn capture_logs(&self, name: &str, path: &Path) -> Result<()> {
+        let (program, args) = match self {
+            Runtime::Docker(rt) => rt.log_command(name),
+            Runtime::Podman(rt) => rt.log_command(name),
+            Runtime::Bubblewrap(rt) => rt.log_command(name),
+            Runtime::Kubernetes(rt) => rt.log_command(name),
+        }?;
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        use std::os::unix::process::CommandExt;
+        let file = logs::open_session_log(path, name, started)?;
+        print_command(program, &args);
+        std::process::Command::new(program)
+            .args(&args)
+            .stdin(std::process::Stdio::null())
+            .stdout(file.try_clone()?)
+            .stderr(file)
+            // Its own process group, so a Ctrl-C in the terminal ab ran from
+            // doesn't stop the capture
+            .process_group(0)
+            .spawn()
+            .map_err(|e| eyre::eyre!("Failed to start {}: {}", program, e))?;
+        Ok(())
     }
 
-    #[test]
-    fn test_add_mounts_overlay_under_rw_skipped() {
-        // overlay mount under rw parent should be skipped
-        let temp_dir = std::env::temp_dir().join(format!("ab_o_rw_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+    /// Run `command` in a running container, returning its exit code
+    pub fn exec_in_container(
+        &self,
+        name: &str,
+        command: &[String],
+        env: &[String],
+        workdir: Option<&str>,
+        tty: bool,
+    ) -> Result<i32> {
+        match self {
+            Runtime::Docker(rt) => rt.exec_in_container(name, command, env, workdir, tty),
+            Runtime::Podman(rt) => rt.exec_in_container(name, command, env, workdir, tty),
+            Runtime::Bubblewrap(rt) => rt.exec_in_container(name, command, env, workdir, tty),
+            Runtime::Kubernetes(rt) => rt.exec_in_container(name, command, env, workdir, tty),
+        }
+    }
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+    /// Verify the image exists locally, pulling it according to `policy`
+    pub fn ensure_image(&self, image: &str, policy: PullPolicy) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => ensure_image(rt, image, policy),
+            Runtime::Podman(rt) => ensure_image(rt, image, policy),
+            Runtime::Bubblewrap(rt) => ensure_image(rt, image, policy),
+            Runtime::Kubernetes(rt) => ensure_image(rt, image, policy),
+        }
+    }
 
-        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+    /// Build `build` into the image `tag` if it isn't present locally, or always with `rebuild`
+    pub fn ensure_built(&self, build: &BuildConfig, tag: &str, rebuild: bool) -> Result<()> {
+        match self {
+            Runtime::Docker(rt) => ensure_built(rt, build, tag, rebuild),
+            Runtime::Podman(rt) => ensure_built(rt, build, tag, rebuild),
+            Runtime::Bubblewrap(rt) => ensure_built(rt, build, tag, rebuild),
+            Runtime::Kubernetes(rt) => ensure_built(rt, build, tag, rebuild),
+        }
+    }
+}
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Overlay,
-        };
+/// Policy for pulling the image during the spawn preflight check
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PullPolicy {
+    /// Pull only if the image is not present locally
+    #[default]
+    Missing,
+    /// Always pull before spawning
+    Always,
+    /// Never pull; fail if the image is not present locally
+    Never,
+}
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
+/// Make sure `image` is available locally before spawning, pulling it
+/// according to `policy`.
+fn ensure_image(backend: &dyn ContainerBackend, image: &str, policy: PullPolicy) -> Result<()> {
+    if image.is_empty() {
+        return Err(eyre::eyre!("No image configured (set runtime.image)"));
+    }
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    let exists = backend
+        .image_exists(image)
+        .map_err(|e| eyre::eyre!("Failed to check for image '{}': {}", image, e))?;
+
+    match (exists, policy) {
+        (true, PullPolicy::Missing | PullPolicy::Never) => Ok(()),
+        (false, PullPolicy::Never) => Err(eyre::eyre!(
+            "Image '{}' not found locally and --pull=never was given",
+            image
+        )),
+        (_, PullPolicy::Missing | PullPolicy::Always) => {
+            eprintln!("Pulling image '{}'...", image);
+            backend.pull_image(image)
+        }
+    }
+}
 
-        // Should still have just 1 mount
-        assert_eq!(binds.len(), 1);
+/// Make sure the image `tag` built from `build` exists before spawning. The tag
+/// hashes the build's inputs, so an existing one is up to date unless files in
+/// the context changed, which `rebuild` covers.
+fn ensure_built(
+    backend: &dyn ContainerBackend,
+    build: &BuildConfig,
+    tag: &str,
+    rebuild: bool,
+) -> Result<()> {
+    if !rebuild
+        && backend
+            .image_exists(tag)
+            .map_err(|e| eyre::eyre!("Failed to check for image '{}': {}", tag, e))?
+    {
+        return Ok(());
     }
 
-    #[test]
-    fn test_add_mounts_ro_under_overlay_skipped() {
-        // ro mount under overlay parent should be skipped
-        let temp_dir = std::env::temp_dir().join(format!("ab_ro_o_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+    eprintln!("Building image '{}' from {}...", tag, build.file.display());
+    backend.build_image(build, tag)
+}
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+/// Factory to create the appropriate container runtime
+pub fn create_runtime(config: &Config) -> Runtime {
+    match config.runtime.backend.as_str() {
+        "kubernetes" => Runtime::Kubernetes(kubernetes::KubernetesRuntime::new(
+            config.runtime.kubernetes.clone(),
+        )),
+        backend => backend_runtime(backend),
+    }
+}
 
-        let mut binds = vec![format!("{}:{}:O", temp_dir.display(), temp_dir.display())];
+/// Which backend-specific runtime features a backend supports. Checked when
+/// building and spawning a container instead of matching on the backend name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendCapabilities {
+    /// Backend name, for error messages
+    pub name: &'static str,
+    /// Overlay (`:O`) mounts
+    pub overlay_mounts: bool,
+    /// `--userns keep-id`, mapping the host user to the same uid in the container
+    pub userns_keep_id: bool,
+    /// GPUs requested as CDI devices (`--device nvidia.com/gpu=all`) instead of `--gpus`
+    pub cdi_gpus: bool,
+}
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
-        };
+pub(crate) const PODMAN_CAPABILITIES: BackendCapabilities = BackendCapabilities {
+    name: "podman",
+    overlay_mounts: true,
+    userns_keep_id: true,
+    cdi_gpus: true,
+};
+
+pub(crate) const DOCKER_CAPABILITIES: BackendCapabilities = BackendCapabilities {
+    name: "docker",
+    overlay_mounts: false,
+    userns_keep_id: false,
+    cdi_gpus: false,
+};
+
+pub(crate) const BWRAP_CAPABILITIES: BackendCapabilities = BackendCapabilities {
+    name: "bwrap",
+    overlay_mounts: false,
+    userns_keep_id: false,
+    cdi_gpus: false,
+};
+
+pub(crate) const KUBERNETES_CAPABILITIES: BackendCapabilities = BackendCapabilities {
+    name: "kubernetes",
+    overlay_mounts: false,
+    userns_keep_id: false,
+    cdi_gpus: false,
+};
+
+impl BackendCapabilities {
+    /// Capabilities for a backend name; anything but `podman`, `bwrap`, or
+    /// `kubernetes` is Docker
+    pub fn for_backend(backend: &str) -> Self {
+        match backend {
+            "podman" => PODMAN_CAPABILITIES,
+            "bwrap" => BWRAP_CAPABILITIES,
+            "kubernetes" => KUBERNETES_CAPABILITIES,
+            _ => DOCKER_CAPABILITIES,
+        }
+    }
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
+    /// Error if `supported` is false, naming the feature and this backend
+    pub fn require(&self, supported: bool, feature: &str) -> Result<()> {
+        if supported {
+            return Ok(());
+        }
+        self.unsupported(feature)
+    }
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    /// Error for `feature` (e.g. `"Named volumes are"`), which this backend lacks
+    pub fn unsupported<T>(&self, feature: &str) -> Result<T> {
+        Err(eyre::eyre!(
+            "{} not supported by the {} backend",
+            feature,
+            self.name
+        ))
+    }
 
-        // Should still have just 1 mount
-        assert_eq!(binds.len(), 1);
+    /// Error if the container config uses a feature this backend lacks
+    pub fn check(&self, config: &ContainerConfig) -> Result<()> {
+        let has_overlay = config
+            .mounts
+            .iter()
+            .any(|bind| matches!(parse_bind(bind).mode.parse(), Ok(MountMode::Overlay)));
+        self.require(
+            self.overlay_mounts || !has_overlay,
+            "Overlay (:O) mounts are",
+        )
     }
+}
 
-    #[test]
-    fn test_add_mounts_rw_under_overlay_skipped() {
-        // rw mount under overlay parent should be skipped
-        let temp_dir = std::env::temp_dir().join(format!("ab_rw_o_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+/// Runtime for a backend name; anything but `podman`, `bwrap`, or `kubernetes`
+/// is Docker. Kubernetes uses the current kubeconfig context and namespace.
+fn backend_runtime(backend: &str) -> Runtime {
+    match backend {
+        "podman" => Runtime::Podman(podman::PodmanRuntime::new()),
+        "bwrap" => Runtime::Bubblewrap(bwrap::BubblewrapRuntime::new()),
+        "kubernetes" => Runtime::Kubernetes(kubernetes::KubernetesRuntime::new(Default::default())),
+        _ => Runtime::Docker(docker::DockerRuntime::new()),
+    }
+}
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+/// Rewrite `container` per the first `runtime.mount_remap` rule whose `from` prefix
+/// (compared by whole path components) it falls under; `None` if none match.
+/// A leading `~` in a rule stands for `container_home`.
+pub fn remap_container_path(
+    container: &Path,
+    remaps: &[MountRemap],
+    container_home: &str,
+) -> Option<PathBuf> {
+    let expand = |prefix: &str| match prefix.strip_prefix('~') {
+        Some(rest) => PathBuf::from(format!("{}{}", container_home, rest)),
+        None => PathBuf::from(prefix),
+    };
+    remaps.iter().find_map(|remap| {
+        let rest = container.strip_prefix(expand(&remap.from)).ok()?;
+        let to = expand(&remap.to);
+        Some(if rest.as_os_str().is_empty() {
+            to
+        } else {
+            to.join(rest)
+        })
+    })
+}
 
-        let mut binds = vec![format!("{}:{}:O", temp_dir.display(), temp_dir.display())];
+/// `runtime.container_root_prefix`: a container path that the workspace,
+/// source repo, and home-relative mount targets are placed under
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RootPrefix<'a> {
+    /// Absolute prefix without a trailing slash; `None` leaves paths unchanged
+    pub prefix: Option<&'a str>,
+    /// Also prefix absolute (non-home) mounts
+    pub absolute: bool,
+}
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Rw,
+impl<'a> RootPrefix<'a> {
+    pub fn from_config(runtime: &'a RuntimeConfig) -> Result<Self> {
+        let prefix = match runtime.container_root_prefix.as_deref() {
+            Some(prefix) if !prefix.starts_with('/') => {
+                return Err(eyre::eyre!(
+                    "runtime.container_root_prefix '{}' must be an absolute path",
+                    prefix
+                ));
+            }
+            // `/` is the default root
+            Some(prefix) => Some(prefix.trim_end_matches('/')).filter(|p| !p.is_empty()),
+            None => None,
         };
+        Ok(Self {
+            prefix,
+            absolute: runtime.container_root_prefix_absolute,
+        })
+    }
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
-
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    /// `container` under the prefix. Paths already under it are left alone, so
+    /// a path is never prefixed twice.
+    pub fn apply(&self, container: &Path) -> PathBuf {
+        match self.prefix {
+            Some(prefix) if !container.starts_with(prefix) => {
+                Path::new(prefix).join(container.strip_prefix("/").unwrap_or(container))
+            }
+            _ => container.to_path_buf(),
+        }
+    }
+}
 
-        // Should still have just 1 mount
-        assert_eq!(binds.len(), 1);
+/// Parse mode from string prefix (e.g., "ro:", "rw:", "o:")
+fn parse_mode_prefix(s: &str) -> Option<(MountMode, &str)> {
+    if let Some(rest) = s.strip_prefix("ro:") {
+        Some((MountMode::Ro, rest))
+    } else if let Some(rest) = s.strip_prefix("rw:") {
+        Some((MountMode::Rw, rest))
+    } else if let Some(rest) = s.strip_prefix("o:") {
+        Some((MountMode::Overlay, rest))
+    } else {
+        None
     }
+}
 
-    #[test]
-    fn test_add_mounts_overlay_under_overlay_skipped() {
-        // overlay mount under overlay parent should be skipped
-        let temp_dir = std::env::temp_dir().join(format!("ab_o_o_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+/// Host and container home directories used to translate home-relative mounts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HomeMap {
+    pub host: String,
+    pub container: String,
+}
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+impl HomeMap {
+    /// Default homes: `$HOME` on the host, `/home/$USER` in the container
+    pub fn from_env() -> Result<Self> {
+        let host = std::env::var("HOME")
+            .map_err(|_| eyre::eyre!("Failed to get HOME environment variable"))?;
+        Ok(Self {
+            host,
+            container: format!("/home/{}", env_user_name()),
+        })
+    }
+}
 
-        let mut binds = vec![format!("{}:{}:O", temp_dir.display(), temp_dir.display())];
+/// The user name the container runs as: `$USER`, else `$LOGNAME`, else `user`
+pub fn env_user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "user".to_string())
+}
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Overlay,
-        };
+/// Docs section explaining overlay mounts under Podman's `--userns keep-id`
+const USERNS_OVERLAY_DOC: &str =
+    "docs/src/reference/agent-box/config.md#overlay-mounts-and-podman-keep-id";
 
-        add_mounts(&[&mount], &mut binds, true, &[]).unwrap();
+/// Warnings for Podman overlay mounts whose home-relative translation puts them at a
+/// different container path, where `--userns keep-id` uid mapping often causes
+/// permission errors in the overlay.
+pub fn userns_overlay_warnings(
+    caps: &BackendCapabilities,
+    mounts: &[&Mount],
+    homes: &HomeMap,
+) -> Vec<String> {
+    if !caps.userns_keep_id {
+        return Vec::new();
+    }
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    mounts
+        .iter()
+        .filter(|m| m.mode == MountMode::Overlay && m.home_relative)
+        .filter_map(|m| {
+            let (host, container) = m.resolve_paths(&homes.host, &homes.container).ok()?;
+            (host != container).then(|| {
+                format!(
+                    "WARNING: overlay mount '{}' is translated from {} to {} under podman --userns keep-id; \
+                     files in the overlay may end up with unexpected ownership (see {})",
+                    m.spec, host, container, USERNS_OVERLAY_DOC
+                )
+            })
+        })
+        .collect()
+}
 
-        // Should still have just 1 mount
-        assert_eq!(binds.len(), 1);
+/// Parse a `--home-map HOST_HOME:CONTAINER_HOME` argument
+pub fn parse_home_map(arg: &str) -> Result<HomeMap> {
+    let (host, container) = arg.split_once(':').ok_or_else(|| {
+        eyre::eyre!(
+            "Invalid --home-map '{}': expected HOST_HOME:CONTAINER_HOME",
+            arg
+        )
+    })?;
+
+    for (side, path) in [("host", host), ("container", container)] {
+        if !path.starts_with('/') {
+            return Err(eyre::eyre!(
+                "Invalid --home-map '{}': {} home must be an absolute path",
+                arg,
+                side
+            ));
+        }
     }
 
-    #[test]
-    fn test_add_mounts_rw_under_rw_with_no_skip() {
-        // rw mount under rw parent should NOT be skipped when should_skip=false
-        let temp_dir = std::env::temp_dir().join(format!("ab_rw_rw_noskip_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
-
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+    Ok(HomeMap {
+        host: host.trim_end_matches('/').to_string(),
+        container: container.trim_end_matches('/').to_string(),
+    })
+}
 
-        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
-
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Rw,
-        };
+/// Why mounting `host` writable needs confirmation: it is `/`, `host_home`, or
+/// a directory with at least `threshold` top-level entries as counted by
+/// `count_entries` (`None` for non-directories). `None` when it's fine or
+/// `threshold` is 0.
+pub fn large_mount_reason(
+    host: &Path,
+    host_home: &str,
+    threshold: usize,
+    count_entries: impl Fn(&Path) -> Option<usize>,
+) -> Option<String> {
+    if threshold == 0 {
+        return None;
+    }
+    if host == Path::new("/") {
+        return Some("the root filesystem".to_string());
+    }
+    if !host_home.is_empty() && host == Path::new(host_home) {
+        return Some("your home directory".to_string());
+    }
+    count_entries(host)
+        .filter(|&entries| entries >= threshold)
+        .map(|entries| format!("{}+ entries", entries))
+}
 
-        // Should add even though it's covered, with should_skip=false
-        add_mounts(&[&mount], &mut binds, false, &[]).unwrap();
+/// Parse CLI mount arguments into Mount structs.
+///
+/// Format: `[MODE:]PATH` or `[MODE:]SRC:DST`
+/// - MODE is optional, defaults to "rw"
+/// - Valid modes: "ro", "rw", "o"
+///
+/// Examples:
+/// - `~/data` → mode=rw, spec=~/data
+/// - `ro:~/config` → mode=ro, spec=~/config
+/// - `rw:~/src:/app` → mode=rw, spec=~/src:/app
+pub fn parse_cli_mounts(home_relative: &[String], absolute: &[String]) -> Result<Vec<Mount>> {
+    let mut mounts = Vec::new();
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    for spec in home_relative {
+        mounts.push(parse_single_cli_mount(spec, true)?);
+    }
 
-        // Should have 2 mounts (parent + child)
-        assert_eq!(binds.len(), 2);
+    for spec in absolute {
+        mounts.push(parse_single_cli_mount(spec, false)?);
     }
 
-    #[test]
-    fn test_add_mounts_ro_under_rw_with_no_skip() {
-        // ro mount under rw parent should NOT be skipped when should_skip=false
-        let temp_dir = std::env::temp_dir().join(format!("ab_ro_rw_noskip_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+    Ok(mounts)
+}
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+/// Read-only home-relative mount of the whole host home (`--home-ro`).
+/// More specific mounts still override subpaths through the normal coverage logic.
+pub fn home_ro_mount() -> Mount {
+    Mount {
+        spec: "~".to_string(),
+        home_relative: true,
+        mode: MountMode::Ro,
+        propagation: None,
+        recursive: true,
+    }
+}
 
-        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+/// Parse a single CLI mount argument.
+fn parse_single_cli_mount(arg: &str, home_relative: bool) -> Result<Mount> {
+    // Check for mode prefix (ro:, rw:, o:)
+    let (mode, spec) = match parse_mode_prefix(arg) {
+        Some((mode, rest)) => (mode, rest.to_string()),
+        None => (MountMode::Rw, arg.to_string()),
+    };
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
-        };
+    // Validate the spec is not empty
+    if spec.is_empty() {
+        return Err(eyre::eyre!("Empty mount path after mode prefix: {}", arg));
+    }
 
-        // Should add even though it's covered, with should_skip=false
-        add_mounts(&[&mount], &mut binds, false, &[]).unwrap();
+    // For CLI mounts, allow relative host source paths and resolve them against cwd.
+    // For src:dst, only source is resolved here. Destination is left as-is and
+    // later expanded/validated in Mount::resolve_paths (must be absolute or ~).
+    let spec = if let Some((src, dst)) = spec.split_once(':') {
+        if src.starts_with('/') || src.starts_with('~') {
+            spec
+        } else {
+            let cwd = std::env::current_dir()
+                .map_err(|e| eyre::eyre!("Failed to resolve current directory: {}", e))?;
+            let abs_src = cwd.join(src).to_string_lossy().to_string();
+            format!("{}:{}", abs_src, dst)
+        }
+    } else if spec.starts_with('/') || spec.starts_with('~') {
+        spec
+    } else {
+        let cwd = std::env::current_dir()
+            .map_err(|e| eyre::eyre!("Failed to resolve current directory: {}", e))?;
+        cwd.join(&spec).to_string_lossy().to_string()
+    };
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+    // Validate path format (source side must now be absolute or home-relative)
+    let path_to_check = if spec.contains(':') {
+        // For src:dst format, check the src part
+        spec.split(':').next().unwrap()
+    } else {
+        &spec
+    };
 
-        // Should have 2 mounts (parent + child)
-        assert_eq!(binds.len(), 2);
+    if !path_to_check.starts_with('/') && !path_to_check.starts_with('~') {
+        return Err(eyre::eyre!(
+            "Mount path must be absolute (/...) or home-relative (~/...): {}",
+            arg
+        ));
     }
 
-    #[test]
-    fn test_add_mounts_rw_under_ro_with_no_skip() {
-        // rw under ro should add when should_skip=false
-        let temp_dir = std::env::temp_dir().join(format!("ab_rw_ro_noskip_{}", std::process::id()));
-        let subdir = temp_dir.join("subdir");
+    Ok(Mount {
+        spec,
+        home_relative,
+        mode,
+        propagation: None,
+        recursive: true,
+    })
+}
 
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&subdir).unwrap();
+/// Resolve a file-backed env entry: `KEY=@/path` (or `KEY=@~/path`) becomes
+/// `KEY=<trimmed file contents>`. Other entries are returned unchanged.
+pub fn resolve_env_entry(entry: &str, host_home: Option<&str>) -> Result<String> {
+    let Some((key, path)) = entry
+        .split_once('=')
+        .and_then(|(key, value)| Some((key, value.strip_prefix('@')?)))
+    else {
+        return Ok(entry.to_string());
+    };
 
-        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+    let path = match path.strip_prefix('~') {
+        Some(rest) => {
+            let home = host_home.ok_or_else(|| {
+                eyre::eyre!("Cannot expand '~' in env file for {}: HOME is not set", key)
+            })?;
+            format!("{}{}", home, rest)
+        }
+        None => path.to_string(),
+    };
 
-        let mount = Mount {
-            spec: subdir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Rw,
-        };
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| eyre::eyre!("Failed to read env file for {} ({}): {}", key, path, e))?;
 
-        // Should add even though it's covered, when should_skip=false
-        add_mounts(&[&mount], &mut binds, false, &[]).unwrap();
+    Ok(format!("{}={}", key, contents.trim()))
+}
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+/// Run a `secret_env` credential helper and return its stdout without trailing
+/// newlines. The helper's stdin and stderr stay attached so it can prompt
+/// (e.g. for a GPG passphrase).
+pub fn run_secret_helper(key: &str, helper: &SecretHelper) -> Result<String> {
+    let output = std::process::Command::new(&helper.helper)
+        .args(&helper.args)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| {
+            eyre::eyre!(
+                "Failed to run secret helper '{}' for {}: {}",
+                helper.helper,
+                key,
+                e
+            )
+        })?;
+
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "Secret helper '{}' for {} failed ({})",
+            helper.helper,
+            key,
+            output.status
+        ));
+    }
 
-        // Should have 2 mounts (parent + child)
-        assert_eq!(binds.len(), 2);
+    let value = String::from_utf8(output.stdout).map_err(|_| {
+        eyre::eyre!(
+            "Secret helper '{}' for {} printed non-UTF-8 output",
+            helper.helper,
+            key
+        )
+    })?;
+    let value = value.trim_end_matches(['\r', '\n']);
+    if value.is_empty() {
+        return Err(eyre::eyre!(
+            "Secret helper '{}' for {} printed nothing",
+            helper.helper,
+            key
+        ));
     }
+    Ok(value.to_string())
+}
 
-    #[test]
-    fn test_mount_equality_same_spec() {
-        let m1 = Mount {
-            spec: "~/.config".to_string(),
-            home_relative: true,
-            mode: MountMode::Ro,
-        };
-        let m2 = Mount {
-            spec: "~/.config".to_string(),
-            home_relative: true,
-            mode: MountMode::Ro,
-        };
-        assert_eq!(m1, m2);
+/// `KEY=value` entries for `secret_env`, with each value fetched by `run`
+pub fn resolve_secret_env(
+    secrets: &BTreeMap<String, SecretHelper>,
+    mut run: impl FnMut(&str, &SecretHelper) -> Result<String>,
+) -> Result<Vec<String>> {
+    secrets
+        .iter()
+        .map(|(key, helper)| Ok(format!("{}={}", key, run(key, helper)?)))
+        .collect()
+}
+
+/// Whether `ab spawn --session` should create the session workspace first.
+///
+/// `--new` always creates. Otherwise an existing workspace is used as is, and a
+/// missing one is handled by `policy`; `prompt` asks via `confirm` only when
+/// `interactive`, and fails otherwise.
+pub fn should_create_workspace(
+    policy: OnMissingWorkspace,
+    force_new: bool,
+    workspace: &Path,
+    interactive: bool,
+    confirm: impl FnOnce() -> Result<bool>,
+) -> Result<bool> {
+    if force_new {
+        return Ok(true);
+    }
+    if workspace.exists() {
+        return Ok(false);
     }
 
-    #[test]
-    fn test_mount_equality_different_mode() {
-        let m1 = Mount {
-            spec: "~/.config".to_string(),
-            home_relative: true,
-            mode: MountMode::Ro,
-        };
-        let m2 = Mount {
-            spec: "~/.config".to_string(),
-            home_relative: true,
-            mode: MountMode::Rw,
-        };
-        assert_ne!(m1, m2);
+    let missing = || {
+        format!(
+            "Workspace {} does not exist; pass --new to create it \
+             (or set runtime.on_missing_workspace = \"create\")",
+            workspace.display()
+        )
+    };
+    match policy {
+        OnMissingWorkspace::Create => Ok(true),
+        OnMissingWorkspace::Error => Err(eyre::eyre!(missing())),
+        OnMissingWorkspace::Prompt if !interactive => {
+            Err(eyre::eyre!("{} (not prompting: no terminal)", missing()))
+        }
+        OnMissingWorkspace::Prompt => {
+            if confirm()? {
+                Ok(true)
+            } else {
+                Err(eyre::eyre!("{}", missing()))
+            }
+        }
     }
+}
 
-    #[test]
-    fn test_mount_equality_equivalent_paths() {
-        // These resolve to the same paths, so should be equal
-        let m1 = Mount {
-            spec: "/nix/store".to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
-        };
-        let m2 = Mount {
-            spec: "/nix/store".to_string(),
-            home_relative: true, // different flag, but resolves same
-            mode: MountMode::Ro,
-        };
-        assert_eq!(m1, m2);
+/// Fail with a hint if the session workspace of the requested type is missing
+/// but one of the other type (`other`) exists, e.g. `--git` for a jj session.
+pub fn check_workspace_type(
+    requested: WorkspaceType,
+    session: &str,
+    workspace: &Path,
+    other: &Path,
+) -> Result<()> {
+    if workspace.exists() || !other.exists() {
+        return Ok(());
     }
+    let (wanted, found, flag) = match requested {
+        WorkspaceType::Git => ("git", "jj", "--jj"),
+        WorkspaceType::Jj => ("jj", "git", "--git"),
+    };
+    Err(eyre::eyre!(
+        "requested {} workspace for session '{}' but only a {} workspace exists \
+         ({}); use {} or create it with --new",
+        wanted,
+        session,
+        found,
+        other.display(),
+        flag
+    ))
+}
 
-    // CLI mount parsing tests
+/// Values substituted for `{session}`, `{repo}` and `{workspace}` in `--command`
+/// tokens and `--entrypoint`. `session` and `repo` are unset in `--local` mode.
+#[derive(Debug, Clone, Copy)]
+pub struct SpawnPlaceholders<'a> {
+    pub session: Option<&'a str>,
+    pub repo: Option<&'a str>,
+    pub workspace: &'a Path,
+}
 
-    #[test]
-    fn test_parse_cli_mount_no_mode_defaults_to_rw() {
-        let m = parse_single_cli_mount("~/data", true).unwrap();
-        assert_eq!(m.mode, MountMode::Rw);
-        assert_eq!(m.spec, "~/data");
-        assert!(m.home_relative);
-    }
+impl SpawnPlaceholders<'_> {
+    /// Expand the placeholders in `input` on the host, before any shell sees it.
+    /// Values are inserted verbatim; other `{...}` text is left alone.
+    pub fn expand(&self, input: &str) -> Result<String> {
+        let workspace = self.workspace.to_string_lossy();
+        let placeholders = [
+            ("{session}", self.session),
+            ("{repo}", self.repo),
+            ("{workspace}", Some(workspace.as_ref())),
+        ];
 
-    #[test]
-    fn test_parse_cli_mount_absolute_no_mode() {
-        let m = parse_single_cli_mount("/nix/store", false).unwrap();
-        assert_eq!(m.mode, MountMode::Rw);
-        assert_eq!(m.spec, "/nix/store");
-        assert!(!m.home_relative);
-    }
+        let mut expanded = String::with_capacity(input.len());
+        let mut rest = input;
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match placeholders.iter().find(|(p, _)| rest.starts_with(p)) {
+                Some((placeholder, value)) => {
+                    let value = value.ok_or_else(|| {
+                        eyre::eyre!("Placeholder {} is not available with --local", placeholder)
+                    })?;
+                    expanded.push_str(value);
+                    rest = &rest[placeholder.len()..];
+                }
+                None => {
+                    expanded.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        expanded.push_str(rest);
 
-    #[test]
-    fn test_parse_cli_mount_ro_mode() {
-        let m = parse_single_cli_mount("ro:~/.config/git", true).unwrap();
-        assert_eq!(m.mode, MountMode::Ro);
-        assert_eq!(m.spec, "~/.config/git");
-        assert!(m.home_relative);
+        Ok(expanded)
     }
+}
 
-    #[test]
-    fn test_parse_cli_mount_rw_mode() {
-        let m = parse_single_cli_mount("rw:~/data", true).unwrap();
-        assert_eq!(m.mode, MountMode::Rw);
-        assert_eq!(m.spec, "~/data");
-    }
+/// Parse `/etc/hosts` content into `(hostname, ip)` pairs, one per name (aliases
+/// included), skipping comments and blank lines.
+pub fn parse_etc_hosts(contents: &str) -> Vec<(String, String)> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let ip = fields.next()?;
+            Some(fields.map(move |name| (name.to_string(), ip.to_string())))
+        })
+        .flatten()
+        .collect()
+}
 
-    #[test]
-    fn test_parse_cli_mount_overlay_mode() {
-        let m = parse_single_cli_mount("o:~/.gnupg", true).unwrap();
-        assert_eq!(m.mode, MountMode::Overlay);
-        assert_eq!(m.spec, "~/.gnupg");
-    }
+/// `HOST:IP` entries for `/etc/hosts` names matching any of the glob `patterns`.
+/// Names already mapped in `existing` (or earlier in the file) are skipped.
+pub fn import_hosts(
+    contents: &str,
+    patterns: &[String],
+    existing: &[String],
+) -> Result<Vec<String>> {
+    let patterns = patterns
+        .iter()
+        .map(|p| {
+            GlobPattern::new(p)
+                .map_err(|e| eyre::eyre!("Invalid --import-hosts pattern '{}': {}", p, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    #[test]
-    fn test_parse_cli_mount_with_src_dst() {
-        let m = parse_single_cli_mount("ro:~/src:/app", true).unwrap();
-        assert_eq!(m.mode, MountMode::Ro);
-        assert_eq!(m.spec, "~/src:/app");
+    let mut seen: HashSet<String> = existing
+        .iter()
+        .filter_map(|h| h.split_once(':').map(|(name, _)| name.to_string()))
+        .collect();
+
+    Ok(parse_etc_hosts(contents)
+        .into_iter()
+        .filter(|(name, _)| patterns.iter().any(|p| p.matches(name)))
+        .filter(|(name, _)| seen.insert(name.clone()))
+        .map(|(name, ip)| format!("{}:{}", name, ip))
+        .collect())
+}
+
+/// Nameservers and search domains from the host's `/etc/resolv.conf`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HostDns {
+    pub nameservers: Vec<String>,
+    pub search: Vec<String>,
+}
+
+/// Parse `/etc/resolv.conf` content, skipping comments (`#` or `;`), `options`,
+/// and unknown directives. As in the resolver, the last `search` or `domain`
+/// line sets the search list.
+pub fn parse_resolv_conf(contents: &str) -> HostDns {
+    let mut dns = HostDns::default();
+    for line in contents.lines() {
+        let line = line.split(['#', ';']).next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => dns.nameservers.extend(fields.next().map(str::to_string)),
+            Some("search") => dns.search = fields.map(str::to_string).collect(),
+            Some("domain") => dns.search = fields.next().map(str::to_string).into_iter().collect(),
+            _ => {}
+        }
     }
+    dns
+}
 
-    #[test]
-    fn test_parse_cli_mount_relative_src_with_dst_resolves_against_cwd() {
-        let cwd = std::env::current_dir().unwrap();
-        let m = parse_single_cli_mount("ro:../pierre:/app", true).unwrap();
-        assert_eq!(m.mode, MountMode::Ro);
-        assert_eq!(
-            m.spec,
-            format!("{}:/app", cwd.join("../pierre").to_string_lossy())
+/// `--dns-from-host`: use the host's nameservers and search domains in place of
+/// `runtime.dns`. Loopback nameservers (e.g. systemd-resolved's `127.0.0.53`)
+/// aren't reachable from the container and are skipped.
+pub fn apply_host_dns(config: &mut ContainerConfig, host: HostDns) {
+    let (loopback, nameservers): (Vec<String>, Vec<String>) =
+        host.nameservers.into_iter().partition(|ns| {
+            ns.parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+        });
+    if !loopback.is_empty() {
+        eprintln!(
+            "WARNING: --dns-from-host: skipping loopback nameserver(s) {}",
+            loopback.join(", ")
         );
     }
+    if nameservers.is_empty() {
+        eprintln!("WARNING: --dns-from-host: no usable host nameservers, keeping runtime.dns");
+    } else {
+        config.dns = nameservers;
+    }
+    config.dns_search = host.search;
+}
 
-    #[test]
+/// `--ephemeral`: point the bind of `from` at `to`, keeping its container path
+/// and mode
+pub fn replace_bind_source(mounts: &mut [String], from: &Path, to: &Path) -> Result<()> {
+    let prefix = format!("{}:", from.display());
+    let bind = mounts
+        .iter_mut()
+        .find(|m| m.starts_with(&prefix))
+        .ok_or_else(|| eyre::eyre!("No bind of {} to replace", from.display()))?;
+    *bind = format!("{}:{}", to.display(), &bind[prefix.len()..]);
+    Ok(())
+}
+
+/// Env names agent-box itself sets in the container
+const INJECTED_ENV: &[&str] = &["USER", "HOME", "AGENT_PORTAL_SOCKET"];
+
+/// `--clean-env` guard: every entry in `env` must be agent-box-injected or come
+/// from the config/profile `env` list (or `secret_env`), so nothing leaks in from
+/// the host shell.
+pub fn check_clean_env(env: &[String], profile_env: &[String]) -> Result<()> {
+    let key = |entry: &str| entry.split_once('=').map_or(entry, |(k, _)| k).to_string();
+    let allowed: HashSet<String> = INJECTED_ENV
+        .iter()
+        .map(|k| k.to_string())
+        .chain(profile_env.iter().map(|e| key(e)))
+        .collect();
+
+    let stray: Vec<String> = env
+        .iter()
+        .map(|e| key(e))
+        .filter(|k| !allowed.contains(k))
+        .collect();
+    if !stray.is_empty() {
+        return Err(eyre::eyre!(
+            "--clean-env: unexpected env vars in container env: {}",
+            stray.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Environment variable that overrides `runtime.image` for `ab spawn`
+pub const IMAGE_ENV: &str = "AGENT_BOX_IMAGE";
+
+/// Image to spawn: `--image`, then `AGENT_BOX_IMAGE` (read via `lookup`), then
+/// `runtime.image`. Empty values count as unset.
+pub fn select_image(
+    cli_image: Option<&str>,
+    lookup: impl Fn(&str) -> Option<String>,
+    config_image: &str,
+) -> String {
+    cli_image
+        .filter(|image| !image.is_empty())
+        .map(str::to_string)
+        .or_else(|| lookup(IMAGE_ENV).filter(|image| !image.is_empty()))
+        .unwrap_or_else(|| config_image.to_string())
+}
+
+/// `NAME=value` entries for host variables listed in `env_passthrough`, read via `lookup`.
+/// With a `prefix`, each is emitted as `PREFIX_NAME=value` instead so it can't clobber
+/// the container's own variables. Variables missing on the host are skipped with a warning.
+pub fn passthrough_env(
+    names: &[String],
+    prefix: Option<&str>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Vec<String> {
+    names
+        .iter()
+        .filter_map(|name| {
+            let Some(value) = lookup(name) else {
+                eprintln!(
+                    "WARNING: env_passthrough variable '{}' not found in host environment",
+                    name
+                );
+                return None;
+            };
+            Some(match prefix {
+                Some(prefix) => format!("{}_{}={}", prefix.trim_end_matches('_'), name, value),
+                None => format!("{}={}", name, value),
+            })
+        })
+        .collect()
+}
+
+/// Build container configuration from workspace and source paths
+/// - workspace_path: the directory to mount as working directory
+/// - source_path: the source repo to mount .git/.jj from
+/// - local: if true, workspace and source are the same, so don't double-mount
+/// - ro: if true, mount workspace path as read-only
+/// - clean_env: if true, inherit nothing from the host env (no TERM/COLORTERM/TERMINFO,
+///   no env_passthrough); only USER/HOME, config/profile env and the portal socket remain
+/// - resolved_profile: resolved mounts, env, ports, and hosts from profile resolution
+/// - cli_mounts: additional mounts from CLI arguments
+/// - cli_ports: additional port mappings from CLI arguments
+/// - cli_hosts: additional host entries from CLI arguments
+/// - cli_groups: additional supplementary groups from CLI arguments
+/// - command: command arguments to pass to the container entrypoint
+/// - should_skip: if true, skip mounts that are already covered by parent mounts
+/// - network: optional network mode (e.g. "host", "bridge", "none"); falls back to the
+///   resolved profile's network
+/// - image_workdir: if set, skip the workspace bind and start in this in-image directory
+/// - home_map: override the host/container homes used for home-relative mounts
+/// - mount_cache: per-spawn mount resolutions, shared with profile resolution
+#[allow(clippy::too_many_arguments)]
+pub fn build_container_config(
+    config: &Config,
+    workspace_path: &Path,
+    source_path: &Path,
+    local: bool,
+    ro: bool,
+    source_ro: bool,
+    clean_env: bool,
+    entrypoint_override: Option<&str>,
+    resolved_profile: &ResolvedProfile,
+    cli_mounts: &[Mount],
+    cli_ports: &[String],
+    cli_hosts: &[String],
+    cli_groups: &[String],
+    portal_socket_override: Option<&Path>,
+    command: Option<Vec<String>>,
+    should_skip: bool,
+    network: Option<String>,
+    image_workdir: Option<&str>,
+    home_map: Option<&HomeMap>,
+    mount_cache: &mut MountResolutionCache,
+) -> Result<ContainerConfig> {
+    let pb_to_str = |pb: &Path| {
+        pb.canonicalize()
+            .unwrap_or_else(|_| panic!("couldnt canonicalize: {pb:?}"))
+            .to_string_lossy()
+            .to_string()
+    };
+
+    /// Format a mount as bind string (host:container:mode)
+    pub fn format_bind(host_path: &Path, container_path: &Path, mode: MountMode) -> String {
+        format!(
+            "{}:{}:{}",
+            host_path.display(),
+            container_path.display(),
+            mode.as_str()
+        )
+    }
+
+    let root_prefix = RootPrefix::from_config(&config.runtime)?;
+
+    // With an in-image workdir the code lives in the image, so the workspace isn't bound
+    let mut binds = Vec::new();
+    let working_dir = match image_workdir {
+        Some(dir) => dir.to_string(),
+        None => {
+            let workspace_mode = if ro { MountMode::Ro } else { MountMode::Rw };
+            binds.push(format_bind(
+                workspace_path,
+                &root_prefix.apply(workspace_path),
+                workspace_mode,
+            ));
+            root_prefix
+                .apply(Path::new(&pb_to_str(workspace_path)))
+                .to_string_lossy()
+                .to_string()
+        }
+    };
+
+    // Mount source repo's .git and .jj directories only if not local
+    // (in local mode, workspace IS the source, so they're already included).
+    // Colocated workspaces carry their own .git and .jj, so skip them too.
+    // With source_ro the repo store is read-only while the workspace stays writable.
+    let source_mode = if source_ro {
+        MountMode::Ro
+    } else {
+        MountMode::Rw
+    };
+    if !local && !is_colocated_workspace(workspace_path) {
+        let source_git = source_path.join(".git");
+        let source_jj = source_path.join(".jj");
+
+        if source_git.exists() {
+            binds.push(format_bind(
+                &source_git,
+                &root_prefix.apply(&source_git),
+                source_mode,
+            ));
+        }
+        if source_jj.exists() {
+            binds.push(format_bind(
+                &source_jj,
+                &root_prefix.apply(&source_jj),
+                source_mode,
+            ));
+        }
+    }
+
+    // A linked worktree's .git is just a file pointing into the main repo,
+    // so also mount the common git dir holding the object store and refs.
+    if let Some(common_dir) = linked_worktree_common_dir(source_path) {
+        binds.push(format_bind(
+            &common_dir,
+            &root_prefix.apply(&common_dir),
+            source_mode,
+        ));
+    }
+
+    // Combine profile mounts and CLI mounts
+    let all_mounts: Vec<&Mount> = resolved_profile
+        .mounts
+        .iter()
+        .chain(cli_mounts.iter())
+        .collect();
+
+    // Check for overlay mounts and validate backend
+    let has_overlay = all_mounts.iter().any(|m| m.mode == MountMode::Overlay);
+    let caps = BackendCapabilities::for_backend(&config.runtime.backend);
+    caps.require(
+        caps.overlay_mounts || !has_overlay,
+        "Overlay (:O) mounts are",
+    )?;
+
+    let mut mount_summary = add_mounts(
+        &all_mounts,
+        &mut binds,
+        should_skip,
+        &config.runtime.skip_mounts,
+        &config.runtime.mount_remap,
+        root_prefix,
+        home_map,
+        config.runtime.large_mount_warn_entries,
+        mount_cache,
+    )?;
+    mount_summary.check_max_mounts(binds.len(), config.runtime.max_mounts)?;
+
+    let uid = nix::unistd::getuid().as_raw();
+    let gid = nix::unistd::getgid().as_raw();
+
+    let username = env_user_name();
+
+    let entrypoint = entrypoint_override
+        .map(|s| vec![s.to_string()])
+        .or_else(|| config.runtime.entrypoint.clone());
+
+    // --command wins over the profile/runtime default
+    let command = command.or_else(|| resolved_profile.command.clone());
+    let network = network.or_else(|| resolved_profile.network.clone());
+    if !resolved_profile.allow_hosts.is_empty()
+        && let Some(mode) = network.as_deref()
+        && mode != "bridge"
+    {
+        eyre::bail!(
+            "network.allow_hosts needs the bridge network, not '{}'",
+            mode
+        );
+    }
+
+    let container_home = home_map
+        .map(|homes| homes.container.clone())
+        .unwrap_or_else(|| format!("/home/{}", username));
+
+    let mut env = vec![
+        format!("USER={}", username),
+        format!("HOME={}", container_home),
+    ];
+
+    if !clean_env {
+        for var_name in ["TERM", "COLORTERM"] {
+            if let Ok(value) = std::env::var(var_name) {
+                env.push(format!("{}={}", var_name, value));
+            }
+        }
+    }
+    // Use env from resolved profile (includes runtime.env + profile envs),
+    // loading `KEY=@/path` values from files
+    let host_home = std::env::var("HOME").ok();
+    for entry in &resolved_profile.env {
+        env.push(resolve_env_entry(entry, host_home.as_deref())?);
+    }
+    let mut secret_env = resolve_secret_env(&config.runtime.secret_env, run_secret_helper)?;
+    let (profile_secret_env, secret_files) = secrets::resolve_profile_secrets(
+        &resolved_profile.secrets,
+        host_home.as_deref(),
+        run_secret_helper,
+    )?;
+    secret_env.extend(profile_secret_env);
+    env.extend(secret_env.iter().cloned());
+
+    // Pass through specified environment variables from host
+    if clean_env {
+        if !resolved_profile.env_passthrough.is_empty() {
+            eprintln!(
+                "WARNING: --clean-env ignores env_passthrough: {}",
+                resolved_profile.env_passthrough.join(", ")
+            );
+        }
+    } else {
+        env.extend(passthrough_env(
+            &resolved_profile.env_passthrough,
+            None,
+            |name| std::env::var(name).ok(),
+        ));
+    }
+
+    if !clean_env
+        && let Ok(term) = std::env::var("TERM")
+        && let Some(terminfo_dir) = find_terminfo_dir(&term)
+    {
+        binds.push(format_bind(&terminfo_dir, &terminfo_dir, MountMode::Ro));
+        env.push(format!("TERMINFO={}", terminfo_dir.display()));
+    }
+
+    // Mount portal socket into container (if enabled and socket exists)
+    if config.portal.enabled {
+        let socket_path = if config.portal.global {
+            Some(std::path::PathBuf::from(&config.portal.socket_path))
+        } else {
+            portal_socket_override.map(std::path::PathBuf::from)
+        };
+
+        if let Some(socket_path) = socket_path {
+            if socket_path.exists() {
+                binds.push(format_bind(&socket_path, &socket_path, MountMode::Rw));
+                env.push(format!("AGENT_PORTAL_SOCKET={}", socket_path.display()));
+            } else {
+                eprintln!(
+                    "WARNING: portal is enabled but socket does not exist yet: {}",
+                    socket_path.display()
+                );
+            }
+        }
+    }
+
+    if clean_env {
+        check_clean_env(&env, &[resolved_profile.env.clone(), secret_env].concat())?;
+    }
+
+    // Combine profile ports and CLI ports, deduplicate (first occurrence wins)
+    let mut all_ports: Vec<String> = resolved_profile.ports.clone();
+    all_ports.extend(cli_ports.iter().cloned());
+    let mut seen_ports = HashSet::new();
+    all_ports.retain(|p| seen_ports.insert(p.clone()));
+
+    // Combine profile hosts and CLI hosts, deduplicate (first occurrence wins)
+    let mut all_hosts: Vec<String> = resolved_profile.hosts.clone();
+    all_hosts.extend(cli_hosts.iter().cloned());
+    let mut seen_hosts = HashSet::new();
+    all_hosts.retain(|h| seen_hosts.insert(h.clone()));
+
+    // Combine config groups and CLI groups, deduplicate (first occurrence wins)
+    let mut all_groups: Vec<String> = config.runtime.group_add.clone();
+    all_groups.extend(cli_groups.iter().cloned());
+    let mut seen_groups = HashSet::new();
+    all_groups.retain(|g| seen_groups.insert(g.clone()));
+
+    // Create context file if context is not empty
+    if !resolved_profile.context.is_empty() {
+        // Create a temporary file with a unique name
+        let context_content = resolved_profile.context.join("\n");
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let pid = std::process::id();
+        let context_file_path = format!("/tmp/agent-box-context-{}-{}", timestamp, pid);
+
+        // Write context to file (this automatically closes and flushes)
+        std::fs::write(&context_file_path, context_content.as_bytes())
+            .map_err(|e| eyre::eyre!("Failed to write context file: {}", e))?;
+
+        eprintln!("DEBUG: Created context file at {}", context_file_path);
+        eprintln!("DEBUG: Context content:\n{}", context_content);
+
+        // Expand ~ in context_path to container home directory
+        let container_context_path = if config.context_path.starts_with("~/") {
+            config.context_path.replacen("~", &container_home, 1)
+        } else if config.context_path == "~" {
+            container_home.clone()
+        } else {
+            config.context_path.clone()
+        };
+
+        // Add mount for context file (read-write so user can edit)
+        binds.push(format!(
+            "{}:{}:rw",
+            context_file_path, container_context_path
+        ));
+    }
+
+    mount_summary.count_binds(&binds);
+    let tmpfs: Vec<String> = resolved_profile
+        .tmpfs
+        .iter()
+        .map(|path| match path.strip_prefix('~') {
+            Some(rest) => format!("{}{}", container_home, rest),
+            None => path.clone(),
+        })
+        .collect();
+    mount_summary.tmpfs = tmpfs.len();
+    let volumes = resolved_profile
+        .volumes
+        .iter()
+        .map(|spec| {
+            let volume: VolumeSpec = spec.parse().map_err(|e: String| eyre::eyre!(e))?;
+            let path = match volume.path.strip_prefix('~') {
+                Some(rest) => format!("{}{}", container_home, rest),
+                None => volume.path,
+            };
+            Ok(format!("{}:{}:{}", volume.name, path, volume.mode))
+        })
+        .collect::<Result<Vec<String>>>()?;
+    mount_summary.volumes = volumes.len();
+    let gpus = match &resolved_profile.gpu {
+        Some(gpu) => gpu.devices().map_err(|e| eyre::eyre!(e))?,
+        None => Vec::new(),
+    };
+
+    Ok(ContainerConfig {
+        name: derive_container_name(config, workspace_path, local),
+        image: config.runtime.image.clone(),
+        entrypoint,
+        command,
+        user: format!("{}:{}", uid, gid),
+        group_add: all_groups,
+        working_dir,
+        mounts: binds,
+        env,
+        ports: all_ports,
+        hosts: all_hosts,
+        network,
+        resources: resolved_profile.resources.clone(),
+        labels: Vec::new(),
+        dns: config.runtime.dns.clone(),
+        dns_search: Vec::new(),
+        mount_syntax: config.runtime.mount_syntax,
+        copy_in: Vec::new(),
+        redact_env: config
+            .runtime
+            .redact_env
+            .iter()
+            .chain(config.runtime.secret_env.keys())
+            .chain(resolved_profile.secrets.keys())
+            .cloned()
+            .collect(),
+        mount_summary,
+        detach: false,
+        secret_files,
+        allow_hosts: resolved_profile.allow_hosts.clone(),
+        tmpfs,
+        volumes,
+        gpus,
+        gpu_cdi_kind: config.runtime.gpu.cdi_kind.clone(),
+        security: config.runtime.security.clone(),
+    })
+}
+
+/// Check if a path is covered by any existing mount (exact match or subpath).
+/// Returns Some(existing_mode) if covered, None if not covered.
+fn find_covering_mount<'a>(
+    host_path: &Path,
+    existing_mounts: &'a [ResolvedMount],
+) -> Option<&'a ResolvedMount> {
+    for mount in existing_mounts {
+        // Exact match - already mounted
+        if host_path == mount.host {
+            return Some(mount);
+        }
+
+        // Check if new path is under existing mount
+        if host_path.starts_with(&mount.host) {
+            return Some(mount);
+        }
+    }
+    None
+}
+
+/// Add mounts to the binds vector.
+/// Handles symlinks by mounting the entire symlink chain.
+/// Skips paths that are already covered by a parent mount (unless should_skip is false).
+/// Skips paths that match configured skip_mounts patterns (supports globs).
+/// `home_map` overrides the host/container homes used for home-relative translation.
+/// Resolutions are memoized in `cache`, so a spec repeated across profiles is resolved once.
+///
+/// Mount mode behavior (existing parent → new child):
+///
+/// | Parent | Child | Action |
+/// |--------|-------|--------|
+/// | ro     | ro    | Skip (covered) [unless --no-skip] |
+/// | ro     | rw    | Skip (covered, child mount overrides parent) [unless --no-skip] |
+/// | ro     | O     | Skip (covered) [unless --no-skip] |
+/// | rw     | ro    | Skip (covered, ro ⊆ rw) [unless --no-skip] |
+/// | rw     | rw    | Skip (covered) [unless --no-skip] |
+/// | rw     | O     | Skip (covered) [unless --no-skip] |
+/// | O      | ro    | Skip (covered) [unless --no-skip] |
+/// | O      | rw    | Skip (covered) [unless --no-skip] |
+/// | O      | O     | Skip (covered) [unless --no-skip] |
+#[allow(clippy::too_many_arguments)]
+fn add_mounts(
+    mounts: &[&Mount],
+    binds: &mut Vec<String>,
+    should_skip: bool,
+    skip_patterns: &[String],
+    remaps: &[MountRemap],
+    root_prefix: RootPrefix,
+    home_map: Option<&HomeMap>,
+    large_mount_warn_entries: usize,
+    cache: &mut MountResolutionCache,
+) -> Result<MountSummary> {
+    let mut summary = MountSummary::default();
+
+    // Parse existing binds into resolved mounts for coverage checking
+    let mut existing_resolved: Vec<ResolvedMount> = binds
+        .iter()
+        .filter(|b| b.matches(':').count() >= 2)
+        .map(|b| {
+            let parts = parse_bind(b);
+            ResolvedMount {
+                host: PathBuf::from(parts.host),
+                container: PathBuf::from(parts.container),
+                mode: parts.mode.parse().unwrap_or(MountMode::Rw),
+                propagation: parts.propagation.and_then(|p| p.parse().ok()),
+                recursive: parts.recursive,
+            }
+        })
+        .collect();
+
+    // First, resolve all mounts and collect them
+    let mut all_resolved: Vec<ResolvedMount> = Vec::new();
+    let mut host_home = String::new();
+    if !mounts.is_empty() {
+        let homes = match home_map {
+            Some(homes) => homes.clone(),
+            None => HomeMap::from_env()?,
+        };
+        host_home = homes.host.clone();
+        for mount in mounts {
+            // resolve_chain handles existence check and symlink chain
+            let mut chain = cache.resolve_chain(mount, &homes.host, &homes.container)?;
+            for resolved in &mut chain {
+                if let Some(container) =
+                    remap_container_path(&resolved.container, remaps, &homes.container)
+                {
+                    resolved.container = container;
+                }
+                // Home-relative translations go under the root prefix; other
+                // paths (absolute mounts, symlink targets) only when asked
+                let translated =
+                    mount.home_relative && resolved.container.starts_with(&homes.container);
+                if translated || root_prefix.absolute {
+                    resolved.container = root_prefix.apply(&resolved.container);
+                }
+            }
+            if chain.is_empty() {
+                summary.filtered += 1;
+                summary.dropped.push(format!("{} (missing)", mount.spec));
+            }
+            if summary
+                .largest_expansion
+                .as_ref()
+                .is_none_or(|(_, paths)| chain.len() > *paths)
+            {
+                summary.largest_expansion = Some((mount.spec.clone(), chain.len()));
+            }
+            all_resolved.extend(chain);
+        }
+    }
+
+    // Sort by host path length (shortest first) so parent paths are processed before children.
+    // This ensures that when a symlink chain resolves to paths under /nix/store,
+    // the /nix/store mount is already in existing_resolved and coverage check works.
+    all_resolved.sort_by(|a, b| {
+        a.host
+            .as_os_str()
+            .len()
+            .cmp(&b.host.as_os_str().len())
+            .then_with(|| a.host.cmp(&b.host))
+    });
+
+    for resolved in all_resolved {
+        // Check if this path should be skipped based on configured skip patterns
+        if should_skip_path(&resolved.host, skip_patterns) {
+            eprintln!(
+                "DEBUG: Skipping mount path matching skip_mounts pattern: {}",
+                resolved.host.display(),
+            );
+            summary
+                .dropped
+                .push(format!("{} (matches skip_mounts)", resolved.host.display()));
+            continue;
+        }
+
+        // Skip if covered (unless should_skip is false)
+        if find_covering_mount(&resolved.host, &existing_resolved).is_some() && should_skip {
+            summary.covered += 1;
+            continue;
+        }
+
+        if resolved.mode != MountMode::Ro
+            && let Some(reason) = large_mount_reason(
+                &resolved.host,
+                &host_home,
+                large_mount_warn_entries,
+                |path| {
+                    std::fs::read_dir(path)
+                        .ok()
+                        .map(|entries| entries.take(large_mount_warn_entries).count())
+                },
+            )
+        {
+            summary
+                .large
+                .push(format!("{} ({})", resolved.host.display(), reason));
+        }
+
+        // Add to existing resolved mounts and binds
+        binds.push(resolved.to_bind_string());
+        existing_resolved.push(resolved);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent_box_common::config::{BindPropagation, MountConflict};
+
+    #[test]
+    fn test_resolved_mount_to_bind_string() {
+        let resolved = ResolvedMount {
+            host: PathBuf::from("/host/path"),
+            container: PathBuf::from("/container/path"),
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        assert_eq!(resolved.to_bind_string(), "/host/path:/container/path:ro");
+    }
+
+    #[test]
+    fn test_resolved_mount_overlay() {
+        let resolved = ResolvedMount {
+            host: PathBuf::from("/host"),
+            container: PathBuf::from("/container"),
+            mode: MountMode::Overlay,
+            propagation: None,
+            recursive: true,
+        };
+        assert_eq!(resolved.to_bind_string(), "/host:/container:O");
+    }
+
+    #[test]
+    fn test_mount_args_volume_and_mount_syntax() {
+        let args = |bind: &str, syntax| mount_args(bind, syntax);
+
+        // -v stays the default and passes the bind string through
+        assert_eq!(
+            args("/host:/ctr:ro,rslave", MountSyntax::Volume),
+            ["-v", "/host:/ctr:ro,rslave"]
+        );
+
+        assert_eq!(
+            args("/host:/ctr:ro", MountSyntax::Mount),
+            ["--mount", "type=bind,source=/host,target=/ctr,readonly"]
+        );
+        assert_eq!(
+            args("/host:/ctr:rw,rslave", MountSyntax::Mount),
+            [
+                "--mount",
+                "type=bind,source=/host,target=/ctr,bind-propagation=rslave"
+            ]
+        );
+        assert_eq!(
+            args("/a,b:/ctr:rw", MountSyntax::Mount),
+            ["--mount", "type=bind,\"source=/a,b\",target=/ctr"]
+        );
+        // Overlays have no bind form
+        assert_eq!(
+            args("/host:/ctr:O", MountSyntax::Mount),
+            ["-v", "/host:/ctr:O"]
+        );
+
+        let propagated = ResolvedMount {
+            host: PathBuf::from("/host"),
+            container: PathBuf::from("/ctr"),
+            mode: MountMode::Ro,
+            propagation: Some(BindPropagation::Rslave),
+            recursive: true,
+        };
+        let bind = propagated.to_bind_string();
+        assert_eq!(bind, "/host:/ctr:ro,rslave");
+        assert_eq!(
+            binds_json(&[bind]),
+            serde_json::json!([
+                {"host": "/host", "container": "/ctr", "mode": "ro", "propagation": "rslave"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_mount_args_nonrecursive() {
+        let nonrecursive = ResolvedMount {
+            host: PathBuf::from("/home/me"),
+            container: PathBuf::from("/home/me"),
+            mode: MountMode::Ro,
+            propagation: Some(BindPropagation::Rslave),
+            recursive: false,
+        };
+        let bind = nonrecursive.to_bind_string();
+        assert_eq!(bind, "/home/me:/home/me:ro,rslave,nonrecursive");
+
+        // -v can't express a non-recursive bind, so it always uses --mount
+        let expected = [
+            "--mount",
+            "type=bind,source=/home/me,target=/home/me,readonly,bind-propagation=rslave,bind-nonrecursive",
+        ];
+        assert_eq!(mount_args(&bind, MountSyntax::Volume), expected);
+        assert_eq!(mount_args(&bind, MountSyntax::Mount), expected);
+        assert_eq!(
+            mount_args("/host:/ctr:rw,nonrecursive", MountSyntax::Volume),
+            [
+                "--mount",
+                "type=bind,source=/host,target=/ctr,bind-nonrecursive"
+            ]
+        );
+        assert_eq!(
+            binds_json(&[bind]),
+            serde_json::json!([{
+                "host": "/home/me",
+                "container": "/home/me",
+                "mode": "ro",
+                "propagation": "rslave",
+                "recursive": false
+            }])
+        );
+
+        // Overlays are always recursive
+        let overlay = ResolvedMount {
+            mode: MountMode::Overlay,
+            ..nonrecursive
+        };
+        assert_eq!(overlay.to_bind_string(), "/home/me:/home/me:O,rslave");
+    }
+
+    #[test]
+    fn test_env_file_formats() {
+        let env = vec![
+            "USER=me".to_string(),
+            "GREETING=it's a=b".to_string(),
+            "EMPTY=".to_string(),
+            "BARE".to_string(),
+        ];
+
+        assert_eq!(
+            env_file(&env, false),
+            "USER=me\nGREETING=it's a=b\nEMPTY=\nBARE\n"
+        );
+        assert_eq!(
+            env_file(&env, true),
+            "export USER='me'\n\
+             export GREETING='it'\\''s a=b'\n\
+             export EMPTY=''\n\
+             export BARE\n"
+        );
+        assert_eq!(env_file(&[], true), "");
+    }
+
+    #[test]
+    fn test_session_container_lookup() {
+        assert_eq!(
+            session_labels("feat", "fr/tool"),
+            ["com.agent-box.session=feat", "com.agent-box.repo=fr/tool"]
+        );
+        assert_eq!(
+            ps_by_labels_args(&session_labels("feat", "fr/tool")),
+            [
+                "ps",
+                "--filter",
+                "label=com.agent-box.session=feat",
+                "--filter",
+                "label=com.agent-box.repo=fr/tool",
+                "--format",
+                "{{.Names}}"
+            ]
+        );
+
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            pick_session_container("feat", None, names(&["ab-tool-feat-1"])).unwrap(),
+            "ab-tool-feat-1"
+        );
+
+        let err = pick_session_container("feat", Some("fr/tool"), Vec::new()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("No running container found for session 'feat' in repo 'fr/tool'"),
+            "{}",
+            err
+        );
+
+        let err = pick_session_container("feat", None, names(&["ab-b-feat", "ab-a-feat"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("2 running containers found"), "{}", err);
+        assert!(err.contains("ab-a-feat, ab-b-feat"), "{}", err);
+        assert!(err.contains("pass --repo"), "{}", err);
+    }
+
+    /// Backend stub that records pulls and reports a fixed image presence
+    struct StubBackend {
+        exists: bool,
+        pulls: std::sync::Mutex<Vec<String>>,
+        builds: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl StubBackend {
+        fn new(exists: bool) -> Self {
+            Self {
+                exists,
+                pulls: std::sync::Mutex::new(Vec::new()),
+                builds: std::sync::Mutex::new(Vec::new()),
+            }
+        }
+
+        fn builds(&self) -> Vec<String> {
+            self.builds.lock().unwrap().clone()
+        }
+
+        fn pulls(&self) -> Vec<String> {
+            self.pulls.lock().unwrap().clone()
+        }
+    }
+
+    impl ContainerBackend for StubBackend {
+        fn capabilities(&self) -> BackendCapabilities {
+            DOCKER_CAPABILITIES
+        }
+
+        fn image_exists(&self, _image: &str) -> Result<bool> {
+            Ok(self.exists)
+        }
+
+        fn pull_image(&self, image: &str) -> Result<()> {
+            self.pulls.lock().unwrap().push(image.to_string());
+            Ok(())
+        }
+
+        fn build_image(&self, _build: &BuildConfig, tag: &str) -> Result<()> {
+            self.builds.lock().unwrap().push(tag.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_image_user_mismatch() {
+        // Unset and root users are replaced by --user without ownership surprises
+        for user in ["", "root", "0", "0:0", "root:root"] {
+            assert_eq!(image_user_mismatch(user, 1000, 1000), None, "{}", user);
+        }
+        assert_eq!(image_user_mismatch("1000", 1000, 1000), None);
+        assert_eq!(image_user_mismatch("1000:1000", 1000, 1000), None);
+
+        let warn = |user: &str| image_user_mismatch(user, 1000, 100).unwrap();
+        assert!(warn("1001").contains("image runs as uid 1001"));
+        assert!(warn("1001:100").contains("image runs as uid 1001"));
+        assert!(warn("1000:1000").contains("image runs with gid 1000"));
+        assert!(warn("1000:staff").contains("named group 'staff'"));
+        let named = warn("node");
+        assert!(named.contains("named user 'node'"), "{}", named);
+        assert!(named.contains("1000:100"), "{}", named);
+    }
+
+    #[test]
+    fn test_parse_passwd_home() {
+        let passwd = "\
+# comment
+root:x:0:0:root:/root:/bin/bash
+
+node:x:1000:1000::/home/node:/bin/sh
+me:x:1001:1001:Me,,,:/srv/me/:/bin/zsh
+nohome:x:1002:1002:::/bin/false
+broken line
+";
+
+        // By name, with trailing slashes trimmed
+        assert_eq!(
+            parse_passwd_home(passwd, "me", 4242).as_deref(),
+            Some("/srv/me")
+        );
+        assert_eq!(
+            parse_passwd_home(passwd, "root", 1000).as_deref(),
+            Some("/root")
+        );
+        // Falls back to the uid when the name isn't in the image
+        assert_eq!(
+            parse_passwd_home(passwd, "alice", 1000).as_deref(),
+            Some("/home/node")
+        );
+        // Entries without an absolute home don't count
+        assert_eq!(parse_passwd_home(passwd, "nohome", 1002), None);
+        assert_eq!(parse_passwd_home(passwd, "alice", 4242), None);
+        assert_eq!(parse_passwd_home("", "root", 0), None);
+        assert_eq!(
+            parse_passwd_home("slash:x:5:5::/:/bin/sh", "slash", 5).as_deref(),
+            Some("/")
+        );
+    }
+
+    #[test]
+    fn test_passwd_home_cache_round_trip() {
+        let dir = std::env::temp_dir().join(format!("ab_passwd_cache_{}", std::process::id()));
+        let path = dir.join("agent-box").join("passwd-homes.json");
+
+        // Missing and corrupt caches read as empty
+        assert!(load_passwd_home_cache(&path).is_empty());
+        let mut cache = BTreeMap::new();
+        cache.insert("sha256:abc me 1000".to_string(), "/srv/me".to_string());
+        save_passwd_home_cache(&path, &cache).unwrap();
+        assert_eq!(load_passwd_home_cache(&path), cache);
+        std::fs::write(&path, "not json").unwrap();
+        assert!(load_passwd_home_cache(&path).is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_ensure_image_exists_does_not_pull() {
+        let backend = StubBackend::new(true);
+        ensure_image(&backend, "test:latest", PullPolicy::Missing).unwrap();
+        assert!(backend.pulls().is_empty());
+    }
+
+    #[test]
+    fn test_ensure_image_missing_pulls() {
+        let backend = StubBackend::new(false);
+        ensure_image(&backend, "test:latest", PullPolicy::Missing).unwrap();
+        assert_eq!(backend.pulls(), vec!["test:latest"]);
+    }
+
+    #[test]
+    fn test_ensure_image_missing_with_pull_never_errors() {
+        let backend = StubBackend::new(false);
+        let err = ensure_image(&backend, "test:latest", PullPolicy::Never).unwrap_err();
+        assert!(err.to_string().contains("not found locally"));
+        assert!(backend.pulls().is_empty());
+    }
+
+    #[test]
+    fn test_ensure_image_pull_always_pulls_existing() {
+        let backend = StubBackend::new(true);
+        ensure_image(&backend, "test:latest", PullPolicy::Always).unwrap();
+        assert_eq!(backend.pulls(), vec!["test:latest"]);
+    }
+
+    #[test]
+    fn test_ensure_built_builds_missing_or_on_rebuild() {
+        let build = BuildConfig {
+            file: PathBuf::from("/repo/Containerfile"),
+            context: None,
+            args: BTreeMap::new(),
+            target: None,
+        };
+
+        let backend = StubBackend::new(true);
+        ensure_built(&backend, &build, "agent-box-build:abc", false).unwrap();
+        assert!(backend.builds().is_empty());
+        ensure_built(&backend, &build, "agent-box-build:abc", true).unwrap();
+        assert_eq!(backend.builds(), ["agent-box-build:abc"]);
+
+        let backend = StubBackend::new(false);
+        ensure_built(&backend, &build, "agent-box-build:abc", false).unwrap();
+        assert_eq!(backend.builds(), ["agent-box-build:abc"]);
+        assert!(backend.pulls().is_empty());
+    }
+
+    #[test]
+    fn test_build_args() {
+        let mut build = BuildConfig {
+            file: PathBuf::from("/repo/.devcontainer/Containerfile"),
+            context: None,
+            args: BTreeMap::from([
+                ("RUST".to_string(), "1.90".to_string()),
+                ("USER".to_string(), "me".to_string()),
+            ]),
+            target: None,
+        };
+        assert_eq!(
+            build_args(&build, "agent-box-build:abc"),
+            [
+                "build",
+                "--file",
+                "/repo/.devcontainer/Containerfile",
+                "--tag",
+                "agent-box-build:abc",
+                "--build-arg",
+                "RUST=1.90",
+                "--build-arg",
+                "USER=me",
+                "/repo/.devcontainer",
+            ]
+        );
+
+        build.args.clear();
+        build.context = Some(PathBuf::from("/repo"));
+        build.target = Some("dev".to_string());
+        assert_eq!(
+            build_args(&build, "agent-box-build:abc"),
+            [
+                "build",
+                "--file",
+                "/repo/.devcontainer/Containerfile",
+                "--tag",
+                "agent-box-build:abc",
+                "--target",
+                "dev",
+                "/repo",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copy_in_args() {
+        assert_eq!(
+            copy_in_args(
+                "ab-repo-main-1",
+                Path::new("/host/setup.sh"),
+                "/work/setup.sh"
+            ),
+            vec!["cp", "/host/setup.sh", "ab-repo-main-1:/work/setup.sh"]
+        );
+    }
+
+    #[test]
+    fn test_start_attached_args() {
+        assert_eq!(
+            start_attached_args("ab-repo-main-1"),
+            vec!["start", "--attach", "--interactive", "ab-repo-main-1"]
+        );
+        assert_eq!(
+            start_args("ab-repo-main-1", false),
+            start_attached_args("ab-repo-main-1")
+        );
+        assert_eq!(
+            start_args("ab-repo-main", true),
+            vec!["start", "ab-repo-main"]
+        );
+    }
+
+    #[test]
+    fn test_group_add_args() {
+        let groups = vec!["video".to_string(), "989".to_string()];
+        assert_eq!(
+            group_add_args(&groups),
+            vec!["--group-add", "video", "--group-add", "989"]
+        );
+        assert!(group_add_args(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_copy_in() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_copy_in_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let file = temp_dir.join("setup.sh");
+        std::fs::write(&file, b"echo hi").unwrap();
+        let host = file.canonicalize().unwrap();
+
+        let absolute =
+            parse_copy_in(&format!("{}:/etc/setup.sh", file.display()), "/work").unwrap();
+        assert_eq!(absolute.host, host);
+        assert_eq!(absolute.container, "/etc/setup.sh");
+
+        let relative =
+            parse_copy_in(&format!("{}:scripts/setup.sh", file.display()), "/work/").unwrap();
+        assert_eq!(relative.container, "/work/scripts/setup.sh");
+
+        let missing = parse_copy_in(&format!("{}:/x", temp_dir.join("nope").display()), "/work");
+        assert!(missing.unwrap_err().to_string().contains("not accessible"));
+
+        assert!(parse_copy_in("no-colon", "/work").is_err());
+        assert!(parse_copy_in(&format!("{}:", file.display()), "/work").is_err());
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_wrap_with_init_script() {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let prefix = ["-c", INIT_SCRIPT_WRAPPER, INIT_SCRIPT_PATH];
+
+        // Entrypoint then command become the arguments exec'd after the script
+        let (entrypoint, command) = wrap_with_init_script(
+            Some(strings(&["/entry"])),
+            Some(strings(&["claude", "--resume"])),
+        );
+        assert_eq!(entrypoint, vec!["/bin/sh"]);
+        assert_eq!(
+            command,
+            strings(&[&prefix[..], &["/entry", "claude", "--resume"]].concat())
+        );
+
+        let (_, command) = wrap_with_init_script(None, Some(strings(&["bash"])));
+        assert_eq!(command, strings(&[&prefix[..], &["bash"]].concat()));
+
+        let (entrypoint, command) = wrap_with_init_script(None, None);
+        assert_eq!(entrypoint, vec!["/bin/sh"]);
+        assert_eq!(command, strings(&prefix));
+    }
+
+    #[test]
+    fn test_post_create_wrapper_runs_command_first() {
+        let run = |post_create: &str, command: &str| {
+            let (entrypoint, args) = wrap_with_post_create(
+                None,
+                Some(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    command.to_string(),
+                ]),
+                post_create,
+            );
+            std::process::Command::new(&entrypoint[0])
+                .args(&args)
+                .stdin(std::process::Stdio::null())
+                .output()
+                .unwrap()
+        };
+
+        // Variables set by the post-create command don't leak into the command
+        let output = run("X=1; echo setup", "echo \"cmd ${X:-unset}\"");
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "setup\ncmd unset\n"
+        );
+
+        // A failing post-create command stops before the command
+        let output = run("echo setup && exit 4", "echo cmd");
+        assert_eq!(output.status.code(), Some(4));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "setup\n");
+    }
+
+    #[test]
+    fn test_init_script_wrapper_runs_script_then_command() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_init_script_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let log = temp_dir.join("log");
+        let script = temp_dir.join("init.sh");
+
+        // Run the wrapped command on the host, with the script at its host path
+        let run = |command: Option<Vec<String>>| {
+            let (entrypoint, mut args) = wrap_with_init_script(None, command);
+            args[2] = script.to_string_lossy().to_string();
+            std::process::Command::new(&entrypoint[0])
+                .args(&args)
+                .stdin(std::process::Stdio::null())
+                .status()
+                .unwrap()
+        };
+        let cmd = |c: &str| Some(vec!["sh".to_string(), "-c".to_string(), c.to_string()]);
+
+        std::fs::write(&script, format!("echo init >> '{}'\n", log.display())).unwrap();
+        let status = run(cmd(&format!("echo \"cmd $0\" >> '{}'", log.display())));
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "init\ncmd sh\n");
+
+        // With no command, a shell runs after the script (here reading empty stdin)
+        std::fs::remove_file(&log).unwrap();
+        assert!(run(None).success());
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "init\n");
+
+        // A failing script stops before the command
+        std::fs::write(&script, "exit 3\n").unwrap();
+        let status = run(cmd(&format!("echo cmd >> '{}'", log.display())));
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "init\n");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_check_workspace_type() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_wtype_{}", std::process::id()));
+        let git = temp_dir.join("git").join("repo").join("foo");
+        let jj = temp_dir.join("jj").join("repo").join("foo");
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&jj).unwrap();
+
+        // Only the jj workspace exists
+        let err = check_workspace_type(WorkspaceType::Git, "foo", &git, &jj)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with(
+                "requested git workspace for session 'foo' but only a jj workspace exists"
+            ),
+            "{}",
+            err
+        );
+        assert!(err.ends_with("use --jj or create it with --new"), "{}", err);
+        assert!(check_workspace_type(WorkspaceType::Jj, "foo", &jj, &git).is_ok());
+
+        // Vice versa
+        std::fs::remove_dir_all(&jj).unwrap();
+        std::fs::create_dir_all(&git).unwrap();
+        let err = check_workspace_type(WorkspaceType::Jj, "foo", &jj, &git)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("requested jj workspace"), "{}", err);
+        assert!(err.contains("use --git"), "{}", err);
+
+        // Both or neither existing is not a mismatch
+        std::fs::create_dir_all(&jj).unwrap();
+        assert!(check_workspace_type(WorkspaceType::Jj, "foo", &jj, &git).is_ok());
+        std::fs::remove_dir_all(&temp_dir).unwrap();
+        assert!(check_workspace_type(WorkspaceType::Git, "foo", &git, &jj).is_ok());
+    }
+
+    #[test]
+    fn test_collect_tar_dirs() {
+        let listing = "\
+drwxr-xr-x 0/0               0 2024-01-01 00:00 ./
+drwxr-xr-x 0/0               0 2024-01-01 00:00 etc/
+-rw-r--r-- 0/0             123 2024-01-01 00:00 etc/hosts
+lrwxrwxrwx 0/0               0 2024-01-01 00:00 bin -> usr/bin
+drwxr-xr-x 0/0               0 2024-01-01 00:00 usr/
+drwxr-xr-x 0/0               0 2024-01-01 00:00 usr/lib/
+drwxr-xr-x 0/0               0 2024-01-01 00:00 usrlocal/
+drwxr-xr-x 0/0               0 2024-01-01 00:00 /abs/dir/
+";
+        let all = collect_tar_dirs(listing.as_bytes(), None).unwrap();
+        assert_eq!(
+            all,
+            vec!["/", "/etc", "/usr", "/usr/lib", "/usrlocal", "/abs/dir"]
+        );
+        assert_eq!(
+            collect_tar_dirs(listing.as_bytes(), Some("/")).unwrap(),
+            all
+        );
+        assert_eq!(collect_tar_dirs(listing.as_bytes(), Some("")).unwrap(), all);
+
+        // Root matches itself and its children, not siblings sharing a prefix
+        assert_eq!(
+            collect_tar_dirs(listing.as_bytes(), Some("/usr/")).unwrap(),
+            vec!["/usr", "/usr/lib"]
+        );
+
+        // Non-UTF-8 bytes don't abort the listing
+        let mixed =
+            b"drwxr-xr-x 0/0 0 2024-01-01 00:00 caf\xe9/\ndrwxr-xr-x 0/0 0 2024-01-01 00:00 ok/";
+        assert_eq!(
+            collect_tar_dirs(&mixed[..], None).unwrap(),
+            vec!["/caf\u{fffd}", "/ok"]
+        );
+    }
+
+    #[test]
+    fn test_collect_tar_dirs_large_stream() {
+        const DIRS: usize = 20_000;
+        const FILES_PER_DIR: usize = 10;
+
+        // Generate the listing lazily so it's never held in memory at once
+        struct Listing {
+            dir: usize,
+            file: usize,
+            pending: std::io::Cursor<Vec<u8>>,
+        }
+        impl std::io::Read for Listing {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                loop {
+                    let n = self.pending.read(buf)?;
+                    if n > 0 || self.dir == DIRS {
+                        return Ok(n);
+                    }
+                    let line = if self.file == 0 {
+                        format!("drwxr-xr-x 0/0 0 2024-01-01 00:00 d{}/\n", self.dir)
+                    } else {
+                        format!(
+                            "-rw-r--r-- 0/0 9 2024-01-01 00:00 d{}/f{}\n",
+                            self.dir, self.file
+                        )
+                    };
+                    self.file += 1;
+                    if self.file > FILES_PER_DIR {
+                        self.file = 0;
+                        self.dir += 1;
+                    }
+                    self.pending = std::io::Cursor::new(line.into_bytes());
+                }
+            }
+        }
+        let listing = || Listing {
+            dir: 0,
+            file: 0,
+            pending: std::io::Cursor::new(Vec::new()),
+        };
+
+        let all = collect_tar_dirs(std::io::BufReader::new(listing()), None).unwrap();
+        assert_eq!(all.len(), DIRS);
+        assert_eq!(all[0], "/d0");
+        assert_eq!(all[DIRS - 1], format!("/d{}", DIRS - 1));
+
+        let rooted = collect_tar_dirs(std::io::BufReader::new(listing()), Some("/d1234")).unwrap();
+        assert_eq!(rooted, vec!["/d1234"]);
+    }
+
+    #[test]
+    fn test_find_covering_mount_exact_match() {
+        let mounts = vec![ResolvedMount {
+            host: PathBuf::from("/host/path"),
+            container: PathBuf::from("/container/path"),
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        }];
+        let result = find_covering_mount(Path::new("/host/path"), &mounts).map(|m| m.mode);
+        assert_eq!(result, Some(MountMode::Ro));
+    }
+
+    #[test]
+    fn test_find_covering_mount_subpath() {
+        let mounts = vec![ResolvedMount {
+            host: PathBuf::from("/nix/store"),
+            container: PathBuf::from("/nix/store"),
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        }];
+        let result =
+            find_covering_mount(Path::new("/nix/store/abc123-package"), &mounts).map(|m| m.mode);
+        assert_eq!(result, Some(MountMode::Ro));
+    }
+
+    #[test]
+    fn test_find_covering_mount_not_covered() {
+        let mounts = vec![ResolvedMount {
+            host: PathBuf::from("/nix/store"),
+            container: PathBuf::from("/nix/store"),
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        }];
+        let result = find_covering_mount(Path::new("/home/user"), &mounts);
+        assert_eq!(result, None);
+    }
+
+    const HOST_HOME: &str = "/home/hostuser";
+    const CONTAINER_HOME: &str = "/home/containeruser";
+
+    /// Helper to create a Mount and resolve with test homes (without canonicalization)
+    fn resolve_test(spec: &str, home_relative: bool) -> (String, String) {
+        let mount = Mount {
+            spec: spec.to_string(),
+            home_relative,
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+        // Use resolve_paths directly to avoid canonicalization in tests
+        mount.resolve_paths(HOST_HOME, CONTAINER_HOME).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_absolute_single_path() {
+        // absolute (home_relative=false): same path on both sides
+        let (host, container) = resolve_test("/nix/store", false);
+        assert_eq!(host, "/nix/store");
+        assert_eq!(container, "/nix/store");
+    }
+
+    #[test]
+    fn test_resolve_absolute_single_path_with_tilde() {
+        // absolute with ~: expands to host home, container gets same absolute path
+        let (host, container) = resolve_test("~/.config", false);
+        assert_eq!(host, "/home/hostuser/.config");
+        assert_eq!(container, "/home/hostuser/.config"); // same path, NOT translated
+    }
+
+    #[test]
+    fn test_resolve_home_relative_single_path() {
+        // home_relative=true: host home prefix replaced with container home
+        let (host, container) = resolve_test("~/.config", true);
+        assert_eq!(host, "/home/hostuser/.config");
+        assert_eq!(container, "/home/containeruser/.config"); // translated!
+    }
+
+    #[test]
+    fn test_resolve_home_relative_path_not_under_home() {
+        // home_relative=true but path not under home: use as-is
+        let (host, container) = resolve_test("/nix/store", true);
+        assert_eq!(host, "/nix/store");
+        assert_eq!(container, "/nix/store");
+    }
+
+    #[test]
+    fn test_resolve_explicit_mapping_absolute() {
+        // Explicit source:dest mapping
+        let (host, container) = resolve_test("/host/path:/container/path", false);
+        assert_eq!(host, "/host/path");
+        assert_eq!(container, "/container/path");
+    }
+
+    #[test]
+    fn test_resolve_explicit_mapping_with_tilde() {
+        // Explicit mapping with ~ on dest side expands to container home
+        let (host, container) = resolve_test("/run/user/1000/gnupg:~/.gnupg", true);
+        assert_eq!(host, "/run/user/1000/gnupg");
+        assert_eq!(container, "/home/containeruser/.gnupg");
+    }
+
+    #[test]
+    fn test_resolve_explicit_mapping_tilde_both_sides() {
+        // ~ on both sides: host ~ -> host home, container ~ -> container home
+        let (host, container) = resolve_test("~/.foo:~/.bar", false);
+        assert_eq!(host, "/home/hostuser/.foo");
+        assert_eq!(container, "/home/containeruser/.bar");
+    }
+
+    #[test]
+    fn test_add_mounts_skips_covered_paths() {
+        // Test that symlink chain paths under already-mounted directories are skipped
+        let base_dir = std::env::temp_dir().join(format!("ab_base_{}", std::process::id()));
+        let temp_dir = std::env::temp_dir().join(format!("ab_covered_{}", std::process::id()));
+        let link_path = temp_dir.join("mylink");
+
+        // Create base directory that will be mounted
+        let _ = std::fs::remove_dir_all(&base_dir);
+        std::fs::create_dir_all(&base_dir).unwrap();
+
+        // Create temp directory for the symlink
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+
+        // Create initial bind for the base directory
+        let mut binds = vec![format!("{}:{}:ro", base_dir.display(), base_dir.display())];
+
+        // Create symlink to base_dir (which exists)
+        std::os::unix::fs::symlink(&base_dir, &link_path).unwrap();
+
+        let mount = Mount {
+            spec: link_path.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let _ = std::fs::remove_dir_all(&base_dir);
+
+        // Should have 2 mounts: original base_dir and the symlink itself
+        // The symlink target (base_dir) should NOT be added again
+        assert_eq!(binds.len(), 2);
+        assert!(binds[0].contains(&base_dir.file_name().unwrap().to_string_lossy().to_string()));
+        assert!(binds[1].contains("mylink"));
+    }
+
+    #[test]
+    fn test_add_mounts_ro_under_rw_allowed() {
+        // ro mount under rw parent should be skipped (covered)
+        let temp_dir = std::env::temp_dir().join(format!("ab_ro_rw_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount (subdir skipped as covered by parent)
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_rw_under_ro_skipped() {
+        // rw mount under ro parent should be skipped (covered)
+        let temp_dir = std::env::temp_dir().join(format!("ab_rw_ro_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount (subdir skipped as covered)
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_overlay_under_ro_skipped() {
+        // overlay mount under ro parent should be skipped (covered)
+        let temp_dir = std::env::temp_dir().join(format!("ab_o_ro_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Overlay,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount (subdir skipped as covered)
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_ro_under_ro_skipped() {
+        // ro mount under ro parent should be skipped
+        let temp_dir = std::env::temp_dir().join(format!("ab_ro_ro_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_rw_under_rw_skipped() {
+        // rw mount under rw parent should be skipped
+        let temp_dir = std::env::temp_dir().join(format!("ab_rw_rw_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_overlay_under_rw_skipped() {
+        // overlay mount under rw parent should be skipped
+        let temp_dir = std::env::temp_dir().join(format!("ab_o_rw_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Overlay,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_ro_under_overlay_skipped() {
+        // ro mount under overlay parent should be skipped
+        let temp_dir = std::env::temp_dir().join(format!("ab_ro_o_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:O", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_rw_under_overlay_skipped() {
+        // rw mount under overlay parent should be skipped
+        let temp_dir = std::env::temp_dir().join(format!("ab_rw_o_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:O", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_overlay_under_overlay_skipped() {
+        // overlay mount under overlay parent should be skipped
+        let temp_dir = std::env::temp_dir().join(format!("ab_o_o_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:O", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Overlay,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should still have just 1 mount
+        assert_eq!(binds.len(), 1);
+    }
+
+    #[test]
+    fn test_add_mounts_rw_under_rw_with_no_skip() {
+        // rw mount under rw parent should NOT be skipped when should_skip=false
+        let temp_dir = std::env::temp_dir().join(format!("ab_rw_rw_noskip_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+
+        // Should add even though it's covered, with should_skip=false
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            false,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should have 2 mounts (parent + child)
+        assert_eq!(binds.len(), 2);
+    }
+
+    #[test]
+    fn test_add_mounts_ro_under_rw_with_no_skip() {
+        // ro mount under rw parent should NOT be skipped when should_skip=false
+        let temp_dir = std::env::temp_dir().join(format!("ab_ro_rw_noskip_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:rw", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        // Should add even though it's covered, with should_skip=false
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            false,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should have 2 mounts (parent + child)
+        assert_eq!(binds.len(), 2);
+    }
+
+    #[test]
+    fn test_add_mounts_rw_under_ro_with_no_skip() {
+        // rw under ro should add when should_skip=false
+        let temp_dir = std::env::temp_dir().join(format!("ab_rw_ro_noskip_{}", std::process::id()));
+        let subdir = temp_dir.join("subdir");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let mut binds = vec![format!("{}:{}:ro", temp_dir.display(), temp_dir.display())];
+
+        let mount = Mount {
+            spec: subdir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+
+        // Should add even though it's covered, when should_skip=false
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            false,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should have 2 mounts (parent + child)
+        assert_eq!(binds.len(), 2);
+    }
+
+    #[test]
+    fn test_add_mounts_standalone_file() {
+        // A single file mount produces exactly one bind string for that file
+        let temp_dir = std::env::temp_dir().join(format!("ab_file_{}", std::process::id()));
+        let file = temp_dir.join("gitconfig");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        std::fs::write(&file, "[user]\n").unwrap();
+
+        let mut binds = Vec::new();
+
+        let mount = Mount {
+            spec: file.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        assert_eq!(
+            binds,
+            vec![format!("{}:{}:ro", file.display(), file.display())]
+        );
+    }
+
+    #[test]
+    fn test_add_mounts_file_under_mounted_dir() {
+        // A file inside an already-mounted directory is skipped as covered,
+        // but still added when should_skip=false
+        let temp_dir = std::env::temp_dir().join(format!("ab_file_dir_{}", std::process::id()));
+        let file = temp_dir.join("settings.json");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        std::fs::write(&file, "{}").unwrap();
+
+        let parent = format!("{}:{}:rw", temp_dir.display(), temp_dir.display());
+        let mount = Mount {
+            spec: file.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        let mut skipped = vec![parent.clone()];
+        add_mounts(
+            &[&mount],
+            &mut skipped,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        let mut kept = vec![parent.clone()];
+        add_mounts(
+            &[&mount],
+            &mut kept,
+            false,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        assert_eq!(skipped, vec![parent.clone()]);
+        assert_eq!(
+            kept,
+            vec![parent, format!("{}:{}:ro", file.display(), file.display())]
+        );
+    }
+
+    #[test]
+    fn test_add_mounts_file_sibling_prefix_not_covered() {
+        // A file whose name shares a string prefix with a mounted dir is not covered
+        let temp_dir = std::env::temp_dir().join(format!("ab_file_prefix_{}", std::process::id()));
+        let mounted = temp_dir.join("conf");
+        let file = temp_dir.join("conf.toml");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&mounted).unwrap();
+        std::fs::write(&file, "").unwrap();
+
+        let mut binds = vec![format!("{}:{}:rw", mounted.display(), mounted.display())];
+
+        let mount = Mount {
+            spec: file.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        assert_eq!(binds.len(), 2);
+        assert!(binds[1].starts_with(&format!("{}:", file.display())));
+    }
+
+    #[test]
+    fn test_add_mounts_require_all() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_require_all_{}", std::process::id()));
+        let present = temp_dir.join("present");
+        let skipped = temp_dir.join("skipped");
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&present).unwrap();
+        std::fs::create_dir_all(&skipped).unwrap();
+
+        let mount = |path: &Path| Mount {
+            spec: path.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let add = |mounts: &[&Mount], skip_patterns: &[String]| {
+            add_mounts(
+                mounts,
+                &mut Vec::new(),
+                true,
+                skip_patterns,
+                &[],
+                RootPrefix::default(),
+                None,
+                0,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+        };
+
+        // Everything present and nothing skipped passes
+        let summary = add(&[&mount(&present), &mount(&skipped)], &[]);
+        assert!(summary.require_all().is_ok());
+
+        // Missing and skip-pattern mounts are each listed
+        let missing = mount(&temp_dir.join("missing"));
+        let summary = add(
+            &[&mount(&present), &mount(&skipped), &missing],
+            &[skipped.to_string_lossy().to_string()],
+        );
+        let err = summary.require_all().unwrap_err().to_string();
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        assert!(err.contains("2 mount(s) would not be mounted"), "{}", err);
+        assert!(
+            err.contains(&format!("{} (missing)", missing.spec)),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains(&format!("{} (matches skip_mounts)", skipped.display())),
+            "{}",
+            err
+        );
+        assert!(
+            !err.contains(&format!("{} (", present.display())),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_remap_container_path() {
+        let remaps = [
+            MountRemap {
+                from: "~/.config".to_string(),
+                to: "/etc/xdg".to_string(),
+            },
+            MountRemap {
+                from: "/data".to_string(),
+                to: "~/data".to_string(),
+            },
+            MountRemap {
+                from: "/data/cache".to_string(),
+                to: "/cache".to_string(),
+            },
+        ];
+        let remap = |path: &str| remap_container_path(Path::new(path), &remaps, "/home/me");
+
+        assert_eq!(
+            remap("/home/me/.config/git/config"),
+            Some(PathBuf::from("/etc/xdg/git/config"))
+        );
+        assert_eq!(remap("/home/me/.config"), Some(PathBuf::from("/etc/xdg")));
+        assert_eq!(remap("/data/x"), Some(PathBuf::from("/home/me/data/x")));
+        // First match wins, even over a longer prefix
+        assert_eq!(
+            remap("/data/cache/y"),
+            Some(PathBuf::from("/home/me/data/cache/y"))
+        );
+
+        // Prefixes match whole components; everything else is left alone
+        assert_eq!(remap("/home/me/.configs"), None);
+        assert_eq!(remap("/home/me/.cache"), None);
+        assert_eq!(remap("/etc/hosts"), None);
+        assert_eq!(
+            remap_container_path(Path::new("/data"), &[], "/home/me"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_add_mounts_applies_mount_remap() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_mount_remap_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let host_home = temp_dir.join("home");
+        std::fs::create_dir_all(host_home.join(".config/git")).unwrap();
+        std::fs::create_dir_all(host_home.join(".cache")).unwrap();
+        let host_home = host_home.canonicalize().unwrap();
+
+        let mount = |spec: &str| Mount {
+            spec: spec.to_string(),
+            home_relative: true,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let (git, cache) = (mount("~/.config/git"), mount("~/.cache"));
+        let homes = HomeMap {
+            host: host_home.to_string_lossy().to_string(),
+            container: "/home/me".to_string(),
+        };
+        let remaps = [MountRemap {
+            from: "~/.config".to_string(),
+            to: "/etc/xdg".to_string(),
+        }];
+
+        let mut binds = Vec::new();
+        add_mounts(
+            &[&git, &cache],
+            &mut binds,
+            true,
+            &[],
+            &remaps,
+            RootPrefix::default(),
+            Some(&homes),
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        assert!(
+            binds.contains(&format!(
+                "{}/.config/git:/etc/xdg/git:ro",
+                host_home.display()
+            )),
+            "{:?}",
+            binds
+        );
+        assert!(
+            binds.contains(&format!(
+                "{}/.cache:/home/me/.cache:ro",
+                host_home.display()
+            )),
+            "{:?}",
+            binds
+        );
+    }
+
+    #[test]
+    fn test_large_mount_reason() {
+        let counts = |path: &Path| match path.to_str() {
+            Some("/data/huge") => Some(1000),
+            Some("/data/small") => Some(999),
+            _ => None,
+        };
+        let reason = |host: &str, threshold| {
+            large_mount_reason(Path::new(host), "/home/me", threshold, counts)
+        };
+
+        assert_eq!(reason("/", 1000).as_deref(), Some("the root filesystem"));
+        assert_eq!(
+            reason("/home/me", 1000).as_deref(),
+            Some("your home directory")
+        );
+        assert_eq!(reason("/data/huge", 1000).as_deref(), Some("1000+ entries"));
+        assert_eq!(reason("/data/small", 1000), None);
+        assert_eq!(reason("/data/small", 10).as_deref(), Some("999+ entries"));
+        // Files and unreadable paths have no count
+        assert_eq!(reason("/home/me/.gitconfig", 1), None);
+        assert_eq!(reason("/home/me/src", 1000), None);
+        // 0 disables the check entirely
+        assert_eq!(reason("/", 0), None);
+        assert_eq!(reason("/home/me", 0), None);
+        // No known home (no mounts resolved) doesn't match everything
+        assert_eq!(large_mount_reason(Path::new(""), "", 1000, counts), None);
+    }
+
+    #[test]
+    fn test_add_mounts_flags_large_writable_mounts() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_large_mount_{}", std::process::id()));
+        let big = temp_dir.join("big");
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&big).unwrap();
+        for i in 0..3 {
+            std::fs::write(big.join(format!("f{}", i)), "").unwrap();
+        }
+        std::fs::create_dir_all(temp_dir.join("small")).unwrap();
+
+        let mount = |path: &Path, mode| Mount {
+            spec: path.to_string_lossy().to_string(),
+            home_relative: false,
+            mode,
+            propagation: None,
+            recursive: true,
+        };
+        let homes = HomeMap {
+            host: temp_dir.join("home").to_string_lossy().to_string(),
+            container: "/home/me".to_string(),
+        };
+        let summary = |mounts: &[&Mount]| {
+            add_mounts(
+                mounts,
+                &mut Vec::new(),
+                true,
+                &[],
+                &[],
+                RootPrefix::default(),
+                Some(&homes),
+                3,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+        };
+
+        let rw_big = mount(&big, MountMode::Rw);
+        let ro_big = mount(&big, MountMode::Ro);
+        let rw_small = mount(&temp_dir.join("small"), MountMode::Rw);
+        assert_eq!(
+            summary(&[&rw_big, &rw_small]).large,
+            [format!("{} (3+ entries)", big.display())]
+        );
+        assert!(summary(&[&ro_big, &rw_small]).large.is_empty());
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_confirm_large_mounts() {
+        let summary = MountSummary {
+            large: vec!["/ (the root filesystem)".to_string()],
+            ..Default::default()
+        };
+
+        // --yes and no large mounts never ask
+        summary
+            .confirm_large_mounts(true, |_| unreachable!())
+            .unwrap();
+        MountSummary::default()
+            .confirm_large_mounts(false, |_| unreachable!())
+            .unwrap();
+
+        summary
+            .confirm_large_mounts(false, |list| {
+                assert_eq!(
+                    list,
+                    "1 large writable mount(s):\n  / (the root filesystem)"
+                );
+                Ok(true)
+            })
+            .unwrap();
+        let err = summary
+            .confirm_large_mounts(false, |_| Ok(false))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Aborted"), "{}", err);
+    }
+
+    #[test]
+    fn test_root_prefix_apply_and_validation() {
+        let mut runtime = RuntimeConfig::default();
+        assert_eq!(
+            RootPrefix::from_config(&runtime).unwrap(),
+            RootPrefix::default()
+        );
+        runtime.container_root_prefix = Some("/".to_string());
+        assert_eq!(RootPrefix::from_config(&runtime).unwrap().prefix, None);
+        runtime.container_root_prefix = Some("sandbox".to_string());
+        assert!(RootPrefix::from_config(&runtime).is_err());
+
+        runtime.container_root_prefix = Some("/sandbox/".to_string());
+        let prefix = RootPrefix::from_config(&runtime).unwrap();
+        assert_eq!(prefix.prefix, Some("/sandbox"));
+        assert!(!prefix.absolute);
+
+        let apply = |path: &str| prefix.apply(Path::new(path));
+        assert_eq!(apply("/home/me/ws"), Path::new("/sandbox/home/me/ws"));
+        assert_eq!(apply("/"), Path::new("/sandbox"));
+        // Never prefixed twice, but only whole components count
+        assert_eq!(apply("/sandbox/home/me"), Path::new("/sandbox/home/me"));
+        assert_eq!(apply("/sandboxes/x"), Path::new("/sandbox/sandboxes/x"));
+        assert_eq!(
+            RootPrefix::default().apply(Path::new("/home/me")),
+            Path::new("/home/me")
+        );
+    }
+
+    #[test]
+    fn test_container_root_prefix_across_mount_kinds() {
+        use agent_box_common::config::{Config, ResolvedProfile};
+
+        let temp_dir = std::env::temp_dir().join(format!("ab_root_prefix_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let workspace = temp_dir.join("ws");
+        let source = temp_dir.join("src");
+        let host_home = temp_dir.join("home");
+        let data = temp_dir.join("data");
+        for dir in [
+            &workspace,
+            &source.join(".git"),
+            &host_home.join(".config"),
+            &data,
+        ] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let temp_dir = temp_dir.canonicalize().unwrap();
+        let (workspace, source, host_home, data) = (
+            temp_dir.join("ws"),
+            temp_dir.join("src"),
+            temp_dir.join("home"),
+            temp_dir.join("data"),
+        );
+
+        let mut config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+        config.runtime.container_root_prefix = Some("/sandbox".to_string());
+
+        let mount = |spec: String, home_relative| Mount {
+            spec,
+            home_relative,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let cli_mounts = [
+            mount("~/.config".to_string(), true),
+            mount(data.to_string_lossy().to_string(), false),
+        ];
+        let homes = HomeMap {
+            host: host_home.to_string_lossy().to_string(),
+            container: "/home/me".to_string(),
+        };
+        let build = |config: &Config| {
+            build_container_config(
+                config,
+                &workspace,
+                &source,
+                false,
+                false,
+                false,
+                false,
+                None,
+                &ResolvedProfile::default(),
+                &cli_mounts,
+                &[],
+                &[],
+                &[],
+                None,
+                None,
+                false,
+                None,
+                None,
+                Some(&homes),
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+        };
+
+        let sandbox = |path: &Path| format!("/sandbox{}", path.display());
+        let container_config = build(&config);
+        assert_eq!(container_config.working_dir, sandbox(&workspace));
+        // The terminfo mount after these depends on the test environment's TERM
+        assert_eq!(
+            container_config.mounts[..4],
+            [
+                format!("{}:{}:rw", workspace.display(), sandbox(&workspace)),
+                format!(
+                    "{}:{}:rw",
+                    source.join(".git").display(),
+                    sandbox(&source.join(".git"))
+                ),
+                format!("{}:{}:ro", data.display(), data.display()),
+                format!(
+                    "{}/.config:/sandbox/home/me/.config:ro",
+                    host_home.display()
+                ),
+            ]
+        );
+        // HOME is what processes inside the root see
+        assert!(container_config.env.contains(&"HOME=/home/me".to_string()));
+
+        // Opting in prefixes absolute mounts too, without double-prefixing the rest
+        config.runtime.container_root_prefix_absolute = true;
+        let container_config = build(&config);
+        assert_eq!(
+            container_config.mounts[2..4],
+            [
+                format!("{}:{}:ro", data.display(), sandbox(&data)),
+                format!(
+                    "{}/.config:/sandbox/home/me/.config:ro",
+                    host_home.display()
+                ),
+            ]
+        );
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_add_mounts_max_mounts_names_exploding_spec() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_max_mounts_{}", std::process::id()));
+        let farm = temp_dir.join("farm");
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&farm).unwrap();
+        for i in 0..20 {
+            std::fs::write(farm.join(format!("f{}", i)), "").unwrap();
+        }
+        std::fs::write(temp_dir.join("single"), "").unwrap();
+
+        let mount = |spec: String| Mount {
+            spec,
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let single = mount(temp_dir.join("single").to_string_lossy().to_string());
+        let glob = mount(format!("{}/*", farm.display()));
+
+        let mut binds = Vec::new();
+        let summary = add_mounts(
+            &[&single, &glob],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        assert_eq!(binds.len(), 21);
+        assert_eq!(summary.largest_expansion, Some((glob.spec.clone(), 20)));
+        summary.check_max_mounts(binds.len(), 21).unwrap();
+        summary.check_max_mounts(binds.len(), 0).unwrap();
+
+        let err = summary
+            .check_max_mounts(binds.len(), 10)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("21 mounts exceed runtime.max_mounts (10)"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains(&format!("mount '{}' alone expanded to 20 paths", glob.spec)),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_add_mounts_summary_counts() {
+        // One added dir, one file covered by it, one missing path
+        let temp_dir = std::env::temp_dir().join(format!("ab_summary_{}", std::process::id()));
+        let covered = temp_dir.join("covered.txt");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        std::fs::write(&covered, "").unwrap();
+
+        let mount = |path: &Path, mode| Mount {
+            spec: path.to_string_lossy().to_string(),
+            home_relative: false,
+            mode,
+            propagation: None,
+            recursive: true,
+        };
+        let dir = mount(&temp_dir, MountMode::Rw);
+        let file = mount(&covered, MountMode::Ro);
+        let missing = mount(&temp_dir.join("missing"), MountMode::Ro);
+
+        let mut binds = vec!["/workspace:/workspace:rw".to_string()];
+        let mut summary = add_mounts(
+            &[&dir, &file, &missing],
+            &mut binds,
+            true,
+            &[],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+        binds.push("/nix/store:/nix/store:ro".to_string());
+        binds.push("/cache:/cache:O".to_string());
+        summary.count_binds(&binds);
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        assert_eq!(
+            summary,
+            MountSummary {
+                ro: 1,
+                rw: 2,
+                overlay: 1,
+                tmpfs: 0,
+                volumes: 0,
+                covered: 1,
+                filtered: 1,
+                dropped: vec![format!("{} (missing)", missing.spec)],
+                largest_expansion: Some((dir.spec.clone(), 1)),
+                large: vec![],
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "Mounts: 1 ro, 2 rw, 1 overlay (1 skipped as covered, 1 filtered missing)"
+        );
+        assert_eq!(
+            MountSummary {
+                rw: 1,
+                ..Default::default()
+            }
+            .to_string(),
+            "Mounts: 0 ro, 1 rw, 0 overlay"
+        );
+    }
+
+    #[test]
+    fn test_mount_equality_same_spec() {
+        let m1 = Mount {
+            spec: "~/.config".to_string(),
+            home_relative: true,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let m2 = Mount {
+            spec: "~/.config".to_string(),
+            home_relative: true,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        assert_eq!(m1, m2);
+    }
+
+    #[test]
+    fn test_mount_equality_different_mode() {
+        let m1 = Mount {
+            spec: "~/.config".to_string(),
+            home_relative: true,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let m2 = Mount {
+            spec: "~/.config".to_string(),
+            home_relative: true,
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+        assert_ne!(m1, m2);
+    }
+
+    #[test]
+    fn test_mount_equality_equivalent_paths() {
+        // These resolve to the same paths, so should be equal
+        let m1 = Mount {
+            spec: "/nix/store".to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let m2 = Mount {
+            spec: "/nix/store".to_string(),
+            home_relative: true, // different flag, but resolves same
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        assert_eq!(m1, m2);
+    }
+
+    // CLI mount parsing tests
+
+    #[test]
+    fn test_home_ro_mount_maps_host_home_to_container_home() {
+        let m = home_ro_mount();
+        assert_eq!(m.mode, MountMode::Ro);
+        assert!(m.home_relative);
+
+        let (host, container) = m.resolve_paths(HOST_HOME, CONTAINER_HOME).unwrap();
+        assert_eq!(host, HOST_HOME);
+        assert_eq!(container, CONTAINER_HOME);
+    }
+
+    #[test]
+    fn test_parse_cli_mount_no_mode_defaults_to_rw() {
+        let m = parse_single_cli_mount("~/data", true).unwrap();
+        assert_eq!(m.mode, MountMode::Rw);
+        assert_eq!(m.spec, "~/data");
+        assert!(m.home_relative);
+    }
+
+    #[test]
+    fn test_parse_cli_mount_absolute_no_mode() {
+        let m = parse_single_cli_mount("/nix/store", false).unwrap();
+        assert_eq!(m.mode, MountMode::Rw);
+        assert_eq!(m.spec, "/nix/store");
+        assert!(!m.home_relative);
+    }
+
+    #[test]
+    fn test_parse_cli_mount_ro_mode() {
+        let m = parse_single_cli_mount("ro:~/.config/git", true).unwrap();
+        assert_eq!(m.mode, MountMode::Ro);
+        assert_eq!(m.spec, "~/.config/git");
+        assert!(m.home_relative);
+    }
+
+    #[test]
+    fn test_parse_cli_mount_rw_mode() {
+        let m = parse_single_cli_mount("rw:~/data", true).unwrap();
+        assert_eq!(m.mode, MountMode::Rw);
+        assert_eq!(m.spec, "~/data");
+    }
+
+    #[test]
+    fn test_parse_cli_mount_overlay_mode() {
+        let m = parse_single_cli_mount("o:~/.gnupg", true).unwrap();
+        assert_eq!(m.mode, MountMode::Overlay);
+        assert_eq!(m.spec, "~/.gnupg");
+    }
+
+    #[test]
+    fn test_parse_cli_mount_with_src_dst() {
+        let m = parse_single_cli_mount("ro:~/src:/app", true).unwrap();
+        assert_eq!(m.mode, MountMode::Ro);
+        assert_eq!(m.spec, "~/src:/app");
+    }
+
+    #[test]
+    fn test_parse_cli_mount_relative_src_with_dst_resolves_against_cwd() {
+        let cwd = std::env::current_dir().unwrap();
+        let m = parse_single_cli_mount("ro:../pierre:/app", true).unwrap();
+        assert_eq!(m.mode, MountMode::Ro);
+        assert_eq!(
+            m.spec,
+            format!("{}:/app", cwd.join("../pierre").to_string_lossy())
+        );
+    }
+
+    #[test]
     fn test_parse_cli_mount_absolute_with_mode() {
         let m = parse_single_cli_mount("ro:/etc/hosts", false).unwrap();
         assert_eq!(m.mode, MountMode::Ro);
@@ -1219,270 +5447,1945 @@ mod tests {
         assert!(!m.home_relative);
     }
 
-    #[test]
-    fn test_parse_cli_mount_empty_after_mode_fails() {
-        assert!(parse_single_cli_mount("ro:", true).is_err());
+    #[test]
+    fn test_parse_cli_mount_empty_after_mode_fails() {
+        assert!(parse_single_cli_mount("ro:", true).is_err());
+    }
+
+    #[test]
+    fn test_parse_cli_mount_relative_path_resolves_against_cwd() {
+        let cwd = std::env::current_dir().unwrap();
+
+        let m1 = parse_single_cli_mount("data/stuff", true).unwrap();
+        assert!(m1.spec.starts_with('/'));
+        assert_eq!(m1.spec, cwd.join("data/stuff").to_string_lossy());
+
+        let m2 = parse_single_cli_mount("ro:data/stuff", true).unwrap();
+        assert_eq!(m2.mode, MountMode::Ro);
+        assert_eq!(m2.spec, cwd.join("data/stuff").to_string_lossy());
+    }
+
+    #[test]
+    fn test_parse_cli_mounts_mixed() {
+        let home_rel = vec!["~/data".to_string(), "ro:~/.config".to_string()];
+        let absolute = vec!["/nix/store".to_string(), "o:/tmp/overlay".to_string()];
+
+        let mounts = parse_cli_mounts(&home_rel, &absolute).unwrap();
+
+        assert_eq!(mounts.len(), 4);
+
+        assert_eq!(mounts[0].spec, "~/data");
+        assert_eq!(mounts[0].mode, MountMode::Rw);
+        assert!(mounts[0].home_relative);
+
+        assert_eq!(mounts[1].spec, "~/.config");
+        assert_eq!(mounts[1].mode, MountMode::Ro);
+        assert!(mounts[1].home_relative);
+
+        assert_eq!(mounts[2].spec, "/nix/store");
+        assert_eq!(mounts[2].mode, MountMode::Rw);
+        assert!(!mounts[2].home_relative);
+
+        assert_eq!(mounts[3].spec, "/tmp/overlay");
+        assert_eq!(mounts[3].mode, MountMode::Overlay);
+        assert!(!mounts[3].home_relative);
+    }
+
+    #[test]
+    fn test_security_args() {
+        assert_eq!(
+            security_args(&SecurityConfig::default()),
+            ["--cap-drop", "ALL", "--security-opt", "no-new-privileges"]
+        );
+        let security = SecurityConfig {
+            cap_drop: vec!["NET_RAW".to_string()],
+            cap_add: vec!["SYS_PTRACE".to_string()],
+            seccomp_profile: Some("/etc/ab/seccomp.json".to_string()),
+            no_new_privileges: false,
+            read_only_rootfs: true,
+        };
+        assert_eq!(
+            security_args(&security),
+            [
+                "--cap-drop",
+                "NET_RAW",
+                "--cap-add",
+                "SYS_PTRACE",
+                "--security-opt",
+                "seccomp=/etc/ab/seccomp.json",
+                "--read-only",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gpu_args() {
+        let all = ["all".to_string()];
+        let pair = ["0".to_string(), "1".to_string()];
+        assert!(gpu_args(&[], None, true).is_empty());
+        assert_eq!(gpu_args(&all, None, false), ["--gpus", "all"]);
+        assert_eq!(gpu_args(&pair, None, false), ["--gpus", "\"device=0,1\""]);
+        assert_eq!(
+            gpu_args(&all, None, true),
+            ["--device", "nvidia.com/gpu=all"]
+        );
+        assert_eq!(
+            gpu_args(&pair, Some("amd.com/gpu"), true),
+            ["--device", "amd.com/gpu=0", "--device", "amd.com/gpu=1"]
+        );
+    }
+
+    #[test]
+    fn test_take_cli_tmpfs_and_tmpfs_args() {
+        let mut home_relative = vec!["tmpfs:~/.cache".to_string(), "ro:~/.config".to_string()];
+        let mut absolute = vec!["tmpfs:/tmp".to_string()];
+        let tmpfs = take_cli_tmpfs(&mut home_relative, &mut absolute).unwrap();
+        assert_eq!(tmpfs, ["~/.cache", "/tmp"]);
+        assert_eq!(home_relative, ["ro:~/.config"]);
+        assert!(absolute.is_empty());
+        assert!(take_cli_tmpfs(&mut vec!["tmpfs:cache".to_string()], &mut vec![]).is_err());
+
+        assert_eq!(
+            tmpfs_args("/home/me/.cache", MountSyntax::Volume),
+            ["--tmpfs", "/home/me/.cache"]
+        );
+        assert_eq!(
+            tmpfs_args("/tmp", MountSyntax::Mount),
+            ["--mount", "type=tmpfs,destination=/tmp"]
+        );
+    }
+
+    #[test]
+    fn test_take_cli_volumes_and_volume_args() {
+        let mut home_relative = vec![
+            "volume:cargo-cache:~/.cargo/registry".to_string(),
+            "~/.config".to_string(),
+        ];
+        let mut absolute = vec!["volume:models:/models:ro".to_string()];
+        let volumes = take_cli_volumes(&mut home_relative, &mut absolute).unwrap();
+        assert_eq!(
+            volumes,
+            ["cargo-cache:~/.cargo/registry", "models:/models:ro"]
+        );
+        assert_eq!(home_relative, ["~/.config"]);
+        assert!(take_cli_volumes(&mut vec!["volume:cache".to_string()], &mut vec![]).is_err());
+
+        assert_eq!(
+            volume_args("models:/models:ro", MountSyntax::Volume),
+            ["-v", "models:/models:ro"]
+        );
+        assert_eq!(
+            volume_args("models:/models:ro", MountSyntax::Mount),
+            [
+                "--mount",
+                "type=volume,source=models,target=/models,readonly"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_cli_mounts_empty() {
+        let mounts = parse_cli_mounts(&[], &[]).unwrap();
+        assert!(mounts.is_empty());
+    }
+
+    #[test]
+    fn test_parse_cli_mount_tilde_src_absolute_dst() {
+        // ~/src:/app - tilde on source, absolute on dest
+        let m = parse_single_cli_mount("rw:~/src:/app", true).unwrap();
+        assert_eq!(m.mode, MountMode::Rw);
+        assert_eq!(m.spec, "~/src:/app");
+    }
+
+    #[test]
+    fn test_parse_cli_mount_absolute_src_tilde_dst() {
+        // /host/path:~/data - absolute source, tilde dest
+        let m = parse_single_cli_mount("/run/user/1000/socket:~/.gnupg/socket", true).unwrap();
+        assert_eq!(m.mode, MountMode::Rw);
+        assert_eq!(m.spec, "/run/user/1000/socket:~/.gnupg/socket");
+    }
+
+    #[test]
+    fn test_mount_mode_as_str() {
+        assert_eq!(MountMode::Ro.as_str(), "ro");
+        assert_eq!(MountMode::Rw.as_str(), "rw");
+        assert_eq!(MountMode::Overlay.as_str(), "O");
+    }
+
+    #[test]
+    fn test_should_skip_path() {
+        // Test exact string matching (no wildcards) - glob crate matches exact strings
+        let skip_paths = vec!["/nix/store".to_string(), "/nix/var/nix".to_string()];
+
+        assert!(should_skip_path(Path::new("/nix/store"), &skip_paths));
+        // With glob crate, exact string patterns don't do prefix matching
+        assert!(!should_skip_path(
+            Path::new("/nix/store/package"),
+            &skip_paths
+        ));
+        assert!(should_skip_path(Path::new("/nix/var/nix"), &skip_paths));
+        assert!(!should_skip_path(Path::new("/home/user"), &skip_paths));
+        assert!(!should_skip_path(Path::new("/nix"), &skip_paths));
+        assert!(!should_skip_path(Path::new("/tmp"), &skip_paths));
+    }
+
+    #[test]
+    fn test_glob_pattern_matching() {
+        // Test glob patterns using glob crate
+        let skip_patterns = vec![
+            "/nix/store/glibc*".to_string(),
+            "/tmp/test-*.txt".to_string(),
+        ];
+
+        // Exact paths don't match
+        assert!(!should_skip_path(Path::new("/nix/store"), &skip_patterns));
+
+        // Matches glob patterns
+        assert!(should_skip_path(
+            Path::new("/nix/store/glibc-2.39"),
+            &skip_patterns
+        ));
+        assert!(should_skip_path(
+            Path::new("/nix/store/glibc-2.39-123"),
+            &skip_patterns
+        ));
+        assert!(should_skip_path(
+            Path::new("/tmp/test-file.txt"),
+            &skip_patterns
+        ));
+        assert!(should_skip_path(
+            Path::new("/tmp/test-123.txt"),
+            &skip_patterns
+        ));
+
+        // Non-matching paths
+        assert!(!should_skip_path(
+            Path::new("/nix/store/rustc-1.70"),
+            &skip_patterns
+        ));
+        assert!(!should_skip_path(
+            Path::new("/tmp/test-file"),
+            &skip_patterns
+        ));
+        assert!(!should_skip_path(
+            Path::new("/tmp/other.txt"),
+            &skip_patterns
+        ));
+    }
+
+    #[test]
+    fn test_add_mounts_skips_special_paths() {
+        // Test that paths matching skip_mounts are filtered out
+        // Create a symlink under /tmp that points to /nix (which would be in skip_paths)
+        let temp_dir = std::env::temp_dir().join(format!("ab_skip_paths_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+
+        let nix_symlink = temp_dir.join("nix-link");
+        // Create a symlink to /nix (which exists on NixOS systems)
+        let _ = std::os::unix::fs::symlink("/nix", &nix_symlink);
+
+        let mut binds = vec![];
+
+        let mount = Mount {
+            spec: nix_symlink.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        // With skip paths matching /nix, the resolved /nix path should be skipped
+        // But the symlink itself should still be added
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            true,
+            &["/nix".to_string()],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should have 1 mount (the symlink itself, but not /nix)
+        // Note: on non-NixOS systems, /nix might not exist, so the mount might be filtered out
+        // as non-existent. We just check it's either 0 or 1 (not more).
+        assert!(binds.len() <= 1);
+    }
+
+    #[test]
+    fn test_add_mounts_skip_paths_always_respected() {
+        // Test that skip_mounts are always respected, even when should_skip=false
+        let temp_dir = std::env::temp_dir().join(format!("ab_skip_always_{}", std::process::id()));
+        let nix_dir = temp_dir.join("nix");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&nix_dir).unwrap();
+
+        let mut binds = vec![];
+
+        let mount = Mount {
+            spec: nix_dir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        // Even though should_skip=false, skip_mounts should still be respected
+        // (special paths should never be mounted)
+        add_mounts(
+            &[&mount],
+            &mut binds,
+            false, // should_skip=false only affects coverage checks, not skip_mounts
+            &[nix_dir.to_string_lossy().to_string()],
+            &[],
+            RootPrefix::default(),
+            None,
+            0,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        // Should have 0 mounts (skipped due to skip_mounts)
+        assert_eq!(binds.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_mode_prefix() {
+        assert_eq!(
+            parse_mode_prefix("ro:~/data"),
+            Some((MountMode::Ro, "~/data"))
+        );
+        assert_eq!(
+            parse_mode_prefix("rw:/path"),
+            Some((MountMode::Rw, "/path"))
+        );
+        assert_eq!(
+            parse_mode_prefix("o:~/.gnupg"),
+            Some((MountMode::Overlay, "~/.gnupg"))
+        );
+        assert_eq!(parse_mode_prefix("~/data"), None);
+        assert_eq!(parse_mode_prefix("/nix/store"), None);
+    }
+
+    #[test]
+    fn test_derive_container_name_uses_workspace_path() {
+        use agent_box_common::config::Config;
+        use std::collections::HashMap;
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: agent_box_common::config::RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig::default(),
+        };
+
+        let workspace_path = PathBuf::from("/workspaces/git/fr/agent-box/main");
+        let name = derive_container_name(&config, &workspace_path, false);
+
+        assert!(name.starts_with("ab-agent-box-main-"));
+        assert!(name.len() <= 63);
+    }
+
+    #[test]
+    fn test_derive_container_name_uses_local_prefix() {
+        use agent_box_common::config::Config;
+        use std::collections::HashMap;
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: agent_box_common::config::RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig::default(),
+        };
+
+        let workspace_path = std::env::temp_dir()
+            .join("agent-box-repo")
+            .join("agent-box-local-name-test");
+        let name = derive_container_name(&config, &workspace_path, true);
+
+        assert!(name.starts_with("ab-agent-box-local-name-test-local-"));
+        assert!(name.len() <= 63);
+    }
+
+    #[test]
+    fn test_derive_container_name_local_uses_repo_path() {
+        use agent_box_common::config::Config;
+        use std::collections::HashMap;
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: agent_box_common::config::RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig::default(),
+        };
+
+        // A local spawn inside base_repo_dir is named after the repo's relative path
+        let name = derive_container_name(&config, Path::new("/repos/fr/Agent Box"), true);
+        assert!(name.starts_with("ab-fr-agent-box-local-"), "{}", name);
+        assert!(name.len() <= 63);
+
+        // Long paths are truncated without losing the local tag
+        let long = format!("/repos/{}", "nested/".repeat(20));
+        let name = derive_container_name(&config, Path::new(&long), true);
+        assert!(name.starts_with("ab-nested-nested-"), "{}", name);
+        assert!(name.contains("-local-"), "{}", name);
+        assert!(name.len() <= 63);
+    }
+
+    #[test]
+    fn test_context_file_created_and_mounted() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
+
+        // Create a test workspace directory
+        let temp_dir = std::env::temp_dir().join(format!("ab_context_{}", std::process::id()));
+        let workspace_path = temp_dir.join("workspace");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&workspace_path).unwrap();
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig::default(),
+        };
+
+        let resolved_profile = ResolvedProfile {
+            mounts: vec![],
+            env: vec![],
+            env_passthrough: vec![],
+            ports: vec![],
+            hosts: vec![],
+            context: vec![
+                "context-line-1".to_string(),
+                "context-line-2".to_string(),
+                "context-line-3".to_string(),
+            ],
+            command: None,
+            network: None,
+            resources: Default::default(),
+            build: None,
+            secrets: Default::default(),
+            allow_hosts: Vec::new(),
+            tmpfs: Vec::new(),
+            volumes: Vec::new(),
+            gpu: None,
+        };
+
+        let container_config = build_container_config(
+            &config,
+            &workspace_path,
+            &workspace_path,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &resolved_profile,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Find the context mount (host side should be our temp context file)
+        let context_mount = container_config
+            .mounts
+            .iter()
+            .find(|m| m.starts_with("/tmp/agent-box-context-") && m.ends_with(":/tmp/context:rw"))
+            .expect("Context mount not found");
+
+        // Extract the host path from the mount string (format: host:container:mode)
+        let parts: Vec<&str> = context_mount.split(':').collect();
+        assert_eq!(parts.len(), 3, "Mount should have 3 parts");
+        let host_path = parts[0];
+        assert_eq!(parts[1], "/tmp/context");
+        assert_eq!(parts[2], "rw");
+
+        // Verify the context file exists and has correct content
+        assert!(
+            PathBuf::from(host_path).exists(),
+            "Context file should exist at {}",
+            host_path
+        );
+
+        let content = fs::read_to_string(host_path).unwrap();
+        assert_eq!(content, "context-line-1\ncontext-line-2\ncontext-line-3");
+
+        // Clean up
+        let _ = fs::remove_file(host_path);
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_no_context_file_when_empty() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
+
+        // Create a test workspace directory
+        let temp_dir = std::env::temp_dir().join(format!("ab_no_context_{}", std::process::id()));
+        let workspace_path = temp_dir.join("workspace");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&workspace_path).unwrap();
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig::default(),
+        };
+
+        let resolved_profile = ResolvedProfile {
+            mounts: vec![],
+            env: vec![],
+            env_passthrough: vec![],
+            ports: vec![],
+            hosts: vec![],
+            context: vec![], // Empty context
+            command: None,
+            network: None,
+            resources: Default::default(),
+            build: None,
+            secrets: Default::default(),
+            allow_hosts: Vec::new(),
+            tmpfs: Vec::new(),
+            volumes: Vec::new(),
+            gpu: None,
+        };
+
+        let container_config = build_container_config(
+            &config,
+            &workspace_path,
+            &workspace_path,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &resolved_profile,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Verify no context mount exists
+        let context_mount = container_config
+            .mounts
+            .iter()
+            .find(|m| m.contains("/tmp/context"));
+
+        assert!(
+            context_mount.is_none(),
+            "Should not have context mount when context is empty"
+        );
+
+        // Clean up
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_context_path_tilde_expansion() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
+
+        // Create a test workspace directory
+        let temp_dir = std::env::temp_dir().join(format!("ab_tilde_{}", std::process::id()));
+        let workspace_path = temp_dir.join("workspace");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&workspace_path).unwrap();
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "~/.my-context".to_string(), // Test tilde expansion
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig::default(),
+        };
+
+        let resolved_profile = ResolvedProfile {
+            mounts: vec![],
+            env: vec![],
+            env_passthrough: vec![],
+            ports: vec![],
+            hosts: vec![],
+            context: vec!["line1".to_string(), "line2".to_string()],
+            command: None,
+            network: None,
+            resources: Default::default(),
+            build: None,
+            secrets: Default::default(),
+            allow_hosts: Vec::new(),
+            tmpfs: Vec::new(),
+            volumes: Vec::new(),
+            gpu: None,
+        };
+
+        let container_config = build_container_config(
+            &config,
+            &workspace_path,
+            &workspace_path,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &resolved_profile,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        // Get the username that would be used in the container
+        let username = std::env::var("USER")
+            .or_else(|_| std::env::var("LOGNAME"))
+            .unwrap_or_else(|_| "user".to_string());
+
+        let expected_container_home = format!("/home/{}", username);
+        let expected_path = format!("{}/.my-context", expected_container_home);
+
+        // Find the context mount
+        let context_mount = container_config
+            .mounts
+            .iter()
+            .find(|m| m.contains(&expected_path))
+            .expect("Context mount with expanded path not found");
+
+        // Verify the path was expanded correctly
+        assert!(
+            context_mount.ends_with(&format!(":{}:rw", expected_path)),
+            "Context path should be expanded to {} but got {}",
+            expected_path,
+            context_mount
+        );
+
+        // Clean up the temp file
+        let parts: Vec<&str> = context_mount.split(':').collect();
+        let host_path = parts[0];
+        let _ = fs::remove_file(host_path);
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_managed_portal_uses_override_socket() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
+
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab_managed_portal_{}", std::process::id()));
+        let workspace_path = temp_dir.join("workspace");
+        let socket_path = temp_dir.join("portal.sock");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&workspace_path).unwrap();
+        fs::write(&socket_path, b"").unwrap();
+
+        let portal = agent_box_common::portal::PortalConfig {
+            global: false,
+            socket_path: "/run/user/1000/agent-portal/portal.sock".to_string(),
+            ..Default::default()
+        };
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal,
+        };
+
+        let resolved_profile = ResolvedProfile::default();
+        let container_config = build_container_config(
+            &config,
+            &workspace_path,
+            &workspace_path,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &resolved_profile,
+            &[],
+            &[],
+            &[],
+            &[],
+            Some(socket_path.as_path()),
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        assert!(
+            container_config
+                .env
+                .iter()
+                .any(|v| v == &format!("AGENT_PORTAL_SOCKET={}", socket_path.display()))
+        );
+        assert!(
+            container_config.mounts.iter().any(|m| {
+                m == &format!("{}:{}:rw", socket_path.display(), socket_path.display())
+            })
+        );
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_managed_portal_without_override_does_not_mount_socket() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
+
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab_managed_portal_none_{}", std::process::id()));
+        let workspace_path = temp_dir.join("workspace");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&workspace_path).unwrap();
+
+        let portal = agent_box_common::portal::PortalConfig {
+            global: false,
+            ..Default::default()
+        };
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal,
+        };
+
+        let resolved_profile = ResolvedProfile::default();
+        let container_config = build_container_config(
+            &config,
+            &workspace_path,
+            &workspace_path,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &resolved_profile,
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        assert!(
+            !container_config
+                .env
+                .iter()
+                .any(|v| v.starts_with("AGENT_PORTAL_SOCKET="))
+        );
+
+        let _ = fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_build_container_config_passes_through_terminal_env_and_terminfo() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
+
+        let temp_dir = std::env::temp_dir().join(format!("ab_terminfo_{}", std::process::id()));
+        let workspace_path = temp_dir.join("workspace");
+        let terminfo_dir = temp_dir.join("terminfo");
+        let terminfo_entry = terminfo_dir.join("x").join("xterm-kitty");
+        let _ = fs::remove_dir_all(&temp_dir);
+        fs::create_dir_all(&workspace_path).unwrap();
+        fs::create_dir_all(terminfo_entry.parent().unwrap()).unwrap();
+        fs::write(&terminfo_entry, b"fake terminfo").unwrap();
+
+        let old_term = std::env::var_os("TERM");
+        let old_colorterm = std::env::var_os("COLORTERM");
+        let old_terminfo = std::env::var_os("TERMINFO");
+
+        unsafe {
+            std::env::set_var("TERM", "xterm-kitty");
+            std::env::set_var("COLORTERM", "truecolor");
+            std::env::set_var("TERMINFO", &terminfo_dir);
+        }
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig::default(),
+        };
+
+        let container_config = build_container_config(
+            &config,
+            &workspace_path,
+            &workspace_path,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &ResolvedProfile::default(),
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        assert!(container_config.env.iter().any(|v| v == "TERM=xterm-kitty"));
+        assert!(
+            container_config
+                .env
+                .iter()
+                .any(|v| v == "COLORTERM=truecolor")
+        );
+        assert!(
+            container_config
+                .env
+                .iter()
+                .any(|v| v == &format!("TERMINFO={}", terminfo_dir.display()))
+        );
+        assert!(
+            container_config
+                .mounts
+                .iter()
+                .any(|m| m == &format!("{}:{}:ro", terminfo_dir.display(), terminfo_dir.display()))
+        );
+
+        unsafe {
+            match old_term {
+                Some(value) => std::env::set_var("TERM", value),
+                None => std::env::remove_var("TERM"),
+            }
+            match old_colorterm {
+                Some(value) => std::env::set_var("COLORTERM", value),
+                None => std::env::remove_var("COLORTERM"),
+            }
+            match old_terminfo {
+                Some(value) => std::env::set_var("TERMINFO", value),
+                None => std::env::remove_var("TERMINFO"),
+            }
+        }
+
+        let _ = fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_parse_cli_mount_relative_path_resolves_against_cwd() {
-        let cwd = std::env::current_dir().unwrap();
+    fn test_build_container_config_mounts_common_dir_for_linked_worktree() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
 
-        let m1 = parse_single_cli_mount("data/stuff", true).unwrap();
-        assert!(m1.spec.starts_with('/'));
-        assert_eq!(m1.spec, cwd.join("data/stuff").to_string_lossy());
+        let git = |dir: &Path, args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .current_dir(dir)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+        };
 
-        let m2 = parse_single_cli_mount("ro:data/stuff", true).unwrap();
-        assert_eq!(m2.mode, MountMode::Ro);
-        assert_eq!(m2.spec, cwd.join("data/stuff").to_string_lossy());
+        let temp_dir = std::env::temp_dir().join(format!("ab_linked_wt_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let main = temp_dir.join("main");
+        let linked = temp_dir.join("linked");
+        fs::create_dir_all(&main).unwrap();
+        git(&main, &["init", "-q"]);
+        git(&main, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(
+            &main,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "linked",
+                linked.to_str().unwrap(),
+            ],
+        );
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: HashMap::new(),
+            runtime: RuntimeConfig {
+                backend: "podman".to_string(),
+                image: "test:latest".to_string(),
+                entrypoint: None,
+                mounts: Default::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                dns: vec![],
+                skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+
+        let container_config = build_container_config(
+            &config,
+            &linked,
+            &linked,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &ResolvedProfile::default(),
+            &[],
+            &[],
+            &[],
+            &[],
+            None,
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        let common_dir = main.join(".git").canonicalize().unwrap();
+        assert!(container_config.mounts.contains(&format!(
+            "{}:{}:rw",
+            common_dir.display(),
+            common_dir.display()
+        )));
+
+        let _ = fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_parse_cli_mounts_mixed() {
-        let home_rel = vec!["~/data".to_string(), "ro:~/.config".to_string()];
-        let absolute = vec!["/nix/store".to_string(), "o:/tmp/overlay".to_string()];
+    fn test_build_container_config_image_workdir_skips_workspace_bind() {
+        use agent_box_common::config::{Config, ResolvedProfile};
+        use std::fs;
+        use std::path::PathBuf;
 
-        let mounts = parse_cli_mounts(&home_rel, &absolute).unwrap();
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab_image_workdir_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let source = temp_dir.join("source");
+        let workspace = temp_dir.join("workspace");
+        let data = temp_dir.join("data");
+        for dir in [source.join(".git"), workspace.join(".jj"), data.clone()] {
+            fs::create_dir_all(dir).unwrap();
+        }
 
-        assert_eq!(mounts.len(), 4);
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+        let profile = ResolvedProfile {
+            mounts: vec![Mount {
+                spec: data.to_string_lossy().to_string(),
+                home_relative: false,
+                mode: MountMode::Ro,
+                propagation: None,
+                recursive: true,
+            }],
+            ..Default::default()
+        };
 
-        assert_eq!(mounts[0].spec, "~/data");
-        assert_eq!(mounts[0].mode, MountMode::Rw);
-        assert!(mounts[0].home_relative);
+        let build = |image_workdir: Option<&str>| {
+            build_container_config(
+                &config,
+                &workspace,
+                &source,
+                false,
+                false,
+                false,
+                false,
+                None,
+                &profile,
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                None,
+                true,
+                None,
+                image_workdir,
+                None,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+        };
 
-        assert_eq!(mounts[1].spec, "~/.config");
-        assert_eq!(mounts[1].mode, MountMode::Ro);
-        assert!(mounts[1].home_relative);
+        let workspace_bind = format!("{0}:{0}:rw", workspace.display());
+        let source_git = format!("{0}:{0}:rw", source.join(".git").display());
+        let data_bind = format!("{0}:{0}:ro", data.display());
 
-        assert_eq!(mounts[2].spec, "/nix/store");
-        assert_eq!(mounts[2].mode, MountMode::Rw);
-        assert!(!mounts[2].home_relative);
+        let image_config = build(Some("/src/app"));
+        assert_eq!(image_config.working_dir, "/src/app");
+        assert!(
+            !image_config
+                .mounts
+                .iter()
+                .any(|m| m.starts_with(&format!("{}:", workspace.display())))
+        );
+        assert!(image_config.mounts.contains(&source_git));
+        assert!(image_config.mounts.contains(&data_bind));
 
-        assert_eq!(mounts[3].spec, "/tmp/overlay");
-        assert_eq!(mounts[3].mode, MountMode::Overlay);
-        assert!(!mounts[3].home_relative);
-    }
+        let bound_config = build(None);
+        assert_eq!(bound_config.mounts[0], workspace_bind);
+        assert_eq!(
+            bound_config.working_dir,
+            workspace.canonicalize().unwrap().to_string_lossy()
+        );
 
-    #[test]
-    fn test_parse_cli_mounts_empty() {
-        let mounts = parse_cli_mounts(&[], &[]).unwrap();
-        assert!(mounts.is_empty());
+        let _ = fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_parse_cli_mount_tilde_src_absolute_dst() {
-        // ~/src:/app - tilde on source, absolute on dest
-        let m = parse_single_cli_mount("rw:~/src:/app", true).unwrap();
-        assert_eq!(m.mode, MountMode::Rw);
-        assert_eq!(m.spec, "~/src:/app");
+    fn test_check_clean_env() {
+        let profile_env = vec!["FOO=bar".to_string(), "TOKEN=@~/.token".to_string()];
+        let env = vec![
+            "USER=u".to_string(),
+            "HOME=/home/u".to_string(),
+            "FOO=bar".to_string(),
+            "TOKEN=secret".to_string(),
+        ];
+        assert!(check_clean_env(&env, &profile_env).is_ok());
+
+        let mut stray = env.clone();
+        stray.push("TERM=xterm".to_string());
+        let err = check_clean_env(&stray, &profile_env)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unexpected env vars in container env: TERM"));
     }
 
     #[test]
-    fn test_parse_cli_mount_absolute_src_tilde_dst() {
-        // /host/path:~/data - absolute source, tilde dest
-        let m = parse_single_cli_mount("/run/user/1000/socket:~/.gnupg/socket", true).unwrap();
-        assert_eq!(m.mode, MountMode::Rw);
-        assert_eq!(m.spec, "/run/user/1000/socket:~/.gnupg/socket");
-    }
+    fn test_build_container_config_clean_env_has_no_host_vars() {
+        use agent_box_common::config::{Config, ResolvedProfile};
+        use std::path::PathBuf;
 
-    #[test]
-    fn test_mount_mode_as_str() {
-        assert_eq!(MountMode::Ro.as_str(), "ro");
-        assert_eq!(MountMode::Rw.as_str(), "rw");
-        assert_eq!(MountMode::Overlay.as_str(), "O");
+        let workspace = std::env::temp_dir().join(format!("ab_clean_env_{}", std::process::id()));
+        std::fs::create_dir_all(&workspace).unwrap();
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+        // PATH is always set on the host, so it shows up unless the env is clean
+        let profile = ResolvedProfile {
+            env: vec!["FOO=bar".to_string()],
+            env_passthrough: vec!["PATH".to_string()],
+            ..Default::default()
+        };
+
+        let build = |clean_env: bool| {
+            build_container_config(
+                &config,
+                &workspace,
+                &workspace,
+                true,
+                false,
+                false,
+                clean_env,
+                None,
+                &profile,
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                None,
+                true,
+                None,
+                None,
+                None,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+        };
+
+        let keys = |env: &[String]| -> Vec<String> {
+            env.iter()
+                .map(|e| e.split_once('=').unwrap().0.to_string())
+                .collect()
+        };
+
+        assert_eq!(keys(&build(true).env), ["USER", "HOME", "FOO"]);
+        assert!(keys(&build(false).env).contains(&"PATH".to_string()));
+
+        let _ = std::fs::remove_dir_all(&workspace);
     }
 
     #[test]
-    fn test_should_skip_path() {
-        // Test exact string matching (no wildcards) - glob crate matches exact strings
-        let skip_paths = vec!["/nix/store".to_string(), "/nix/var/nix".to_string()];
+    fn test_build_container_config_merges_and_dedups_groups() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
+        use std::path::PathBuf;
 
-        assert!(should_skip_path(Path::new("/nix/store"), &skip_paths));
-        // With glob crate, exact string patterns don't do prefix matching
-        assert!(!should_skip_path(
-            Path::new("/nix/store/package"),
-            &skip_paths
-        ));
-        assert!(should_skip_path(Path::new("/nix/var/nix"), &skip_paths));
-        assert!(!should_skip_path(Path::new("/home/user"), &skip_paths));
-        assert!(!should_skip_path(Path::new("/nix"), &skip_paths));
-        assert!(!should_skip_path(Path::new("/tmp"), &skip_paths));
+        let workspace = std::env::temp_dir().join(format!("ab_group_add_{}", std::process::id()));
+        std::fs::create_dir_all(&workspace).unwrap();
+
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: RuntimeConfig {
+                group_add: vec!["video".to_string(), "render".to_string()],
+                ..Default::default()
+            },
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+
+        let container_config = build_container_config(
+            &config,
+            &workspace,
+            &workspace,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &ResolvedProfile::default(),
+            &[],
+            &[],
+            &[],
+            &["render".to_string(), "989".to_string()],
+            None,
+            None,
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
+        )
+        .unwrap();
+
+        assert_eq!(container_config.group_add, ["video", "render", "989"]);
+
+        let _ = std::fs::remove_dir_all(&workspace);
     }
 
     #[test]
-    fn test_glob_pattern_matching() {
-        // Test glob patterns using glob crate
-        let skip_patterns = vec![
-            "/nix/store/glibc*".to_string(),
-            "/tmp/test-*.txt".to_string(),
-        ];
+    fn test_build_container_config_cli_command_beats_profile_command() {
+        use agent_box_common::config::{Config, ResolvedProfile};
+        use std::path::PathBuf;
 
-        // Exact paths don't match
-        assert!(!should_skip_path(Path::new("/nix/store"), &skip_patterns));
+        let workspace = std::env::temp_dir().join(format!("ab_command_{}", std::process::id()));
+        std::fs::create_dir_all(&workspace).unwrap();
 
-        // Matches glob patterns
-        assert!(should_skip_path(
-            Path::new("/nix/store/glibc-2.39"),
-            &skip_patterns
-        ));
-        assert!(should_skip_path(
-            Path::new("/nix/store/glibc-2.39-123"),
-            &skip_patterns
-        ));
-        assert!(should_skip_path(
-            Path::new("/tmp/test-file.txt"),
-            &skip_patterns
-        ));
-        assert!(should_skip_path(
-            Path::new("/tmp/test-123.txt"),
-            &skip_patterns
-        ));
+        let config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+        let profile = ResolvedProfile {
+            command: Some(vec!["./ci/agent.sh".to_string(), "--once".to_string()]),
+            ..Default::default()
+        };
+
+        let build = |command: Option<Vec<String>>| {
+            build_container_config(
+                &config,
+                &workspace,
+                &workspace,
+                true,
+                false,
+                false,
+                false,
+                None,
+                &profile,
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                command,
+                true,
+                None,
+                None,
+                None,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+            .command
+        };
+
+        assert_eq!(
+            build(None),
+            Some(vec!["./ci/agent.sh".to_string(), "--once".to_string()])
+        );
+        assert_eq!(
+            build(Some(vec!["bash".to_string()])),
+            Some(vec!["bash".to_string()])
+        );
 
-        // Non-matching paths
-        assert!(!should_skip_path(
-            Path::new("/nix/store/rustc-1.70"),
-            &skip_patterns
-        ));
-        assert!(!should_skip_path(
-            Path::new("/tmp/test-file"),
-            &skip_patterns
-        ));
-        assert!(!should_skip_path(
-            Path::new("/tmp/other.txt"),
-            &skip_patterns
-        ));
+        let _ = std::fs::remove_dir_all(&workspace);
     }
 
     #[test]
-    fn test_add_mounts_skips_special_paths() {
-        // Test that paths matching skip_mounts are filtered out
-        // Create a symlink under /tmp that points to /nix (which would be in skip_paths)
-        let temp_dir = std::env::temp_dir().join(format!("ab_skip_paths_{}", std::process::id()));
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&temp_dir).unwrap();
-
-        let nix_symlink = temp_dir.join("nix-link");
-        // Create a symlink to /nix (which exists on NixOS systems)
-        let _ = std::os::unix::fs::symlink("/nix", &nix_symlink);
+    fn test_entrypoint_precedence_and_image_entrypoint() {
+        use agent_box_common::config::{Config, ResolvedProfile};
+        use std::path::PathBuf;
 
-        let mut binds = vec![];
+        let workspace = std::env::temp_dir().join(format!("ab_entrypoint_{}", std::process::id()));
+        std::fs::create_dir_all(&workspace).unwrap();
 
-        let mount = Mount {
-            spec: nix_symlink.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
+        let mut config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+        config.runtime.entrypoint = Some(vec!["claude".to_string(), "--yolo".to_string()]);
+
+        let build = |cli_entrypoint: Option<&str>| {
+            build_container_config(
+                &config,
+                &workspace,
+                &workspace,
+                true,
+                false,
+                false,
+                false,
+                cli_entrypoint,
+                &ResolvedProfile::default(),
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                None,
+                true,
+                None,
+                None,
+                None,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
         };
 
-        // With skip paths matching /nix, the resolved /nix path should be skipped
-        // But the symlink itself should still be added
-        add_mounts(&[&mount], &mut binds, true, &["/nix".to_string()]).unwrap();
+        // Config, then CLI override
+        assert_eq!(
+            entrypoint_args(build(None).entrypoint.as_ref()),
+            ["--entrypoint", "claude --yolo"]
+        );
+        assert_eq!(
+            entrypoint_args(build(Some("codex")).entrypoint.as_ref()),
+            ["--entrypoint", "codex"]
+        );
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
+        // --use-image-entrypoint drops it, so the image's own entrypoint runs
+        let mut container_config = build(Some("codex"));
+        container_config.entrypoint = None;
+        assert!(entrypoint_args(container_config.entrypoint.as_ref()).is_empty());
 
-        // Should have 1 mount (the symlink itself, but not /nix)
-        // Note: on non-NixOS systems, /nix might not exist, so the mount might be filtered out
-        // as non-existent. We just check it's either 0 or 1 (not more).
-        assert!(binds.len() <= 1);
+        let _ = std::fs::remove_dir_all(&workspace);
     }
 
     #[test]
-    fn test_add_mounts_skip_paths_always_respected() {
-        // Test that skip_mounts are always respected, even when should_skip=false
-        let temp_dir = std::env::temp_dir().join(format!("ab_skip_always_{}", std::process::id()));
-        let nix_dir = temp_dir.join("nix");
-
-        let _ = std::fs::remove_dir_all(&temp_dir);
-        std::fs::create_dir_all(&nix_dir).unwrap();
+    fn test_spawn_record_round_trip() {
+        use agent_box_common::config::{Config, ResolvedProfile};
+        use std::path::PathBuf;
 
-        let mut binds = vec![];
+        let workspace = std::env::temp_dir().join(format!("ab_record_{}", std::process::id()));
+        std::fs::create_dir_all(&workspace).unwrap();
 
-        let mount = Mount {
-            spec: nix_dir.to_string_lossy().to_string(),
-            home_relative: false,
-            mode: MountMode::Ro,
+        let mut config = Config {
+            workspace_dir: PathBuf::from("/workspaces"),
+            base_repo_dir: PathBuf::from("/repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
         };
+        config.runtime.backend = "podman".to_string();
+        config.runtime.image = "agent:latest".to_string();
+        config.runtime.entrypoint = Some(vec!["claude".to_string()]);
 
-        // Even though should_skip=false, skip_mounts should still be respected
-        // (special paths should never be mounted)
-        add_mounts(
-            &[&mount],
-            &mut binds,
-            false, // should_skip=false only affects coverage checks, not skip_mounts
-            &[nix_dir.to_string_lossy().to_string()],
+        let mut container_config = build_container_config(
+            &config,
+            &workspace,
+            &workspace,
+            true,
+            false,
+            false,
+            false,
+            None,
+            &ResolvedProfile::default(),
+            &[],
+            &["8080:80".to_string()],
+            &["db:10.0.0.2".to_string()],
+            &[],
+            None,
+            Some(vec!["make".to_string(), "test".to_string()]),
+            true,
+            None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
         )
         .unwrap();
+        container_config.env.push("TOKEN=secret".to_string());
+        container_config.copy_in.push(CopyIn {
+            host: PathBuf::from("/host/file"),
+            container: "/etc/file".to_string(),
+        });
+
+        let path = workspace.join("spawn.json");
+        SpawnRecord::new(&config.runtime.backend, &container_config)
+            .write(&path)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
 
-        // Clean up
-        let _ = std::fs::remove_dir_all(&temp_dir);
-
-        // Should have 0 mounts (skipped due to skip_mounts)
-        assert_eq!(binds.len(), 0);
-    }
-
-    #[test]
-    fn test_parse_mode_prefix() {
-        assert_eq!(
-            parse_mode_prefix("ro:~/data"),
-            Some((MountMode::Ro, "~/data"))
-        );
+        let record = SpawnRecord::read(&path).unwrap();
+        assert_eq!(record.backend, "podman");
+        assert!(matches!(record.runtime(), Runtime::Podman(_)));
+        // The summary is only for the pre-spawn status line and isn't recorded
+        container_config.mount_summary = MountSummary::default();
+        assert_eq!(record.container, container_config);
         assert_eq!(
-            parse_mode_prefix("rw:/path"),
-            Some((MountMode::Rw, "/path"))
+            SpawnRecord::from_json(&record.to_json().unwrap()).unwrap(),
+            record
         );
-        assert_eq!(
-            parse_mode_prefix("o:~/.gnupg"),
-            Some((MountMode::Overlay, "~/.gnupg"))
+
+        let mut future = record.clone();
+        future.version += 1;
+        assert!(SpawnRecord::from_json(&future.to_json().unwrap()).is_err());
+        let mut unknown = record;
+        unknown.backend = "lxc".to_string();
+        assert!(SpawnRecord::from_json(&unknown.to_json().unwrap()).is_err());
+
+        container_config.secret_files.push(secrets::SecretFile {
+            path: "/run/secrets/token".to_string(),
+            value: "hunter2".to_string(),
+        });
+        let secret_path = workspace.join("secret-spawn.json");
+        assert!(
+            SpawnRecord::new(&config.runtime.backend, &container_config)
+                .write(&secret_path)
+                .is_err()
         );
-        assert_eq!(parse_mode_prefix("~/data"), None);
-        assert_eq!(parse_mode_prefix("/nix/store"), None);
+        assert!(!secret_path.exists());
+
+        let _ = std::fs::remove_dir_all(&workspace);
     }
 
     #[test]
-    fn test_derive_container_name_uses_workspace_path() {
-        use agent_box_common::config::Config;
-        use std::collections::HashMap;
+    fn test_build_container_config_cli_network_beats_profile_network() {
+        use agent_box_common::config::{Config, ResolvedProfile};
+        use std::path::PathBuf;
+
+        let workspace = std::env::temp_dir().join(format!("ab_network_{}", std::process::id()));
+        std::fs::create_dir_all(&workspace).unwrap();
 
         let config = Config {
             workspace_dir: PathBuf::from("/workspaces"),
             base_repo_dir: PathBuf::from("/repos"),
             default_profile: None,
-            profiles: HashMap::new(),
-            runtime: agent_box_common::config::RuntimeConfig {
-                backend: "podman".to_string(),
-                image: "test:latest".to_string(),
-                entrypoint: None,
-                mounts: Default::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                dns: vec![],
-                skip_mounts: vec![],
-            },
+            profiles: Default::default(),
+            runtime: Default::default(),
             context: String::new(),
             context_path: "/tmp/context".to_string(),
-            portal: agent_box_common::portal::PortalConfig::default(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
         };
 
-        let workspace_path = PathBuf::from("/workspaces/git/fr/agent-box/main");
-        let name = derive_container_name(&config, &workspace_path, false);
+        let build = |profile_network: Option<&str>, cli_network: Option<&str>| {
+            let profile = ResolvedProfile {
+                network: profile_network.map(str::to_string),
+                ..Default::default()
+            };
+            build_container_config(
+                &config,
+                &workspace,
+                &workspace,
+                true,
+                false,
+                false,
+                false,
+                None,
+                &profile,
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                None,
+                true,
+                cli_network.map(str::to_string),
+                None,
+                None,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+            .network
+        };
 
-        assert!(name.starts_with("ab-agent-box-main-"));
-        assert!(name.len() <= 63);
+        assert_eq!(build(None, None), None);
+        assert_eq!(build(Some("none"), None), Some("none".to_string()));
+        assert_eq!(build(Some("none"), Some("host")), Some("host".to_string()));
+        assert_eq!(build(None, Some("host")), Some("host".to_string()));
+
+        let _ = std::fs::remove_dir_all(&workspace);
     }
 
     #[test]
-    fn test_derive_container_name_uses_local_prefix() {
-        use agent_box_common::config::Config;
+    fn test_build_container_config_colocated_skips_source_mounts() {
+        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
         use std::collections::HashMap;
+        use std::fs;
+        use std::path::PathBuf;
+
+        let temp_dir = std::env::temp_dir().join(format!("ab_colocated_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&temp_dir);
+        let source = temp_dir.join("source");
+        let colocated = temp_dir.join("colocated");
+        let plain = temp_dir.join("plain");
+        for dir in [
+            source.join(".git"),
+            source.join(".jj"),
+            colocated.join(".git"),
+            colocated.join(".jj"),
+            plain.join(".jj"),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        assert!(is_colocated_workspace(&colocated));
+        assert!(!is_colocated_workspace(&plain));
 
         let config = Config {
             workspace_dir: PathBuf::from("/workspaces"),
             base_repo_dir: PathBuf::from("/repos"),
             default_profile: None,
             profiles: HashMap::new(),
-            runtime: agent_box_common::config::RuntimeConfig {
+            runtime: RuntimeConfig {
                 backend: "podman".to_string(),
                 image: "test:latest".to_string(),
                 entrypoint: None,
@@ -1493,41 +7396,112 @@ mod tests {
                 hosts: vec![],
                 dns: vec![],
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
-            portal: agent_box_common::portal::PortalConfig::default(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
         };
 
-        let workspace_path = std::env::temp_dir()
-            .join("agent-box-repo")
-            .join("agent-box-local-name-test");
-        let name = derive_container_name(&config, &workspace_path, true);
+        let build = |workspace: &Path, source_ro: bool| {
+            build_container_config(
+                &config,
+                workspace,
+                &source,
+                false,
+                false,
+                source_ro,
+                false,
+                None,
+                &ResolvedProfile::default(),
+                &[],
+                &[],
+                &[],
+                &[],
+                None,
+                None,
+                true,
+                None,
+                None,
+                None,
+                &mut MountResolutionCache::default(),
+            )
+            .unwrap()
+        };
 
-        assert!(name.starts_with("ab-agent-box-local-name-test-"));
-        assert!(name.len() <= 63);
+        let source_git = format!("{0}:{0}:rw", source.join(".git").display());
+        let source_jj = format!("{0}:{0}:rw", source.join(".jj").display());
+
+        let colocated_config = build(&colocated, false);
+        assert!(!colocated_config.mounts.contains(&source_git));
+        assert!(!colocated_config.mounts.contains(&source_jj));
+
+        let plain_config = build(&plain, false);
+        assert!(plain_config.mounts.contains(&source_git));
+        assert!(plain_config.mounts.contains(&source_jj));
+
+        // --source-ro only changes the .git/.jj mounts; the workspace stays writable
+        let source_ro_config = build(&plain, true);
+        assert!(
+            source_ro_config
+                .mounts
+                .contains(&format!("{0}:{0}:ro", source.join(".git").display()))
+        );
+        assert!(
+            source_ro_config
+                .mounts
+                .contains(&format!("{0}:{0}:ro", source.join(".jj").display()))
+        );
+        assert!(!source_ro_config.mounts.contains(&source_git));
+        assert_eq!(
+            source_ro_config.mounts[0],
+            format!("{0}:{0}:rw", plain.display())
+        );
+
+        let _ = fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_context_file_created_and_mounted() {
+    fn test_redaction_masks_printed_command_but_not_container_env() {
         use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
         use std::collections::HashMap;
-        use std::fs;
         use std::path::PathBuf;
 
-        // Create a test workspace directory
-        let temp_dir = std::env::temp_dir().join(format!("ab_context_{}", std::process::id()));
-        let workspace_path = temp_dir.join("workspace");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&workspace_path).unwrap();
-
         let config = Config {
             workspace_dir: PathBuf::from("/workspaces"),
             base_repo_dir: PathBuf::from("/repos"),
             default_profile: None,
             profiles: HashMap::new(),
             runtime: RuntimeConfig {
-                backend: "podman".to_string(),
+                backend: "docker".to_string(),
                 image: "test:latest".to_string(),
                 entrypoint: None,
                 mounts: Default::default(),
@@ -1537,84 +7511,351 @@ mod tests {
                 hosts: vec![],
                 dns: vec![],
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec!["MY_CREDS".to_string()],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
-            portal: agent_box_common::portal::PortalConfig::default(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
         };
-
         let resolved_profile = ResolvedProfile {
-            mounts: vec![],
-            env: vec![],
-            env_passthrough: vec![],
-            ports: vec![],
-            hosts: vec![],
-            context: vec![
-                "context-line-1".to_string(),
-                "context-line-2".to_string(),
-                "context-line-3".to_string(),
+            env: vec![
+                "GITHUB_TOKEN=ghp_supersecret".to_string(),
+                "MY_CREDS=hunter2".to_string(),
+                "EDITOR=vim".to_string(),
             ],
+            ..Default::default()
         };
 
+        let ws = std::env::temp_dir().join(format!("ab_redact_ws_{}", std::process::id()));
+        std::fs::create_dir_all(&ws).unwrap();
         let container_config = build_container_config(
             &config,
-            &workspace_path,
-            &workspace_path,
+            &ws,
+            &ws,
             true,
             false,
+            false,
+            false,
             None,
             &resolved_profile,
             &[],
             &[],
             &[],
+            &[],
             None,
             None,
             true,
             None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
         )
         .unwrap();
 
-        // Find the context mount (host side should be our temp context file)
-        let context_mount = container_config
-            .mounts
-            .iter()
-            .find(|m| m.starts_with("/tmp/agent-box-context-") && m.ends_with(":/tmp/context:rw"))
-            .expect("Context mount not found");
+        // Real values still reach the backend
+        assert!(
+            container_config
+                .env
+                .contains(&"GITHUB_TOKEN=ghp_supersecret".to_string())
+        );
+        assert!(
+            container_config
+                .env
+                .contains(&"MY_CREDS=hunter2".to_string())
+        );
+
+        let mut args = vec!["run".to_string()];
+        for e in &container_config.env {
+            args.push("-e".to_string());
+            args.push(e.clone());
+        }
+        args.push(container_config.image.clone());
+
+        let printed = format_command("docker", &redact_args(&args, &container_config.redact_env));
+        assert!(printed.contains("-e GITHUB_TOKEN=****"));
+        assert!(printed.contains("-e MY_CREDS=****"));
+        assert!(printed.contains("-e EDITOR=vim"));
+        assert!(!printed.contains("ghp_supersecret"));
+        assert!(!printed.contains("hunter2"));
+
+        // Redaction works on a copy; the original args are untouched
+        assert!(args.contains(&"GITHUB_TOKEN=ghp_supersecret".to_string()));
+
+        let _ = std::fs::remove_dir_all(&ws);
+    }
+
+    fn start_failure(code: i32, stderr: &str) -> eyre::Report {
+        use std::os::unix::process::ExitStatusExt;
+
+        StartFailure {
+            runtime: "Docker",
+            status: std::process::ExitStatus::from_raw(code << 8),
+            stderr: stderr.to_string(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_should_retry_spawn_only_transient_start_failures() {
+        assert!(should_retry_spawn(&start_failure(
+            125,
+            "docker: Cannot connect to the Docker daemon at unix:///var/run/docker.sock. Is the docker daemon running?"
+        )));
+        assert!(should_retry_spawn(&start_failure(
+            125,
+            "Error: dial tcp 127.0.0.1:2375: connect: connection refused"
+        )));
+        // Start failure, but not a transient one
+        assert!(!should_retry_spawn(&start_failure(
+            125,
+            "docker: Error response from daemon: Conflict. The container name is already in use"
+        )));
+        // The container itself exited non-zero
+        assert!(!should_retry_spawn(&eyre::eyre!(
+            "Docker container exited with status: exit status: 1"
+        )));
+    }
+
+    #[test]
+    fn test_spawn_with_retries_stops_after_limit() {
+        let attempts = std::cell::Cell::new(0);
+        let sleeps = std::cell::RefCell::new(Vec::new());
+        let result = spawn_with_retries(
+            2,
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(start_failure(125, "daemon is not running"))
+            },
+            |d| sleeps.borrow_mut().push(d),
+        );
+        assert!(result.unwrap_err().downcast_ref::<StartFailure>().is_some());
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(
+            sleeps.into_inner(),
+            [
+                std::time::Duration::from_millis(500),
+                std::time::Duration::from_secs(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spawn_with_retries_recovers_and_skips_exit_codes() {
+        let attempts = std::cell::Cell::new(0);
+        let result = spawn_with_retries(
+            3,
+            || {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() == 1 {
+                    Err(start_failure(125, "connection refused"))
+                } else {
+                    Ok(())
+                }
+            },
+            |_| {},
+        );
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 2);
+
+        // Default of 0 retries and container exit codes are never retried
+        let attempts = std::cell::Cell::new(0);
+        let result = spawn_with_retries(
+            3,
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(eyre::eyre!(
+                    "Docker container exited with status: exit status: 2"
+                ))
+            },
+            |_| {},
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    /// Backend recording `--copy-in` spawn steps, failing the ones it's told to
+    struct CopyInBackend {
+        calls: std::sync::Mutex<Vec<String>>,
+        fail_copy: bool,
+        start_failures: std::sync::atomic::AtomicU32,
+    }
+
+    impl CopyInBackend {
+        fn new(fail_copy: bool, start_failures: u32) -> Self {
+            Self {
+                calls: std::sync::Mutex::new(Vec::new()),
+                fail_copy,
+                start_failures: std::sync::atomic::AtomicU32::new(start_failures),
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl ContainerBackend for CopyInBackend {
+        fn capabilities(&self) -> BackendCapabilities {
+            DOCKER_CAPABILITIES
+        }
+
+        fn create_container(&self, config: &ContainerConfig) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("create {}", config.name));
+            Ok(())
+        }
+
+        fn copy_into_container(&self, _container: &str, _host: &Path, dest: &str) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("cp {}", dest));
+            if self.fail_copy {
+                eyre::bail!("no such file");
+            }
+            Ok(())
+        }
+
+        fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("start {}", config.name));
+            let failures = &self.start_failures;
+            if failures.load(std::sync::atomic::Ordering::SeqCst) > 0 {
+                failures.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                return Err(start_failure(125, "connection refused"));
+            }
+            Ok(())
+        }
 
-        // Extract the host path from the mount string (format: host:container:mode)
-        let parts: Vec<&str> = context_mount.split(':').collect();
-        assert_eq!(parts.len(), 3, "Mount should have 3 parts");
-        let host_path = parts[0];
-        assert_eq!(parts[1], "/tmp/context");
-        assert_eq!(parts[2], "rw");
+        fn remove_container(&self, name: &str) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("rm {}", name));
+            Ok(())
+        }
+    }
 
-        // Verify the context file exists and has correct content
-        assert!(
-            PathBuf::from(host_path).exists(),
-            "Context file should exist at {}",
-            host_path
+    #[test]
+    fn test_spawn_with_copy_in_retries_only_the_start() {
+        let config = ContainerConfig {
+            name: "ab-r-main".to_string(),
+            copy_in: vec![CopyIn {
+                host: PathBuf::from("/host/setup.sh"),
+                container: "/work/setup.sh".to_string(),
+            }],
+            ..ContainerConfig::for_test()
+        };
+
+        let backend = CopyInBackend::new(false, 1);
+        spawn_with_copy_in(&backend, &config, 2, |_| {}).unwrap();
+        assert_eq!(
+            backend.calls(),
+            [
+                "create ab-r-main",
+                "cp /work/setup.sh",
+                "start ab-r-main",
+                "start ab-r-main"
+            ]
         );
 
-        let content = fs::read_to_string(host_path).unwrap();
-        assert_eq!(content, "context-line-1\ncontext-line-2\ncontext-line-3");
+        // Out of retries: the created container is removed
+        let backend = CopyInBackend::new(false, 2);
+        assert!(spawn_with_copy_in(&backend, &config, 1, |_| {}).is_err());
+        assert_eq!(
+            backend.calls(),
+            [
+                "create ab-r-main",
+                "cp /work/setup.sh",
+                "start ab-r-main",
+                "start ab-r-main",
+                "rm ab-r-main"
+            ]
+        );
 
-        // Clean up
-        let _ = fs::remove_file(host_path);
-        let _ = fs::remove_dir_all(&temp_dir);
+        let backend = CopyInBackend::new(true, 0);
+        assert!(spawn_with_copy_in(&backend, &config, 2, |_| {}).is_err());
+        assert_eq!(
+            backend.calls(),
+            ["create ab-r-main", "cp /work/setup.sh", "rm ab-r-main"]
+        );
     }
 
     #[test]
-    fn test_no_context_file_when_empty() {
+    fn test_spawn_refuses_secret_files_with_detach() {
+        let config = ContainerConfig {
+            detach: true,
+            secret_files: vec![secrets::SecretFile {
+                path: "/run/secrets/token".to_string(),
+                value: "hunter2".to_string(),
+            }],
+            ..ContainerConfig::for_test()
+        };
+        let err = Runtime::Docker(docker::DockerRuntime::new())
+            .spawn_container_with_retries(&config, 0)
+            .unwrap_err();
+        assert!(err.to_string().contains("--detach"), "{}", err);
+    }
+
+    #[test]
+    fn test_retry_backoff_is_capped() {
+        assert_eq!(retry_backoff(1), std::time::Duration::from_millis(500));
+        assert_eq!(retry_backoff(3), std::time::Duration::from_secs(2));
+        assert_eq!(retry_backoff(10), std::time::Duration::from_secs(8));
+    }
+
+    #[test]
+    fn test_parse_home_map() {
+        assert_eq!(
+            parse_home_map("/home/me/:/root").unwrap(),
+            HomeMap {
+                host: "/home/me".to_string(),
+                container: "/root".to_string(),
+            }
+        );
+        assert!(parse_home_map("/home/me").is_err());
+        assert!(parse_home_map("~:/root").is_err());
+        assert!(parse_home_map("/home/me:root").is_err());
+    }
+
+    #[test]
+    fn test_build_container_config_home_map_overrides_container_paths() {
         use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
         use std::collections::HashMap;
-        use std::fs;
-        use std::path::PathBuf;
 
-        // Create a test workspace directory
-        let temp_dir = std::env::temp_dir().join(format!("ab_no_context_{}", std::process::id()));
-        let workspace_path = temp_dir.join("workspace");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&workspace_path).unwrap();
+        let temp_dir = std::env::temp_dir().join(format!("ab_home_map_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let fake_home = temp_dir.join("home");
+        let ws = temp_dir.join("ws");
+        std::fs::create_dir_all(fake_home.join("data")).unwrap();
+        std::fs::create_dir_all(&ws).unwrap();
+        let fake_home = fake_home.canonicalize().unwrap();
 
         let config = Config {
             workspace_dir: PathBuf::from("/workspaces"),
@@ -1622,7 +7863,7 @@ mod tests {
             default_profile: None,
             profiles: HashMap::new(),
             runtime: RuntimeConfig {
-                backend: "podman".to_string(),
+                backend: "docker".to_string(),
                 image: "test:latest".to_string(),
                 entrypoint: None,
                 mounts: Default::default(),
@@ -1632,29 +7873,67 @@ mod tests {
                 hosts: vec![],
                 dns: vec![],
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
-            portal: agent_box_common::portal::PortalConfig::default(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
         };
-
-        let resolved_profile = ResolvedProfile {
-            mounts: vec![],
-            env: vec![],
-            env_passthrough: vec![],
-            ports: vec![],
-            hosts: vec![],
-            context: vec![], // Empty context
+        let cli_mounts = vec![Mount {
+            spec: "~/data".to_string(),
+            home_relative: true,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        }];
+        let home_map = HomeMap {
+            host: fake_home.to_string_lossy().to_string(),
+            container: "/home/mapped".to_string(),
         };
 
         let container_config = build_container_config(
             &config,
-            &workspace_path,
-            &workspace_path,
+            &ws,
+            &ws,
             true,
             false,
+            false,
+            false,
             None,
-            &resolved_profile,
+            &ResolvedProfile {
+                tmpfs: vec!["~/.cache".to_string(), "/scratch".to_string()],
+                volumes: vec!["cargo-cache:~/.cargo/registry".to_string()],
+                ..Default::default()
+            },
+            &cli_mounts,
             &[],
             &[],
             &[],
@@ -1662,136 +7941,555 @@ mod tests {
             None,
             true,
             None,
+            None,
+            Some(&home_map),
+            &mut MountResolutionCache::default(),
         )
         .unwrap();
 
-        // Verify no context mount exists
-        let context_mount = container_config
+        // tmpfs paths under ~ are in the container home
+        assert_eq!(container_config.tmpfs, ["/home/mapped/.cache", "/scratch"]);
+        assert_eq!(container_config.mount_summary.tmpfs, 2);
+        assert_eq!(
+            container_config.volumes,
+            ["cargo-cache:/home/mapped/.cargo/registry:rw"]
+        );
+        let expected = format!("{}:/home/mapped/data:ro", fake_home.join("data").display());
+        assert!(
+            container_config.mounts.contains(&expected),
+            "missing {} in {:?}",
+            expected,
+            container_config.mounts
+        );
+        assert!(
+            container_config
+                .env
+                .contains(&"HOME=/home/mapped".to_string())
+        );
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_resource_args() {
+        assert!(resource_args(&ResourcesConfig::default()).is_empty());
+        let resources = ResourcesConfig {
+            cpus: Some(1.5),
+            memory: Some("4g".to_string()),
+            memory_swap: Some("-1".to_string()),
+            pids_limit: Some(512),
+            ulimits: [("nofile", "1024:4096"), ("core", "0")]
+                .into_iter()
+                .map(|(name, limit)| (name.to_string(), limit.to_string()))
+                .collect(),
+        };
+        assert_eq!(
+            resource_args(&resources),
+            [
+                "--cpus=1.5",
+                "--memory=4g",
+                "--memory-swap=-1",
+                "--pids-limit=512",
+                "--ulimit",
+                "core=0",
+                "--ulimit",
+                "nofile=1024:4096",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replace_bind_source() {
+        let mut mounts = vec![
+            "/ws/repo/s1/sub:/ws/repo/s1/sub:ro".to_string(),
+            "/ws/repo/s1:/ws/repo/s1:rw".to_string(),
+        ];
+        replace_bind_source(
+            &mut mounts,
+            Path::new("/ws/repo/s1"),
+            Path::new("/state/1-s1/workspace"),
+        )
+        .unwrap();
+        assert_eq!(
+            mounts,
+            [
+                "/ws/repo/s1/sub:/ws/repo/s1/sub:ro",
+                "/state/1-s1/workspace:/ws/repo/s1:rw"
+            ]
+        );
+        assert!(
+            replace_bind_source(&mut mounts, Path::new("/ws/repo/s2"), Path::new("/x")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_run_args_capability_gated() {
+        let mut config = ContainerConfig {
+            command: Some(vec!["bash".to_string()]),
+            mounts: vec!["/work:/work:rw".to_string()],
+            ..ContainerConfig::for_test()
+        };
+
+        assert_eq!(
+            BackendCapabilities::for_backend("podman"),
+            PODMAN_CAPABILITIES
+        );
+        assert_eq!(
+            BackendCapabilities::for_backend("docker"),
+            DOCKER_CAPABILITIES
+        );
+
+        // --userns keep-id only for podman
+        let podman = run_args(&config, &PODMAN_CAPABILITIES).unwrap();
+        assert_eq!(&podman[5..7], ["--userns", "keep-id"]);
+        let docker = run_args(&config, &DOCKER_CAPABILITIES).unwrap();
+        assert!(!docker.contains(&"--userns".to_string()));
+        assert_eq!(&docker[5..7], ["--user", "1000:1000"]);
+        assert_eq!(docker.last().unwrap(), "bash");
+
+        // --detach starts it in the background, still with a tty to attach to,
+        // and left for the log capture to remove
+        config.detach = true;
+        let detached = run_args(&config, &DOCKER_CAPABILITIES).unwrap();
+        assert_eq!(
+            &detached[..6],
+            ["run", "--name", "ab-test", "-it", "-d", "--user"]
+        );
+        config.detach = false;
+
+        // Overlay mounts are an error for docker rather than a bad -v flag
+        config
             .mounts
-            .iter()
-            .find(|m| m.contains("/tmp/context"));
+            .push("/home/u/.cache:/home/u/.cache:O".to_string());
+        let podman = run_args(&config, &PODMAN_CAPABILITIES).unwrap();
+        assert!(podman.contains(&"/home/u/.cache:/home/u/.cache:O".to_string()));
+        let err = run_args(&config, &DOCKER_CAPABILITIES).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Overlay (:O) mounts are not supported by the docker backend"
+        );
+    }
+
+    #[test]
+    fn test_userns_overlay_warnings_only_for_podman_overlay_translated() {
+        let homes = HomeMap {
+            host: HOST_HOME.to_string(),
+            container: CONTAINER_HOME.to_string(),
+        };
+        let mount = |spec: &str, home_relative: bool, mode: MountMode| Mount {
+            spec: spec.to_string(),
+            home_relative,
+            mode,
+            propagation: None,
+            recursive: true,
+        };
+
+        let translated_overlay = mount("~/.cache", true, MountMode::Overlay);
+        let warnings =
+            userns_overlay_warnings(&PODMAN_CAPABILITIES, &[&translated_overlay], &homes);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("~/.cache"));
+        assert!(warnings[0].contains(USERNS_OVERLAY_DOC));
 
+        // Docker has no overlay mounts or keep-id
         assert!(
-            context_mount.is_none(),
-            "Should not have context mount when context is empty"
+            userns_overlay_warnings(&DOCKER_CAPABILITIES, &[&translated_overlay], &homes)
+                .is_empty()
         );
 
-        // Clean up
-        let _ = fs::remove_dir_all(&temp_dir);
+        // Not overlay
+        let translated_rw = mount("~/.cache", true, MountMode::Rw);
+        assert!(
+            userns_overlay_warnings(&PODMAN_CAPABILITIES, &[&translated_rw], &homes).is_empty()
+        );
+
+        // Overlay, but the path is the same on both sides
+        let absolute_overlay = mount("~/.cache", false, MountMode::Overlay);
+        let outside_home = mount("/nix/store", true, MountMode::Overlay);
+        assert!(
+            userns_overlay_warnings(
+                &PODMAN_CAPABILITIES,
+                &[&absolute_overlay, &outside_home],
+                &homes
+            )
+            .is_empty()
+        );
     }
 
+    const ETC_HOSTS: &str = "\
+127.0.0.1   localhost
+::1         localhost ip6-localhost
+# 10.0.0.9  db.internal
+10.0.0.5    api.internal api   # staging API
+10.0.0.6    web.internal
+
+192.168.1.10 nas.lan
+";
+
     #[test]
-    fn test_context_path_tilde_expansion() {
-        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
-        use std::collections::HashMap;
-        use std::fs;
-        use std::path::PathBuf;
+    fn test_parse_etc_hosts() {
+        let entries = parse_etc_hosts(ETC_HOSTS);
+        let pair = |n: &str, ip: &str| (n.to_string(), ip.to_string());
+        assert_eq!(
+            entries,
+            vec![
+                pair("localhost", "127.0.0.1"),
+                pair("localhost", "::1"),
+                pair("ip6-localhost", "::1"),
+                pair("api.internal", "10.0.0.5"),
+                pair("api", "10.0.0.5"),
+                pair("web.internal", "10.0.0.6"),
+                pair("nas.lan", "192.168.1.10"),
+            ]
+        );
+    }
 
-        // Create a test workspace directory
-        let temp_dir = std::env::temp_dir().join(format!("ab_tilde_{}", std::process::id()));
-        let workspace_path = temp_dir.join("workspace");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&workspace_path).unwrap();
+    #[test]
+    fn test_parse_resolv_conf() {
+        let dns = parse_resolv_conf(
+            "# Generated by NetworkManager
+search corp.example lan
+nameserver 10.0.0.2
+nameserver   10.0.0.3  # secondary
+; nameserver 10.0.0.9
+options edns0 trust-ad
+nameserver fe80::1%eth0
+sortlist 130.155.160.0/255.255.240.0
+",
+        );
+        assert_eq!(
+            dns,
+            HostDns {
+                nameservers: vec![
+                    "10.0.0.2".to_string(),
+                    "10.0.0.3".to_string(),
+                    "fe80::1%eth0".to_string(),
+                ],
+                search: vec!["corp.example".to_string(), "lan".to_string()],
+            }
+        );
 
-        let config = Config {
-            workspace_dir: PathBuf::from("/workspaces"),
-            base_repo_dir: PathBuf::from("/repos"),
-            default_profile: None,
-            profiles: HashMap::new(),
-            runtime: RuntimeConfig {
-                backend: "podman".to_string(),
-                image: "test:latest".to_string(),
-                entrypoint: None,
-                mounts: Default::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                dns: vec![],
-                skip_mounts: vec![],
+        // The last search/domain line wins
+        let dns = parse_resolv_conf("search a.example b.example\ndomain c.example\n");
+        assert_eq!(dns.search, ["c.example"]);
+        assert!(parse_resolv_conf("").nameservers.is_empty());
+    }
+
+    #[test]
+    fn test_import_hosts_filters_by_pattern() {
+        let hosts = import_hosts(ETC_HOSTS, &["*.internal".to_string()], &[]).unwrap();
+        assert_eq!(hosts, ["api.internal:10.0.0.5", "web.internal:10.0.0.6"]);
+
+        let hosts =
+            import_hosts(ETC_HOSTS, &["nas.*".to_string(), "api".to_string()], &[]).unwrap();
+        assert_eq!(hosts, ["api:10.0.0.5", "nas.lan:192.168.1.10"]);
+    }
+
+    #[test]
+    fn test_import_hosts_dedups_against_existing_and_repeats() {
+        let existing = vec!["web.internal:172.17.0.1".to_string()];
+        let hosts = import_hosts(ETC_HOSTS, &["*".to_string()], &existing).unwrap();
+        assert_eq!(
+            hosts,
+            [
+                "localhost:127.0.0.1",
+                "ip6-localhost:::1",
+                "api.internal:10.0.0.5",
+                "api:10.0.0.5",
+                "nas.lan:192.168.1.10",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_hosts_rejects_invalid_pattern() {
+        let err = import_hosts(ETC_HOSTS, &["[".to_string()], &[])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Invalid --import-hosts pattern '['"));
+    }
+
+    /// `should_create_workspace` for a missing workspace, recording whether it prompted
+    fn create_missing(
+        policy: OnMissingWorkspace,
+        force_new: bool,
+        interactive: bool,
+        answer: bool,
+    ) -> (Result<bool>, bool) {
+        let mut prompted = false;
+        let result = should_create_workspace(
+            policy,
+            force_new,
+            Path::new("/nonexistent/ab-workspaces/jj/proj/feat"),
+            interactive,
+            || {
+                prompted = true;
+                Ok(answer)
             },
-            context: String::new(),
-            context_path: "~/.my-context".to_string(), // Test tilde expansion
-            portal: agent_box_common::portal::PortalConfig::default(),
+        );
+        (result, prompted)
+    }
+
+    #[test]
+    fn test_missing_workspace_create() {
+        let (result, prompted) = create_missing(OnMissingWorkspace::Create, false, true, false);
+        assert!(result.unwrap());
+        assert!(!prompted);
+    }
+
+    #[test]
+    fn test_missing_workspace_error_suggests_new() {
+        let (result, prompted) = create_missing(OnMissingWorkspace::Error, false, true, true);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("/nonexistent/ab-workspaces/jj/proj/feat does not exist"));
+        assert!(err.contains("--new"));
+        assert!(!prompted);
+    }
+
+    #[test]
+    fn test_missing_workspace_prompt() {
+        let (result, prompted) = create_missing(OnMissingWorkspace::Prompt, false, true, true);
+        assert!(result.unwrap() && prompted);
+
+        let (result, prompted) = create_missing(OnMissingWorkspace::Prompt, false, true, false);
+        assert!(result.is_err() && prompted);
+
+        // Without a terminal, fail instead of prompting
+        let (result, prompted) = create_missing(OnMissingWorkspace::Prompt, false, false, true);
+        assert!(result.unwrap_err().to_string().contains("not prompting"));
+        assert!(!prompted);
+    }
+
+    #[test]
+    fn test_new_forces_create_and_existing_workspace_is_reused() {
+        for policy in [
+            OnMissingWorkspace::Create,
+            OnMissingWorkspace::Error,
+            OnMissingWorkspace::Prompt,
+        ] {
+            let (result, prompted) = create_missing(policy, true, false, false);
+            assert!(result.unwrap());
+            assert!(!prompted);
+
+            let existing =
+                should_create_workspace(policy, false, &std::env::temp_dir(), true, || {
+                    panic!("existing workspace should not prompt")
+                });
+            assert!(!existing.unwrap());
+        }
+    }
+
+    fn placeholders() -> SpawnPlaceholders<'static> {
+        SpawnPlaceholders {
+            session: Some("foo"),
+            repo: Some("fr/agent-box"),
+            workspace: Path::new("/workspaces/jj/fr/agent-box/foo"),
+        }
+    }
+
+    #[test]
+    fn test_placeholder_session() {
+        assert_eq!(placeholders().expand("echo {session}").unwrap(), "echo foo");
+    }
+
+    #[test]
+    fn test_placeholder_repo() {
+        assert_eq!(
+            placeholders().expand("--repo={repo}").unwrap(),
+            "--repo=fr/agent-box"
+        );
+    }
+
+    #[test]
+    fn test_placeholder_workspace() {
+        assert_eq!(
+            placeholders()
+                .expand("cd {workspace} && {session}")
+                .unwrap(),
+            "cd /workspaces/jj/fr/agent-box/foo && foo"
+        );
+    }
+
+    #[test]
+    fn test_placeholder_leaves_other_braces_and_values_alone() {
+        assert_eq!(
+            placeholders().expand("${HOME} {a,b} {").unwrap(),
+            "${HOME} {a,b} {"
+        );
+        let ctx = SpawnPlaceholders {
+            session: Some("{repo}"),
+            ..placeholders()
+        };
+        assert_eq!(ctx.expand("{session}").unwrap(), "{repo}");
+    }
+
+    #[test]
+    fn test_placeholder_unavailable_in_local_mode() {
+        let ctx = SpawnPlaceholders {
+            session: None,
+            repo: None,
+            workspace: Path::new("/home/u/proj"),
         };
+        assert_eq!(ctx.expand("{workspace}").unwrap(), "/home/u/proj");
+        let err = ctx.expand("echo {session}").unwrap_err().to_string();
+        assert!(err.contains("{session} is not available with --local"));
+    }
+
+    #[test]
+    fn test_select_image_precedence() {
+        let env =
+            |value: &'static str| move |name: &str| (name == IMAGE_ENV).then(|| value.to_string());
+        let unset = |_: &str| None;
+
+        assert_eq!(select_image(None, unset, "config:1"), "config:1");
+        assert_eq!(select_image(None, env("env:1"), "config:1"), "env:1");
+        assert_eq!(
+            select_image(Some("cli:1"), env("env:1"), "config:1"),
+            "cli:1"
+        );
+        assert_eq!(select_image(Some("cli:1"), unset, "config:1"), "cli:1");
+    }
+
+    #[test]
+    fn test_select_image_ignores_empty_values() {
+        let empty = |_: &str| Some(String::new());
+        assert_eq!(select_image(None, empty, "config:1"), "config:1");
+        assert_eq!(
+            select_image(Some(""), |_: &str| Some("env:1".to_string()), "config:1"),
+            "env:1"
+        );
+    }
+
+    #[test]
+    fn test_passthrough_env_prefix() {
+        let host_env: std::collections::HashMap<&str, &str> =
+            [("HOME", "/home/me"), ("EDITOR", "vim")].into();
+        let lookup = |name: &str| host_env.get(name).map(|v| v.to_string());
+        let names = vec![
+            "HOME".to_string(),
+            "MISSING".to_string(),
+            "EDITOR".to_string(),
+        ];
+
+        assert_eq!(
+            passthrough_env(&names, None, lookup),
+            ["HOME=/home/me", "EDITOR=vim"]
+        );
+        assert_eq!(
+            passthrough_env(&names, Some("HOST"), lookup),
+            ["HOST_HOME=/home/me", "HOST_EDITOR=vim"]
+        );
+        // A trailing underscore in the prefix is not doubled
+        assert_eq!(
+            passthrough_env(&names[..1], Some("HOST_"), lookup),
+            ["HOST_HOME=/home/me"]
+        );
+    }
 
-        let resolved_profile = ResolvedProfile {
-            mounts: vec![],
-            env: vec![],
-            env_passthrough: vec![],
-            ports: vec![],
-            hosts: vec![],
-            context: vec!["line1".to_string(), "line2".to_string()],
+    #[test]
+    fn test_add_mounts_resolves_repeated_spec_once() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_add_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+
+        let mount = Mount {
+            spec: temp_dir.to_string_lossy().to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let homes = HomeMap {
+            host: HOST_HOME.to_string(),
+            container: CONTAINER_HOME.to_string(),
         };
 
-        let container_config = build_container_config(
-            &config,
-            &workspace_path,
-            &workspace_path,
-            true,
+        let mut cache = MountResolutionCache::default();
+        // Profile dedup already resolved it once
+        cache
+            .resolve_chain(&mount, &homes.host, &homes.container)
+            .unwrap();
+
+        let mut binds = Vec::new();
+        add_mounts(
+            &[&mount, &mount],
+            &mut binds,
             false,
-            None,
-            &resolved_profile,
-            &[],
             &[],
             &[],
-            None,
-            None,
-            true,
-            None,
+            RootPrefix::default(),
+            Some(&homes),
+            0,
+            &mut cache,
         )
         .unwrap();
 
-        // Get the username that would be used in the container
-        let username = std::env::var("USER")
-            .or_else(|_| std::env::var("LOGNAME"))
-            .unwrap_or_else(|_| "user".to_string());
+        assert_eq!(cache.resolutions(), 1);
+        let canonical = temp_dir.canonicalize().unwrap();
+        assert_eq!(
+            binds,
+            vec![
+                format!("{}:{}:ro", canonical.display(), canonical.display()),
+                format!("{}:{}:ro", canonical.display(), canonical.display()),
+            ]
+        );
 
-        let expected_container_home = format!("/home/{}", username);
-        let expected_path = format!("{}/.my-context", expected_container_home);
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
 
-        // Find the context mount
-        let context_mount = container_config
-            .mounts
-            .iter()
-            .find(|m| m.contains(&expected_path))
-            .expect("Context mount with expanded path not found");
+    #[test]
+    fn test_resolve_env_entry_reads_file() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_env_file_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        std::fs::write(temp_dir.join("token"), "  s3cr3t\n").unwrap();
+        let home = temp_dir.to_string_lossy().to_string();
 
-        // Verify the path was expanded correctly
-        assert!(
-            context_mount.ends_with(&format!(":{}:rw", expected_path)),
-            "Context path should be expanded to {} but got {}",
-            expected_path,
-            context_mount
+        let absolute = format!("API_TOKEN=@{}", temp_dir.join("token").display());
+        assert_eq!(
+            resolve_env_entry(&absolute, None).unwrap(),
+            "API_TOKEN=s3cr3t"
+        );
+        assert_eq!(
+            resolve_env_entry("API_TOKEN=@~/token", Some(&home)).unwrap(),
+            "API_TOKEN=s3cr3t"
         );
 
-        // Clean up the temp file
-        let parts: Vec<&str> = context_mount.split(':').collect();
-        let host_path = parts[0];
-        let _ = fs::remove_file(host_path);
-        let _ = fs::remove_dir_all(&temp_dir);
+        // Plain values and `@` elsewhere in the value pass through unchanged
+        assert_eq!(resolve_env_entry("FOO=bar", None).unwrap(), "FOO=bar");
+        assert_eq!(
+            resolve_env_entry("EMAIL=me@example.com", None).unwrap(),
+            "EMAIL=me@example.com"
+        );
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
     }
 
     #[test]
-    fn test_managed_portal_uses_override_socket() {
+    fn test_resolve_env_entry_missing_file_errors() {
+        let err = resolve_env_entry("API_TOKEN=@/nonexistent/ab/token", None)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Failed to read env file for API_TOKEN"));
+        assert!(err.contains("/nonexistent/ab/token"));
+
+        let err = resolve_env_entry("API_TOKEN=@~/token", None)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("HOME is not set"));
+    }
+
+    #[test]
+    fn test_binds_json_from_container_config() {
         use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
         use std::collections::HashMap;
-        use std::fs;
-        use std::path::PathBuf;
 
-        let temp_dir =
-            std::env::temp_dir().join(format!("ab_managed_portal_{}", std::process::id()));
+        let temp_dir = std::env::temp_dir().join(format!("ab_binds_json_{}", std::process::id()));
         let workspace_path = temp_dir.join("workspace");
-        let socket_path = temp_dir.join("portal.sock");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&workspace_path).unwrap();
-        fs::write(&socket_path, b"").unwrap();
-
-        let portal = agent_box_common::portal::PortalConfig {
-            global: false,
-            socket_path: "/run/user/1000/agent-portal/portal.sock".to_string(),
-            ..Default::default()
-        };
+        let data = temp_dir.join("data");
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&workspace_path).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
 
         let config = Config {
             workspace_dir: PathBuf::from("/workspaces"),
@@ -1799,150 +8497,144 @@ mod tests {
             default_profile: None,
             profiles: HashMap::new(),
             runtime: RuntimeConfig {
-                backend: "podman".to_string(),
                 image: "test:latest".to_string(),
-                entrypoint: None,
-                mounts: Default::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                dns: vec![],
-                skip_mounts: vec![],
+                ..Default::default()
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
-            portal,
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        };
+        let resolved_profile = ResolvedProfile {
+            mounts: vec![Mount {
+                spec: data.to_string_lossy().to_string(),
+                home_relative: false,
+                mode: MountMode::Ro,
+                propagation: None,
+                recursive: true,
+            }],
+            ..Default::default()
         };
 
-        let resolved_profile = ResolvedProfile::default();
         let container_config = build_container_config(
             &config,
             &workspace_path,
             &workspace_path,
             true,
             false,
+            false,
+            true,
             None,
             &resolved_profile,
             &[],
             &[],
             &[],
-            Some(socket_path.as_path()),
+            &[],
+            None,
             None,
             true,
             None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
         )
         .unwrap();
 
-        assert!(
-            container_config
-                .env
+        let json = binds_json(&container_config.mounts);
+        let mounts = json.as_array().unwrap();
+        assert!(mounts.iter().all(|m| {
+            ["host", "container", "mode"]
                 .iter()
-                .any(|v| v == &format!("AGENT_PORTAL_SOCKET={}", socket_path.display()))
-        );
-        assert!(
-            container_config.mounts.iter().any(|m| {
-                m == &format!("{}:{}:rw", socket_path.display(), socket_path.display())
-            })
+                .all(|key| m.get(key).is_some_and(|v| v.is_string()))
+        }));
+        assert!(mounts.contains(&serde_json::json!({
+            "host": workspace_path.to_string_lossy(),
+            "container": workspace_path.to_string_lossy(),
+            "mode": "rw",
+        })));
+        assert!(mounts.contains(&serde_json::json!({
+            "host": data.to_string_lossy(),
+            "container": data.to_string_lossy(),
+            "mode": "ro",
+        })));
+
+        assert_eq!(
+            binds_json(&["/cache:/cache:O".to_string()]),
+            serde_json::json!([{ "host": "/cache", "container": "/cache", "mode": "overlay" }])
         );
 
-        let _ = fs::remove_dir_all(&temp_dir);
+        let _ = std::fs::remove_dir_all(&temp_dir);
     }
 
-    #[test]
-    fn test_managed_portal_without_override_does_not_mount_socket() {
-        use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
-        use std::collections::HashMap;
-        use std::fs;
-        use std::path::PathBuf;
+    fn secret(helper: &str, args: &[&str]) -> SecretHelper {
+        SecretHelper {
+            helper: helper.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
 
-        let temp_dir =
-            std::env::temp_dir().join(format!("ab_managed_portal_none_{}", std::process::id()));
-        let workspace_path = temp_dir.join("workspace");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&workspace_path).unwrap();
+    #[test]
+    fn test_resolve_secret_env_injects_helper_output() {
+        let secrets = BTreeMap::from([
+            ("GH_TOKEN".to_string(), secret("pass", &["show", "gh"])),
+            ("NPM_TOKEN".to_string(), secret("gopass", &["npm"])),
+        ]);
+
+        let mut calls = Vec::new();
+        let env = resolve_secret_env(&secrets, |key, helper| {
+            calls.push(format!("{} {}", helper.helper, helper.args.join(" ")));
+            Ok(format!("value-of-{}", key))
+        })
+        .unwrap();
 
-        let portal = agent_box_common::portal::PortalConfig {
-            global: false,
-            ..Default::default()
-        };
+        assert_eq!(calls, vec!["pass show gh", "gopass npm"]);
+        assert_eq!(
+            env,
+            vec!["GH_TOKEN=value-of-GH_TOKEN", "NPM_TOKEN=value-of-NPM_TOKEN"]
+        );
 
-        let config = Config {
-            workspace_dir: PathBuf::from("/workspaces"),
-            base_repo_dir: PathBuf::from("/repos"),
-            default_profile: None,
-            profiles: HashMap::new(),
-            runtime: RuntimeConfig {
-                backend: "podman".to_string(),
-                image: "test:latest".to_string(),
-                entrypoint: None,
-                mounts: Default::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                dns: vec![],
-                skip_mounts: vec![],
-            },
-            context: String::new(),
-            context_path: "/tmp/context".to_string(),
-            portal,
-        };
+        let err = resolve_secret_env(&secrets, |_, _| eyre::bail!("locked")).unwrap_err();
+        assert_eq!(err.to_string(), "locked");
+    }
 
-        let resolved_profile = ResolvedProfile::default();
-        let container_config = build_container_config(
-            &config,
-            &workspace_path,
-            &workspace_path,
-            true,
-            false,
-            None,
-            &resolved_profile,
-            &[],
-            &[],
-            &[],
-            None,
-            None,
-            true,
-            None,
-        )
-        .unwrap();
+    #[test]
+    fn test_run_secret_helper() {
+        let value = run_secret_helper("API_TOKEN", &secret("printf", &["s3cret\\n\\n"])).unwrap();
+        assert_eq!(value, "s3cret");
 
+        let err = run_secret_helper("API_TOKEN", &secret("false", &[]))
+            .unwrap_err()
+            .to_string();
         assert!(
-            !container_config
-                .env
-                .iter()
-                .any(|v| v.starts_with("AGENT_PORTAL_SOCKET="))
+            err.contains("Secret helper 'false' for API_TOKEN failed"),
+            "{}",
+            err
         );
 
-        let _ = fs::remove_dir_all(&temp_dir);
+        let err = run_secret_helper("API_TOKEN", &secret("printf", &[""]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("printed nothing"), "{}", err);
+
+        let err = run_secret_helper("API_TOKEN", &secret("/nonexistent/ab-helper", &[]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Failed to run secret helper"), "{}", err);
     }
 
     #[test]
-    fn test_build_container_config_passes_through_terminal_env_and_terminfo() {
+    fn test_build_container_config_injects_and_redacts_secret_env() {
         use agent_box_common::config::{Config, ResolvedProfile, RuntimeConfig};
         use std::collections::HashMap;
-        use std::fs;
-        use std::path::PathBuf;
-
-        let temp_dir = std::env::temp_dir().join(format!("ab_terminfo_{}", std::process::id()));
-        let workspace_path = temp_dir.join("workspace");
-        let terminfo_dir = temp_dir.join("terminfo");
-        let terminfo_entry = terminfo_dir.join("x").join("xterm-kitty");
-        let _ = fs::remove_dir_all(&temp_dir);
-        fs::create_dir_all(&workspace_path).unwrap();
-        fs::create_dir_all(terminfo_entry.parent().unwrap()).unwrap();
-        fs::write(&terminfo_entry, b"fake terminfo").unwrap();
-
-        let old_term = std::env::var_os("TERM");
-        let old_colorterm = std::env::var_os("COLORTERM");
-        let old_terminfo = std::env::var_os("TERMINFO");
 
-        unsafe {
-            std::env::set_var("TERM", "xterm-kitty");
-            std::env::set_var("COLORTERM", "truecolor");
-            std::env::set_var("TERMINFO", &terminfo_dir);
-        }
+        let workspace_path =
+            std::env::temp_dir().join(format!("ab_secret_env_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&workspace_path);
+        std::fs::create_dir_all(&workspace_path).unwrap();
 
         let config = Config {
             workspace_dir: PathBuf::from("/workspaces"),
@@ -1950,20 +8642,21 @@ mod tests {
             default_profile: None,
             profiles: HashMap::new(),
             runtime: RuntimeConfig {
-                backend: "podman".to_string(),
                 image: "test:latest".to_string(),
-                entrypoint: None,
-                mounts: Default::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                dns: vec![],
-                skip_mounts: vec![],
+                secret_env: BTreeMap::from([(
+                    "DEPLOY_CREDS".to_string(),
+                    secret("printf", &["hunter2"]),
+                )]),
+                ..Default::default()
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
-            portal: agent_box_common::portal::PortalConfig::default(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: agent_box_common::portal::PortalConfig {
+                enabled: false,
+                ..Default::default()
+            },
         };
 
         let container_config = build_container_config(
@@ -1972,53 +8665,43 @@ mod tests {
             &workspace_path,
             true,
             false,
+            false,
+            true,
             None,
             &ResolvedProfile::default(),
             &[],
             &[],
             &[],
+            &[],
             None,
             None,
             true,
             None,
+            None,
+            None,
+            &mut MountResolutionCache::default(),
         )
         .unwrap();
 
-        assert!(container_config.env.iter().any(|v| v == "TERM=xterm-kitty"));
-        assert!(
-            container_config
-                .env
-                .iter()
-                .any(|v| v == "COLORTERM=truecolor")
-        );
         assert!(
             container_config
                 .env
-                .iter()
-                .any(|v| v == &format!("TERMINFO={}", terminfo_dir.display()))
+                .contains(&"DEPLOY_CREDS=hunter2".to_string())
         );
+        // Not matched by the default patterns, but masked as a secret
         assert!(
             container_config
-                .mounts
-                .iter()
-                .any(|m| m == &format!("{}:{}:ro", terminfo_dir.display(), terminfo_dir.display()))
+                .redact_env
+                .contains(&"DEPLOY_CREDS".to_string())
         );
+        let args: Vec<String> = container_config
+            .env
+            .iter()
+            .flat_map(|e| ["-e".to_string(), e.clone()])
+            .collect();
+        let printed = redact_args(&args, &container_config.redact_env);
+        assert!(printed.contains(&"DEPLOY_CREDS=****".to_string()));
 
-        unsafe {
-            match old_term {
-                Some(value) => std::env::set_var("TERM", value),
-                None => std::env::remove_var("TERM"),
-            }
-            match old_colorterm {
-                Some(value) => std::env::set_var("COLORTERM", value),
-                None => std::env::remove_var("COLORTERM"),
-            }
-            match old_terminfo {
-                Some(value) => std::env::set_var("TERMINFO", value),
-                None => std::env::remove_var("TERMINFO"),
-            }
-        }
-
-        let _ = fs::remove_dir_all(&temp_dir);
+        let _ = std::fs::remove_dir_all(&workspace_path);
     }
 }
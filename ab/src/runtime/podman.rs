@@ -1,7 +1,18 @@
 use eyre::{Context, Result};
+use std::path::Path;
 
 use super::docker::ContainerBackend;
-use super::{ContainerConfig, print_command};
+use agent_box_common::config::BuildConfig;
+
+use super::egress::{self, EgressProxy};
+use super::logs;
+use super::session::{self, SessionContainer};
+use super::volume;
+use super::{
+    BackendCapabilities, ContainerConfig, PODMAN_CAPABILITIES, build_args, copy_in_args,
+    inspect_image_id, inspect_image_user, list_containers_by_labels, list_image_dirs,
+    print_command, read_image_file, redact_args, run_args, run_attached, run_build, start_args,
+};
 
 /// Podman container runtime implementation
 pub struct PodmanRuntime;
@@ -19,6 +30,10 @@ impl Default for PodmanRuntime {
 }
 
 impl ContainerBackend for PodmanRuntime {
+    fn capabilities(&self) -> BackendCapabilities {
+        PODMAN_CAPABILITIES
+    }
+
     fn path_exists_in_image(&self, image: &str, path: &str) -> Result<bool> {
         use std::process::Stdio;
 
@@ -84,185 +99,199 @@ impl ContainerBackend for PodmanRuntime {
     }
 
     fn list_paths_in_image(&self, image: &str, root_path: Option<&str>) -> Result<Vec<String>> {
-        use std::process::Stdio;
+        list_image_dirs("podman", image, root_path)
+    }
 
-        // Create a container without starting it
-        let create_output = std::process::Command::new("podman")
-            .args(["create", image])
-            .output()
-            .wrap_err("Failed to create container")?;
+    fn image_user(&self, image: &str) -> Result<String> {
+        inspect_image_user("podman", image)
+    }
 
-        if !create_output.status.success() {
-            let stderr = String::from_utf8_lossy(&create_output.stderr);
-            return Err(eyre::eyre!("Failed to create container: {}", stderr));
-        }
+    fn image_id(&self, image: &str) -> Result<String> {
+        inspect_image_id("podman", image)
+    }
 
-        let container_id = String::from_utf8_lossy(&create_output.stdout)
-            .trim()
-            .to_string();
+    fn read_image_file(&self, image: &str, path: &str) -> Result<String> {
+        read_image_file("podman", image, path)
+    }
 
-        // Export the container filesystem and list contents with tar
-        let export_child = std::process::Command::new("podman")
-            .args(["export", &container_id])
-            .stdout(Stdio::piped())
-            .spawn()
-            .wrap_err("Failed to spawn podman export")?;
+    fn list_containers_by_labels(&self, labels: &[String]) -> Result<Vec<String>> {
+        list_containers_by_labels("podman", labels)
+    }
 
-        let tar_child = std::process::Command::new("tar")
-            .args(["-tv"]) // Verbose mode shows file types
-            .stdin(export_child.stdout.unwrap())
-            .stdout(Stdio::piped())
-            .spawn()
-            .wrap_err("Failed to spawn tar")?;
+    fn list_sessions(&self) -> Result<Vec<SessionContainer>> {
+        session::list_sessions("podman")
+    }
 
-        let output = tar_child
-            .wait_with_output()
-            .wrap_err("Failed to read tar output")?;
+    fn stop_container(&self, name: &str) -> Result<()> {
+        print_command("podman", &["stop".to_string(), name.to_string()]);
+        session::stop_container("podman", name)
+    }
 
-        // Cleanup the container
-        let _ = std::process::Command::new("podman")
-            .args(["rm", &container_id])
-            .output();
+    fn exec_in_container(
+        &self,
+        name: &str,
+        command: &[String],
+        env: &[String],
+        workdir: Option<&str>,
+        tty: bool,
+    ) -> Result<i32> {
+        let args = session::exec_args(name, command, env, workdir, tty);
+        print_command("podman", &args);
+        session::run_exec("podman", &args)
+    }
 
-        if !output.status.success() {
-            return Err(eyre::eyre!("Failed to list tar contents"));
-        }
+    fn attach_container(&self, name: &str) -> Result<()> {
+        let args = vec!["attach".to_string(), name.to_string()];
+        print_command("podman", &args);
+        run_attached("podman", "Podman", &args)
+    }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let all_paths: Vec<String> = stdout
-            .lines()
-            // Filter for directories only (line starts with 'd' in permissions)
-            .filter(|line| line.starts_with('d'))
-            .filter_map(|line| {
-                // Parse tar verbose output: "drwxr-xr-x 0/0  0 2024-01-01 00:00 path/to/dir/"
-                // The path is the last field
-                line.split_whitespace().last().map(|s| {
-                    let trimmed = s.trim_end_matches('/');
-                    if trimmed.is_empty() || trimmed == "." {
-                        "/".to_string()
-                    } else if trimmed.starts_with('/') {
-                        trimmed.to_string()
-                    } else {
-                        format!("/{}", trimmed)
-                    }
-                })
-            })
-            .collect();
-
-        // Filter by root_path if specified
-        let filtered_paths: Vec<String> = if let Some(root) = root_path {
-            let root_normalized = root.trim_end_matches('/');
-            all_paths
-                .into_iter()
-                .filter(|p| {
-                    if root_normalized.is_empty() || root_normalized == "/" {
-                        true
-                    } else {
-                        p == root_normalized || p.starts_with(&format!("{}/", root_normalized))
-                    }
-                })
-                .collect()
-        } else {
-            all_paths
-        };
+    fn image_exists(&self, image: &str) -> Result<bool> {
+        let status = std::process::Command::new("podman")
+            .args(["image", "inspect", image])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .wrap_err("Failed to execute podman image inspect")?;
 
-        Ok(filtered_paths)
+        Ok(status.success())
     }
 
-    fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
-        eprintln!("DEBUG: Creating container with Podman:");
-        eprintln!("  Name: {}", config.name);
-        eprintln!("  Image: {}", config.image);
-        eprintln!("  Entrypoint: {:?}", config.entrypoint);
-        eprintln!("  Command: {:?}", config.command);
-        eprintln!("  User: {}", config.user);
-        eprintln!("  Working dir: {}", config.working_dir);
-        eprintln!("  Mounts: {} volumes", config.mounts.len());
-        eprintln!("  Env vars: {} variables", config.env.len());
-        eprintln!("  Ports: {} mappings", config.ports.len());
-        eprintln!("  Hosts: {} entries", config.hosts.len());
-        eprintln!("  Network: {:?}", config.network);
-        eprintln!("  DNS: {} servers", config.dns.len());
+    fn pull_image(&self, image: &str) -> Result<()> {
+        let status = std::process::Command::new("podman")
+            .args(["pull", image])
+            .status()
+            .wrap_err("Failed to execute podman pull")?;
 
-        let mut args = vec![
-            "run".to_string(),
-            "--name".to_string(),
-            config.name.clone(),
-            "--rm".to_string(),
-            "-it".to_string(),
-            "--userns".to_string(),
-            "keep-id".to_string(),
-            "--user".to_string(),
-            config.user.clone(),
-            "--workdir".to_string(),
-            config.working_dir.clone(),
-        ];
-
-        // Add network mode if specified
-        if let Some(ref network) = config.network {
-            args.push("--network".to_string());
-            args.push(network.clone());
+        if !status.success() {
+            return Err(eyre::eyre!(
+                "Failed to pull image '{}' with Podman: {}",
+                image,
+                status
+            ));
         }
 
-        // Add mounts
-        for mount in &config.mounts {
-            args.push("-v".to_string());
-            args.push(mount.clone());
-        }
+        Ok(())
+    }
 
-        // Add environment variables
-        for env in &config.env {
-            args.push("-e".to_string());
-            args.push(env.clone());
-        }
+    fn build_image(&self, build: &BuildConfig, tag: &str) -> Result<()> {
+        let args = build_args(build, tag);
+        print_command("podman", &args);
+        run_build("podman", "Podman", &args)
+    }
 
-        // Add port mappings
-        for port in &config.ports {
-            args.push("-p".to_string());
-            args.push(port.clone());
-        }
+    fn start_egress_proxy(
+        &self,
+        proxy: &EgressProxy,
+        image: &str,
+        allow_hosts: &[String],
+    ) -> Result<()> {
+        egress::start_egress_proxy("podman", "podman", proxy, image, allow_hosts)
+    }
 
-        // Add custom host entries
-        for host in &config.hosts {
-            args.push("--add-host".to_string());
-            args.push(host.clone());
-        }
+    fn stop_egress_proxy(&self, proxy: &EgressProxy) -> Result<()> {
+        egress::stop_egress_proxy("podman", proxy)
+    }
 
-        // Add custom DNS servers
-        for dns in &config.dns {
-            args.push("--dns".to_string());
-            args.push(dns.clone());
-        }
+    fn ensure_volume(&self, name: &str) -> Result<()> {
+        volume::ensure_volume("podman", name)
+    }
 
-        // Add entrypoint if specified
-        if let Some(entrypoint) = &config.entrypoint {
-            args.push("--entrypoint".to_string());
-            args.push(entrypoint.join(" "));
-        }
+    fn list_volumes(&self) -> Result<Vec<String>> {
+        volume::list_volumes("podman")
+    }
 
-        // Add image
-        args.push(config.image.clone());
+    fn remove_volume(&self, name: &str) -> Result<()> {
+        volume::remove_volume("podman", name)
+    }
 
-        // Add command arguments (passed to entrypoint)
-        if let Some(command) = &config.command {
-            args.extend(command.clone());
-        }
+    fn log_command(&self, name: &str) -> Result<(&'static str, Vec<String>)> {
+        Ok(("sh", logs::follow_and_remove_args("podman", name)))
+    }
 
+    fn copy_into_container(&self, container: &str, host: &Path, dest: &str) -> Result<()> {
+        let args = copy_in_args(container, host, dest);
         print_command("podman", &args);
 
-        // Execute podman run with inherited stdio
-        let status = std::process::Command::new("podman")
+        let output = std::process::Command::new("podman")
             .args(&args)
-            .status()
-            .wrap_err("Failed to execute podman command")?;
+            .output()
+            .wrap_err("Failed to execute podman cp")?;
 
-        if !status.success() {
+        if !output.status.success() {
             return Err(eyre::eyre!(
-                "Podman container exited with status: {}",
-                status
+                "Failed to copy {} into container: {}",
+                host.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
             ));
         }
 
         Ok(())
     }
+
+    fn create_container(&self, config: &ContainerConfig) -> Result<()> {
+        let mut args = run_args(config, &self.capabilities())?;
+        args[0] = "create".to_string();
+        print_command("podman", &redact_args(&args, &config.redact_env));
+
+        let output = std::process::Command::new("podman")
+            .args(&args)
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .wrap_err("Failed to execute podman create")?;
+
+        if !output.status.success() {
+            return Err(eyre::eyre!(
+                "Failed to create Podman container: {}",
+                output.status
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn remove_container(&self, name: &str) -> Result<()> {
+        let output = std::process::Command::new("podman")
+            .args(["rm", "-f", name])
+            .output()
+            .wrap_err("Failed to execute podman rm")?;
+
+        if !output.status.success() {
+            return Err(eyre::eyre!(
+                "Failed to remove container {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn spawn_container(&self, config: &ContainerConfig) -> Result<()> {
+        eprintln!("DEBUG: Creating container with Podman:");
+        eprintln!("  Name: {}", config.name);
+        eprintln!("  Image: {}", config.image);
+        eprintln!("  Entrypoint: {:?}", config.entrypoint);
+        eprintln!("  Command: {:?}", config.command);
+        eprintln!("  User: {}", config.user);
+        eprintln!("  Working dir: {}", config.working_dir);
+        eprintln!("  Mounts: {} volumes", config.mounts.len());
+        eprintln!("  Env vars: {} variables", config.env.len());
+        eprintln!("  Ports: {} mappings", config.ports.len());
+        eprintln!("  Hosts: {} entries", config.hosts.len());
+        eprintln!("  Network: {:?}", config.network);
+        eprintln!("  DNS: {} servers", config.dns.len());
+
+        // With --copy-in, `create_container` has already created it and the files are in
+        let args = if config.copy_in.is_empty() {
+            run_args(config, &self.capabilities())?
+        } else {
+            start_args(&config.name, config.detach)
+        };
+
+        print_command("podman", &redact_args(&args, &config.redact_env));
+
+        // Execute podman run with inherited stdin/stdout
+        run_attached("podman", "Podman", &args)
+    }
 }
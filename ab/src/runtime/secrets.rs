@@ -0,0 +1,239 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use agent_box_common::config::{SecretConfig, SecretHelper};
+
+/// A secret the container gets as a read-only file at `path`
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SecretFile {
+    pub path: String,
+    pub value: String,
+}
+
+impl std::fmt::Debug for SecretFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretFile")
+            .field("path", &self.path)
+            .field("value", &agent_box_common::config::REDACTED)
+            .finish()
+    }
+}
+
+/// Fetch each of a profile's `secrets` on the host: `KEY=value` env entries for
+/// the plain ones, and files for those with a `path`. Commands are run by `run`.
+pub fn resolve_profile_secrets(
+    secrets: &BTreeMap<String, SecretConfig>,
+    host_home: Option<&str>,
+    mut run: impl FnMut(&str, &SecretHelper) -> Result<String>,
+) -> Result<(Vec<String>, Vec<SecretFile>)> {
+    let mut env = Vec::new();
+    let mut files = Vec::new();
+    for (name, secret) in secrets {
+        let value = if let Some(command) = &secret.command {
+            let (helper, args) = command
+                .split_first()
+                .ok_or_else(|| eyre::eyre!("secrets.{}.command is empty", name))?;
+            run(
+                name,
+                &SecretHelper {
+                    helper: helper.clone(),
+                    args: args.to_vec(),
+                },
+            )?
+        } else if let Some(file) = &secret.file {
+            let file = match file.strip_prefix('~') {
+                Some(rest) => {
+                    let home = host_home.ok_or_else(|| {
+                        eyre::eyre!(
+                            "Cannot expand '~' in secrets.{}.file: HOME is not set",
+                            name
+                        )
+                    })?;
+                    format!("{}{}", home, rest)
+                }
+                None => file.clone(),
+            };
+            let contents = std::fs::read_to_string(&file)
+                .map_err(|e| eyre::eyre!("Failed to read secret {} ({}): {}", name, file, e))?;
+            // Files keep their contents as is; env values can't carry the trailing newline
+            if secret.path.is_some() {
+                contents
+            } else {
+                contents.trim().to_string()
+            }
+        } else if let Some(var) = &secret.env {
+            std::env::var(var)
+                .map_err(|_| eyre::eyre!("Secret {} comes from ${}, which is not set", name, var))?
+        } else {
+            eyre::bail!(
+                "secrets.{} must set exactly one of command, file, or env",
+                name
+            );
+        };
+        if value.is_empty() {
+            eyre::bail!("Secret {} is empty", name);
+        }
+
+        match &secret.path {
+            Some(path) => files.push(SecretFile {
+                path: path.clone(),
+                value,
+            }),
+            None => env.push(format!("{}={}", name, value)),
+        }
+    }
+    Ok((env, files))
+}
+
+/// Private host directory holding a spawn's secret files, under `$XDG_RUNTIME_DIR`
+/// (a tmpfs on most systems) when set. Removed when dropped, once the spawn
+/// returns, which is why detached spawns can't have secret files.
+pub(crate) struct SecretDir {
+    dir: PathBuf,
+}
+
+impl SecretDir {
+    /// Write `files` and return the directory with the binds that mount them read-only
+    pub(crate) fn create(files: &[SecretFile]) -> Result<(Self, Vec<String>)> {
+        use std::io::Write;
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+        let base = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let dir = base.join(format!(
+            "agent-box-secrets-{}-{}",
+            std::process::id(),
+            stamp
+        ));
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .map_err(|e| eyre::eyre!("Failed to create {}: {}", dir.display(), e))?;
+        let secret_dir = Self { dir };
+
+        let mut binds = Vec::new();
+        for (i, file) in files.iter().enumerate() {
+            let name = Path::new(&file.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let host = secret_dir.dir.join(format!("{}-{}", i, name));
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o400)
+                .open(&host)
+                .and_then(|mut f| f.write_all(file.value.as_bytes()))
+                .map_err(|e| {
+                    eyre::eyre!("Failed to write secret file {}: {}", host.display(), e)
+                })?;
+            binds.push(format!("{}:{}:ro", host.display(), file.path));
+        }
+        Ok((secret_dir, binds))
+    }
+}
+
+impl Drop for SecretDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(command: Option<&[&str]>, file: Option<&str>, env: Option<&str>) -> SecretConfig {
+        SecretConfig {
+            command: command.map(|c| c.iter().map(|s| s.to_string()).collect()),
+            file: file.map(str::to_string),
+            env: env.map(str::to_string),
+            path: None,
+        }
+    }
+
+    #[test]
+    fn test_resolve_profile_secrets_from_each_source() {
+        let home = std::env::temp_dir().join(format!("ab-test-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(home.join("npm"), "npm-secret\n").unwrap();
+        std::fs::write(home.join("gcp.json"), "{\"key\": 1}\n").unwrap();
+
+        let mut gcp = secret(None, Some("~/gcp.json"), None);
+        gcp.path = Some("/run/secrets/gcp.json".to_string());
+        let secrets = BTreeMap::from([
+            (
+                "GH_TOKEN".to_string(),
+                secret(Some(&["pass", "show", "gh"]), None, None),
+            ),
+            ("NPM_TOKEN".to_string(), secret(None, Some("~/npm"), None)),
+            ("PATH_COPY".to_string(), secret(None, None, Some("PATH"))),
+            ("GCP".to_string(), gcp),
+        ]);
+
+        let mut ran = Vec::new();
+        let (env, files) = resolve_profile_secrets(&secrets, home.to_str(), |name, helper| {
+            ran.push(format!(
+                "{} {} {}",
+                name,
+                helper.helper,
+                helper.args.join(" ")
+            ));
+            Ok("gh-secret".to_string())
+        })
+        .unwrap();
+        std::fs::remove_dir_all(&home).ok();
+
+        assert_eq!(ran, ["GH_TOKEN pass show gh"]);
+        assert_eq!(
+            env,
+            [
+                "GH_TOKEN=gh-secret".to_string(),
+                "NPM_TOKEN=npm-secret".to_string(),
+                format!("PATH_COPY={}", std::env::var("PATH").unwrap()),
+            ]
+        );
+        assert_eq!(
+            files,
+            [SecretFile {
+                path: "/run/secrets/gcp.json".to_string(),
+                value: "{\"key\": 1}\n".to_string(),
+            }]
+        );
+        assert!(!format!("{:?}", files).contains("key"));
+
+        let missing = BTreeMap::from([(
+            "X".to_string(),
+            secret(None, None, Some("AB_TEST_SURELY_UNSET")),
+        )]);
+        let err = resolve_profile_secrets(&missing, None, |_, _| unreachable!()).unwrap_err();
+        assert!(err.to_string().contains("$AB_TEST_SURELY_UNSET"), "{}", err);
+    }
+
+    #[test]
+    fn test_secret_dir_writes_private_files_and_cleans_up() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let files = [SecretFile {
+            path: "/run/secrets/token".to_string(),
+            value: "hunter2".to_string(),
+        }];
+        let (dir, binds) = SecretDir::create(&files).unwrap();
+        let host = binds[0].strip_suffix(":/run/secrets/token:ro").unwrap();
+        assert_eq!(std::fs::read_to_string(host).unwrap(), "hunter2");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(Path::new(host)), 0o400);
+        assert_eq!(mode(&dir.dir), 0o700);
+
+        let path = dir.dir.clone();
+        drop(dir);
+        assert!(!path.exists());
+    }
+}
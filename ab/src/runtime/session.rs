@@ -0,0 +1,423 @@
+use eyre::Result;
+use std::io::Write;
+
+use agent_box_common::repo::ListEntry;
+
+use super::audit::format_timestamp;
+use super::{REPO_LABEL, SESSION_LABEL, sanitize_container_name_component};
+
+/// Stable container name for a detached session container, so a session has at
+/// most one. Example: `ab-fr-agent-box-main`.
+pub fn session_container_name(repo: &str, session: &str) -> String {
+    let repo = sanitize_container_name_component(repo);
+    let session = sanitize_container_name_component(session);
+    let name = format!("ab-{}-{}", repo, session);
+    if name.len() <= 63 {
+        return name;
+    }
+    // Keep the session name whole when trimming; it's what tells them apart
+    let max_repo_len = 63usize.saturating_sub("ab--".len() + session.len());
+    let repo = repo[..max_repo_len.min(repo.len())].trim_matches('-');
+    format!("ab-{}-{}", repo, session)
+}
+
+/// A container spawned for a session, as listed by `ab ps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionContainer {
+    pub name: String,
+    pub repo: String,
+    pub session: String,
+    pub image: String,
+    /// Runtime status text, e.g. `Up 5 minutes`
+    pub status: String,
+}
+
+/// `ps` args listing every container carrying a session label, one JSON object per line
+pub(crate) fn ps_sessions_args() -> Vec<String> {
+    vec![
+        "ps".to_string(),
+        "--all".to_string(),
+        "--filter".to_string(),
+        format!("label={}", SESSION_LABEL),
+        "--format".to_string(),
+        "{{json .}}".to_string(),
+    ]
+}
+
+/// Parse `ps --format '{{json .}}'` output. Docker gives `Names` and `Labels` as
+/// strings (`k=v,k=v`); Podman gives a name array and a label map.
+pub fn parse_ps_sessions(output: &str) -> Result<Vec<SessionContainer>> {
+    let mut sessions = Vec::new();
+    for line in output.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let entry: serde_json::Value = serde_json::from_str(line)
+            .map_err(|e| eyre::eyre!("Invalid ps output line '{}': {}", line, e))?;
+
+        let name = match &entry["Names"] {
+            serde_json::Value::String(names) => names.split(',').next().map(str::to_string),
+            serde_json::Value::Array(names) => names
+                .first()
+                .and_then(|name| name.as_str())
+                .map(str::to_string),
+            _ => None,
+        }
+        .unwrap_or_default();
+        let label = |key: &str| match &entry["Labels"] {
+            serde_json::Value::String(labels) => labels
+                .split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string()),
+            serde_json::Value::Object(labels) => {
+                labels.get(key).and_then(|v| v.as_str()).map(str::to_string)
+            }
+            _ => None,
+        };
+        let text = |key: &str| entry[key].as_str().unwrap_or_default().to_string();
+
+        sessions.push(SessionContainer {
+            name,
+            repo: label(REPO_LABEL).unwrap_or_default(),
+            session: label(SESSION_LABEL).unwrap_or_default(),
+            image: text("Image"),
+            status: text("Status"),
+        });
+    }
+    sessions.sort_by(|a, b| (&a.repo, &a.session).cmp(&(&b.repo, &b.session)));
+    Ok(sessions)
+}
+
+/// Session containers known to the runtime `program`, running or not
+pub(crate) fn list_sessions(program: &str) -> Result<Vec<SessionContainer>> {
+    let output = std::process::Command::new(program)
+        .args(ps_sessions_args())
+        .output()
+        .map_err(|e| eyre::eyre!("Failed to execute {} ps: {}", program, e))?;
+
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "Failed to list containers: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    parse_ps_sessions(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Stop the container `name` and remove it
+pub(crate) fn stop_container(program: &str, name: &str) -> Result<()> {
+    let output = std::process::Command::new(program)
+        .args(["stop", name])
+        .output()
+        .map_err(|e| eyre::eyre!("Failed to execute {} stop: {}", program, e))?;
+
+    if !output.status.success() {
+        return Err(eyre::eyre!(
+            "Failed to stop container {}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // Detached containers run without `--rm`; their log capture usually removes
+    // them first, so a failure here means there's nothing left to remove
+    let _ = std::process::Command::new(program)
+        .args(["rm", name])
+        .output();
+    Ok(())
+}
+
+/// `exec` args running `command` in container `name`. The user and working
+/// directory set at spawn apply unless `workdir` overrides the latter, and the
+/// container's env is inherited; `env` adds to it.
+pub(crate) fn exec_args(
+    name: &str,
+    command: &[String],
+    env: &[String],
+    workdir: Option<&str>,
+    tty: bool,
+) -> Vec<String> {
+    let mut args = vec![
+        "exec".to_string(),
+        if tty { "-it" } else { "-i" }.to_string(),
+    ];
+    for var in env {
+        args.push("-e".to_string());
+        args.push(var.clone());
+    }
+    if let Some(workdir) = workdir {
+        args.push("--workdir".to_string());
+        args.push(workdir.to_string());
+    }
+    args.push(name.to_string());
+    args.extend(command.iter().cloned());
+    args
+}
+
+/// Run `program args` on the terminal and return its exit code, which for
+/// `exec` is the command's own
+pub(crate) fn run_exec(program: &str, args: &[String]) -> Result<i32> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| eyre::eyre!("Failed to execute {} exec: {}", program, e))?;
+    // Killed by a signal: report it the way a shell would
+    Ok(status.code().unwrap_or(128))
+}
+
+/// `ab ps` table of session containers
+pub fn write_sessions_table(sessions: &[SessionContainer], out: &mut dyn Write) -> Result<()> {
+    if sessions.is_empty() {
+        writeln!(out, "No session containers")?;
+        return Ok(());
+    }
+
+    let headers = ["REPO", "SESSION", "NAME", "STATUS", "IMAGE"];
+    let rows: Vec<[&str; 5]> = sessions
+        .iter()
+        .map(|s| {
+            [
+                s.repo.as_str(),
+                s.session.as_str(),
+                s.name.as_str(),
+                s.status.as_str(),
+                s.image.as_str(),
+            ]
+        })
+        .collect();
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    for row in std::iter::once(&headers).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// The container of `entry`'s session among `containers`, if it has one
+pub fn entry_container<'a>(
+    entry: &ListEntry,
+    containers: &'a [SessionContainer],
+) -> Option<&'a SessionContainer> {
+    let session = entry.session.as_deref()?;
+    containers
+        .iter()
+        .find(|c| c.repo == entry.repo && c.session == session)
+}
+
+/// `ab list` table of repos and workspaces; with `containers`, a column with
+/// each session's container status
+pub fn write_list_table(
+    entries: &[ListEntry],
+    containers: Option<&[SessionContainer]>,
+    out: &mut dyn Write,
+) -> Result<()> {
+    if entries.is_empty() {
+        writeln!(out, "No repositories or workspaces")?;
+        return Ok(());
+    }
+
+    let mut headers = vec!["REPO", "TYPE", "SESSION", "MODIFIED"];
+    if containers.is_some() {
+        headers.push("CONTAINER");
+    }
+    headers.push("PATH");
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|e| {
+            let mut row = vec![
+                e.repo.clone(),
+                e.wtype.as_str().to_string(),
+                e.session.clone().unwrap_or_else(|| "-".to_string()),
+                e.modified
+                    .map(format_timestamp)
+                    .unwrap_or_else(|| "-".to_string()),
+            ];
+            if let Some(containers) = containers {
+                row.push(
+                    entry_container(e, containers)
+                        .map(|c| c.status.clone())
+                        .unwrap_or_else(|| "-".to_string()),
+                );
+            }
+            row.push(e.path.display().to_string());
+            row
+        })
+        .collect();
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header_row: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header_row).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_container_name_is_stable() {
+        assert_eq!(
+            session_container_name("fr/agent-box", "main"),
+            "ab-fr-agent-box-main"
+        );
+        assert_eq!(
+            session_container_name("fr/agent-box", "main"),
+            session_container_name("fr/agent-box", "main")
+        );
+        assert_eq!(
+            session_container_name("Work/API", "Fix Bug"),
+            "ab-work-api-fix-bug"
+        );
+
+        let long = session_container_name(&"r".repeat(80), "feature");
+        assert_eq!(long.len(), 63);
+        assert!(long.ends_with("-feature"), "{}", long);
+    }
+
+    #[test]
+    fn test_parse_ps_sessions_docker_and_podman() {
+        let docker = r#"{"Image":"agent:latest","Labels":"com.agent-box.session=main,com.agent-box.repo=fr/agent-box","Names":"ab-fr-agent-box-main","Status":"Up 5 minutes"}"#;
+        let podman = r#"{"Image":"localhost/agent:latest","Labels":{"com.agent-box.repo":"fr/api","com.agent-box.session":"fix"},"Names":["ab-fr-api-fix"],"Status":"Up 2 hours"}"#;
+
+        let sessions = parse_ps_sessions(&format!("{}\n\n{}\n", podman, docker)).unwrap();
+        assert_eq!(
+            sessions,
+            [
+                SessionContainer {
+                    name: "ab-fr-agent-box-main".to_string(),
+                    repo: "fr/agent-box".to_string(),
+                    session: "main".to_string(),
+                    image: "agent:latest".to_string(),
+                    status: "Up 5 minutes".to_string(),
+                },
+                SessionContainer {
+                    name: "ab-fr-api-fix".to_string(),
+                    repo: "fr/api".to_string(),
+                    session: "fix".to_string(),
+                    image: "localhost/agent:latest".to_string(),
+                    status: "Up 2 hours".to_string(),
+                },
+            ]
+        );
+
+        assert!(parse_ps_sessions("").unwrap().is_empty());
+        assert!(parse_ps_sessions("not json").is_err());
+    }
+
+    #[test]
+    fn test_exec_args() {
+        let command = ["cargo".to_string(), "test".to_string()];
+        assert_eq!(
+            exec_args("ab-fr-agent-box-main", &command, &[], None, true),
+            ["exec", "-it", "ab-fr-agent-box-main", "cargo", "test"]
+        );
+        assert_eq!(
+            exec_args(
+                "ab-fr-agent-box-main",
+                &command,
+                &["TERM=xterm".to_string(), "RUST_LOG=debug".to_string()],
+                Some("/workspace/sub"),
+                false,
+            ),
+            [
+                "exec",
+                "-i",
+                "-e",
+                "TERM=xterm",
+                "-e",
+                "RUST_LOG=debug",
+                "--workdir",
+                "/workspace/sub",
+                "ab-fr-agent-box-main",
+                "cargo",
+                "test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_sessions_table() {
+        let sessions = [SessionContainer {
+            name: "ab-fr-agent-box-main".to_string(),
+            repo: "fr/agent-box".to_string(),
+            session: "main".to_string(),
+            image: "agent:latest".to_string(),
+            status: "Up 5 minutes".to_string(),
+        }];
+
+        let mut out = Vec::new();
+        write_sessions_table(&sessions, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "REPO          SESSION  NAME                  STATUS        IMAGE\n\
+             fr/agent-box  main     ab-fr-agent-box-main  Up 5 minutes  agent:latest\n"
+        );
+
+        let mut out = Vec::new();
+        write_sessions_table(&[], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "No session containers\n");
+    }
+
+    #[test]
+    fn test_write_list_table() {
+        let entry = |session: Option<&str>, path: &str| ListEntry {
+            repo: "fr/agent-box".to_string(),
+            session: session.map(str::to_string),
+            wtype: agent_box_common::path::WorkspaceType::Git,
+            path: path.into(),
+            modified: Some(86400),
+        };
+        let entries = [
+            entry(None, "/src/fr/agent-box"),
+            entry(Some("main"), "/ws/git/fr/agent-box/main"),
+            entry(Some("old"), "/ws/git/fr/agent-box/old"),
+        ];
+        let containers = [SessionContainer {
+            name: "ab-fr-agent-box-main".to_string(),
+            repo: "fr/agent-box".to_string(),
+            session: "main".to_string(),
+            image: "agent:latest".to_string(),
+            status: "Up 5 minutes".to_string(),
+        }];
+
+        let mut out = Vec::new();
+        write_list_table(&entries[..2], None, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "REPO          TYPE  SESSION  MODIFIED              PATH\n\
+             fr/agent-box  git   -        1970-01-02T00:00:00Z  /src/fr/agent-box\n\
+             fr/agent-box  git   main     1970-01-02T00:00:00Z  /ws/git/fr/agent-box/main\n"
+        );
+
+        let mut out = Vec::new();
+        write_list_table(&entries[1..], Some(&containers), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "REPO          TYPE  SESSION  MODIFIED              CONTAINER     PATH\n\
+             fr/agent-box  git   main     1970-01-02T00:00:00Z  Up 5 minutes  /ws/git/fr/agent-box/main\n\
+             fr/agent-box  git   old      1970-01-02T00:00:00Z  -             /ws/git/fr/agent-box/old\n"
+        );
+    }
+}
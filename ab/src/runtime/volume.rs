@@ -0,0 +1,214 @@
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Label on the named volumes agent-box creates, which `ab volume ls` and
+/// `ab volume rm` are limited to
+pub const VOLUME_LABEL: &str = "com.agent-box.volume";
+
+/// `volume create` args for a labeled volume
+fn create_args(name: &str) -> Vec<String> {
+    vec![
+        "volume".to_string(),
+        "create".to_string(),
+        "--label".to_string(),
+        format!("{}=1", VOLUME_LABEL),
+        name.to_string(),
+    ]
+}
+
+/// `volume ls` args listing the names of labeled volumes
+fn list_args() -> Vec<String> {
+    [
+        "volume",
+        "ls",
+        "--filter",
+        &format!("label={}", VOLUME_LABEL),
+        "--format",
+        "{{.Name}}",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Create the volume `name` unless it exists; an existing volume is used as-is,
+/// even when agent-box didn't create it
+pub(crate) fn ensure_volume(program: &str, name: &str) -> Result<()> {
+    let exists = Command::new(program)
+        .args(["volume", "inspect", name])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .wrap_err_with(|| format!("Failed to execute {} volume inspect", program))?
+        .success();
+    if !exists {
+        run(program, &create_args(name))?;
+    }
+    Ok(())
+}
+
+/// Names of the volumes agent-box created, sorted
+pub(crate) fn list_volumes(program: &str) -> Result<Vec<String>> {
+    let mut names: Vec<String> = run(program, &list_args())?
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Remove the volume `name`; the runtime refuses while a container uses it
+pub(crate) fn remove_volume(program: &str, name: &str) -> Result<()> {
+    run(
+        program,
+        &["volume".to_string(), "rm".to_string(), name.to_string()],
+    )
+    .map(|_| ())
+}
+
+/// One row of `ab volume ls`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VolumeRow {
+    pub name: String,
+    /// Whether agent-box has created it
+    pub created: bool,
+    /// `runtime` and the profiles whose mounts use it
+    pub used_by: Vec<String>,
+}
+
+/// Rows for the `created` volumes and every volume named in `used_by`
+/// (volume name to config layers), sorted by name
+pub fn volume_rows(created: &[String], used_by: BTreeMap<String, Vec<String>>) -> Vec<VolumeRow> {
+    let mut rows: BTreeMap<String, VolumeRow> = used_by
+        .into_iter()
+        .map(|(name, used_by)| {
+            let row = VolumeRow {
+                name: name.clone(),
+                created: false,
+                used_by,
+            };
+            (name, row)
+        })
+        .collect();
+    for name in created {
+        rows.entry(name.clone())
+            .or_insert_with(|| VolumeRow {
+                name: name.clone(),
+                created: false,
+                used_by: Vec::new(),
+            })
+            .created = true;
+    }
+    rows.into_values().collect()
+}
+
+/// Write `rows` as an aligned `NAME CREATED USED BY` table
+pub fn write_volume_table(rows: &[VolumeRow], out: &mut dyn Write) -> Result<()> {
+    if rows.is_empty() {
+        writeln!(out, "No named volumes")?;
+        return Ok(());
+    }
+    let width = rows.iter().map(|r| r.name.len()).max().unwrap_or(0).max(4);
+    writeln!(out, "{:<width$}  CREATED  USED BY", "NAME", width = width)?;
+    for row in rows {
+        let used_by = if row.used_by.is_empty() {
+            "-".to_string()
+        } else {
+            row.used_by.join(", ")
+        };
+        writeln!(
+            out,
+            "{:<width$}  {:<7}  {}",
+            row.name,
+            if row.created { "yes" } else { "no" },
+            used_by,
+            width = width
+        )?;
+    }
+    Ok(())
+}
+
+/// Run `program args`, returning stdout or failing with stderr
+fn run(program: &str, args: &[String]) -> Result<String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .wrap_err_with(|| format!("Failed to execute {} {}", program, args[..2].join(" ")))?;
+    if !output.status.success() {
+        eyre::bail!(
+            "{} {} failed: {}",
+            program,
+            args[..2].join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_args() {
+        assert_eq!(
+            create_args("cargo-cache"),
+            [
+                "volume",
+                "create",
+                "--label",
+                "com.agent-box.volume=1",
+                "cargo-cache"
+            ]
+        );
+        assert_eq!(
+            list_args(),
+            [
+                "volume",
+                "ls",
+                "--filter",
+                "label=com.agent-box.volume",
+                "--format",
+                "{{.Name}}"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_volume_rows_and_table() {
+        let rows = volume_rows(
+            &["cargo-cache".to_string(), "old".to_string()],
+            BTreeMap::from([
+                (
+                    "cargo-cache".to_string(),
+                    vec!["runtime".to_string(), "rust".to_string()],
+                ),
+                ("npm".to_string(), vec!["node".to_string()]),
+            ]),
+        );
+        assert_eq!(
+            rows.iter()
+                .map(|r| (r.name.as_str(), r.created))
+                .collect::<Vec<_>>(),
+            [("cargo-cache", true), ("npm", false), ("old", true)]
+        );
+
+        let mut out = Vec::new();
+        write_volume_table(&rows, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "NAME         CREATED  USED BY\n\
+             cargo-cache  yes      runtime, rust\n\
+             npm          no       node\n\
+             old          yes      -\n"
+        );
+
+        let mut out = Vec::new();
+        write_volume_table(&[], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "No named volumes\n");
+    }
+}
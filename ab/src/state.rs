@@ -0,0 +1,216 @@
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::runtime::ContainerConfig;
+
+/// Written to the root of a session workspace by each `ab spawn`
+pub const STATE_FILE: &str = ".agent-box.state.toml";
+
+/// What the last `ab spawn` in a workspace used, for `ab status` to compare the
+/// current config against
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceState {
+    /// When the spawn started, in seconds since the Unix epoch
+    pub spawned: u64,
+    pub image: String,
+    /// `default_profile` at the time, applied before `profiles`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    /// Profiles given with `-p`, in order
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    /// Bind mounts as `HOST:CONTAINER:MODE`, then `tmpfs:PATH` and `volume:SPEC` entries
+    #[serde(default)]
+    pub mounts: Vec<String>,
+}
+
+impl WorkspaceState {
+    pub fn new(
+        container: &ContainerConfig,
+        default_profile: Option<&str>,
+        profiles: &[String],
+        spawned: u64,
+    ) -> Self {
+        let mut mounts = container.mounts.clone();
+        mounts.extend(container.tmpfs.iter().map(|path| format!("tmpfs:{}", path)));
+        mounts.extend(
+            container
+                .volumes
+                .iter()
+                .map(|spec| format!("volume:{}", spec)),
+        );
+        Self {
+            spawned,
+            image: container.image.clone(),
+            default_profile: default_profile.map(str::to_string),
+            profiles: profiles.to_vec(),
+            mounts,
+        }
+    }
+
+    pub fn path(workspace: &Path) -> PathBuf {
+        workspace.join(STATE_FILE)
+    }
+
+    /// The state in `workspace`, if a spawn has written one
+    pub fn read(workspace: &Path) -> Result<Option<Self>> {
+        let path = Self::path(workspace);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Failed to read {}", path.display())),
+        };
+        toml::from_str(&text)
+            .map(Some)
+            .wrap_err_with(|| format!("Invalid {}", path.display()))
+    }
+
+    pub fn write(&self, workspace: &Path) -> Result<()> {
+        let path = Self::path(workspace);
+        let text = toml::to_string_pretty(self)?;
+        std::fs::write(&path, text).wrap_err_with(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// How `current` (what a spawn would use now) differs from this state, one
+    /// line per difference; empty means no drift
+    pub fn drift(&self, current: &Self) -> Vec<String> {
+        let mut drift = Vec::new();
+        if self.default_profile != current.default_profile {
+            drift.push(format!(
+                "default_profile: {} -> {}",
+                self.default_profile.as_deref().unwrap_or("(none)"),
+                current.default_profile.as_deref().unwrap_or("(none)")
+            ));
+        }
+        if self.image != current.image {
+            drift.push(format!("image: {} -> {}", self.image, current.image));
+        }
+        let before: BTreeSet<&String> = self.mounts.iter().collect();
+        let after: BTreeSet<&String> = current.mounts.iter().collect();
+        drift.extend(
+            before
+                .difference(&after)
+                .map(|mount| format!("mount removed: {}", mount)),
+        );
+        drift.extend(
+            after
+                .difference(&before)
+                .map(|mount| format!("mount added: {}", mount)),
+        );
+        drift
+    }
+}
+
+/// Add [`STATE_FILE`] to the `info/exclude` of the git repo at `source`, so
+/// workspaces don't show it as untracked (jj reads that file too). Repos without
+/// a git dir are left alone.
+pub fn exclude_state_file(source: &Path) -> Result<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(source)
+        .args([
+            "rev-parse",
+            "--path-format=absolute",
+            "--git-path",
+            "info/exclude",
+        ])
+        .output()
+        .wrap_err("Failed to run git rev-parse")?;
+    if !output.status.success() {
+        return Ok(());
+    }
+    let exclude = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    let mut text = std::fs::read_to_string(&exclude).unwrap_or_default();
+    let pattern = format!("/{}", STATE_FILE);
+    if text.lines().any(|line| line.trim() == pattern) {
+        return Ok(());
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(&pattern);
+    text.push('\n');
+    if let Some(dir) = exclude.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&exclude, text)
+        .wrap_err_with(|| format!("Failed to write {}", exclude.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(image: &str, mounts: &[&str]) -> WorkspaceState {
+        WorkspaceState {
+            spawned: 100,
+            image: image.to_string(),
+            default_profile: Some("base".to_string()),
+            profiles: vec!["rust".to_string()],
+            mounts: mounts.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_state_roundtrip_and_drift() {
+        let temp_dir = std::env::temp_dir().join(format!("ab-test-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+
+        assert_eq!(WorkspaceState::read(&temp_dir).unwrap(), None);
+        let last = state("img:1", &["/a:/a:ro", "/b:/b:rw", "tmpfs:/tmp"]);
+        last.write(&temp_dir).unwrap();
+        assert_eq!(WorkspaceState::read(&temp_dir).unwrap(), Some(last.clone()));
+        std::fs::remove_dir_all(&temp_dir).ok();
+
+        assert!(last.drift(&last).is_empty());
+        let mut current = state("img:2", &["/a:/a:ro", "/c:/c:ro", "tmpfs:/tmp"]);
+        current.default_profile = None;
+        assert_eq!(
+            last.drift(&current),
+            [
+                "default_profile: base -> (none)",
+                "image: img:1 -> img:2",
+                "mount removed: /b:/b:rw",
+                "mount added: /c:/c:ro",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exclude_state_file() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-state-exclude-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&temp_dir)
+            .args(["init", "-q"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        exclude_state_file(&temp_dir).unwrap();
+        exclude_state_file(&temp_dir).unwrap();
+        let exclude = std::fs::read_to_string(temp_dir.join(".git/info/exclude")).unwrap();
+        assert_eq!(
+            exclude
+                .lines()
+                .filter(|l| *l == "/.agent-box.state.toml")
+                .count(),
+            1
+        );
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+
+        // Not a git repo: nothing to do
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        exclude_state_file(&temp_dir).unwrap();
+        assert!(!temp_dir.join(".git").exists());
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}
@@ -0,0 +1,107 @@
+use serde_json::{Value, json};
+use std::time::{Duration, Instant};
+
+/// Wall-clock durations of the individual `ab spawn` setup phases
+#[derive(Debug, Default)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    /// Record an already measured phase
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        self.phases.push((phase, duration));
+    }
+
+    /// Run `f`, recording how long it took under `phase`
+    pub fn record<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(phase, start.elapsed());
+        result
+    }
+
+    /// Sum of all recorded phases
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|(_, d)| *d).sum()
+    }
+
+    /// Format a summary table, one phase per line followed by the total
+    pub fn summary(&self) -> String {
+        let width = self
+            .phases
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(std::iter::once("total".len()))
+            .max()
+            .unwrap_or(0);
+
+        let mut out = String::from("Timings:\n");
+        for (name, duration) in &self.phases {
+            out.push_str(&format!("  {:<width$}  {:>10.2?}\n", name, duration));
+        }
+        out.push_str(&format!("  {:<width$}  {:>10.2?}\n", "total", self.total()));
+        out
+    }
+
+    /// The summary as `{"phases": [{"phase", "ms"}], "total_ms"}`, in milliseconds
+    pub fn to_json(&self) -> Value {
+        let ms = |duration: &Duration| duration.as_secs_f64() * 1000.0;
+        let phases: Vec<Value> = self
+            .phases
+            .iter()
+            .map(|(name, duration)| json!({ "phase": name, "ms": ms(duration) }))
+            .collect();
+        json!({ "phases": phases, "total_ms": ms(&self.total()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_reports_all_phases() {
+        let mut timings = Timings::default();
+        timings.add("config load", Duration::from_millis(3));
+        let value = timings.record("validation", || 42);
+        timings.add("profile resolution", Duration::from_millis(1));
+        timings.add("mount building", Duration::from_millis(5));
+        timings.add("container", Duration::from_millis(10));
+
+        assert_eq!(value, 42);
+
+        let summary = timings.summary();
+        for phase in [
+            "config load",
+            "validation",
+            "profile resolution",
+            "mount building",
+            "container",
+            "total",
+        ] {
+            assert!(summary.contains(phase), "missing phase '{}'", phase);
+        }
+        assert!(timings.total() >= Duration::from_millis(19));
+
+        let value = timings.to_json();
+        let phases: Vec<&str> = value["phases"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|phase| phase["phase"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            phases,
+            [
+                "config load",
+                "validation",
+                "profile resolution",
+                "mount building",
+                "container"
+            ]
+        );
+        assert_eq!(value["phases"][0]["ms"], 3.0);
+        assert!(value["total_ms"].as_f64().unwrap() >= 19.0);
+    }
+}
@@ -0,0 +1,326 @@
+use eyre::{Result, WrapErr};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::time::{Duration, Instant};
+
+/// Quiet period after the last change before `ab spawn --watch` re-spawns
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Outcome of waiting on a [`ChangeSource`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// A watched file changed
+    Changed,
+    /// The timeout elapsed without a change
+    Quiet,
+    /// The source will never report another change
+    Closed,
+}
+
+/// Something that reports changes to the watched config files
+pub trait ChangeSource {
+    /// Block until a change, or until `timeout` elapses (forever if `None`)
+    fn wait(&mut self, timeout: Option<Duration>) -> Wake;
+}
+
+/// Block until a burst of changes has settled for `debounce`.
+/// Returns false if the source closed before any change arrived.
+pub fn wait_for_change(source: &mut dyn ChangeSource, debounce: Duration) -> bool {
+    match source.wait(None) {
+        Wake::Changed => {}
+        Wake::Quiet | Wake::Closed => return false,
+    }
+    while source.wait(Some(debounce)) == Wake::Changed {}
+    true
+}
+
+/// Spawn with `config`, then re-spawn with the reloaded config whenever the
+/// watched files change, until `source` closes.
+///
+/// `snapshot` captures the watched files' contents, so events that leave them
+/// unchanged (touches, saves without edits) don't re-spawn. A config that fails
+/// to `load` is reported and the previous one is kept until the next change.
+/// Spawn failures (including non-zero container exits) are reported too.
+pub fn watch_loop<C>(
+    mut config: C,
+    source: &mut dyn ChangeSource,
+    debounce: Duration,
+    snapshot: impl Fn() -> Vec<Option<String>>,
+    load: impl Fn() -> Result<C>,
+    mut spawn: impl FnMut(&C) -> Result<()>,
+) -> Result<()> {
+    loop {
+        let mut seen = snapshot();
+        if let Err(e) = spawn(&config) {
+            eprintln!("Error: {}", e);
+        }
+        eprintln!("Watching config for changes (Ctrl-C to stop)...");
+
+        loop {
+            if !wait_for_change(source, debounce) {
+                return Ok(());
+            }
+            let current = snapshot();
+            if current == seen {
+                continue;
+            }
+            seen = current;
+            match load() {
+                Ok(reloaded) => {
+                    config = reloaded;
+                    eprintln!("Config changed, re-spawning");
+                    break;
+                }
+                Err(e) => eprintln!("Error: {}\nFix the config to re-spawn.", e),
+            }
+        }
+    }
+}
+
+/// Read each path, `None` if it doesn't exist or can't be read
+pub fn read_snapshot(paths: &[PathBuf]) -> Vec<Option<String>> {
+    paths
+        .iter()
+        .map(|p| std::fs::read_to_string(p).ok())
+        .collect()
+}
+
+/// [`ChangeSource`] backed by filesystem notifications.
+///
+/// Watches each file's parent directory, since editors often save by
+/// replacing the file, and filters events down to the given paths.
+pub struct NotifySource {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    paths: Vec<PathBuf>,
+}
+
+impl NotifySource {
+    pub fn new(paths: &[PathBuf]) -> Result<Self> {
+        let (tx, events) = channel();
+        let mut watcher =
+            notify::recommended_watcher(tx).wrap_err("Failed to start file watcher")?;
+
+        let mut dirs: Vec<&Path> = paths.iter().filter_map(|p| p.parent()).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .wrap_err_with(|| format!("Failed to watch {}", dir.display()))?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            paths: paths.to_vec(),
+        })
+    }
+}
+
+impl ChangeSource for NotifySource {
+    fn wait(&mut self, timeout: Option<Duration>) -> Wake {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let event = match deadline {
+                None => self
+                    .events
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+                Some(deadline) => self
+                    .events
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            };
+            match event {
+                Ok(Ok(event)) if event.paths.iter().any(|p| self.paths.contains(p)) => {
+                    return Wake::Changed;
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => eprintln!("Warning: file watcher error: {}", e),
+                Err(RecvTimeoutError::Timeout) => return Wake::Quiet,
+                Err(RecvTimeoutError::Disconnected) => return Wake::Closed,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+
+    /// Replays scripted wakes, then reports `Closed`
+    struct Scripted {
+        wakes: VecDeque<Wake>,
+        timeouts: Vec<Option<Duration>>,
+    }
+
+    impl Scripted {
+        fn new(wakes: &[Wake]) -> Self {
+            Self {
+                wakes: wakes.iter().copied().collect(),
+                timeouts: Vec::new(),
+            }
+        }
+    }
+
+    impl ChangeSource for Scripted {
+        fn wait(&mut self, timeout: Option<Duration>) -> Wake {
+            self.timeouts.push(timeout);
+            self.wakes.pop_front().unwrap_or(Wake::Closed)
+        }
+    }
+
+    const D: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn test_wait_for_change_debounces_a_burst() {
+        let mut source = Scripted::new(&[
+            Wake::Changed,
+            Wake::Changed,
+            Wake::Changed,
+            Wake::Quiet,
+            Wake::Changed,
+        ]);
+
+        assert!(wait_for_change(&mut source, D));
+        // Blocks for the first change, then waits out the burst
+        assert_eq!(source.timeouts, vec![None, Some(D), Some(D), Some(D)]);
+        assert_eq!(source.wakes.len(), 1);
+    }
+
+    #[test]
+    fn test_wait_for_change_reports_closed_source() {
+        assert!(!wait_for_change(&mut Scripted::new(&[]), D));
+    }
+
+    /// Each change writes the next contents to `file`, then settles
+    struct Edits<'a> {
+        file: &'a RefCell<String>,
+        pending: VecDeque<&'static str>,
+        settling: bool,
+    }
+
+    impl ChangeSource for Edits<'_> {
+        fn wait(&mut self, _: Option<Duration>) -> Wake {
+            if std::mem::take(&mut self.settling) {
+                return Wake::Quiet;
+            }
+            match self.pending.pop_front() {
+                Some(contents) => {
+                    *self.file.borrow_mut() = contents.to_string();
+                    self.settling = true;
+                    Wake::Changed
+                }
+                None => Wake::Closed,
+            }
+        }
+    }
+
+    /// Run `watch_loop` over a file edited to each of `edits` in turn.
+    /// Returns the configs spawned and the number of reloads.
+    fn run_watch(edits: &[&'static str]) -> (Vec<String>, usize) {
+        let file = RefCell::new(String::from("v1"));
+        let loads = Cell::new(0);
+        let mut spawned = Vec::new();
+        let mut source = Edits {
+            file: &file,
+            pending: edits.iter().copied().collect(),
+            settling: false,
+        };
+
+        watch_loop(
+            String::from("v1"),
+            &mut source,
+            D,
+            || vec![Some(file.borrow().clone())],
+            || {
+                loads.set(loads.get() + 1);
+                let contents = file.borrow().clone();
+                if contents.starts_with("bad") {
+                    eyre::bail!("invalid config");
+                }
+                Ok(contents)
+            },
+            |config: &String| {
+                spawned.push(config.clone());
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        (spawned, loads.get())
+    }
+
+    #[test]
+    fn test_watch_loop_respawns_on_change() {
+        let (spawned, loads) = run_watch(&["v2", "v3"]);
+        assert_eq!(spawned, vec!["v1", "v2", "v3"]);
+        assert_eq!(loads, 2);
+    }
+
+    #[test]
+    fn test_watch_loop_ignores_unchanged_contents() {
+        let (spawned, loads) = run_watch(&["v1", "v2"]);
+        assert_eq!(spawned, vec!["v1", "v2"]);
+        assert_eq!(loads, 1);
+    }
+
+    #[test]
+    fn test_watch_loop_keeps_watching_after_invalid_config() {
+        let (spawned, loads) = run_watch(&["bad = [", "v2"]);
+        // The invalid edit is reported, not spawned, and the fix re-spawns
+        assert_eq!(spawned, vec!["v1", "v2"]);
+        assert_eq!(loads, 2);
+    }
+
+    #[test]
+    fn test_watch_loop_continues_after_spawn_failure() {
+        let mut source = Scripted::new(&[Wake::Changed, Wake::Quiet]);
+        let version = Cell::new(1);
+        let mut spawns = 0;
+
+        watch_loop(
+            (),
+            &mut source,
+            D,
+            || {
+                version.set(version.get() + 1);
+                vec![Some(version.get().to_string())]
+            },
+            || Ok(()),
+            |_| {
+                spawns += 1;
+                eyre::bail!("container exited with status 1")
+            },
+        )
+        .unwrap();
+
+        assert_eq!(spawns, 2);
+    }
+
+    #[test]
+    fn test_notify_source_reports_replaced_file() {
+        let dir = std::env::temp_dir().join(format!("ab-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join(".agent-box.toml");
+        std::fs::write(&config, "a = 1\n").unwrap();
+
+        let mut source = NotifySource::new(std::slice::from_ref(&config)).unwrap();
+
+        // Unrelated files in the same directory are ignored
+        std::fs::write(dir.join("other.txt"), "x").unwrap();
+        assert_eq!(source.wait(Some(Duration::from_millis(200))), Wake::Quiet);
+
+        // Editors commonly save by writing a temp file and renaming it over
+        let tmp = dir.join(".agent-box.toml.tmp");
+        std::fs::write(&tmp, "a = 2\n").unwrap();
+        std::fs::rename(&tmp, &config).unwrap();
+        assert_eq!(source.wait(Some(Duration::from_secs(5))), Wake::Changed);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
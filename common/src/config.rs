@@ -4,12 +4,13 @@ use figment::{
     providers::{Format, Toml},
 };
 use schemars::JsonSchema;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::path::expand_path;
@@ -17,13 +18,16 @@ use crate::portal::PortalConfig;
 use crate::repo::find_git_root;
 
 /// Mount mode for container volumes
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
 pub enum MountMode {
     /// Read-only mount
     Ro,
     /// Read-write mount
+    #[default]
     Rw,
     /// Overlay mount (Podman only)
+    #[serde(alias = "o", alias = "O")]
     Overlay,
 }
 
@@ -49,6 +53,15 @@ impl MountMode {
             MountMode::Overlay => "O",
         }
     }
+
+    /// Rank used by `mount_conflict = "strictest"` (lower is stricter)
+    fn strictness(&self) -> u8 {
+        match self {
+            MountMode::Ro => 0,
+            MountMode::Rw => 1,
+            MountMode::Overlay => 2,
+        }
+    }
 }
 
 impl fmt::Display for MountMode {
@@ -57,23 +70,105 @@ impl fmt::Display for MountMode {
     }
 }
 
+/// Serialized as the mount flag string (`"ro"`, `"rw"`, `"O"`)
+impl Serialize for MountMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Bind propagation for a mount (`bind-propagation` in `--mount` syntax)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BindPropagation {
+    Private,
+    Rprivate,
+    Shared,
+    Rshared,
+    Slave,
+    Rslave,
+}
+
+impl BindPropagation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BindPropagation::Private => "private",
+            BindPropagation::Rprivate => "rprivate",
+            BindPropagation::Shared => "shared",
+            BindPropagation::Rshared => "rshared",
+            BindPropagation::Slave => "slave",
+            BindPropagation::Rslave => "rslave",
+        }
+    }
+}
+
+impl FromStr for BindPropagation {
+    type Err = eyre::ErrReport;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "private" => Ok(BindPropagation::Private),
+            "rprivate" => Ok(BindPropagation::Rprivate),
+            "shared" => Ok(BindPropagation::Shared),
+            "rshared" => Ok(BindPropagation::Rshared),
+            "slave" => Ok(BindPropagation::Slave),
+            "rslave" => Ok(BindPropagation::Rslave),
+            _ => Err(eyre::eyre!("Invalid bind propagation: {}", s)),
+        }
+    }
+}
+
+/// How the backends pass binds to the container runtime
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MountSyntax {
+    /// `-v host:container:mode`
+    #[default]
+    Volume,
+    /// `--mount type=bind,source=...,target=...[,readonly]`; overlay mounts keep `-v`
+    Mount,
+}
+
 /// A resolved mount ready for use (after path expansion and canonicalization)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct ResolvedMount {
     pub host: PathBuf,
     pub container: PathBuf,
     pub mode: MountMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub propagation: Option<BindPropagation>,
+    /// Whether mounts nested under `host` are bound too (the runtime default)
+    #[serde(skip_serializing_if = "is_true")]
+    pub recursive: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
 }
 
 impl ResolvedMount {
     /// Format as bind string for docker/podman -v flag
+    /// (`host:container:mode`, plus `,propagation` when set and `,nonrecursive`
+    /// for non-recursive binds; overlay mounts are always recursive)
     pub fn to_bind_string(&self) -> String {
-        format!(
+        let mut bind = format!(
             "{}:{}:{}",
             self.host.display(),
             self.container.display(),
             self.mode.as_str()
-        )
+        );
+        if let Some(propagation) = self.propagation {
+            bind.push(',');
+            bind.push_str(propagation.as_str());
+        }
+        if !self.recursive && self.mode != MountMode::Overlay {
+            bind.push_str(",nonrecursive");
+        }
+        bind
     }
 }
 
@@ -94,6 +189,10 @@ pub struct Mount {
     pub home_relative: bool,
     /// Mount mode
     pub mode: MountMode,
+    /// Bind propagation, if not the runtime default
+    pub propagation: Option<BindPropagation>,
+    /// Whether nested mounts under the source are bound too
+    pub recursive: bool,
 }
 
 impl Mount {
@@ -275,6 +374,7 @@ impl Mount {
                     container_home,
                     &mut resolved_mounts,
                     &mut seen_paths,
+                    &mut std::collections::HashSet::new(),
                 )?;
             }
 
@@ -300,12 +400,16 @@ impl Mount {
             container_home,
             &mut resolved_mounts,
             &mut seen_paths,
+            &mut std::collections::HashSet::new(),
         )?;
 
         Ok(resolved_mounts)
     }
 
     /// Recursively collect all paths in a symlink chain.
+    ///
+    /// `chain` holds the symlinks visited on the current chain, so a cycle
+    /// (a -> b -> a) fails with a clear error rather than looping.
     fn collect_symlink_chain(
         &self,
         path: &PathBuf,
@@ -313,22 +417,38 @@ impl Mount {
         container_home: &str,
         resolved_mounts: &mut Vec<ResolvedMount>,
         seen: &mut std::collections::HashSet<PathBuf>,
+        chain: &mut std::collections::HashSet<PathBuf>,
     ) -> Result<()> {
-        // Canonicalize to get the absolute path (resolves . and ..)
-        let canonical = path
-            .canonicalize()
-            .wrap_err(format!("Failed to canonicalize path: {}", path.display()))?;
-
-        // If we've already seen this canonical path, skip
-        if seen.contains(&canonical) {
-            return Ok(());
-        }
-
         // Check if the path itself is a symlink
         let metadata = std::fs::symlink_metadata(path)
             .wrap_err(format!("Failed to get metadata for: {}", path.display()))?;
 
         if metadata.is_symlink() {
+            // Identify the link by its canonical parent, so different spellings
+            // of the same link are caught as a cycle
+            let link = path
+                .parent()
+                .and_then(|parent| parent.canonicalize().ok())
+                .zip(path.file_name())
+                .map(|(parent, name)| parent.join(name))
+                .unwrap_or_else(|| path.clone());
+            if !chain.insert(link) {
+                return Err(eyre::eyre!(
+                    "symlink cycle detected at {} (mount: {})",
+                    path.display(),
+                    self.spec
+                ));
+            }
+
+            // If we've already seen the final target, skip the whole chain.
+            // Canonicalizing fails on a cycle, which the walk below reports.
+            if path
+                .canonicalize()
+                .is_ok_and(|canonical| seen.contains(&canonical))
+            {
+                return Ok(());
+            }
+
             // Mount the symlink itself (not following it)
             let path_str = path.to_string_lossy().to_string();
             let container_path = self.derive_container_path(&path_str, host_home, container_home);
@@ -336,6 +456,8 @@ impl Mount {
                 host: path.clone(),
                 container: container_path,
                 mode: self.mode,
+                propagation: self.propagation,
+                recursive: self.recursive,
             });
 
             // Read the symlink target
@@ -356,10 +478,20 @@ impl Mount {
                 container_home,
                 resolved_mounts,
                 seen,
+                chain,
             )?;
         } else {
+            // Canonicalize to get the absolute path (resolves . and ..)
+            let canonical = path
+                .canonicalize()
+                .wrap_err(format!("Failed to canonicalize path: {}", path.display()))?;
+
+            // If we've already seen this canonical path, skip
+            if !seen.insert(canonical.clone()) {
+                return Ok(());
+            }
+
             // Not a symlink - mount the final target
-            seen.insert(canonical.clone());
             let canonical_str = canonical.to_string_lossy().to_string();
             let container_path =
                 self.derive_container_path(&canonical_str, host_home, container_home);
@@ -367,6 +499,8 @@ impl Mount {
                 host: canonical,
                 container: container_path,
                 mode: self.mode,
+                propagation: self.propagation,
+                recursive: self.recursive,
             });
         }
 
@@ -392,7 +526,10 @@ impl Mount {
 impl PartialEq for Mount {
     fn eq(&self, other: &Self) -> bool {
         // Two mounts are equal if they have the same mode and resolve to the same paths
-        if self.mode != other.mode {
+        if self.mode != other.mode
+            || self.propagation != other.propagation
+            || self.recursive != other.recursive
+        {
             return false;
         }
 
@@ -414,6 +551,8 @@ impl Eq for Mount {}
 impl std::hash::Hash for Mount {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.mode.hash(state);
+        self.propagation.hash(state);
+        self.recursive.hash(state);
         // Hash the resolved paths for consistency with PartialEq
         let dummy_home = "/home/user";
         if let Ok((host, container)) = self.resolve_paths(dummy_home, dummy_home) {
@@ -427,6 +566,83 @@ impl std::hash::Hash for Mount {
     }
 }
 
+/// Identifies one mount resolution: the spec as written and the homes it was resolved against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MountKey {
+    pub spec: String,
+    pub home_relative: bool,
+    pub mode: MountMode,
+    pub propagation: Option<BindPropagation>,
+    pub recursive: bool,
+    pub host_home: String,
+    pub container_home: String,
+}
+
+impl MountKey {
+    pub fn new(mount: &Mount, host_home: &str, container_home: &str) -> Self {
+        Self {
+            spec: mount.spec.clone(),
+            home_relative: mount.home_relative,
+            mode: mount.mode,
+            propagation: mount.propagation,
+            recursive: mount.recursive,
+            host_home: host_home.to_string(),
+            container_home: container_home.to_string(),
+        }
+    }
+}
+
+/// Per-spawn memoization of mount resolution, so each unique mount is
+/// canonicalized and its symlink chain walked only once.
+/// Failed resolutions are not cached.
+#[derive(Debug, Default)]
+pub struct MountResolutionCache {
+    chains: HashMap<MountKey, Vec<ResolvedMount>>,
+    pairs: HashMap<MountKey, (String, String)>,
+    resolutions: usize,
+}
+
+impl MountResolutionCache {
+    /// Cached [`Mount::to_resolved_mounts_with_homes`]
+    pub fn resolve_chain(
+        &mut self,
+        mount: &Mount,
+        host_home: &str,
+        container_home: &str,
+    ) -> Result<Vec<ResolvedMount>> {
+        let key = MountKey::new(mount, host_home, container_home);
+        if let Some(resolved) = self.chains.get(&key) {
+            return Ok(resolved.clone());
+        }
+        self.resolutions += 1;
+        let resolved = mount.to_resolved_mounts_with_homes(host_home, container_home)?;
+        self.chains.insert(key, resolved.clone());
+        Ok(resolved)
+    }
+
+    /// Cached [`Mount::resolve_with_homes`]
+    pub fn resolve_pair(
+        &mut self,
+        mount: &Mount,
+        host_home: &str,
+        container_home: &str,
+    ) -> Result<(String, String)> {
+        let key = MountKey::new(mount, host_home, container_home);
+        if let Some(resolved) = self.pairs.get(&key) {
+            return Ok(resolved.clone());
+        }
+        self.resolutions += 1;
+        let resolved = mount.resolve_with_homes(host_home, container_home)?;
+        self.pairs.insert(key, resolved.clone());
+        Ok(resolved)
+    }
+
+    /// Number of resolutions that actually hit the filesystem (cache misses)
+    pub fn resolutions(&self) -> usize {
+        self.resolutions
+    }
+}
+
 impl fmt::Display for Mount {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}: {}", self.mode, self.spec)?;
@@ -437,7 +653,27 @@ impl fmt::Display for Mount {
     }
 }
 
-#[derive(Debug, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+/// Serialized as `{"mode": "ro", "spec": "~/.config", "home_relative": true}`
+impl Serialize for Mount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let len = 3 + usize::from(self.propagation.is_some()) + usize::from(!self.recursive);
+        let mut state = serializer.serialize_struct("Mount", len)?;
+        state.serialize_field("mode", &self.mode)?;
+        state.serialize_field("spec", &self.spec)?;
+        state.serialize_field("home_relative", &self.home_relative)?;
+        if let Some(propagation) = &self.propagation {
+            state.serialize_field("propagation", propagation)?;
+        }
+        if !self.recursive {
+            state.serialize_field("recursive", &self.recursive)?;
+        }
+        state.end()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
 pub struct MountPaths {
     #[serde(default)]
     pub absolute: Vec<String>,
@@ -445,7 +681,124 @@ pub struct MountPaths {
     pub home_relative: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+/// One element of the unified `mounts.entries` list
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct MountEntry {
+    /// Path or `source:dest` mapping, as in the `ro`/`rw`/`o` lists
+    pub spec: String,
+    /// `ro`, `rw` (default), or `overlay` (`o`)
+    #[serde(default)]
+    pub mode: MountMode,
+    /// Translate the home directory prefix; defaults to whether `spec` starts with `~`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_relative: Option<bool>,
+    /// Bind propagation (`rslave`, `rshared`, ...), passed as `,propagation` after the mode
+    /// or as `bind-propagation=` with `runtime.mount_syntax = "mount"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_propagation: Option<BindPropagation>,
+    /// Set to `false` for a non-recursive bind that doesn't expose mounts nested under `spec`.
+    /// Passed as `--mount ...,bind-nonrecursive` whatever `runtime.mount_syntax` is;
+    /// ignored for overlay mounts
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub recursive: bool,
+}
+
+impl MountEntry {
+    pub fn to_mount(&self) -> Mount {
+        Mount {
+            spec: self.spec.clone(),
+            home_relative: self
+                .home_relative
+                .unwrap_or_else(|| self.spec.starts_with('~')),
+            mode: self.mode,
+            propagation: self.bind_propagation,
+            recursive: self.recursive,
+        }
+    }
+}
+
+/// Empty in-memory mounts at container paths, shadowing whatever is there;
+/// nothing written to them reaches the host
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct TmpfsMounts {
+    /// Absolute container paths, or `~/...` for paths under the container home
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+impl TmpfsMounts {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Validation problems, one message per bad path
+    pub fn problems(&self) -> Vec<String> {
+        self.paths
+            .iter()
+            .filter(|path| {
+                !(path.starts_with('/') || *path == "~" || path.starts_with("~/"))
+                    || path.contains([':', ','])
+            })
+            .map(|path| {
+                format!(
+                    "mounts.tmpfs path '{}' must be absolute or start with '~/', without ':' or ','",
+                    path
+                )
+            })
+            .collect()
+    }
+}
+
+/// A persistent named volume mount: `NAME:PATH` or `NAME:PATH:MODE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeSpec {
+    pub name: String,
+    /// Absolute container path, or `~/...` under the container home
+    pub path: String,
+    /// `ro` or `rw`
+    pub mode: MountMode,
+}
+
+impl FromStr for VolumeSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = |why: &str| format!("volume '{}' {}", spec, why);
+        let mut parts = spec.splitn(3, ':');
+        let (Some(name), Some(path)) = (parts.next(), parts.next()) else {
+            return Err(invalid("must be NAME:PATH or NAME:PATH:MODE"));
+        };
+        let valid_name = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        if !valid_name {
+            return Err(invalid(
+                "needs a name of letters, digits, '_', '.', and '-', starting with a letter or digit",
+            ));
+        }
+        if !(path.starts_with('/') || path == "~" || path.starts_with("~/")) || path.contains(',') {
+            return Err(invalid(
+                "needs a path that is absolute or starts with '~/', without ','",
+            ));
+        }
+        let mode = match parts.next() {
+            None | Some("rw") => MountMode::Rw,
+            Some("ro") => MountMode::Ro,
+            Some(_) => return Err(invalid("needs mode ro or rw")),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            path: path.to_string(),
+            mode,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
 pub struct MountsConfig {
     #[serde(default)]
     pub ro: MountPaths,
@@ -453,6 +806,29 @@ pub struct MountsConfig {
     pub rw: MountPaths,
     #[serde(default)]
     pub o: MountPaths,
+    /// Mounts with per-entry modes, after the `ro`/`rw`/`o` lists
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<MountEntry>,
+    /// tmpfs mounts; these have no host side, so they aren't part of [`Self::to_mounts`]
+    #[serde(default, skip_serializing_if = "TmpfsMounts::is_empty")]
+    pub tmpfs: TmpfsMounts,
+    /// Named volumes as [`VolumeSpec`] strings, kept by the runtime across containers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+}
+
+impl MountsConfig {
+    /// Validation problems with the tmpfs paths and volume specs
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = self.tmpfs.problems();
+        problems.extend(
+            self.volumes
+                .iter()
+                .filter_map(|spec| spec.parse::<VolumeSpec>().err())
+                .map(|e| format!("mounts.{}", e)),
+        );
+        problems
+    }
 }
 
 impl MountsConfig {
@@ -465,6 +841,8 @@ impl MountsConfig {
                 spec: spec.clone(),
                 home_relative: false,
                 mode: MountMode::Ro,
+                propagation: None,
+                recursive: true,
             });
         }
         for spec in &self.ro.home_relative {
@@ -472,6 +850,8 @@ impl MountsConfig {
                 spec: spec.clone(),
                 home_relative: true,
                 mode: MountMode::Ro,
+                propagation: None,
+                recursive: true,
             });
         }
         for spec in &self.rw.absolute {
@@ -479,6 +859,8 @@ impl MountsConfig {
                 spec: spec.clone(),
                 home_relative: false,
                 mode: MountMode::Rw,
+                propagation: None,
+                recursive: true,
             });
         }
         for spec in &self.rw.home_relative {
@@ -486,6 +868,8 @@ impl MountsConfig {
                 spec: spec.clone(),
                 home_relative: true,
                 mode: MountMode::Rw,
+                propagation: None,
+                recursive: true,
             });
         }
         for spec in &self.o.absolute {
@@ -493,6 +877,8 @@ impl MountsConfig {
                 spec: spec.clone(),
                 home_relative: false,
                 mode: MountMode::Overlay,
+                propagation: None,
+                recursive: true,
             });
         }
         for spec in &self.o.home_relative {
@@ -500,8 +886,11 @@ impl MountsConfig {
                 spec: spec.clone(),
                 home_relative: true,
                 mode: MountMode::Overlay,
+                propagation: None,
+                recursive: true,
             });
         }
+        mounts.extend(self.entries.iter().map(MountEntry::to_mount));
 
         mounts
     }
@@ -509,7 +898,7 @@ impl MountsConfig {
 
 /// A profile defines a named set of mounts, environment variables, and port mappings.
 /// Profiles can extend other profiles via the `extends` field.
-#[derive(Debug, Deserialize, Default, Clone, PartialEq, JsonSchema)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
 pub struct ProfileConfig {
     /// List of profile names this profile extends (inherits from)
     #[serde(default)]
@@ -532,146 +921,1069 @@ pub struct ProfileConfig {
     /// Context for this profile
     #[serde(default)]
     pub context: String,
+    /// Default container command, as a shell-style string or an array.
+    /// Overrides `runtime.command` and earlier profiles; `--command` overrides it.
+    #[serde(default, deserialize_with = "deserialize_command")]
+    #[schemars(with = "Option<CommandSpec>")]
+    pub command: Option<Vec<String>>,
+    /// Container network mode (e.g. "host", "bridge", "none"), or a table with a
+    /// `mode` and the `allow_hosts` the container may reach through a proxy.
+    /// The mode overrides `runtime.network` and earlier profiles; `--network` overrides it.
+    #[serde(default, deserialize_with = "deserialize_network")]
+    #[schemars(with = "Option<NetworkSpec>")]
+    pub network: Option<NetworkConfig>,
+    /// Resource limits; set ones override `runtime.resources` and earlier profiles
+    #[serde(default)]
+    pub resources: ResourcesConfig,
+    /// Image to build and run instead of `runtime.image`.
+    /// Overrides earlier profiles' builds; `--image` overrides it.
+    #[serde(default)]
+    pub build: Option<BuildConfig>,
+    /// Secrets fetched on the host at spawn, by env var name (or file name);
+    /// a later profile's secret replaces an earlier one of the same name
+    #[serde(default)]
+    pub secrets: BTreeMap<String, SecretConfig>,
+    /// GPUs to pass through: `true` or `"all"`, `"device=0"` or `"device=0,1"`,
+    /// or `false` for none. Overrides `runtime.gpu.devices` and earlier profiles.
+    #[serde(default)]
+    pub gpu: Option<GpuSetting>,
 }
 
-/// Deserialize entrypoint from a shell-style string into Vec<String>
-fn deserialize_entrypoint<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let opt: Option<String> = Option::deserialize(deserializer)?;
-    opt.map(|s| shell_words::split(&s).map_err(serde::de::Error::custom))
-        .transpose()
+/// A GPU selection: `true`/`false`, or a string `"all"` or `"device=ID[,ID...]"`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(untagged)]
+pub enum GpuSetting {
+    Enabled(bool),
+    Devices(String),
 }
 
-fn default_backend() -> String {
-    "podman".to_string()
+impl GpuSetting {
+    /// The GPUs this selects: `["all"]`, device IDs, or none
+    pub fn devices(&self) -> Result<Vec<String>, String> {
+        match self {
+            GpuSetting::Enabled(true) => Ok(vec!["all".to_string()]),
+            GpuSetting::Enabled(false) => Ok(Vec::new()),
+            GpuSetting::Devices(spec) if spec == "all" => Ok(vec!["all".to_string()]),
+            GpuSetting::Devices(spec) => {
+                let invalid = || {
+                    format!(
+                        "gpu '{}' must be true, false, \"all\", or \"device=ID[,ID...]\"",
+                        spec
+                    )
+                };
+                let ids: Vec<String> = spec
+                    .strip_prefix("device=")
+                    .ok_or_else(invalid)?
+                    .split(',')
+                    .map(str::to_string)
+                    .collect();
+                if ids
+                    .iter()
+                    .any(|id| id.is_empty() || id == "all" || id.contains(char::is_whitespace))
+                {
+                    return Err(invalid());
+                }
+                Ok(ids)
+            }
+        }
+    }
 }
 
-fn default_context_path() -> String {
-    "/tmp/context".to_string()
+/// `[runtime.gpu]`: GPU passthrough for every spawn, and how Podman requests GPUs
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, Eq, JsonSchema)]
+pub struct GpuConfig {
+    /// GPUs every spawn gets unless a profile sets `gpu`, with the same values
+    #[serde(default)]
+    pub devices: Option<GpuSetting>,
+    /// CDI device kind Podman requests GPUs as (default `nvidia.com/gpu`)
+    #[serde(default)]
+    pub cdi_kind: Option<String>,
 }
 
-fn default_dns() -> Vec<String> {
-    vec!["1.1.1.1".to_string(), "8.8.8.8".to_string()]
+impl GpuConfig {
+    /// What's wrong with these settings, for `validate_config`
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .devices
+            .iter()
+            .filter_map(|devices| devices.devices().err())
+            .map(|e| format!("gpu.devices: {}", e))
+            .collect();
+        if let Some(kind) = &self.cdi_kind
+            && !kind
+                .split_once('/')
+                .is_some_and(|(vendor, class)| !vendor.is_empty() && !class.is_empty())
+        {
+            problems.push(format!(
+                "gpu.cdi_kind '{}' must be VENDOR/CLASS, e.g. nvidia.com/gpu",
+                kind
+            ));
+        }
+        problems
+    }
 }
 
-#[derive(Debug, Deserialize, Default, Clone, PartialEq, JsonSchema)]
-pub struct RuntimeConfig {
-    #[serde(default = "default_backend")]
-    pub backend: String,
-    #[serde(default)]
-    pub image: String,
-    #[serde(default, deserialize_with = "deserialize_entrypoint")]
-    pub entrypoint: Option<Vec<String>>,
-    #[serde(default)]
-    pub mounts: MountsConfig,
-    #[serde(default)]
-    pub env: Vec<String>,
-    /// Environment variable names to pass through from host to container
-    #[serde(default)]
-    pub env_passthrough: Vec<String>,
-    /// Port mappings to expose (Docker `-p` syntax: `[HOST_IP:]HOST_PORT:CONTAINER_PORT`)
+/// `[profiles.NAME.secrets]` entry: where a secret's value comes from (exactly one
+/// of `command`, `file`, or `env`), and whether the container gets it as an env
+/// var (the default) or as a file at `path`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct SecretConfig {
+    /// Command whose stdout (minus trailing newlines) is the value, as a
+    /// shell-style string or an array. It is run without a shell.
+    #[serde(default, deserialize_with = "deserialize_command")]
+    #[schemars(with = "Option<CommandSpec>")]
+    pub command: Option<Vec<String>>,
+    /// Host file holding the value (`~` is the host home)
     #[serde(default)]
-    pub ports: Vec<String>,
-    /// Custom host-to-IP mappings added to `/etc/hosts` inside the container (`HOST:IP`)
+    pub file: Option<String>,
+    /// Host env var holding the value
     #[serde(default)]
-    pub hosts: Vec<String>,
-    /// DNS servers to use inside the container (passed as `--dns` to the runtime).
-    /// When set, the runtime generates `/etc/resolv.conf` from these servers
-    /// instead of copying the host's configuration.
-    #[serde(default = "default_dns")]
-    pub dns: Vec<String>,
+    pub env: Option<String>,
+    /// Absolute container path to put the value in a read-only file at,
+    /// instead of setting an env var
     #[serde(default)]
-    pub skip_mounts: Vec<String>,
+    pub path: Option<String>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, JsonSchema)]
-pub struct Config {
-    pub workspace_dir: PathBuf,
-    pub base_repo_dir: PathBuf,
-    /// Default profile name to always apply (if set)
-    #[serde(default)]
-    pub default_profile: Option<String>,
-    /// Named profiles that can be selected via CLI
-    #[serde(default)]
-    pub profiles: HashMap<String, ProfileConfig>,
-    #[serde(default)]
-    pub runtime: RuntimeConfig,
-    /// Root-level context
+impl SecretConfig {
+    /// What's wrong with secret `name`, for `validate_config`
+    pub fn problems(&self, name: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        let sources = [
+            self.command.is_some(),
+            self.file.is_some(),
+            self.env.is_some(),
+        ];
+        if sources.iter().filter(|set| **set).count() != 1 {
+            problems.push(format!(
+                "secrets.{} must set exactly one of command, file, or env",
+                name
+            ));
+        }
+        if self.command.as_ref().is_some_and(Vec::is_empty) {
+            problems.push(format!("secrets.{}.command is empty", name));
+        }
+        if let Some(path) = &self.path
+            && !path.starts_with('/')
+        {
+            problems.push(format!(
+                "secrets.{}.path '{}' must be an absolute container path",
+                name, path
+            ));
+        }
+        problems
+    }
+}
+
+/// `[profiles.NAME.network]`: the network mode, and optionally the hosts the
+/// container may reach. With `allow_hosts` set, `ab spawn` puts the container on
+/// an internal network whose only way out is a filtering HTTP(S) proxy sidecar.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default, JsonSchema)]
+pub struct NetworkConfig {
+    /// Network mode (e.g. "host", "bridge", "none")
     #[serde(default)]
-    pub context: String,
-    /// Path where context file will be mounted inside the container
-    #[serde(default = "default_context_path")]
-    pub context_path: String,
-    /// Host portal service configuration
+    pub mode: Option<String>,
+    /// Hosts the proxy lets through, e.g. "github.com" or "*.npmjs.org"
+    /// (any subdomain). Profiles' lists are combined.
     #[serde(default)]
-    pub portal: PortalConfig,
+    pub allow_hosts: Vec<String>,
 }
 
-/// Resolved mounts, env, ports, and hosts from profile resolution
-#[derive(Debug, Default, Clone, PartialEq)]
-pub struct ResolvedProfile {
-    pub mounts: Vec<Mount>,
-    pub env: Vec<String>,
-    pub env_passthrough: Vec<String>,
-    pub ports: Vec<String>,
-    pub hosts: Vec<String>,
-    pub context: Vec<String>,
+impl NetworkConfig {
+    /// What's wrong with this network section, for `validate_config`
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.allow_hosts.is_empty()
+            && let Some(mode) = self.mode.as_deref()
+            && mode != "bridge"
+        {
+            problems.push(format!(
+                "network.allow_hosts needs mode \"bridge\" (or no mode), not '{}'",
+                mode
+            ));
+        }
+        for host in &self.allow_hosts {
+            let name = host.strip_prefix("*.").unwrap_or(host);
+            if name.is_empty()
+                || name.contains(['*', '/', ':'])
+                || name.contains(char::is_whitespace)
+            {
+                problems.push(format!(
+                    "network.allow_hosts entry '{}' must be a host name, optionally starting with '*.'",
+                    host
+                ));
+            }
+        }
+        problems
+    }
 }
 
-impl ResolvedProfile {
-    /// Merge another resolved profile into this one
-    pub fn merge(&mut self, other: &ResolvedProfile) {
-        self.mounts.extend(other.mounts.iter().cloned());
-        self.env.extend(other.env.iter().cloned());
-        self.env_passthrough
-            .extend(other.env_passthrough.iter().cloned());
-        self.ports.extend(other.ports.iter().cloned());
-        self.hosts.extend(other.hosts.iter().cloned());
-        self.context.extend(other.context.iter().cloned());
-    }
+/// A network written either as a bare mode or as a `[network]` table
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum NetworkSpec {
+    Mode(String),
+    Table(NetworkConfig),
+}
 
-    /// Deduplicate mounts by resolved path (first occurrence wins).
-    /// Uses canonicalized paths when possible to handle symlinks.
-    pub fn dedup_mounts(&mut self) {
-        let mut seen = HashSet::new();
+/// Deserialize a network from a mode string or a table into [`NetworkConfig`]
+fn deserialize_network<'de, D>(deserializer: D) -> Result<Option<NetworkConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(
+        Option::<NetworkSpec>::deserialize(deserializer)?.map(|spec| match spec {
+            NetworkSpec::Mode(mode) => NetworkConfig {
+                mode: Some(mode),
+                allow_hosts: Vec::new(),
+            },
+            NetworkSpec::Table(network) => network,
+        }),
+    )
+}
 
-        // Get home dir for resolution
-        let host_home = std::env::var("HOME").unwrap_or_else(|_| "/home/user".to_string());
-        let container_user = std::env::var("USER")
-            .or_else(|_| std::env::var("LOGNAME"))
-            .unwrap_or_else(|_| "user".to_string());
-        let container_home = format!("/home/{}", container_user);
+/// `[profiles.NAME.build]`: a Dockerfile or Containerfile `ab spawn` builds the
+/// image from, tagged by a hash of its inputs so it's rebuilt only when they change
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct BuildConfig {
+    /// Dockerfile or Containerfile. Relative paths are resolved against the
+    /// directory of the config file that sets them.
+    pub file: PathBuf,
+    /// Build context directory (default: the file's directory)
+    #[serde(default)]
+    pub context: Option<PathBuf>,
+    /// Build arguments (`--build-arg NAME=VALUE`)
+    #[serde(default)]
+    pub args: BTreeMap<String, String>,
+    /// Stage to build in a multi-stage file (`--target`)
+    #[serde(default)]
+    pub target: Option<String>,
+}
 
-        self.mounts.retain(|m| {
-            // Try to resolve to canonical bind string
-            // Fall back to non-canonical comparison if path doesn't exist
-            let key = m
-                .resolve()
-                .map(|(h, c)| format!("{}:{}:{}", h, c, m.mode))
-                .or_else(|_| {
-                    m.resolve_paths(&host_home, &container_home)
-                        .map(|(h, c)| format!("{}:{}:{}", h, c, m.mode))
-                })
-                .unwrap_or_else(|_| format!("{}:{}:{}", m.spec, m.home_relative, m.mode));
-            seen.insert(key)
-        });
+impl BuildConfig {
+    /// The build context: `context`, or the file's directory
+    pub fn context_dir(&self) -> PathBuf {
+        match &self.context {
+            Some(context) => context.clone(),
+            None => self
+                .file
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| PathBuf::from(".")),
+        }
     }
 
-    /// Deduplicate ports by exact string match (first occurrence wins).
-    pub fn dedup_ports(&mut self) {
-        let mut seen = HashSet::new();
-        self.ports.retain(|p| seen.insert(p.clone()));
+    /// Tag for the built image, e.g. `agent-box-build:3f2a9c1e0b7d4a65`, from a
+    /// hash of the file's contents, the context path, args, and target.
+    /// Files in the context aren't hashed; `ab spawn --rebuild` picks up their changes.
+    pub fn image_tag(&self) -> Result<String> {
+        let contents = std::fs::read(&self.file)
+            .map_err(|e| eyre::eyre!("Failed to read build file {}: {}", self.file.display(), e))?;
+        Ok(self.image_tag_for(&contents))
     }
 
-    /// Deduplicate hosts by exact string match (first occurrence wins).
-    pub fn dedup_hosts(&mut self) {
-        let mut seen = HashSet::new();
-        self.hosts.retain(|h| seen.insert(h.clone()));
+    /// [`Self::image_tag`] for the given file contents
+    pub fn image_tag_for(&self, contents: &[u8]) -> String {
+        let mut input = contents.to_vec();
+        let mut field = |value: &str| {
+            input.push(0);
+            input.extend_from_slice(value.as_bytes());
+        };
+        field(&self.context_dir().to_string_lossy());
+        for (name, value) in &self.args {
+            field(&format!("{}={}", name, value));
+        }
+        field(self.target.as_deref().unwrap_or_default());
+
+        let hash = gix::objs::compute_hash(gix::hash::Kind::Sha1, gix::objs::Kind::Blob, &input)
+            .expect("SHA-1 hashing an in-memory buffer can't fail");
+        format!("agent-box-build:{}", &hash.to_hex().to_string()[..16])
     }
+}
 
-    /// Get mount specs filtered by mode and home_relative flag (for testing)
+/// Container resource limits: `[runtime.resources]`, or `resources` in a profile.
+/// A profile's settings override earlier layers; `ulimits` merge by name.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct ResourcesConfig {
+    /// CPUs the container may use, e.g. `2` or `1.5` (`--cpus`)
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Memory limit with a `b`, `k`, `m`, or `g` suffix, e.g. `"4g"` (`--memory`)
+    #[serde(default)]
+    pub memory: Option<String>,
+    /// Memory plus swap limit, e.g. `"8g"`, or `"-1"` for unlimited swap (`--memory-swap`)
+    #[serde(default)]
+    pub memory_swap: Option<String>,
+    /// Maximum number of processes, or `-1` for no limit (`--pids-limit`)
+    #[serde(default)]
+    pub pids_limit: Option<i64>,
+    /// Limits by name as `"LIMIT"` or `"SOFT:HARD"`, e.g. `nofile = "1024:4096"` (`--ulimit`)
+    #[serde(default)]
+    pub ulimits: BTreeMap<String, String>,
+}
+
+impl ResourcesConfig {
+    /// Apply `other` on top of this: its set limits win
+    pub fn merge(&mut self, other: &ResourcesConfig) {
+        if other.cpus.is_some() {
+            self.cpus = other.cpus;
+        }
+        if other.memory.is_some() {
+            self.memory = other.memory.clone();
+        }
+        if other.memory_swap.is_some() {
+            self.memory_swap = other.memory_swap.clone();
+        }
+        if other.pids_limit.is_some() {
+            self.pids_limit = other.pids_limit;
+        }
+        self.ulimits.extend(
+            other
+                .ulimits
+                .iter()
+                .map(|(name, limit)| (name.clone(), limit.clone())),
+        );
+    }
+
+    /// True if no limit is set
+    pub fn is_empty(&self) -> bool {
+        *self == ResourcesConfig::default()
+    }
+
+    /// What's wrong with these limits, for `validate_config`
+    pub fn problems(&self) -> Vec<String> {
+        // What the runtimes accept: a number, then an optional `k`/`m`/`g`/`t`/`p`,
+        // `i`, and `b`, e.g. `512m`, `1.5g`, or `4GiB`
+        let is_size = |s: &str| {
+            let unit_start = s
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(s.len());
+            let (number, unit) = s.split_at(unit_start);
+            let unit = unit.trim_start().to_ascii_lowercase();
+            let unit = unit.strip_suffix('b').unwrap_or(&unit);
+            let unit = unit.strip_suffix('i').unwrap_or(unit);
+            !number.starts_with('.')
+                && number.parse::<f64>().is_ok()
+                && matches!(unit, "" | "k" | "m" | "g" | "t" | "p")
+        };
+        let is_limit =
+            |s: &str| s == "-1" || (!s.is_empty() && s.chars().all(|c| c.is_ascii_digit()));
+
+        let mut problems = Vec::new();
+        if let Some(cpus) = self.cpus
+            && (cpus.is_nan() || cpus <= 0.0)
+        {
+            problems.push(format!("resources.cpus must be positive, got {}", cpus));
+        }
+        if let Some(memory) = &self.memory
+            && !is_size(memory)
+        {
+            problems.push(format!(
+                "resources.memory '{}' is not a size like \"512m\" or \"4g\"",
+                memory
+            ));
+        }
+        if let Some(swap) = &self.memory_swap {
+            if swap != "-1" && !is_size(swap) {
+                problems.push(format!(
+                    "resources.memory_swap '{}' is not a size like \"8g\" or \"-1\"",
+                    swap
+                ));
+            }
+            if self.memory.is_none() {
+                problems.push("resources.memory_swap needs resources.memory".to_string());
+            }
+        }
+        for (name, limit) in &self.ulimits {
+            let valid = match limit.split_once(':') {
+                Some((soft, hard)) => is_limit(soft) && is_limit(hard),
+                None => is_limit(limit),
+            };
+            if !valid {
+                problems.push(format!(
+                    "resources.ulimits.{} '{}' is not \"LIMIT\" or \"SOFT:HARD\"",
+                    name, limit
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Deserialize entrypoint from a shell-style string into Vec<String>
+fn deserialize_entrypoint<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let opt: Option<String> = Option::deserialize(deserializer)?;
+    opt.map(|s| shell_words::split(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// A command written either as a shell-style string or as an argv array
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum CommandSpec {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+/// Deserialize a command from a shell-style string or an array into Vec<String>
+fn deserialize_command<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Option::<CommandSpec>::deserialize(deserializer)? {
+        Some(CommandSpec::Shell(s)) => {
+            Some(shell_words::split(&s).map_err(serde::de::Error::custom)?)
+        }
+        Some(CommandSpec::Argv(argv)) => Some(argv),
+        None => None,
+    })
+}
+
+/// Serialize entrypoint back into the shell-style string it was parsed from
+fn serialize_entrypoint<S>(
+    entrypoint: &Option<Vec<String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    entrypoint
+        .as_ref()
+        .map(shell_words::join)
+        .serialize(serializer)
+}
+
+/// Parse an age such as `90s`, `30m`, `12h`, `7d`, or `2w` into seconds
+pub fn parse_age(s: &str) -> Result<u64, String> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        Some('w') => 7 * 86400,
+        _ => {
+            return Err(format!(
+                "invalid age '{}': expected a number and a unit (s, m, h, d, or w), e.g. 7d",
+                s
+            ));
+        }
+    };
+    s[..s.len() - 1]
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("invalid age '{}': expected e.g. 12h or 7d", s))
+}
+
+/// Parse permission bits written in octal, with or without a leading `0`/`0o`
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if !digits.is_empty() && mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "invalid mode '{}': expected octal permission bits like \"0775\"",
+            s
+        )),
+    }
+}
+
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse_mode(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Serialize a mode back as a 4-digit octal string
+fn serialize_mode<S>(mode: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    mode.map(|mode| format!("{:04o}", mode))
+        .serialize(serializer)
+}
+
+fn default_backend() -> String {
+    "podman".to_string()
+}
+
+fn default_context_path() -> String {
+    "/tmp/context".to_string()
+}
+
+fn default_max_mounts() -> usize {
+    500
+}
+
+fn default_large_mount_warn_entries() -> usize {
+    1000
+}
+
+fn default_dns() -> Vec<String> {
+    vec!["1.1.1.1".to_string(), "8.8.8.8".to_string()]
+}
+
+/// What `ab spawn --session` does when the session workspace doesn't exist
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnMissingWorkspace {
+    /// Create it, as with `--new`
+    Create,
+    /// Fail, suggesting `--new`
+    #[default]
+    Error,
+    /// Ask whether to create it (fails when not interactive)
+    Prompt,
+}
+
+/// Which mount wins when the same host:container path is mounted with different modes
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MountConflict {
+    /// Keep the mount that was merged first
+    #[default]
+    First,
+    /// Keep the mount that was merged last
+    Last,
+    /// Keep the strictest mode: `ro`, then `rw`, then overlay
+    Strictest,
+    /// Fail, listing every conflicting mount
+    Error,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, JsonSchema)]
+pub struct RuntimeConfig {
+    #[serde(default = "default_backend")]
+    pub backend: String,
+    #[serde(default)]
+    pub image: String,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_entrypoint",
+        serialize_with = "serialize_entrypoint"
+    )]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    pub mounts: MountsConfig,
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Environment variable names to pass through from host to container
+    #[serde(default)]
+    pub env_passthrough: Vec<String>,
+    /// Port mappings to expose (Docker `-p` syntax: `[HOST_IP:]HOST_PORT:CONTAINER_PORT`)
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// Custom host-to-IP mappings added to `/etc/hosts` inside the container (`HOST:IP`)
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// DNS servers to use inside the container (passed as `--dns` to the runtime).
+    /// When set, the runtime generates `/etc/resolv.conf` from these servers
+    /// instead of copying the host's configuration.
+    #[serde(default = "default_dns")]
+    pub dns: Vec<String>,
+    #[serde(default)]
+    pub skip_mounts: Vec<String>,
+    /// Check that `image` is available locally before spawning, pulling it if missing
+    #[serde(default)]
+    pub verify_image: bool,
+    /// Extra env var name patterns (globs) whose values are masked in printed output.
+    /// Always includes the built-in `DEFAULT_REDACT_ENV` patterns.
+    #[serde(default)]
+    pub redact_env: Vec<String>,
+    /// Extra attempts when the runtime fails to start the container with a transient
+    /// error (e.g. daemon not running). Container exit codes are never retried.
+    #[serde(default)]
+    pub spawn_retries: u32,
+    /// In-image working directory used by `ab spawn --no-workdir-mount`
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// Supplementary groups (GIDs or names) for the container user (`--group-add`)
+    #[serde(default)]
+    pub group_add: Vec<String>,
+    /// Policy when profiles mount the same path with different modes
+    #[serde(default)]
+    pub mount_conflict: MountConflict,
+    /// What `ab spawn --session` does when the workspace doesn't exist (`--new` forces `create`)
+    #[serde(default)]
+    pub on_missing_workspace: OnMissingWorkspace,
+    /// Default container command, as a shell-style string or an array.
+    /// Used when `ab spawn` gets no `--command`; profiles may override it.
+    #[serde(default, deserialize_with = "deserialize_command")]
+    #[schemars(with = "Option<CommandSpec>")]
+    pub command: Option<Vec<String>>,
+    /// Container network mode (e.g. "host", "bridge", "none").
+    /// Used when `ab spawn` gets no `--network`; profiles may override it.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// CPU, memory, process, and ulimit limits for the container; profiles may override them
+    #[serde(default)]
+    pub resources: ResourcesConfig,
+    /// Env vars whose values come from a credential helper's stdout at spawn.
+    /// Their values are always masked in printed output.
+    #[serde(default)]
+    pub secret_env: BTreeMap<String, SecretHelper>,
+    /// Host shell script run in the container before the entrypoint and command
+    /// (overridden by `ab spawn --init-script`)
+    #[serde(default)]
+    pub init_script: Option<PathBuf>,
+    /// Discover repos under `base_repo_dir` with fewer `stat` calls (for NFS and
+    /// other network filesystems)
+    #[serde(default)]
+    pub fast_discovery: bool,
+    /// Relative paths that must all exist for a directory without `.git` or `.jj`
+    /// to be discovered as a (bare) repo, e.g. `["HEAD", "refs", "objects"]`.
+    /// Empty (the default) only recognizes `.git` and `.jj`.
+    #[serde(default)]
+    pub bare_repo_markers: Vec<String>,
+    /// Fail `ab spawn` when the resolved mounts exceed this many (0 disables the
+    /// limit); guards against runaway symlink-chain expansion
+    #[serde(default = "default_max_mounts")]
+    pub max_mounts: usize,
+    /// Ask before mounting `/`, the host home, or a directory with at least this
+    /// many top-level entries read-write or as an overlay (0 disables the check)
+    #[serde(default = "default_large_mount_warn_entries")]
+    pub large_mount_warn_entries: usize,
+    /// Pass binds as `-v` (`volume`, the default) or `--mount type=bind,...` (`mount`)
+    #[serde(default)]
+    pub mount_syntax: MountSyntax,
+    /// Permission bits applied with `chmod` to newly created workspace directories,
+    /// as an octal string like `"0775"`; unset leaves them to the process umask
+    #[serde(
+        default,
+        deserialize_with = "deserialize_mode",
+        serialize_with = "serialize_mode",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schemars(with = "Option<String>")]
+    pub workspace_mode: Option<u32>,
+    /// Container path prefixes to rewrite on resolved mounts, first match wins
+    #[serde(default)]
+    pub mount_remap: Vec<MountRemap>,
+    /// Absolute container path prepended to the workspace, source repo, and
+    /// home-relative mount targets and the working directory, e.g. `/sandbox`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_root_prefix: Option<String>,
+    /// Also prefix absolute (non-home) mounts with `container_root_prefix`
+    #[serde(default)]
+    pub container_root_prefix_absolute: bool,
+    /// Cluster settings for `backend = "kubernetes"`
+    #[serde(default)]
+    pub kubernetes: KubernetesConfig,
+    /// GPU passthrough; profiles may override which GPUs
+    #[serde(default)]
+    pub gpu: GpuConfig,
+    /// Capability, seccomp, and root filesystem hardening; hardened by default
+    #[serde(default)]
+    pub security: SecurityConfig,
+}
+
+/// Moves resolved mounts whose container path is under `from` to the same
+/// relative path under `to`, e.g. `~/.config` to `/etc/xdg`.
+/// A leading `~` in either means the container home.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct MountRemap {
+    pub from: String,
+    pub to: String,
+}
+
+/// `[runtime.security]`: how locked down the container is. The defaults drop
+/// every capability and block privilege escalation, which agents running as
+/// the host user don't need.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(default)]
+pub struct SecurityConfig {
+    /// Capabilities to drop (`--cap-drop`), e.g. `ALL` or `NET_RAW` (default `["ALL"]`)
+    pub cap_drop: Vec<String>,
+    /// Capabilities to add back after dropping (`--cap-add`)
+    pub cap_add: Vec<String>,
+    /// Absolute host path of a seccomp profile, or `unconfined`; unset keeps the
+    /// runtime's default profile
+    pub seccomp_profile: Option<String>,
+    /// Keep processes from gaining privileges through setuid binaries
+    /// (`--security-opt no-new-privileges`, default `true`)
+    pub no_new_privileges: bool,
+    /// Mount the image's root filesystem read-only (`--read-only`); mounts and
+    /// tmpfs paths stay writable
+    pub read_only_rootfs: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            cap_drop: vec!["ALL".to_string()],
+            cap_add: Vec::new(),
+            seccomp_profile: None,
+            no_new_privileges: true,
+            read_only_rootfs: false,
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// What's wrong with these settings, for `validate_config`
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = [("cap_drop", &self.cap_drop), ("cap_add", &self.cap_add)]
+            .into_iter()
+            .flat_map(|(key, caps)| caps.iter().map(move |cap| (key, cap)))
+            .filter(|(_, cap)| {
+                cap.is_empty()
+                    || !cap
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+            })
+            .map(|(key, cap)| {
+                format!(
+                    "security.{} entry '{}' must be a capability name, e.g. NET_RAW or ALL",
+                    key, cap
+                )
+            })
+            .collect();
+        if let Some(profile) = &self.seccomp_profile
+            && profile != "unconfined"
+            && !profile.starts_with('/')
+        {
+            problems.push(format!(
+                "security.seccomp_profile '{}' must be an absolute path or \"unconfined\"",
+                profile
+            ));
+        }
+        problems
+    }
+}
+
+/// How the kubernetes backend gives a pod the host paths of bind mounts
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KubernetesMountSource {
+    /// A `hostPath` volume per bind; needs a single-node cluster on this host
+    #[default]
+    HostPath,
+    /// `subPath`s of one PersistentVolumeClaim that holds `claim_root`
+    Pvc,
+}
+
+/// `[runtime.kubernetes]`: where the kubernetes backend runs pods
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq, Eq, JsonSchema)]
+pub struct KubernetesConfig {
+    /// kubeconfig context (`kubectl --context`); defaults to the current context
+    #[serde(default)]
+    pub context: Option<String>,
+    /// Namespace for pods and volume claims; defaults to the context's namespace
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// How bind mounts reach the pod
+    #[serde(default)]
+    pub mounts: KubernetesMountSource,
+    /// Claim that backs bind mounts when `mounts = "pvc"`
+    #[serde(default)]
+    pub claim: Option<String>,
+    /// Host directory the claim's root holds, e.g. the parent of `workspace_dir`
+    /// and `base_repo_dir`; binds must be under it
+    #[serde(default)]
+    pub claim_root: Option<PathBuf>,
+    /// Storage class for claims that named volumes create
+    #[serde(default)]
+    pub storage_class: Option<String>,
+    /// Size requested for claims that named volumes create (default `10Gi`)
+    #[serde(default)]
+    pub volume_size: Option<String>,
+    /// Seconds to wait for a pod to become ready (default 300)
+    #[serde(default)]
+    pub ready_timeout: Option<u64>,
+}
+
+impl KubernetesConfig {
+    /// What's wrong with these settings, for `validate_config`
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.mounts == KubernetesMountSource::Pvc {
+            if self.claim.is_none() {
+                problems.push("kubernetes.claim is required when mounts = \"pvc\"".to_string());
+            }
+            match &self.claim_root {
+                None => problems
+                    .push("kubernetes.claim_root is required when mounts = \"pvc\"".to_string()),
+                Some(root) if !root.is_absolute() => problems.push(format!(
+                    "kubernetes.claim_root '{}' must be absolute",
+                    root.display()
+                )),
+                Some(_) => {}
+            }
+        }
+        problems
+    }
+}
+
+/// Command whose stdout (minus trailing newlines) is a secret env var's value,
+/// e.g. `{ helper = "pass", args = ["show", "github/token"] }`
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct SecretHelper {
+    pub helper: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Env var name patterns whose values are always masked in printed output
+pub const DEFAULT_REDACT_ENV: &[&str] = &["*_TOKEN", "*_KEY", "*_SECRET", "*PASSWORD*"];
+
+/// Placeholder printed instead of a redacted value
+pub const REDACTED: &str = "****";
+
+/// Returns true if the value of env var `name` should be masked in printed output.
+/// Matching is case-insensitive against `DEFAULT_REDACT_ENV` and `extra_patterns`.
+pub fn should_redact_env(name: &str, extra_patterns: &[String]) -> bool {
+    let options = glob::MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+
+    DEFAULT_REDACT_ENV
+        .iter()
+        .copied()
+        .chain(extra_patterns.iter().map(String::as_str))
+        .any(|pattern| {
+            glob::Pattern::new(pattern)
+                .map(|p| p.matches_with(name, options))
+                .unwrap_or(false)
+        })
+}
+
+/// Mask the value of a `KEY=VALUE` env entry if `KEY` should be redacted
+pub fn redact_env_entry(entry: &str, extra_patterns: &[String]) -> String {
+    match entry.split_once('=') {
+        Some((name, _)) if should_redact_env(name, extra_patterns) => {
+            format!("{}={}", name, REDACTED)
+        }
+        _ => entry.to_string(),
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, JsonSchema)]
+pub struct Config {
+    pub workspace_dir: PathBuf,
+    pub base_repo_dir: PathBuf,
+    /// Default profile name to always apply (if set)
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    /// Named profiles that can be selected via CLI
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+    /// Root-level context
+    #[serde(default)]
+    pub context: String,
+    /// Path where context file will be mounted inside the container
+    #[serde(default = "default_context_path")]
+    pub context_path: String,
+    /// Spawn aliases: name -> argv tokens, expanded by `ab run <alias>`
+    #[serde(default)]
+    pub aliases: HashMap<String, Vec<String>>,
+    /// Which workspaces `ab gc` removes
+    #[serde(default)]
+    pub gc: GcConfig,
+    /// Host portal service configuration
+    #[serde(default)]
+    pub portal: PortalConfig,
+}
+
+/// `[gc]`: which workspaces `ab gc` removes
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, JsonSchema)]
+pub struct GcConfig {
+    /// Only workspaces not modified for at least this long, e.g. "12h", "7d", "2w".
+    /// `ab gc --older-than` overrides it.
+    #[serde(default = "default_gc_older_than")]
+    pub older_than: String,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            older_than: default_gc_older_than(),
+        }
+    }
+}
+
+fn default_gc_older_than() -> String {
+    "7d".to_string()
+}
+
+/// Resolved mounts, env, ports, and hosts from profile resolution
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ResolvedProfile {
+    pub mounts: Vec<Mount>,
+    pub env: Vec<String>,
+    pub env_passthrough: Vec<String>,
+    pub ports: Vec<String>,
+    pub hosts: Vec<String>,
+    pub context: Vec<String>,
+    /// Default container command; the last layer that sets one wins
+    pub command: Option<Vec<String>>,
+    /// Container network mode; the last layer that sets one wins
+    pub network: Option<String>,
+    /// Hosts reachable through the egress proxy, from every layer; empty means no proxy
+    pub allow_hosts: Vec<String>,
+    /// tmpfs mount paths (`~` is the container home), from every layer
+    pub tmpfs: Vec<String>,
+    /// Named volume specs (`NAME:PATH[:MODE]`), from every layer
+    pub volumes: Vec<String>,
+    /// Resource limits; the last layer that sets each one wins
+    pub resources: ResourcesConfig,
+    /// Image build; the last layer that sets one wins
+    pub build: Option<BuildConfig>,
+    /// Secrets by name; the last layer that sets each one wins
+    pub secrets: BTreeMap<String, SecretConfig>,
+    /// GPU selection; the last layer that sets one wins
+    pub gpu: Option<GpuSetting>,
+}
+
+impl ResolvedProfile {
+    /// Merge another resolved profile into this one
+    pub fn merge(&mut self, other: &ResolvedProfile) {
+        self.mounts.extend(other.mounts.iter().cloned());
+        self.env.extend(other.env.iter().cloned());
+        self.env_passthrough
+            .extend(other.env_passthrough.iter().cloned());
+        self.ports.extend(other.ports.iter().cloned());
+        self.hosts.extend(other.hosts.iter().cloned());
+        self.context.extend(other.context.iter().cloned());
+        if other.command.is_some() {
+            self.command = other.command.clone();
+        }
+        if other.network.is_some() {
+            self.network = other.network.clone();
+        }
+        self.allow_hosts.extend(other.allow_hosts.iter().cloned());
+        self.tmpfs.extend(other.tmpfs.iter().cloned());
+        self.volumes.extend(other.volumes.iter().cloned());
+        self.resources.merge(&other.resources);
+        if other.build.is_some() {
+            self.build = other.build.clone();
+        }
+        self.secrets.extend(
+            other
+                .secrets
+                .iter()
+                .map(|(name, secret)| (name.clone(), secret.clone())),
+        );
+        if other.gpu.is_some() {
+            self.gpu = other.gpu.clone();
+        }
+    }
+
+    /// Deduplicate mounts by resolved path (first occurrence wins).
+    /// Uses canonicalized paths when possible to handle symlinks.
+    pub fn dedup_mounts(&mut self) {
+        self.dedup_mounts_with(&mut MountResolutionCache::default());
+    }
+
+    /// [`Self::dedup_mounts`], reusing resolutions from `cache`
+    pub fn dedup_mounts_with(&mut self, cache: &mut MountResolutionCache) {
+        self.dedup_mounts_with_policy(MountConflict::First, cache)
+            .expect("the first-wins policy never fails");
+    }
+
+    /// Deduplicate mounts by resolved host:container path.
+    ///
+    /// Repeats with the same mode are always dropped. When the same path is
+    /// mounted with different modes, `policy` picks the one that stays (in the
+    /// position of the first occurrence), or fails listing every conflict.
+    pub fn dedup_mounts_with_policy(
+        &mut self,
+        policy: MountConflict,
+        cache: &mut MountResolutionCache,
+    ) -> Result<()> {
+        // Get home dir for resolution
+        let env_home = std::env::var("HOME").ok();
+        let host_home = env_home.clone().unwrap_or_else(|| "/home/user".to_string());
+        let container_user = std::env::var("USER")
+            .or_else(|_| std::env::var("LOGNAME"))
+            .unwrap_or_else(|_| "user".to_string());
+        let container_home = format!("/home/{}", container_user);
+
+        let mut kept: Vec<Mount> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut conflicts = Vec::new();
+
+        for m in self.mounts.drain(..) {
+            // Try to resolve to canonical host:container paths
+            // Fall back to non-canonical comparison if path doesn't exist
+            let key = env_home
+                .as_deref()
+                .ok_or_else(|| eyre::eyre!("HOME not set"))
+                .and_then(|home| cache.resolve_pair(&m, home, &container_home))
+                .or_else(|_| m.resolve_paths(&host_home, &container_home))
+                .map(|(h, c)| format!("{}:{}", h, c))
+                .unwrap_or_else(|_| format!("{}:{}", m.spec, m.home_relative));
+
+            let Some(&i) = index.get(&key) else {
+                index.insert(key, kept.len());
+                kept.push(m);
+                continue;
+            };
+            if kept[i].mode == m.mode {
+                continue;
+            }
+            match policy {
+                MountConflict::First => {}
+                MountConflict::Last => kept[i] = m,
+                MountConflict::Strictest => {
+                    if m.mode.strictness() < kept[i].mode.strictness() {
+                        kept[i] = m;
+                    }
+                }
+                MountConflict::Error => conflicts.push(format!("{}  vs  {}", kept[i], m)),
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Err(eyre::eyre!(
+                "Conflicting mount modes (runtime.mount_conflict = \"error\"):\n  {}",
+                conflicts.join("\n  ")
+            ));
+        }
+
+        self.mounts = kept;
+        Ok(())
+    }
+
+    /// Deduplicate ports by exact string match (first occurrence wins).
+    pub fn dedup_ports(&mut self) {
+        let mut seen = HashSet::new();
+        self.ports.retain(|p| seen.insert(p.clone()));
+    }
+
+    /// Deduplicate hosts by exact string match (first occurrence wins).
+    pub fn dedup_hosts(&mut self) {
+        let mut seen = HashSet::new();
+        self.hosts.retain(|h| seen.insert(h.clone()));
+    }
+
+    /// Remove duplicate allowed hosts (case-insensitive), keeping the first occurrence
+    pub fn dedup_allow_hosts(&mut self) {
+        let mut seen = HashSet::new();
+        self.allow_hosts
+            .retain(|h| seen.insert(h.to_ascii_lowercase()));
+    }
+
+    /// Deduplicate tmpfs paths by exact string match (first occurrence wins).
+    pub fn dedup_tmpfs(&mut self) {
+        let mut seen = HashSet::new();
+        self.tmpfs.retain(|p| seen.insert(p.clone()));
+    }
+
+    /// Deduplicate volume specs by exact string match (first occurrence wins).
+    pub fn dedup_volumes(&mut self) {
+        let mut seen = HashSet::new();
+        self.volumes.retain(|v| seen.insert(v.clone()));
+    }
+
+    /// Get mount specs filtered by mode and home_relative flag (for testing)
     #[cfg(test)]
     fn get_mount_specs(&self, mode: MountMode, home_relative: bool) -> Vec<&str> {
         self.mounts
@@ -682,6 +1994,29 @@ impl ResolvedProfile {
     }
 }
 
+/// Split `--profile` arguments on commas, preserving order.
+///
+/// Repeated flags and comma lists can be mixed: `-p git,rust -p debug`
+/// yields `["git", "rust", "debug"]`. Empty segments are rejected.
+pub fn split_profile_args(args: &[String]) -> Result<Vec<String>> {
+    let mut profiles = Vec::new();
+
+    for arg in args {
+        for segment in arg.split(',') {
+            let name = segment.trim();
+            if name.is_empty() {
+                return Err(eyre::eyre!(
+                    "Empty profile name in '--profile {}'. Use comma-separated names like 'git,rust'",
+                    arg
+                ));
+            }
+            profiles.push(name.to_string());
+        }
+    }
+
+    Ok(profiles)
+}
+
 /// Resolve profiles with inheritance, returning merged mounts and env.
 ///
 /// Resolution order:
@@ -709,7 +2044,30 @@ pub fn collect_profiles_to_apply<'a>(
     profiles_to_apply
 }
 
+/// The image build the given profiles (with `default_profile`) resolve to, without
+/// resolving their mounts. Lets `ab spawn` pick the image before anything else.
+pub fn resolve_build(config: &Config, profile_names: &[String]) -> Result<Option<BuildConfig>> {
+    let mut build = None;
+    for profile_name in collect_profiles_to_apply(config, profile_names) {
+        let resolved = resolve_single_profile(config, profile_name, &mut HashSet::new())?;
+        if resolved.build.is_some() {
+            build = resolved.build;
+        }
+    }
+    Ok(build)
+}
+
 pub fn resolve_profiles(config: &Config, profile_names: &[String]) -> Result<ResolvedProfile> {
+    resolve_profiles_with_cache(config, profile_names, &mut MountResolutionCache::default())
+}
+
+/// [`resolve_profiles`], recording mount resolutions in `cache` so later
+/// bind construction in the same spawn can reuse them
+pub fn resolve_profiles_with_cache(
+    config: &Config,
+    profile_names: &[String],
+    cache: &mut MountResolutionCache,
+) -> Result<ResolvedProfile> {
     let mut resolved = ResolvedProfile {
         mounts: config.runtime.mounts.to_mounts(),
         env: config.runtime.env.clone(),
@@ -721,6 +2079,15 @@ pub fn resolve_profiles(config: &Config, profile_names: &[String]) -> Result<Res
         } else {
             vec![config.context.clone()]
         },
+        command: config.runtime.command.clone(),
+        network: config.runtime.network.clone(),
+        allow_hosts: Vec::new(),
+        tmpfs: config.runtime.mounts.tmpfs.paths.clone(),
+        volumes: config.runtime.mounts.volumes.clone(),
+        resources: config.runtime.resources.clone(),
+        build: None,
+        secrets: BTreeMap::new(),
+        gpu: config.runtime.gpu.devices.clone(),
     };
 
     let profiles_to_apply = collect_profiles_to_apply(config, profile_names);
@@ -732,13 +2099,76 @@ pub fn resolve_profiles(config: &Config, profile_names: &[String]) -> Result<Res
     }
 
     // Deduplicate mounts, ports, and hosts (exact spec match)
-    resolved.dedup_mounts();
+    resolved.dedup_mounts_with_policy(config.runtime.mount_conflict, cache)?;
     resolved.dedup_ports();
     resolved.dedup_hosts();
+    resolved.dedup_allow_hosts();
+    resolved.dedup_tmpfs();
+    resolved.dedup_volumes();
 
     Ok(resolved)
 }
 
+/// Every mount [`resolve_profiles`] would collect, before deduplication,
+/// paired with where it came from (`"runtime"` or the profile name).
+///
+/// Mounts are listed in the same order `resolve_profiles` merges them, so the
+/// first entry for a path is the one dedup keeps.
+pub fn resolve_mount_sources(
+    config: &Config,
+    profile_names: &[String],
+) -> Result<Vec<(Mount, String)>> {
+    let mut sources: Vec<(Mount, String)> = config
+        .runtime
+        .mounts
+        .to_mounts()
+        .into_iter()
+        .map(|m| (m, "runtime".to_string()))
+        .collect();
+
+    for profile_name in collect_profiles_to_apply(config, profile_names) {
+        collect_mount_sources(config, profile_name, &mut HashSet::new(), &mut sources)?;
+    }
+
+    Ok(sources)
+}
+
+/// Append the mounts of `profile_name` and its extends chain to `out`,
+/// mirroring the order of [`resolve_single_profile`]
+fn collect_mount_sources(
+    config: &Config,
+    profile_name: &str,
+    visited: &mut HashSet<String>,
+    out: &mut Vec<(Mount, String)>,
+) -> Result<()> {
+    if !visited.insert(profile_name.to_string()) {
+        return Err(eyre::eyre!(
+            "Circular profile dependency detected: '{}' was already visited in chain: {:?}",
+            profile_name,
+            visited
+        ));
+    }
+
+    let profile = config
+        .profiles
+        .get(profile_name)
+        .ok_or_else(|| eyre::eyre!("Unknown profile '{}'", profile_name))?;
+
+    for parent_name in &profile.extends {
+        collect_mount_sources(config, parent_name, visited, out)?;
+    }
+    out.extend(
+        profile
+            .mounts
+            .to_mounts()
+            .into_iter()
+            .map(|m| (m, profile_name.to_string())),
+    );
+
+    visited.remove(profile_name);
+    Ok(())
+}
+
 /// Resolve a single profile with its extends chain.
 /// Uses `visited` to detect cycles.
 fn resolve_single_profile(
@@ -775,8 +2205,15 @@ fn resolve_single_profile(
         resolved.merge(&parent_resolved);
     }
 
-    // Then apply this profile's own mounts, env, ports, hosts, and context
+    // Then apply this profile's own mounts, env, ports, hosts, context, command, network,
+    // resources, build, secrets, and gpu
     resolved.mounts.extend(profile.mounts.to_mounts());
+    resolved
+        .tmpfs
+        .extend(profile.mounts.tmpfs.paths.iter().cloned());
+    resolved
+        .volumes
+        .extend(profile.mounts.volumes.iter().cloned());
     resolved.env.extend(profile.env.iter().cloned());
     resolved
         .env_passthrough
@@ -786,6 +2223,30 @@ fn resolve_single_profile(
     if !profile.context.is_empty() {
         resolved.context.push(profile.context.clone());
     }
+    if profile.command.is_some() {
+        resolved.command = profile.command.clone();
+    }
+    if let Some(network) = &profile.network {
+        if network.mode.is_some() {
+            resolved.network = network.mode.clone();
+        }
+        resolved
+            .allow_hosts
+            .extend(network.allow_hosts.iter().cloned());
+    }
+    resolved.resources.merge(&profile.resources);
+    if profile.build.is_some() {
+        resolved.build = profile.build.clone();
+    }
+    resolved.secrets.extend(
+        profile
+            .secrets
+            .iter()
+            .map(|(name, secret)| (name.clone(), secret.clone())),
+    );
+    if profile.gpu.is_some() {
+        resolved.gpu = profile.gpu.clone();
+    }
 
     // Remove from visited after processing (allow same profile in different branches)
     visited.remove(profile_name);
@@ -793,11 +2254,407 @@ fn resolve_single_profile(
     Ok(resolved)
 }
 
-/// Build a Figment from global and optional repo-local config paths.
-/// Uses admerge: arrays concatenate, scalars override, dicts union recursively.
-fn build_figment(global_config_path: &PathBuf, repo_config_path: Option<&PathBuf>) -> Figment {
-    let mut figment = Figment::from(Toml::file(global_config_path));
+/// Expand an alias into `spawn` arguments, appending `extra_args`.
+///
+/// If the first token of an alias names another alias, it is expanded
+/// recursively, so `dev = ["base", "-p", "rust"]` builds on the `base` alias.
+/// Cycles are reported as errors.
+pub fn expand_alias(
+    aliases: &HashMap<String, Vec<String>>,
+    name: &str,
+    extra_args: &[String],
+) -> Result<Vec<String>> {
+    let mut chain: Vec<&str> = Vec::new();
+    let mut current = name;
+    let mut suffix: Vec<String> = extra_args.to_vec();
+
+    loop {
+        if chain.contains(&current) {
+            chain.push(current);
+            return Err(eyre::eyre!(
+                "Recursive alias detected: {}",
+                chain.join(" -> ")
+            ));
+        }
+        chain.push(current);
+
+        let tokens = aliases.get(current).ok_or_else(|| {
+            let mut available: Vec<_> = aliases.keys().collect();
+            available.sort();
+            eyre::eyre!(
+                "Unknown alias '{}'. Available aliases: {:?}",
+                current,
+                available
+            )
+        })?;
+
+        match tokens.split_first() {
+            Some((first, rest)) if aliases.contains_key(first) => {
+                suffix.splice(0..0, rest.iter().cloned());
+                current = first;
+            }
+            _ => {
+                let mut args = tokens.clone();
+                args.extend(suffix);
+                return Ok(args);
+            }
+        }
+    }
+}
+
+/// Settings imported from a `devcontainer.json` (`ab spawn --devcontainer`)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Devcontainer {
+    /// `image`
+    pub image: Option<String>,
+    /// Bind `mounts`, `containerEnv`/`remoteEnv`, and `forwardPorts`/`appPort`
+    pub profile: ResolvedProfile,
+    /// `postCreateCommand` as one shell command line
+    pub post_create_command: Option<String>,
+    /// Settings that were present but not imported
+    pub warnings: Vec<String>,
+}
+
+/// Paths `${...}` variables in a `devcontainer.json` expand to
+pub struct DevcontainerPaths<'a> {
+    /// `${localWorkspaceFolder}`: the workspace on the host
+    pub local_workspace: &'a Path,
+    /// `${containerWorkspaceFolder}`: where the workspace is mounted in the container
+    pub container_workspace: &'a Path,
+}
+
+/// devcontainer.json keys `ab` has no equivalent for
+const UNSUPPORTED_DEVCONTAINER_KEYS: &[&str] = &[
+    "build",
+    "dockerFile",
+    "dockerComposeFile",
+    "features",
+    "runArgs",
+    "workspaceMount",
+    "workspaceFolder",
+    "containerUser",
+    "remoteUser",
+    "initializeCommand",
+    "onCreateCommand",
+    "updateContentCommand",
+    "postStartCommand",
+    "postAttachCommand",
+];
+
+/// The devcontainer.json of a workspace: `.devcontainer/devcontainer.json`,
+/// then `.devcontainer.json`
+pub fn find_devcontainer(workspace: &Path) -> Option<PathBuf> {
+    [
+        workspace.join(".devcontainer").join("devcontainer.json"),
+        workspace.join(".devcontainer.json"),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+}
+
+/// Read and parse the devcontainer.json at `path`, expanding `${localEnv:...}`
+/// from the host environment
+pub fn load_devcontainer(path: &Path, paths: &DevcontainerPaths) -> Result<Devcontainer> {
+    let text = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    parse_devcontainer(&text, paths, |name| std::env::var(name).ok())
+        .wrap_err_with(|| format!("Invalid devcontainer file {}", path.display()))
+}
+
+/// Parse devcontainer.json `text` (JSON with comments), reading host
+/// variables for `${localEnv:NAME}` through `lookup`
+pub fn parse_devcontainer(
+    text: &str,
+    paths: &DevcontainerPaths,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<Devcontainer> {
+    let json: serde_json::Value =
+        serde_json::from_str(&strip_jsonc(text)).map_err(|e| eyre::eyre!("Invalid JSON: {}", e))?;
+    let obj = json
+        .as_object()
+        .ok_or_else(|| eyre::eyre!("Expected a JSON object"))?;
+    let expand = |s: &str| expand_devcontainer_vars(s, paths, &lookup);
+
+    let mut devcontainer = Devcontainer::default();
+    for key in UNSUPPORTED_DEVCONTAINER_KEYS {
+        if obj.contains_key(*key) {
+            devcontainer.warnings.push(format!(
+                "devcontainer '{}' is not supported, ignoring it",
+                key
+            ));
+        }
+    }
+
+    if let Some(image) = obj.get("image") {
+        let image = image
+            .as_str()
+            .ok_or_else(|| eyre::eyre!("'image' must be a string"))?;
+        devcontainer.image = Some(expand(image)?);
+    }
+
+    for mount in obj
+        .get("mounts")
+        .and_then(|m| m.as_array())
+        .into_iter()
+        .flatten()
+    {
+        let fields: Vec<(String, String)> = match mount {
+            serde_json::Value::String(spec) => spec
+                .split(',')
+                .map(|field| match field.split_once('=') {
+                    Some((k, v)) => (k.trim().to_string(), v.trim().to_string()),
+                    None => (field.trim().to_string(), "true".to_string()),
+                })
+                .collect(),
+            serde_json::Value::Object(fields) => fields
+                .iter()
+                .map(|(k, v)| {
+                    let v = v
+                        .as_str()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| v.to_string());
+                    (k.clone(), v)
+                })
+                .collect(),
+            _ => return Err(eyre::eyre!("Invalid mount {}", mount)),
+        };
+        let field = |names: &[&str]| {
+            fields
+                .iter()
+                .find(|(k, _)| names.contains(&k.as_str()))
+                .map(|(_, v)| v.as_str())
+        };
+
+        // The runtime's default mount type is a volume
+        let kind = field(&["type"]).unwrap_or("volume");
+        if kind != "bind" {
+            devcontainer.warnings.push(format!(
+                "devcontainer {} mount {} is not supported, ignoring it",
+                kind, mount
+            ));
+            continue;
+        }
+        let (Some(source), Some(target)) = (
+            field(&["source", "src"]),
+            field(&["target", "destination", "dst"]),
+        ) else {
+            return Err(eyre::eyre!(
+                "Bind mount {} needs a source and a target",
+                mount
+            ));
+        };
+        let readonly = field(&["readonly", "ro"]).is_some_and(|v| v != "false");
+
+        devcontainer.profile.mounts.push(Mount {
+            spec: format!("{}:{}", expand(source)?, expand(target)?),
+            home_relative: false,
+            mode: if readonly {
+                MountMode::Ro
+            } else {
+                MountMode::Rw
+            },
+            propagation: None,
+            recursive: true,
+        });
+    }
+
+    // remoteEnv comes last so it wins, as it would for the remote process
+    for key in ["containerEnv", "remoteEnv"] {
+        for (name, value) in obj
+            .get(key)
+            .and_then(|e| e.as_object())
+            .into_iter()
+            .flatten()
+        {
+            match value {
+                // A null remoteEnv value unsets the variable
+                serde_json::Value::Null => {}
+                serde_json::Value::String(value) => {
+                    devcontainer
+                        .profile
+                        .env
+                        .push(format!("{}={}", name, expand(value)?));
+                }
+                _ => return Err(eyre::eyre!("{}.{} must be a string", key, name)),
+            }
+        }
+    }
+
+    let mut ports = Vec::new();
+    for key in ["forwardPorts", "appPort"] {
+        match obj.get(key) {
+            Some(serde_json::Value::Array(values)) => ports.extend(values.iter().map(|v| (key, v))),
+            Some(value) => ports.push((key, value)),
+            None => {}
+        }
+    }
+    for (key, port) in ports {
+        match port {
+            serde_json::Value::Number(n) => devcontainer.profile.ports.push(format!("{}:{}", n, n)),
+            serde_json::Value::String(s) if s.chars().all(|c| c.is_ascii_digit()) => {
+                devcontainer.profile.ports.push(format!("{}:{}", s, s))
+            }
+            // appPort strings are already in `-p` form
+            serde_json::Value::String(s) if key == "appPort" => {
+                devcontainer.profile.ports.push(s.clone())
+            }
+            _ => devcontainer.warnings.push(format!(
+                "devcontainer {} entry {} is not supported, ignoring it",
+                key, port
+            )),
+        }
+    }
+
+    if let Some(command) = obj.get("postCreateCommand") {
+        devcontainer.post_create_command = Some(expand(&lifecycle_command(command)?)?);
+    }
+
+    Ok(devcontainer)
+}
+
+/// A devcontainer lifecycle command as a shell command line. The object form's
+/// named commands run one after another, in name order.
+fn lifecycle_command(command: &serde_json::Value) -> Result<String> {
+    match command {
+        serde_json::Value::String(line) => Ok(line.clone()),
+        serde_json::Value::Array(args) => {
+            let args = args
+                .iter()
+                .map(|arg| {
+                    arg.as_str()
+                        .ok_or_else(|| eyre::eyre!("Command arguments must be strings"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(shell_words::join(args))
+        }
+        serde_json::Value::Object(commands) => Ok(commands
+            .values()
+            .map(lifecycle_command)
+            .collect::<Result<Vec<_>>>()?
+            .join(" && ")),
+        _ => Err(eyre::eyre!("Invalid lifecycle command {}", command)),
+    }
+}
+
+/// Expand the `${...}` variables devcontainer.json values may contain
+fn expand_devcontainer_vars(
+    value: &str,
+    paths: &DevcontainerPaths,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+    let basename = |path: &Path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| eyre::eyre!("Unterminated variable in '{}'", value))?;
+        let var = &rest[start + 2..start + end];
+        let expanded = match var {
+            "localWorkspaceFolder" => paths.local_workspace.display().to_string(),
+            "localWorkspaceFolderBasename" => basename(paths.local_workspace),
+            "containerWorkspaceFolder" => paths.container_workspace.display().to_string(),
+            "containerWorkspaceFolderBasename" => basename(paths.container_workspace),
+            _ => match var
+                .strip_prefix("localEnv:")
+                .or_else(|| var.strip_prefix("env:"))
+            {
+                Some(env) => {
+                    let (name, default) = env.split_once(':').unwrap_or((env, ""));
+                    lookup(name).unwrap_or_else(|| default.to_string())
+                }
+                None => {
+                    return Err(eyre::eyre!(
+                        "Unsupported variable '${{{}}}' in '{}'",
+                        var,
+                        value
+                    ));
+                }
+            },
+        };
+        out.push_str(&expanded);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Strip `//` and `/* */` comments and trailing commas, which devcontainer.json
+/// allows, so the rest parses as plain JSON
+fn strip_jsonc(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => while chars.next_if(|&c| c != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+
+    // Comments are gone, so a comma directly before a closer (modulo whitespace) is trailing
+    let mut cleaned = String::with_capacity(out.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in out.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' && matches!(out[i + 1..].trim_start().chars().next(), Some('}' | ']')) {
+            continue;
+        }
+        cleaned.push(c);
+    }
+    cleaned
+}
 
+/// Build a Figment from the optional global and repo-local config paths.
+/// Uses admerge: arrays concatenate, scalars override, dicts union recursively.
+fn build_figment(
+    global_config_path: Option<&PathBuf>,
+    repo_config_path: Option<&PathBuf>,
+) -> Figment {
+    let mut figment = Figment::new();
+
+    if let Some(global_path) = global_config_path {
+        figment = figment.admerge(Toml::file(global_path));
+    }
     if let Some(repo_path) = repo_config_path {
         figment = figment.admerge(Toml::file(repo_path));
     }
@@ -805,11 +2662,51 @@ fn build_figment(global_config_path: &PathBuf, repo_config_path: Option<&PathBuf
     figment
 }
 
-/// Load configuration with layered merging:
-/// 1. Load ~/.agent-box.toml (global config, required)
-/// 2. Load <git_root>/.agent-box.toml (repo config, optional)
-/// 3. Merge using admerge: arrays are concatenated, scalars are overridden
-pub fn load_config() -> Result<Config> {
+/// Environment variable that skips the global config when set to `1`
+pub const NO_GLOBAL_CONFIG_ENV: &str = "AGENT_BOX_NO_GLOBAL";
+
+static NO_GLOBAL_CONFIG: AtomicBool = AtomicBool::new(false);
+
+/// Load only the repo-local config (or go back to layering it over the global one)
+pub fn set_no_global_config(value: bool) {
+    NO_GLOBAL_CONFIG.store(value, Ordering::Relaxed);
+}
+
+/// Returns true if the global config is disabled via `--no-global-config` or the environment
+pub fn is_no_global_config() -> bool {
+    NO_GLOBAL_CONFIG.load(Ordering::Relaxed)
+        || matches!(
+            std::env::var(NO_GLOBAL_CONFIG_ENV).ok().as_deref(),
+            Some("1") | Some("true")
+        )
+}
+
+/// Starting content for a new global `~/.agent-box.toml`
+pub const CONFIG_TEMPLATE: &str = r#"# agent-box configuration
+workspace_dir = "~/workspaces"
+base_repo_dir = "~/repos"
+
+[runtime]
+backend = "podman"
+image = ""
+# entrypoint = "claude --dangerously-skip-permissions"
+# env = ["FOO=bar"]
+
+# [profiles.example]
+# env = ["EXAMPLE=1"]
+"#;
+
+/// Starting content for a new repo-local `.agent-box.toml`
+pub const REPO_CONFIG_TEMPLATE: &str = r#"# Repo-local agent-box configuration, merged over ~/.agent-box.toml
+# (arrays are concatenated, scalars override the global values)
+
+# [runtime]
+# env = ["FOO=bar"]
+"#;
+
+/// Paths of the global config (`~/.agent-box.toml`) and, when inside a git repo,
+/// the repo-local `.agent-box.toml`, in merge order. Either file may not exist.
+pub fn config_layer_paths() -> Result<(PathBuf, Option<PathBuf>)> {
     let home = std::env::var("HOME").wrap_err("Failed to get HOME environment variable")?;
     let global_config_path = PathBuf::from(&home).join(".agent-box.toml");
 
@@ -818,24 +2715,332 @@ pub fn load_config() -> Result<Config> {
         .ok()
         .map(|root| root.join(".agent-box.toml"));
 
-    let figment = build_figment(&global_config_path, repo_config_path.as_ref());
+    Ok((global_config_path, repo_config_path))
+}
 
-    let mut config: Config = figment.extract().map_err(|e| {
-        // Convert figment::Error to eyre::Report with nice formatting
-        eyre::eyre!("{}", e)
-    })?;
+/// Load configuration with layered merging:
+/// 1. Load ~/.agent-box.toml (global config, required)
+/// 2. Load <git_root>/.agent-box.toml (repo config, optional)
+/// 3. Merge using admerge: arrays are concatenated, scalars are overridden
+///
+/// With `--no-global-config` (or `AGENT_BOX_NO_GLOBAL=1`) only the repo-local
+/// config is loaded; see [`load_repo_config_from`].
+pub fn load_config() -> Result<Config> {
+    let (global_config_path, repo_config_path) = config_layer_paths()?;
+    if is_no_global_config() {
+        let repo_config_path = repo_config_path.ok_or_else(|| {
+            eyre::eyre!("--no-global-config needs to run inside a git repo with an .agent-box.toml")
+        })?;
+        return load_repo_config_from(&repo_config_path);
+    }
+    load_config_from(&global_config_path, repo_config_path.as_ref())
+}
+
+/// Load and merge the given global and (optional) repo-local config files
+pub fn load_config_from(
+    global_config_path: &PathBuf,
+    repo_config_path: Option<&PathBuf>,
+) -> Result<Config> {
+    finish_config(&build_figment(Some(global_config_path), repo_config_path))
+}
+
+/// Load the repo-local config on its own, ignoring `~/.agent-box.toml`.
+///
+/// The file must exist and set the keys the global config normally provides
+/// (`workspace_dir` and `base_repo_dir`).
+pub fn load_repo_config_from(repo_config_path: &PathBuf) -> Result<Config> {
+    if !repo_config_path.exists() {
+        eyre::bail!(
+            "--no-global-config: repo config {} does not exist",
+            repo_config_path.display()
+        );
+    }
+    let figment = build_figment(None, Some(repo_config_path));
+    let missing: Vec<&str> = ["workspace_dir", "base_repo_dir"]
+        .into_iter()
+        .filter(|key| figment.find_value(key).is_err())
+        .collect();
+    if !missing.is_empty() {
+        eyre::bail!(
+            "--no-global-config: {} must set {} since ~/.agent-box.toml is not loaded",
+            repo_config_path.display(),
+            missing.join(" and ")
+        );
+    }
+    finish_config(&figment)
+}
+
+/// Resolve a relative directory `key` against the directory of the config file
+/// that set it (or the home directory if it came from elsewhere), not the CWD,
+/// warning that it is relative. Absolute and `~` paths are returned unchanged.
+fn anchor_config_dir(figment: &Figment, key: &str, path: &Path) -> PathBuf {
+    if path.is_absolute() || path.starts_with("~") {
+        return path.to_path_buf();
+    }
+
+    let base = figment
+        .find_metadata(key)
+        .and_then(|metadata| metadata.source.as_ref()?.file_path()?.parent())
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var_os("HOME").map(PathBuf::from))
+        .unwrap_or_default();
+    let anchored = base.join(path);
+    eprintln!(
+        "WARNING: {} '{}' is relative; resolving it against {} (use an absolute or ~ path)",
+        key,
+        path.display(),
+        base.display()
+    );
+    anchored
+}
+
+/// Resolve a relative `[profiles.NAME.build]` path against the directory of the
+/// config file that sets `key`, so a repo's `.agent-box.toml` can point into the repo
+fn anchor_build_path(figment: &Figment, key: &str, path: &Path) -> PathBuf {
+    if path.is_absolute() || path.starts_with("~") {
+        return path.to_path_buf();
+    }
+    figment
+        .find_metadata(key)
+        .and_then(|metadata| metadata.source.as_ref()?.file_path()?.parent())
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Extract a [`Config`] from `figment` and expand its paths
+fn finish_config(figment: &Figment) -> Result<Config> {
+    let mut config = extract_config(figment)?;
+
+    // Expand all paths, anchoring relative directories to their config file
+    config.workspace_dir = expand_path(&anchor_config_dir(
+        figment,
+        "workspace_dir",
+        &config.workspace_dir,
+    ))
+    .wrap_err("Failed to expand workspace_dir path")?;
+    config.base_repo_dir = expand_path(&anchor_config_dir(
+        figment,
+        "base_repo_dir",
+        &config.base_repo_dir,
+    ))
+    .wrap_err("Failed to expand base_repo_dir path")?;
+    if let Some(script) = &config.runtime.init_script {
+        config.runtime.init_script =
+            Some(expand_path(script).wrap_err("Failed to expand init_script path")?);
+    }
+    for (name, profile) in &mut config.profiles {
+        if let Some(build) = &mut profile.build {
+            let key = format!("profiles.{}.build", name);
+            build.file = expand_path(&anchor_build_path(figment, &key, &build.file))
+                .wrap_err_with(|| format!("Failed to expand {}.file path", key))?;
+            if let Some(context) = &build.context {
+                build.context = Some(
+                    expand_path(&anchor_build_path(figment, &key, context))
+                        .wrap_err_with(|| format!("Failed to expand {}.context path", key))?,
+                );
+            }
+        }
+    }
+
+    interpolate_config(&mut config, |name| std::env::var(name).ok())?;
+
+    Ok(config)
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` in `value` through `lookup`. The default
+/// is used when the variable is unset or empty; an unset variable without one is an error.
+pub fn interpolate_env(
+    value: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> std::result::Result<String, String> {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unterminated '${{' in '{}'", value))?;
+        let var = &rest[start + 2..start + end];
+        let (name, default) = match var.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (var, None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid variable '${{{}}}' in '{}'", var, value));
+        }
+        let expanded = match (lookup(name), default) {
+            (Some(found), Some(default)) if found.is_empty() => default.to_string(),
+            (Some(found), _) => found,
+            (None, Some(default)) => default.to_string(),
+            (None, None) => {
+                return Err(format!(
+                    "${{{}}} is not set and has no default (use ${{{}:-default}})",
+                    name, name
+                ));
+            }
+        };
+        out.push_str(&expanded);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Interpolate environment variables into the mount, env, image, and entrypoint
+/// values of `config`, failing with every unset variable at once
+fn interpolate_config(config: &mut Config, lookup: impl Fn(&str) -> Option<String>) -> Result<()> {
+    let mut problems = Vec::new();
+    let mut expand = |key: &str, value: &mut String| match interpolate_env(value, &lookup) {
+        Ok(expanded) => *value = expanded,
+        Err(e) => problems.push(format!("{}: {}", key, e)),
+    };
+
+    let runtime = &mut config.runtime;
+    expand("runtime.image", &mut runtime.image);
+    for arg in runtime.entrypoint.iter_mut().flatten() {
+        expand("runtime.entrypoint", arg);
+    }
+    for value in &mut runtime.env {
+        expand("runtime.env", value);
+    }
+    interpolate_mounts("runtime.mounts", &mut runtime.mounts, &mut expand);
+    for (name, profile) in &mut config.profiles {
+        for value in &mut profile.env {
+            expand(&format!("profiles.{}.env", name), value);
+        }
+        interpolate_mounts(
+            &format!("profiles.{}.mounts", name),
+            &mut profile.mounts,
+            &mut expand,
+        );
+    }
+
+    if !problems.is_empty() {
+        eyre::bail!("Invalid config values:\n  {}", problems.join("\n  "));
+    }
+    Ok(())
+}
+
+fn interpolate_mounts(
+    key: &str,
+    mounts: &mut MountsConfig,
+    expand: &mut impl FnMut(&str, &mut String),
+) {
+    for (mode, paths) in [
+        ("ro", &mut mounts.ro),
+        ("rw", &mut mounts.rw),
+        ("o", &mut mounts.o),
+    ] {
+        for value in paths.absolute.iter_mut().chain(&mut paths.home_relative) {
+            expand(&format!("{}.{}", key, mode), value);
+        }
+    }
+    for entry in &mut mounts.entries {
+        expand(&format!("{}.entries", key), &mut entry.spec);
+    }
+    for value in &mut mounts.tmpfs.paths {
+        expand(&format!("{}.tmpfs", key), value);
+    }
+    for value in &mut mounts.volumes {
+        expand(&format!("{}.volumes", key), value);
+    }
+}
+
+/// Extract a [`Config`], accepting a legacy `[docker]` table in place of `[runtime]`.
+///
+/// When `[runtime]` is absent, `[docker]` is read as the runtime config with
+/// `backend = "docker"` (unless it sets its own backend). A deprecation warning
+/// is printed either way; when both tables are present `[docker]` is ignored.
+fn extract_config(figment: &Figment) -> Result<Config> {
+    // Convert figment::Error to eyre::Report with nice formatting
+    let mut config: Config = figment.extract().map_err(|e| eyre::eyre!("{}", e))?;
+
+    let Ok(mut docker) = figment.find_value("docker") else {
+        return Ok(config);
+    };
+
+    if figment.find_value("runtime").is_ok() {
+        eprintln!(
+            "WARNING: Ignoring deprecated [docker] config section because [runtime] is also set"
+        );
+        return Ok(config);
+    }
 
-    // Expand all paths
-    config.workspace_dir =
-        expand_path(&config.workspace_dir).wrap_err("Failed to expand workspace_dir path")?;
-    config.base_repo_dir =
-        expand_path(&config.base_repo_dir).wrap_err("Failed to expand base_repo_dir path")?;
+    eprintln!(
+        "WARNING: The [docker] config section is deprecated; rename it to [runtime] \
+         (`ab dbg migrate-config` can do this for you)"
+    );
+    // Legacy configs spelled the entrypoint as an argv array
+    if let figment::value::Value::Dict(_, dict) = &mut docker
+        && let Some(words) = dict.get("entrypoint").and_then(|v| v.as_array())
+    {
+        let words: Vec<&str> = words.iter().filter_map(|w| w.as_str()).collect();
+        let joined = shell_words::join(words);
+        dict.insert("entrypoint".to_string(), joined.into());
+    }
+    let mut runtime: RuntimeConfig = docker
+        .deserialize()
+        .map_err(|e| eyre::eyre!("Invalid [docker] config section: {}", e))?;
+    if figment.find_value("docker.backend").is_err() {
+        runtime.backend = "docker".to_string();
+    }
+    config.runtime = runtime;
 
     Ok(config)
 }
 
+/// Serialize the merged, path-expanded config as a lockfile.
+/// The output is pretty-printed JSON with sorted keys so it diffs cleanly.
+pub fn freeze_config(config: &Config) -> Result<String> {
+    let value = serde_json::to_value(config).wrap_err("Failed to serialize config")?;
+    serde_json::to_string_pretty(&value).wrap_err("Failed to serialize config")
+}
+
+/// Compare `config` against a lockfile written by [`freeze_config`].
+/// Returns one line per drifted key (e.g. `runtime.image: "a" -> "b"`); empty means no drift.
+pub fn config_drift(lockfile: &str, config: &Config) -> Result<Vec<String>> {
+    let frozen: Config = serde_json::from_str(lockfile).wrap_err("Failed to parse lockfile")?;
+    let expected = serde_json::to_value(&frozen).wrap_err("Failed to serialize lockfile")?;
+    let actual = serde_json::to_value(config).wrap_err("Failed to serialize config")?;
+
+    let mut drift = Vec::new();
+    diff_values("", &expected, &actual, &mut drift);
+    Ok(drift)
+}
+
+fn diff_values(
+    path: &str,
+    expected: &serde_json::Value,
+    actual: &serde_json::Value,
+    drift: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    let (Value::Object(expected), Value::Object(actual)) = (expected, actual) else {
+        if expected != actual {
+            drift.push(format!("{}: {} -> {}", path, expected, actual));
+        }
+        return;
+    };
+
+    let keys: std::collections::BTreeSet<&String> = expected.keys().chain(actual.keys()).collect();
+    for key in keys {
+        let child = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match (expected.get(key), actual.get(key)) {
+            (Some(e), Some(a)) => diff_values(&child, e, a, drift),
+            (Some(e), None) => drift.push(format!("{}: removed (was {})", child, e)),
+            (None, Some(a)) => drift.push(format!("{}: added ({})", child, a)),
+            (None, None) => {}
+        }
+    }
+}
+
 /// Validation error for profile configuration
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ProfileValidationError {
     pub profile_name: Option<String>,
     pub message: String,
@@ -851,7 +3056,7 @@ impl std::fmt::Display for ProfileValidationError {
 }
 
 /// Result of config validation
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ValidationResult {
     pub errors: Vec<ProfileValidationError>,
     pub warnings: Vec<ProfileValidationError>,
@@ -885,15 +3090,95 @@ pub fn validate_config(config: &Config) -> ValidationResult {
         let available: Vec<_> = config.profiles.keys().cloned().collect();
         errors.push(ProfileValidationError {
             profile_name: None,
-            message: format!(
-                "default_profile '{}' is not defined. Available profiles: {:?}",
-                default, available
-            ),
+            message: format!(
+                "default_profile '{}' is not defined. Available profiles: {:?}",
+                default, available
+            ),
+        });
+    }
+
+    if let Err(e) = parse_age(&config.gc.older_than) {
+        errors.push(ProfileValidationError {
+            profile_name: None,
+            message: format!("gc.older_than: {}", e),
+        });
+    }
+
+    for problem in config.runtime.resources.problems() {
+        errors.push(ProfileValidationError {
+            profile_name: None,
+            message: format!("runtime.{}", problem),
+        });
+    }
+    for problem in config.runtime.mounts.problems() {
+        errors.push(ProfileValidationError {
+            profile_name: None,
+            message: format!("runtime.{}", problem),
+        });
+    }
+    for problem in config.runtime.security.problems() {
+        errors.push(ProfileValidationError {
+            profile_name: None,
+            message: format!("runtime.{}", problem),
+        });
+    }
+    for problem in config.runtime.gpu.problems() {
+        errors.push(ProfileValidationError {
+            profile_name: None,
+            message: format!("runtime.{}", problem),
+        });
+    }
+    for problem in config.runtime.kubernetes.problems() {
+        errors.push(ProfileValidationError {
+            profile_name: None,
+            message: format!("runtime.{}", problem),
         });
     }
 
     // Check each profile
     for (profile_name, profile) in &config.profiles {
+        for problem in profile.resources.problems() {
+            errors.push(ProfileValidationError {
+                profile_name: Some(profile_name.clone()),
+                message: problem,
+            });
+        }
+        for problem in profile.mounts.problems() {
+            errors.push(ProfileValidationError {
+                profile_name: Some(profile_name.clone()),
+                message: problem,
+            });
+        }
+        for problem in profile.network.iter().flat_map(NetworkConfig::problems) {
+            errors.push(ProfileValidationError {
+                profile_name: Some(profile_name.clone()),
+                message: problem,
+            });
+        }
+        if let Some(Err(problem)) = profile.gpu.as_ref().map(GpuSetting::devices) {
+            errors.push(ProfileValidationError {
+                profile_name: Some(profile_name.clone()),
+                message: problem,
+            });
+        }
+        for (name, secret) in &profile.secrets {
+            for problem in secret.problems(name) {
+                errors.push(ProfileValidationError {
+                    profile_name: Some(profile_name.clone()),
+                    message: problem,
+                });
+            }
+        }
+        // Only a warning: the profile may not be used on this machine
+        if let Some(build) = &profile.build
+            && !build.file.is_file()
+        {
+            warnings.push(ProfileValidationError {
+                profile_name: Some(profile_name.clone()),
+                message: format!("build.file {} does not exist", build.file.display()),
+            });
+        }
+
         // Check for self-reference
         if profile.extends.contains(profile_name) {
             errors.push(ProfileValidationError {
@@ -927,19 +3212,7 @@ pub fn validate_config(config: &Config) -> ValidationResult {
         }
 
         // Warn about empty profiles (no mounts, no env, no env_passthrough, no ports, no hosts, no context, no extends)
-        if profile.extends.is_empty()
-            && profile.env.is_empty()
-            && profile.env_passthrough.is_empty()
-            && profile.ports.is_empty()
-            && profile.hosts.is_empty()
-            && profile.context.is_empty()
-            && profile.mounts.ro.absolute.is_empty()
-            && profile.mounts.ro.home_relative.is_empty()
-            && profile.mounts.rw.absolute.is_empty()
-            && profile.mounts.rw.home_relative.is_empty()
-            && profile.mounts.o.absolute.is_empty()
-            && profile.mounts.o.home_relative.is_empty()
-        {
+        if is_empty_profile(profile) {
             warnings.push(ProfileValidationError {
                 profile_name: Some(profile_name.clone()),
                 message:
@@ -952,6 +3225,30 @@ pub fn validate_config(config: &Config) -> ValidationResult {
     ValidationResult { errors, warnings }
 }
 
+/// True if a profile sets nothing at all (no mounts, env, env_passthrough, ports,
+/// hosts, context, or extends); `validate_config` warns about these.
+pub fn is_empty_profile(profile: &ProfileConfig) -> bool {
+    profile.extends.is_empty()
+        && profile.env.is_empty()
+        && profile.env_passthrough.is_empty()
+        && profile.ports.is_empty()
+        && profile.hosts.is_empty()
+        && profile.context.is_empty()
+        && profile.command.is_none()
+        && profile.network.is_none()
+        && profile.resources.is_empty()
+        && profile.build.is_none()
+        && profile.secrets.is_empty()
+        && profile.gpu.is_none()
+        && profile.mounts.ro.absolute.is_empty()
+        && profile.mounts.ro.home_relative.is_empty()
+        && profile.mounts.rw.absolute.is_empty()
+        && profile.mounts.rw.home_relative.is_empty()
+        && profile.mounts.o.absolute.is_empty()
+        && profile.mounts.o.home_relative.is_empty()
+        && profile.mounts.entries.is_empty()
+}
+
 /// Detect circular dependencies starting from a profile.
 /// Returns Some(cycle_path) if a cycle is found, None otherwise.
 fn detect_cycle(config: &Config, start: &str) -> Option<Vec<String>> {
@@ -974,75 +3271,594 @@ fn detect_cycle_recursive(
 
     let profile = config.profiles.get(current)?;
 
-    visited.insert(current.to_string());
-    path.push(current.to_string());
+    visited.insert(current.to_string());
+    path.push(current.to_string());
+
+    for parent in &profile.extends {
+        if let Some(cycle) = detect_cycle_recursive(config, parent, visited, path) {
+            return Some(cycle);
+        }
+    }
+
+    path.pop();
+    visited.remove(current);
+    None
+}
+
+/// Profile `extends` adjacency: each profile name mapped to the profiles it extends
+pub fn profile_graph(config: &Config) -> BTreeMap<String, Vec<String>> {
+    config
+        .profiles
+        .iter()
+        .map(|(name, profile)| (name.clone(), profile.extends.clone()))
+        .collect()
+}
+
+/// Mount counts for a profile: `(own, total)`, where `total` includes mounts
+/// inherited through `extends` (after dedup). Fails on unknown or cyclic extends.
+pub fn profile_mount_counts(config: &Config, name: &str) -> Result<(usize, usize)> {
+    let profile = config
+        .profiles
+        .get(name)
+        .ok_or_else(|| eyre::eyre!("Unknown profile '{}'", name))?;
+    let mut resolved = resolve_single_profile(config, name, &mut HashSet::new())?;
+    resolved.dedup_mounts();
+    Ok((profile.mounts.to_mounts().len(), resolved.mounts.len()))
+}
+
+/// Render the `extends` graph as an indented tree.
+///
+/// Each profile that no other profile extends is a root, with the profiles it
+/// extends nested beneath it. A profile already expanded elsewhere is marked
+/// `(see above)`, one that re-enters the current chain `(cycle)` (the same path
+/// tracking as `detect_cycle`), and a missing one `(undefined)`. Profiles that are
+/// only reachable through a cycle are rendered as extra roots.
+pub fn render_profile_tree(config: &Config) -> String {
+    let graph = profile_graph(config);
+    let extended: HashSet<&str> = graph.values().flatten().map(String::as_str).collect();
+
+    let mut out = String::new();
+    let mut expanded = HashSet::new();
+    let roots = graph
+        .keys()
+        .filter(|name| !extended.contains(name.as_str()));
+    for root in roots {
+        render_tree_node(
+            &graph,
+            root,
+            "",
+            None,
+            &mut Vec::new(),
+            &mut expanded,
+            &mut out,
+        );
+    }
+    for name in graph.keys() {
+        if !expanded.contains(name.as_str()) {
+            render_tree_node(
+                &graph,
+                name,
+                "",
+                None,
+                &mut Vec::new(),
+                &mut expanded,
+                &mut out,
+            );
+        }
+    }
+    out
+}
+
+/// `is_last` is `None` for a root, otherwise whether the node is its parent's last child
+fn render_tree_node<'a>(
+    graph: &'a BTreeMap<String, Vec<String>>,
+    name: &'a str,
+    prefix: &str,
+    is_last: Option<bool>,
+    path: &mut Vec<&'a str>,
+    expanded: &mut HashSet<&'a str>,
+    out: &mut String,
+) {
+    let (branch, child_prefix) = match is_last {
+        None => ("", String::new()),
+        Some(true) => ("└── ", format!("{}    ", prefix)),
+        Some(false) => ("├── ", format!("{}│   ", prefix)),
+    };
+
+    let children = graph.get(name);
+    let marker = if path.contains(&name) {
+        " (cycle)"
+    } else if children.is_none() {
+        " (undefined)"
+    } else if expanded.contains(name) && children.is_some_and(|c| !c.is_empty()) {
+        " (see above)"
+    } else {
+        ""
+    };
+    out.push_str(&format!("{}{}{}{}\n", prefix, branch, name, marker));
+    if !marker.is_empty() {
+        return;
+    }
+
+    let children = children.expect("checked above");
+    expanded.insert(name);
+    path.push(name);
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        render_tree_node(graph, child, &child_prefix, Some(last), path, expanded, out);
+    }
+    path.pop();
+}
+
+/// Validate config and return errors as a formatted Result
+pub fn validate_config_or_err(config: &Config) -> Result<()> {
+    let result = validate_config(config);
+
+    if !result.is_ok() {
+        let error_messages: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
+        return Err(eyre::eyre!(
+            "Configuration validation failed:\n  - {}",
+            error_messages.join("\n  - ")
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::result_large_err)]
+mod tests {
+    use super::*;
+    use figment::Jail;
+
+    #[test]
+    fn test_mount_mode_serializes_as_flag() {
+        assert_eq!(serde_json::to_value(MountMode::Ro).unwrap(), "ro");
+        assert_eq!(serde_json::to_value(MountMode::Rw).unwrap(), "rw");
+        assert_eq!(serde_json::to_value(MountMode::Overlay).unwrap(), "O");
+    }
+
+    #[test]
+    fn test_mount_serializes_to_clean_json() {
+        let mount = Mount {
+            spec: "~/.config".to_string(),
+            home_relative: true,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        assert_eq!(
+            serde_json::to_string(&mount).unwrap(),
+            r#"{"mode":"ro","spec":"~/.config","home_relative":true}"#
+        );
+    }
+
+    #[test]
+    fn test_resolved_mount_serializes_paths_as_strings() {
+        let mount = ResolvedMount {
+            host: PathBuf::from("/home/u/.config"),
+            container: PathBuf::from("/home/c/.config"),
+            mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&mount).unwrap(),
+            serde_json::json!({
+                "host": "/home/u/.config",
+                "container": "/home/c/.config",
+                "mode": "rw",
+            })
+        );
+    }
+
+    #[test]
+    fn test_resolved_profile_serializes_all_fields() {
+        let profile = ResolvedProfile {
+            mounts: vec![Mount {
+                spec: "/nix/store".to_string(),
+                home_relative: false,
+                mode: MountMode::Overlay,
+                propagation: None,
+                recursive: true,
+            }],
+            env: vec!["FOO=bar".to_string()],
+            env_passthrough: vec!["TERM".to_string()],
+            ports: vec!["8080:8080".to_string()],
+            hosts: vec!["db:10.0.0.2".to_string()],
+            context: vec!["be nice".to_string()],
+            command: Some(vec!["make".to_string(), "test".to_string()]),
+            network: Some("none".to_string()),
+            allow_hosts: vec!["github.com".to_string()],
+            tmpfs: vec!["~/.cache".to_string()],
+            volumes: vec!["cargo-cache:~/.cargo/registry".to_string()],
+            resources: ResourcesConfig {
+                memory: Some("4g".to_string()),
+                ..Default::default()
+            },
+            build: Some(BuildConfig {
+                file: PathBuf::from("/repo/Containerfile"),
+                context: None,
+                args: BTreeMap::from([("RUST".to_string(), "1.90".to_string())]),
+                target: None,
+            }),
+            secrets: BTreeMap::from([(
+                "GH_TOKEN".to_string(),
+                SecretConfig {
+                    command: Some(vec!["pass".to_string(), "gh".to_string()]),
+                    file: None,
+                    env: None,
+                    path: None,
+                },
+            )]),
+            gpu: Some(GpuSetting::Devices("device=0".to_string())),
+        };
+        assert_eq!(
+            serde_json::to_value(&profile).unwrap(),
+            serde_json::json!({
+                "mounts": [{"mode": "O", "spec": "/nix/store", "home_relative": false}],
+                "env": ["FOO=bar"],
+                "env_passthrough": ["TERM"],
+                "ports": ["8080:8080"],
+                "hosts": ["db:10.0.0.2"],
+                "context": ["be nice"],
+                "command": ["make", "test"],
+                "network": "none",
+                "allow_hosts": ["github.com"],
+                "tmpfs": ["~/.cache"],
+                "volumes": ["cargo-cache:~/.cargo/registry"],
+                "resources": {
+                    "cpus": null,
+                    "memory": "4g",
+                    "memory_swap": null,
+                    "pids_limit": null,
+                    "ulimits": {},
+                },
+                "build": {
+                    "file": "/repo/Containerfile",
+                    "context": null,
+                    "args": {"RUST": "1.90"},
+                    "target": null,
+                },
+                "secrets": {
+                    "GH_TOKEN": {"command": ["pass", "gh"], "file": null, "env": null, "path": null},
+                },
+                "gpu": "device=0",
+            })
+        );
+    }
+
+    #[test]
+    fn test_global_config_only() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+
+                [runtime]
+                backend = "docker"
+                image = "test:latest"
+                env = ["FOO=bar"]
+
+                [runtime.mounts.ro]
+                absolute = ["/nix/store"]
+                home_relative = ["~/.config/git"]
+                "#,
+            )?;
+
+            let global_path = jail.directory().join("global.toml");
+            let figment = build_figment(Some(&global_path), None);
+            let config: Config = figment.extract()?;
+
+            assert_eq!(config.workspace_dir, PathBuf::from("/workspaces"));
+            assert_eq!(config.base_repo_dir, PathBuf::from("/repos"));
+            assert_eq!(config.runtime.backend, "docker");
+            assert_eq!(config.runtime.image, "test:latest");
+            assert_eq!(config.runtime.env, vec!["FOO=bar"]);
+            assert_eq!(config.runtime.mounts.ro.absolute, vec!["/nix/store"]);
+            assert_eq!(
+                config.runtime.mounts.ro.home_relative,
+                vec!["~/.config/git"]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_interpolate_env_vars() {
+        let lookup = |name: &str| match name {
+            "USER" => Some("alice".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        assert_eq!(
+            interpolate_env("/home/${USER}/.cargo", lookup).unwrap(),
+            "/home/alice/.cargo"
+        );
+        assert_eq!(
+            interpolate_env("img:${TAG:-latest} ${EMPTY:-x}${EMPTY}", lookup).unwrap(),
+            "img:latest x"
+        );
+        assert_eq!(interpolate_env("$USER", lookup).unwrap(), "$USER");
+        assert!(
+            interpolate_env("${TAG}", lookup)
+                .unwrap_err()
+                .contains("${TAG} is not set")
+        );
+        assert!(interpolate_env("${USER", lookup).is_err());
+        assert!(interpolate_env("${A B}", lookup).is_err());
+
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+
+                [runtime]
+                image = "registry/${USER}/dev:${TAG:-latest}"
+                entrypoint = "/home/${USER}/bin/init --login"
+                env = ["GIT_AUTHOR_NAME=${USER}"]
+
+                [runtime.mounts.ro]
+                absolute = ["/home/${USER}/.gitconfig"]
+
+                [profiles.rust.mounts]
+                volumes = ["cargo-${USER}:~/.cargo"]
+                entries = [{ spec = "/data/${USER}" }]
+                "#,
+            )?;
+
+            let figment = build_figment(Some(&jail.directory().join("global.toml")), None);
+            let mut config = extract_config(&figment).unwrap();
+            interpolate_config(&mut config, lookup).unwrap();
+            assert_eq!(config.runtime.image, "registry/alice/dev:latest");
+            assert_eq!(
+                config.runtime.entrypoint,
+                Some(vec![
+                    "/home/alice/bin/init".to_string(),
+                    "--login".to_string()
+                ])
+            );
+            assert_eq!(config.runtime.env, vec!["GIT_AUTHOR_NAME=alice"]);
+            assert_eq!(
+                config.runtime.mounts.ro.absolute,
+                vec!["/home/alice/.gitconfig"]
+            );
+            let rust = &config.profiles["rust"].mounts;
+            assert_eq!(rust.volumes, vec!["cargo-alice:~/.cargo"]);
+            assert_eq!(rust.entries[0].spec, "/data/alice");
+
+            let mut config = extract_config(&figment).unwrap();
+            let err = interpolate_config(&mut config, |_| None)
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("runtime.image: ${USER} is not set"), "{}", err);
+            assert!(
+                err.contains("profiles.rust.mounts.volumes: ${USER}"),
+                "{}",
+                err
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_legacy_docker_section_maps_to_runtime() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+
+                [docker]
+                image = "legacy:latest"
+                entrypoint = ["/bin/bash", "-l"]
+                env = ["FOO=bar"]
+
+                [docker.mounts.ro]
+                absolute = ["/nix/store"]
+                "#,
+            )?;
+
+            let figment = build_figment(Some(&jail.directory().join("global.toml")), None);
+            let config = extract_config(&figment).unwrap();
+
+            assert_eq!(config.runtime.backend, "docker");
+            assert_eq!(config.runtime.image, "legacy:latest");
+            assert_eq!(
+                config.runtime.entrypoint,
+                Some(vec!["/bin/bash".to_string(), "-l".to_string()])
+            );
+            assert_eq!(config.runtime.env, vec!["FOO=bar"]);
+            assert_eq!(config.runtime.mounts.ro.absolute, vec!["/nix/store"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_legacy_docker_section_keeps_explicit_backend() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+
+                [docker]
+                backend = "podman"
+                image = "legacy:latest"
+                "#,
+            )?;
+
+            let figment = build_figment(Some(&jail.directory().join("global.toml")), None);
+            let config = extract_config(&figment).unwrap();
+
+            assert_eq!(config.runtime.backend, "podman");
+            assert_eq!(config.runtime.image, "legacy:latest");
 
-    for parent in &profile.extends {
-        if let Some(cycle) = detect_cycle_recursive(config, parent, visited, path) {
-            return Some(cycle);
-        }
+            Ok(())
+        });
     }
 
-    path.pop();
-    visited.remove(current);
-    None
-}
+    #[test]
+    fn test_runtime_section_wins_over_legacy_docker() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
 
-/// Validate config and return errors as a formatted Result
-pub fn validate_config_or_err(config: &Config) -> Result<()> {
-    let result = validate_config(config);
+                [docker]
+                image = "legacy:latest"
+                "#,
+            )?;
+            jail.create_file(
+                "repo.toml",
+                r#"
+                [runtime]
+                backend = "podman"
+                image = "current:latest"
+                "#,
+            )?;
 
-    if !result.is_ok() {
-        let error_messages: Vec<String> = result.errors.iter().map(|e| e.to_string()).collect();
-        return Err(eyre::eyre!(
-            "Configuration validation failed:\n  - {}",
-            error_messages.join("\n  - ")
-        ));
+            let figment = build_figment(
+                Some(&jail.directory().join("global.toml")),
+                Some(&jail.directory().join("repo.toml")),
+            );
+            let config = extract_config(&figment).unwrap();
+
+            assert_eq!(config.runtime.backend, "podman");
+            assert_eq!(config.runtime.image, "current:latest");
+
+            Ok(())
+        });
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_workspace_mode_parses_octal() {
+        let runtime: RuntimeConfig = Figment::from(Toml::string(r#"workspace_mode = "0775""#))
+            .extract()
+            .unwrap();
+        assert_eq!(runtime.workspace_mode, Some(0o775));
+        assert_eq!(
+            serde_json::to_value(&runtime).unwrap()["workspace_mode"],
+            "0775"
+        );
 
-#[cfg(test)]
-#[allow(clippy::result_large_err)]
-mod tests {
-    use super::*;
-    use figment::Jail;
+        assert_eq!(parse_mode("2770"), Ok(0o2770));
+        assert_eq!(parse_mode("0o750"), Ok(0o750));
+        for bad in ["", "0o", "0789", "17777", "rwx"] {
+            assert!(parse_mode(bad).is_err(), "{}", bad);
+        }
+
+        let err = Figment::from(Toml::string(r#"workspace_mode = "0999""#))
+            .extract::<RuntimeConfig>()
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid mode '0999'"), "{}", err);
+    }
 
     #[test]
-    fn test_global_config_only() {
+    fn test_load_repo_config_only() {
         Jail::expect_with(|jail| {
             jail.create_file(
                 "global.toml",
                 r#"
-                workspace_dir = "/workspaces"
-                base_repo_dir = "/repos"
+                workspace_dir = "/global/workspaces"
+                base_repo_dir = "/global/repos"
 
                 [runtime]
-                backend = "docker"
-                image = "test:latest"
-                env = ["FOO=bar"]
+                image = "global:latest"
+                env = ["GLOBAL=1"]
+                "#,
+            )?;
+            jail.create_file(
+                "repo.toml",
+                r#"
+                workspace_dir = "/repo/workspaces"
+                base_repo_dir = "/repo/repos"
 
-                [runtime.mounts.ro]
-                absolute = ["/nix/store"]
-                home_relative = ["~/.config/git"]
+                [runtime]
+                env = ["REPO=1"]
                 "#,
             )?;
 
-            let global_path = jail.directory().join("global.toml");
-            let figment = build_figment(&global_path, None);
-            let config: Config = figment.extract()?;
+            let config = load_repo_config_from(&jail.directory().join("repo.toml")).unwrap();
 
-            assert_eq!(config.workspace_dir, PathBuf::from("/workspaces"));
-            assert_eq!(config.base_repo_dir, PathBuf::from("/repos"));
-            assert_eq!(config.runtime.backend, "docker");
-            assert_eq!(config.runtime.image, "test:latest");
-            assert_eq!(config.runtime.env, vec!["FOO=bar"]);
-            assert_eq!(config.runtime.mounts.ro.absolute, vec!["/nix/store"]);
+            assert_eq!(config.workspace_dir, PathBuf::from("/repo/workspaces"));
+            assert_eq!(config.base_repo_dir, PathBuf::from("/repo/repos"));
+            assert_eq!(config.runtime.image, "");
+            assert_eq!(config.runtime.env, vec!["REPO=1"]);
+
+            jail.set_env(NO_GLOBAL_CONFIG_ENV, "1");
+            assert!(is_no_global_config());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_relative_dirs_resolve_against_config_file() {
+        Jail::expect_with(|jail| {
+            jail.create_dir("home")?;
+            jail.create_dir("repo")?;
+            jail.create_dir("elsewhere")?;
+            jail.create_file(
+                "home/.agent-box.toml",
+                r#"
+                workspace_dir = "workspaces"
+                base_repo_dir = "/abs/repos"
+                "#,
+            )?;
+            jail.create_file("repo/.agent-box.toml", r#"base_repo_dir = "../src""#)?;
+
+            // Relative to the file that set each key, whatever the CWD is
+            jail.change_dir("elsewhere")?;
+            let global = jail.directory().join("home/.agent-box.toml");
+            let repo = jail.directory().join("repo/.agent-box.toml");
+
+            let config = load_config_from(&global, None).unwrap();
             assert_eq!(
-                config.runtime.mounts.ro.home_relative,
-                vec!["~/.config/git"]
+                config.workspace_dir,
+                jail.directory().join("home/workspaces")
+            );
+            assert_eq!(config.base_repo_dir, PathBuf::from("/abs/repos"));
+
+            let config = load_config_from(&global, Some(&repo)).unwrap();
+            assert_eq!(
+                config.workspace_dir,
+                jail.directory().join("home/workspaces")
             );
+            assert_eq!(config.base_repo_dir, jail.directory().join("repo/../src"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_load_repo_config_only_requires_dirs() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "repo.toml",
+                r#"
+                workspace_dir = "/repo/workspaces"
+                "#,
+            )?;
+
+            let err = load_repo_config_from(&jail.directory().join("repo.toml")).unwrap_err();
+            let msg = err.to_string();
+            assert!(msg.contains("must set base_repo_dir"), "{}", msg);
+            assert!(!msg.contains("workspace_dir"), "{}", msg);
+
+            let err = load_repo_config_from(&jail.directory().join("missing.toml")).unwrap_err();
+            assert!(err.to_string().contains("does not exist"), "{}", err);
 
             Ok(())
         });
@@ -1074,7 +3890,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // Scalars should be overridden by repo config
@@ -1128,7 +3944,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // Arrays should be concatenated (global first, then repo)
@@ -1158,6 +3974,137 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_mount_entries_parse_with_defaults() {
+        let mounts: MountsConfig = Figment::from(Toml::string(
+            r#"
+            entries = [
+                { spec = "~/.gitconfig", mode = "ro" },
+                { spec = "/nix/store", mode = "ro" },
+                { spec = "~/cache:/cache", mode = "overlay" },
+                { spec = "/data" },
+                { spec = "/home/me/notes", home_relative = true, mode = "o" },
+            ]
+            "#,
+        ))
+        .extract()
+        .unwrap();
+
+        let summary: Vec<(String, bool, MountMode)> = mounts
+            .to_mounts()
+            .into_iter()
+            .map(|m| (m.spec, m.home_relative, m.mode))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("~/.gitconfig".to_string(), true, MountMode::Ro),
+                ("/nix/store".to_string(), false, MountMode::Ro),
+                ("~/cache:/cache".to_string(), true, MountMode::Overlay),
+                ("/data".to_string(), false, MountMode::Rw),
+                ("/home/me/notes".to_string(), true, MountMode::Overlay),
+            ]
+        );
+
+        let mounts: MountsConfig = Figment::from(Toml::string(
+            r#"entries = [{ spec = "/tmp", mode = "ro", bind_propagation = "rslave" }]"#,
+        ))
+        .extract()
+        .unwrap();
+        let mount = &mounts.to_mounts()[0];
+        assert_eq!(mount.propagation, Some(BindPropagation::Rslave));
+        assert_eq!(
+            mount
+                .to_resolved_mounts_with_homes("/home/me", "/home/me")
+                .unwrap()[0]
+                .to_bind_string(),
+            "/tmp:/tmp:ro,rslave"
+        );
+
+        let mounts: MountsConfig = Figment::from(Toml::string(
+            r#"entries = [{ spec = "/tmp", recursive = false }, { spec = "/var" }]"#,
+        ))
+        .extract()
+        .unwrap();
+        assert!(!mounts.entries[0].recursive);
+        assert!(mounts.entries[1].recursive);
+        let mounts = mounts.to_mounts();
+        assert_eq!(
+            mounts[0]
+                .to_resolved_mounts_with_homes("/home/me", "/home/me")
+                .unwrap()[0]
+                .to_bind_string(),
+            "/tmp:/tmp:rw,nonrecursive"
+        );
+        assert_ne!(mounts[0], mounts[1]);
+        assert_eq!(
+            serde_json::to_value(&mounts[0]).unwrap()["recursive"],
+            serde_json::json!(false)
+        );
+
+        let err = Figment::from(Toml::string(r#"entries = [{ spec = "/x", mode = "rx" }]"#))
+            .extract::<MountsConfig>()
+            .unwrap_err();
+        assert!(err.to_string().contains("rx"), "{}", err);
+    }
+
+    #[test]
+    fn test_mount_entries_merge_with_split_form() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+
+                [runtime]
+                image = "test:latest"
+
+                [runtime.mounts]
+                entries = [{ spec = "~/.ssh", mode = "ro" }]
+
+                [runtime.mounts.ro]
+                absolute = ["/nix/store"]
+
+                [profiles.rust.mounts]
+                entries = [{ spec = "~/.cargo" }]
+                "#,
+            )?;
+            jail.create_file(
+                "repo.toml",
+                r#"
+                [runtime.mounts]
+                entries = [{ spec = "/opt/tools", mode = "ro" }]
+                "#,
+            )?;
+
+            let global_path = jail.directory().join("global.toml");
+            let repo_path = jail.directory().join("repo.toml");
+            let config: Config = build_figment(Some(&global_path), Some(&repo_path)).extract()?;
+
+            // Entries concatenate across layers like the split lists
+            let specs: Vec<String> = config
+                .runtime
+                .mounts
+                .to_mounts()
+                .into_iter()
+                .map(|m| format!("{}:{}", m.spec, m.mode))
+                .collect();
+            assert_eq!(specs, vec!["/nix/store:ro", "~/.ssh:ro", "/opt/tools:ro"]);
+
+            // Profiles accept entries too, and are not considered empty
+            let rust = &config.profiles["rust"];
+            assert!(!is_empty_profile(rust));
+            let resolved = resolve_profiles(&config, &["rust".to_string()]).unwrap();
+            let last = resolved.mounts.last().unwrap();
+            assert_eq!(
+                (last.spec.as_str(), last.home_relative, last.mode),
+                ("~/.cargo", true, MountMode::Rw)
+            );
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_repo_config_can_override_top_level() {
         Jail::expect_with(|jail| {
@@ -1181,7 +4128,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // workspace_dir should be overridden
@@ -1218,7 +4165,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // entrypoint is a string, so repo overrides global (no concatenation)
@@ -1256,7 +4203,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // If repo doesn't set entrypoint, global's value is used
@@ -1293,7 +4240,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // If global doesn't set entrypoint, repo config's value is used directly
@@ -1322,7 +4269,7 @@ mod tests {
             )?;
 
             let global_path = jail.directory().join("global.toml");
-            let figment = build_figment(&global_path, None);
+            let figment = build_figment(Some(&global_path), None);
             let config: Config = figment.extract()?;
 
             // Shell-words parsing should handle quoted arguments
@@ -1356,7 +4303,7 @@ mod tests {
             )?;
 
             let global_path = jail.directory().join("global.toml");
-            let figment = build_figment(&global_path, None);
+            let figment = build_figment(Some(&global_path), None);
             let config: Config = figment.extract()?;
 
             assert_eq!(
@@ -1384,7 +4331,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("nonexistent.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // Should work fine with just global config
@@ -1410,7 +4357,7 @@ mod tests {
             )?;
 
             let global_path = jail.directory().join("global.toml");
-            let figment = build_figment(&global_path, None);
+            let figment = build_figment(Some(&global_path), None);
             let config: Config = figment.extract()?;
 
             // Backend should default to "podman"
@@ -1435,7 +4382,7 @@ mod tests {
             )?;
 
             let global_path = jail.directory().join("global.toml");
-            let figment = build_figment(&global_path, None);
+            let figment = build_figment(Some(&global_path), None);
             let config: Config = figment.extract()?;
 
             // Arrays should default to empty
@@ -1492,7 +4439,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // All nested arrays should be properly merged
@@ -1528,13 +4475,247 @@ mod tests {
                 hosts: vec![],
                 dns: vec![],
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
             portal: crate::portal::PortalConfig::default(),
         }
     }
 
+    #[test]
+    fn test_split_profile_args_comma_separated() {
+        let args = vec!["git,rust,debug".to_string()];
+        assert_eq!(
+            split_profile_args(&args).unwrap(),
+            vec!["git", "rust", "debug"]
+        );
+    }
+
+    #[test]
+    fn test_split_profile_args_mixed_preserves_order() {
+        let args = vec![
+            "base".to_string(),
+            "git, rust".to_string(),
+            "debug".to_string(),
+        ];
+        assert_eq!(
+            split_profile_args(&args).unwrap(),
+            vec!["base", "git", "rust", "debug"]
+        );
+    }
+
+    #[test]
+    fn test_split_profile_args_rejects_empty_segments() {
+        for arg in ["git,,rust", ",git", "git,", ""] {
+            let err = split_profile_args(&[arg.to_string()]).unwrap_err();
+            assert!(
+                err.to_string().contains("Empty profile name"),
+                "unexpected error for '{}': {}",
+                arg,
+                err
+            );
+        }
+    }
+
+    fn make_aliases(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(name, tokens)| {
+                (
+                    name.to_string(),
+                    tokens.iter().map(|t| t.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_alias_appends_extra_args() {
+        let aliases = make_aliases(&[("dev", &["--local", "-p", "rust"])]);
+        let expanded =
+            expand_alias(&aliases, "dev", &["-P".to_string(), "8080".to_string()]).unwrap();
+        assert_eq!(expanded, vec!["--local", "-p", "rust", "-P", "8080"]);
+    }
+
+    #[test]
+    fn test_expand_alias_nested() {
+        let aliases = make_aliases(&[
+            ("base", &["--local", "-p", "git"]),
+            ("dev", &["base", "-p", "rust"]),
+        ]);
+        let expanded = expand_alias(&aliases, "dev", &["--ro".to_string()]).unwrap();
+        assert_eq!(expanded, vec!["--local", "-p", "git", "-p", "rust", "--ro"]);
+    }
+
+    #[test]
+    fn test_expand_alias_recursion_guard() {
+        let aliases = make_aliases(&[("a", &["b", "-p", "x"]), ("b", &["a"])]);
+        let err = expand_alias(&aliases, "a", &[]).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Recursive alias detected: a -> b -> a")
+        );
+
+        let aliases = make_aliases(&[("self", &["self"])]);
+        assert!(expand_alias(&aliases, "self", &[]).is_err());
+    }
+
+    #[test]
+    fn test_expand_alias_unknown() {
+        let aliases = make_aliases(&[("dev", &["--local"])]);
+        let err = expand_alias(&aliases, "nope", &[]).unwrap_err();
+        assert!(err.to_string().contains("Unknown alias 'nope'"));
+    }
+
+    #[test]
+    fn test_aliases_parsing_from_toml() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+
+                [aliases]
+                dev = ["--local", "-p", "rust"]
+                "#,
+            )?;
+
+            let global_path = jail.directory().join("global.toml");
+            let config: Config = build_figment(Some(&global_path), None).extract()?;
+
+            assert_eq!(config.aliases["dev"], vec!["--local", "-p", "rust"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_should_redact_env_defaults() {
+        assert!(should_redact_env("GITHUB_TOKEN", &[]));
+        assert!(should_redact_env("OPENAI_API_KEY", &[]));
+        assert!(should_redact_env("AWS_SECRET", &[]));
+        assert!(should_redact_env("DB_PASSWORD", &[]));
+        assert!(should_redact_env("github_token", &[]));
+        assert!(!should_redact_env("PATH", &[]));
+        assert!(!should_redact_env("TERM", &[]));
+    }
+
+    #[test]
+    fn test_should_redact_env_extra_patterns() {
+        let extra = vec!["MY_CREDS".to_string(), "VAULT_*".to_string()];
+        assert!(should_redact_env("MY_CREDS", &extra));
+        assert!(should_redact_env("VAULT_ADDR", &extra));
+        assert!(!should_redact_env("MY_CREDS", &[]));
+    }
+
+    #[test]
+    fn test_redact_env_entry() {
+        assert_eq!(redact_env_entry("GH_TOKEN=abc=def", &[]), "GH_TOKEN=****");
+        assert_eq!(redact_env_entry("FOO=bar", &[]), "FOO=bar");
+        assert_eq!(redact_env_entry("NO_VALUE", &[]), "NO_VALUE");
+    }
+
+    fn config_with_extends(graph: &[(&str, &[&str])]) -> Config {
+        let mut config = make_test_config();
+        for (name, extends) in graph {
+            config.profiles.insert(
+                name.to_string(),
+                ProfileConfig {
+                    extends: extends.iter().map(|e| e.to_string()).collect(),
+                    ..Default::default()
+                },
+            );
+        }
+        config
+    }
+
+    #[test]
+    fn test_render_profile_tree_diamond() {
+        let config = config_with_extends(&[
+            ("app", &["left", "right"]),
+            ("left", &["base"]),
+            ("right", &["base"]),
+            ("base", &["core"]),
+            ("core", &[]),
+        ]);
+
+        assert_eq!(
+            render_profile_tree(&config),
+            "\
+app
+├── left
+│   └── base
+│       └── core
+└── right
+    └── base (see above)
+"
+        );
+    }
+
+    #[test]
+    fn test_render_profile_tree_cycle_and_undefined() {
+        let config = config_with_extends(&[("a", &["b"]), ("b", &["a"]), ("solo", &["missing"])]);
+
+        assert_eq!(
+            render_profile_tree(&config),
+            "\
+solo
+└── missing (undefined)
+a
+└── b
+    └── a (cycle)
+"
+        );
+    }
+
+    #[test]
+    fn test_profile_graph_and_mount_counts() {
+        let mut config = config_with_extends(&[("base", &[]), ("rust", &["base"])]);
+        config.profiles.get_mut("base").unwrap().mounts.ro.absolute =
+            vec!["/nix/store".to_string()];
+        config
+            .profiles
+            .get_mut("rust")
+            .unwrap()
+            .mounts
+            .rw
+            .home_relative = vec!["~/.cargo".to_string()];
+
+        assert_eq!(
+            serde_json::to_value(profile_graph(&config)).unwrap(),
+            serde_json::json!({"base": [], "rust": ["base"]})
+        );
+        assert_eq!(profile_mount_counts(&config, "rust").unwrap(), (1, 2));
+        assert_eq!(profile_mount_counts(&config, "base").unwrap(), (1, 1));
+    }
+
     #[test]
     fn test_resolve_profiles_no_profiles() {
         let config = make_test_config();
@@ -1564,6 +4745,12 @@ mod tests {
                 env_passthrough: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1597,6 +4784,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1617,6 +4810,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1650,6 +4849,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1663,6 +4868,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1686,6 +4897,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1699,6 +4916,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1722,6 +4945,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1735,6 +4964,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1748,6 +4983,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1761,6 +5002,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1781,6 +5028,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_mount_sources_keeps_duplicates() {
+        let mut config = make_test_config();
+        let ro = |path: &str| MountsConfig {
+            ro: MountPaths {
+                absolute: vec![path.to_string()],
+                home_relative: vec![],
+            },
+            ..Default::default()
+        };
+
+        // base is reached through both git and jj; dev mounts /nix/store again
+        config.profiles.insert(
+            "base".to_string(),
+            ProfileConfig {
+                mounts: ro("/nix/store"),
+                ..Default::default()
+            },
+        );
+        for name in ["git", "jj"] {
+            config.profiles.insert(
+                name.to_string(),
+                ProfileConfig {
+                    extends: vec!["base".to_string()],
+                    ..Default::default()
+                },
+            );
+        }
+        config.profiles.insert(
+            "dev".to_string(),
+            ProfileConfig {
+                extends: vec!["git".to_string(), "jj".to_string()],
+                mounts: ro("/nix/store"),
+                ..Default::default()
+            },
+        );
+
+        let profiles = ["dev".to_string()];
+        let deduped = resolve_profiles(&config, &profiles).unwrap();
+        let raw = resolve_mount_sources(&config, &profiles).unwrap();
+
+        assert_eq!(deduped.mounts.len(), 1);
+        assert_eq!(raw.len(), 3);
+        let sources: Vec<_> = raw.iter().map(|(_, s)| s.as_str()).collect();
+        assert_eq!(sources, vec!["base", "base", "dev"]);
+        assert!(raw.iter().all(|(m, _)| m.spec == "/nix/store"));
+    }
+
     #[test]
     fn test_resolve_profiles_circular_dependency_detected() {
         let mut config = make_test_config();
@@ -1796,6 +5091,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1809,6 +5110,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1832,6 +5139,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1865,6 +5178,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1892,12 +5211,21 @@ mod tests {
                         home_relative: vec!["~/.base-rw".to_string()],
                     },
                     o: MountPaths::default(),
+                    entries: vec![],
+                    tmpfs: TmpfsMounts::default(),
+                    volumes: Vec::new(),
                 },
                 env: vec![],
                 env_passthrough: vec![],
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1915,12 +5243,21 @@ mod tests {
                         absolute: vec![],
                         home_relative: vec!["~/.extra-o".to_string()],
                     },
+                    entries: vec![],
+                    tmpfs: TmpfsMounts::default(),
+                    volumes: Vec::new(),
                 },
                 env: vec![],
                 env_passthrough: vec![],
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1972,6 +5309,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -1992,6 +5335,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2031,6 +5380,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2050,6 +5405,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2069,6 +5430,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2082,6 +5449,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2125,6 +5498,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2145,6 +5524,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2186,6 +5571,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2205,6 +5596,12 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
@@ -2238,6 +5635,8 @@ mod tests {
             spec: symlink_a.to_string_lossy().to_string(),
             home_relative: false,
             mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
         };
 
         let resolved_mounts = mount.to_resolved_mounts().unwrap();
@@ -2259,6 +5658,35 @@ mod tests {
         assert!(all_binds.contains("real"), "should contain real");
     }
 
+    #[test]
+    fn test_mount_symlink_cycle_errors() {
+        // Create: link_a -> link_b -> link_a, matched through a glob
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab_symlink_cycle_{}", std::process::id()));
+        let link_a = temp_dir.join("link_a");
+        let link_b = temp_dir.join("link_b");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        std::os::unix::fs::symlink("link_b", &link_a).unwrap();
+        std::os::unix::fs::symlink(&link_a, &link_b).unwrap();
+
+        let mount = Mount {
+            spec: format!("{}/link_*", temp_dir.display()),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let result = mount.to_resolved_mounts_with_homes("/home/host", "/home/user");
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("symlink cycle detected at"), "{}", err);
+        assert!(err.contains("link_a"), "{}", err);
+    }
+
     #[test]
     fn test_mount_to_bind_strings_no_symlink() {
         // Test that regular paths just return one bind string
@@ -2273,6 +5701,8 @@ mod tests {
             spec: temp_dir.to_string_lossy().to_string(),
             home_relative: false,
             mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
         };
 
         let resolved_mounts = mount.to_resolved_mounts().unwrap();
@@ -2297,6 +5727,8 @@ mod tests {
             spec: nonexistent_path.to_string(),
             home_relative: false,
             mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
         };
 
         let resolved_mounts = mount.to_resolved_mounts().unwrap();
@@ -2312,6 +5744,8 @@ mod tests {
             spec: "~/nonexistent_directory_that_should_not_exist".to_string(),
             home_relative: true,
             mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
         };
 
         let resolved_mounts = mount.to_resolved_mounts().unwrap();
@@ -2337,6 +5771,8 @@ mod tests {
             spec: glob_spec,
             home_relative: false,
             mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
         };
 
         let resolved_mounts = mount.to_resolved_mounts().unwrap();
@@ -2368,6 +5804,8 @@ mod tests {
             spec: "/tmp/ab_glob_no_match_*/this_should_never_exist_*".to_string(),
             home_relative: false,
             mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
         };
 
         let resolved_mounts = mount.to_resolved_mounts().unwrap();
@@ -2380,6 +5818,8 @@ mod tests {
             spec: "/tmp/kitty-*:/mnt/kitty".to_string(),
             home_relative: false,
             mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
         };
 
         let result = mount.to_resolved_mounts();
@@ -2408,6 +5848,8 @@ mod tests {
             spec: glob_spec,
             home_relative: true,
             mode: MountMode::Rw,
+            propagation: None,
+            recursive: true,
         };
 
         let container_home = "/home/container_user";
@@ -2460,7 +5902,7 @@ mod tests {
             )?;
 
             let config_path = jail.directory().join("config.toml");
-            let figment = build_figment(&config_path, None);
+            let figment = build_figment(Some(&config_path), None);
             let config: Config = figment.extract()?;
 
             assert_eq!(config.default_profile, Some("base".to_string()));
@@ -2520,7 +5962,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // Should have all 3 profiles merged
@@ -2587,7 +6029,7 @@ mod tests {
 
             let global_path = jail.directory().join("global.toml");
             let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
             let config: Config = figment.extract()?;
 
             // default_profile should be overridden to "dev"
@@ -2602,61 +6044,373 @@ mod tests {
     }
 
     #[test]
-    fn test_layered_profiles_repo_adds_env_to_global_profile() {
-        // Test that repo config can add env vars to a global profile
-        Jail::expect_with(|jail| {
-            jail.create_file(
-                "global.toml",
-                r#"
-                workspace_dir = "/workspaces"
-                base_repo_dir = "/repos"
-
-                [profiles.rust]
-                env = ["CARGO_HOME=~/.cargo"]
+    fn test_layered_profiles_repo_adds_env_to_global_profile() {
+        // Test that repo config can add env vars to a global profile
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "global.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+
+                [profiles.rust]
+                env = ["CARGO_HOME=~/.cargo"]
+
+                [profiles.rust.mounts.ro]
+                home_relative = ["~/.cargo/config.toml"]
+
+                [runtime]
+                image = "test:latest"
+                "#,
+            )?;
+
+            jail.create_file(
+                "repo.toml",
+                r#"
+                # Add more env vars and mounts to the global rust profile
+                [profiles.rust]
+                env = ["RUST_BACKTRACE=1"]
+
+                [profiles.rust.mounts.rw]
+                home_relative = ["~/.cargo/registry"]
+                "#,
+            )?;
+
+            let global_path = jail.directory().join("global.toml");
+            let repo_path = jail.directory().join("repo.toml");
+            let figment = build_figment(Some(&global_path), Some(&repo_path));
+            let config: Config = figment.extract()?;
+
+            // Profile should have merged env and mounts
+            let rust = config.profiles.get("rust").unwrap();
+            assert_eq!(rust.env, vec!["CARGO_HOME=~/.cargo", "RUST_BACKTRACE=1"]);
+            assert_eq!(rust.mounts.ro.home_relative, vec!["~/.cargo/config.toml"]);
+            assert_eq!(rust.mounts.rw.home_relative, vec!["~/.cargo/registry"]);
+
+            Ok(())
+        });
+    }
+
+    // Validation tests
+
+    #[test]
+    fn test_validate_config_valid() {
+        let mut config = make_test_config();
+        config.default_profile = Some("base".to_string());
+        config.profiles.insert(
+            "base".to_string(),
+            ProfileConfig {
+                extends: vec![],
+                mounts: MountsConfig::default(),
+                env: vec!["A=1".to_string()],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+
+        let result = validate_config(&config);
+        assert!(result.is_ok());
+        assert!(result.errors.is_empty());
+    }
+
+    #[test]
+    fn test_validate_config_invalid_default_profile() {
+        let mut config = make_test_config();
+        config.default_profile = Some("nonexistent".to_string());
+
+        let result = validate_config(&config);
+        assert!(!result.is_ok());
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].message.contains("default_profile"));
+        assert!(result.errors[0].message.contains("nonexistent"));
+    }
+
+    #[test]
+    fn test_validate_config_kubernetes_pvc() {
+        let mut config = make_test_config();
+        config.runtime.kubernetes.mounts = KubernetesMountSource::Pvc;
+        config.runtime.kubernetes.claim_root = Some(PathBuf::from("home"));
+
+        let result = validate_config(&config);
+        let messages: Vec<_> = result.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "runtime.kubernetes.claim is required when mounts = \"pvc\"",
+                "runtime.kubernetes.claim_root 'home' must be absolute",
+            ]
+        );
+
+        config.runtime.kubernetes.claim = Some("agent-home".to_string());
+        config.runtime.kubernetes.claim_root = Some(PathBuf::from("/home"));
+        assert!(validate_config(&config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_config_invalid_extends() {
+        let mut config = make_test_config();
+        config.profiles.insert(
+            "broken".to_string(),
+            ProfileConfig {
+                extends: vec!["nonexistent".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+
+        let result = validate_config(&config);
+        assert!(!result.is_ok());
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].message.contains("nonexistent"));
+        assert_eq!(result.errors[0].profile_name, Some("broken".to_string()));
+    }
+
+    #[test]
+    fn test_validate_config_self_reference() {
+        let mut config = make_test_config();
+        config.profiles.insert(
+            "self_ref".to_string(),
+            ProfileConfig {
+                extends: vec!["self_ref".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+
+        let result = validate_config(&config);
+        assert!(!result.is_ok());
+        // Should have self-reference error
+        assert!(
+            result
+                .errors
+                .iter()
+                .any(|e| e.message.contains("self-reference"))
+        );
+    }
+
+    #[test]
+    fn test_validate_config_circular_dependency() {
+        let mut config = make_test_config();
+        config.profiles.insert(
+            "a".to_string(),
+            ProfileConfig {
+                extends: vec!["b".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+        config.profiles.insert(
+            "b".to_string(),
+            ProfileConfig {
+                extends: vec!["c".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+        config.profiles.insert(
+            "c".to_string(),
+            ProfileConfig {
+                extends: vec!["a".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+
+        let result = validate_config(&config);
+        assert!(!result.is_ok());
+        // Should detect cycle
+        assert!(result.errors.iter().any(|e| e.message.contains("circular")));
+    }
+
+    #[test]
+    fn test_validate_config_empty_profile_warning() {
+        let mut config = make_test_config();
+        config.profiles.insert(
+            "empty".to_string(),
+            ProfileConfig {
+                extends: vec![],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+
+        let result = validate_config(&config);
+        assert!(result.is_ok()); // warnings don't make it invalid
+        assert!(result.has_warnings());
+        assert!(
+            result.warnings[0]
+                .message
+                .contains("no mounts, env, env_passthrough, ports, hosts, context, or extends")
+        );
+    }
+
+    #[test]
+    fn test_validate_config_multiple_errors() {
+        let mut config = make_test_config();
+        config.default_profile = Some("nonexistent".to_string());
+        config.profiles.insert(
+            "broken1".to_string(),
+            ProfileConfig {
+                extends: vec!["also_nonexistent".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+        config.profiles.insert(
+            "broken2".to_string(),
+            ProfileConfig {
+                extends: vec!["broken2".to_string()], // self-reference
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
 
-                [profiles.rust.mounts.ro]
-                home_relative = ["~/.cargo/config.toml"]
+        let result = validate_config(&config);
+        assert!(!result.is_ok());
+        // Should have multiple errors: default_profile, extends unknown, self-reference
+        assert!(result.errors.len() >= 3);
+    }
 
-                [runtime]
-                image = "test:latest"
-                "#,
-            )?;
+    #[test]
+    fn test_validate_config_or_err_success() {
+        let mut config = make_test_config();
+        config.profiles.insert(
+            "valid".to_string(),
+            ProfileConfig {
+                extends: vec![],
+                mounts: MountsConfig::default(),
+                env: vec!["A=1".to_string()],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
 
-            jail.create_file(
-                "repo.toml",
-                r#"
-                # Add more env vars and mounts to the global rust profile
-                [profiles.rust]
-                env = ["RUST_BACKTRACE=1"]
+        let result = validate_config_or_err(&config);
+        assert!(result.is_ok());
+    }
 
-                [profiles.rust.mounts.rw]
-                home_relative = ["~/.cargo/registry"]
-                "#,
-            )?;
+    #[test]
+    fn test_validate_config_or_err_failure() {
+        let mut config = make_test_config();
+        config.default_profile = Some("nonexistent".to_string());
 
-            let global_path = jail.directory().join("global.toml");
-            let repo_path = jail.directory().join("repo.toml");
-            let figment = build_figment(&global_path, Some(&repo_path));
-            let config: Config = figment.extract()?;
+        let result = validate_config_or_err(&config);
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("validation failed"));
+        assert!(err.contains("nonexistent"));
+    }
 
-            // Profile should have merged env and mounts
-            let rust = config.profiles.get("rust").unwrap();
-            assert_eq!(rust.env, vec!["CARGO_HOME=~/.cargo", "RUST_BACKTRACE=1"]);
-            assert_eq!(rust.mounts.ro.home_relative, vec!["~/.cargo/config.toml"]);
-            assert_eq!(rust.mounts.rw.home_relative, vec!["~/.cargo/registry"]);
+    #[test]
+    fn test_validate_config_no_profiles_is_valid() {
+        let config = make_test_config();
 
-            Ok(())
-        });
+        let result = validate_config(&config);
+        assert!(result.is_ok());
+        assert!(!result.has_warnings());
     }
 
-    // Validation tests
-
     #[test]
-    fn test_validate_config_valid() {
+    fn test_validate_config_deep_valid_chain() {
         let mut config = make_test_config();
-        config.default_profile = Some("base".to_string());
         config.profiles.insert(
-            "base".to_string(),
+            "a".to_string(),
             ProfileConfig {
                 extends: vec![],
                 mounts: MountsConfig::default(),
@@ -2665,402 +6419,1201 @@ mod tests {
                 ports: vec![],
                 hosts: vec![],
                 context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+        config.profiles.insert(
+            "b".to_string(),
+            ProfileConfig {
+                extends: vec!["a".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec!["B=1".to_string()],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+        config.profiles.insert(
+            "c".to_string(),
+            ProfileConfig {
+                extends: vec!["b".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec!["C=1".to_string()],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
+        config.profiles.insert(
+            "d".to_string(),
+            ProfileConfig {
+                extends: vec!["c".to_string()],
+                mounts: MountsConfig::default(),
+                env: vec!["D=1".to_string()],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: String::new(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
+        config.default_profile = Some("d".to_string());
 
         let result = validate_config(&config);
         assert!(result.is_ok());
-        assert!(result.errors.is_empty());
     }
 
     #[test]
-    fn test_validate_config_invalid_default_profile() {
+    fn test_resolve_profiles_context_merged() {
         let mut config = make_test_config();
-        config.default_profile = Some("nonexistent".to_string());
+        config.context = "root-context".to_string();
 
-        let result = validate_config(&config);
-        assert!(!result.is_ok());
-        assert_eq!(result.errors.len(), 1);
-        assert!(result.errors[0].message.contains("default_profile"));
-        assert!(result.errors[0].message.contains("nonexistent"));
-    }
+        config.profiles.insert(
+            "base".to_string(),
+            ProfileConfig {
+                extends: vec![],
+                mounts: MountsConfig::default(),
+                env: vec![],
+                env_passthrough: vec![],
+                ports: vec![],
+                hosts: vec![],
+                context: "base-context".to_string(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
+            },
+        );
 
-    #[test]
-    fn test_validate_config_invalid_extends() {
-        let mut config = make_test_config();
         config.profiles.insert(
-            "broken".to_string(),
+            "extended".to_string(),
             ProfileConfig {
-                extends: vec!["nonexistent".to_string()],
+                extends: vec!["base".to_string()],
                 mounts: MountsConfig::default(),
                 env: vec![],
                 env_passthrough: vec![],
                 ports: vec![],
                 hosts: vec![],
-                context: String::new(),
+                context: "extended-context".to_string(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
-        let result = validate_config(&config);
-        assert!(!result.is_ok());
-        assert_eq!(result.errors.len(), 1);
-        assert!(result.errors[0].message.contains("nonexistent"));
-        assert_eq!(result.errors[0].profile_name, Some("broken".to_string()));
+        let resolved = resolve_profiles(&config, &["extended".to_string()]).unwrap();
+
+        // Context should be: root + base + extended
+        assert_eq!(
+            resolved.context,
+            vec!["root-context", "base-context", "extended-context"]
+        );
     }
 
     #[test]
-    fn test_validate_config_self_reference() {
+    fn test_resolve_profiles_context_from_root_only() {
+        let mut config = make_test_config();
+        config.context = "root-context".to_string();
+
+        let resolved = resolve_profiles(&config, &[]).unwrap();
+
+        // Should have context from root config only
+        assert_eq!(resolved.context, vec!["root-context"]);
+    }
+
+    #[test]
+    fn test_resolve_profiles_context_from_profile_only() {
         let mut config = make_test_config();
+
         config.profiles.insert(
-            "self_ref".to_string(),
+            "with_context".to_string(),
             ProfileConfig {
-                extends: vec!["self_ref".to_string()],
+                extends: vec![],
                 mounts: MountsConfig::default(),
                 env: vec![],
                 env_passthrough: vec![],
                 ports: vec![],
                 hosts: vec![],
-                context: String::new(),
+                context: "profile-context".to_string(),
+                command: None,
+                network: None,
+                resources: Default::default(),
+                build: None,
+                secrets: Default::default(),
+                gpu: None,
             },
         );
 
-        let result = validate_config(&config);
-        assert!(!result.is_ok());
-        // Should have self-reference error
-        assert!(
-            result
-                .errors
-                .iter()
-                .any(|e| e.message.contains("self-reference"))
-        );
+        let resolved = resolve_profiles(&config, &["with_context".to_string()]).unwrap();
+
+        // Should have context from profile only
+        assert_eq!(resolved.context, vec!["profile-context"]);
+    }
+
+    #[test]
+    fn test_context_parsing_from_toml() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
+                context = "root-context"
+
+                [profiles.base]
+                context = "base-context"
+                env = ["BASE=1"]
+
+                [profiles.dev]
+                extends = ["base"]
+                context = "dev-context"
+                env = ["DEV=1"]
+
+                [runtime]
+                image = "test:latest"
+                "#,
+            )?;
+
+            let config_path = jail.directory().join("config.toml");
+            let figment = build_figment(Some(&config_path), None);
+            let config: Config = figment.extract()?;
+
+            // Check root-level context
+            assert_eq!(config.context, "root-context");
+
+            // Check profile contexts
+            let base = config.profiles.get("base").unwrap();
+            assert_eq!(base.context, "base-context");
+
+            let dev = config.profiles.get("dev").unwrap();
+            assert_eq!(dev.extends, vec!["base"]);
+            assert_eq!(dev.context, "dev-context");
+
+            // Test resolution
+            let resolved = resolve_profiles(&config, &["dev".to_string()]).unwrap();
+            assert_eq!(
+                resolved.context,
+                vec!["root-context", "base-context", "dev-context"]
+            );
+
+            Ok(())
+        });
     }
 
     #[test]
-    fn test_validate_config_circular_dependency() {
+    fn test_freeze_config_round_trip() {
         let mut config = make_test_config();
+        config.runtime.entrypoint = Some(vec![
+            "bash".to_string(),
+            "-c".to_string(),
+            "echo hi there".to_string(),
+        ]);
         config.profiles.insert(
-            "a".to_string(),
-            ProfileConfig {
-                extends: vec!["b".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
-            },
-        );
-        config.profiles.insert(
-            "b".to_string(),
+            "git".to_string(),
             ProfileConfig {
-                extends: vec!["c".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
+                env: vec!["GIT=1".to_string()],
+                ..Default::default()
             },
         );
-        config.profiles.insert(
-            "c".to_string(),
-            ProfileConfig {
-                extends: vec!["a".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
-            },
+        config.aliases.insert(
+            "claude".to_string(),
+            vec!["-p".to_string(), "git".to_string()],
         );
 
-        let result = validate_config(&config);
-        assert!(!result.is_ok());
-        // Should detect cycle
-        assert!(result.errors.iter().any(|e| e.message.contains("circular")));
+        let lockfile = freeze_config(&config).unwrap();
+        let thawed: Config = serde_json::from_str(&lockfile).unwrap();
+        assert_eq!(thawed, config);
+        assert!(config_drift(&lockfile, &config).unwrap().is_empty());
     }
 
     #[test]
-    fn test_validate_config_empty_profile_warning() {
+    fn test_config_drift_reports_changed_keys() {
         let mut config = make_test_config();
-        config.profiles.insert(
-            "empty".to_string(),
-            ProfileConfig {
-                extends: vec![],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
-            },
+        let lockfile = freeze_config(&config).unwrap();
+
+        config.runtime.image = "other:latest".to_string();
+        config
+            .profiles
+            .insert("rust".to_string(), ProfileConfig::default());
+
+        let drift = config_drift(&lockfile, &config).unwrap();
+        assert_eq!(drift.len(), 2, "unexpected drift: {:?}", drift);
+        assert!(drift.iter().any(|d| d.starts_with("profiles.rust: added")));
+        assert!(drift.contains(&"runtime.image: \"test:latest\" -> \"other:latest\"".to_string()));
+    }
+
+    #[test]
+    fn test_config_drift_rejects_invalid_lockfile() {
+        let err = config_drift("not json", &make_test_config()).unwrap_err();
+        assert!(err.to_string().contains("Failed to parse lockfile"));
+    }
+
+    #[test]
+    fn test_mount_resolution_cache_resolves_repeated_spec_once() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_mount_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(temp_dir.join("target")).unwrap();
+        std::os::unix::fs::symlink(temp_dir.join("target"), temp_dir.join("link")).unwrap();
+        let home = temp_dir.to_string_lossy().to_string();
+
+        let mount = Mount {
+            spec: "~/link".to_string(),
+            home_relative: true,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let uncached = mount
+            .to_resolved_mounts_with_homes(&home, "/home/container")
+            .unwrap();
+
+        let mut cache = MountResolutionCache::default();
+        for _ in 0..3 {
+            let cached = cache
+                .resolve_chain(&mount, &home, "/home/container")
+                .unwrap();
+            assert_eq!(cached, uncached);
+        }
+        assert_eq!(cache.resolutions(), 1);
+
+        // Same spec with a different mode or homes is a different resolution
+        let rw = Mount {
+            mode: MountMode::Rw,
+            ..mount.clone()
+        };
+        cache.resolve_chain(&rw, &home, "/home/container").unwrap();
+        cache.resolve_chain(&mount, &home, "/home/other").unwrap();
+        assert_eq!(cache.resolutions(), 3);
+
+        // Canonical pairs are cached separately and match the uncached result
+        let pair = cache
+            .resolve_pair(&mount, &home, "/home/container")
+            .unwrap();
+        cache
+            .resolve_pair(&mount, &home, "/home/container")
+            .unwrap();
+        assert_eq!(
+            pair,
+            mount.resolve_with_homes(&home, "/home/container").unwrap()
         );
+        assert_eq!(cache.resolutions(), 4);
 
-        let result = validate_config(&config);
-        assert!(result.is_ok()); // warnings don't make it invalid
-        assert!(result.has_warnings());
-        assert!(
-            result.warnings[0]
-                .message
-                .contains("no mounts, env, env_passthrough, ports, hosts, context, or extends")
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    #[test]
+    fn test_dedup_mounts_with_cache_matches_dedup_mounts() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_dedup_cache_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        let spec = temp_dir.to_string_lossy().to_string();
+        let mount = Mount {
+            spec: spec.clone(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let missing = Mount {
+            spec: format!("{}/missing", spec),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+
+        let profile = ResolvedProfile {
+            mounts: vec![
+                mount.clone(),
+                missing.clone(),
+                mount.clone(),
+                missing.clone(),
+            ],
+            ..Default::default()
+        };
+
+        let mut uncached = profile.clone();
+        uncached.dedup_mounts();
+
+        let mut cached = profile;
+        let mut cache = MountResolutionCache::default();
+        cached.dedup_mounts_with(&mut cache);
+
+        assert_eq!(cached.mounts.len(), 2);
+        assert_eq!(cached, uncached);
+        if std::env::var("HOME").is_ok() {
+            // The existing path resolves once; the missing one fails and is retried
+            assert_eq!(cache.resolutions(), 3);
+        }
+
+        let _ = std::fs::remove_dir_all(&temp_dir);
+    }
+
+    fn conflicting_mounts() -> ResolvedProfile {
+        let mount = |spec: &str, mode| Mount {
+            spec: spec.to_string(),
+            home_relative: false,
+            mode,
+            propagation: None,
+            recursive: true,
+        };
+        ResolvedProfile {
+            mounts: vec![
+                mount("/nonexistent/ab-conflict/a", MountMode::Rw),
+                mount("/nonexistent/ab-conflict/b", MountMode::Overlay),
+                mount("/nonexistent/ab-conflict/a", MountMode::Ro),
+                mount("/nonexistent/ab-conflict/b", MountMode::Rw),
+                mount("/nonexistent/ab-conflict/a", MountMode::Overlay),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn dedup_modes(policy: MountConflict) -> Result<Vec<MountMode>> {
+        let mut profile = conflicting_mounts();
+        profile.dedup_mounts_with_policy(policy, &mut MountResolutionCache::default())?;
+        assert!(profile.mounts[0].spec.ends_with("/a"));
+        assert!(profile.mounts[1].spec.ends_with("/b"));
+        Ok(profile.mounts.iter().map(|m| m.mode).collect())
+    }
+
+    #[test]
+    fn test_mount_conflict_first() {
+        assert_eq!(
+            dedup_modes(MountConflict::First).unwrap(),
+            vec![MountMode::Rw, MountMode::Overlay]
         );
     }
 
     #[test]
-    fn test_validate_config_multiple_errors() {
-        let mut config = make_test_config();
-        config.default_profile = Some("nonexistent".to_string());
-        config.profiles.insert(
-            "broken1".to_string(),
-            ProfileConfig {
-                extends: vec!["also_nonexistent".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
-            },
+    fn test_mount_conflict_last() {
+        assert_eq!(
+            dedup_modes(MountConflict::Last).unwrap(),
+            vec![MountMode::Overlay, MountMode::Rw]
         );
-        config.profiles.insert(
-            "broken2".to_string(),
-            ProfileConfig {
-                extends: vec!["broken2".to_string()], // self-reference
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
-            },
+    }
+
+    #[test]
+    fn test_mount_conflict_strictest() {
+        assert_eq!(
+            dedup_modes(MountConflict::Strictest).unwrap(),
+            vec![MountMode::Ro, MountMode::Rw]
         );
+    }
 
-        let result = validate_config(&config);
-        assert!(!result.is_ok());
-        // Should have multiple errors: default_profile, extends unknown, self-reference
-        assert!(result.errors.len() >= 3);
+    #[test]
+    fn test_mount_conflict_error_lists_every_conflict() {
+        let err = dedup_modes(MountConflict::Error).unwrap_err().to_string();
+        assert!(err.contains("rw: /nonexistent/ab-conflict/a  vs  ro: /nonexistent/ab-conflict/a"));
+        assert!(err.contains("rw: /nonexistent/ab-conflict/a  vs  O: /nonexistent/ab-conflict/a"));
+        assert!(err.contains("O: /nonexistent/ab-conflict/b  vs  rw: /nonexistent/ab-conflict/b"));
     }
 
     #[test]
-    fn test_validate_config_or_err_success() {
+    fn test_mount_conflict_same_mode_is_not_a_conflict() {
+        let mount = Mount {
+            spec: "/nonexistent/ab-conflict/same".to_string(),
+            home_relative: false,
+            mode: MountMode::Ro,
+            propagation: None,
+            recursive: true,
+        };
+        let mut profile = ResolvedProfile {
+            mounts: vec![mount.clone(), mount.clone()],
+            ..Default::default()
+        };
+        profile
+            .dedup_mounts_with_policy(MountConflict::Error, &mut MountResolutionCache::default())
+            .unwrap();
+        assert_eq!(profile.mounts, vec![mount]);
+    }
+
+    #[test]
+    fn test_resolve_profiles_applies_mount_conflict_policy() {
         let mut config = make_test_config();
+        config.runtime.mount_conflict = MountConflict::Error;
+        config.runtime.mounts.rw.absolute = vec!["/nonexistent/ab-conflict/p".to_string()];
         config.profiles.insert(
-            "valid".to_string(),
+            "locked".to_string(),
             ProfileConfig {
-                extends: vec![],
-                mounts: MountsConfig::default(),
-                env: vec!["A=1".to_string()],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
+                mounts: MountsConfig {
+                    ro: MountPaths {
+                        absolute: vec!["/nonexistent/ab-conflict/p".to_string()],
+                        home_relative: vec![],
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
             },
         );
 
-        let result = validate_config_or_err(&config);
-        assert!(result.is_ok());
+        let err = resolve_profiles(&config, &["locked".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("Conflicting mount modes"));
+
+        config.runtime.mount_conflict = MountConflict::Strictest;
+        let resolved = resolve_profiles(&config, &["locked".to_string()]).unwrap();
+        assert_eq!(
+            resolved.get_mount_specs(MountMode::Ro, false),
+            vec!["/nonexistent/ab-conflict/p"]
+        );
+        assert!(resolved.get_mount_specs(MountMode::Rw, false).is_empty());
     }
 
     #[test]
-    fn test_validate_config_or_err_failure() {
-        let mut config = make_test_config();
-        config.default_profile = Some("nonexistent".to_string());
+    fn test_command_parses_shell_string_and_array() {
+        let parse =
+            |toml: &str| -> ProfileConfig { Figment::from(Toml::string(toml)).extract().unwrap() };
 
-        let result = validate_config_or_err(&config);
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(err.contains("validation failed"));
-        assert!(err.contains("nonexistent"));
+        assert_eq!(
+            parse("command = \"./ci/agent.sh --name 'my agent'\"").command,
+            Some(vec![
+                "./ci/agent.sh".to_string(),
+                "--name".to_string(),
+                "my agent".to_string()
+            ])
+        );
+        assert_eq!(
+            parse("command = [\"sh\", \"-c\", \"make test\"]").command,
+            Some(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "make test".to_string()
+            ])
+        );
+        assert_eq!(parse("env = []").command, None);
+
+        let err = Figment::from(Toml::string("command = \"unterminated 'quote\""))
+            .extract::<ProfileConfig>()
+            .unwrap_err();
+        assert!(err.to_string().contains("command"));
     }
 
     #[test]
-    fn test_validate_config_no_profiles_is_valid() {
-        let config = make_test_config();
+    fn test_secret_env_parses_helper_table() {
+        let runtime: RuntimeConfig = Figment::from(Toml::string(
+            r#"
+            [secret_env]
+            GH_TOKEN = { helper = "pass", args = ["show", "github/token"] }
+            NPM_TOKEN = { helper = "npm-token" }
+            "#,
+        ))
+        .extract()
+        .unwrap();
 
-        let result = validate_config(&config);
-        assert!(result.is_ok());
-        assert!(!result.has_warnings());
+        assert_eq!(
+            runtime.secret_env["GH_TOKEN"],
+            SecretHelper {
+                helper: "pass".to_string(),
+                args: vec!["show".to_string(), "github/token".to_string()],
+            }
+        );
+        assert!(runtime.secret_env["NPM_TOKEN"].args.is_empty());
+
+        let err = Figment::from(Toml::string("[secret_env]\nX = { args = [] }"))
+            .extract::<RuntimeConfig>()
+            .unwrap_err();
+        assert!(err.to_string().contains("helper"));
     }
 
     #[test]
-    fn test_validate_config_deep_valid_chain() {
+    fn test_resolve_profiles_command_last_layer_wins() {
         let mut config = make_test_config();
+        let command = |c: &str| Some(vec![c.to_string()]);
+        config.runtime.command = command("runtime");
         config.profiles.insert(
-            "a".to_string(),
+            "base".to_string(),
             ProfileConfig {
-                extends: vec![],
-                mounts: MountsConfig::default(),
-                env: vec!["A=1".to_string()],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
+                command: command("base"),
+                ..Default::default()
             },
         );
         config.profiles.insert(
-            "b".to_string(),
+            "child".to_string(),
             ProfileConfig {
-                extends: vec!["a".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec!["B=1".to_string()],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
+                extends: vec!["base".to_string()],
+                command: command("child"),
+                ..Default::default()
             },
         );
         config.profiles.insert(
-            "c".to_string(),
+            "inherits".to_string(),
             ProfileConfig {
-                extends: vec!["b".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec!["C=1".to_string()],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
+                extends: vec!["base".to_string()],
+                ..Default::default()
+            },
+        );
+        config
+            .profiles
+            .insert("plain".to_string(), ProfileConfig::default());
+
+        let resolve = |names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            resolve_profiles(&config, &names).unwrap().command
+        };
+
+        assert_eq!(resolve(&[]), command("runtime"));
+        assert_eq!(resolve(&["plain"]), command("runtime"));
+        assert_eq!(resolve(&["inherits"]), command("base"));
+        assert_eq!(resolve(&["child"]), command("child"));
+        // Later CLI profiles override earlier ones; profiles without a command don't reset it
+        assert_eq!(resolve(&["child", "base"]), command("base"));
+        assert_eq!(resolve(&["child", "plain"]), command("child"));
+    }
+
+    #[test]
+    fn test_resolve_profiles_network_last_layer_wins() {
+        let mut config = make_test_config();
+        let network = |n: &str| Some(n.to_string());
+        let mode = |n: &str| {
+            Some(NetworkConfig {
+                mode: network(n),
+                allow_hosts: Vec::new(),
+            })
+        };
+        config.profiles.insert(
+            "build".to_string(),
+            ProfileConfig {
+                network: mode("none"),
+                ..Default::default()
             },
         );
         config.profiles.insert(
-            "d".to_string(),
+            "web".to_string(),
             ProfileConfig {
-                extends: vec!["c".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec!["D=1".to_string()],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: String::new(),
+                extends: vec!["build".to_string()],
+                network: mode("host"),
+                ..Default::default()
             },
         );
-        config.default_profile = Some("d".to_string());
+        config
+            .profiles
+            .insert("plain".to_string(), ProfileConfig::default());
 
-        let result = validate_config(&config);
-        assert!(result.is_ok());
+        let resolve = |config: &Config, names: &[&str]| {
+            let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+            resolve_profiles(config, &names).unwrap().network
+        };
+
+        assert_eq!(resolve(&config, &[]), None);
+        assert_eq!(resolve(&config, &["build"]), network("none"));
+        assert_eq!(resolve(&config, &["web"]), network("host"));
+        assert_eq!(resolve(&config, &["web", "build"]), network("none"));
+        assert_eq!(resolve(&config, &["build", "plain"]), network("none"));
+
+        config.runtime.network = network("bridge");
+        assert_eq!(resolve(&config, &["plain"]), network("bridge"));
+        assert_eq!(resolve(&config, &["build"]), network("none"));
     }
 
     #[test]
-    fn test_resolve_profiles_context_merged() {
+    fn test_resolve_profiles_resources_layer_per_limit() {
         let mut config = make_test_config();
-        config.context = "root-context".to_string();
-
+        config.runtime.resources = Figment::from(Toml::string(
+            "cpus = 2\nmemory = \"4g\"\nulimits = { nofile = \"1024:4096\" }",
+        ))
+        .extract()
+        .unwrap();
         config.profiles.insert(
-            "base".to_string(),
+            "heavy".to_string(),
             ProfileConfig {
-                extends: vec![],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: "base-context".to_string(),
+                resources: ResourcesConfig {
+                    memory: Some("16g".to_string()),
+                    pids_limit: Some(4096),
+                    ulimits: BTreeMap::from([
+                        ("nofile".to_string(), "65536".to_string()),
+                        ("nproc".to_string(), "2048".to_string()),
+                    ]),
+                    ..Default::default()
+                },
+                ..Default::default()
             },
         );
-
         config.profiles.insert(
-            "extended".to_string(),
+            "child".to_string(),
             ProfileConfig {
-                extends: vec!["base".to_string()],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: "extended-context".to_string(),
+                extends: vec!["heavy".to_string()],
+                resources: ResourcesConfig {
+                    cpus: Some(0.5),
+                    ..Default::default()
+                },
+                ..Default::default()
             },
         );
 
-        let resolved = resolve_profiles(&config, &["extended".to_string()]).unwrap();
+        let resources = resolve_profiles(&config, &["child".to_string()])
+            .unwrap()
+            .resources;
+        assert_eq!(
+            resources,
+            ResourcesConfig {
+                cpus: Some(0.5),
+                memory: Some("16g".to_string()),
+                memory_swap: None,
+                pids_limit: Some(4096),
+                ulimits: BTreeMap::from([
+                    ("nofile".to_string(), "65536".to_string()),
+                    ("nproc".to_string(), "2048".to_string()),
+                ]),
+            }
+        );
+        assert!(!is_empty_profile(&config.profiles["child"]));
+        assert!(validate_config(&config).errors.is_empty());
+
+        config.runtime.resources.memory_swap = Some("lots".to_string());
+        config
+            .profiles
+            .get_mut("child")
+            .unwrap()
+            .resources
+            .ulimits
+            .insert("core".to_string(), "0:x".to_string());
+        let errors: Vec<String> = validate_config(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "runtime.resources.memory_swap 'lots' is not a size like \"8g\" or \"-1\"",
+                "resources.ulimits.core '0:x' is not \"LIMIT\" or \"SOFT:HARD\"",
+            ]
+        );
 
-        // Context should be: root + base + extended
+        let problems = |resources: ResourcesConfig| resources.problems();
+        assert!(
+            problems(ResourcesConfig {
+                memory: Some("1.5GiB".to_string()),
+                memory_swap: Some("-1".to_string()),
+                ..Default::default()
+            })
+            .is_empty()
+        );
         assert_eq!(
-            resolved.context,
-            vec!["root-context", "base-context", "extended-context"]
+            problems(ResourcesConfig {
+                cpus: Some(0.0),
+                memory_swap: Some("8g".to_string()),
+                ..Default::default()
+            }),
+            [
+                "resources.cpus must be positive, got 0",
+                "resources.memory_swap needs resources.memory",
+            ]
         );
     }
 
     #[test]
-    fn test_resolve_profiles_context_from_root_only() {
-        let mut config = make_test_config();
-        config.context = "root-context".to_string();
+    fn test_profile_build_resolves_against_config_file() {
+        Jail::expect_with(|jail| {
+            jail.create_dir("home")?;
+            jail.create_dir("repo")?;
+            jail.create_file(
+                "home/.agent-box.toml",
+                r#"
+                workspace_dir = "/workspaces"
+                base_repo_dir = "/repos"
 
-        let resolved = resolve_profiles(&config, &[]).unwrap();
+                [profiles.base.build]
+                file = "/images/base/Dockerfile"
+                "#,
+            )?;
+            jail.create_file(
+                "repo/.agent-box.toml",
+                r#"
+                [profiles.rust]
+                extends = ["base"]
 
-        // Should have context from root config only
-        assert_eq!(resolved.context, vec!["root-context"]);
+                [profiles.rust.build]
+                file = "Containerfile"
+                args = { RUST = "1.90" }
+                "#,
+            )?;
+            jail.create_file("repo/Containerfile", "FROM rust:1.90\n")?;
+            let global = jail.directory().join("home/.agent-box.toml");
+            let repo = jail.directory().join("repo/.agent-box.toml");
+
+            let config = load_config_from(&global, Some(&repo)).unwrap();
+            let build = resolve_build(&config, &["rust".to_string()])
+                .unwrap()
+                .unwrap();
+            assert_eq!(build.file, jail.directory().join("repo/Containerfile"));
+            assert_eq!(build.context_dir(), jail.directory().join("repo"));
+            assert_eq!(
+                resolve_build(&config, &["base".to_string()])
+                    .unwrap()
+                    .unwrap()
+                    .file,
+                PathBuf::from("/images/base/Dockerfile")
+            );
+            assert_eq!(resolve_build(&config, &[]).unwrap(), None);
+            assert_eq!(
+                resolve_profiles(&config, &["rust".to_string()])
+                    .unwrap()
+                    .build,
+                Some(build.clone())
+            );
+
+            // Missing files only warn; the profile may be unused here
+            let warnings: Vec<String> = validate_config(&config)
+                .warnings
+                .into_iter()
+                .map(|w| w.message)
+                .collect();
+            assert_eq!(
+                warnings,
+                ["build.file /images/base/Dockerfile does not exist"]
+            );
+
+            // The tag changes with any input, and only then
+            let tag = build.image_tag().unwrap();
+            assert!(tag.starts_with("agent-box-build:"), "{}", tag);
+            assert_eq!(tag.len(), "agent-box-build:".len() + 16);
+            assert_eq!(build.image_tag_for(b"FROM rust:1.90\n"), tag);
+            assert_ne!(build.image_tag_for(b"FROM rust:1.91\n"), tag);
+            let mut other = build.clone();
+            other.args.insert("RUST".to_string(), "1.91".to_string());
+            assert_ne!(other.image_tag().unwrap(), tag);
+            other = build.clone();
+            other.target = Some("dev".to_string());
+            assert_ne!(other.image_tag().unwrap(), tag);
+
+            Ok(())
+        });
     }
 
     #[test]
-    fn test_resolve_profiles_context_from_profile_only() {
+    fn test_profile_secrets_parse_and_validate() {
+        let profile: ProfileConfig = Figment::from(Toml::string(
+            r#"
+            [secrets]
+            GH_TOKEN = { command = "pass show github/token" }
+            GCP = { file = "~/.config/gcloud/key.json", path = "/run/secrets/gcp.json" }
+            BOTH = { env = "X", file = "/x" }
+            NONE = { path = "run/secrets/none" }
+            "#,
+        ))
+        .extract()
+        .unwrap();
+        assert_eq!(
+            profile.secrets["GH_TOKEN"].command,
+            Some(vec![
+                "pass".to_string(),
+                "show".to_string(),
+                "github/token".to_string()
+            ])
+        );
+        assert!(!is_empty_profile(&profile));
+
         let mut config = make_test_config();
+        config.profiles.insert("p".to_string(), profile);
+        let errors: Vec<String> = validate_config(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "secrets.BOTH must set exactly one of command, file, or env",
+                "secrets.NONE must set exactly one of command, file, or env",
+                "secrets.NONE.path 'run/secrets/none' must be an absolute container path",
+            ]
+        );
+    }
 
+    #[test]
+    fn test_profile_network_table_allow_hosts() {
+        let profile =
+            |toml: &str| -> ProfileConfig { Figment::from(Toml::string(toml)).extract().unwrap() };
+        let mut config = make_test_config();
+        config
+            .profiles
+            .insert("plain".to_string(), profile(r#"network = "host""#));
         config.profiles.insert(
-            "with_context".to_string(),
-            ProfileConfig {
-                extends: vec![],
-                mounts: MountsConfig::default(),
-                env: vec![],
-                env_passthrough: vec![],
-                ports: vec![],
-                hosts: vec![],
-                context: "profile-context".to_string(),
-            },
+            "gh".to_string(),
+            profile(
+                r#"
+                [network]
+                allow_hosts = ["github.com", "*.githubusercontent.com"]
+                "#,
+            ),
+        );
+        config.profiles.insert(
+            "npm".to_string(),
+            profile(
+                r#"
+                extends = ["gh"]
+                network = { mode = "bridge", allow_hosts = ["registry.npmjs.org", "GitHub.com"] }
+                "#,
+            ),
+        );
+        assert_eq!(
+            config.profiles["plain"].network,
+            Some(NetworkConfig {
+                mode: Some("host".to_string()),
+                allow_hosts: Vec::new(),
+            })
         );
 
-        let resolved = resolve_profiles(&config, &["with_context".to_string()]).unwrap();
+        let resolved = resolve_profiles(&config, &["npm".to_string()]).unwrap();
+        assert_eq!(resolved.network.as_deref(), Some("bridge"));
+        assert_eq!(
+            resolved.allow_hosts,
+            [
+                "github.com",
+                "*.githubusercontent.com",
+                "registry.npmjs.org"
+            ]
+        );
+        // A table without a mode keeps the earlier layer's
+        let resolved = resolve_profiles(&config, &["plain".to_string(), "gh".to_string()]).unwrap();
+        assert_eq!(resolved.network.as_deref(), Some("host"));
+        assert_eq!(resolved.allow_hosts.len(), 2);
+        assert!(validate_config(&config).errors.is_empty());
 
-        // Should have context from profile only
-        assert_eq!(resolved.context, vec!["profile-context"]);
+        config.profiles.insert(
+            "bad".to_string(),
+            profile(r#"network = { mode = "none", allow_hosts = ["https://x.org", "*"] }"#),
+        );
+        let errors: Vec<String> = validate_config(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "network.allow_hosts needs mode \"bridge\" (or no mode), not 'none'",
+                "network.allow_hosts entry 'https://x.org' must be a host name, optionally starting with '*.'",
+                "network.allow_hosts entry '*' must be a host name, optionally starting with '*.'",
+            ]
+        );
     }
 
     #[test]
-    fn test_context_parsing_from_toml() {
-        Jail::expect_with(|jail| {
-            jail.create_file(
-                "config.toml",
+    fn test_runtime_security() {
+        let runtime =
+            |toml: &str| -> RuntimeConfig { Figment::from(Toml::string(toml)).extract().unwrap() };
+        // Hardened unless the config says otherwise
+        assert_eq!(runtime("").security, SecurityConfig::default());
+        let security = runtime(
+            r#"
+            [security]
+            cap_add = ["SYS_PTRACE"]
+            no_new_privileges = false
+            "#,
+        )
+        .security;
+        assert_eq!(security.cap_drop, ["ALL"]);
+        assert_eq!(security.cap_add, ["SYS_PTRACE"]);
+        assert!(!security.no_new_privileges);
+        assert!(security.problems().is_empty());
+
+        let mut config = make_test_config();
+        config.runtime.security.cap_add = vec!["CAP NET".to_string()];
+        config.runtime.security.seccomp_profile = Some("seccomp.json".to_string());
+        let errors: Vec<String> = validate_config(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "runtime.security.cap_add entry 'CAP NET' must be a capability name, e.g. NET_RAW or ALL",
+                "runtime.security.seccomp_profile 'seccomp.json' must be an absolute path or \"unconfined\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profile_gpu() {
+        let profile =
+            |toml: &str| -> ProfileConfig { Figment::from(Toml::string(toml)).extract().unwrap() };
+        let mut config = make_test_config();
+        config.runtime.gpu.devices = Some(GpuSetting::Devices("device=0".to_string()));
+        config
+            .profiles
+            .insert("ml".to_string(), profile("gpu = true"));
+        config
+            .profiles
+            .insert("cpu".to_string(), profile("gpu = false"));
+        config
+            .profiles
+            .insert("pair".to_string(), profile(r#"gpu = "device=1,2""#));
+
+        let gpus = |profiles: &[&str]| {
+            let names: Vec<String> = profiles.iter().map(|p| p.to_string()).collect();
+            resolve_profiles(&config, &names)
+                .unwrap()
+                .gpu
+                .map(|gpu| gpu.devices().unwrap())
+        };
+        assert_eq!(gpus(&[]), Some(vec!["0".to_string()]));
+        assert_eq!(gpus(&["ml"]), Some(vec!["all".to_string()]));
+        assert_eq!(gpus(&["ml", "cpu"]), Some(vec![]));
+        assert_eq!(
+            gpus(&["pair"]),
+            Some(vec!["1".to_string(), "2".to_string()])
+        );
+        assert!(validate_config(&config).errors.is_empty());
+
+        config
+            .profiles
+            .insert("bad".to_string(), profile(r#"gpu = "gpu0""#));
+        config.runtime.gpu.cdi_kind = Some("nvidia".to_string());
+        let errors: Vec<String> = validate_config(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "runtime.gpu.cdi_kind 'nvidia' must be VENDOR/CLASS, e.g. nvidia.com/gpu",
+                "gpu 'gpu0' must be true, false, \"all\", or \"device=ID[,ID...]\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profile_tmpfs_mounts() {
+        let profile =
+            |toml: &str| -> ProfileConfig { Figment::from(Toml::string(toml)).extract().unwrap() };
+        let mut config = make_test_config();
+        config.runtime.mounts.tmpfs.paths = vec!["/tmp".to_string()];
+        config.profiles.insert(
+            "scratch".to_string(),
+            profile(
                 r#"
-                workspace_dir = "/workspaces"
-                base_repo_dir = "/repos"
-                context = "root-context"
+                [mounts.tmpfs]
+                paths = ["~/.cache", "/tmp"]
+                "#,
+            ),
+        );
+        config.profiles.insert(
+            "build".to_string(),
+            profile(
+                r#"
+                extends = ["scratch"]
+                [mounts.tmpfs]
+                paths = ["/work/target"]
+                "#,
+            ),
+        );
 
-                [profiles.base]
-                context = "base-context"
-                env = ["BASE=1"]
+        let resolved = resolve_profiles(&config, &["build".to_string()]).unwrap();
+        assert_eq!(resolved.tmpfs, ["/tmp", "~/.cache", "/work/target"]);
+        // tmpfs paths have no host side, so they aren't bind mounts
+        assert!(resolved.mounts.is_empty());
+        assert!(validate_config(&config).errors.is_empty());
 
-                [profiles.dev]
-                extends = ["base"]
-                context = "dev-context"
-                env = ["DEV=1"]
+        config.profiles.insert(
+            "bad".to_string(),
+            profile(
+                r#"
+                [mounts.tmpfs]
+                paths = ["cache", "/tmp:size=1g"]
+                "#,
+            ),
+        );
+        let errors: Vec<String> = validate_config(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "mounts.tmpfs path 'cache' must be absolute or start with '~/', without ':' or ','",
+                "mounts.tmpfs path '/tmp:size=1g' must be absolute or start with '~/', without ':' or ','",
+            ]
+        );
+    }
 
-                [runtime]
-                image = "test:latest"
+    #[test]
+    fn test_volume_spec_and_profile_volumes() {
+        assert_eq!(
+            "cargo-cache:~/.cargo/registry".parse::<VolumeSpec>(),
+            Ok(VolumeSpec {
+                name: "cargo-cache".to_string(),
+                path: "~/.cargo/registry".to_string(),
+                mode: MountMode::Rw,
+            })
+        );
+        assert_eq!(
+            "models:/models:ro".parse::<VolumeSpec>().unwrap().mode,
+            MountMode::Ro
+        );
+        assert!("nopath".parse::<VolumeSpec>().is_err());
+        assert!("-x:/a".parse::<VolumeSpec>().is_err());
+        assert!("x:/a:O".parse::<VolumeSpec>().is_err());
+
+        let profile =
+            |toml: &str| -> ProfileConfig { Figment::from(Toml::string(toml)).extract().unwrap() };
+        let mut config = make_test_config();
+        config.runtime.mounts.volumes = vec!["cargo-cache:~/.cargo/registry".to_string()];
+        config.profiles.insert(
+            "rust".to_string(),
+            profile(
+                r#"
+                [mounts]
+                volumes = ["cargo-cache:~/.cargo/registry", "target:/work/target"]
                 "#,
-            )?;
+            ),
+        );
+        let resolved = resolve_profiles(&config, &["rust".to_string()]).unwrap();
+        assert_eq!(
+            resolved.volumes,
+            ["cargo-cache:~/.cargo/registry", "target:/work/target"]
+        );
+        assert!(validate_config(&config).errors.is_empty());
 
-            let config_path = jail.directory().join("config.toml");
-            let figment = build_figment(&config_path, None);
-            let config: Config = figment.extract()?;
+        config.runtime.mounts.volumes.push("cache".to_string());
+        let errors: Vec<String> = validate_config(&config)
+            .errors
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(
+            errors,
+            ["runtime.mounts.volume 'cache' must be NAME:PATH or NAME:PATH:MODE"]
+        );
+    }
 
-            // Check root-level context
-            assert_eq!(config.context, "root-context");
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90s"), Ok(90));
+        assert_eq!(parse_age("30m"), Ok(1800));
+        assert_eq!(parse_age("12h"), Ok(43200));
+        assert_eq!(parse_age("7d"), Ok(604800));
+        assert_eq!(parse_age("2w"), Ok(1209600));
+        for bad in ["", "7", "d", "-1d", "1.5h", "7y"] {
+            assert!(parse_age(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(parse_age(&GcConfig::default().older_than), Ok(604800));
+    }
 
-            // Check profile contexts
-            let base = config.profiles.get("base").unwrap();
-            assert_eq!(base.context, "base-context");
+    #[test]
+    fn test_mount_conflict_parses_from_toml() {
+        let runtime: RuntimeConfig = Figment::from(Toml::string("mount_conflict = \"strictest\""))
+            .extract()
+            .unwrap();
+        assert_eq!(runtime.mount_conflict, MountConflict::Strictest);
+        assert_eq!(
+            RuntimeConfig::default().mount_conflict,
+            MountConflict::First
+        );
+    }
 
-            let dev = config.profiles.get("dev").unwrap();
-            assert_eq!(dev.extends, vec!["base"]);
-            assert_eq!(dev.context, "dev-context");
+    #[test]
+    fn test_parse_devcontainer() {
+        let text = r#"{
+            // Image and a comment with "quotes"
+            "image": "mcr.microsoft.com/devcontainers/rust:1", /* inline */
+            "mounts": [
+                "source=${localEnv:HOME}/.cargo,target=/usr/local/cargo,type=bind,readonly",
+                { "source": "${localWorkspaceFolder}/data", "target": "/data", "type": "bind" },
+                "source=cache,target=/cache,type=volume",
+            ],
+            "containerEnv": { "RUST_LOG": "debug", "URL": "http://example.com/a//b" },
+            "remoteEnv": { "WS": "${containerWorkspaceFolderBasename}", "GONE": null },
+            "forwardPorts": [3000, "8080", "db:5432"],
+            "appPort": "9000:9001",
+            "postCreateCommand": { "deps": ["cargo", "fetch"], "tools": "rustup component add clippy" },
+            "features": {},
+        }"#;
+        let paths = DevcontainerPaths {
+            local_workspace: Path::new("/home/me/ws/repo/main"),
+            container_workspace: Path::new("/work/main"),
+        };
+        let lookup = |name: &str| (name == "HOME").then(|| "/home/me".to_string());
 
-            // Test resolution
-            let resolved = resolve_profiles(&config, &["dev".to_string()]).unwrap();
-            assert_eq!(
-                resolved.context,
-                vec!["root-context", "base-context", "dev-context"]
-            );
+        let devcontainer = parse_devcontainer(text, &paths, lookup).unwrap();
+        assert_eq!(
+            devcontainer.image.as_deref(),
+            Some("mcr.microsoft.com/devcontainers/rust:1")
+        );
+        let mounts: Vec<_> = devcontainer
+            .profile
+            .mounts
+            .iter()
+            .map(|m| (m.spec.as_str(), m.mode))
+            .collect();
+        assert_eq!(
+            mounts,
+            [
+                ("/home/me/.cargo:/usr/local/cargo", MountMode::Ro),
+                ("/home/me/ws/repo/main/data:/data", MountMode::Rw),
+            ]
+        );
+        assert_eq!(
+            devcontainer.profile.env,
+            ["RUST_LOG=debug", "URL=http://example.com/a//b", "WS=main"]
+        );
+        assert_eq!(
+            devcontainer.profile.ports,
+            ["3000:3000", "8080:8080", "9000:9001"]
+        );
+        assert_eq!(
+            devcontainer.post_create_command.as_deref(),
+            Some("cargo fetch && rustup component add clippy")
+        );
+        assert_eq!(
+            devcontainer.warnings.len(),
+            3,
+            "{:?}",
+            devcontainer.warnings
+        );
+        assert!(devcontainer.warnings[0].contains("'features'"));
+        assert!(devcontainer.warnings[1].contains("volume mount"));
+        assert!(devcontainer.warnings[2].contains("db:5432"));
 
-            Ok(())
-        });
+        let err = parse_devcontainer(r#"{"image": "${devcontainerId}"}"#, &paths, |_| None)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Unsupported variable '${devcontainerId}'"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            parse_devcontainer(r#"{"image": "img:${localEnv:TAG:latest}"}"#, &paths, |_| {
+                None
+            })
+            .unwrap()
+            .image
+            .as_deref(),
+            Some("img:latest")
+        );
     }
 }
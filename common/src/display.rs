@@ -1,7 +1,10 @@
 use eyre::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::config::Config;
-use crate::path::RepoIdentifier;
+use crate::path::{GitWorktreeInfo, JjWorkspaceInfo, RepoIdentifier};
 
 // ANSI color codes
 const RESET: &str = "\x1b[0m";
@@ -12,22 +15,58 @@ const GREEN: &str = "\x1b[32m";
 const YELLOW: &str = "\x1b[33m";
 const MAGENTA: &str = "\x1b[35m";
 
-/// Show repository information and list workspaces
-pub fn info(config: &Config) -> Result<()> {
-    let cwd = std::env::current_dir()?;
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Make `info`, `list`, `dbg resolve`, and `dbg validate` print JSON instead of text
+pub fn set_json_output(value: bool) {
+    JSON_OUTPUT.store(value, Ordering::Relaxed);
+}
+
+/// Returns true if `--format json` was given
+pub fn is_json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
 
-    let repo = gix::discover(&cwd).ok();
-    let repo_path = repo
-        .as_ref()
+/// The repo of the git repository containing the current directory, if any
+fn current_repo(config: &Config) -> Result<Option<RepoIdentifier>> {
+    let cwd = std::env::current_dir()?;
+    let repo_path = gix::discover(&cwd)
+        .ok()
         .and_then(|r| r.workdir().map(|p| p.to_path_buf()));
+    repo_path
+        .map(|path| RepoIdentifier::from_repo_path(config, &path))
+        .transpose()
+}
+
+/// What `ab info` shows, as `--format json` prints it
+#[derive(Debug, Serialize)]
+pub struct RepoInfo {
+    /// Repo path relative to `base_repo_dir`
+    pub repo: PathBuf,
+    /// Source repo path
+    pub path: PathBuf,
+    pub git_worktrees: Vec<GitWorktreeInfo>,
+    pub jj_workspaces: Vec<JjWorkspaceInfo>,
+}
+
+/// Repository information and workspaces of the current directory's repo
+pub fn repo_info(config: &Config) -> Result<RepoInfo> {
+    let repo_id = current_repo(config)?.ok_or_else(|| eyre::eyre!("Not in a git repository"))?;
+    Ok(RepoInfo {
+        repo: repo_id.relative_path().to_path_buf(),
+        path: repo_id.source_path(config),
+        git_worktrees: repo_id.git_worktrees(config)?,
+        jj_workspaces: repo_id.jj_workspaces(config)?,
+    })
+}
 
-    let Some(repo_path) = repo_path else {
+/// Show repository information and list workspaces
+pub fn info(config: &Config) -> Result<()> {
+    let Some(repo_id) = current_repo(config)? else {
         eprintln!("Not in a git repository");
         return Ok(());
     };
 
-    let repo_id = RepoIdentifier::from_repo_path(config, &repo_path)?;
-
     // Git worktrees
     println!("{BOLD}Git Worktrees:{RESET}");
     match repo_id.git_worktrees(config) {
@@ -1,6 +1,8 @@
 pub mod config;
 pub mod display;
+pub mod migrate;
 pub mod path;
 pub mod portal;
 pub mod portal_client;
+pub mod prompt;
 pub mod repo;
@@ -0,0 +1,270 @@
+use eyre::{Result, WrapErr, bail};
+use std::collections::HashSet;
+use toml_edit::{DocumentMut, Item, Value};
+
+/// Rewrite a legacy config's `[docker]` table into the current `[runtime]` shape.
+///
+/// `docker.image`, `docker.entrypoint`, `docker.mounts` and any other keys move to
+/// `runtime` unchanged (an array entrypoint is joined into a shell string), and
+/// `runtime.backend = "docker"` is set unless already present. Comments and
+/// formatting elsewhere in the file are preserved.
+///
+/// Returns `None` if there is no `[docker]` table.
+pub fn migrate_legacy_docker_config(input: &str) -> Result<Option<String>> {
+    let mut doc: DocumentMut = input.parse().wrap_err("Failed to parse config as TOML")?;
+
+    if !doc.contains_key("docker") {
+        return Ok(None);
+    }
+    if doc.contains_key("runtime") {
+        bail!(
+            "Config has both [docker] and [runtime] tables; merge [docker] into [runtime] by hand"
+        );
+    }
+
+    let Some(Item::Table(mut runtime)) = doc.remove("docker") else {
+        bail!("[docker] must be a table");
+    };
+
+    if let Some(entrypoint) = runtime.get_mut("entrypoint")
+        && let Some(words) = entrypoint.as_array()
+    {
+        let words: Vec<&str> = words.iter().filter_map(Value::as_str).collect();
+        *entrypoint = toml_edit::value(shell_words::join(words));
+    }
+
+    if !runtime.contains_key("backend") {
+        runtime.insert("backend", toml_edit::value("docker"));
+        // Keep backend first, as in the documented [runtime] examples
+        runtime.sort_values_by(|a, _, b, _| (b.get() == "backend").cmp(&(a.get() == "backend")));
+    }
+
+    doc.insert("runtime", Item::Table(runtime));
+
+    Ok(Some(doc.to_string()))
+}
+
+/// Fix profile problems reported by `ab dbg validate` in one config layer.
+///
+/// Removes the profiles in `remove` that this layer defines, and strips `extends`
+/// entries naming profiles outside `known` (which should already exclude `remove`).
+/// Only items present in `input` are touched, so each layer fixes what it defined.
+///
+/// Returns `None` if the layer needs no changes.
+pub fn fix_profiles(
+    input: &str,
+    remove: &HashSet<String>,
+    known: &HashSet<String>,
+) -> Result<Option<String>> {
+    let mut doc: DocumentMut = input.parse().wrap_err("Failed to parse config as TOML")?;
+    let mut changed = false;
+
+    let Some(profiles) = doc.get_mut("profiles").and_then(Item::as_table_like_mut) else {
+        return Ok(None);
+    };
+
+    for name in remove {
+        changed |= profiles.remove(name).is_some();
+    }
+
+    for (_, profile) in profiles.iter_mut() {
+        let Some(profile) = profile.as_table_like_mut() else {
+            continue;
+        };
+        let Some(extends) = profile.get_mut("extends").and_then(Item::as_array_mut) else {
+            continue;
+        };
+        let before = extends.len();
+        extends.retain(|parent| parent.as_str().is_some_and(|p| known.contains(p)));
+        if extends.len() == before {
+            continue;
+        }
+        changed = true;
+        if extends.is_empty() {
+            profile.remove("extends");
+        } else {
+            extends.fmt();
+        }
+    }
+
+    Ok(changed.then(|| doc.to_string()))
+}
+
+/// Line diff of `old` -> `new`, showing only changed lines prefixed with `-`/`+`
+pub fn line_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // Longest common subsequence table over suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push_str(&format!("-{}\n", old[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+{}\n", new[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use figment::providers::{Format, Toml};
+
+    const LEGACY: &str = r#"workspace_dir = "~/workspaces"
+base_repo_dir = "~/repos"
+
+# Container settings
+[docker]
+image = "agent-box:latest"
+entrypoint = ["/bin/bash", "-c", "echo hi there"]
+
+[docker.mounts.ro]
+absolute = ["/nix/store"]
+
+[docker.mounts.rw]
+home_relative = ["~/.config/git"]
+"#;
+
+    #[test]
+    fn test_migrate_maps_docker_to_runtime() {
+        let migrated = migrate_legacy_docker_config(LEGACY).unwrap().unwrap();
+
+        assert!(!migrated.contains("[docker"));
+        assert!(migrated.contains("# Container settings\n[runtime]\nbackend = \"docker\"\n"));
+        assert!(migrated.contains("[runtime.mounts.ro]"));
+
+        let config: Config = figment::Figment::new()
+            .merge(Toml::string(&migrated))
+            .extract()
+            .unwrap();
+        assert_eq!(config.runtime.backend, "docker");
+        assert_eq!(config.runtime.image, "agent-box:latest");
+        assert_eq!(
+            config.runtime.entrypoint,
+            Some(vec![
+                "/bin/bash".to_string(),
+                "-c".to_string(),
+                "echo hi there".to_string()
+            ])
+        );
+        assert_eq!(config.runtime.mounts.ro.absolute, ["/nix/store"]);
+        assert_eq!(config.runtime.mounts.rw.home_relative, ["~/.config/git"]);
+    }
+
+    #[test]
+    fn test_migrate_keeps_string_entrypoint_and_explicit_backend() {
+        let input =
+            "[docker]\nimage = \"x\"\nbackend = \"podman\"\nentrypoint = \"claude --yolo\"\n";
+        let migrated = migrate_legacy_docker_config(input).unwrap().unwrap();
+        assert_eq!(
+            migrated,
+            "[runtime]\nimage = \"x\"\nbackend = \"podman\"\nentrypoint = \"claude --yolo\"\n"
+        );
+    }
+
+    #[test]
+    fn test_migrate_without_docker_table_is_noop() {
+        let input = "[runtime]\nimage = \"x\"\n";
+        assert_eq!(migrate_legacy_docker_config(input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_rejects_docker_and_runtime_together() {
+        let input = "[docker]\nimage = \"a\"\n\n[runtime]\nimage = \"b\"\n";
+        let err = migrate_legacy_docker_config(input).unwrap_err().to_string();
+        assert!(err.contains("both [docker] and [runtime]"));
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    const PROFILES: &str = r#"workspace_dir = "/workspaces"
+base_repo_dir = "/repos"
+default_profile = "base"
+
+[profiles.base]
+env = ["A=1"]
+
+# Nothing here yet
+[profiles.empty]
+
+[profiles.rust]
+extends = ["base", "missing", "empty"]
+
+[profiles.rust.mounts.ro]
+absolute = ["/opt/rust"]
+"#;
+
+    #[test]
+    fn test_fix_profiles_removes_empty_and_dangling_extends() {
+        let fixed = fix_profiles(PROFILES, &names(&["empty"]), &names(&["base", "rust"]))
+            .unwrap()
+            .unwrap();
+
+        assert!(!fixed.contains("[profiles.empty]"));
+        assert!(fixed.contains("extends = [\"base\"]\n"));
+        assert!(fixed.contains("[profiles.rust.mounts.ro]"));
+
+        let config: Config = figment::Figment::new()
+            .merge(Toml::string(&fixed))
+            .extract()
+            .unwrap();
+        assert_eq!(config.profiles.len(), 2);
+        assert_eq!(config.profiles["rust"].extends, ["base"]);
+        let result = crate::config::validate_config(&config);
+        assert!(result.is_ok() && !result.has_warnings(), "{:?}", result);
+    }
+
+    #[test]
+    fn test_fix_profiles_only_touches_items_in_this_layer() {
+        // A repo layer extending a profile defined in the global layer
+        let input = "[profiles.local]\nextends = [\"base\"]\nenv = [\"B=2\"]\n";
+        let fixed = fix_profiles(input, &names(&["empty"]), &names(&["base", "local"])).unwrap();
+        assert_eq!(fixed, None);
+    }
+
+    #[test]
+    fn test_fix_profiles_without_profiles_is_noop() {
+        let input = "[runtime]\nimage = \"x\"\n";
+        assert_eq!(
+            fix_profiles(input, &names(&["a"]), &names(&[])).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fix_profiles_drops_extends_left_empty() {
+        let input = "[profiles.rust]\nextends = [\"missing\"]\nenv = [\"A=1\"]\n";
+        let fixed = fix_profiles(input, &names(&[]), &names(&["rust"])).unwrap();
+        assert_eq!(fixed.as_deref(), Some("[profiles.rust]\nenv = [\"A=1\"]\n"));
+    }
+
+    #[test]
+    fn test_line_diff_shows_only_changed_lines() {
+        let old = "a\nb\nc\nd\n";
+        let new = "a\nc\nD\n";
+        assert_eq!(line_diff(old, new), "-b\n-d\n+D\n");
+        assert_eq!(line_diff(old, old), "");
+    }
+}
@@ -1,19 +1,31 @@
 use eyre::{Result, eyre};
 use jj_lib::object_id::ObjectId;
 use jj_lib::repo::Repo;
+use serde::Serialize;
 use std::path::{Path, PathBuf};
 
 use crate::config::Config;
 
 /// Type of workspace (git or jj)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum WorkspaceType {
     Git,
     Jj,
 }
 
+impl WorkspaceType {
+    /// Lowercase name, also the workspace_dir subdirectory: `git` or `jj`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WorkspaceType::Git => "git",
+            WorkspaceType::Jj => "jj",
+        }
+    }
+}
+
 /// Information about a git worktree
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct GitWorktreeInfo {
     pub path: PathBuf,
     pub id: Option<String>,
@@ -22,7 +34,7 @@ pub struct GitWorktreeInfo {
 }
 
 /// Information about a JJ workspace
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct JjWorkspaceInfo {
     pub name: String,
     pub commit_id: String,
@@ -38,6 +50,14 @@ pub struct RepoIdentifier {
     pub relative_path: PathBuf,
 }
 
+/// True if `path` has a `.git` or `.jj` entry, or all of the (non-empty)
+/// `bare_markers` relative paths
+fn is_repo_dir(path: &Path, bare_markers: &[String]) -> bool {
+    path.join(".git").exists()
+        || path.join(".jj").exists()
+        || (!bare_markers.is_empty() && bare_markers.iter().all(|m| path.join(m).exists()))
+}
+
 impl RepoIdentifier {
     /// Create from a path within base_repo_dir
     pub fn from_repo_path(config: &Config, full_path: &Path) -> Result<Self> {
@@ -102,14 +122,17 @@ impl RepoIdentifier {
 
     /// Helper function to discover repositories in a directory based on a filter predicate
     /// Stops descending into directories that are already repos.
-    fn discover_repos_in_dir<F>(base_dir: &Path, is_repo: F) -> Result<Vec<Self>>
+    /// Each repo is passed to `on_repo` as it is found; returning false stops the walk.
+    fn discover_repos_in_dir<F>(
+        base_dir: &Path,
+        is_repo: F,
+        on_repo: &mut dyn FnMut(Self) -> bool,
+    ) -> Result<()>
     where
         F: Fn(&Path) -> bool + Copy,
     {
-        let mut repos = Vec::new();
-
         if !base_dir.exists() {
-            return Ok(repos);
+            return Ok(());
         }
 
         // Walk the directory to find all repos matching the predicate
@@ -151,20 +174,102 @@ impl RepoIdentifier {
                 continue;
             };
 
-            repos.push(Self {
+            if !on_repo(Self {
                 relative_path: relative_path.to_path_buf(),
-            });
+            }) {
+                break;
+            }
         }
 
-        Ok(repos)
+        Ok(())
+    }
+
+    /// Same results as [`Self::discover_repos_in_dir`] with fewer `stat` calls, for
+    /// network filesystems (`runtime.fast_discovery`).
+    ///
+    /// Entry types come from readdir (`d_type`) where the OS provides them; only
+    /// symlinks are stat'ed. Each directory is checked with `is_repo` once, and a
+    /// repo's children are never listed, instead of re-checking each child's parent.
+    fn discover_repos_in_dir_fast<F>(
+        base_dir: &Path,
+        is_repo: F,
+        on_repo: &mut dyn FnMut(Self) -> bool,
+    ) -> Result<()>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        if !base_dir.exists() {
+            return Ok(());
+        }
+
+        let mut walker = walkdir::WalkDir::new(base_dir)
+            .follow_links(false)
+            .into_iter();
+
+        while let Some(entry) = walker.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let path = entry.path();
+            let is_base = entry.depth() == 0;
+
+            // Skip .git and .jj directories
+            if !is_base && (entry.file_name() == ".git" || entry.file_name() == ".jj") {
+                if entry.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+
+            let is_dir = if entry.file_type().is_symlink() {
+                path.is_dir()
+            } else {
+                entry.file_type().is_dir()
+            };
+            if !is_dir || !is_repo(path) {
+                continue;
+            }
+
+            // Don't descend into repos (the base dir itself is still walked)
+            if !is_base && entry.file_type().is_dir() {
+                walker.skip_current_dir();
+            }
+
+            let Ok(relative_path) = path.strip_prefix(base_dir) else {
+                continue;
+            };
+            if !on_repo(Self {
+                relative_path: relative_path.to_path_buf(),
+            }) {
+                break;
+            }
+        }
+
+        Ok(())
     }
 
     /// Discover all repositories in the base_repo_dir.
-    /// Returns a vector of RepoIdentifiers for all repositories found (with .git or .jj).
+    /// Returns a vector of RepoIdentifiers for all repositories found (with .git or .jj,
+    /// or every `runtime.bare_repo_markers` path).
     pub fn discover_repo_ids(config: &Config) -> Result<Vec<Self>> {
-        Self::discover_repos_in_dir(&config.base_repo_dir, |path| {
-            path.join(".git").exists() || path.join(".jj").exists()
-        })
+        let mut repos = Vec::new();
+        Self::for_each_repo_id(config, |repo| {
+            repos.push(repo);
+            true
+        })?;
+        Ok(repos)
+    }
+
+    /// [`Self::discover_repo_ids`], passing each repo to `on_repo` in walk order as
+    /// soon as it is found. Returning false from `on_repo` stops the walk.
+    pub fn for_each_repo_id(config: &Config, mut on_repo: impl FnMut(Self) -> bool) -> Result<()> {
+        let markers = &config.runtime.bare_repo_markers;
+        let is_repo = |path: &Path| is_repo_dir(path, markers);
+        if config.runtime.fast_discovery {
+            Self::discover_repos_in_dir_fast(&config.base_repo_dir, is_repo, &mut on_repo)
+        } else {
+            Self::discover_repos_in_dir(&config.base_repo_dir, is_repo, &mut on_repo)
+        }
     }
 
     /// Get all JJ workspaces for this repository using JJ's workspace tracking
@@ -311,7 +416,7 @@ mod tests {
     use super::*;
 
     fn make_test_config() -> Config {
-        use crate::config::RuntimeConfig;
+        use crate::config::{MountConflict, OnMissingWorkspace, RuntimeConfig};
         use std::collections::HashMap;
 
         Config {
@@ -325,14 +430,40 @@ mod tests {
                 entrypoint: None,
                 mounts: Default::default(),
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
                 env: Default::default(),
                 env_passthrough: vec![],
                 ports: Default::default(),
                 hosts: Default::default(),
                 dns: Default::default(),
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
             portal: crate::portal::PortalConfig::default(),
         }
     }
@@ -369,7 +500,7 @@ mod tests {
 
     #[test]
     fn test_find_matching_exact_match() {
-        use crate::config::RuntimeConfig;
+        use crate::config::{MountConflict, OnMissingWorkspace, RuntimeConfig};
 
         let temp_dir = std::env::temp_dir().join(format!("ab-test-locate-{}", std::process::id()));
         let base_repo_dir = temp_dir.join("repos");
@@ -389,14 +520,40 @@ mod tests {
                 entrypoint: None,
                 mounts: Default::default(),
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
                 env: Default::default(),
                 env_passthrough: vec![],
                 ports: Default::default(),
                 hosts: Default::default(),
                 dns: Default::default(),
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
             portal: crate::portal::PortalConfig::default(),
         };
 
@@ -411,7 +568,7 @@ mod tests {
 
     #[test]
     fn test_find_matching_partial_match() {
-        use crate::config::RuntimeConfig;
+        use crate::config::{MountConflict, OnMissingWorkspace, RuntimeConfig};
 
         let temp_dir =
             std::env::temp_dir().join(format!("ab-test-locate-partial-{}", std::process::id()));
@@ -432,14 +589,40 @@ mod tests {
                 entrypoint: None,
                 mounts: Default::default(),
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
                 env: Default::default(),
                 env_passthrough: vec![],
                 ports: Default::default(),
                 hosts: Default::default(),
                 dns: Default::default(),
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
             portal: crate::portal::PortalConfig::default(),
         };
 
@@ -454,7 +637,7 @@ mod tests {
 
     #[test]
     fn test_find_matching_no_match() {
-        use crate::config::RuntimeConfig;
+        use crate::config::{MountConflict, OnMissingWorkspace, RuntimeConfig};
 
         let temp_dir =
             std::env::temp_dir().join(format!("ab-test-locate-nomatch-{}", std::process::id()));
@@ -475,14 +658,40 @@ mod tests {
                 entrypoint: None,
                 mounts: Default::default(),
                 skip_mounts: vec![],
+                container_root_prefix_absolute: false,
+                kubernetes: Default::default(),
+                container_root_prefix: None,
+                large_mount_warn_entries: 0,
+                mount_remap: vec![],
+                workspace_mode: None,
+                bare_repo_markers: vec![],
+                mount_syntax: Default::default(),
+                max_mounts: 0,
+                fast_discovery: false,
+                init_script: None,
+                secret_env: Default::default(),
+                on_missing_workspace: OnMissingWorkspace::Error,
+                command: None,
+                network: None,
+                resources: Default::default(),
+                mount_conflict: MountConflict::First,
+                group_add: vec![],
+                working_dir: None,
+                spawn_retries: 0,
+                redact_env: vec![],
+                verify_image: false,
                 env: Default::default(),
                 env_passthrough: vec![],
                 ports: Default::default(),
                 hosts: Default::default(),
                 dns: Default::default(),
+                gpu: Default::default(),
+                security: Default::default(),
             },
             context: String::new(),
             context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
             portal: crate::portal::PortalConfig::default(),
         };
 
@@ -502,4 +711,130 @@ mod tests {
         let matches = RepoIdentifier::find_matching(&config, "anything").unwrap();
         assert!(matches.is_empty());
     }
+
+    #[test]
+    fn test_fast_discovery_matches_and_checks_fewer_paths() {
+        use std::cell::Cell;
+
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab_fast_discovery_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let mkdir = |rel: &str| std::fs::create_dir_all(temp_dir.join(rel)).unwrap();
+        let touch = |rel: &str| std::fs::write(temp_dir.join(rel), "").unwrap();
+
+        mkdir("fr/agent-box/.git");
+        mkdir("fr/agent-box/nested/inner/.git"); // inside a repo, not discovered
+        mkdir("work/tool/.jj");
+        mkdir("work/linked");
+        touch("work/linked/.git"); // worktree-style .git file
+        mkdir("plain/empty");
+        touch("notes.txt");
+        std::os::unix::fs::symlink(temp_dir.join("work/tool"), temp_dir.join("tool-link")).unwrap();
+        // Plenty of files inside a repo, which the fast walk never lists
+        for i in 0..200 {
+            touch(&format!("fr/agent-box/file{}.rs", i));
+        }
+
+        let calls = Cell::new(0);
+        let is_repo = |path: &Path| {
+            calls.set(calls.get() + 1);
+            path.join(".git").exists() || path.join(".jj").exists()
+        };
+        let sorted = |mut repos: Vec<RepoIdentifier>| {
+            repos.sort();
+            repos
+                .into_iter()
+                .map(|r| r.relative_path.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let collect = |fast: bool| {
+            let mut repos = Vec::new();
+            let mut push = |repo| {
+                repos.push(repo);
+                true
+            };
+            if fast {
+                RepoIdentifier::discover_repos_in_dir_fast(&temp_dir, is_repo, &mut push).unwrap();
+            } else {
+                RepoIdentifier::discover_repos_in_dir(&temp_dir, is_repo, &mut push).unwrap();
+            }
+            repos
+        };
+
+        let slow = sorted(collect(false));
+        let slow_calls = calls.replace(0);
+        let fast = sorted(collect(true));
+        let fast_calls = calls.get();
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+
+        assert_eq!(
+            slow,
+            vec!["fr/agent-box", "tool-link", "work/linked", "work/tool"]
+        );
+        assert_eq!(fast, slow);
+        // One marker check per directory, instead of one per entry's parent
+        assert!(
+            fast_calls * 10 < slow_calls,
+            "fast: {}, slow: {}",
+            fast_calls,
+            slow_calls
+        );
+    }
+
+    #[test]
+    fn test_discover_repo_ids_with_bare_repo_markers() {
+        let temp_dir = std::env::temp_dir().join(format!("ab_bare_markers_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let mkdir = |rel: &str| std::fs::create_dir_all(temp_dir.join(rel)).unwrap();
+
+        mkdir("src/app/.git");
+        mkdir("mirror/proj.git/refs");
+        mkdir("mirror/proj.git/objects");
+        std::fs::write(
+            temp_dir.join("mirror/proj.git/HEAD"),
+            "ref: refs/heads/main\n",
+        )
+        .unwrap();
+        // Missing `objects`, so not a repo under the custom markers
+        mkdir("mirror/partial.git/refs");
+        std::fs::write(temp_dir.join("mirror/partial.git/HEAD"), "").unwrap();
+
+        let mut config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.clone(),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        let discover = |config: &Config| {
+            let mut repos: Vec<String> = RepoIdentifier::discover_repo_ids(config)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.relative_path.to_string_lossy().to_string())
+                .collect();
+            repos.sort();
+            repos
+        };
+
+        assert_eq!(discover(&config), vec!["src/app"]);
+
+        config.runtime.bare_repo_markers = vec![
+            "HEAD".to_string(),
+            "refs".to_string(),
+            "objects".to_string(),
+        ];
+        assert_eq!(discover(&config), vec!["mirror/proj.git", "src/app"]);
+
+        config.runtime.fast_discovery = true;
+        assert_eq!(discover(&config), vec!["mirror/proj.git", "src/app"]);
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
 }
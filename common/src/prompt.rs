@@ -0,0 +1,72 @@
+use eyre::{Result, bail};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Environment variable that disables interactive prompts when set to `1`
+pub const NON_INTERACTIVE_ENV: &str = "AGENT_BOX_NONINTERACTIVE";
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Disable (or re-enable) interactive prompts for this process
+pub fn set_non_interactive(value: bool) {
+    NON_INTERACTIVE.store(value, Ordering::Relaxed);
+}
+
+/// Returns true if prompts are disabled via `--no-interactive` or the environment
+pub fn is_non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+        || env_value_enables(std::env::var(NON_INTERACTIVE_ENV).ok().as_deref())
+}
+
+/// Prompts stay disabled until this is dropped. It holds a lock, so tests
+/// using it can't re-enable prompts under each other, and it resets the flag
+/// so other tests in the binary still run interactive.
+#[cfg(test)]
+pub(crate) struct NonInteractiveGuard {
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl NonInteractiveGuard {
+    pub(crate) fn new() -> Self {
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        set_non_interactive(true);
+        Self { _lock: lock }
+    }
+}
+
+#[cfg(test)]
+impl Drop for NonInteractiveGuard {
+    fn drop(&mut self) {
+        set_non_interactive(false);
+    }
+}
+
+fn env_value_enables(value: Option<&str>) -> bool {
+    matches!(value, Some("1") | Some("true"))
+}
+
+/// Fail fast instead of prompting when running non-interactively.
+///
+/// `what` describes the missing input and how to provide it, e.g.
+/// `"session name required; pass it with --session"`.
+pub fn require_interactive(what: &str) -> Result<()> {
+    if is_non_interactive() {
+        bail!("{} (running in non-interactive mode)", what);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_value_enables() {
+        assert!(env_value_enables(Some("1")));
+        assert!(env_value_enables(Some("true")));
+        assert!(!env_value_enables(Some("0")));
+        assert!(!env_value_enables(Some("")));
+        assert!(!env_value_enables(None));
+    }
+}
@@ -1,9 +1,11 @@
 use eyre::{OptionExt, Result, WrapErr, bail};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use crate::config::Config;
 use crate::path::RepoIdentifier;
 use crate::path::path_to_str;
+use crate::prompt::require_interactive;
 
 /// Find the git root directory by traversing up from the current directory
 pub fn find_git_root() -> Result<PathBuf> {
@@ -23,8 +25,32 @@ pub fn find_git_root() -> Result<PathBuf> {
         .map(|p: &std::path::Path| p.to_path_buf())
 }
 
+/// Return the common git dir of a linked worktree.
+///
+/// In a linked worktree `.git` is a file pointing into the main repository's
+/// `worktrees/` directory, so mounting it alone does not bring along the object
+/// store. Returns `None` if `repo_path/.git` is not a file or cannot be opened.
+pub fn linked_worktree_common_dir(repo_path: &Path) -> Option<PathBuf> {
+    if !repo_path.join(".git").is_file() {
+        return None;
+    }
+
+    let repo = gix::open(repo_path).ok()?;
+    let common_dir = repo.common_dir();
+    Some(
+        common_dir
+            .canonicalize()
+            .unwrap_or_else(|_| common_dir.to_path_buf()),
+    )
+}
+
 /// Prompt user to select from a list of repos
 fn prompt_select_repo(repos: Vec<RepoIdentifier>, prompt: &str) -> Result<RepoIdentifier> {
+    require_interactive(&format!(
+        "{} repositories match; pass a more specific repository name",
+        repos.len()
+    ))?;
+
     let options: Vec<String> = repos
         .iter()
         .map(|r| r.relative_path().display().to_string())
@@ -66,6 +92,322 @@ pub fn locate_repo(config: &Config, search: Option<&str>) -> Result<RepoIdentifi
     }
 }
 
+/// Keep repos whose relative path contains `filter` (case-insensitive), capped at `limit`
+pub fn filter_repos(
+    repos: Vec<RepoIdentifier>,
+    filter: Option<&str>,
+    limit: Option<usize>,
+) -> Vec<RepoIdentifier> {
+    let filter = filter.map(str::to_lowercase);
+    repos
+        .into_iter()
+        .filter(|repo| repo_matches(repo, filter.as_deref()))
+        .take(limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// True if `repo`'s relative path contains the already-lowercased `filter`
+fn repo_matches(repo: &RepoIdentifier, filter: Option<&str>) -> bool {
+    filter.is_none_or(|f| {
+        repo.relative_path()
+            .to_string_lossy()
+            .to_lowercase()
+            .contains(f)
+    })
+}
+
+/// Write one JSON object (`repo`, `path`) per discovered repo to `out` as soon as
+/// it is found (`ab dbg list --json-lines`). Unlike [`list_repos`], repos come in
+/// walk order rather than sorted, so nothing is buffered.
+pub fn write_repos_json_lines(
+    config: &Config,
+    filter: Option<&str>,
+    limit: Option<usize>,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let filter = filter.map(str::to_lowercase);
+    let mut remaining = limit.unwrap_or(usize::MAX);
+    if remaining == 0 {
+        return Ok(());
+    }
+    let mut result: std::io::Result<()> = Ok(());
+
+    RepoIdentifier::for_each_repo_id(config, |repo| {
+        if !repo_matches(&repo, filter.as_deref()) {
+            return true;
+        }
+        let line = repo_json(config, &repo);
+        result = writeln!(out, "{}", line).and_then(|()| out.flush());
+        remaining -= 1;
+        result.is_ok() && remaining > 0
+    })?;
+
+    Ok(result?)
+}
+
+/// Discover all repos in base_repo_dir, sorted by path, then filter and limit them
+pub fn list_repos(
+    config: &Config,
+    filter: Option<&str>,
+    limit: Option<usize>,
+) -> Result<Vec<RepoIdentifier>> {
+    let mut repos = RepoIdentifier::discover_repo_ids(config)?;
+    repos.sort();
+    Ok(filter_repos(repos, filter, limit))
+}
+
+/// Write `repos`' total and their session workspace counts (`ab dbg list --count`).
+///
+/// Workspaces are counted from the session directories under `workspace_dir`,
+/// without opening any repository.
+pub fn write_repo_counts(
+    config: &Config,
+    repos: &[RepoIdentifier],
+    out: &mut dyn Write,
+) -> Result<()> {
+    let counts = repo_counts(config, repos);
+    writeln!(out, "repos: {}", counts.repos)?;
+    writeln!(out, "git worktrees: {}", counts.git_worktrees)?;
+    writeln!(out, "jj workspaces: {}", counts.jj_workspaces)?;
+    Ok(())
+}
+
+/// Number of repos and of their git worktrees and jj workspaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct RepoCounts {
+    pub repos: usize,
+    pub git_worktrees: usize,
+    pub jj_workspaces: usize,
+}
+
+/// Count `repos` and their workspace directories, for `ab dbg list --count`
+pub fn repo_counts(config: &Config, repos: &[RepoIdentifier]) -> RepoCounts {
+    let count = |wtype: crate::path::WorkspaceType| -> usize {
+        repos
+            .iter()
+            .filter_map(|repo_id| std::fs::read_dir(repo_id.workspace_path(config, wtype, "")).ok())
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                    .count()
+            })
+            .sum()
+    };
+
+    RepoCounts {
+        repos: repos.len(),
+        git_worktrees: count(crate::path::WorkspaceType::Git),
+        jj_workspaces: count(crate::path::WorkspaceType::Jj),
+    }
+}
+
+/// A repo as `ab dbg list` prints it in JSON: its relative and source paths
+pub fn repo_json(config: &Config, repo: &RepoIdentifier) -> serde_json::Value {
+    serde_json::json!({
+        "repo": repo.relative_path().to_string_lossy(),
+        "path": repo.source_path(config).to_string_lossy(),
+    })
+}
+
+/// A repo's source checkout or one of its session workspaces, as `ab list` shows it
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ListEntry {
+    pub repo: String,
+    /// Session name; none for the source checkout
+    pub session: Option<String>,
+    #[serde(rename = "type")]
+    pub wtype: crate::path::WorkspaceType,
+    pub path: PathBuf,
+    /// When the directory last changed, in seconds since the Unix epoch
+    pub modified: Option<u64>,
+}
+
+/// The source checkouts of `repos`, each followed by its session workspaces
+/// (git ones first, each kind sorted by session). With `wtype`, only entries of
+/// that kind are kept; a source checkout's kind is what [`detect_workspace_type`] says.
+pub fn list_entries(
+    config: &Config,
+    repos: &[RepoIdentifier],
+    wtype: Option<crate::path::WorkspaceType>,
+) -> Vec<ListEntry> {
+    use crate::path::WorkspaceType;
+
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    };
+
+    let mut entries = Vec::new();
+    for repo_id in repos {
+        let repo = repo_id.relative_path().to_string_lossy().to_string();
+        let source = repo_id.source_path(config);
+        let source_type = detect_workspace_type(&source);
+        if wtype.is_none_or(|t| t == source_type) {
+            entries.push(ListEntry {
+                repo: repo.clone(),
+                session: None,
+                wtype: source_type,
+                modified: modified(&source),
+                path: source,
+            });
+        }
+
+        for kind in [WorkspaceType::Git, WorkspaceType::Jj] {
+            if wtype.is_some_and(|t| t != kind) {
+                continue;
+            }
+            let Ok(dirs) = std::fs::read_dir(repo_id.workspace_path(config, kind, "")) else {
+                continue;
+            };
+            let mut sessions: Vec<(String, PathBuf)> = dirs
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .map(|e| (e.file_name().to_string_lossy().to_string(), e.path()))
+                .collect();
+            sessions.sort();
+            entries.extend(sessions.into_iter().map(|(session, path)| ListEntry {
+                repo: repo.clone(),
+                session: Some(session),
+                wtype: kind,
+                modified: modified(&path),
+                path,
+            }));
+        }
+    }
+    entries
+}
+
+/// The source checkout or session workspace `path` is in, if any; the deepest
+/// match wins, so a workspace nested under a source checkout is found
+pub fn workspace_at(config: &Config, path: &Path) -> Result<Option<ListEntry>> {
+    let repos = RepoIdentifier::discover_repo_ids(config)?;
+    Ok(list_entries(config, &repos, None)
+        .into_iter()
+        .filter(|entry| path.starts_with(&entry.path))
+        .max_by_key(|entry| entry.path.components().count()))
+}
+
+/// Why `ab gc` keeps the session workspace `entry`, if it does: it changed less
+/// than `older_than` seconds before `now`, it has uncommitted changes, or it has
+/// commits that no other branch, remote branch, or bookmark has. A workspace
+/// whose status can't be checked is kept too.
+pub fn gc_keep_reason(entry: &ListEntry, older_than: u64, now: u64) -> Option<String> {
+    match entry.modified {
+        None => return Some("modification time unknown".to_string()),
+        Some(modified) if now.saturating_sub(modified) < older_than => {
+            return Some("modified recently".to_string());
+        }
+        Some(_) => {}
+    }
+    let unsaved = match entry.wtype {
+        crate::path::WorkspaceType::Git => git_unsaved_work(&entry.path),
+        crate::path::WorkspaceType::Jj => jj_unsaved_work(&entry.path),
+    };
+    unsaved.unwrap_or_else(|e| Some(format!("status check failed: {}", e)))
+}
+
+/// Run `program args` in `dir` and return its stdout, failing with its stderr
+fn command_stdout(program: &str, dir: &Path, args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new(program)
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .wrap_err_with(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Uncommitted changes, or commits on no other branch or remote branch, in the
+/// git worktree at `path`
+fn git_unsaved_work(path: &Path) -> Result<Option<String>> {
+    let status = command_stdout("git", path, &["status", "--porcelain"])?;
+    if !status.trim().is_empty() {
+        return Ok(Some(format!(
+            "{} uncommitted change(s)",
+            status.lines().count()
+        )));
+    }
+
+    // The worktree's own branch doesn't count as having its commits elsewhere
+    let branch = command_stdout("git", path, &["symbolic-ref", "-q", "--short", "HEAD"])
+        .map(|b| b.trim().to_string())
+        .ok();
+    let exclude = branch.map(|b| format!("--exclude={}", b));
+    let mut args = vec!["rev-list", "--count", "HEAD", "--not"];
+    args.extend(exclude.as_deref());
+    args.extend(["--branches", "--remotes"]);
+    let count: usize = command_stdout("git", path, &args)?
+        .trim()
+        .parse()
+        .wrap_err("Unexpected git rev-list output")?;
+    Ok((count > 0).then(|| format!("{} commit(s) on no other branch or remote", count)))
+}
+
+/// Non-empty changes (including the working copy) on no bookmark or remote
+/// bookmark, in the jj workspace at `path`
+fn jj_unsaved_work(path: &Path) -> Result<Option<String>> {
+    let changes = command_stdout(
+        "jj",
+        path,
+        &[
+            "log",
+            "--no-graph",
+            "-r",
+            "(::@ ~ ::(bookmarks() | remote_bookmarks())) ~ empty()",
+            "-T",
+            "change_id ++ \"\\n\"",
+        ],
+    )?;
+    let count = changes.lines().filter(|l| !l.is_empty()).count();
+    Ok((count > 0).then(|| format!("{} change(s) on no bookmark", count)))
+}
+
+/// `git worktree prune` in `repo_id`'s source checkout, dropping the
+/// registrations of worktrees whose directories are gone. Returns what git
+/// reports; with `dry_run`, only what it would prune.
+pub fn prune_git_worktrees(
+    config: &Config,
+    repo_id: &RepoIdentifier,
+    dry_run: bool,
+) -> Result<String> {
+    let source_path = repo_id.source_path(config);
+    let mut args = vec!["worktree", "prune", "--verbose"];
+    if dry_run {
+        args.push("--dry-run");
+    }
+    let output = std::process::Command::new("git")
+        .current_dir(&source_path)
+        .args(&args)
+        .output()
+        .wrap_err("Failed to run git worktree prune")?;
+    if !output.status.success() {
+        bail!(
+            "git worktree prune failed in {}: {}",
+            source_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    // --verbose reports on stderr
+    Ok(format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+    .trim()
+    .to_string())
+}
+
 /// Resolve repo argument to a RepoIdentifier
 /// - If None: find git root from cwd and compute RepoId from it
 /// - If Some: use locate_repo to find the repo_id (prompts if multiple matches)
@@ -77,58 +419,371 @@ pub fn resolve_repo_id(config: &Config, repo_name: Option<&str>) -> Result<RepoI
             RepoIdentifier::from_repo_path(config, &git_root)
         }
     };
-    println!("debug: {repo_id:?}");
+    eprintln!("debug: {repo_id:?}");
     repo_id
 }
 
 /// Create a new workspace (git worktree or jj workspace)
+/// Progress messages are written to `progress`; returns the new workspace path.
+/// With `diff_base`, the base revision is checked before creation and a diff
+/// stat of the new workspace against it is written to `progress` afterwards.
+/// With `auto_rename`, a session whose jj workspace name is already taken gets a
+/// numeric suffix instead of failing.
+/// Without `workspace_type`, it is detected from the source repo (see [`detect_workspace_type`]).
+#[allow(clippy::too_many_arguments)]
 pub fn new_workspace(
     config: &Config,
     repo_name: Option<&str>,
     session_name: Option<&str>,
-    workspace_type: crate::path::WorkspaceType,
-) -> Result<()> {
+    workspace_type: Option<crate::path::WorkspaceType>,
+    bookmark: Option<&str>,
+    diff_base: Option<&str>,
+    auto_rename: bool,
+    progress: &mut dyn Write,
+) -> Result<PathBuf> {
+    if bookmark.is_some() && workspace_type == Some(crate::path::WorkspaceType::Git) {
+        bail!("--bookmark is only supported for jj workspaces");
+    }
+
     // Resolve repo_id from repo_name argument
     let repo_id = resolve_repo_id(config, repo_name)?;
 
+    let workspace_type = match workspace_type {
+        Some(workspace_type) => workspace_type,
+        None => {
+            let detected = detect_workspace_type(&repo_id.source_path(config));
+            if bookmark.is_some() && detected == crate::path::WorkspaceType::Git {
+                bail!(
+                    "--bookmark is only supported for jj workspaces, and {} has no .jj directory",
+                    repo_id.source_path(config).display()
+                );
+            }
+            detected
+        }
+    };
+
     // Get session name
-    let session = get_session_name(session_name)?;
+    let mut session = get_session_name(session_name)?;
+
+    // jj refuses a workspace name the repo already tracks, even if its directory is gone
+    if workspace_type == crate::path::WorkspaceType::Jj {
+        let existing: Vec<String> = repo_id
+            .jj_workspaces(config)?
+            .into_iter()
+            .map(|ws| ws.name)
+            .collect();
+        let renamed = free_jj_workspace_name(&session, auto_rename, |name| {
+            existing.iter().any(|ws| ws == name) || repo_id.jj_workspace_path(config, name).exists()
+        })?;
+        if renamed != session {
+            writeln!(
+                progress,
+                "jj workspace '{}' already exists; using '{}' instead",
+                session, renamed
+            )?;
+            session = renamed;
+        }
+    }
 
     // Calculate paths
     let source_path = repo_id.source_path(config);
     let workspace_path = repo_id.workspace_path(config, workspace_type, &session);
 
-    println!(
+    writeln!(
+        progress,
         "Creating new {} workspace:",
         match workspace_type {
             crate::path::WorkspaceType::Git => "git worktree",
             crate::path::WorkspaceType::Jj => "jj workspace",
         }
-    );
-    println!("  Source: {}", source_path.display());
-    println!("  Workspace: {}", workspace_path.display());
-    println!("  Session: {}", session);
+    )?;
+    writeln!(progress, "  Source: {}", source_path.display())?;
+    writeln!(progress, "  Workspace: {}", workspace_path.display())?;
+    writeln!(progress, "  Session: {}", session)?;
+
+    if let Some(base) = diff_base {
+        check_diff_base(workspace_type, &source_path, base)?;
+    }
 
     // Run the appropriate CLI command
     match workspace_type {
         crate::path::WorkspaceType::Git => {
-            create_git_worktree(config, &repo_id, &session)?;
+            create_git_worktree(config, &repo_id, &session, progress)?;
         }
         crate::path::WorkspaceType::Jj => {
-            create_jj_workspace(config, &repo_id, &session)?;
+            create_jj_workspace(config, &repo_id, &session, bookmark, progress)?;
         }
     }
 
-    println!(
+    if let Some(mode) = config.runtime.workspace_mode {
+        apply_workspace_mode(&workspace_path, mode, progress)?;
+    }
+
+    writeln!(
+        progress,
         "\n✓ Successfully created workspace at: {}",
         workspace_path.display()
-    );
+    )?;
+
+    if let Some(base) = diff_base {
+        write_diff_stat(workspace_type, &workspace_path, &session, base, progress)?;
+    }
+
+    Ok(workspace_path)
+}
+
+/// `chmod` a newly created workspace directory to `runtime.workspace_mode`.
+/// Filesystems that don't support it get a warning instead of an error.
+fn apply_workspace_mode(path: &Path, mode: u32, progress: &mut dyn Write) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        match std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)) {
+            Ok(()) => writeln!(progress, "  Set workspace mode to {:04o}", mode)?,
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => writeln!(
+                progress,
+                "WARNING: could not set workspace mode {:04o} on {}: {}",
+                mode,
+                path.display(),
+                e
+            )?,
+            Err(e) => {
+                return Err(e).wrap_err(format!(
+                    "Failed to set mode {:04o} on {}",
+                    mode,
+                    path.display()
+                ));
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode, progress);
+
+    Ok(())
+}
+
+/// Workspace type to use when neither `--git` nor `--jj` is given:
+/// jj if the source repo is a colocated jj repo (has `.jj`), git otherwise.
+pub fn detect_workspace_type(source_path: &Path) -> crate::path::WorkspaceType {
+    if source_path.join(".jj").is_dir() {
+        crate::path::WorkspaceType::Jj
+    } else {
+        crate::path::WorkspaceType::Git
+    }
+}
+
+/// Pick the jj workspace name for `session`.
+///
+/// Returns `session` if `taken` rejects it, otherwise the first free
+/// `session-N` (N >= 2) with `auto_rename`, or an error without it.
+fn free_jj_workspace_name(
+    session: &str,
+    auto_rename: bool,
+    taken: impl Fn(&str) -> bool,
+) -> Result<String> {
+    if !taken(session) {
+        return Ok(session.to_string());
+    }
+    if !auto_rename {
+        bail!(
+            "jj workspace '{}' already exists in repo; choose another name or \
+             `jj workspace forget {}` it (or pass --auto-rename)",
+            session,
+            session
+        );
+    }
+    (2..)
+        .map(|n| format!("{}-{}", session, n))
+        .find(|name| !taken(name))
+        .ok_or_else(|| eyre::eyre!("No free jj workspace name for '{}'", session))
+}
+
+/// Command that fails unless `base` names exactly one revision, as (program, args).
+/// Run in the source repo.
+fn diff_base_check_command(
+    workspace_type: crate::path::WorkspaceType,
+    base: &str,
+) -> (&'static str, Vec<String>) {
+    match workspace_type {
+        crate::path::WorkspaceType::Git => (
+            "git",
+            vec![
+                "rev-parse".to_string(),
+                "--verify".to_string(),
+                "--quiet".to_string(),
+                "--end-of-options".to_string(),
+                format!("{}^{{commit}}", base),
+            ],
+        ),
+        crate::path::WorkspaceType::Jj => (
+            "jj",
+            vec![
+                "log".to_string(),
+                "--no-graph".to_string(),
+                "-r".to_string(),
+                format!("exactly({}, 1)", base),
+                "-T".to_string(),
+                "commit_id".to_string(),
+            ],
+        ),
+    }
+}
+
+/// Command printing the diff stat of the new workspace against `base`, as
+/// (program, args). Run in the workspace.
+fn diff_stat_command(
+    workspace_type: crate::path::WorkspaceType,
+    session: &str,
+    base: &str,
+) -> (&'static str, Vec<String>) {
+    match workspace_type {
+        crate::path::WorkspaceType::Git => (
+            "git",
+            vec![
+                "diff".to_string(),
+                "--stat".to_string(),
+                format!("{}..{}", base, session),
+            ],
+        ),
+        crate::path::WorkspaceType::Jj => (
+            "jj",
+            vec![
+                "diff".to_string(),
+                "--stat".to_string(),
+                "--from".to_string(),
+                base.to_string(),
+                "--to".to_string(),
+                "@".to_string(),
+            ],
+        ),
+    }
+}
+
+/// Fail if `base` doesn't resolve to a single revision in the source repo
+fn check_diff_base(
+    workspace_type: crate::path::WorkspaceType,
+    source_path: &Path,
+    base: &str,
+) -> Result<()> {
+    let (program, args) = diff_base_check_command(workspace_type, base);
+    let output = std::process::Command::new(program)
+        .current_dir(source_path)
+        .args(&args)
+        .output()
+        .wrap_err_with(|| format!("Failed to run {}", program))?;
+
+    if !output.status.success() {
+        bail!(
+            "Base revision '{}' not found in {}: {}",
+            base,
+            source_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
 
+/// Write the diff stat of the workspace against `base` to `out`
+fn write_diff_stat(
+    workspace_type: crate::path::WorkspaceType,
+    workspace_path: &Path,
+    session: &str,
+    base: &str,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let (program, args) = diff_stat_command(workspace_type, session, base);
+    let output = std::process::Command::new(program)
+        .current_dir(workspace_path)
+        .args(&args)
+        .output()
+        .wrap_err_with(|| format!("Failed to run {}", program))?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to compute diff stat ({} {}): {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    writeln!(out, "\nChanges since {}:", base)?;
+    if output.stdout.is_empty() {
+        writeln!(out, "  (no changes)")?;
+    } else {
+        out.write_all(&output.stdout)?;
+    }
     Ok(())
 }
 
+/// `jj` invocations that create the workspace for `session`, as (working dir, args).
+/// With a bookmark, the new workspace's working copy is then moved onto it with `jj new`.
+fn jj_workspace_commands(
+    source_path: &Path,
+    workspace_path: &Path,
+    session: &str,
+    bookmark: Option<&str>,
+) -> Result<Vec<(PathBuf, Vec<String>)>> {
+    let mut commands = vec![(
+        source_path.to_path_buf(),
+        vec![
+            "workspace".to_string(),
+            "add".to_string(),
+            "--name".to_string(),
+            session.to_string(),
+            path_to_str(workspace_path)?.to_string(),
+        ],
+    )];
+
+    if let Some(bookmark) = bookmark {
+        commands.push((
+            workspace_path.to_path_buf(),
+            vec!["new".to_string(), bookmark_revset(bookmark)],
+        ));
+    }
+
+    Ok(commands)
+}
+
+/// Revset matching exactly the local bookmark `name`
+fn bookmark_revset(name: &str) -> String {
+    format!("bookmarks(exact:{:?})", name)
+}
+
+/// Returns true if the jj repo at `source_path` has a local bookmark `name`
+fn jj_bookmark_exists(source_path: &Path, name: &str) -> Result<bool> {
+    let output = std::process::Command::new("jj")
+        .current_dir(source_path)
+        .args([
+            "log",
+            "--no-graph",
+            "-r",
+            &bookmark_revset(name),
+            "-T",
+            "commit_id",
+        ])
+        .output()?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to look up bookmark '{}': {}",
+            name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(!output.stdout.is_empty())
+}
+
 /// Create a new jj workspace from an existing colocated jj repo
-fn create_jj_workspace(config: &Config, repo_id: &RepoIdentifier, session: &str) -> Result<()> {
+fn create_jj_workspace(
+    config: &Config,
+    repo_id: &RepoIdentifier,
+    session: &str,
+    bookmark: Option<&str>,
+    progress: &mut dyn Write,
+) -> Result<()> {
     let source_path = repo_id.source_path(config);
     let workspace_path = repo_id.jj_workspace_path(config, session);
 
@@ -147,34 +802,45 @@ fn create_jj_workspace(config: &Config, repo_id: &RepoIdentifier, session: &str)
         std::fs::create_dir_all(parent)?;
     }
 
-    println!("Creating jj workspace from colocated repo...");
-
-    // Use jj workspace add from the colocated repo
-    let output = std::process::Command::new("jj")
-        .current_dir(&source_path)
-        .args([
-            "workspace",
-            "add",
-            "--name",
-            session,
-            path_to_str(&workspace_path)?,
-        ])
-        .output()?;
-
-    if !output.status.success() {
+    if let Some(bookmark) = bookmark
+        && !jj_bookmark_exists(&source_path, bookmark)?
+    {
         bail!(
-            "Failed to create jj workspace: {}",
-            String::from_utf8_lossy(&output.stderr)
+            "Bookmark '{}' not found in {}",
+            bookmark,
+            source_path.display()
         );
     }
 
-    println!("  ✓ JJ workspace created successfully");
+    writeln!(progress, "Creating jj workspace from colocated repo...")?;
+
+    for (dir, args) in jj_workspace_commands(&source_path, &workspace_path, session, bookmark)? {
+        let output = std::process::Command::new("jj")
+            .current_dir(&dir)
+            .args(&args)
+            .output()?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to create jj workspace (jj {}): {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    writeln!(progress, "  ✓ JJ workspace created successfully")?;
 
     Ok(())
 }
 
 /// Create a new git worktree from a git repository
-fn create_git_worktree(config: &Config, repo_id: &RepoIdentifier, session: &str) -> Result<()> {
+fn create_git_worktree(
+    config: &Config,
+    repo_id: &RepoIdentifier,
+    session: &str,
+    progress: &mut dyn Write,
+) -> Result<()> {
     let source_path = repo_id.source_path(config);
     let workspace_path = repo_id.git_workspace_path(config, session);
 
@@ -199,11 +865,11 @@ fn create_git_worktree(config: &Config, repo_id: &RepoIdentifier, session: &str)
         args.push("-b");
         args.push(session);
         args.push(path_to_str(&workspace_path)?);
-        println!("  Creating new branch: {}", session);
+        writeln!(progress, "  Creating new branch: {}", session)?;
     } else {
         args.push(path_to_str(&workspace_path)?);
         args.push(session);
-        println!("  Using existing branch: {}", session);
+        writeln!(progress, "  Using existing branch: {}", session)?;
     }
 
     let output = std::process::Command::new("git")
@@ -218,7 +884,7 @@ fn create_git_worktree(config: &Config, repo_id: &RepoIdentifier, session: &str)
         );
     }
 
-    println!("  ✓ Git worktree created successfully");
+    writeln!(progress, "  ✓ Git worktree created successfully")?;
 
     Ok(())
 }
@@ -237,6 +903,8 @@ fn get_session_name(session_name: Option<&str>) -> Result<String> {
             Ok(trimmed.to_string())
         }
         None => {
+            require_interactive("session name required; pass it with --session")?;
+
             let validator = |input: &str| {
                 let trimmed = input.trim();
                 if trimmed.is_empty() {
@@ -263,6 +931,83 @@ fn get_session_name(session_name: Option<&str>) -> Result<String> {
     }
 }
 
+/// Tracked files with uncommitted changes in a git checkout, as `git status
+/// --porcelain` lines; untracked files don't count
+fn uncommitted_tracked_changes(source_path: &Path) -> Result<Vec<String>> {
+    let output = std::process::Command::new("git")
+        .current_dir(source_path)
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()
+        .wrap_err("Failed to run git status")?;
+    if !output.status.success() {
+        bail!(
+            "git status failed in {}: {}",
+            source_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Export a repo's committed history as a bare git clone at `dest`.
+///
+/// Refuses when tracked files have uncommitted changes, since they wouldn't be
+/// in the export; `allow_dirty` skips that check and exports the committed
+/// state as is.
+pub fn export_repo(
+    config: &Config,
+    repo_id: &RepoIdentifier,
+    dest: &Path,
+    allow_dirty: bool,
+    progress: &mut dyn Write,
+) -> Result<()> {
+    let source_path = repo_id.source_path(config);
+    if !source_path.join(".git").exists() {
+        bail!("{} has no .git directory to export", source_path.display());
+    }
+    if dest.exists() {
+        bail!("Export destination {} already exists", dest.display());
+    }
+
+    if !allow_dirty {
+        let changes = uncommitted_tracked_changes(&source_path)?;
+        if !changes.is_empty() {
+            bail!(
+                "{} has uncommitted changes to tracked files, which would not be exported:\n  {}\n\
+                 Commit them, or pass --allow-dirty to export the committed state",
+                source_path.display(),
+                changes.join("\n  ")
+            );
+        }
+    }
+
+    writeln!(
+        progress,
+        "Exporting {} to {}",
+        source_path.display(),
+        dest.display()
+    )?;
+    let output = std::process::Command::new("git")
+        .args(["clone", "--bare", "--quiet"])
+        .arg(&source_path)
+        .arg(dest)
+        .output()
+        .wrap_err("Failed to run git clone")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to export {}: {}",
+            source_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    writeln!(progress, "  ✓ Exported")?;
+
+    Ok(())
+}
+
 /// Remove all workspaces for a given repo ID
 pub fn remove_repo(config: &Config, repo_id: &RepoIdentifier, dry_run: bool) -> Result<()> {
     let paths_to_remove: Vec<(&str, PathBuf)> = vec![
@@ -316,3 +1061,891 @@ pub fn remove_repo(config: &Config, repo_id: &RepoIdentifier, dry_run: bool) ->
 
     Ok(())
 }
+
+/// Remove a single session's workspace, leaving the repo's other workspaces intact
+pub fn remove_workspace(
+    config: &Config,
+    repo_id: &RepoIdentifier,
+    workspace_type: crate::path::WorkspaceType,
+    session: &str,
+) -> Result<()> {
+    let source_path = repo_id.source_path(config);
+    let workspace_path = repo_id.workspace_path(config, workspace_type, session);
+
+    if !workspace_path.exists() {
+        println!("Workspace already removed: {}", workspace_path.display());
+        return Ok(());
+    }
+
+    println!("Removing workspace: {}", workspace_path.display());
+
+    // Unregister the workspace from the source repo before deleting it
+    let output = match workspace_type {
+        crate::path::WorkspaceType::Git => std::process::Command::new("git")
+            .current_dir(&source_path)
+            .args([
+                "worktree",
+                "remove",
+                "--force",
+                path_to_str(&workspace_path)?,
+            ])
+            .output()?,
+        crate::path::WorkspaceType::Jj => std::process::Command::new("jj")
+            .current_dir(&source_path)
+            .args(["workspace", "forget", session])
+            .output()?,
+    };
+
+    if !output.status.success() {
+        bail!(
+            "Failed to remove workspace '{}': {}",
+            session,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    if workspace_path.exists() {
+        std::fs::remove_dir_all(&workspace_path)?;
+    }
+
+    println!("  ✓ Removed");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::NonInteractiveGuard;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    fn repo_ids(paths: &[&str]) -> Vec<RepoIdentifier> {
+        paths
+            .iter()
+            .map(|p| RepoIdentifier {
+                relative_path: PathBuf::from(p),
+            })
+            .collect()
+    }
+
+    fn rel_paths(repos: &[RepoIdentifier]) -> Vec<String> {
+        repos
+            .iter()
+            .map(|r| r.relative_path().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_repos_substring_is_case_insensitive() {
+        let repos = repo_ids(&["fr/agent-box", "fr/Agent-Tools", "work/api"]);
+        let filtered = filter_repos(repos, Some("AGENT"), None);
+        assert_eq!(rel_paths(&filtered), ["fr/agent-box", "fr/Agent-Tools"]);
+    }
+
+    #[test]
+    fn test_filter_repos_matches_parent_directories() {
+        let repos = repo_ids(&["fr/agent-box", "work/api", "work/web"]);
+        let filtered = filter_repos(repos, Some("work/"), None);
+        assert_eq!(rel_paths(&filtered), ["work/api", "work/web"]);
+    }
+
+    #[test]
+    fn test_filter_repos_limit_applies_after_filter() {
+        let repos = repo_ids(&["a/one", "b/two", "a/three", "a/four"]);
+        let filtered = filter_repos(repos, Some("a/"), Some(2));
+        assert_eq!(rel_paths(&filtered), ["a/one", "a/three"]);
+
+        let repos = repo_ids(&["a/one", "b/two"]);
+        assert_eq!(
+            rel_paths(&filter_repos(repos, None, Some(0))),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_filter_repos_without_options_keeps_all() {
+        let repos = repo_ids(&["a/one", "b/two"]);
+        assert_eq!(
+            rel_paths(&filter_repos(repos, None, None)),
+            ["a/one", "b/two"]
+        );
+    }
+
+    #[test]
+    fn test_linked_worktree_common_dir() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-linked-worktree-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let main = temp_dir.join("main");
+        let linked = temp_dir.join("linked");
+        std::fs::create_dir_all(&main).unwrap();
+
+        git(&main, &["init", "-q"]);
+        git(&main, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(
+            &main,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "linked",
+                linked.to_str().unwrap(),
+            ],
+        );
+
+        // The main checkout has a .git directory, so nothing extra is needed
+        assert_eq!(linked_worktree_common_dir(&main), None);
+
+        // The linked worktree resolves to the main repo's .git directory
+        assert_eq!(
+            linked_worktree_common_dir(&linked),
+            Some(main.join(".git").canonicalize().unwrap())
+        );
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_get_session_name_fails_fast_when_non_interactive() {
+        let _non_interactive = NonInteractiveGuard::new();
+
+        let err = get_session_name(None).unwrap_err().to_string();
+        assert!(err.contains("session name required"));
+        assert!(err.contains("non-interactive"));
+
+        // An explicit name never prompts
+        assert_eq!(get_session_name(Some("main")).unwrap(), "main");
+    }
+
+    #[test]
+    fn test_prompt_select_repo_fails_fast_when_non_interactive() {
+        let _non_interactive = NonInteractiveGuard::new();
+
+        let repos = vec![
+            RepoIdentifier {
+                relative_path: PathBuf::from("a/agent-box"),
+            },
+            RepoIdentifier {
+                relative_path: PathBuf::from("b/agent-box"),
+            },
+        ];
+        let err = prompt_select_repo(repos, "Select one:")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("2 repositories match"));
+        assert!(err.contains("non-interactive"));
+    }
+
+    #[test]
+    fn test_remove_workspace_only_removes_that_session() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-remove-workspace-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        let repo_id = RepoIdentifier {
+            relative_path: PathBuf::from("proj"),
+        };
+        let source = repo_id.source_path(&config);
+        std::fs::create_dir_all(&source).unwrap();
+        git(&source, &["init", "-q"]);
+        git(&source, &["commit", "-q", "--allow-empty", "-m", "init"]);
+
+        let mut progress = Vec::new();
+        create_git_worktree(&config, &repo_id, "one", &mut progress).unwrap();
+        create_git_worktree(&config, &repo_id, "two", &mut progress).unwrap();
+
+        let git_type = crate::path::WorkspaceType::Git;
+        remove_workspace(&config, &repo_id, git_type, "one").unwrap();
+
+        assert!(!repo_id.workspace_path(&config, git_type, "one").exists());
+        assert!(repo_id.workspace_path(&config, git_type, "two").exists());
+
+        let worktrees = std::process::Command::new("git")
+            .current_dir(&source)
+            .args(["worktree", "list"])
+            .output()
+            .unwrap();
+        let worktrees = String::from_utf8_lossy(&worktrees.stdout);
+        assert!(!worktrees.contains("/one "));
+        assert!(worktrees.contains("/two "));
+
+        // Removing again is a no-op
+        remove_workspace(&config, &repo_id, git_type, "one").unwrap();
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_gc_keep_reason_and_prune_git_worktrees() {
+        use crate::path::WorkspaceType;
+
+        let temp_dir = std::env::temp_dir().join(format!("ab-test-gc-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        let repo_id = RepoIdentifier {
+            relative_path: PathBuf::from("proj"),
+        };
+        let source = repo_id.source_path(&config);
+        std::fs::create_dir_all(&source).unwrap();
+        git(&source, &["init", "-q"]);
+        git(&source, &["commit", "-q", "--allow-empty", "-m", "init"]);
+
+        let mut progress = Vec::new();
+        for session in ["clean", "dirty", "ahead", "gone"] {
+            create_git_worktree(&config, &repo_id, session, &mut progress).unwrap();
+        }
+        let path = |session| repo_id.workspace_path(&config, WorkspaceType::Git, session);
+        std::fs::write(path("dirty").join("file"), "x").unwrap();
+        git(
+            &path("ahead"),
+            &["commit", "-q", "--allow-empty", "-m", "wip"],
+        );
+        std::fs::remove_dir_all(path("gone")).unwrap();
+
+        let entries = list_entries(&config, std::slice::from_ref(&repo_id), None);
+        let reasons: Vec<(String, Option<String>)> = entries
+            .iter()
+            .filter_map(|e| {
+                let session = e.session.clone()?;
+                Some((session, gc_keep_reason(e, 60, e.modified.unwrap() + 3600)))
+            })
+            .collect();
+        let recent = gc_keep_reason(&entries[1], 60, entries[1].modified.unwrap());
+
+        let would_prune = prune_git_worktrees(&config, &repo_id, true).unwrap();
+        let pruned = prune_git_worktrees(&config, &repo_id, false).unwrap();
+        let pruned_again = prune_git_worktrees(&config, &repo_id, false).unwrap();
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+
+        assert_eq!(
+            reasons,
+            [
+                (
+                    "ahead".to_string(),
+                    Some("1 commit(s) on no other branch or remote".to_string())
+                ),
+                ("clean".to_string(), None),
+                (
+                    "dirty".to_string(),
+                    Some("1 uncommitted change(s)".to_string())
+                ),
+            ]
+        );
+        assert_eq!(recent.as_deref(), Some("modified recently"));
+        assert!(would_prune.contains("gone"), "{}", would_prune);
+        assert!(pruned.contains("gone"), "{}", pruned);
+        assert_eq!(pruned_again, "");
+    }
+
+    #[test]
+    fn test_export_repo_dirty_check_and_allow_dirty() {
+        let temp_dir = std::env::temp_dir().join(format!("ab-test-export-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        let source = config.base_repo_dir.join("proj");
+        std::fs::create_dir_all(&source).unwrap();
+        git(&source, &["init", "-q"]);
+        std::fs::write(source.join("file"), "committed").unwrap();
+        git(&source, &["add", "file"]);
+        git(&source, &["commit", "-q", "-m", "init"]);
+        let repo_id = RepoIdentifier {
+            relative_path: PathBuf::from("proj"),
+        };
+
+        // Untracked files don't block the export
+        std::fs::write(source.join("untracked"), "").unwrap();
+        let clean = temp_dir.join("clean.git");
+        export_repo(&config, &repo_id, &clean, false, &mut Vec::new()).unwrap();
+        assert!(clean.join("HEAD").exists());
+
+        std::fs::write(source.join("file"), "dirty").unwrap();
+        let dest = temp_dir.join("proj.git");
+        let err = export_repo(&config, &repo_id, &dest, false, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("M file"), "{}", err);
+        assert!(err.to_string().contains("--allow-dirty"), "{}", err);
+        assert!(!dest.exists());
+
+        // --allow-dirty exports the committed state
+        export_repo(&config, &repo_id, &dest, true, &mut Vec::new()).unwrap();
+        let output = std::process::Command::new("git")
+            .current_dir(&dest)
+            .args(["show", "HEAD:file"])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "committed");
+
+        let err = export_repo(&config, &repo_id, &dest, true, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{}", err);
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_detect_workspace_type() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-detect-wtype-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let git_only = temp_dir.join("git-only");
+        let colocated = temp_dir.join("colocated");
+        std::fs::create_dir_all(git_only.join(".git")).unwrap();
+        std::fs::create_dir_all(colocated.join(".git")).unwrap();
+        std::fs::create_dir_all(colocated.join(".jj")).unwrap();
+
+        assert_eq!(
+            detect_workspace_type(&git_only),
+            crate::path::WorkspaceType::Git
+        );
+        assert_eq!(
+            detect_workspace_type(&colocated),
+            crate::path::WorkspaceType::Jj
+        );
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_new_workspace_without_type_uses_git_when_no_jj() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-new-auto-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        let source = config.base_repo_dir.join("proj");
+        std::fs::create_dir_all(&source).unwrap();
+        git(&source, &["init", "-q"]);
+        git(&source, &["commit", "-q", "--allow-empty", "-m", "init"]);
+
+        // A bookmark needs jj, which this repo doesn't have
+        let err = new_workspace(
+            &config,
+            Some("proj"),
+            Some("feat"),
+            None,
+            Some("main"),
+            None,
+            false,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("has no .jj directory"), "{}", err);
+
+        let path = new_workspace(
+            &config,
+            Some("proj"),
+            Some("feat"),
+            None,
+            None,
+            None,
+            false,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(path, config.workspace_dir.join("git/proj/feat"));
+        assert!(path.join(".git").exists());
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_new_workspace_applies_workspace_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-workspace-mode-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let mut config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        config.runtime.workspace_mode = Some(0o2770);
+        let source = config.base_repo_dir.join("proj");
+        std::fs::create_dir_all(&source).unwrap();
+        git(&source, &["init", "-q"]);
+        git(&source, &["commit", "-q", "--allow-empty", "-m", "init"]);
+
+        let mut progress = Vec::new();
+        let path = new_workspace(
+            &config,
+            Some("proj"),
+            Some("feat"),
+            Some(crate::path::WorkspaceType::Git),
+            None,
+            None,
+            false,
+            &mut progress,
+        )
+        .unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o2770);
+        assert!(
+            String::from_utf8(progress)
+                .unwrap()
+                .contains("Set workspace mode to 2770")
+        );
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_new_workspace_returns_path_and_writes_progress_to_writer() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-new-workspace-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        let source = config.base_repo_dir.join("proj");
+        std::fs::create_dir_all(&source).unwrap();
+        git(&source, &["init", "-q"]);
+        git(&source, &["commit", "-q", "--allow-empty", "-m", "init"]);
+
+        let mut progress = Vec::new();
+        let path = new_workspace(
+            &config,
+            Some("proj"),
+            Some("feat"),
+            Some(crate::path::WorkspaceType::Git),
+            None,
+            None,
+            false,
+            &mut progress,
+        )
+        .unwrap();
+
+        assert_eq!(path, config.workspace_dir.join("git/proj/feat"));
+        assert!(path.join(".git").exists());
+        let progress = String::from_utf8(progress).unwrap();
+        assert!(progress.contains("Creating new git worktree workspace:"));
+        assert!(progress.contains("Successfully created workspace at:"));
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_jj_workspace_commands_without_bookmark() {
+        let commands = jj_workspace_commands(
+            Path::new("/repos/proj"),
+            Path::new("/workspaces/jj/proj/feat"),
+            "feat",
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            commands,
+            vec![(
+                PathBuf::from("/repos/proj"),
+                vec![
+                    "workspace".to_string(),
+                    "add".to_string(),
+                    "--name".to_string(),
+                    "feat".to_string(),
+                    "/workspaces/jj/proj/feat".to_string(),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_jj_workspace_commands_with_bookmark_runs_new_in_workspace() {
+        let commands = jj_workspace_commands(
+            Path::new("/repos/proj"),
+            Path::new("/workspaces/jj/proj/feat"),
+            "feat",
+            Some("release/1.0"),
+        )
+        .unwrap();
+
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].0, PathBuf::from("/repos/proj"));
+        assert_eq!(commands[0].1[..2], ["workspace", "add"]);
+        assert_eq!(
+            commands[1],
+            (
+                PathBuf::from("/workspaces/jj/proj/feat"),
+                vec![
+                    "new".to_string(),
+                    "bookmarks(exact:\"release/1.0\")".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_diff_stat_commands() {
+        use crate::path::WorkspaceType;
+
+        assert_eq!(
+            diff_stat_command(WorkspaceType::Git, "feat", "main"),
+            (
+                "git",
+                vec!["diff".into(), "--stat".into(), "main..feat".into()]
+            )
+        );
+        assert_eq!(
+            diff_stat_command(WorkspaceType::Jj, "feat", "trunk()"),
+            (
+                "jj",
+                vec![
+                    "diff".into(),
+                    "--stat".into(),
+                    "--from".into(),
+                    "trunk()".into(),
+                    "--to".into(),
+                    "@".into()
+                ]
+            )
+        );
+
+        let (program, args) = diff_base_check_command(WorkspaceType::Git, "main");
+        assert_eq!(program, "git");
+        assert_eq!(args[..2], ["rev-parse", "--verify"]);
+        assert_eq!(args.last().unwrap(), "main^{commit}");
+
+        let (program, args) = diff_base_check_command(WorkspaceType::Jj, "main");
+        assert_eq!(program, "jj");
+        assert!(args.contains(&"exactly(main, 1)".to_string()));
+    }
+
+    #[test]
+    fn test_new_workspace_diff_stat_against_base() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-diff-stat-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        let source = config.base_repo_dir.join("proj");
+        std::fs::create_dir_all(&source).unwrap();
+        git(&source, &["init", "-q"]);
+        git(&source, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(&source, &["tag", "base"]);
+        std::fs::write(source.join("review.txt"), "change\n").unwrap();
+        git(&source, &["add", "review.txt"]);
+        git(&source, &["commit", "-q", "-m", "change"]);
+        git(&source, &["branch", "feat"]);
+
+        // A bad base fails before any workspace is created
+        let err = new_workspace(
+            &config,
+            Some("proj"),
+            Some("feat"),
+            Some(crate::path::WorkspaceType::Git),
+            None,
+            Some("no-such-rev"),
+            false,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Base revision 'no-such-rev' not found")
+        );
+        assert!(!config.workspace_dir.join("git/proj/feat").exists());
+
+        let mut progress = Vec::new();
+        new_workspace(
+            &config,
+            Some("proj"),
+            Some("feat"),
+            Some(crate::path::WorkspaceType::Git),
+            None,
+            Some("base"),
+            false,
+            &mut progress,
+        )
+        .unwrap();
+
+        let progress = String::from_utf8(progress).unwrap();
+        assert!(progress.contains("Changes since base:"));
+        assert!(progress.contains("review.txt"));
+        assert!(progress.contains("1 file changed"));
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+
+    #[test]
+    fn test_free_jj_workspace_name() {
+        let existing = ["default", "foo", "foo-2"];
+        let taken = |name: &str| existing.contains(&name);
+
+        assert_eq!(free_jj_workspace_name("bar", false, taken).unwrap(), "bar");
+        assert_eq!(free_jj_workspace_name("foo", true, taken).unwrap(), "foo-3");
+
+        let err = free_jj_workspace_name("foo", false, taken)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("jj workspace 'foo' already exists in repo"),
+            "{}",
+            err
+        );
+        assert!(err.contains("jj workspace forget foo"), "{}", err);
+    }
+
+    #[test]
+    fn test_list_entries_sources_then_sessions() {
+        use crate::path::WorkspaceType;
+
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-list-entries-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        for dir in [
+            "repos/a/.git",
+            "repos/b/.jj",
+            "workspaces/git/a/two",
+            "workspaces/git/a/one",
+            "workspaces/jj/a/three",
+            "workspaces/jj/b/four",
+        ] {
+            std::fs::create_dir_all(temp_dir.join(dir)).unwrap();
+        }
+        std::fs::write(temp_dir.join("workspaces/git/a/notes.txt"), "").unwrap();
+
+        let repos = list_repos(&config, None, None).unwrap();
+        let summary = |wtype| -> Vec<String> {
+            list_entries(&config, &repos, wtype)
+                .into_iter()
+                .map(|e| {
+                    assert!(e.modified.is_some());
+                    format!(
+                        "{} {} {} {}",
+                        e.repo,
+                        e.wtype.as_str(),
+                        e.session.as_deref().unwrap_or("-"),
+                        e.path.strip_prefix(&temp_dir).unwrap().display()
+                    )
+                })
+                .collect()
+        };
+        let all = summary(None);
+        let jj = summary(Some(WorkspaceType::Jj));
+        let at = |path: &str| {
+            workspace_at(&config, &temp_dir.join(path))
+                .unwrap()
+                .map(|e| (e.repo, e.session))
+        };
+        assert_eq!(
+            at("workspaces/git/a/one/src/lib"),
+            Some(("a".to_string(), Some("one".to_string())))
+        );
+        assert_eq!(at("repos/b"), Some(("b".to_string(), None)));
+        assert_eq!(at("workspaces/git/a"), None);
+        std::fs::remove_dir_all(&temp_dir).ok();
+
+        assert_eq!(
+            all,
+            [
+                "a git - repos/a",
+                "a git one workspaces/git/a/one",
+                "a git two workspaces/git/a/two",
+                "a jj three workspaces/jj/a/three",
+                "b jj - repos/b",
+                "b jj four workspaces/jj/b/four",
+            ]
+        );
+        assert_eq!(
+            jj,
+            [
+                "a jj three workspaces/jj/a/three",
+                "b jj - repos/b",
+                "b jj four workspaces/jj/b/four",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_repo_counts_prints_only_counts() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-repo-counts-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        for dir in [
+            "repos/a/.git",
+            "repos/b/.jj",
+            "repos/c/.git",
+            "workspaces/git/a/one",
+            "workspaces/git/a/two",
+            "workspaces/jj/b/three",
+            "workspaces/jj/gone/orphan",
+        ] {
+            std::fs::create_dir_all(temp_dir.join(dir)).unwrap();
+        }
+        // Stray files are not workspaces
+        std::fs::write(temp_dir.join("workspaces/git/a/notes.txt"), "").unwrap();
+
+        let repos = list_repos(&config, None, None).unwrap();
+        let mut out = Vec::new();
+        write_repo_counts(&config, &repos, &mut out).unwrap();
+        let json = serde_json::to_value(repo_counts(&config, &repos)).unwrap();
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "repos: 3\ngit worktrees: 2\njj workspaces: 1\n"
+        );
+        assert_eq!(
+            json,
+            serde_json::json!({"repos": 3, "git_worktrees": 2, "jj_workspaces": 1})
+        );
+    }
+
+    #[test]
+    fn test_write_repos_json_lines_emits_one_object_per_line() {
+        let temp_dir =
+            std::env::temp_dir().join(format!("ab-test-json-lines-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let config = Config {
+            workspace_dir: temp_dir.join("workspaces"),
+            base_repo_dir: temp_dir.join("repos"),
+            default_profile: None,
+            profiles: Default::default(),
+            runtime: Default::default(),
+            context: String::new(),
+            context_path: "/tmp/context".to_string(),
+            aliases: Default::default(),
+            gc: Default::default(),
+            portal: crate::portal::PortalConfig::default(),
+        };
+        for dir in [
+            "repos/fr/agent-box/.git",
+            "repos/fr/tool/.jj",
+            "repos/other/.git",
+        ] {
+            std::fs::create_dir_all(temp_dir.join(dir)).unwrap();
+        }
+
+        let lines = |filter: Option<&str>, limit: Option<usize>| {
+            let mut out = Vec::new();
+            write_repos_json_lines(&config, filter, limit, &mut out).unwrap();
+            let mut repos: Vec<serde_json::Value> = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            repos.sort_by_key(|r| r["repo"].as_str().unwrap().to_string());
+            repos
+        };
+
+        let all = lines(None, None);
+        let repos: Vec<&str> = all.iter().map(|r| r["repo"].as_str().unwrap()).collect();
+        assert_eq!(repos, ["fr/agent-box", "fr/tool", "other"]);
+        assert_eq!(
+            all[0]["path"].as_str().unwrap(),
+            config.base_repo_dir.join("fr/agent-box").to_string_lossy()
+        );
+
+        assert_eq!(lines(Some("FR/"), None).len(), 2);
+        assert_eq!(lines(Some("fr/"), Some(1)).len(), 1);
+        assert!(lines(None, Some(0)).is_empty());
+
+        std::fs::remove_dir_all(&temp_dir).ok();
+    }
+}